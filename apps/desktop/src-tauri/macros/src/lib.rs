@@ -0,0 +1,82 @@
+// Attributes for the desktop app's commands. Tauri answers an async command
+// from a task of its own, so the invoke handler only sees it spawned; the
+// command has to time itself, from inside its future. What a command
+// answers never passes back through the invoke handler either, so every
+// command measures its own answer
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Error, Ident, ItemFn, ReturnType, Type};
+
+/// Measures a command for `metrics::CommandStats`: the size of what it
+/// answers and, when it fails, its error. An async command is also timed
+/// from its first poll until its future finishes or is dropped; the invoke
+/// handler times the others. Goes above `#[tauri::command]`: the command
+/// gains a hidden `State` argument for the stats, which Tauri fills in
+/// like any other.
+#[proc_macro_attribute]
+pub fn timed(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return Error::new(Span::call_site(), "`timed` takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let mut function = parse_macro_input!(item as ItemFn);
+    let name = function.sig.ident.to_string();
+    // Out of reach of the body's own names
+    let stats = Ident::new("command_stats", Span::mixed_site());
+    let timer = Ident::new("command_timer", Span::mixed_site());
+    let output = Ident::new("command_output", Span::mixed_site());
+    let ty: Type = match &function.sig.output {
+        ReturnType::Default => parse_quote!(()),
+        ReturnType::Type(_, ty) => (**ty).clone(),
+    };
+    let answer = if returns_result(&ty) {
+        quote!(crate::metrics::Answer::of_result(&#output))
+    } else {
+        quote!(crate::metrics::Answer::of(&#output))
+    };
+    function
+        .sig
+        .inputs
+        .push(parse_quote!(#stats: ::tauri::State<'_, crate::metrics::CommandStats>));
+    let body = &function.block;
+    function.block = if function.sig.asyncness.is_some() {
+        parse_quote!({
+            let #timer = #stats.time(#name);
+            // Typed up front so `?` in the body knows what it converts to
+            let #output: #ty =
+                crate::metrics::returning::<#ty, _>(async move #body).await;
+            #timer.answered(#answer);
+            #output
+        })
+    } else {
+        parse_quote!({
+            let #output: #ty = (move || -> #ty #body)();
+            #stats.answered(#name, #answer);
+            #output
+        })
+    };
+    quote!(
+        #[allow(
+            clippy::too_many_arguments,
+            clippy::redundant_closure_call,
+            clippy::let_unit_value
+        )]
+        #function
+    )
+    .into()
+}
+
+/// Whether a command returns a `Result`, which Tauri answers with its
+/// value or rejects with its error.
+fn returns_result(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Result"),
+        _ => false,
+    }
+}
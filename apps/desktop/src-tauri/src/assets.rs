@@ -0,0 +1,130 @@
+// Custom URI scheme that lets the webview play cached audio without copying
+// the bytes over IPC, e.g. `sclip-audio://localhost/tts/<key>.mp3` or
+// `sclip-audio://localhost/preview/<voice name>.mp3`, and music mixes as
+// `sclip-audio://localhost/mix/<key>.wav`. Previews are served trimmed to
+// `max_preview_ms` unless the URL ends in `?full=1`.
+
+use std::fs;
+use tauri::http::{header, Request, Response, StatusCode};
+use tauri::{AppHandle, Manager, UriSchemeContext};
+
+use crate::cache::{contained_path, is_cache_key};
+use crate::settings::SettingsStore;
+
+pub const SCHEME: &str = "sclip-audio";
+
+#[derive(Debug, Clone, Copy)]
+pub enum AssetKind {
+    Tts,
+    Preview,
+    Mix,
+}
+
+impl AssetKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            AssetKind::Tts => "tts",
+            AssetKind::Preview => "preview",
+            AssetKind::Mix => "mix",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            AssetKind::Tts | AssetKind::Preview => "mp3",
+            AssetKind::Mix => "wav",
+        }
+    }
+}
+
+/// URL under which the webview can fetch the cache entry `name`. Windows and
+/// Android WebViews only accept custom schemes in the
+/// `http://<scheme>.localhost` form.
+pub fn asset_url(kind: AssetKind, name: &str) -> String {
+    if cfg!(any(windows, target_os = "android")) {
+        format!(
+            "http://{}.localhost/{}/{}.{}",
+            SCHEME,
+            kind.as_str(),
+            name,
+            kind.extension()
+        )
+    } else {
+        format!(
+            "{}://localhost/{}/{}.{}",
+            SCHEME,
+            kind.as_str(),
+            name,
+            kind.extension()
+        )
+    }
+}
+
+fn not_found() -> Response<Vec<u8>> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Vec::new())
+        .unwrap()
+}
+
+fn resolve(app_handle: &AppHandle, kind: &str, name: &str, full: bool) -> Option<Vec<u8>> {
+    let name = name
+        .strip_suffix(".mp3")
+        .or_else(|| name.strip_suffix(".wav"))
+        .unwrap_or(name);
+    if !is_cache_key(name) {
+        return None;
+    }
+    let path = match kind {
+        "tts" => {
+            let cache = crate::cache::tts_cache(app_handle).ok()?;
+            contained_path(cache.dir(), &cache.entry_path(name))?
+        }
+        // `locate` validates the voice name and containment itself
+        "preview" => {
+            let settings = app_handle.state::<SettingsStore>();
+            let sentence = crate::voices::sentences::for_voice(&settings, name);
+            let cache = crate::cache::preview_cache(app_handle).ok()?;
+            let path = cache.locate(name, &sentence)?;
+            let bytes = fs::read(&path).ok()?;
+            if full {
+                return Some(bytes);
+            }
+            let trimmed = cache.trimmed(&path, &bytes, settings.get().max_preview_ms);
+            return Some(trimmed.unwrap_or(bytes));
+        }
+        "mix" => {
+            let path = crate::audio::music::mix_path(app_handle, name).ok()?;
+            contained_path(path.parent()?, &path)?
+        }
+        _ => return None,
+    };
+    fs::read(path).ok()
+}
+
+pub fn handle(
+    ctx: UriSchemeContext<'_, tauri::Wry>,
+    request: Request<Vec<u8>>,
+) -> Response<Vec<u8>> {
+    let path = request.uri().path().trim_start_matches('/');
+    let Some((kind, name)) = path.split_once('/') else {
+        return not_found();
+    };
+
+    let full = request
+        .uri()
+        .query()
+        .is_some_and(|query| query.split('&').any(|pair| pair == "full=1"));
+    let content_type = match kind {
+        "mix" => "audio/wav",
+        _ => "audio/mpeg",
+    };
+    match resolve(ctx.app_handle(), kind, name, full) {
+        Some(bytes) => Response::builder()
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .body(bytes)
+            .unwrap(),
+        None => not_found(),
+    }
+}
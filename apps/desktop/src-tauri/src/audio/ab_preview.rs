@@ -0,0 +1,360 @@
+// Back-to-back comparison of two voices. Both samples come from the preview
+// cache, synthesized into it when missing, before anything plays; a task
+// then paces A, gap, B, gap for the requested loops and announces each turn
+// through "ab-preview-position". The app has no audio output of its own: the
+// webview player plays the sample each event names, from the bytes the
+// command returned, already stretched to the preview rate, while the
+// arbiter keeps main playback down for the run
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{Emitter, Manager};
+use tokio::sync::Notify;
+
+use super::arbitration::PlaybackArbiter;
+use super::mp3;
+use crate::error::AppError;
+use crate::paths::roots::{AllowedRoots, Read};
+use crate::settings::SettingsStore;
+use crate::tts::lanes::Lane;
+use crate::tts::TtsService;
+
+/// Longest pause allowed between the two samples.
+const MAX_GAP_MS: u32 = 10_000;
+/// Most A/B rounds one command may play.
+const MAX_LOOPS: u32 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AbSide {
+    A,
+    B,
+}
+
+impl AbSide {
+    fn label(self) -> &'static str {
+        match self {
+            AbSide::A => "a",
+            AbSide::B => "b",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AbSample {
+    pub voice_name: String,
+    /// Of the sample at 1×.
+    pub duration_ms: f64,
+    /// The sample at the run's `rate`.
+    pub audio: Vec<u8>,
+}
+
+/// What `play_ab_preview` returns once both samples are ready.
+#[derive(Debug, Clone, Serialize)]
+pub struct AbPreview {
+    /// Identifies this run's events, and its preview to the arbiter.
+    pub session_id: u64,
+    pub a: AbSample,
+    pub b: AbSample,
+    pub gap_ms: u32,
+    pub loops: u32,
+    /// The preview rate the samples were stretched to.
+    pub rate: f32,
+}
+
+/// Payload of "ab-preview-position": play `current` now.
+#[derive(Debug, Clone, Serialize)]
+pub struct AbPreviewPosition {
+    pub session_id: u64,
+    pub current: AbSide,
+    /// From 0.
+    pub loop_index: u32,
+    pub voice_name: String,
+}
+
+/// Payload of "ab-preview-ended".
+#[derive(Debug, Clone, Serialize)]
+pub struct AbPreviewEnded {
+    pub session_id: u64,
+    /// True if `stop_audio` or a newer A/B preview ended it early.
+    pub stopped: bool,
+}
+
+struct Session {
+    id: u64,
+    stop: Arc<Notify>,
+}
+
+#[derive(Default)]
+struct Sessions {
+    active: Option<Session>,
+    next_id: u64,
+}
+
+/// Managed state holding the A/B preview that's playing, if any.
+#[derive(Default)]
+pub struct AbPreviewPlayer {
+    sessions: Mutex<Sessions>,
+}
+
+impl AbPreviewPlayer {
+    /// Starts a session, stopping the one before it.
+    fn begin(&self) -> (u64, Arc<Notify>) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if let Some(previous) = sessions.active.take() {
+            previous.stop.notify_one();
+        }
+        sessions.next_id += 1;
+        let stop = Arc::new(Notify::new());
+        sessions.active = Some(Session {
+            id: sessions.next_id,
+            stop: stop.clone(),
+        });
+        (sessions.next_id, stop)
+    }
+
+    fn end(&self, session_id: u64) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.active.as_ref().is_some_and(|s| s.id == session_id) {
+            sessions.active = None;
+        }
+    }
+
+    /// Stops the session playing; false if none is.
+    pub fn stop(&self) -> bool {
+        match self.sessions.lock().unwrap().active.take() {
+            Some(session) => {
+                // A permit is kept if the task isn't waiting right now
+                session.stop.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn preview_id(session_id: u64) -> String {
+    format!("ab-preview-{}", session_id)
+}
+
+/// The sample of `voice_name` speaking `sentence`, as the voice picker
+/// would play it at `rate`: from the preview cache, else synthesized into it.
+async fn sample(
+    app_handle: &tauri::AppHandle,
+    tts: &TtsService,
+    roots: &AllowedRoots,
+    voice_name: &str,
+    sentence: &str,
+    max_preview_ms: u32,
+    rate: f32,
+) -> Result<AbSample, AppError> {
+    if !crate::cache::is_voice_name(voice_name) {
+        return Err(AppError::InvalidVoiceName {
+            voice_name: voice_name.to_string(),
+        });
+    }
+    let cache = crate::cache::preview_cache(app_handle)?;
+    let cached = match cache.locate(voice_name, sentence) {
+        Some(path) => {
+            let bytes = roots.validate_path::<Read>(&path)?.read()?;
+            mp3::is_plausible_mp3(&bytes).then(|| {
+                cache
+                    .trimmed(&path, &bytes, max_preview_ms)
+                    .unwrap_or(bytes)
+            })
+        }
+        None => None,
+    };
+    let audio = match cached {
+        Some(audio) => audio,
+        None => {
+            let bytes = crate::voices::previews::regenerate_preview(
+                tts,
+                &cache,
+                voice_name,
+                sentence,
+                Lane::Interactive,
+            )
+            .await?;
+            super::trim::trim(&bytes, max_preview_ms).unwrap_or(bytes)
+        }
+    };
+    let duration_ms = mp3::duration_ms(&audio).ok_or_else(|| AppError::CorruptPreview {
+        voice_name: voice_name.to_string(),
+    })?;
+    let audio = tauri::async_runtime::spawn_blocking(move || super::stretch::at_rate(audio, rate))
+        .await
+        .map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })??;
+    Ok(AbSample {
+        voice_name: voice_name.to_string(),
+        duration_ms,
+        audio,
+    })
+}
+
+/// Pauses for `duration`; false if stopped first.
+async fn wait(stop: &Notify, duration: Duration) -> bool {
+    tokio::select! {
+        _ = tokio::time::sleep(duration) => true,
+        _ = stop.notified() => false,
+    }
+}
+
+async fn run(
+    app_handle: tauri::AppHandle,
+    session_id: u64,
+    stop: Arc<Notify>,
+    samples: [(AbSide, String, Duration); 2],
+    gap: Duration,
+    loops: u32,
+    rate: f32,
+) -> bool {
+    for loop_index in 0..loops {
+        for (i, (current, voice_name, duration)) in samples.iter().enumerate() {
+            let position = AbPreviewPosition {
+                session_id,
+                current: *current,
+                loop_index,
+                voice_name: voice_name.clone(),
+            };
+            if let Err(e) = app_handle.emit("ab-preview-position", position) {
+                log::warn!("Failed to emit ab-preview-position: {}", e);
+            }
+            let last = loop_index + 1 == loops && i + 1 == samples.len();
+            let played = duration.div_f32(rate);
+            let pause = if last { played } else { played + gap };
+            if !wait(&stop, pause).await {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Plays `voice_a` and `voice_b` saying `text` (default: their preview
+/// sentences) back to back, `gap_ms` apart, `loops` times. Both samples
+/// are made before anything plays, so a voice that fails names itself
+/// without half a comparison having played; `stop_audio` ends the run.
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn play_ab_preview(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+    roots: tauri::State<'_, AllowedRoots>,
+    voice_a: String,
+    voice_b: String,
+    text: Option<String>,
+    gap_ms: u32,
+    loops: u32,
+) -> Result<AbPreview, AppError> {
+    if gap_ms > MAX_GAP_MS {
+        return Err(AppError::InvalidAudioRequest {
+            message: format!("gap_ms must be at most {}", MAX_GAP_MS),
+        });
+    }
+    if !(1..=MAX_LOOPS).contains(&loops) {
+        return Err(AppError::InvalidAudioRequest {
+            message: format!("loops must be between 1 and {}", MAX_LOOPS),
+        });
+    }
+    if let Some(text) = &text {
+        crate::tts::speakable::check(text)?;
+    }
+    let max_preview_ms = settings.get().max_preview_ms;
+    // Samples are stretched to the preview rate, set when the run starts,
+    // but the gaps are wall time; the arbiter counts in audio time
+    let rate = super::arbitration::default_rate(&app_handle);
+    let mut samples = Vec::with_capacity(2);
+    for (side, voice_name) in [(AbSide::A, &voice_a), (AbSide::B, &voice_b)] {
+        let sentence = match &text {
+            Some(text) => text.clone(),
+            None => crate::voices::sentences::for_voice(&settings, voice_name),
+        };
+        let sample = sample(
+            &app_handle,
+            &tts,
+            &roots,
+            voice_name,
+            &sentence,
+            max_preview_ms,
+            rate,
+        )
+        .await
+        .map_err(|e| AppError::AbPreviewFailed {
+            side: side.label().to_string(),
+            voice_name: voice_name.clone(),
+            message: e.to_string(),
+        })?;
+        samples.push(sample);
+    }
+    let b = samples.pop().expect("two samples");
+    let a = samples.pop().expect("two samples");
+
+    let player = app_handle.state::<AbPreviewPlayer>();
+    let (session_id, stop) = player.begin();
+    let turns = [(AbSide::A, &a), (AbSide::B, &b)].map(|(side, s)| {
+        (
+            side,
+            s.voice_name.clone(),
+            Duration::from_secs_f64(s.duration_ms / 1000.0),
+        )
+    });
+    let gap = Duration::from_millis(gap_ms as u64);
+    let total = turns
+        .iter()
+        .map(|t| t.2 + gap.mul_f32(rate))
+        .sum::<Duration>()
+        * loops;
+    app_handle.state::<PlaybackArbiter>().start_preview(
+        &app_handle,
+        preview_id(session_id),
+        Some(total),
+    );
+
+    let task_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        let stopped = run(
+            task_handle.clone(),
+            session_id,
+            stop,
+            turns,
+            gap,
+            loops,
+            rate,
+        )
+        .await;
+        task_handle.state::<AbPreviewPlayer>().end(session_id);
+        task_handle
+            .state::<PlaybackArbiter>()
+            .stop_preview(&task_handle, &preview_id(session_id));
+        if let Err(e) = task_handle.emit(
+            "ab-preview-ended",
+            AbPreviewEnded {
+                session_id,
+                stopped,
+            },
+        ) {
+            log::warn!("Failed to emit ab-preview-ended: {}", e);
+        }
+    });
+
+    Ok(AbPreview {
+        session_id,
+        a,
+        b,
+        gap_ms,
+        loops,
+        rate,
+    })
+}
+
+/// Stops the A/B preview playing; false if none is.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn stop_audio(player: tauri::State<'_, AbPreviewPlayer>) -> bool {
+    player.stop()
+}
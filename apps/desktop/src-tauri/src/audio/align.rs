@@ -0,0 +1,699 @@
+// Sentence timings for narration recorded outside the app, for captions.
+// Google Speech-to-Text transcribes the file with word offsets, streamed in
+// windows under its five-minute stream limit; the words are then matched to
+// the script's sentences, as the chunker splits them, in script order. A
+// window or sentence that fails is flagged and the rest kept. Words spoken
+// across a window cut may be lost, leaving their sentence a little short
+use chrono::Utc;
+use gcloud_sdk::google::cloud::speech::v1::recognition_config::AudioEncoding;
+use gcloud_sdk::google::cloud::speech::v1::streaming_recognize_request::StreamingRequest;
+use gcloud_sdk::google::cloud::speech::v1::{
+    RecognitionConfig, SpeechContext, StreamingRecognitionConfig, StreamingRecognizeRequest,
+};
+use serde::Serialize;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::Duration;
+use tauri::Emitter;
+use tokio_stream::StreamExt;
+
+use super::mp3;
+use super::probe::{self, Codec};
+use crate::error::AppError;
+use crate::paths::roots::{AllowedRoots, Read, Write};
+use crate::paths::ExportedPaths;
+use crate::project::timed_script::{
+    self, TimedScript, TimedScriptFormat, TimedScriptSource, TimedSegment, TIMED_SCRIPT_VERSION,
+};
+use crate::read_only::WriteAccess;
+use crate::tts::{SpeechApi, TtsService};
+
+/// Audio per stream, under Google's limit of about five minutes.
+const WINDOW_MS: f64 = 240_000.0;
+/// Audio bytes per streamed message; Google takes up to 25 KB.
+const CHUNK_BYTES: usize = 16 * 1024;
+/// How long one window may take to come back, beyond its own length.
+const WINDOW_TIMEOUT: Duration = Duration::from_secs(120);
+/// Share of a sentence's words that must be heard for it to count as matched.
+const MIN_COVERAGE: f64 = 0.5;
+/// Recognized words searched for a sentence beyond twice its length.
+const SEARCH_SLACK: usize = 40;
+/// Unmatched words allowed between two matched words of one sentence; a
+/// match further out is a coincidence, not the sentence.
+const MAX_WORD_GAP: usize = 6;
+/// Script sentences sent as phrase hints, by Google's limits.
+const MAX_HINTS: usize = 500;
+const MAX_HINT_CHARS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlignmentStatus {
+    Matched,
+    /// Not heard clearly enough; timed by sharing out the gap between the
+    /// matched sentences either side.
+    Interpolated,
+    /// Not heard, and no matched sentences on both sides to time it by.
+    Unmatched,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SentenceAlignment {
+    pub index: usize,
+    pub text: String,
+    pub start_ms: Option<f64>,
+    pub end_ms: Option<f64>,
+    pub status: AlignmentStatus,
+    /// From 0 to 1: the share of the sentence's words heard, weighted by
+    /// the recognizer's confidence in them. 0 unless matched.
+    pub confidence: f32,
+    pub words: usize,
+    pub matched_words: usize,
+}
+
+/// Speech that matched no sentence, or audio that couldn't be transcribed.
+#[derive(Debug, Clone, Serialize)]
+pub struct UnmatchedRegion {
+    pub start_ms: f64,
+    pub end_ms: f64,
+    /// What was heard there; empty for a failed window.
+    pub transcript: String,
+    /// Why the window failed, for audio that wasn't transcribed.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlignmentReport {
+    pub audio_path: PathBuf,
+    pub language_code: String,
+    pub duration_ms: Option<f64>,
+    pub sentences: Vec<SentenceAlignment>,
+    pub matched_sentences: usize,
+    pub unmatched: Vec<UnmatchedRegion>,
+    /// Where the timings were written as a timed script, if asked.
+    pub output_path: Option<PathBuf>,
+    pub warnings: Vec<String>,
+}
+
+/// Payload of "alignment-progress", as the audio is uploaded.
+#[derive(Debug, Clone, Serialize)]
+pub struct AlignmentProgress {
+    pub uploaded_bytes: u64,
+    pub total_bytes: u64,
+    /// From 0.
+    pub window_index: usize,
+    pub windows: usize,
+}
+
+/// A stretch of the file streamed on its own.
+struct Window {
+    bytes: Range<usize>,
+    offset_ms: f64,
+    duration_ms: f64,
+}
+
+/// The file cut the way Speech-to-Text takes it.
+struct Prepared {
+    encoding: AudioEncoding,
+    sample_rate: u32,
+    /// 0 leaves it to the recognizer.
+    channels: u16,
+    windows: Vec<Window>,
+}
+
+/// A recognized word, normalized for matching.
+#[derive(Debug, Clone)]
+struct Word {
+    token: String,
+    start_ms: f64,
+    end_ms: f64,
+    confidence: f32,
+}
+
+fn invalid(message: impl Into<String>) -> AppError {
+    AppError::InvalidAudioRequest {
+        message: message.into(),
+    }
+}
+
+/// Lowercased runs of letters, digits and inner apostrophes: what a script
+/// word and a recognized one have in common.
+fn tokens(text: &str) -> Vec<String> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '\'' || c == '’'))
+        .map(|word| word.trim_matches(['\'', '’']).to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// Sample rate, channels and the sample data of a 16-bit PCM WAV.
+fn wav_data(bytes: &[u8]) -> Option<(u32, u16, Range<usize>)> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return None;
+    }
+    let mut format = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let len = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().ok()?) as usize;
+        let body = pos + 8..(pos + 8).saturating_add(len).min(bytes.len());
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let fmt = &bytes[body];
+                let tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+                let channels = u16::from_le_bytes([fmt[2], fmt[3]]);
+                let sample_rate = u32::from_le_bytes(fmt[4..8].try_into().ok()?);
+                let bits = u16::from_le_bytes([fmt[14], fmt[15]]);
+                if !matches!(tag, 1 | 0xFFFE) || bits != 16 || channels == 0 || sample_rate == 0 {
+                    return None;
+                }
+                format = Some((sample_rate, channels));
+            }
+            b"data" => {
+                let (sample_rate, channels) = format?;
+                return Some((sample_rate, channels, body));
+            }
+            _ => {}
+        }
+        pos += 8 + len + (len & 1);
+    }
+    None
+}
+
+/// Cuts a WAV at frame boundaries and an MP3 between MPEG frames; FLAC and
+/// Opus are streamed whole, so must fit one window.
+fn prepare(bytes: &[u8]) -> Result<Prepared, AppError> {
+    if let Some((sample_rate, channels, data)) = wav_data(bytes) {
+        let frame = channels as usize * 2;
+        let frame_ms = 1000.0 / sample_rate as f64;
+        let per_window = ((WINDOW_MS / frame_ms) as usize).max(1) * frame;
+        let windows = data
+            .clone()
+            .step_by(per_window)
+            .map(|start| {
+                let end = (start + per_window).min(data.end);
+                let end = start + (end - start) / frame * frame;
+                Window {
+                    bytes: start..end,
+                    offset_ms: ((start - data.start) / frame) as f64 * frame_ms,
+                    duration_ms: ((end - start) / frame) as f64 * frame_ms,
+                }
+            })
+            .filter(|w| !w.bytes.is_empty())
+            .collect();
+        return Ok(Prepared {
+            encoding: AudioEncoding::Linear16,
+            sample_rate,
+            channels,
+            windows,
+        });
+    }
+
+    let frames: Vec<mp3::Frame> = mp3::frames(bytes, mp3::id3v2_len(bytes))
+        .into_iter()
+        .filter(|f| !mp3::is_vbr_header(&bytes[f.start..f.start + f.len]))
+        .collect();
+    if let Some(first) = frames.first() {
+        let mut windows: Vec<Window> = Vec::new();
+        let mut at = 0.0;
+        for frame in &frames {
+            let frame_ms = frame.header.samples() as f64 * 1000.0 / frame.header.sample_rate as f64;
+            match windows.last_mut() {
+                Some(window) if window.duration_ms + frame_ms <= WINDOW_MS => {
+                    window.bytes.end = frame.start + frame.len;
+                    window.duration_ms += frame_ms;
+                }
+                _ => windows.push(Window {
+                    bytes: frame.start..frame.start + frame.len,
+                    offset_ms: at,
+                    duration_ms: frame_ms,
+                }),
+            }
+            at += frame_ms;
+        }
+        return Ok(Prepared {
+            encoding: AudioEncoding::Mp3,
+            sample_rate: first.header.sample_rate,
+            channels: 0,
+            windows,
+        });
+    }
+
+    Err(invalid(
+        "audio must be a 16-bit PCM WAV or an MP3 to be cut for streaming",
+    ))
+}
+
+/// FLAC and Opus can't be cut here, so are streamed whole if they fit one
+/// window.
+fn prepare_whole(bytes: &[u8], probe: &probe::AudioProbe) -> Result<Prepared, AppError> {
+    let encoding = match probe.codec {
+        Codec::Flac => AudioEncoding::Flac,
+        Codec::Opus => AudioEncoding::OggOpus,
+        _ => return prepare(bytes),
+    };
+    let duration_ms = probe.duration_ms.unwrap_or(0.0);
+    if duration_ms > WINDOW_MS {
+        return Err(invalid(format!(
+            "{:.0} s of FLAC or Opus is too long to stream whole; convert it to WAV or MP3",
+            duration_ms / 1000.0
+        )));
+    }
+    Ok(Prepared {
+        encoding,
+        sample_rate: probe.sample_rate.unwrap_or_default(),
+        channels: 0,
+        windows: vec![Window {
+            bytes: 0..bytes.len(),
+            offset_ms: 0.0,
+            duration_ms,
+        }],
+    })
+}
+
+fn ms(duration: Option<&gcloud_sdk::prost_types::Duration>) -> f64 {
+    duration.map_or(0.0, |d| d.seconds as f64 * 1000.0 + d.nanos as f64 / 1e6)
+}
+
+/// Transcribes one window, returning its words with offsets from the start
+/// of the file. `uploaded` counts the bytes sent across windows.
+#[allow(clippy::too_many_arguments)]
+async fn recognize_window(
+    app_handle: &tauri::AppHandle,
+    client: &SpeechApi,
+    config: &RecognitionConfig,
+    quota_project: Option<&str>,
+    audio: &[u8],
+    window: &Window,
+    progress: (usize, usize, u64),
+    uploaded: &mut u64,
+) -> Result<Vec<Word>, AppError> {
+    let (window_index, windows, total_bytes) = progress;
+    let mut requests = vec![StreamingRecognizeRequest {
+        streaming_request: Some(StreamingRequest::StreamingConfig(
+            StreamingRecognitionConfig {
+                config: Some(config.clone()),
+                ..Default::default()
+            },
+        )),
+    }];
+    requests.extend(
+        audio[window.bytes.clone()]
+            .chunks(CHUNK_BYTES)
+            .map(|chunk| StreamingRecognizeRequest {
+                streaming_request: Some(StreamingRequest::AudioContent(chunk.to_vec())),
+            }),
+    );
+
+    // Messages are pulled as the upload goes, so this follows it
+    let app = app_handle.clone();
+    let mut sent = *uploaded;
+    let mut last_percent = u64::MAX;
+    let stream = tokio_stream::iter(requests).map(move |request| {
+        if let Some(StreamingRequest::AudioContent(chunk)) = &request.streaming_request {
+            sent += chunk.len() as u64;
+            let percent = sent * 100 / total_bytes.max(1);
+            if percent != last_percent {
+                last_percent = percent;
+                let progress = AlignmentProgress {
+                    uploaded_bytes: sent,
+                    total_bytes,
+                    window_index,
+                    windows,
+                };
+                if let Err(e) = app.emit("alignment-progress", progress) {
+                    log::warn!("Failed to emit alignment-progress: {}", e);
+                }
+            }
+        }
+        request
+    });
+    *uploaded += window.bytes.len() as u64;
+
+    let request = crate::tts::quota::request(stream, quota_project)?;
+    let status_error = |status| crate::tts::quota::status_error(status, quota_project);
+    let call = async {
+        let mut responses = client
+            .get()
+            .streaming_recognize(request)
+            .await
+            .map_err(status_error)?
+            .into_inner();
+        let mut words = Vec::new();
+        while let Some(response) = responses.message().await.map_err(status_error)? {
+            if let Some(error) = response.error.filter(|e| e.code != 0) {
+                return Err(AppError::Tts {
+                    message: format!("Speech-to-Text failed: {}", error.message),
+                });
+            }
+            let heard = response
+                .results
+                .into_iter()
+                .filter(|r| r.is_final)
+                .filter_map(|r| r.alternatives.into_iter().next());
+            for alternative in heard {
+                for word in alternative.words {
+                    let start_ms = window.offset_ms + ms(word.start_time.as_ref());
+                    let end_ms = window.offset_ms + ms(word.end_time.as_ref());
+                    // One recognized "word" can be several script tokens, e.g. "e-mail"
+                    for token in tokens(&word.word) {
+                        words.push(Word {
+                            token,
+                            start_ms,
+                            end_ms,
+                            confidence: word.confidence,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(words)
+    };
+    let limit = WINDOW_TIMEOUT + Duration::from_millis(window.duration_ms as u64);
+    tokio::time::timeout(limit, call)
+        .await
+        .map_err(|_| AppError::TtsOffline {
+            message: format!("Speech-to-Text gave no result within {:?}", limit),
+        })?
+}
+
+/// Index pairs of the longest common subsequence of `a` and `b`.
+fn common_pairs(a: &[String], b: &[&str]) -> Vec<(usize, usize)> {
+    let width = b.len() + 1;
+    let mut lcs = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i * width + j] = if a[i] == b[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+/// Drops matched words at either end that stand further than
+/// `MAX_WORD_GAP` from the rest.
+fn trim_outliers(pairs: &mut Vec<(usize, usize)>) {
+    while pairs.len() > 1 && pairs[1].1 - pairs[0].1 > MAX_WORD_GAP + 1 {
+        pairs.remove(0);
+    }
+    while pairs.len() > 1 && pairs[pairs.len() - 1].1 - pairs[pairs.len() - 2].1 > MAX_WORD_GAP + 1
+    {
+        pairs.pop();
+    }
+}
+
+/// Matches each sentence, in order, to the recognized words after the
+/// previous match. Returns the sentences and which words were used.
+fn match_sentences(sentences: &[String], words: &[Word]) -> (Vec<SentenceAlignment>, Vec<bool>) {
+    let heard: Vec<&str> = words.iter().map(|w| w.token.as_str()).collect();
+    let mut used = vec![false; words.len()];
+    let mut cursor = 0;
+    let mut aligned = Vec::with_capacity(sentences.len());
+    for (index, text) in sentences.iter().enumerate() {
+        let script = tokens(text);
+        let end = (cursor + script.len() * 2 + SEARCH_SLACK).min(words.len());
+        let mut pairs = common_pairs(&script, &heard[cursor..end]);
+        trim_outliers(&mut pairs);
+        let coverage = pairs.len() as f64 / script.len().max(1) as f64;
+        let mut sentence = SentenceAlignment {
+            index,
+            text: text.clone(),
+            start_ms: None,
+            end_ms: None,
+            status: AlignmentStatus::Unmatched,
+            confidence: 0.0,
+            words: script.len(),
+            matched_words: pairs.len(),
+        };
+        if let (Some(&(_, first)), Some(&(_, last)), true) =
+            (pairs.first(), pairs.last(), coverage >= MIN_COVERAGE)
+        {
+            let (first, last) = (cursor + first, cursor + last);
+            let heard_confidence = pairs
+                .iter()
+                .map(|&(_, j)| words[cursor + j].confidence as f64)
+                .sum::<f64>()
+                / pairs.len() as f64;
+            // Google leaves confidence at 0 where it doesn't report one
+            let heard_confidence = if heard_confidence > 0.0 {
+                heard_confidence
+            } else {
+                1.0
+            };
+            sentence.start_ms = Some(words[first].start_ms);
+            sentence.end_ms = Some(words[last].end_ms);
+            sentence.status = AlignmentStatus::Matched;
+            sentence.confidence = (coverage * heard_confidence).min(1.0) as f32;
+            used[first..=last].iter_mut().for_each(|u| *u = true);
+            cursor = last + 1;
+        }
+        aligned.push(sentence);
+    }
+    (aligned, used)
+}
+
+/// Times each run of unmatched sentences between two matched ones by
+/// sharing out the gap between them by word count.
+fn interpolate(sentences: &mut [SentenceAlignment]) {
+    let matched: Vec<usize> = sentences
+        .iter()
+        .filter(|s| s.status == AlignmentStatus::Matched)
+        .map(|s| s.index)
+        .collect();
+    for pair in matched.windows(2) {
+        let (before, after) = (pair[0], pair[1]);
+        if after == before + 1 {
+            continue;
+        }
+        let (Some(start), Some(end)) = (sentences[before].end_ms, sentences[after].start_ms) else {
+            continue;
+        };
+        let run = &mut sentences[before + 1..after];
+        let total: usize = run.iter().map(|s| s.words.max(1)).sum();
+        let mut at = start;
+        for sentence in run {
+            let share = (end - start).max(0.0) * sentence.words.max(1) as f64 / total as f64;
+            sentence.start_ms = Some(at);
+            sentence.end_ms = Some(at + share);
+            sentence.status = AlignmentStatus::Interpolated;
+            at += share;
+        }
+    }
+}
+
+/// Runs of recognized words no sentence took.
+fn unmatched_regions(words: &[Word], used: &[bool]) -> Vec<UnmatchedRegion> {
+    let mut regions: Vec<UnmatchedRegion> = Vec::new();
+    let mut previous_unused = false;
+    for (word, &used) in words.iter().zip(used) {
+        if used {
+            previous_unused = false;
+            continue;
+        }
+        match regions.last_mut() {
+            Some(region) if previous_unused => {
+                region.end_ms = word.end_ms;
+                region.transcript.push(' ');
+                region.transcript.push_str(&word.token);
+            }
+            _ => regions.push(UnmatchedRegion {
+                start_ms: word.start_ms,
+                end_ms: word.end_ms,
+                transcript: word.token.clone(),
+                error: None,
+            }),
+        }
+        previous_unused = true;
+    }
+    regions
+}
+
+fn timed_script_of(report: &AlignmentReport) -> TimedScript {
+    let segments: Vec<TimedSegment> = report
+        .sentences
+        .iter()
+        .map(|s| TimedSegment {
+            index: s.index,
+            id: s.index.to_string(),
+            voice_name: None,
+            language_code: Some(report.language_code.clone()),
+            start_ms: s.start_ms,
+            end_ms: s.end_ms,
+            duration_ms: s.start_ms.zip(s.end_ms).map(|(start, end)| end - start),
+            review_status: None,
+            audio_path: Some(report.audio_path.clone()),
+            text: s.text.clone(),
+        })
+        .collect();
+    TimedScript {
+        version: TIMED_SCRIPT_VERSION,
+        source: TimedScriptSource::Alignment,
+        source_path: report.audio_path.clone(),
+        generated_at: Utc::now(),
+        total_duration_ms: report.duration_ms,
+        missing_timing: segments.iter().filter(|s| s.start_ms.is_none()).count(),
+        warnings: report.warnings.clone(),
+        segments,
+    }
+}
+
+/// Transcribes the narration at `audio_path` through Google Speech-to-Text,
+/// with the credentials and quota project synthesis uses, and times each
+/// sentence of `script_text` by the words matched to it. Long WAV and MP3
+/// files are streamed in windows, with "alignment-progress" following the
+/// upload; a window that fails is reported in `unmatched` with its error
+/// and the others kept. With `output_path` the timings are also written as
+/// a timed script, JSON unless `format` says CSV, as `export_timed_script`
+/// writes one.
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn align_audio_to_script(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    exported: tauri::State<'_, ExportedPaths>,
+    audio_path: PathBuf,
+    script_text: String,
+    language_code: String,
+    output_path: Option<PathBuf>,
+    format: Option<TimedScriptFormat>,
+) -> Result<AlignmentReport, AppError> {
+    let audio = roots.validate_path::<Read>(&audio_path)?;
+    let output = match &output_path {
+        Some(path) => {
+            access.check()?;
+            Some(roots.validate_path::<Write>(path)?)
+        }
+        None => None,
+    };
+    let sentences = crate::tts::chunks::script_sentences(&script_text);
+    if sentences.is_empty() {
+        return Err(invalid("script is empty"));
+    }
+    let probe = probe::probe_file(audio.path())?;
+    let bytes = audio.read()?;
+    let prepared = prepare_whole(&bytes, &probe)?;
+
+    let config = RecognitionConfig {
+        encoding: prepared.encoding as i32,
+        sample_rate_hertz: prepared.sample_rate as i32,
+        audio_channel_count: prepared.channels as i32,
+        language_code: language_code.clone(),
+        enable_word_time_offsets: true,
+        enable_word_confidence: true,
+        speech_contexts: vec![SpeechContext {
+            phrases: sentences
+                .iter()
+                .filter(|s| s.chars().count() <= MAX_HINT_CHARS)
+                .take(MAX_HINTS)
+                .cloned()
+                .collect(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let client = tts.speech_client().await?;
+    let quota_project = tts.quota_project(None);
+    let total_bytes: u64 = prepared.windows.iter().map(|w| w.bytes.len() as u64).sum();
+    let mut uploaded = 0;
+    let mut words = Vec::new();
+    let mut failed = Vec::new();
+    let mut first_error = None;
+    for (index, window) in prepared.windows.iter().enumerate() {
+        let result = recognize_window(
+            &app_handle,
+            &client,
+            &config,
+            quota_project.as_deref(),
+            &bytes,
+            window,
+            (index, prepared.windows.len(), total_bytes),
+            &mut uploaded,
+        )
+        .await;
+        match result {
+            Ok(heard) => words.extend(heard),
+            Err(e) => {
+                log::warn!(
+                    "Alignment window {} of {} failed: {}",
+                    index + 1,
+                    audio.path().display(),
+                    e
+                );
+                failed.push(UnmatchedRegion {
+                    start_ms: window.offset_ms,
+                    end_ms: window.offset_ms + window.duration_ms,
+                    transcript: String::new(),
+                    error: Some(e.to_string()),
+                });
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    if words.is_empty() {
+        if let Some(e) = first_error {
+            return Err(e);
+        }
+    }
+
+    let (mut aligned, used) = match_sentences(&sentences, &words);
+    interpolate(&mut aligned);
+    let mut unmatched = unmatched_regions(&words, &used);
+    unmatched.extend(failed);
+    unmatched.sort_by(|a, b| a.start_ms.total_cmp(&b.start_ms));
+    let mut warnings = Vec::new();
+    for sentence in aligned
+        .iter()
+        .filter(|s| s.status != AlignmentStatus::Matched)
+    {
+        warnings.push(format!(
+            "sentence {}: not heard in the recording ({} of {} words)",
+            sentence.index, sentence.matched_words, sentence.words
+        ));
+    }
+    let mut report = AlignmentReport {
+        audio_path: audio.path().to_path_buf(),
+        language_code,
+        duration_ms: probe.duration_ms,
+        matched_sentences: aligned
+            .iter()
+            .filter(|s| s.status == AlignmentStatus::Matched)
+            .count(),
+        sentences: aligned,
+        unmatched,
+        output_path: None,
+        warnings,
+    };
+    if let Some(output) = output {
+        let script = timed_script_of(&report);
+        output.write_atomic(&timed_script::encode(
+            &script,
+            format.unwrap_or(TimedScriptFormat::Json),
+        )?)?;
+        exported.record(output.path());
+        report.output_path = Some(output.into_path_buf());
+    }
+    log::info!(
+        "Aligned {} of {} sentence(s) with {}",
+        report.matched_sentences,
+        report.sentences.len(),
+        report.audio_path.display()
+    );
+    Ok(report)
+}
@@ -0,0 +1,468 @@
+// Arbitration between a voice preview and the project's main playback, so
+// clicking a preview while the timeline plays doesn't mix two streams. The
+// players report what they play; this decides and announces, through
+// "playback-arbitration", whether main playback pauses or ducks and when it
+// comes back, leaving the gain ramp itself to the player holding the audio.
+// The preview's playback rate is decided here the same way: previews are
+// served time-stretched to it by `stretch::at_rate`, "playback-rate" tells
+// the player to fetch the audio at a new rate when it changes, and
+// position is tracked here across rate changes
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::Emitter;
+
+use crate::error::AppError;
+use crate::settings::SettingsStore;
+
+/// How long a preview without a known duration may keep main playback
+/// down before the watchdog gives it back.
+const DEFAULT_PREVIEW_LEASE: Duration = Duration::from_secs(30);
+/// Added to a preview's duration before the watchdog steps in, so a stop
+/// reported a little late doesn't race it.
+const LEASE_GRACE: Duration = Duration::from_millis(750);
+pub const MIN_PLAYBACK_RATE: f32 = 0.5;
+pub const MAX_PLAYBACK_RATE: f32 = 2.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreviewArbitrationMode {
+    /// Pause main playback and resume it after the preview.
+    Pause,
+    /// Lower main playback to `duck_gain` while the preview plays.
+    #[default]
+    Duck,
+    /// Let both play.
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreviewArbitrationSettings {
+    pub mode: PreviewArbitrationMode,
+    /// Gain of main playback while ducked, from 0 to 1.
+    pub duck_gain: f32,
+    /// Length of the gain ramp into and out of the duck, so it never
+    /// sounds like a hard mute.
+    pub ramp_ms: u32,
+}
+
+impl Default for PreviewArbitrationSettings {
+    fn default() -> Self {
+        Self {
+            mode: PreviewArbitrationMode::default(),
+            duck_gain: 0.25,
+            ramp_ms: 120,
+        }
+    }
+}
+
+/// What main playback should do now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackAction {
+    Pause,
+    Resume,
+    Duck,
+    Unduck,
+    /// Nothing changes for main playback.
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArbitrationReason {
+    PreviewStarted,
+    PreviewStopped,
+    /// A preview outlived its lease without being stopped, e.g. its player
+    /// was torn down mid-play.
+    Watchdog,
+    MainStarted,
+    MainStopped,
+}
+
+/// Payload of "playback-arbitration", and what the commands return.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaybackArbitration {
+    pub action: PlaybackAction,
+    pub reason: ArbitrationReason,
+    /// Gain main playback should ramp to over `ramp_ms`.
+    pub main_gain: f32,
+    pub ramp_ms: u32,
+    pub main_playing: bool,
+    /// True while main playback is paused for a preview.
+    pub main_paused: bool,
+    pub active_preview: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Held {
+    #[default]
+    None,
+    Paused,
+    Ducked,
+}
+
+/// Payload of "playback-rate", and what `get_playback_position` returns.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaybackPosition {
+    pub preview_id: Option<String>,
+    /// Into the preview's audio, whatever rate it played at; into the
+    /// audio served at `rate`, it's `position_ms / rate`.
+    pub position_ms: Option<f64>,
+    pub duration_ms: Option<f64>,
+    /// The preview's rate, or the one the next preview starts at.
+    pub rate: f32,
+}
+
+struct ActivePreview {
+    id: String,
+    /// Tells the watchdog of an earlier preview, or an earlier rate of
+    /// this one, it was superseded.
+    generation: u64,
+    duration: Option<Duration>,
+    rate: f32,
+    /// Audio played before `since`, at earlier rates.
+    played: Duration,
+    since: Instant,
+}
+
+impl ActivePreview {
+    fn position(&self) -> Duration {
+        self.played + self.since.elapsed().mul_f32(self.rate)
+    }
+
+    /// How long the watchdog gives it from now, at its rate.
+    fn lease(&self) -> Duration {
+        self.duration.map_or(DEFAULT_PREVIEW_LEASE, |d| {
+            d.saturating_sub(self.played).div_f32(self.rate) + LEASE_GRACE
+        })
+    }
+}
+
+/// Why `rate` isn't a playback rate, if it isn't one.
+pub fn check_playback_rate(rate: f32) -> Result<(), String> {
+    if (MIN_PLAYBACK_RATE..=MAX_PLAYBACK_RATE).contains(&rate) {
+        Ok(())
+    } else {
+        Err(format!(
+            "must be {} to {}, not {}",
+            MIN_PLAYBACK_RATE, MAX_PLAYBACK_RATE, rate
+        ))
+    }
+}
+
+#[derive(Default)]
+struct Arbitration {
+    main_playing: bool,
+    preview: Option<ActivePreview>,
+    /// What this arbiter currently does to main playback.
+    held: Held,
+    generation: u64,
+}
+
+/// Managed state arbitrating previews against main playback.
+#[derive(Default)]
+pub struct PlaybackArbiter {
+    state: Arc<Mutex<Arbitration>>,
+}
+
+impl Arbitration {
+    /// Brings main playback in line with whether a preview plays, returning
+    /// what changed.
+    fn settle(&mut self, settings: &PreviewArbitrationSettings) -> PlaybackAction {
+        let want = match (&self.preview, self.main_playing, settings.mode) {
+            (Some(_), true, PreviewArbitrationMode::Pause) => Held::Paused,
+            (Some(_), true, PreviewArbitrationMode::Duck) => Held::Ducked,
+            _ => Held::None,
+        };
+        let action = match (self.held, want) {
+            (Held::None, Held::Paused) => PlaybackAction::Pause,
+            (Held::None, Held::Ducked) => PlaybackAction::Duck,
+            (Held::Paused, Held::None) => PlaybackAction::Resume,
+            (Held::Ducked, Held::None) => PlaybackAction::Unduck,
+            // Already held one way or the other: a mode change applies from
+            // the next preview
+            _ => return PlaybackAction::None,
+        };
+        self.held = want;
+        action
+    }
+
+    fn report(
+        &self,
+        action: PlaybackAction,
+        reason: ArbitrationReason,
+        settings: &PreviewArbitrationSettings,
+    ) -> PlaybackArbitration {
+        PlaybackArbitration {
+            action,
+            reason,
+            main_gain: match self.held {
+                Held::Ducked => settings.duck_gain.clamp(0.0, 1.0),
+                Held::None | Held::Paused => 1.0,
+            },
+            ramp_ms: settings.ramp_ms,
+            main_playing: self.main_playing,
+            main_paused: self.held == Held::Paused,
+            active_preview: self.preview.as_ref().map(|p| p.id.clone()),
+        }
+    }
+}
+
+fn settings(app_handle: &tauri::AppHandle) -> PreviewArbitrationSettings {
+    use tauri::Manager;
+
+    app_handle
+        .try_state::<SettingsStore>()
+        .map(|settings| settings.get().preview_arbitration)
+        .unwrap_or_default()
+}
+
+/// The rate previews start at, by the settings.
+pub(crate) fn default_rate(app_handle: &tauri::AppHandle) -> f32 {
+    use tauri::Manager;
+
+    app_handle
+        .try_state::<SettingsStore>()
+        .map(|settings| settings.get().preview_playback_rate)
+        .filter(|&rate| check_playback_rate(rate).is_ok())
+        .unwrap_or(1.0)
+}
+
+fn announce(app_handle: &tauri::AppHandle, arbitration: &PlaybackArbitration) {
+    if let Err(e) = app_handle.emit("playback-arbitration", arbitration) {
+        log::warn!("Failed to emit playback-arbitration: {}", e);
+    }
+}
+
+impl PlaybackArbiter {
+    fn apply(
+        &self,
+        app_handle: &tauri::AppHandle,
+        reason: ArbitrationReason,
+        f: impl FnOnce(&mut Arbitration),
+    ) -> PlaybackArbitration {
+        let settings = settings(app_handle);
+        let arbitration = {
+            let mut state = self.state.lock().unwrap();
+            f(&mut state);
+            let action = state.settle(&settings);
+            state.report(action, reason, &settings)
+        };
+        if arbitration.action != PlaybackAction::None {
+            announce(app_handle, &arbitration);
+        }
+        arbitration
+    }
+
+    /// Makes `preview_id` the active preview, replacing any other, and
+    /// arms the watchdog for it.
+    pub fn start_preview(
+        &self,
+        app_handle: &tauri::AppHandle,
+        preview_id: String,
+        duration: Option<Duration>,
+    ) -> PlaybackArbitration {
+        let rate = default_rate(app_handle);
+        let mut armed = (0, DEFAULT_PREVIEW_LEASE);
+        let arbitration = self.apply(app_handle, ArbitrationReason::PreviewStarted, |state| {
+            state.generation += 1;
+            let preview = ActivePreview {
+                id: preview_id,
+                generation: state.generation,
+                duration,
+                rate,
+                played: Duration::ZERO,
+                since: Instant::now(),
+            };
+            armed = (preview.generation, preview.lease());
+            state.preview = Some(preview);
+        });
+        self.arm_watchdog(app_handle, armed.0, armed.1);
+        arbitration
+    }
+
+    /// Gives back main playback after `lease` unless the preview was
+    /// stopped or superseded by then.
+    fn arm_watchdog(&self, app_handle: &tauri::AppHandle, generation: u64, lease: Duration) {
+        let state = self.state.clone();
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            tokio::time::sleep(lease).await;
+            // Checked under the same lock that clears it, so a preview
+            // started meanwhile is left alone
+            PlaybackArbiter { state }.apply(&app_handle, ArbitrationReason::Watchdog, |state| {
+                if state
+                    .preview
+                    .as_ref()
+                    .is_some_and(|p| p.generation == generation)
+                {
+                    log::warn!(
+                        "Preview still active after {:?}, restoring main playback",
+                        lease
+                    );
+                    state.preview = None;
+                }
+            });
+        });
+    }
+
+    /// Plays the active preview, and every later one, at `rate`, keeping
+    /// its position; the watchdog is re-armed for the time left at it.
+    pub fn set_rate(&self, app_handle: &tauri::AppHandle, rate: f32) -> PlaybackPosition {
+        let mut armed = None;
+        {
+            let mut state = self.state.lock().unwrap();
+            state.generation += 1;
+            let generation = state.generation;
+            if let Some(preview) = state.preview.as_mut() {
+                preview.played = preview.position();
+                preview.since = Instant::now();
+                preview.rate = rate;
+                preview.generation = generation;
+                armed = Some((generation, preview.lease()));
+            }
+        }
+        if let Some((generation, lease)) = armed {
+            self.arm_watchdog(app_handle, generation, lease);
+        }
+        let mut position = self.position(app_handle);
+        position.rate = rate;
+        if let Err(e) = app_handle.emit("playback-rate", &position) {
+            log::warn!("Failed to emit playback-rate: {}", e);
+        }
+        position
+    }
+
+    pub fn position(&self, app_handle: &tauri::AppHandle) -> PlaybackPosition {
+        let state = self.state.lock().unwrap();
+        match &state.preview {
+            Some(preview) => {
+                let position = preview.position();
+                PlaybackPosition {
+                    preview_id: Some(preview.id.clone()),
+                    // Capped, as a player holds at the end until it reports the stop
+                    position_ms: Some(
+                        preview
+                            .duration
+                            .map_or(position, |d| position.min(d))
+                            .as_secs_f64()
+                            * 1000.0,
+                    ),
+                    duration_ms: preview.duration.map(|d| d.as_secs_f64() * 1000.0),
+                    rate: preview.rate,
+                }
+            }
+            None => PlaybackPosition {
+                preview_id: None,
+                position_ms: None,
+                duration_ms: None,
+                rate: default_rate(app_handle),
+            },
+        }
+    }
+
+    /// Ends `preview_id`; a stop for a preview already replaced by a newer
+    /// one changes nothing.
+    pub fn stop_preview(
+        &self,
+        app_handle: &tauri::AppHandle,
+        preview_id: &str,
+    ) -> PlaybackArbitration {
+        self.apply(app_handle, ArbitrationReason::PreviewStopped, |state| {
+            if state.preview.as_ref().is_some_and(|p| p.id == preview_id) {
+                state.preview = None;
+            }
+        })
+    }
+
+    pub fn set_main_playing(
+        &self,
+        app_handle: &tauri::AppHandle,
+        playing: bool,
+    ) -> PlaybackArbitration {
+        let reason = if playing {
+            ArbitrationReason::MainStarted
+        } else {
+            ArbitrationReason::MainStopped
+        };
+        self.apply(app_handle, reason, |state| {
+            state.main_playing = playing;
+            // Stopped by the user: nothing to resume or unduck later
+            if !playing {
+                state.held = Held::None;
+            }
+        })
+    }
+}
+
+/// Reports that a voice preview started playing; `duration_ms`, if known,
+/// bounds how long it may hold main playback down.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn start_preview_playback(
+    app_handle: tauri::AppHandle,
+    arbiter: tauri::State<'_, PlaybackArbiter>,
+    preview_id: String,
+    duration_ms: Option<u64>,
+) -> PlaybackArbitration {
+    arbiter.start_preview(
+        &app_handle,
+        preview_id,
+        duration_ms.map(Duration::from_millis),
+    )
+}
+
+/// Reports that a voice preview ended or was stopped.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn stop_preview_playback(
+    app_handle: tauri::AppHandle,
+    arbiter: tauri::State<'_, PlaybackArbiter>,
+    preview_id: String,
+) -> PlaybackArbitration {
+    arbiter.stop_preview(&app_handle, &preview_id)
+}
+
+/// Reports that the project's main playback started or stopped by the
+/// user; a pause asked for by "playback-arbitration" isn't reported back.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn set_main_playback(
+    app_handle: tauri::AppHandle,
+    arbiter: tauri::State<'_, PlaybackArbiter>,
+    playing: bool,
+) -> PlaybackArbitration {
+    arbiter.set_main_playing(&app_handle, playing)
+}
+
+/// Sets the rate previews play at, from 0.5 to 2.0, and keeps it as the
+/// default. A preview playing now changes speed without re-synthesis: its
+/// player fetches the audio stretched to the new rate and carries on from
+/// the position "playback-rate" reports.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn set_playback_rate(
+    app_handle: tauri::AppHandle,
+    arbiter: tauri::State<'_, PlaybackArbiter>,
+    settings: tauri::State<'_, SettingsStore>,
+    rate: f32,
+) -> Result<PlaybackPosition, AppError> {
+    check_playback_rate(rate).map_err(|message| AppError::InvalidAudioRequest {
+        message: format!("playback rate {}", message),
+    })?;
+    settings.update(|s| s.preview_playback_rate = rate)?;
+    Ok(arbiter.set_rate(&app_handle, rate))
+}
+
+/// Where the active preview is, at what rate; with none playing, the rate
+/// the next one starts at.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_playback_position(
+    app_handle: tauri::AppHandle,
+    arbiter: tauri::State<'_, PlaybackArbiter>,
+) -> PlaybackPosition {
+    arbiter.position(&app_handle)
+}
@@ -0,0 +1,194 @@
+// Channel layout of rendered audio. Google and the OS voices speak mono,
+// which some editors place on the left channel only when muxed into a
+// stereo timeline, so outputs can be made stereo (or mono) on the way out
+use super::mp3::{self, FrameHeader};
+use super::placeholder::wav_header;
+use super::stretch::{read_wav, Pcm};
+use crate::error::AppError;
+
+/// Gain of each channel in a stereo-to-mono downmix: the -3 dB pan law, so
+/// a centred voice keeps its loudness instead of gaining 6 dB.
+const DOWNMIX_GAIN: f32 = std::f32::consts::FRAC_1_SQRT_2;
+/// MPEG channel mode of a single-channel stream.
+const MPEG_MONO: u8 = 0b11;
+
+/// Validates a requested channel count, which is 1 or 2.
+pub fn check(channels: Option<u8>) -> Result<Option<u16>, AppError> {
+    match channels {
+        None => Ok(None),
+        Some(c @ (1 | 2)) => Ok(Some(c as u16)),
+        Some(c) => Err(AppError::InvalidAudioRequest {
+            message: format!("channels must be 1 or 2, not {}", c),
+        }),
+    }
+}
+
+/// `pcm` with `channels` channels. Mono becomes stereo by copying each
+/// sample to both sides, so the two are sample-identical; stereo becomes
+/// mono by summing the sides at `DOWNMIX_GAIN`.
+pub(super) fn remix(pcm: Pcm, channels: u16) -> Result<Pcm, AppError> {
+    let samples = match (pcm.channels, channels) {
+        (from, to) if from == to => return Ok(pcm),
+        (1, 2) => pcm.samples.iter().flat_map(|&s| [s, s]).collect(),
+        (2, 1) => pcm
+            .samples
+            .chunks_exact(2)
+            .map(|side| {
+                let mixed = (side[0] as f32 + side[1] as f32) * DOWNMIX_GAIN;
+                mixed.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+            })
+            .collect(),
+        (from, to) => {
+            return Err(AppError::InvalidAudioRequest {
+                message: format!("can't remix {} channels to {}", from, to),
+            })
+        }
+    };
+    Ok(Pcm {
+        sample_rate: pcm.sample_rate,
+        channels,
+        samples,
+    })
+}
+
+/// A 16-bit PCM WAV of `pcm`.
+pub(super) fn encode_wav(pcm: &Pcm) -> Result<Vec<u8>, AppError> {
+    let mut wav = wav_header(pcm.sample_rate, pcm.channels, pcm.frames() as u64)?.to_vec();
+    wav.reserve(pcm.samples.len() * 2);
+    for sample in &pcm.samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    Ok(wav)
+}
+
+/// Channel count of WAV or MP3 audio, from its header or first frame.
+pub fn count(bytes: &[u8]) -> Option<u16> {
+    if bytes.starts_with(b"RIFF") {
+        return read_wav(bytes).ok().map(|pcm| pcm.channels);
+    }
+    let header = FrameHeader::parse(bytes.get(mp3::id3v2_len(bytes)..)?)?;
+    Some(if header.channel_mode == MPEG_MONO {
+        1
+    } else {
+        2
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A second of a 440 Hz tone at 16 kHz, on one channel.
+    fn tone() -> Pcm {
+        let samples = (0..16_000)
+            .map(|i| {
+                let t = i as f32 / 16_000.0;
+                ((t * 440.0 * std::f32::consts::TAU).sin() * 8000.0).round() as i16
+            })
+            .collect();
+        Pcm {
+            sample_rate: 16_000,
+            channels: 1,
+            samples,
+        }
+    }
+
+    /// RMS of channel `channel` of `pcm`.
+    fn rms(pcm: &Pcm, channel: usize) -> f64 {
+        let side: Vec<f64> = pcm
+            .samples
+            .iter()
+            .skip(channel)
+            .step_by(pcm.channels as usize)
+            .map(|&s| s as f64)
+            .collect();
+        (side.iter().map(|s| s * s).sum::<f64>() / side.len() as f64).sqrt()
+    }
+
+    fn sides(pcm: &Pcm, channel: usize) -> Vec<i16> {
+        pcm.samples
+            .iter()
+            .skip(channel)
+            .step_by(pcm.channels as usize)
+            .copied()
+            .collect()
+    }
+
+    #[test]
+    fn accepts_only_one_or_two_channels() {
+        assert_eq!(check(None).unwrap(), None);
+        assert_eq!(check(Some(1)).unwrap(), Some(1));
+        assert_eq!(check(Some(2)).unwrap(), Some(2));
+        assert!(matches!(
+            check(Some(6)),
+            Err(AppError::InvalidAudioRequest { .. })
+        ));
+        assert!(check(Some(0)).is_err());
+    }
+
+    #[test]
+    fn duplicated_channels_are_sample_identical() {
+        let mono = tone();
+        let stereo = remix(tone(), 2).unwrap();
+        assert_eq!(stereo.channels, 2);
+        assert_eq!(stereo.frames(), mono.frames());
+        assert_eq!(sides(&stereo, 0), mono.samples);
+        assert_eq!(sides(&stereo, 1), mono.samples);
+        assert_eq!(rms(&stereo, 0), rms(&mono, 0));
+        assert_eq!(rms(&stereo, 1), rms(&mono, 0));
+    }
+
+    #[test]
+    fn downmixes_at_the_pan_law() {
+        let mono = tone();
+        let back = remix(remix(tone(), 2).unwrap(), 1).unwrap();
+        assert_eq!(back.channels, 1);
+        assert_eq!(back.frames(), mono.frames());
+        // Both sides summed at -3 dB each: a centred voice comes out 3 dB up
+        let gain = rms(&back, 0) / rms(&mono, 0);
+        assert!((gain - std::f64::consts::SQRT_2).abs() < 1e-3, "{}", gain);
+
+        let loud = Pcm {
+            sample_rate: 16_000,
+            channels: 2,
+            samples: vec![i16::MAX, i16::MAX, i16::MIN, i16::MIN, 100, -100],
+        };
+        assert_eq!(remix(loud, 1).unwrap().samples, [i16::MAX, i16::MIN, 0]);
+    }
+
+    #[test]
+    fn keeps_the_layout_asked_for_and_refuses_others() {
+        let same = remix(tone(), 1).unwrap();
+        assert_eq!(same.samples, tone().samples);
+        let surround = Pcm {
+            sample_rate: 16_000,
+            channels: 6,
+            samples: vec![0; 12],
+        };
+        assert!(remix(surround, 2).is_err());
+    }
+
+    #[test]
+    fn counts_channels_of_wav_and_mp3() {
+        let mono = tone();
+        assert_eq!(count(&encode_wav(&mono).unwrap()), Some(1));
+        let stereo = encode_wav(&remix(tone(), 2).unwrap()).unwrap();
+        assert_eq!(count(&stereo), Some(2));
+        assert_eq!(
+            read_wav(&stereo).unwrap().samples,
+            remix(tone(), 2).unwrap().samples
+        );
+
+        // MPEG-1 Layer III frame headers, joint stereo and mono
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x44];
+        frame.resize(417, 0);
+        assert_eq!(count(&frame), Some(2));
+        frame[3] = 0xC4;
+        assert_eq!(count(&frame), Some(1));
+        // Behind an ID3v2 tag of 10 bytes with an empty body
+        let mut tagged = b"ID3\x04\x00\x00\x00\x00\x00\x00".to_vec();
+        tagged.extend_from_slice(&frame);
+        assert_eq!(count(&tagged), Some(1));
+        assert_eq!(count(b"not audio"), None);
+    }
+}
@@ -0,0 +1,617 @@
+// Lossless joining of MP3s made with the same encoding, as every chunk and
+// segment of one voice and config is. Their frames are copied as they are
+// under one rebuilt Xing/Info header whose LAME tag carries the first
+// input's encoder delay and the last one's padding, so gapless players trim
+// the ends exactly. Inputs that don't match are refused: appending their
+// bytes plays with the first input's timing and seeking, and the app has no
+// MP3 encoder to re-encode them with. An output preset that needs PCM joins
+// the decoded audio instead, into a WAV in its format
+use serde::Serialize;
+use std::path::PathBuf;
+
+use super::mp3::{self, Frame, FrameHeader, MpegVersion};
+use super::presets::{OutputFormat, OutputOptions};
+use super::stretch::Pcm;
+use super::{channels, decode, stretch};
+use crate::cancellation::{CancellationRegistry, CancellationToken};
+use crate::error::AppError;
+use crate::paths::roots::{AllowedPath, AllowedRoots, Read, Write};
+use crate::read_only::WriteAccess;
+use crate::settings::SettingsStore;
+
+/// Most files one `concat_audio` call joins.
+const MAX_INPUTS: usize = 1000;
+/// Bytes of the LAME tag after the Xing fields.
+const LAME_TAG_LEN: usize = 36;
+/// Xing flags for frame count, byte count, seek table and quality.
+const XING_FLAGS: u32 = 0x0F;
+const TOC_LEN: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct Concatenated {
+    pub audio: Vec<u8>,
+    /// Silence left at each join, from the encoder padding and delay that
+    /// only whole frames could be dropped of; `None` where an input has no
+    /// LAME tag to tell.
+    pub join_gaps_ms: Vec<Option<f64>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConcatReport {
+    pub output_path: PathBuf,
+    pub join_gaps_ms: Vec<Option<f64>>,
+    pub bytes: usize,
+    pub duration_ms: Option<f64>,
+}
+
+/// Encoder delay and padding, in samples, from a LAME (or Lavc) tag.
+#[derive(Debug, Clone, Copy)]
+struct Gapless {
+    delay: u32,
+    padding: u32,
+}
+
+struct Input<'a> {
+    bytes: &'a [u8],
+    /// Audio frames, without the Xing/Info frame.
+    frames: Vec<Frame>,
+    /// The 36 bytes of the LAME tag and what they say.
+    lame: Option<([u8; LAME_TAG_LEN], Gapless)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Bitrate {
+    Constant(u32),
+    Variable,
+}
+
+impl Bitrate {
+    fn describe(self) -> String {
+        match self {
+            Bitrate::Constant(kbps) => format!("{} kbps", kbps),
+            Bitrate::Variable => "variable".to_string(),
+        }
+    }
+}
+
+/// Bytes of Layer III side info after the header and CRC.
+fn side_info_len(header: &FrameHeader) -> usize {
+    let mono = header.channel_mode == 0b11;
+    match (header.version, mono) {
+        (MpegVersion::Mpeg1, true) => 17,
+        (MpegVersion::Mpeg1, false) => 32,
+        (_, true) => 9,
+        (_, false) => 17,
+    }
+}
+
+fn has_crc(frame: &[u8]) -> bool {
+    frame[1] & 1 == 0
+}
+
+fn xing_offset(frame: &[u8], header: &FrameHeader) -> usize {
+    4 + if has_crc(frame) { 2 } else { 0 } + side_info_len(header)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// The LAME tag of a Xing/Info frame, found after whichever Xing fields
+/// its flags say are present.
+fn lame_tag(frame: &[u8], header: &FrameHeader) -> Option<([u8; LAME_TAG_LEN], Gapless)> {
+    let offset = xing_offset(frame, header);
+    let xing = frame.get(offset..offset + 8)?;
+    if &xing[..4] != b"Xing" && &xing[..4] != b"Info" {
+        return None;
+    }
+    let flags = read_u32(&xing[4..]);
+    let fields = [(1, 4), (2, 4), (4, TOC_LEN), (8, 4)];
+    let position = offset
+        + 8
+        + fields
+            .iter()
+            .filter(|(bit, _)| flags & bit != 0)
+            .map(|(_, len)| len)
+            .sum::<usize>();
+    let tag: [u8; LAME_TAG_LEN] = frame
+        .get(position..position + LAME_TAG_LEN)?
+        .try_into()
+        .ok()?;
+    // An encoder name such as "LAME3.100" or "Lavc59.37"
+    if !tag[..4].iter().all(u8::is_ascii_alphanumeric) {
+        return None;
+    }
+    let gapless = Gapless {
+        delay: (tag[21] as u32) << 4 | (tag[22] >> 4) as u32,
+        padding: ((tag[22] & 0x0F) as u32) << 8 | tag[23] as u32,
+    };
+    Some((tag, gapless))
+}
+
+fn parse(bytes: &[u8]) -> Option<Input<'_>> {
+    let tag_len = mp3::id3v2_len(bytes).min(bytes.len());
+    let mut frames = mp3::frames(bytes, tag_len);
+    let mut lame = None;
+    if let Some(first) = frames.first() {
+        let frame = &bytes[first.start..first.start + first.len];
+        if mp3::is_vbr_header(frame) {
+            lame = lame_tag(frame, &first.header);
+            frames.remove(0);
+        }
+    }
+    (!frames.is_empty()).then_some(Input {
+        bytes,
+        frames,
+        lame,
+    })
+}
+
+fn same_stream(a: &FrameHeader, b: &FrameHeader) -> bool {
+    a.version == b.version
+        && a.layer == b.layer
+        && a.sample_rate == b.sample_rate
+        && a.channel_mode == b.channel_mode
+}
+
+fn bitrate(input: &Input) -> Bitrate {
+    let first = input.frames[0].header.bitrate_kbps;
+    if input.frames.iter().all(|f| f.header.bitrate_kbps == first) {
+        Bitrate::Constant(first)
+    } else {
+        Bitrate::Variable
+    }
+}
+
+/// `main_data_begin`: how far back into earlier frames this frame's audio
+/// data starts. Non-zero in the first frame of a file means it borrows from
+/// a frame that was never there.
+fn main_data_begin(frame: &[u8], header: &FrameHeader) -> u32 {
+    let at = 4 + if has_crc(frame) { 2 } else { 0 };
+    let Some(bytes) = frame.get(at..at + 2) else {
+        return 0;
+    };
+    let bits = u16::from_be_bytes([bytes[0], bytes[1]]) as u32;
+    match header.version {
+        MpegVersion::Mpeg1 => bits >> 7,
+        MpegVersion::Mpeg2 | MpegVersion::Mpeg25 => bits >> 8,
+    }
+}
+
+/// CRC-16 as LAME computes its tag and music checksums.
+fn crc16(mut crc: u16, bytes: &[u8]) -> u16 {
+    for &b in bytes {
+        crc ^= b as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// A header like `model`'s without CRC or padding, at `preferred` if its
+/// frame holds `needed` bytes, else at the lowest bitrate that does.
+fn info_header(model: &[u8], preferred: Option<u32>, needed: usize) -> Option<[u8; 4]> {
+    let candidates: Vec<([u8; 4], u32)> = (1..15u8)
+        .filter_map(|index| {
+            let bytes = [
+                model[0],
+                model[1] | 1,
+                (model[2] & 0x0D) | (index << 4),
+                model[3],
+            ];
+            let header = FrameHeader::parse(&bytes)?;
+            (header.frame_len()? >= needed).then_some((bytes, header.bitrate_kbps))
+        })
+        .collect();
+    candidates
+        .iter()
+        .find(|(_, kbps)| Some(*kbps) == preferred)
+        .or(candidates.first())
+        .map(|(bytes, _)| *bytes)
+}
+
+/// The Xing/Info frame for `audio`, frames of equal length in time that
+/// start at `offsets`.
+fn info_frame(
+    model: &Frame,
+    model_bytes: &[u8],
+    bitrate: Bitrate,
+    offsets: &[usize],
+    audio: &[u8],
+    lame: Option<([u8; LAME_TAG_LEN], Gapless)>,
+) -> Option<Vec<u8>> {
+    let xing_at = 4 + side_info_len(&model.header);
+    let lame_at = xing_at + 8 + 4 + 4 + TOC_LEN + 4;
+    let needed = lame_at + if lame.is_some() { LAME_TAG_LEN } else { 0 };
+    let preferred = match bitrate {
+        Bitrate::Constant(kbps) => Some(kbps),
+        Bitrate::Variable => None,
+    };
+    let header = info_header(model_bytes, preferred, needed)?;
+    let len = FrameHeader::parse(&header)?.frame_len()?;
+    let total = len + audio.len();
+
+    let mut frame = vec![0u8; len];
+    frame[..4].copy_from_slice(&header);
+    let name: &[u8; 4] = match bitrate {
+        Bitrate::Constant(_) => b"Info",
+        Bitrate::Variable => b"Xing",
+    };
+    frame[xing_at..xing_at + 4].copy_from_slice(name);
+    frame[xing_at + 4..xing_at + 8].copy_from_slice(&XING_FLAGS.to_be_bytes());
+    frame[xing_at + 8..xing_at + 12].copy_from_slice(&(offsets.len() as u32).to_be_bytes());
+    frame[xing_at + 12..xing_at + 16].copy_from_slice(&(total as u32).to_be_bytes());
+    // Seek table: where each percent of the playing time starts, in 256ths
+    // of the stream; every frame lasts as long as the others
+    let toc_at = xing_at + 16;
+    for i in 0..TOC_LEN {
+        let offset = len + offsets[offsets.len() * i / TOC_LEN];
+        frame[toc_at + i] = (offset * 256 / total).min(255) as u8;
+    }
+    if let Some((mut tag, gapless)) = lame {
+        tag[21] = (gapless.delay >> 4) as u8;
+        tag[22] = ((gapless.delay & 0x0F) << 4) as u8 | (gapless.padding >> 8) as u8;
+        tag[23] = gapless.padding as u8;
+        tag[28..32].copy_from_slice(&(total as u32).to_be_bytes());
+        tag[32..34].copy_from_slice(&crc16(0, audio).to_be_bytes());
+        frame[lame_at..lame_at + LAME_TAG_LEN].copy_from_slice(&tag);
+        let tag_crc = crc16(0, &frame[..lame_at + LAME_TAG_LEN - 2]);
+        frame[lame_at + LAME_TAG_LEN - 2..lame_at + LAME_TAG_LEN]
+            .copy_from_slice(&tag_crc.to_be_bytes());
+    }
+    Some(frame)
+}
+
+fn frame_concat(parts: &[Vec<u8>]) -> Result<Concatenated, String> {
+    let inputs = parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            parse(part).ok_or_else(|| format!("input {} has no MPEG audio frames", i + 1))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let Some(first) = inputs.first() else {
+        return Err("no inputs".to_string());
+    };
+    let model = first.frames[0];
+    if model.header.layer != 3 {
+        return Err("only Layer III streams are joined frame by frame".to_string());
+    }
+
+    let bitrates: Vec<Bitrate> = inputs.iter().map(bitrate).collect();
+    for (i, input) in inputs.iter().enumerate() {
+        if !input
+            .frames
+            .iter()
+            .all(|f| same_stream(&f.header, &model.header))
+        {
+            return Err(format!(
+                "input {} differs from input 1 in MPEG version, sample rate or channel mode",
+                i + 1
+            ));
+        }
+        if bitrates[i] != bitrates[0] {
+            return Err(format!(
+                "input {} differs from input 1 in bitrate ({} against {})",
+                i + 1,
+                bitrates[i].describe(),
+                bitrates[0].describe()
+            ));
+        }
+        let frame = &input.frames[0];
+        if i > 0 && main_data_begin(&input.bytes[frame.start..], &frame.header) != 0 {
+            return Err(format!(
+                "input {} starts with data borrowed from before it",
+                i + 1
+            ));
+        }
+    }
+
+    let samples_per_frame = model.header.samples();
+    let sample_rate = model.header.sample_rate as f64;
+    let mut audio = Vec::with_capacity(parts.iter().map(Vec::len).sum());
+    let mut offsets = Vec::new();
+    // Padding still played at the end of each input
+    let mut paddings = Vec::with_capacity(inputs.len());
+    for (i, input) in inputs.iter().enumerate() {
+        let mut kept = input.frames.len();
+        let mut padding = input.lame.map(|(_, g)| g.padding);
+        // Frames made only of padding go, except after the last input,
+        // where the tag tells players to trim it; decoding what's kept
+        // doesn't depend on frames after it
+        if let Some(samples) = padding.filter(|_| i + 1 < inputs.len()) {
+            let whole = ((samples / samples_per_frame) as usize).min(kept - 1);
+            kept -= whole;
+            padding = Some(samples - whole as u32 * samples_per_frame);
+        }
+        for frame in &input.frames[..kept] {
+            offsets.push(audio.len());
+            audio.extend_from_slice(&input.bytes[frame.start..frame.start + frame.len]);
+        }
+        paddings.push(padding);
+    }
+    let join_gaps_ms = (1..inputs.len())
+        .map(|i| {
+            let padding = paddings[i - 1]?;
+            let delay = inputs[i].lame?.1.delay;
+            Some((padding + delay) as f64 * 1000.0 / sample_rate)
+        })
+        .collect();
+
+    let lame = first.lame.map(|(tag, gapless)| {
+        let padding = paddings.last().copied().flatten().unwrap_or(0);
+        (
+            tag,
+            Gapless {
+                delay: gapless.delay,
+                padding,
+            },
+        )
+    });
+    let info = info_frame(
+        &model,
+        &first.bytes[model.start..model.start + 4],
+        bitrates[0],
+        &offsets,
+        &audio,
+        lame,
+    )
+    .ok_or_else(|| "no bitrate fits the rebuilt VBR header".to_string())?;
+
+    let tag_len = mp3::id3v2_len(first.bytes).min(first.bytes.len());
+    let mut joined = Vec::with_capacity(tag_len + info.len() + audio.len());
+    joined.extend_from_slice(&first.bytes[..tag_len]);
+    joined.extend_from_slice(&info);
+    joined.extend_from_slice(&audio);
+    Ok(Concatenated {
+        audio: joined,
+        join_gaps_ms,
+    })
+}
+
+/// Joins `parts` frame by frame; they must share MPEG version, sample rate,
+/// channel mode and bitrate (or all be VBR).
+pub fn concat(parts: &[Vec<u8>]) -> Result<Concatenated, AppError> {
+    frame_concat(parts).map_err(|reason| AppError::InvalidAudioRequest {
+        message: format!("can't join the MP3s losslessly: {}", reason),
+    })
+}
+
+/// Joins `parts`, MP3s or WAVs, decoded: each is brought to the first
+/// one's sample rate and channels, and the whole rendered in `format`.
+/// MP3s lose their encoder delay and padding when decoded, so the joins
+/// are gapless.
+pub fn concat_pcm(parts: &[Vec<u8>], format: &OutputFormat) -> Result<Vec<u8>, AppError> {
+    let mut joined: Option<Pcm> = None;
+    for part in parts {
+        let pcm = decode::decode(part)?;
+        match &mut joined {
+            None => joined = Some(pcm),
+            Some(joined) => {
+                let pcm = super::resample::resample(
+                    &pcm,
+                    joined.sample_rate,
+                    &std::sync::atomic::AtomicBool::new(false),
+                )
+                .ok_or_else(|| AppError::InvalidAudioRequest {
+                    message: "resampling was cancelled".to_string(),
+                })?;
+                let pcm = channels::remix(pcm, joined.channels)?;
+                joined.samples.extend_from_slice(&pcm.samples);
+            }
+        }
+    }
+    let joined = joined.ok_or_else(|| AppError::InvalidAudioRequest {
+        message: "nothing to join".to_string(),
+    })?;
+    format.render(&channels::encode_wav(&joined)?)
+}
+
+/// Reads `inputs` and writes them joined to `output`, checking `token`
+/// before every file is read and before the output is written.
+fn join_files(
+    inputs: &[AllowedPath<Read>],
+    output: &AllowedPath<Write>,
+    format: &OutputFormat,
+    token: &CancellationToken,
+) -> Result<ConcatReport, AppError> {
+    let mut parts = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        token.check()?;
+        let bytes = input.read()?;
+        // Only decoded inputs can be WAVs
+        let wav = format.needs_pcm() && bytes.starts_with(b"RIFF");
+        if !(wav || mp3::is_plausible_mp3(&bytes)) {
+            return Err(AppError::InvalidAudioRequest {
+                message: format!("{} is not an MP3", input.path().display()),
+            });
+        }
+        parts.push(bytes);
+    }
+    if format.needs_pcm() {
+        let audio = concat_pcm(&parts, format)?;
+        token.check()?;
+        output.write_atomic(&audio)?;
+        return Ok(ConcatReport {
+            output_path: output.path().to_path_buf(),
+            join_gaps_ms: vec![Some(0.0); parts.len() - 1],
+            bytes: audio.len(),
+            duration_ms: stretch::wav_duration_ms(&audio),
+        });
+    }
+    let concatenated = concat(&parts)?;
+    token.check()?;
+    output.write_atomic(&concatenated.audio)?;
+    Ok(ConcatReport {
+        output_path: output.path().to_path_buf(),
+        join_gaps_ms: concatenated.join_gaps_ms,
+        bytes: concatenated.audio.len(),
+        duration_ms: mp3::duration_ms(&concatenated.audio),
+    })
+}
+
+/// Joins the MP3s at `input_paths`, in order, into `output_path`, frame by
+/// frame; inputs of different encodings are refused. A `preset` that needs
+/// PCM joins the decoded MP3s or WAVs instead and writes a WAV in its
+/// format. A cancelled join writes nothing.
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn concat_audio(
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    settings: tauri::State<'_, SettingsStore>,
+    cancellation: tauri::State<'_, CancellationRegistry>,
+    input_paths: Vec<PathBuf>,
+    output_path: PathBuf,
+    preset: Option<String>,
+    cancel_token: Option<String>,
+) -> Result<ConcatReport, AppError> {
+    access.check()?;
+    let format = OutputOptions {
+        preset,
+        ..OutputOptions::default()
+    }
+    .resolve(&settings.get())?;
+    let extension = format.extension();
+    if !output_path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(extension))
+    {
+        return Err(AppError::InvalidAudioRequest {
+            message: format!("this preset writes a .{} file", extension),
+        });
+    }
+    if input_paths.is_empty() || input_paths.len() > MAX_INPUTS {
+        return Err(AppError::InvalidAudioRequest {
+            message: format!("between 1 and {} input files can be joined", MAX_INPUTS),
+        });
+    }
+    let inputs = input_paths
+        .iter()
+        .map(|p| roots.validate_path::<Read>(p))
+        .collect::<Result<Vec<_>, _>>()?;
+    let output = roots.validate_path::<Write>(&output_path)?;
+    let token = cancellation.register(cancel_token, "concat_audio")?;
+    tauri::async_runtime::spawn_blocking(move || join_files(&inputs, &output, &format, &token))
+        .await
+        .map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    /// `count` silent frames under `header`.
+    fn stream(header: [u8; 4], count: usize) -> Vec<u8> {
+        let len = FrameHeader::parse(&header).unwrap().frame_len().unwrap();
+        let mut frame = vec![0u8; len];
+        frame[..4].copy_from_slice(&header);
+        frame.repeat(count)
+    }
+
+    /// MPEG-1 Layer III, mono, 128 kbps at 44.1 kHz.
+    const CD_RATE: [u8; 4] = [0xFF, 0xFB, 0x90, 0xC0];
+    /// The same at 48 kHz.
+    const DAT_RATE: [u8; 4] = [0xFF, 0xFB, 0x94, 0xC0];
+    /// The same at 44.1 kHz and 64 kbps.
+    const LOW_BITRATE: [u8; 4] = [0xFF, 0xFB, 0x50, 0xC0];
+
+    #[test]
+    fn joins_matching_inputs_frame_by_frame() {
+        let parts = [stream(CD_RATE, 10), stream(CD_RATE, 20)];
+        let joined = concat(&parts).unwrap();
+        let total: f64 = parts.iter().filter_map(|p| mp3::duration_ms(p)).sum();
+        let duration = mp3::duration_ms(&joined.audio).unwrap();
+        assert!((duration - total).abs() < 1.0);
+        // Without LAME tags the joins' silence is unknown
+        assert_eq!(joined.join_gaps_ms, [None]);
+        let info = parse(&joined.audio).unwrap();
+        assert_eq!(info.frames.len(), 30);
+    }
+
+    #[test]
+    fn refuses_inputs_of_other_encodings() {
+        for other in [DAT_RATE, LOW_BITRATE] {
+            let parts = [stream(CD_RATE, 10), stream(other, 10)];
+            let Err(AppError::InvalidAudioRequest { message }) = concat(&parts) else {
+                panic!("joined a {:02X?} stream with a {:02X?} one", CD_RATE, other);
+            };
+            assert!(
+                message.contains("input 2 differs from input 1"),
+                "{}",
+                message
+            );
+        }
+        assert!(concat(&[stream(CD_RATE, 1), b"not audio".to_vec()]).is_err());
+    }
+
+    #[test]
+    fn a_pcm_preset_joins_the_decoded_audio() {
+        let preview: &[u8] =
+            include_bytes!("../../../../../resources/preview_cache/voice_en-US-Standard-C.mp3");
+        let decoded = decode::decode(preview).unwrap();
+        let wav = channels::encode_wav(&Pcm {
+            sample_rate: 16_000,
+            channels: 2,
+            samples: vec![500; 16_000 * 2],
+        })
+        .unwrap();
+        let format = OutputFormat {
+            audio_encoding: super::super::presets::OutputEncoding::Linear16,
+            sample_rate_hertz: Some(48_000),
+            ..OutputFormat::default()
+        };
+        let joined =
+            stretch::read_wav(&concat_pcm(&[preview.to_vec(), wav], &format).unwrap()).unwrap();
+        // The first input's channels, the preset's rate
+        assert_eq!((joined.sample_rate, joined.channels), (48_000, 1));
+        let expected_ms = decoded.frames() as f64 * 1000.0 / 24_000.0 + 1000.0;
+        let joined_ms = joined.frames() as f64 * 1000.0 / 48_000.0;
+        assert!((joined_ms - expected_ms).abs() < 1.0);
+    }
+
+    #[test]
+    fn a_cancelled_join_writes_nothing() {
+        let scratch = Scratch::new("concat");
+        let dir = &scratch.0;
+        let roots = AllowedRoots::scratch(dir);
+        let inputs: Vec<AllowedPath<Read>> = (0..2)
+            .map(|i| {
+                let path = dir.join(format!("{}.mp3", i));
+                std::fs::write(&path, stream(CD_RATE, 10)).unwrap();
+                roots.validate_path(&path).unwrap()
+            })
+            .collect();
+        let output = roots
+            .validate_path::<Write>(&dir.join("joined.mp3"))
+            .unwrap();
+        let registry = CancellationRegistry::default();
+        let token = registry
+            .register(Some("join".into()), "concat_audio")
+            .unwrap();
+        let format = OutputFormat::default();
+
+        registry.cancel("join");
+        let result = join_files(&inputs, &output, &format, &token);
+        assert!(matches!(result, Err(AppError::Cancelled { .. })));
+        assert!(!output.path().exists());
+
+        let token = CancellationRegistry::default()
+            .register(None, "concat_audio")
+            .unwrap();
+        let report = join_files(&inputs, &output, &format, &token).unwrap();
+        assert_eq!(
+            report.bytes as u64,
+            std::fs::metadata(output.path()).unwrap().len()
+        );
+    }
+}
@@ -0,0 +1,135 @@
+// Audio in either format the app synthesizes, as 16-bit PCM: WAV is read
+// as it is and MP3, Google's default encoding, is decoded, so mixing and
+// analysis work on whatever a segment was synthesized as
+use std::io::Cursor;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use super::mp3;
+use super::stretch::{read_wav, Pcm};
+use crate::error::AppError;
+
+fn invalid(message: impl Into<String>) -> AppError {
+    AppError::InvalidAudioRequest {
+        message: message.into(),
+    }
+}
+
+/// `bytes`, a 16-bit PCM WAV or an MP3, as samples.
+pub(super) fn decode(bytes: &[u8]) -> Result<Pcm, AppError> {
+    if bytes.starts_with(b"RIFF") {
+        read_wav(bytes)
+    } else if mp3::is_plausible_mp3(bytes) {
+        decode_mp3(bytes)
+    } else {
+        Err(invalid("audio must be a 16-bit PCM WAV or an MP3"))
+    }
+}
+
+/// Decodes every frame of an MP3, skipping ones that are corrupt; the
+/// encoder's delay and padding are trimmed, so the samples line up with
+/// the WAV the same request would give.
+fn decode_mp3(bytes: &[u8]) -> Result<Pcm, AppError> {
+    let unreadable = |e: Error| invalid(format!("can't decode the MP3: {}", e));
+    let source = MediaSourceStream::new(Box::new(Cursor::new(bytes.to_vec())), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension("mp3");
+    let format_options = FormatOptions {
+        enable_gapless: true,
+        ..FormatOptions::default()
+    };
+    let mut format = symphonia::default::get_probe()
+        .format(&hint, source, &format_options, &MetadataOptions::default())
+        .map_err(unreadable)?
+        .format;
+    let track = format
+        .default_track()
+        .ok_or_else(|| invalid("the MP3 has no audio track"))?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(unreadable)?;
+
+    let mut format_of = None;
+    let mut samples = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(unreadable(e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(Error::DecodeError(e)) => {
+                log::debug!("Skipping an MP3 frame that doesn't decode: {}", e);
+                continue;
+            }
+            Err(e) => return Err(unreadable(e)),
+        };
+        let spec = *decoded.spec();
+        let channels = spec.channels.count() as u16;
+        match format_of {
+            None => format_of = Some((spec.rate, channels)),
+            Some(known) if known != (spec.rate, channels) => {
+                return Err(invalid("the MP3 changes sample rate or channels"));
+            }
+            Some(_) => {}
+        }
+        let mut buffer = SampleBuffer::<i16>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        samples.extend_from_slice(buffer.samples());
+    }
+    let (sample_rate, channels) =
+        format_of.ok_or_else(|| invalid("the MP3 has no frames that decode"))?;
+    Ok(Pcm {
+        sample_rate,
+        channels,
+        samples,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A voice preview Google synthesized, bundled with the app.
+    const PREVIEW: &[u8] =
+        include_bytes!("../../../../../resources/preview_cache/voice_en-US-Standard-C.mp3");
+
+    #[test]
+    fn decodes_an_mp3_to_its_length() {
+        let pcm = decode(PREVIEW).unwrap();
+        assert_eq!(pcm.channels, 1);
+        assert_eq!(pcm.sample_rate, 24_000);
+        let decoded_ms = pcm.frames() as f64 * 1000.0 / pcm.sample_rate as f64;
+        let framed_ms = mp3::duration_ms(PREVIEW).unwrap();
+        // Only the encoder delay and padding are dropped
+        assert!(decoded_ms <= framed_ms && framed_ms - decoded_ms < 100.0);
+        assert!(pcm.samples.iter().any(|&s| s.unsigned_abs() > 1000));
+    }
+
+    #[test]
+    fn reads_a_wav_as_it_is() {
+        let mut wav = crate::audio::placeholder::wav_header(16_000, 1, 2)
+            .unwrap()
+            .to_vec();
+        wav.extend_from_slice(&[1, 0, 2, 0]);
+        let pcm = decode(&wav).unwrap();
+        assert_eq!(pcm.sample_rate, 16_000);
+        assert_eq!(pcm.samples, [1, 2]);
+    }
+
+    #[test]
+    fn refuses_other_data() {
+        assert!(decode(b"not audio at all").is_err());
+        assert!(decode(&PREVIEW[..2]).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+// Integrated loudness after ITU-R BS.1770 (K-weighting, 400 ms blocks with
+// 75% overlap, absolute and relative gating), and gain to a target level
+use super::stretch::Pcm;
+
+/// Blocks quieter than this are silence and never count.
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// Blocks this far under the ungated level don't count either.
+const RELATIVE_GATE_LU: f64 = -10.0;
+const BLOCK_MS: usize = 400;
+const STEP_MS: usize = 100;
+/// Normalization never pushes a sample peak above this.
+const PEAK_CEILING_DBFS: f64 = -1.0;
+
+/// A biquad in direct form I, coefficients normalized by a0.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+}
+
+impl Biquad {
+    fn filter(&self, input: &[f64]) -> Vec<f64> {
+        let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+        input
+            .iter()
+            .map(|&x| {
+                let y = self.b[0] * x + self.b[1] * x1 + self.b[2] * x2
+                    - self.a[0] * y1
+                    - self.a[1] * y2;
+                (x2, x1, y2, y1) = (x1, x, y1, y);
+                y
+            })
+            .collect()
+    }
+}
+
+/// The two K-weighting stages, a high shelf for the head's acoustics and
+/// a high pass, designed for `sample_rate` from the filters BS.1770
+/// specifies at 48 kHz.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = sample_rate as f64;
+
+    let (gain_db, q, fc) = (
+        3.999_843_853_97,
+        0.707_175_236_955_419_3,
+        1_681.974_450_955_533,
+    );
+    let a = 10f64.powf(gain_db / 40.0);
+    let w0 = 2.0 * std::f64::consts::PI * fc / fs;
+    let (sin, cos) = w0.sin_cos();
+    let alpha = sin / (2.0 * q);
+    let root = 2.0 * a.sqrt() * alpha;
+    let a0 = (a + 1.0) - (a - 1.0) * cos + root;
+    let shelf = Biquad {
+        b: [
+            a * ((a + 1.0) + (a - 1.0) * cos + root) / a0,
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos) / a0,
+            a * ((a + 1.0) + (a - 1.0) * cos - root) / a0,
+        ],
+        a: [
+            2.0 * ((a - 1.0) - (a + 1.0) * cos) / a0,
+            ((a + 1.0) - (a - 1.0) * cos - root) / a0,
+        ],
+    };
+
+    let (q, fc) = (0.500_327_037_323_877_3, 38.135_470_876_024_44);
+    let w0 = 2.0 * std::f64::consts::PI * fc / fs;
+    let (sin, cos) = w0.sin_cos();
+    let alpha = sin / (2.0 * q);
+    let a0 = 1.0 + alpha;
+    let high_pass = Biquad {
+        b: [
+            (1.0 + cos) / 2.0 / a0,
+            -(1.0 + cos) / a0,
+            (1.0 + cos) / 2.0 / a0,
+        ],
+        a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+    };
+    [shelf, high_pass]
+}
+
+fn lufs(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+/// Integrated loudness of `pcm` in LUFS; `None` for audio too short for
+/// one block or entirely under the absolute gate. Every channel weighs 1,
+/// as for mono and stereo.
+pub(super) fn integrated(pcm: &Pcm) -> Option<f64> {
+    let channels = pcm.channels as usize;
+    let frames = pcm.frames();
+    let rate = pcm.sample_rate as usize;
+    let block = rate * BLOCK_MS / 1000;
+    let step = rate * STEP_MS / 1000;
+    if block == 0 || frames < block {
+        return None;
+    }
+
+    let [shelf, high_pass] = k_weighting(pcm.sample_rate);
+    let weighted: Vec<Vec<f64>> = (0..channels)
+        .map(|c| {
+            let samples: Vec<f64> = (0..frames)
+                .map(|f| pcm.samples[f * channels + c] as f64 / 32768.0)
+                .collect();
+            high_pass.filter(&shelf.filter(&samples))
+        })
+        .collect();
+
+    // Running square sums make every block O(1)
+    let squares: Vec<Vec<f64>> = weighted
+        .iter()
+        .map(|channel| {
+            let mut sums = Vec::with_capacity(frames + 1);
+            sums.push(0.0);
+            let mut sum = 0.0;
+            for sample in channel {
+                sum += sample * sample;
+                sums.push(sum);
+            }
+            sums
+        })
+        .collect();
+    let blocks: Vec<f64> = (0..=(frames - block) / step)
+        .map(|i| {
+            let start = i * step;
+            squares
+                .iter()
+                .map(|sums| (sums[start + block] - sums[start]) / block as f64)
+                .sum()
+        })
+        .collect();
+
+    let above = |gate: f64| -> Vec<f64> {
+        blocks
+            .iter()
+            .copied()
+            .filter(|&z| z > 0.0 && lufs(z) > gate)
+            .collect()
+    };
+    let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+    let audible = above(ABSOLUTE_GATE_LUFS);
+    if audible.is_empty() {
+        return None;
+    }
+    let relative_gate = lufs(mean(&audible)) + RELATIVE_GATE_LU;
+    let gated = above(relative_gate.max(ABSOLUTE_GATE_LUFS));
+    Some(lufs(mean(&gated)))
+}
+
+/// Scales `pcm` to `target_lufs`, or as close as `PEAK_CEILING_DBFS`
+/// allows; returns the gain applied in dB. Silence is left alone.
+pub(super) fn normalize(pcm: &mut Pcm, target_lufs: f64) -> Option<f64> {
+    let measured = integrated(pcm)?;
+    let peak = pcm
+        .samples
+        .iter()
+        .map(|&s| (s as f64).abs() / 32768.0)
+        .fold(0.0, f64::max);
+    let headroom = PEAK_CEILING_DBFS - 20.0 * peak.max(f64::MIN_POSITIVE).log10();
+    let gain_db = (target_lufs - measured).min(headroom);
+    let gain = 10f64.powf(gain_db / 20.0);
+    for sample in &mut pcm.samples {
+        *sample = (*sample as f64 * gain)
+            .round()
+            .clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+    }
+    Some(gain_db)
+}
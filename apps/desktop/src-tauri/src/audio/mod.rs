@@ -0,0 +1,48 @@
+// Audio helpers shared by the preview cache and synthesis commands
+pub mod ab_preview;
+pub mod align;
+pub mod arbitration;
+pub mod channels;
+pub mod concat;
+pub mod decode;
+pub mod loudness;
+pub mod mp3;
+pub mod music;
+pub mod placeholder;
+pub mod presets;
+pub mod probe;
+pub mod quality;
+pub mod record;
+pub mod resample;
+pub mod stretch;
+pub mod tags;
+pub mod trim;
+pub mod waveform;
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![
+        tags::read_audio_tags,
+        placeholder::generate_placeholder_audio,
+        stretch::fit_audio_to_duration,
+        waveform::get_waveforms_batch,
+        arbitration::start_preview_playback,
+        arbitration::stop_preview_playback,
+        arbitration::set_main_playback,
+        arbitration::set_playback_rate,
+        arbitration::get_playback_position,
+        ab_preview::play_ab_preview,
+        ab_preview::stop_audio,
+        record::get_recording_capability,
+        record::list_input_devices,
+        record::start_recording,
+        record::stop_recording,
+        align::align_audio_to_script,
+        concat::concat_audio,
+        music::list_music_presets,
+        music::preview_with_music,
+        presets::list_output_presets,
+        presets::save_output_preset,
+        presets::delete_output_preset,
+    ]
+}
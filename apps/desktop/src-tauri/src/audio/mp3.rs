@@ -0,0 +1,322 @@
+// Minimal MP3 container inspection: ID3v2 skipping and MPEG audio frame header parsing.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MpegVersion {
+    Mpeg1,
+    Mpeg2,
+    Mpeg25,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub version: MpegVersion,
+    pub layer: u8,
+    pub bitrate_kbps: u32,
+    pub sample_rate: u32,
+    pub padding: bool,
+    pub channel_mode: u8,
+}
+
+const BITRATES_V1: [[u32; 15]; 3] = [
+    [
+        0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+    ],
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+    ],
+    [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ],
+];
+
+const BITRATES_V2: [[u32; 15]; 3] = [
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+    ],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+impl FrameHeader {
+    /// Parses the 4-byte frame header at the start of `bytes`.
+    pub fn parse(bytes: &[u8]) -> Option<FrameHeader> {
+        if bytes.len() < 4 || bytes[0] != 0xFF || bytes[1] & 0xE0 != 0xE0 {
+            return None;
+        }
+
+        let version = match (bytes[1] >> 3) & 0b11 {
+            0b00 => MpegVersion::Mpeg25,
+            0b10 => MpegVersion::Mpeg2,
+            0b11 => MpegVersion::Mpeg1,
+            _ => return None,
+        };
+        let layer = match (bytes[1] >> 1) & 0b11 {
+            0b01 => 3,
+            0b10 => 2,
+            0b11 => 1,
+            _ => return None,
+        };
+
+        let bitrate_index = (bytes[2] >> 4) as usize;
+        if bitrate_index == 0b1111 {
+            return None;
+        }
+        let table = match version {
+            MpegVersion::Mpeg1 => &BITRATES_V1,
+            _ => &BITRATES_V2,
+        };
+        let bitrate_kbps = table[(layer - 1) as usize][bitrate_index];
+
+        let base_rate = match (bytes[2] >> 2) & 0b11 {
+            0 => 44100,
+            1 => 48000,
+            2 => 32000,
+            _ => return None,
+        };
+        let sample_rate = match version {
+            MpegVersion::Mpeg1 => base_rate,
+            MpegVersion::Mpeg2 => base_rate / 2,
+            MpegVersion::Mpeg25 => base_rate / 4,
+        };
+
+        Some(FrameHeader {
+            version,
+            layer,
+            bitrate_kbps,
+            sample_rate,
+            padding: (bytes[2] >> 1) & 1 == 1,
+            channel_mode: bytes[3] >> 6,
+        })
+    }
+
+    /// Length of the frame in bytes including the header, or `None` for
+    /// free-format streams where it can't be derived from the header alone.
+    pub fn frame_len(&self) -> Option<usize> {
+        if self.bitrate_kbps == 0 {
+            return None;
+        }
+        let bitrate = self.bitrate_kbps * 1000;
+        let padding = self.padding as u32;
+        let len = match (self.layer, self.version) {
+            (1, _) => (12 * bitrate / self.sample_rate + padding) * 4,
+            (3, MpegVersion::Mpeg2 | MpegVersion::Mpeg25) => {
+                72 * bitrate / self.sample_rate + padding
+            }
+            _ => 144 * bitrate / self.sample_rate + padding,
+        };
+        Some(len as usize)
+    }
+}
+
+/// Size of a leading ID3v2 tag (header, body and optional footer), or 0 if
+/// the data doesn't start with one.
+pub fn id3v2_len(bytes: &[u8]) -> usize {
+    if bytes.len() < 10 || &bytes[..3] != b"ID3" {
+        return 0;
+    }
+    // The tag size is a 28-bit "syncsafe" integer: 7 bits per byte.
+    let size = bytes[6..10]
+        .iter()
+        .fold(0usize, |acc, b| (acc << 7) | (*b & 0x7F) as usize);
+    let footer = if bytes[5] & 0x10 != 0 { 10 } else { 0 };
+    10 + size + footer
+}
+
+/// Checks that `bytes` look like a playable MP3: non-empty, optionally an
+/// ID3v2 tag, followed by at least one complete MPEG audio frame.
+pub fn is_plausible_mp3(bytes: &[u8]) -> bool {
+    let offset = id3v2_len(bytes);
+    if offset >= bytes.len() {
+        return false;
+    }
+
+    match FrameHeader::parse(&bytes[offset..]) {
+        Some(header) => match header.frame_len() {
+            Some(len) => bytes.len() - offset >= len,
+            None => true,
+        },
+        None => false,
+    }
+}
+
+impl FrameHeader {
+    /// PCM samples per channel one frame decodes to.
+    pub fn samples(&self) -> u32 {
+        match (self.layer, self.version) {
+            (1, _) => 384,
+            (3, MpegVersion::Mpeg2 | MpegVersion::Mpeg25) => 576,
+            _ => 1152,
+        }
+    }
+}
+
+/// Playing time in milliseconds, summed over the frames. Stops at the first
+/// byte that isn't a frame, so trailing tags don't count, and leaves out a
+/// leading Xing/Info frame, which holds no audio; `None` if there are no
+/// frames or the stream is free-format.
+pub fn duration_ms(bytes: &[u8]) -> Option<f64> {
+    let mut offset = id3v2_len(bytes);
+    let mut total = 0.0;
+    let mut frames = 0;
+    while offset < bytes.len() {
+        let Some(header) = FrameHeader::parse(&bytes[offset..]) else {
+            break;
+        };
+        let len = header.frame_len()?;
+        if len == 0 || offset + len > bytes.len() {
+            break;
+        }
+        if frames > 0 || !is_vbr_header(&bytes[offset..offset + len]) {
+            total += header.samples() as f64 * 1000.0 / header.sample_rate as f64;
+        }
+        frames += 1;
+        offset += len;
+    }
+    (frames > 0).then_some(total)
+}
+
+/// One complete MPEG audio frame within a buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub start: usize,
+    pub len: usize,
+    pub header: FrameHeader,
+}
+
+/// The consecutive complete frames from `offset`, up to the first byte that
+/// isn't one (a trailing tag, garbage or a cut-off frame).
+pub fn frames(bytes: &[u8], mut offset: usize) -> Vec<Frame> {
+    let mut frames = Vec::new();
+    while offset < bytes.len() {
+        let Some(header) = FrameHeader::parse(&bytes[offset..]) else {
+            break;
+        };
+        let Some(len) = header.frame_len().filter(|&len| len > 0) else {
+            break;
+        };
+        if offset + len > bytes.len() {
+            break;
+        }
+        frames.push(Frame {
+            start: offset,
+            len,
+            header,
+        });
+        offset += len;
+    }
+    frames
+}
+
+/// Whether a frame is a Xing, Info or VBRI header, which holds the frame
+/// count of the whole file instead of audio and would be wrong once cut.
+pub fn is_vbr_header(frame: &[u8]) -> bool {
+    let head = &frame[..frame.len().min(64)];
+    head.windows(4)
+        .any(|w| w == b"Xing" || w == b"Info" || w == b"VBRI")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// MPEG-1 Layer III, 128 kbps, 44.1 kHz, no padding: 417 bytes a frame.
+    const HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0x00];
+
+    fn frame(header: [u8; 4]) -> Vec<u8> {
+        let len = FrameHeader::parse(&header).unwrap().frame_len().unwrap();
+        let mut frame = header.to_vec();
+        frame.resize(len, 0);
+        frame
+    }
+
+    fn id3v2(body: usize) -> Vec<u8> {
+        let size = [
+            (body >> 21) as u8 & 0x7F,
+            (body >> 14) as u8 & 0x7F,
+            (body >> 7) as u8 & 0x7F,
+            body as u8 & 0x7F,
+        ];
+        let mut tag = b"ID3\x04\x00\x00".to_vec();
+        tag.extend_from_slice(&size);
+        tag.resize(10 + body, 0);
+        tag
+    }
+
+    #[test]
+    fn parses_frame_headers() {
+        let header = FrameHeader::parse(&HEADER).unwrap();
+        assert_eq!(header.version, MpegVersion::Mpeg1);
+        assert_eq!(header.layer, 3);
+        assert_eq!(header.bitrate_kbps, 128);
+        assert_eq!(header.sample_rate, 44_100);
+        assert_eq!(header.frame_len(), Some(417));
+        assert_eq!(header.samples(), 1152);
+
+        let padded = FrameHeader::parse(&[0xFF, 0xFB, 0x92, 0x00]).unwrap();
+        assert_eq!(padded.frame_len(), Some(418));
+        // MPEG-2 Layer III at 24 kHz, as Google encodes
+        let mpeg2 = FrameHeader::parse(&[0xFF, 0xF3, 0x64, 0xC4]).unwrap();
+        assert_eq!(mpeg2.version, MpegVersion::Mpeg2);
+        assert_eq!(mpeg2.sample_rate, 24_000);
+        assert_eq!(mpeg2.samples(), 576);
+    }
+
+    #[test]
+    fn refuses_what_isnt_a_frame_header() {
+        assert_eq!(FrameHeader::parse(&HEADER[..3]), None);
+        assert_eq!(FrameHeader::parse(b"RIFF"), None);
+        // Reserved version, reserved layer, bad bitrate, reserved sample rate
+        assert_eq!(FrameHeader::parse(&[0xFF, 0xEB, 0x90, 0x00]), None);
+        assert_eq!(FrameHeader::parse(&[0xFF, 0xF9, 0x90, 0x00]), None);
+        assert_eq!(FrameHeader::parse(&[0xFF, 0xFB, 0xF0, 0x00]), None);
+        assert_eq!(FrameHeader::parse(&[0xFF, 0xFB, 0x9C, 0x00]), None);
+    }
+
+    #[test]
+    fn measures_id3v2_tags() {
+        assert_eq!(id3v2_len(&id3v2(257)), 267);
+        let mut footer = id3v2(257);
+        footer[5] = 0x10;
+        assert_eq!(id3v2_len(&footer), 277);
+        assert_eq!(id3v2_len(&frame(HEADER)), 0);
+    }
+
+    #[test]
+    fn plausible_mp3s_hold_a_whole_frame() {
+        let frame = frame(HEADER);
+        assert!(is_plausible_mp3(&frame));
+        assert!(!is_plausible_mp3(&frame[..frame.len() - 1]));
+        assert!(!is_plausible_mp3(&[]));
+        assert!(!is_plausible_mp3(b"<html>quota exceeded</html>"));
+
+        let mut tagged = id3v2(32);
+        assert!(!is_plausible_mp3(&tagged));
+        tagged.extend_from_slice(&frame);
+        assert!(is_plausible_mp3(&tagged));
+    }
+
+    #[test]
+    fn durations_leave_out_the_vbr_header() {
+        let mut xing = frame(HEADER);
+        xing[36..40].copy_from_slice(b"Xing");
+        let mut bytes = xing;
+        for _ in 0..10 {
+            bytes.extend_from_slice(&frame(HEADER));
+        }
+        bytes.extend_from_slice(b"TAG trailing id3v1");
+        let expected = 10.0 * 1152.0 * 1000.0 / 44_100.0;
+        assert!((duration_ms(&bytes).unwrap() - expected).abs() < 1e-9);
+        assert_eq!(frames(&bytes, 0).len(), 11);
+        assert!(is_vbr_header(&bytes[..417]));
+        assert!(!is_vbr_header(&bytes[417..834]));
+        assert_eq!(duration_ms(b"no frames"), None);
+    }
+
+    #[test]
+    fn the_bundled_preview_is_plausible() {
+        let preview =
+            include_bytes!("../../../../../resources/preview_cache/voice_en-US-Standard-C.mp3");
+        assert!(is_plausible_mp3(preview));
+        assert!(duration_ms(preview).unwrap() > 1000.0);
+    }
+}
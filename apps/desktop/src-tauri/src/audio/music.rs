@@ -0,0 +1,431 @@
+// Narration previewed over background music, since a voice that sounds
+// right alone can disappear under a music bed. The beds are the WAVs
+// bundled under resources/music, copied into app data by the
+// `music-beds-v1` migration; a preset is a bed's file stem. A mix loops or
+// trims its bed to the narration, ducks it under speech and is cached by
+// its inputs, the cache kept under `MAX_MIX_CACHE_BYTES`
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tauri::Manager;
+
+use super::channels::{self, encode_wav};
+use super::decode::decode;
+use super::presets::OutputOptions;
+use super::quality::{self, AudioAnalysis};
+use super::stretch::{read_wav, Pcm};
+use crate::assets::{asset_url, AssetKind};
+use crate::cache::tts::Lookup;
+use crate::cache::{is_cache_key, write_atomic};
+use crate::error::AppError;
+use crate::paths::roots::{AllowedRoots, Read};
+use crate::read_only::WriteAccess;
+use crate::settings::SettingsStore;
+
+/// Directory of the beds in the resources and in app data.
+pub const MUSIC_DIR: &str = "music";
+/// Directory of cached mixes in the app cache.
+pub const MIX_DIR: &str = "music_mixes";
+/// Cached mixes past this many bytes are deleted, oldest first.
+const MAX_MIX_CACHE_BYTES: u64 = 256 * 1024 * 1024;
+/// Bumped when the mix changes, so cached mixes are made again.
+const MIX_VERSION: u32 = 1;
+const MAX_DUCK_DB: f64 = 40.0;
+/// Level of the bed where nobody speaks, below full scale.
+const BED_LEVEL_DB: f64 = -10.0;
+/// Narration is detected in blocks this long.
+const BLOCK_MS: u32 = 10;
+/// Block RMS, in dBFS, above which the narration counts as speech.
+const SPEECH_THRESHOLD_DB: f64 = -45.0;
+/// The bed stays ducked this long after speech, so it doesn't swell
+/// between words.
+const HOLD_MS: u32 = 250;
+/// Time the bed takes to duck fully, and to come back.
+const ATTACK_MS: u32 = 40;
+const RELEASE_MS: u32 = 400;
+/// Fade of the bed into the first and out of the last narration frame.
+const FADE_MS: u32 = 300;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MusicPreset {
+    pub id: String,
+    pub path: PathBuf,
+    pub duration_ms: u64,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MusicPreview {
+    /// Cache key of the mix.
+    pub key: String,
+    /// Where the webview plays the mix from.
+    pub url: String,
+    pub music_preset: String,
+    pub duck_db: f64,
+    pub duration_ms: u64,
+    /// Made by an earlier call with the same inputs.
+    pub cached: bool,
+    pub analysis: AudioAnalysis,
+}
+
+fn invalid(message: impl Into<String>) -> AppError {
+    AppError::InvalidAudioRequest {
+        message: message.into(),
+    }
+}
+
+fn db_to_gain(db: f64) -> f32 {
+    10f64.powf(db / 20.0) as f32
+}
+
+fn duration_ms(pcm: &Pcm) -> u64 {
+    pcm.frames() as u64 * 1000 / pcm.sample_rate as u64
+}
+
+fn music_dir(app_handle: &tauri::AppHandle) -> Result<PathBuf, AppError> {
+    Ok(app_handle.path().app_data_dir()?.join(MUSIC_DIR))
+}
+
+/// The cached mix `key`, for the asset protocol.
+pub fn mix_path(app_handle: &tauri::AppHandle, key: &str) -> Result<PathBuf, AppError> {
+    Ok(app_handle
+        .path()
+        .app_cache_dir()?
+        .join(MIX_DIR)
+        .join(format!("{}.wav", key)))
+}
+
+fn bed_path(dir: &Path, preset: &str) -> Result<PathBuf, AppError> {
+    if !is_cache_key(preset) {
+        return Err(invalid(format!("invalid music preset \"{}\"", preset)));
+    }
+    let path = dir.join(format!("{}.wav", preset));
+    if !path.is_file() {
+        return Err(invalid(format!("no music preset \"{}\"", preset)));
+    }
+    Ok(path)
+}
+
+/// `pcm` at `sample_rate`, by linear interpolation; beds are background,
+/// so nothing finer is needed.
+fn resample(pcm: Pcm, sample_rate: u32) -> Pcm {
+    if pcm.sample_rate == sample_rate || pcm.frames() == 0 {
+        return pcm;
+    }
+    let channels = pcm.channels as usize;
+    let frames = pcm.frames();
+    let out_frames = (frames as u64 * sample_rate as u64 / pcm.sample_rate as u64) as usize;
+    let step = pcm.sample_rate as f64 / sample_rate as f64;
+    let mut samples = Vec::with_capacity(out_frames * channels);
+    for i in 0..out_frames {
+        let at = i as f64 * step;
+        let frame = (at as usize).min(frames - 1);
+        let next = (frame + 1).min(frames - 1);
+        let t = (at - frame as f64) as f32;
+        for c in 0..channels {
+            let a = pcm.samples[frame * channels + c] as f32;
+            let b = pcm.samples[next * channels + c] as f32;
+            samples.push((a + (b - a) * t).round() as i16);
+        }
+    }
+    Pcm {
+        sample_rate,
+        channels: pcm.channels,
+        samples,
+    }
+}
+
+/// Per-frame gain of the bed under `narration`: `BED_LEVEL_DB`, lowered a
+/// further `duck_db` while speech is detected and for `HOLD_MS` after.
+fn duck_envelope(narration: &Pcm, duck_db: f64) -> Vec<f32> {
+    let rate = narration.sample_rate as usize;
+    let channels = narration.channels as usize;
+    let frames = narration.frames();
+    let block = (rate * BLOCK_MS as usize / 1000).max(1);
+    let hold_blocks = (HOLD_MS / BLOCK_MS) as usize;
+    let threshold = db_to_gain(SPEECH_THRESHOLD_DB) * i16::MAX as f32;
+
+    let full = db_to_gain(BED_LEVEL_DB);
+    let ducked = db_to_gain(BED_LEVEL_DB - duck_db);
+    let attack = (full - ducked) / (rate * ATTACK_MS as usize / 1000).max(1) as f32;
+    let release = (full - ducked) / (rate * RELEASE_MS as usize / 1000).max(1) as f32;
+
+    let mut gains = Vec::with_capacity(frames);
+    let mut gain = full;
+    let mut since_speech = usize::MAX;
+    for start in (0..frames).step_by(block) {
+        let end = (start + block).min(frames);
+        let window = &narration.samples[start * channels..end * channels];
+        let rms = (window.iter().map(|&s| (s as f32).powi(2)).sum::<f32>()
+            / window.len().max(1) as f32)
+            .sqrt();
+        since_speech = if rms > threshold {
+            0
+        } else {
+            since_speech.saturating_add(1)
+        };
+        let target = if since_speech <= hold_blocks {
+            ducked
+        } else {
+            full
+        };
+        for _ in start..end {
+            gain = if gain > target {
+                (gain - attack).max(target)
+            } else {
+                (gain + release).min(target)
+            };
+            gains.push(gain);
+        }
+    }
+    gains
+}
+
+/// `narration` over `bed`, the bed looped or cut to the narration's length
+/// and brought to its rate and channels.
+fn mix(narration: &Pcm, bed: Pcm, duck_db: f64) -> Result<Pcm, AppError> {
+    let bed = resample(
+        channels::remix(bed, narration.channels)?,
+        narration.sample_rate,
+    );
+    let bed_frames = bed.frames();
+    if bed_frames == 0 {
+        return Err(invalid("music bed is empty"));
+    }
+    let channels = narration.channels as usize;
+    let frames = narration.frames();
+    let fade = (narration.sample_rate * FADE_MS / 1000).max(1) as usize;
+    let gains = duck_envelope(narration, duck_db);
+
+    let mut samples = Vec::with_capacity(narration.samples.len());
+    for (frame, &gain) in gains.iter().enumerate() {
+        let edge = frame.min(frames - 1 - frame);
+        let gain = gain * (edge as f32 / fade as f32).min(1.0);
+        let bed_frame = frame % bed_frames;
+        for c in 0..channels {
+            let voice = narration.samples[frame * channels + c] as f32;
+            let music = bed.samples[bed_frame * channels + c] as f32 * gain;
+            samples.push(
+                (voice + music)
+                    .round()
+                    .clamp(i16::MIN as f32, i16::MAX as f32) as i16,
+            );
+        }
+    }
+    Ok(Pcm {
+        sample_rate: narration.sample_rate,
+        channels: narration.channels,
+        samples,
+    })
+}
+
+/// Deletes cached mixes, oldest first, until the rest fit in `max_bytes`,
+/// keeping `keep`.
+fn enforce_cap(dir: &Path, max_bytes: u64, keep: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = entries
+        .flatten()
+        .filter_map(|e| {
+            let meta = e.metadata().ok()?;
+            meta.is_file().then(|| {
+                let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                (modified, meta.len(), e.path())
+            })
+        })
+        .collect();
+    let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+    files.sort();
+    for (_, len, path) in files {
+        if total <= max_bytes {
+            break;
+        }
+        if path == keep {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => total -= len,
+            Err(e) => log::warn!("Failed to remove cached mix {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// The bundled music beds, by id.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn list_music_presets(app_handle: tauri::AppHandle) -> Result<Vec<MusicPreset>, AppError> {
+    let dir = music_dir(&app_handle)?;
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Ok(Vec::new());
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "wav"))
+        .collect();
+    paths.sort();
+    let mut presets = Vec::with_capacity(paths.len());
+    for path in paths {
+        let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        if !is_cache_key(id) {
+            continue;
+        }
+        match fs::read(&path)
+            .map_err(AppError::from)
+            .and_then(|b| read_wav(&b))
+        {
+            Ok(pcm) => presets.push(MusicPreset {
+                id: id.to_string(),
+                duration_ms: duration_ms(&pcm),
+                sample_rate: pcm.sample_rate,
+                channels: pcm.channels,
+                path,
+            }),
+            Err(e) => log::warn!("Skipping music bed {}: {}", path.display(), e),
+        }
+    }
+    Ok(presets)
+}
+
+/// Mixes a narration over the bed `music_preset`, ducked by `duck_db`
+/// under speech, and returns where to play the mix. `voice_audio` is a TTS
+/// cache key or the path of a file, of MP3 or LINEAR16 WAV narration.
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub fn preview_with_music(
+    app_handle: tauri::AppHandle,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    settings: tauri::State<'_, SettingsStore>,
+    voice_audio: String,
+    music_preset: String,
+    duck_db: f64,
+    preset: Option<String>,
+) -> Result<MusicPreview, AppError> {
+    access.check()?;
+    let format = OutputOptions {
+        preset,
+        ..OutputOptions::default()
+    }
+    .resolve(&settings.get())?;
+    if !(0.0..=MAX_DUCK_DB).contains(&duck_db) {
+        return Err(invalid(format!(
+            "duck_db must be between 0 and {}",
+            MAX_DUCK_DB
+        )));
+    }
+    let bed_path = bed_path(&music_dir(&app_handle)?, &music_preset)?;
+    let narration_bytes = if is_cache_key(&voice_audio) {
+        match crate::cache::tts_cache(&app_handle)?.get(&voice_audio)? {
+            Lookup::Hit(bytes) => bytes,
+            _ => {
+                return Err(invalid(format!(
+                    "no cached speech with key {}",
+                    voice_audio
+                )))
+            }
+        }
+    } else {
+        roots
+            .validate_path::<Read>(Path::new(&voice_audio))?
+            .read()?
+    };
+    let narration = decode(&narration_bytes)?;
+    if narration.frames() == 0 {
+        return Err(invalid("narration is empty"));
+    }
+
+    let bed_bytes = fs::read(&bed_path)?;
+    let mut hasher = Sha256::new();
+    hasher.update(MIX_VERSION.to_le_bytes());
+    hasher.update(Sha256::digest(&narration_bytes));
+    hasher.update(Sha256::digest(&bed_bytes));
+    hasher.update(duck_db.to_le_bytes());
+    hasher.update(format!("{:?}", format));
+    let key = format!("{:x}", hasher.finalize());
+    let path = mix_path(&app_handle, &key)?;
+
+    let cached = path.is_file();
+    let mixed = if cached {
+        read_wav(&fs::read(&path)?)?
+    } else {
+        let mut mixed = mix(&narration, read_wav(&bed_bytes)?, duck_db)?;
+        if let Some(rate) = format.sample_rate_hertz {
+            mixed = resample(mixed, rate);
+        }
+        let mixed = format.process(mixed)?;
+        write_atomic(&path, &encode_wav(&mixed)?)?;
+        if let Some(dir) = path.parent() {
+            enforce_cap(dir, MAX_MIX_CACHE_BYTES, &path);
+        }
+        mixed
+    };
+    let analysis = quality::analyze(&mixed, &settings.get().audio_quality);
+    quality::warn(&app_handle, Some(key.clone()), &analysis);
+    Ok(MusicPreview {
+        url: asset_url(AssetKind::Mix, &key),
+        key,
+        music_preset,
+        duck_db,
+        duration_ms: duration_ms(&narration),
+        cached,
+        analysis,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The bed bundled with the app.
+    const BED: &[u8] = include_bytes!("../../../../../resources/music/soft-pad.wav");
+
+    fn tone(sample_rate: u32, frames: usize, amplitude: f32) -> Pcm {
+        let samples = (0..frames)
+            .map(|i| ((i as f32 * 0.05).sin() * amplitude) as i16)
+            .collect();
+        Pcm {
+            sample_rate,
+            channels: 1,
+            samples,
+        }
+    }
+
+    #[test]
+    fn the_bundled_bed_is_a_wav_preset() {
+        let bed = read_wav(BED).unwrap();
+        assert_eq!(bed.channels, 1);
+        assert!(duration_ms(&bed) >= 10_000);
+    }
+
+    #[test]
+    fn the_bed_is_ducked_under_speech() {
+        let rate = 24_000;
+        let mut narration = tone(rate, rate as usize * 2, 8000.0);
+        // Silence in the second half
+        let half = narration.samples.len() / 2;
+        narration.samples[half..].fill(0);
+        let gains = duck_envelope(&narration, 20.0);
+        let speaking = gains[half / 2];
+        let silent = gains[gains.len() - 1];
+        assert!((speaking - db_to_gain(BED_LEVEL_DB - 20.0)).abs() < 1e-3);
+        assert!((silent - db_to_gain(BED_LEVEL_DB)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn a_mix_takes_the_narrations_length_and_format() {
+        let narration = tone(24_000, 24_000 * 3, 8000.0);
+        let mixed = mix(&narration, read_wav(BED).unwrap(), 12.0).unwrap();
+        assert_eq!(mixed.sample_rate, 24_000);
+        assert_eq!(mixed.channels, 1);
+        assert_eq!(mixed.frames(), narration.frames());
+        assert_ne!(mixed.samples, narration.samples);
+    }
+}
@@ -0,0 +1,317 @@
+// Placeholder WAVs of an exact length for the editor to lay out while the
+// real voiceover is pending
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::error::AppError;
+use crate::format::DisplayFormat;
+use crate::paths::roots::{self, AllowedPath, AllowedRoots};
+use crate::read_only::WriteAccess;
+use crate::settings::SettingsStore;
+
+const MAX_DURATION_MS: u64 = 60 * 60 * 1000;
+const MIN_SAMPLE_RATE: u32 = 8000;
+const MAX_SAMPLE_RATE: u32 = 192_000;
+const DEFAULT_SAMPLE_RATE: u32 = 24_000;
+const TONE_HZ: f64 = 440.0;
+/// About -20 dBFS: audible as a placeholder without being startling.
+const TONE_AMPLITUDE: f64 = 0.1;
+const FADE_MS: u64 = 10;
+/// Samples generated and written per block.
+const BLOCK_SAMPLES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaceholderKind {
+    Silence,
+    Tone,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaceholderAudio {
+    pub path: PathBuf,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Per channel.
+    pub samples: u64,
+    /// `samples / sample_rate`, exactly the requested duration whenever it
+    /// falls on a sample boundary.
+    pub duration_ms: f64,
+    pub duration_display: String,
+}
+
+fn invalid(message: impl Into<String>) -> AppError {
+    AppError::InvalidAudioRequest {
+        message: message.into(),
+    }
+}
+
+/// Sample count for a duration, rounded to the nearest sample.
+pub fn sample_count(duration_ms: u64, sample_rate: u32) -> u64 {
+    (duration_ms * sample_rate as u64 + 500) / 1000
+}
+
+/// Canonical 44-byte header for 16-bit PCM; `frames` counts one sample per
+/// channel.
+pub(crate) fn wav_header(
+    sample_rate: u32,
+    channels: u16,
+    frames: u64,
+) -> Result<[u8; 44], AppError> {
+    let block_align = channels as u32 * 2;
+    let data_len = u32::try_from(frames * block_align as u64)
+        .map_err(|_| invalid("audio is too long for WAV"))?;
+    let mut header = [0u8; 44];
+    header[0..4].copy_from_slice(b"RIFF");
+    header[4..8].copy_from_slice(&(36 + data_len).to_le_bytes());
+    header[8..12].copy_from_slice(b"WAVE");
+    header[12..16].copy_from_slice(b"fmt ");
+    header[16..20].copy_from_slice(&16u32.to_le_bytes());
+    header[20..22].copy_from_slice(&1u16.to_le_bytes()); // PCM
+    header[22..24].copy_from_slice(&channels.to_le_bytes());
+    header[24..28].copy_from_slice(&sample_rate.to_le_bytes());
+    header[28..32].copy_from_slice(&(sample_rate * block_align).to_le_bytes());
+    header[32..34].copy_from_slice(&(block_align as u16).to_le_bytes());
+    header[34..36].copy_from_slice(&16u16.to_le_bytes());
+    header[36..40].copy_from_slice(b"data");
+    header[40..44].copy_from_slice(&data_len.to_le_bytes());
+    Ok(header)
+}
+
+/// Sample `n` of a faded sine; the fades keep the start and end from
+/// clicking.
+fn tone_sample(n: u64, samples: u64, sample_rate: u32, fade: u64) -> i16 {
+    let t = n as f64 / sample_rate as f64;
+    let remaining = samples - 1 - n;
+    let gain = if fade == 0 {
+        1.0
+    } else {
+        (n.min(remaining) as f64 / fade as f64).min(1.0)
+    };
+    let value = (2.0 * std::f64::consts::PI * TONE_HZ * t).sin() * TONE_AMPLITUDE * gain;
+    (value * i16::MAX as f64).round() as i16
+}
+
+/// Writes the placeholder through a sibling temp file, a block at a time so
+/// an hour of audio never sits in memory. Stereo repeats each sample on
+/// both channels.
+pub fn generate(
+    duration_ms: u64,
+    kind: PlaceholderKind,
+    sample_rate: Option<u32>,
+    channels: Option<u16>,
+    output: &AllowedPath<roots::Write>,
+    format: &DisplayFormat,
+) -> Result<PlaceholderAudio, AppError> {
+    let output_path = output.path();
+    let sample_rate = sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+    let channels = channels.unwrap_or(1);
+    if duration_ms == 0 || duration_ms > MAX_DURATION_MS {
+        return Err(invalid(format!(
+            "duration must be between 1 and {} ms",
+            MAX_DURATION_MS
+        )));
+    }
+    if !(MIN_SAMPLE_RATE..=MAX_SAMPLE_RATE).contains(&sample_rate) {
+        return Err(invalid(format!(
+            "sample rate must be between {} and {} Hz",
+            MIN_SAMPLE_RATE, MAX_SAMPLE_RATE
+        )));
+    }
+    if !output_path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("wav"))
+    {
+        return Err(invalid("placeholders are written as .wav files"));
+    }
+
+    let samples = sample_count(duration_ms, sample_rate);
+    let fade = (FADE_MS * sample_rate as u64 / 1000).min(samples / 2);
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp = output_path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let result = (|| -> Result<(), AppError> {
+        let mut out = BufWriter::new(fs::File::create(&tmp)?);
+        out.write_all(&wav_header(sample_rate, channels, samples)?)?;
+        let mut block = Vec::with_capacity(BLOCK_SAMPLES * 2 * channels as usize);
+        let mut n = 0u64;
+        while n < samples {
+            let end = (n + BLOCK_SAMPLES as u64).min(samples);
+            block.clear();
+            match kind {
+                PlaceholderKind::Silence => {
+                    block.resize(((end - n) * 2 * channels as u64) as usize, 0)
+                }
+                PlaceholderKind::Tone => {
+                    for i in n..end {
+                        let sample = tone_sample(i, samples, sample_rate, fade).to_le_bytes();
+                        for _ in 0..channels {
+                            block.extend_from_slice(&sample);
+                        }
+                    }
+                }
+            }
+            out.write_all(&block)?;
+            n = end;
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        Ok(())
+    })();
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    fs::rename(&tmp, output_path)?;
+
+    let duration_ms = samples as f64 * 1000.0 / sample_rate as f64;
+    Ok(PlaceholderAudio {
+        path: output_path.to_path_buf(),
+        sample_rate,
+        channels,
+        samples,
+        duration_ms,
+        duration_display: format.duration(duration_ms),
+    })
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn generate_placeholder_audio(
+    settings: tauri::State<'_, SettingsStore>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    duration_ms: u64,
+    kind: PlaceholderKind,
+    sample_rate: Option<u32>,
+    channels: Option<u8>,
+    output_path: PathBuf,
+) -> Result<PlaceholderAudio, AppError> {
+    access.check()?;
+    let channels = super::channels::check(channels)?;
+    let output = roots.validate_path(&output_path)?;
+    let format = DisplayFormat::for_locale(&settings.get().ui_locale);
+    tauri::async_runtime::spawn_blocking(move || {
+        generate(duration_ms, kind, sample_rate, channels, &output, &format)
+    })
+    .await
+    .map_err(|e| AppError::Io {
+        message: e.to_string(),
+    })?
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+    use std::path::Path;
+
+    /// `name` in `scratch`, as an allowed output.
+    fn output_in(scratch: &Scratch, name: &str) -> AllowedPath<roots::Write> {
+        AllowedRoots::scratch(&scratch.0)
+            .validate_path(&scratch.0.join(name))
+            .unwrap()
+    }
+
+    fn samples(path: &Path) -> Vec<i16> {
+        fs::read(path).unwrap()[44..]
+            .chunks_exact(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn rounds_durations_to_the_nearest_sample() {
+        assert_eq!(sample_count(1000, 24_000), 24_000);
+        assert_eq!(sample_count(1, 44_100), 44);
+        assert_eq!(sample_count(10, 22_050), 221);
+    }
+
+    #[test]
+    fn headers_describe_16_bit_pcm() {
+        let header = wav_header(24_000, 2, 10).unwrap();
+        assert_eq!(&header[0..4], b"RIFF");
+        assert_eq!(
+            u32::from_le_bytes(header[4..8].try_into().unwrap()),
+            36 + 40
+        );
+        assert_eq!(u16::from_le_bytes([header[22], header[23]]), 2);
+        assert_eq!(
+            u32::from_le_bytes(header[28..32].try_into().unwrap()),
+            96_000
+        );
+        assert_eq!(u32::from_le_bytes(header[40..44].try_into().unwrap()), 40);
+        assert!(wav_header(24_000, 2, u32::MAX as u64).is_err());
+    }
+
+    #[test]
+    fn silence_is_exactly_as_long_as_asked() {
+        let scratch = Scratch::new("silence");
+        let output = output_in(&scratch, "gap.wav");
+        let format = DisplayFormat::for_locale("en-US");
+        let audio = generate(
+            1500,
+            PlaceholderKind::Silence,
+            Some(16_000),
+            Some(2),
+            &output,
+            &format,
+        )
+        .unwrap();
+        assert_eq!(audio.samples, 24_000);
+        assert_eq!(audio.duration_ms, 1500.0);
+        assert_eq!(audio.duration_display, "1.5 s");
+        let written = samples(&audio.path);
+        assert_eq!(written.len(), 48_000);
+        assert!(written.iter().all(|&s| s == 0));
+        assert!(!scratch.0.join("gap.wav.tmp").exists());
+    }
+
+    #[test]
+    fn tones_fade_in_and_out_on_every_channel() {
+        let scratch = Scratch::new("tone");
+        let output = output_in(&scratch, "beep.wav");
+        let format = DisplayFormat::for_locale("en-US");
+        let audio = generate(200, PlaceholderKind::Tone, None, Some(2), &output, &format).unwrap();
+        assert_eq!(audio.sample_rate, DEFAULT_SAMPLE_RATE);
+        let written = samples(&audio.path);
+        let (left, right): (Vec<i16>, Vec<i16>) = written
+            .chunks_exact(2)
+            .map(|frame| (frame[0], frame[1]))
+            .unzip();
+        assert_eq!(left, right);
+        assert_eq!(left[0], 0);
+        assert!(left.last().unwrap().abs() < 100);
+        let peak = left.iter().map(|s| s.unsigned_abs()).max().unwrap();
+        let full = (TONE_AMPLITUDE * i16::MAX as f64) as u16;
+        assert!(peak > full - 100 && peak <= full + 1);
+    }
+
+    #[test]
+    fn refuses_what_it_cant_write() {
+        let scratch = Scratch::new("refused");
+        let format = DisplayFormat::for_locale("en-US");
+        let wav = output_in(&scratch, "out.wav");
+        for (duration_ms, sample_rate) in
+            [(0, None), (MAX_DURATION_MS + 1, None), (1000, Some(4000))]
+        {
+            assert!(generate(
+                duration_ms,
+                PlaceholderKind::Silence,
+                sample_rate,
+                None,
+                &wav,
+                &format
+            )
+            .is_err());
+        }
+        let mp3 = output_in(&scratch, "out.mp3");
+        assert!(generate(1000, PlaceholderKind::Silence, None, None, &mp3, &format).is_err());
+        assert!(fs::read_dir(&scratch.0).unwrap().next().is_none());
+    }
+}
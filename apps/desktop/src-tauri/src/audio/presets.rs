@@ -0,0 +1,438 @@
+// Named output formats, so "for YouTube" is one choice instead of six
+// knobs. The built-in presets below can't be changed or deleted; user
+// presets live in the `output_presets` setting. A command taking a preset
+// expands it and then applies the caller's explicit options over it
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::AtomicBool;
+
+use super::stretch::Pcm;
+use super::{channels, loudness, resample};
+use crate::error::AppError;
+use crate::read_only::WriteAccess;
+use crate::settings::{Settings, SettingsStore};
+
+/// Target of `normalize` when a format doesn't name one.
+pub const DEFAULT_TARGET_LUFS: f64 = -16.0;
+const MIN_TARGET_LUFS: f64 = -40.0;
+const MAX_TARGET_LUFS: f64 = -5.0;
+const SAMPLE_RATES: &[u32] = &[8000, 16000, 22050, 24000, 32000, 44100, 48000];
+const MAX_NAME_CHARS: usize = 60;
+/// Leading and trailing audio quieter than this is trimmed.
+const TRIM_THRESHOLD_DBFS: f64 = -50.0;
+/// Kept around the speech when trimming, so words don't start abruptly.
+const TRIM_PAD_MS: u32 = 100;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutputEncoding {
+    /// Google's MP3, passed through unprocessed.
+    #[default]
+    #[serde(rename = "MP3")]
+    Mp3,
+    /// 16-bit PCM WAV, which every processing step needs.
+    #[serde(rename = "LINEAR16")]
+    Linear16,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputFormat {
+    pub audio_encoding: OutputEncoding,
+    /// `None` keeps the voice's native rate.
+    pub sample_rate_hertz: Option<u32>,
+    /// 1 or 2; `None` keeps the voice's mono.
+    pub channels: Option<u8>,
+    /// Level `normalize` brings the audio to, `DEFAULT_TARGET_LUFS` if
+    /// unset.
+    pub target_lufs: Option<f64>,
+    /// Cut leading and trailing silence.
+    pub trim_silence: bool,
+    /// Scale to `target_lufs`.
+    pub normalize: bool,
+}
+
+/// A format choice made per call: a preset name, then any fields that
+/// override it.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct OutputOptions {
+    pub preset: Option<String>,
+    pub audio_encoding: Option<OutputEncoding>,
+    pub sample_rate_hertz: Option<u32>,
+    pub channels: Option<u8>,
+    pub target_lufs: Option<f64>,
+    pub trim_silence: Option<bool>,
+    pub normalize: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputPresetEntry {
+    pub name: String,
+    pub builtin: bool,
+    #[serde(flatten)]
+    pub format: OutputFormat,
+}
+
+pub fn builtin_presets() -> Vec<(&'static str, OutputFormat)> {
+    vec![
+        (
+            "YouTube Voiceover",
+            OutputFormat {
+                audio_encoding: OutputEncoding::Linear16,
+                sample_rate_hertz: Some(48000),
+                channels: Some(2),
+                target_lufs: Some(-14.0),
+                trim_silence: true,
+                normalize: true,
+            },
+        ),
+        (
+            "Podcast",
+            OutputFormat {
+                audio_encoding: OutputEncoding::Linear16,
+                sample_rate_hertz: Some(44100),
+                channels: Some(1),
+                target_lufs: Some(-16.0),
+                trim_silence: true,
+                normalize: true,
+            },
+        ),
+        ("Preview/Low", OutputFormat::default()),
+        (
+            "Archive WAV",
+            OutputFormat {
+                audio_encoding: OutputEncoding::Linear16,
+                sample_rate_hertz: Some(48000),
+                ..OutputFormat::default()
+            },
+        ),
+    ]
+}
+
+fn invalid(message: impl Into<String>) -> AppError {
+    AppError::InvalidAudioRequest {
+        message: message.into(),
+    }
+}
+
+fn is_builtin(name: &str) -> bool {
+    builtin_presets()
+        .iter()
+        .any(|(builtin, _)| builtin.eq_ignore_ascii_case(name.trim()))
+}
+
+/// The preset `name`: a built-in, else one from the settings.
+pub fn preset(settings: &Settings, name: &str) -> Result<OutputFormat, AppError> {
+    let name = name.trim();
+    builtin_presets()
+        .into_iter()
+        .find(|(builtin, _)| builtin.eq_ignore_ascii_case(name))
+        .map(|(_, format)| format)
+        .or_else(|| settings.output_presets.get(name).cloned())
+        .ok_or_else(|| invalid(format!("no output preset \"{}\"", name)))
+}
+
+impl OutputFormat {
+    /// Whether the audio has to be decoded to PCM, i.e. isn't Google's MP3
+    /// as sent.
+    pub fn needs_pcm(&self) -> bool {
+        self.audio_encoding == OutputEncoding::Linear16
+            || self.sample_rate_hertz.is_some()
+            || self.channels == Some(2)
+            || self.trim_silence
+            || self.normalize
+    }
+
+    pub fn check(&self) -> Result<(), AppError> {
+        if let Some(rate) = self.sample_rate_hertz {
+            if !SAMPLE_RATES.contains(&rate) {
+                return Err(invalid(format!(
+                    "sample rate {} Hz is not one of {:?}",
+                    rate, SAMPLE_RATES
+                )));
+            }
+        }
+        channels::check(self.channels)?;
+        if let Some(target) = self.target_lufs {
+            if !(MIN_TARGET_LUFS..=MAX_TARGET_LUFS).contains(&target) {
+                return Err(invalid(format!(
+                    "target loudness must be between {} and {} LUFS",
+                    MIN_TARGET_LUFS, MAX_TARGET_LUFS
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Trims, remixes and normalizes `pcm` as this format asks; the sample
+    /// rate is up to whoever made the audio.
+    pub(super) fn process(&self, pcm: Pcm) -> Result<Pcm, AppError> {
+        let pcm = if self.trim_silence { trim(pcm) } else { pcm };
+        let mut pcm = match self.channels {
+            Some(channels) => channels::remix(pcm, channels as u16)?,
+            None => pcm,
+        };
+        if self.normalize {
+            let target = self.target_lufs.unwrap_or(DEFAULT_TARGET_LUFS);
+            if let Some(gain_db) = loudness::normalize(&mut pcm, target) {
+                log::debug!("Normalized to {} LUFS with {:.1} dB", target, gain_db);
+            }
+        }
+        Ok(pcm)
+    }
+
+    /// Extension of the files written in this format.
+    pub fn extension(&self) -> &'static str {
+        match self.audio_encoding {
+            OutputEncoding::Mp3 => "mp3",
+            OutputEncoding::Linear16 => "wav",
+        }
+    }
+
+    /// `audio`, an MP3 or a WAV, as a WAV in this format: decoded,
+    /// resampled to its rate, if it names one, and processed.
+    pub fn render(&self, audio: &[u8]) -> Result<Vec<u8>, AppError> {
+        let mut pcm = super::decode::decode(audio)?;
+        if let Some(rate) = self.sample_rate_hertz {
+            pcm = resample::resample(&pcm, rate, &AtomicBool::new(false))
+                .ok_or_else(|| invalid("resampling was cancelled"))?;
+        }
+        channels::encode_wav(&self.process(pcm)?)
+    }
+
+    /// Whether `process` changes anything.
+    pub fn has_processing(&self) -> bool {
+        self.channels.is_some() || self.trim_silence || self.normalize
+    }
+
+    /// `process` for a WAV, returned as it is when there's nothing to do.
+    pub fn process_wav(&self, wav: &[u8]) -> Result<Vec<u8>, AppError> {
+        if !self.has_processing() {
+            return Ok(wav.to_vec());
+        }
+        let pcm = super::stretch::read_wav(wav)?;
+        channels::encode_wav(&self.process(pcm)?)
+    }
+}
+
+impl OutputOptions {
+    /// The format these options ask for: the preset's, or the default MP3,
+    /// with every field set here taking the place of the preset's. Setting
+    /// processing on an MP3 preset makes the output WAV.
+    pub fn resolve(&self, settings: &Settings) -> Result<OutputFormat, AppError> {
+        let mut format = match &self.preset {
+            Some(name) => preset(settings, name)?,
+            None => OutputFormat::default(),
+        };
+        if let Some(encoding) = self.audio_encoding {
+            format.audio_encoding = encoding;
+        }
+        if self.sample_rate_hertz.is_some() {
+            format.sample_rate_hertz = self.sample_rate_hertz;
+        }
+        if self.channels.is_some() {
+            format.channels = self.channels;
+        }
+        if self.target_lufs.is_some() {
+            format.target_lufs = self.target_lufs;
+        }
+        if let Some(trim_silence) = self.trim_silence {
+            format.trim_silence = trim_silence;
+        }
+        if let Some(normalize) = self.normalize {
+            format.normalize = normalize;
+        }
+        if format.needs_pcm() {
+            format.audio_encoding = OutputEncoding::Linear16;
+        }
+        format.check()?;
+        Ok(format)
+    }
+}
+
+/// `pcm` without the silence before its first and after its last sound
+/// over `TRIM_THRESHOLD_DBFS`, less `TRIM_PAD_MS` each side.
+fn trim(pcm: Pcm) -> Pcm {
+    let channels = pcm.channels as usize;
+    let threshold = (10f64.powf(TRIM_THRESHOLD_DBFS / 20.0) * 32768.0) as i32;
+    let loud = |frame: &[i16]| frame.iter().any(|&s| (s as i32).abs() > threshold);
+    let frames: Vec<&[i16]> = pcm.samples.chunks_exact(channels).collect();
+    let (Some(first), Some(last)) = (
+        frames.iter().position(|f| loud(f)),
+        frames.iter().rposition(|f| loud(f)),
+    ) else {
+        return pcm;
+    };
+    let pad = (pcm.sample_rate * TRIM_PAD_MS / 1000) as usize;
+    let start = first.saturating_sub(pad);
+    let end = (last + 1 + pad).min(frames.len());
+    Pcm {
+        sample_rate: pcm.sample_rate,
+        channels: pcm.channels,
+        samples: pcm.samples[start * channels..end * channels].to_vec(),
+    }
+}
+
+/// Built-in presets first, then the user's, by name.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn list_output_presets(settings: tauri::State<'_, SettingsStore>) -> Vec<OutputPresetEntry> {
+    let user = settings.get().output_presets;
+    builtin_presets()
+        .into_iter()
+        .map(|(name, format)| OutputPresetEntry {
+            name: name.to_string(),
+            builtin: true,
+            format,
+        })
+        .chain(user.into_iter().map(|(name, format)| OutputPresetEntry {
+            name,
+            builtin: false,
+            format,
+        }))
+        .collect()
+}
+
+/// Adds or replaces the user preset `name`. Built-in names are taken, and
+/// an MP3 preset can't ask for processing, which needs WAV.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn save_output_preset(
+    settings: tauri::State<'_, SettingsStore>,
+    access: tauri::State<'_, WriteAccess>,
+    name: String,
+    preset: OutputFormat,
+) -> Result<OutputPresetEntry, AppError> {
+    access.check()?;
+    let name = name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME_CHARS {
+        return Err(invalid(format!(
+            "preset names are 1 to {} characters",
+            MAX_NAME_CHARS
+        )));
+    }
+    if is_builtin(&name) {
+        return Err(invalid(format!(
+            "\"{}\" is a built-in preset and can't be changed",
+            name
+        )));
+    }
+    preset.check()?;
+    if preset.audio_encoding == OutputEncoding::Mp3 && preset.needs_pcm() {
+        return Err(invalid(
+            "MP3 presets are passed through as Google sends them; use LINEAR16 to resample, remix, trim or normalize",
+        ));
+    }
+    settings.update(|s| {
+        s.output_presets.insert(name.clone(), preset.clone());
+    })?;
+    Ok(OutputPresetEntry {
+        name,
+        builtin: false,
+        format: preset,
+    })
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn delete_output_preset(
+    settings: tauri::State<'_, SettingsStore>,
+    access: tauri::State<'_, WriteAccess>,
+    name: String,
+) -> Result<(), AppError> {
+    access.check()?;
+    if is_builtin(&name) {
+        return Err(invalid(format!(
+            "\"{}\" is a built-in preset and can't be deleted",
+            name.trim()
+        )));
+    }
+    let mut found = false;
+    settings.update(|s| found = s.output_presets.remove(name.trim()).is_some())?;
+    if !found {
+        return Err(invalid(format!("no output preset \"{}\"", name.trim())));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(sample_rate: u32, samples: Vec<i16>) -> Vec<u8> {
+        channels::encode_wav(&Pcm {
+            sample_rate,
+            channels: 1,
+            samples,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn explicit_options_override_the_preset() {
+        let mut settings = Settings::default();
+        settings.output_presets.insert(
+            "Mine".to_string(),
+            OutputFormat {
+                audio_encoding: OutputEncoding::Linear16,
+                sample_rate_hertz: Some(22050),
+                channels: Some(2),
+                ..OutputFormat::default()
+            },
+        );
+        let format = OutputOptions {
+            preset: Some("mine".to_string()),
+            channels: Some(1),
+            normalize: Some(true),
+            ..OutputOptions::default()
+        };
+        // User presets are matched exactly, built-ins by any case
+        assert!(format.resolve(&settings).is_err());
+        let format = OutputOptions {
+            preset: Some("Mine".to_string()),
+            ..format
+        }
+        .resolve(&settings)
+        .unwrap();
+        assert_eq!(format.sample_rate_hertz, Some(22050));
+        assert_eq!(format.channels, Some(1));
+        assert!(format.normalize);
+
+        let podcast = OutputOptions {
+            preset: Some("podcast".to_string()),
+            trim_silence: Some(false),
+            ..OutputOptions::default()
+        }
+        .resolve(&settings)
+        .unwrap();
+        assert_eq!(podcast.sample_rate_hertz, Some(44100));
+        assert!(!podcast.trim_silence && podcast.normalize);
+    }
+
+    #[test]
+    fn processing_an_mp3_preset_makes_it_wav() {
+        let format = OutputOptions {
+            preset: Some("Preview/Low".to_string()),
+            channels: Some(2),
+            ..OutputOptions::default()
+        }
+        .resolve(&Settings::default())
+        .unwrap();
+        assert_eq!(format.audio_encoding, OutputEncoding::Linear16);
+        assert_eq!(format.extension(), "wav");
+        assert_eq!(OutputFormat::default().extension(), "mp3");
+    }
+
+    #[test]
+    fn renders_to_the_rate_and_channels_asked_for() {
+        let format = OutputFormat {
+            audio_encoding: OutputEncoding::Linear16,
+            sample_rate_hertz: Some(48000),
+            channels: Some(2),
+            ..OutputFormat::default()
+        };
+        let rendered = format.render(&wav(24000, vec![1000; 2400])).unwrap();
+        let pcm = super::super::stretch::read_wav(&rendered).unwrap();
+        assert_eq!((pcm.sample_rate, pcm.channels), (48000, 2));
+        assert_eq!(pcm.frames(), 4800);
+    }
+}
@@ -0,0 +1,233 @@
+// Header-only inspection of audio files: sample rate, channels, codec and
+// duration from the container, without decoding, so a project's whole
+// media list can be checked in the time it takes to open each file
+use serde::Serialize;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::mp3::{self, FrameHeader};
+
+/// Bytes read from the start of a file; enough for the headers of every
+/// format below, and for an ID3v2 tag with a typical cover image.
+const HEAD_BYTES: u64 = 256 * 1024;
+/// Bytes read from the end of an Ogg file for its last page.
+const TAIL_BYTES: u64 = 64 * 1024;
+/// File extensions treated as audio.
+pub const AUDIO_EXTENSIONS: &[&str] = &[
+    "wav", "wave", "mp3", "flac", "ogg", "oga", "opus", "m4a", "aac", "aif", "aiff",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    /// Integer PCM in a WAV.
+    Pcm,
+    /// Floating-point PCM in a WAV.
+    Float,
+    Mp3,
+    Flac,
+    Vorbis,
+    Opus,
+    /// An audio file whose header isn't one of the above.
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioProbe {
+    pub codec: Codec,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    pub bits_per_sample: Option<u16>,
+    /// From the header's sample count, or the bitrate for an MP3 without
+    /// one; `None` when the header doesn't say.
+    pub duration_ms: Option<f64>,
+}
+
+impl AudioProbe {
+    pub fn unknown() -> Self {
+        Self {
+            codec: Codec::Unknown,
+            sample_rate: None,
+            channels: None,
+            bits_per_sample: None,
+            duration_ms: None,
+        }
+    }
+}
+
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| AUDIO_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+fn u16_le(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_le(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u32_be(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// The fmt chunk and the size of the data chunk, which may end past
+/// `head`; only the chunk headers have to be in it.
+fn probe_wav(head: &[u8]) -> Option<AudioProbe> {
+    let mut format = None;
+    let mut pos = 12;
+    while pos + 8 <= head.len() {
+        let id = &head[pos..pos + 4];
+        let len = u32_le(head, pos + 4)? as usize;
+        match id {
+            b"fmt " => {
+                let body = head.get(pos + 8..pos + 8 + 16)?;
+                let tag = u16_le(body, 0)?;
+                // WAVE_FORMAT_EXTENSIBLE keeps the real tag in its subformat
+                let tag = match tag {
+                    0xFFFE => u16_le(head, pos + 8 + 24).unwrap_or(tag),
+                    tag => tag,
+                };
+                format = Some((
+                    tag,
+                    u16_le(body, 2)?,
+                    u32_le(body, 4)?,
+                    u32_le(body, 8)?,
+                    u16_le(body, 14)?,
+                ));
+            }
+            b"data" => {
+                let (tag, channels, sample_rate, byte_rate, bits) = format?;
+                return Some(AudioProbe {
+                    codec: match tag {
+                        1 => Codec::Pcm,
+                        3 => Codec::Float,
+                        _ => Codec::Unknown,
+                    },
+                    sample_rate: Some(sample_rate).filter(|&r| r > 0),
+                    channels: Some(channels).filter(|&c| c > 0),
+                    bits_per_sample: Some(bits),
+                    duration_ms: (byte_rate > 0).then(|| len as f64 * 1000.0 / byte_rate as f64),
+                });
+            }
+            _ => {}
+        }
+        pos = pos.checked_add(8 + len + (len & 1))?;
+    }
+    None
+}
+
+/// The first frame's header, and the frame count of a Xing or Info header
+/// if it has one; a plain CBR file's length is estimated from its bitrate.
+fn probe_mp3(head: &[u8], file_len: u64) -> Option<AudioProbe> {
+    let offset = mp3::id3v2_len(head);
+    let header = FrameHeader::parse(head.get(offset..)?)?;
+    let frame = head.get(offset..offset + header.frame_len().unwrap_or(0).max(4))?;
+    let xing = frame
+        .windows(4)
+        .position(|w| w == b"Xing" || w == b"Info")
+        .filter(|&at| u32_be(frame, at + 4).is_some_and(|flags| flags & 1 != 0))
+        .and_then(|at| u32_be(frame, at + 8));
+    let duration_ms = match xing {
+        Some(frames) => {
+            Some(frames as f64 * header.samples() as f64 * 1000.0 / header.sample_rate as f64)
+        }
+        None if header.bitrate_kbps > 0 => {
+            Some(file_len.saturating_sub(offset as u64) as f64 * 8.0 / header.bitrate_kbps as f64)
+        }
+        None => None,
+    };
+    Some(AudioProbe {
+        codec: Codec::Mp3,
+        sample_rate: Some(header.sample_rate),
+        channels: Some(if header.channel_mode == 0b11 { 1 } else { 2 }),
+        bits_per_sample: None,
+        duration_ms,
+    })
+}
+
+/// STREAMINFO, which FLAC requires as the first metadata block.
+fn probe_flac(head: &[u8]) -> Option<AudioProbe> {
+    let info = head.get(8..8 + 18)?;
+    let packed = u64::from_be_bytes(info[10..18].try_into().ok()?);
+    let sample_rate = (packed >> 44) as u32;
+    let channels = ((packed >> 41) & 0b111) as u16 + 1;
+    let bits = ((packed >> 36) & 0b1_1111) as u16 + 1;
+    let samples = packed & 0xF_FFFF_FFFF;
+    Some(AudioProbe {
+        codec: Codec::Flac,
+        sample_rate: Some(sample_rate).filter(|&r| r > 0),
+        channels: Some(channels),
+        bits_per_sample: Some(bits),
+        duration_ms: (sample_rate > 0 && samples > 0)
+            .then(|| samples as f64 * 1000.0 / sample_rate as f64),
+    })
+}
+
+/// The identification header in the first page, and the duration from the
+/// granule position of the last page, in `tail`.
+fn probe_ogg(head: &[u8], tail: &[u8]) -> Option<AudioProbe> {
+    let segments = *head.get(26)? as usize;
+    let packet = head.get(27 + segments..)?;
+    let (codec, channels, sample_rate, pre_skip) = if packet.starts_with(b"\x01vorbis") {
+        (
+            Codec::Vorbis,
+            *packet.get(11)? as u16,
+            u32_le(packet, 12)?,
+            0,
+        )
+    } else if packet.starts_with(b"OpusHead") {
+        // Opus always decodes at 48 kHz; the header's rate is only the
+        // input's
+        (
+            Codec::Opus,
+            *packet.get(9)? as u16,
+            48_000,
+            u16_le(packet, 10)? as u64,
+        )
+    } else {
+        return Some(AudioProbe::unknown());
+    };
+    let last_page = tail.windows(4).rposition(|w| w == b"OggS");
+    let granule = last_page
+        .and_then(|at| tail.get(at + 6..at + 14))
+        .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+        .filter(|&g| g != u64::MAX);
+    Some(AudioProbe {
+        codec,
+        sample_rate: Some(sample_rate).filter(|&r| r > 0),
+        channels: Some(channels).filter(|&c| c > 0),
+        bits_per_sample: None,
+        duration_ms: granule
+            .filter(|_| sample_rate > 0)
+            .map(|g| g.saturating_sub(pre_skip) as f64 * 1000.0 / sample_rate as f64),
+    })
+}
+
+/// What the headers of the audio file at `path` say about it. Files in a
+/// format without a probe here are `Codec::Unknown` rather than an error.
+pub fn probe_file(path: &Path) -> std::io::Result<AudioProbe> {
+    let mut file = fs::File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut head = Vec::with_capacity(HEAD_BYTES.min(file_len) as usize);
+    (&mut file).take(HEAD_BYTES).read_to_end(&mut head)?;
+
+    let probe = if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WAVE") {
+        probe_wav(&head)
+    } else if head.starts_with(b"fLaC") {
+        probe_flac(&head)
+    } else if head.starts_with(b"OggS") {
+        let mut tail = Vec::new();
+        if file_len > HEAD_BYTES {
+            file.seek(SeekFrom::Start(file_len.saturating_sub(TAIL_BYTES)))?;
+            file.read_to_end(&mut tail)?;
+        }
+        probe_ogg(&head, if tail.is_empty() { &head } else { &tail })
+    } else {
+        probe_mp3(&head, file_len)
+    };
+    Ok(probe.unwrap_or_else(AudioProbe::unknown))
+}
@@ -0,0 +1,276 @@
+// A quick look at finished audio for the two faults nobody hears until the
+// final render: speech that came back silent (SSML that resolved to
+// nothing) and clipping after gain staging. Google's MP3s are decoded
+// first, so audio is analyzed in whichever format it was synthesized as
+use serde::{Deserialize, Serialize};
+use tauri::Emitter;
+
+use super::decode::decode;
+use super::stretch::Pcm;
+
+/// Samples at either end of the 16-bit range count as clipped.
+const CLIP_LEVEL: i32 = i16::MAX as i32;
+
+/// When an analysis turns into a warning.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QualityThresholds {
+    /// Audio with a lower RMS level is reported as silent.
+    pub min_rms_dbfs: f64,
+    /// Share of clipped samples above which audio is reported as clipped.
+    pub max_clipped_ratio: f64,
+    /// Leading and trailing audio below this level counts as silence.
+    pub silence_dbfs: f64,
+}
+
+impl Default for QualityThresholds {
+    fn default() -> Self {
+        Self {
+            min_rms_dbfs: -50.0,
+            max_clipped_ratio: 0.001,
+            silence_dbfs: -50.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityIssue {
+    Silent,
+    Clipped,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioAnalysis {
+    /// `None` for digital silence.
+    pub peak_dbfs: Option<f64>,
+    pub rms_dbfs: Option<f64>,
+    pub clipped_samples: usize,
+    pub clipped_ratio: f64,
+    pub leading_silence_ms: u64,
+    pub trailing_silence_ms: u64,
+    /// Leading and trailing silence over the whole duration.
+    pub silence_ratio: f64,
+    pub issues: Vec<QualityIssue>,
+}
+
+/// Payload of "audio-quality-warning".
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioQualityWarning {
+    /// File or cache key of the audio; `None` for audio only returned to
+    /// the caller.
+    pub path_or_key: Option<String>,
+    pub issue: QualityIssue,
+    pub analysis: AudioAnalysis,
+}
+
+fn dbfs(level: f64) -> Option<f64> {
+    (level > 0.0).then(|| 20.0 * (level / 32768.0).log10())
+}
+
+fn ms(frames: usize, sample_rate: u32) -> u64 {
+    (frames as u64 * 1000) / sample_rate.max(1) as u64
+}
+
+pub(super) fn analyze(pcm: &Pcm, thresholds: &QualityThresholds) -> AudioAnalysis {
+    let samples = &pcm.samples;
+    let channels = pcm.channels.max(1) as usize;
+    let frames = pcm.frames();
+    let peak = samples.iter().map(|&s| (s as i32).abs()).max().unwrap_or(0);
+    let rms = if samples.is_empty() {
+        0.0
+    } else {
+        let squares: f64 = samples.iter().map(|&s| (s as f64) * (s as f64)).sum();
+        (squares / samples.len() as f64).sqrt()
+    };
+    let clipped_samples = samples
+        .iter()
+        .filter(|&&s| (s as i32).abs() >= CLIP_LEVEL)
+        .count();
+    let clipped_ratio = if samples.is_empty() {
+        0.0
+    } else {
+        clipped_samples as f64 / samples.len() as f64
+    };
+
+    let threshold = 10f64.powf(thresholds.silence_dbfs / 20.0) * 32768.0;
+    let loud = |frame: &[i16]| frame.iter().any(|&s| (s as f64).abs() > threshold);
+    let frame_iter = samples.chunks_exact(channels);
+    let leading = frame_iter.clone().take_while(|f| !loud(f)).count();
+    let trailing = if leading == frames {
+        0
+    } else {
+        frame_iter.rev().take_while(|f| !loud(f)).count()
+    };
+    let silence_ratio = if frames == 0 {
+        1.0
+    } else {
+        (leading + trailing) as f64 / frames as f64
+    };
+
+    let rms_dbfs = dbfs(rms);
+    let mut issues = Vec::new();
+    if rms_dbfs.is_none_or(|level| level < thresholds.min_rms_dbfs) {
+        issues.push(QualityIssue::Silent);
+    }
+    if clipped_ratio > thresholds.max_clipped_ratio {
+        issues.push(QualityIssue::Clipped);
+    }
+    AudioAnalysis {
+        peak_dbfs: dbfs(peak as f64),
+        rms_dbfs,
+        clipped_samples,
+        clipped_ratio,
+        leading_silence_ms: ms(leading, pcm.sample_rate),
+        trailing_silence_ms: ms(trailing, pcm.sample_rate),
+        silence_ratio,
+        issues,
+    }
+}
+
+/// `analyze` for a 16-bit WAV or an MP3; `None` for audio that doesn't
+/// decode.
+pub fn analyze_audio(bytes: &[u8], thresholds: &QualityThresholds) -> Option<AudioAnalysis> {
+    match decode(bytes) {
+        Ok(pcm) => Some(analyze(&pcm, thresholds)),
+        Err(e) => {
+            log::debug!("Skipping quality analysis: {}", e);
+            None
+        }
+    }
+}
+
+/// Analyses of a set of files, such as the segments of a batch, taken
+/// together.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QualitySummary {
+    pub analyzed: usize,
+    /// Ids of the files reported as silent.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub silent: Vec<String>,
+    /// Ids of the files reported as clipped.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub clipped: Vec<String>,
+    /// Loudest peak of any file; `None` when all are digital silence.
+    pub peak_dbfs: Option<f64>,
+    /// Range of the files' RMS levels, leaving out digital silence.
+    pub min_rms_dbfs: Option<f64>,
+    pub max_rms_dbfs: Option<f64>,
+}
+
+impl QualitySummary {
+    pub fn of<'a>(analyses: impl IntoIterator<Item = (&'a str, &'a AudioAnalysis)>) -> Self {
+        let mut summary = Self::default();
+        for (id, analysis) in analyses {
+            summary.analyzed += 1;
+            for issue in &analysis.issues {
+                match issue {
+                    QualityIssue::Silent => summary.silent.push(id.to_string()),
+                    QualityIssue::Clipped => summary.clipped.push(id.to_string()),
+                }
+            }
+            summary.peak_dbfs = max(summary.peak_dbfs, analysis.peak_dbfs);
+            if let Some(rms) = analysis.rms_dbfs {
+                summary.min_rms_dbfs = Some(summary.min_rms_dbfs.map_or(rms, |m| m.min(rms)));
+                summary.max_rms_dbfs = max(summary.max_rms_dbfs, Some(rms));
+            }
+        }
+        summary
+    }
+}
+
+fn max(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Emits "audio-quality-warning" for each issue `analysis` found.
+pub fn warn(app_handle: &tauri::AppHandle, path_or_key: Option<String>, analysis: &AudioAnalysis) {
+    for &issue in &analysis.issues {
+        log::warn!(
+            "Audio {} is {:?} (peak {:?} dBFS, RMS {:?} dBFS, {:.3}% clipped)",
+            path_or_key.as_deref().unwrap_or("(returned)"),
+            issue,
+            analysis.peak_dbfs,
+            analysis.rms_dbfs,
+            analysis.clipped_ratio * 100.0
+        );
+        let warning = AudioQualityWarning {
+            path_or_key: path_or_key.clone(),
+            issue,
+            analysis: analysis.clone(),
+        };
+        if let Err(e) = app_handle.emit("audio-quality-warning", warning) {
+            log::warn!("Failed to emit audio-quality-warning: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcm(samples: Vec<i16>) -> Pcm {
+        Pcm {
+            sample_rate: 1000,
+            channels: 1,
+            samples,
+        }
+    }
+
+    #[test]
+    fn reports_silence_and_clipping() {
+        let thresholds = QualityThresholds::default();
+        let silent = analyze(&pcm(vec![0; 1000]), &thresholds);
+        assert_eq!(silent.issues, [QualityIssue::Silent]);
+        assert_eq!(silent.rms_dbfs, None);
+        assert_eq!(silent.leading_silence_ms, 1000);
+
+        let mut samples = vec![8000i16; 1000];
+        samples[..10].fill(i16::MAX);
+        let clipped = analyze(&pcm(samples), &thresholds);
+        assert_eq!(clipped.issues, [QualityIssue::Clipped]);
+        assert_eq!(clipped.clipped_samples, 10);
+        assert!(clipped.peak_dbfs.unwrap() > -0.01);
+    }
+
+    #[test]
+    fn trims_silence_at_either_end() {
+        let mut samples = vec![0i16; 1000];
+        samples[250..900].fill(4000);
+        let analysis = analyze(&pcm(samples), &QualityThresholds::default());
+        assert!(analysis.issues.is_empty());
+        assert_eq!(analysis.leading_silence_ms, 250);
+        assert_eq!(analysis.trailing_silence_ms, 100);
+        assert!((analysis.silence_ratio - 0.35).abs() < 1e-9);
+    }
+
+    #[test]
+    fn analyzes_mp3_as_synthesized() {
+        let preview =
+            include_bytes!("../../../../../resources/preview_cache/voice_en-US-Standard-C.mp3");
+        let analysis = analyze_audio(preview, &QualityThresholds::default()).unwrap();
+        assert!(analysis.issues.is_empty());
+        assert!(analysis.rms_dbfs.unwrap() > -40.0);
+        assert!(analyze_audio(b"not audio", &QualityThresholds::default()).is_none());
+    }
+
+    #[test]
+    fn summarizes_analyses() {
+        let thresholds = QualityThresholds::default();
+        let silent = analyze(&pcm(vec![0; 100]), &thresholds);
+        let quiet = analyze(&pcm(vec![1000; 100]), &thresholds);
+        let loud = analyze(&pcm(vec![i16::MAX; 100]), &thresholds);
+        let summary = QualitySummary::of([("a", &silent), ("b", &quiet), ("c", &loud)]);
+        assert_eq!(summary.analyzed, 3);
+        assert_eq!(summary.silent, ["a"]);
+        assert_eq!(summary.clipped, ["c"]);
+        assert_eq!(summary.peak_dbfs, loud.peak_dbfs);
+        assert_eq!(summary.min_rms_dbfs, quiet.rms_dbfs);
+        assert_eq!(summary.max_rms_dbfs, loud.rms_dbfs);
+        assert_eq!(QualitySummary::of([]), QualitySummary::default());
+    }
+}
@@ -0,0 +1,759 @@
+// Scratch narration from the microphone, to time an edit before spending
+// TTS credits. Capture runs in-process through cpal on the platform's own
+// audio API (CoreAudio, WASAPI, ALSA), whose callback hands mono 16-bit
+// samples to this side; the WAV file, the level meter and the stop are
+// ours. A device that goes away ends the recording with what was captured
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, Sample, SampleFormat, SizedSample};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{Emitter, Manager};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{mpsc as chunks, oneshot, Notify};
+
+use crate::error::AppError;
+use crate::paths::roots::{AllowedRoots, Write};
+use crate::read_only::WriteAccess;
+use crate::shutdown::ShutdownController;
+
+const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+const SAMPLE_RATES: std::ops::RangeInclusive<u32> = 8_000..=192_000;
+/// Between "recording-level" events.
+const LEVEL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long the device gets to deliver its first audio.
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+/// Silence from a running stream this long means its device went away,
+/// for the backends that don't say so.
+const STALL_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InputDevice {
+    /// What `start_recording` takes as `device_id`.
+    pub id: String,
+    pub name: String,
+    pub is_default: bool,
+}
+
+/// Whether this machine can record, for the UI to offer it or say why not.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingCapability {
+    pub available: bool,
+    /// The platform audio API capture goes through.
+    pub backend: String,
+    pub input_devices: usize,
+    /// Why recording isn't available, when it isn't.
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingStarted {
+    pub recording_id: u64,
+    pub output_path: PathBuf,
+    pub device_id: String,
+    /// The rate asked for, or the device's own when the default isn't one
+    /// it records at.
+    pub sample_rate: u32,
+}
+
+/// Payload of "recording-level", about ten times a second.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingLevel {
+    pub recording_id: u64,
+    /// Both from 0 to 1, over the audio since the last event.
+    pub peak: f32,
+    pub rms: f32,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingEnd {
+    /// By `stop_recording`, or the app exiting.
+    Stopped,
+    /// The device stopped delivering audio; what it sent is kept.
+    DeviceDisconnected,
+}
+
+/// What `stop_recording` returns, and the payload of "recording-stopped"
+/// when the recording ended by itself.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingSummary {
+    pub recording_id: u64,
+    pub output_path: PathBuf,
+    pub duration_ms: f64,
+    /// Highest sample, from 0 to 1.
+    pub peak: f32,
+    /// `None` for silence.
+    pub peak_dbfs: Option<f64>,
+    pub ended_by: RecordingEnd,
+    /// What the audio API said when the device went away.
+    pub message: Option<String>,
+}
+
+struct Active {
+    id: u64,
+    stop: Arc<Notify>,
+    done: oneshot::Receiver<Result<RecordingSummary, AppError>>,
+}
+
+/// Managed state holding the recording in progress, if any.
+#[derive(Default)]
+pub struct Recorder {
+    active: Mutex<Option<Active>>,
+    next_id: AtomicU64,
+}
+
+fn recording_error(message: impl Into<String>) -> AppError {
+    AppError::Recording {
+        message: message.into(),
+    }
+}
+
+/// Where the user lets the app use the microphone.
+fn permission_hint() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "Allow SCLIP under System Settings > Privacy & Security > Microphone, then try again"
+    } else if cfg!(windows) {
+        "Turn on \"Let desktop apps access your microphone\" under Settings > Privacy & security > Microphone, then try again"
+    } else {
+        "Check that your user may use the audio device, e.g. is in the audio group, and that PipeWire or PulseAudio is running"
+    }
+}
+
+/// The error for a capture that failed before any audio, from what the
+/// audio API said.
+fn start_error(message: &str) -> AppError {
+    let lower = message.to_ascii_lowercase();
+    let denied = [
+        "not authorized",
+        "permission",
+        "denied",
+        "not permitted",
+        // E_ACCESSDENIED, as WASAPI reports a microphone turned off in privacy settings
+        "0x80070005",
+    ];
+    if denied.iter().any(|d| lower.contains(d)) {
+        return AppError::MicrophonePermission {
+            message: message.trim().to_string(),
+            hint: permission_hint().to_string(),
+        };
+    }
+    recording_error(format!(
+        "the input device gave no audio: {}",
+        message.trim()
+    ))
+}
+
+/// Whether the user has refused the app the microphone. CoreAudio hands a
+/// refused app silence rather than an error, so this is asked first.
+#[cfg(target_os = "macos")]
+fn microphone_refused() -> bool {
+    use objc2::runtime::AnyObject;
+    use objc2::{class, msg_send};
+
+    #[link(name = "AVFoundation", kind = "framework")]
+    extern "C" {
+        static AVMediaTypeAudio: &'static AnyObject;
+    }
+    // AVAuthorizationStatus: 0 not asked yet, 1 restricted, 2 denied, 3 allowed
+    let status: isize = unsafe {
+        msg_send![
+            class!(AVCaptureDevice),
+            authorizationStatusForMediaType: AVMediaTypeAudio
+        ]
+    };
+    matches!(status, 1 | 2)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn microphone_refused() -> bool {
+    false
+}
+
+/// Runs blocking device work, which some audio APIs do slowly, off the
+/// async runtime.
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T, AppError> + Send + 'static,
+) -> Result<T, AppError> {
+    tauri::async_runtime::spawn_blocking(work)
+        .await
+        .map_err(|e| recording_error(e.to_string()))?
+}
+
+fn input_devices(host: &cpal::Host) -> Result<Vec<InputDevice>, AppError> {
+    let default = host.default_input_device().and_then(|d| d.name().ok());
+    let devices = host
+        .input_devices()
+        .map_err(|e| recording_error(format!("can't list input devices: {}", e)))?;
+    let mut listed: Vec<InputDevice> = Vec::new();
+    for device in devices {
+        // A device that can't name itself can't be picked again by name
+        let Ok(name) = device.name() else {
+            continue;
+        };
+        if listed.iter().any(|d| d.id == name) {
+            continue;
+        }
+        listed.push(InputDevice {
+            is_default: default.as_deref() == Some(name.as_str()),
+            id: name.clone(),
+            name,
+        });
+    }
+    Ok(listed)
+}
+
+/// The input named `device_id`, or the default one.
+fn find_device(host: &cpal::Host, device_id: Option<&str>) -> Result<cpal::Device, AppError> {
+    let Some(id) = device_id else {
+        return host
+            .default_input_device()
+            .ok_or_else(|| recording_error("no input device found"));
+    };
+    host.input_devices()
+        .map_err(|e| recording_error(format!("can't list input devices: {}", e)))?
+        .find(|d| d.name().is_ok_and(|name| name == id))
+        .ok_or_else(|| recording_error(format!("no input device named {}", id)))
+}
+
+/// The formats in order of preference: ones converted losslessly to 16-bit
+/// first.
+fn format_rank(format: SampleFormat) -> u8 {
+    match format {
+        SampleFormat::I16 => 0,
+        SampleFormat::F32 | SampleFormat::I32 => 1,
+        _ => 2,
+    }
+}
+
+/// The device's configuration for recording at `sample_rate`, fewest
+/// channels first. With `None` that's the default rate if the device takes
+/// it, its own rate if not.
+fn stream_config(
+    device: &cpal::Device,
+    sample_rate: Option<u32>,
+) -> Result<cpal::SupportedStreamConfig, AppError> {
+    let ranges: Vec<_> = device
+        .supported_input_configs()
+        .map_err(|e| start_error(&e.to_string()))?
+        .collect();
+    let rate = sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+    let supported = ranges
+        .iter()
+        .filter(|r| (r.min_sample_rate().0..=r.max_sample_rate().0).contains(&rate))
+        .min_by_key(|r| (format_rank(r.sample_format()), r.channels()));
+    if let Some(range) = supported {
+        return Ok(range.with_sample_rate(cpal::SampleRate(rate)));
+    }
+    if sample_rate.is_none() {
+        return device
+            .default_input_config()
+            .map_err(|e| start_error(&e.to_string()));
+    }
+    let offered: Vec<String> = ranges
+        .iter()
+        .map(|r| format!("{}-{} Hz", r.min_sample_rate().0, r.max_sample_rate().0))
+        .collect();
+    Err(AppError::InvalidAudioRequest {
+        message: format!(
+            "the device doesn't record at {} Hz (it offers {})",
+            rate,
+            offered.join(", ")
+        ),
+    })
+}
+
+/// What the stream's callbacks hand the capture task.
+enum Captured {
+    /// Mono samples, in the order they were heard.
+    Audio(Vec<i16>),
+    /// The stream failed, usually because its device went away.
+    Failed(String),
+}
+
+/// `data`, interleaved frames of `channels` samples, as 16-bit mono.
+fn mono<T>(data: &[T], channels: usize) -> Vec<i16>
+where
+    T: Sample,
+    i16: FromSample<T>,
+{
+    data.chunks(channels.max(1))
+        .map(|frame| {
+            let sum: i32 = frame.iter().map(|&s| s.to_sample::<i16>() as i32).sum();
+            (sum / frame.len() as i32) as i16
+        })
+        .collect()
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    sender: chunks::UnboundedSender<Captured>,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample,
+    i16: FromSample<T>,
+{
+    let channels = config.channels as usize;
+    let errors = sender.clone();
+    device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            let _ = sender.send(Captured::Audio(mono(data, channels)));
+        },
+        move |e| {
+            let _ = errors.send(Captured::Failed(e.to_string()));
+        },
+        None,
+    )
+}
+
+/// Keeps the stream playing on its own thread, which it can't leave on
+/// every platform, until dropped.
+struct StreamHandle {
+    _stop: mpsc::Sender<()>,
+}
+
+/// Starts capturing from `device` and returns the stream's handle and the
+/// rate it records at.
+fn open_stream(
+    device_id: Option<String>,
+    sample_rate: Option<u32>,
+    sender: chunks::UnboundedSender<Captured>,
+) -> Result<(StreamHandle, String, u32), AppError> {
+    let (stop, stopped) = mpsc::channel::<()>();
+    let (opened_tx, opened_rx) = mpsc::channel();
+    std::thread::Builder::new()
+        .name("microphone".to_string())
+        .spawn(move || {
+            let opened = (|| {
+                if microphone_refused() {
+                    return Err(AppError::MicrophonePermission {
+                        message: "SCLIP isn't allowed to use the microphone".to_string(),
+                        hint: permission_hint().to_string(),
+                    });
+                }
+                let host = cpal::default_host();
+                let device = find_device(&host, device_id.as_deref())?;
+                let name = device.name().unwrap_or_else(|_| "input".to_string());
+                let supported = stream_config(&device, sample_rate)?;
+                let config = supported.config();
+                let stream = match supported.sample_format() {
+                    SampleFormat::I16 => build_stream::<i16>(&device, &config, sender),
+                    SampleFormat::I32 => build_stream::<i32>(&device, &config, sender),
+                    SampleFormat::F32 => build_stream::<f32>(&device, &config, sender),
+                    SampleFormat::U16 => build_stream::<u16>(&device, &config, sender),
+                    SampleFormat::U8 => build_stream::<u8>(&device, &config, sender),
+                    SampleFormat::I8 => build_stream::<i8>(&device, &config, sender),
+                    SampleFormat::F64 => build_stream::<f64>(&device, &config, sender),
+                    other => {
+                        return Err(recording_error(format!(
+                            "{} delivers {} samples, which can't be recorded",
+                            name, other
+                        )))
+                    }
+                }
+                .map_err(|e| start_error(&e.to_string()))?;
+                stream.play().map_err(|e| start_error(&e.to_string()))?;
+                Ok((stream, name, config.sample_rate.0))
+            })();
+            match opened {
+                Ok((stream, name, rate)) => {
+                    let _ = opened_tx.send(Ok((name, rate)));
+                    // Until the handle is dropped
+                    let _ = stopped.recv();
+                    drop(stream);
+                }
+                Err(e) => {
+                    let _ = opened_tx.send(Err(e));
+                }
+            }
+        })?;
+    let (name, rate) = opened_rx
+        .recv()
+        .map_err(|_| recording_error("the audio thread ended before the stream opened"))??;
+    Ok((StreamHandle { _stop: stop }, name, rate))
+}
+
+/// Peak and sum of squares of the samples heard since the last level event,
+/// and the peak of the whole recording.
+#[derive(Default)]
+struct Meter {
+    window_peak: i32,
+    window_squares: f64,
+    window_samples: u64,
+    peak: i32,
+}
+
+impl Meter {
+    fn add(&mut self, samples: &[i16]) {
+        for &sample in samples {
+            let sample = sample as i32;
+            self.window_peak = self.window_peak.max(sample.abs());
+            self.window_squares += (sample as f64).powi(2);
+            self.window_samples += 1;
+        }
+        self.peak = self.peak.max(self.window_peak);
+    }
+
+    /// The window's peak and RMS from 0 to 1, starting a new window.
+    fn take(&mut self) -> (f32, f32) {
+        let full_scale = i16::MAX as f64;
+        let peak = (self.window_peak as f64 / full_scale).min(1.0);
+        let rms = if self.window_samples == 0 {
+            0.0
+        } else {
+            (self.window_squares / self.window_samples as f64).sqrt() / full_scale
+        };
+        *self = Meter {
+            peak: self.peak,
+            ..Meter::default()
+        };
+        (peak as f32, rms.min(1.0) as f32)
+    }
+}
+
+/// The temp file a recording is written to before it's complete.
+fn partial_path(output: &Path) -> PathBuf {
+    let mut name = output.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    output.with_file_name(name)
+}
+
+struct Capture {
+    app_handle: tauri::AppHandle,
+    id: u64,
+    output: PathBuf,
+    sample_rate: u32,
+    stop: Arc<Notify>,
+}
+
+impl Capture {
+    /// Copies audio from the stream into the WAV file until stopped or the
+    /// device goes away. `ready` hears once audio arrives, or why it never
+    /// did.
+    async fn run(
+        self,
+        stream: StreamHandle,
+        mut audio: chunks::UnboundedReceiver<Captured>,
+        mut signal: crate::shutdown::ShutdownSignal,
+        ready: oneshot::Sender<Result<(), AppError>>,
+    ) -> Result<RecordingSummary, AppError> {
+        let partial = partial_path(&self.output);
+        if let Some(parent) = partial.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut file = tokio::fs::File::create(&partial).await?;
+        file.write_all(&super::placeholder::wav_header(self.sample_rate, 1, 0)?)
+            .await?;
+
+        let started = Instant::now();
+        let mut last_level = Instant::now();
+        let mut ready = Some(ready);
+        let mut meter = Meter::default();
+        let mut frames: u64 = 0;
+        let mut failure = None;
+        let ended_by = loop {
+            // The first audio gets as long as `start_recording` waits
+            let wait = if ready.is_some() {
+                START_TIMEOUT
+            } else {
+                STALL_TIMEOUT
+            };
+            let captured = tokio::select! {
+                _ = self.stop.notified() => break RecordingEnd::Stopped,
+                _ = signal.triggered() => break RecordingEnd::Stopped,
+                captured = tokio::time::timeout(wait, audio.recv()) => captured,
+            };
+            let samples = match captured {
+                Ok(Some(Captured::Audio(samples))) => samples,
+                Ok(Some(Captured::Failed(message))) => {
+                    failure = Some(message);
+                    break RecordingEnd::DeviceDisconnected;
+                }
+                Ok(None) => break RecordingEnd::DeviceDisconnected,
+                Err(_) => {
+                    failure = Some(if ready.is_some() {
+                        format!("nothing within {:?}", START_TIMEOUT)
+                    } else {
+                        "the input device stopped delivering audio".to_string()
+                    });
+                    break RecordingEnd::DeviceDisconnected;
+                }
+            };
+            let pcm: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+            file.write_all(&pcm).await?;
+            frames += samples.len() as u64;
+            meter.add(&samples);
+            if let Some(ready) = ready.take() {
+                let _ = ready.send(Ok(()));
+            }
+            if last_level.elapsed() >= LEVEL_INTERVAL {
+                last_level = Instant::now();
+                let (peak, rms) = meter.take();
+                let level = RecordingLevel {
+                    recording_id: self.id,
+                    peak,
+                    rms,
+                    elapsed_ms: started.elapsed().as_millis() as u64,
+                };
+                if let Err(e) = self.app_handle.emit("recording-level", level) {
+                    log::warn!("Failed to emit recording-level: {}", e);
+                }
+            }
+        };
+
+        drop(stream);
+        if let Some(ready) = ready.take() {
+            drop(file);
+            let _ = tokio::fs::remove_file(&partial).await;
+            let error = match ended_by {
+                RecordingEnd::Stopped => recording_error("stopped before any audio arrived"),
+                RecordingEnd::DeviceDisconnected => start_error(failure.as_deref().unwrap_or("")),
+            };
+            let _ = ready.send(Err(error.clone()));
+            return Err(error);
+        }
+        // What the callback had handed over before the stream stopped
+        while let Ok(Captured::Audio(samples)) = audio.try_recv() {
+            let pcm: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+            file.write_all(&pcm).await?;
+            frames += samples.len() as u64;
+            meter.add(&samples);
+        }
+
+        file.seek(std::io::SeekFrom::Start(0)).await?;
+        file.write_all(&super::placeholder::wav_header(
+            self.sample_rate,
+            1,
+            frames,
+        )?)
+        .await?;
+        file.sync_all().await?;
+        drop(file);
+        tokio::fs::rename(&partial, &self.output).await?;
+
+        let peak = (meter.peak as f64 / i16::MAX as f64).min(1.0);
+        let summary = RecordingSummary {
+            recording_id: self.id,
+            output_path: self.output.clone(),
+            duration_ms: frames as f64 * 1000.0 / self.sample_rate as f64,
+            peak: peak as f32,
+            peak_dbfs: (peak > 0.0).then(|| 20.0 * peak.log10()),
+            ended_by,
+            message: (ended_by == RecordingEnd::DeviceDisconnected)
+                .then_some(failure)
+                .flatten(),
+        };
+        if ended_by == RecordingEnd::DeviceDisconnected {
+            log::warn!(
+                "Recording {} ended when the input device went away, keeping {:.0} ms",
+                self.id,
+                summary.duration_ms
+            );
+            if let Err(e) = self.app_handle.emit("recording-stopped", summary.clone()) {
+                log::warn!("Failed to emit recording-stopped: {}", e);
+            }
+        }
+        Ok(summary)
+    }
+}
+
+/// Whether recording works here: the platform audio API has at least one
+/// input device. The UI asks before offering to record.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn get_recording_capability() -> Result<RecordingCapability, AppError> {
+    blocking(|| {
+        let host = cpal::default_host();
+        let backend = host.id().name().to_string();
+        let (input_devices, reason) = match input_devices(&host) {
+            Ok(devices) if devices.is_empty() => (0, Some("no input device found".to_string())),
+            Ok(devices) => (devices.len(), None),
+            Err(e) => (0, Some(e.to_string())),
+        };
+        Ok(RecordingCapability {
+            available: reason.is_none(),
+            backend,
+            input_devices,
+            reason,
+        })
+    })
+    .await
+}
+
+/// The microphones and other inputs that can be recorded from, the default
+/// first.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn list_input_devices() -> Result<Vec<InputDevice>, AppError> {
+    let mut devices = blocking(|| input_devices(&cpal::default_host())).await?;
+    devices.sort_by_key(|d| !d.is_default);
+    Ok(devices)
+}
+
+/// Starts recording `device_id` (the default input if `None`) as mono
+/// 16-bit WAV at `sample_rate` (default 48 kHz, or the device's own rate
+/// when it doesn't take that) to `output_path`, normally in the project's
+/// resources directory; the path is checked like any other write. Returns
+/// once audio is arriving, so a refused microphone fails here with
+/// `MicrophonePermission`. One recording runs at a time.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn start_recording(
+    app_handle: tauri::AppHandle,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    recorder: tauri::State<'_, Recorder>,
+    output_path: PathBuf,
+    device_id: Option<String>,
+    sample_rate: Option<u32>,
+) -> Result<RecordingStarted, AppError> {
+    access.check()?;
+    if let Some(rate) = sample_rate.filter(|r| !SAMPLE_RATES.contains(r)) {
+        return Err(AppError::InvalidAudioRequest {
+            message: format!(
+                "sample rate must be {} to {} Hz, not {}",
+                SAMPLE_RATES.start(),
+                SAMPLE_RATES.end(),
+                rate
+            ),
+        });
+    }
+    let is_wav = output_path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("wav"));
+    if !is_wav {
+        return Err(AppError::InvalidAudioRequest {
+            message: "recordings are written as .wav".to_string(),
+        });
+    }
+    let output = roots.validate_path::<Write>(&output_path)?.into_path_buf();
+    {
+        let mut active = recorder.active.lock().unwrap();
+        // One that ended by itself has already been announced
+        if let Some(running) = active.as_mut() {
+            if running.done.try_recv().is_ok() {
+                *active = None;
+            } else {
+                return Err(recording_error(
+                    "a recording is already running; stop it first",
+                ));
+            }
+        }
+    }
+
+    let (sender, audio) = chunks::unbounded_channel();
+    let (stream, device_id, sample_rate) =
+        blocking(move || open_stream(device_id, sample_rate, sender)).await?;
+    let id = recorder.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+    let stop = Arc::new(Notify::new());
+    let (ready_tx, ready_rx) = oneshot::channel();
+    let (done_tx, done_rx) = oneshot::channel();
+    let capture = Capture {
+        app_handle: app_handle.clone(),
+        id,
+        output: output.clone(),
+        sample_rate,
+        stop: stop.clone(),
+    };
+    app_handle.state::<ShutdownController>().spawn(
+        "microphone recording",
+        move |signal| async move {
+            let result = capture.run(stream, audio, signal, ready_tx).await;
+            if let Err(e) = &result {
+                log::warn!("Recording {} failed: {}", id, e);
+            }
+            let _ = done_tx.send(result);
+        },
+    );
+
+    // The capture gives up after `START_TIMEOUT` itself
+    match ready_rx.await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => return Err(e),
+        Err(_) => return Err(recording_error("the recording ended before it started")),
+    }
+    *recorder.active.lock().unwrap() = Some(Active {
+        id,
+        stop,
+        done: done_rx,
+    });
+    log::info!(
+        "Recording {} from {} at {} Hz to {}",
+        id,
+        device_id,
+        sample_rate,
+        output.display()
+    );
+    Ok(RecordingStarted {
+        recording_id: id,
+        output_path: output,
+        device_id,
+        sample_rate,
+    })
+}
+
+/// Stops the recording and finishes its WAV file. A recording that ended
+/// by itself, its device gone, is still returned here once, with
+/// `ended_by` saying so.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn stop_recording(
+    recorder: tauri::State<'_, Recorder>,
+) -> Result<RecordingSummary, AppError> {
+    let active = recorder
+        .active
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or_else(|| recording_error("no recording is running"))?;
+    active.stop.notify_one();
+    let summary = active
+        .done
+        .await
+        .map_err(|_| recording_error("the recording task ended without a result"))??;
+    log::info!(
+        "Recording {} stopped after {:.0} ms",
+        active.id,
+        summary.duration_ms
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downmixes_to_16_bit_mono() {
+        assert_eq!(mono(&[1000i16, 3000, -2000, -4000], 2), [2000, -3000]);
+        assert_eq!(mono(&[0.5f32, -1.0], 1), [16384, -32768]);
+        assert_eq!(mono(&[u16::MAX, 32768], 1), [i16::MAX, 0]);
+    }
+
+    #[test]
+    fn meters_each_window_and_the_whole_recording() {
+        let mut meter = Meter::default();
+        meter.add(&[i16::MAX, -i16::MAX]);
+        assert_eq!(meter.take(), (1.0, 1.0));
+        meter.add(&[0, 0]);
+        assert_eq!(meter.take(), (0.0, 0.0));
+        assert_eq!(meter.peak, i16::MAX as i32);
+    }
+
+    #[test]
+    fn denials_name_where_to_allow_the_microphone() {
+        assert!(matches!(
+            start_error("AudioUnit: 'not authorized'"),
+            AppError::MicrophonePermission { .. }
+        ));
+        assert!(matches!(
+            start_error("device busy"),
+            AppError::Recording { .. }
+        ));
+    }
+}
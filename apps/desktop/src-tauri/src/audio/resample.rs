@@ -0,0 +1,90 @@
+// Sample-rate conversion of narration and other foreground audio, by
+// windowed sinc band-limited to the lower of the two rates so downsampling
+// doesn't alias. Music beds get the cheaper interpolation in `music`
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::channels::encode_wav;
+use super::stretch::{read_wav, Pcm};
+use crate::error::AppError;
+
+/// Input samples each side of an output sample when upsampling; more when
+/// downsampling, as the filter widens with the cutoff.
+const HALF_TAPS: usize = 16;
+/// Output frames between checks of the cancel flag.
+const CANCEL_CHECK_FRAMES: usize = 64 * 1024;
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// `pcm` at `sample_rate`; `None` if `cancel` was set before it finished.
+pub(super) fn resample(pcm: &Pcm, sample_rate: u32, cancel: &AtomicBool) -> Option<Pcm> {
+    let channels = pcm.channels as usize;
+    let frames = pcm.frames();
+    if pcm.sample_rate == sample_rate || frames == 0 {
+        return Some(Pcm {
+            sample_rate: pcm.sample_rate,
+            channels: pcm.channels,
+            samples: pcm.samples.clone(),
+        });
+    }
+    let ratio = sample_rate as f64 / pcm.sample_rate as f64;
+    let cutoff = ratio.min(1.0);
+    let half = (HALF_TAPS as f64 / cutoff).ceil() as isize;
+    let out_frames = (frames as f64 * ratio).round() as usize;
+
+    let mut samples = Vec::with_capacity(out_frames * channels);
+    let mut acc = vec![0.0f64; channels];
+    for i in 0..out_frames {
+        if i % CANCEL_CHECK_FRAMES == 0 && cancel.load(Ordering::Relaxed) {
+            return None;
+        }
+        let at = i as f64 / ratio;
+        let center = at.floor() as isize;
+        acc.iter_mut().for_each(|a| *a = 0.0);
+        let mut weight_sum = 0.0;
+        for n in (center - half + 1).max(0)..=(center + half).min(frames as isize - 1) {
+            let x = at - n as f64;
+            let window = 0.5 * (1.0 + (PI * x / half as f64).cos());
+            let weight = cutoff * sinc(cutoff * x) * window;
+            weight_sum += weight;
+            let frame = &pcm.samples[n as usize * channels..(n as usize + 1) * channels];
+            for (a, &s) in acc.iter_mut().zip(frame) {
+                *a += s as f64 * weight;
+            }
+        }
+        // Normalized so the edges, where taps fall outside, keep their level
+        let scale = if weight_sum.abs() > 1e-9 {
+            1.0 / weight_sum
+        } else {
+            1.0
+        };
+        for a in &acc {
+            samples.push((a * scale).round().clamp(i16::MIN as f64, i16::MAX as f64) as i16);
+        }
+    }
+    Some(Pcm {
+        sample_rate,
+        channels: pcm.channels,
+        samples,
+    })
+}
+
+/// A 16-bit PCM WAV at `sample_rate`, with the same channels; `None` if
+/// `cancel` was set before it finished.
+pub fn resample_wav(
+    wav: &[u8],
+    sample_rate: u32,
+    cancel: &AtomicBool,
+) -> Result<Option<Vec<u8>>, AppError> {
+    let pcm = read_wav(wav)?;
+    match resample(&pcm, sample_rate, cancel) {
+        Some(resampled) => Ok(Some(encode_wav(&resampled)?)),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,393 @@
+// Pitch-preserving time stretch (WSOLA) so a narration can be fitted to a
+// fixed video slot without rewriting the script, and a voice preview played
+// faster or slower without synthesizing it again
+use serde::Serialize;
+use std::path::PathBuf;
+
+use super::channels;
+use super::quality::{self, AudioAnalysis, QualityThresholds};
+use crate::error::AppError;
+use crate::format::DisplayFormat;
+use crate::paths::roots::{AllowedPath, AllowedRoots, Read, Write};
+use crate::read_only::WriteAccess;
+use crate::settings::SettingsStore;
+
+const MAX_TARGET_MS: u64 = 60 * 60 * 1000;
+/// Analysis window; long enough to span a pitch period of low voices.
+const WINDOW_MS: u32 = 30;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FittedAudio {
+    pub path: PathBuf,
+    pub input_duration_ms: f64,
+    pub duration_ms: f64,
+    pub duration_display: String,
+    /// Output duration over input duration; above 1 slows the speech down.
+    pub ratio: f64,
+    pub channels: u16,
+    pub analysis: AudioAnalysis,
+}
+
+fn invalid(message: impl Into<String>) -> AppError {
+    AppError::InvalidAudioRequest {
+        message: message.into(),
+    }
+}
+
+/// 16-bit PCM samples, interleaved by channel.
+pub(super) struct Pcm {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub samples: Vec<i16>,
+}
+
+impl Pcm {
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+}
+
+/// Playing time of a 16-bit PCM WAV in milliseconds.
+pub fn wav_duration_ms(bytes: &[u8]) -> Option<f64> {
+    let pcm = read_wav(bytes).ok()?;
+    Some(pcm.frames() as f64 * 1000.0 / pcm.sample_rate as f64)
+}
+
+/// Reads a 16-bit PCM WAV, as written by Google's LINEAR16 encoding, the OS
+/// voices and `generate_placeholder_audio`.
+pub(super) fn read_wav(bytes: &[u8]) -> Result<Pcm, AppError> {
+    let unsupported = || invalid("input must be a 16-bit PCM WAV file (LINEAR16)");
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(unsupported());
+    }
+    let mut format = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let len = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        let body = &bytes[pos + 8..(pos + 8).saturating_add(len).min(bytes.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let tag = u16::from_le_bytes([body[0], body[1]]);
+                let channels = u16::from_le_bytes([body[2], body[3]]);
+                let sample_rate = u32::from_le_bytes(body[4..8].try_into().unwrap());
+                let bits = u16::from_le_bytes([body[14], body[15]]);
+                // 1 is PCM, 0xFFFE WAVE_FORMAT_EXTENSIBLE
+                if !matches!(tag, 1 | 0xFFFE) || bits != 16 || channels == 0 || sample_rate == 0 {
+                    return Err(unsupported());
+                }
+                format = Some((sample_rate, channels));
+            }
+            b"data" => {
+                let (sample_rate, channels) = format.ok_or_else(unsupported)?;
+                let samples = body
+                    .chunks_exact(2)
+                    .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                    .collect();
+                return Ok(Pcm {
+                    sample_rate,
+                    channels,
+                    samples,
+                });
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        pos += 8 + len + (len & 1);
+    }
+    Err(unsupported())
+}
+
+fn hann(n: usize) -> Vec<f32> {
+    (0..n)
+        .map(|i| {
+            let x = std::f32::consts::PI * i as f32 / n as f32;
+            x.sin() * x.sin()
+        })
+        .collect()
+}
+
+/// Offset in `-tolerance..=tolerance` around `nominal` whose window looks
+/// most like `reference`, by normalized cross-correlation over `mono`.
+fn best_offset(
+    mono: &[f32],
+    reference: usize,
+    nominal: usize,
+    tolerance: usize,
+    window: usize,
+) -> usize {
+    let expected = &mono[reference..reference + window];
+    let first = nominal.saturating_sub(tolerance);
+    let last = (nominal + tolerance).min(mono.len() - window);
+    let mut best = (f32::MIN, nominal.min(last));
+    // Every other sample is plenty to find the alignment at speech rates
+    for candidate in first..=last {
+        let segment = &mono[candidate..candidate + window];
+        let (mut dot, mut energy) = (0.0f32, 0.0f32);
+        for i in (0..window).step_by(2) {
+            dot += expected[i] * segment[i];
+            energy += segment[i] * segment[i];
+        }
+        let score = if energy > 0.0 {
+            dot / energy.sqrt()
+        } else {
+            0.0
+        };
+        if score > best.0 {
+            best = (score, candidate);
+        }
+    }
+    best.1
+}
+
+/// WSOLA: Hann windows overlapping by half are taken from the input every
+/// `hop / ratio` frames and added to the output every `hop` frames, each one
+/// nudged by up to a quarter window to where it continues the previous
+/// one's waveform. Matching waveforms is what keeps the overlaps free of
+/// clicks and phasing. Returns exactly `target_frames` frames.
+fn wsola(pcm: &Pcm, target_frames: usize) -> Vec<i16> {
+    let channels = pcm.channels as usize;
+    let frames = pcm.frames();
+    let window = ((pcm.sample_rate * WINDOW_MS / 1000) as usize).max(16) & !1;
+    let hop = window / 2;
+    let tolerance = window / 4;
+    let analysis_hop = hop as f64 * frames as f64 / target_frames as f64;
+
+    // Zero padding lets every window, searched or natural, stay in bounds
+    let padded = frames + window * 3;
+    let sample = |frame: usize, channel: usize| -> f32 {
+        if frame < frames {
+            pcm.samples[frame * channels + channel] as f32
+        } else {
+            0.0
+        }
+    };
+    let mono: Vec<f32> = (0..padded)
+        .map(|f| (0..channels).map(|c| sample(f, c)).sum::<f32>() / channels as f32)
+        .collect();
+
+    let weights = hann(window);
+    let mut out = vec![0.0f32; (target_frames + window) * channels];
+    let mut norm = vec![0.0f32; target_frames + window];
+    let mut previous = 0usize;
+    let mut k = 0usize;
+    while k * hop < target_frames {
+        let nominal = ((k as f64 * analysis_hop).round() as usize).min(frames + window);
+        let position = if k == 0 {
+            0
+        } else {
+            best_offset(&mono, previous + hop, nominal, tolerance, window)
+        };
+        let at = k * hop;
+        for (i, w) in weights.iter().enumerate() {
+            for c in 0..channels {
+                out[(at + i) * channels + c] += sample(position + i, c) * w;
+            }
+            norm[at + i] += w;
+        }
+        previous = position;
+        k += 1;
+    }
+
+    (0..target_frames * channels)
+        .map(|i| {
+            let weight = norm[i / channels];
+            let value = if weight > 1e-6 { out[i] / weight } else { 0.0 };
+            value.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+        })
+        .collect()
+}
+
+/// `audio`, a WAV or an MP3, as a WAV that plays it at `rate` with its
+/// pitch kept; at 1× it's returned as it is.
+pub(crate) fn at_rate(audio: Vec<u8>, rate: f32) -> Result<Vec<u8>, AppError> {
+    super::arbitration::check_playback_rate(rate)
+        .map_err(|message| invalid(format!("playback rate {}", message)))?;
+    if rate == 1.0 {
+        return Ok(audio);
+    }
+    let pcm = super::decode::decode(&audio)?;
+    let frames = pcm.frames();
+    if frames == 0 {
+        return Ok(audio);
+    }
+    let target_frames = ((frames as f64 / rate as f64).round() as usize).max(1);
+    channels::encode_wav(&Pcm {
+        sample_rate: pcm.sample_rate,
+        channels: pcm.channels,
+        samples: wsola(&pcm, target_frames),
+    })
+}
+
+/// Stretches or compresses the WAV at `input` to `target_ms` without
+/// changing its pitch. Refuses with `StretchTooLarge` when that takes more
+/// than `max_stretch_pct` either way, where trimming the script is the
+/// better fix. `channels`, if set, remixes the result to that many channels.
+pub fn fit(
+    input: &AllowedPath<Read>,
+    target_ms: u64,
+    max_stretch_pct: f64,
+    channels: Option<u16>,
+    output: &AllowedPath<Write>,
+    format: &DisplayFormat,
+    thresholds: &QualityThresholds,
+) -> Result<FittedAudio, AppError> {
+    if target_ms == 0 || target_ms > MAX_TARGET_MS {
+        return Err(invalid(format!(
+            "target duration must be between 1 and {} ms",
+            MAX_TARGET_MS
+        )));
+    }
+    if !(0.0..=100.0).contains(&max_stretch_pct) {
+        return Err(invalid("max_stretch_pct must be between 0 and 100"));
+    }
+    if !output
+        .path()
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("wav"))
+    {
+        return Err(invalid("fitted audio is written as a .wav file"));
+    }
+
+    let pcm = read_wav(&input.read()?)?;
+    let frames = pcm.frames();
+    if frames == 0 {
+        return Err(invalid("input audio is empty"));
+    }
+    let input_duration_ms = frames as f64 * 1000.0 / pcm.sample_rate as f64;
+    let target_frames = super::placeholder::sample_count(target_ms, pcm.sample_rate) as usize;
+    let ratio = target_frames as f64 / frames as f64;
+    if (ratio - 1.0).abs() * 100.0 > max_stretch_pct {
+        return Err(AppError::StretchTooLarge {
+            required_ratio: ratio,
+            max_stretch_pct,
+            input_duration_ms,
+            target_ms,
+        });
+    }
+
+    let samples = if target_frames == frames {
+        pcm.samples.clone()
+    } else {
+        wsola(&pcm, target_frames)
+    };
+    let fitted = Pcm {
+        sample_rate: pcm.sample_rate,
+        channels: pcm.channels,
+        samples,
+    };
+    let fitted = channels::remix(fitted, channels.unwrap_or(pcm.channels))?;
+    let wav = channels::encode_wav(&fitted)?;
+    output.write_atomic(&wav)?;
+
+    let duration_ms = target_frames as f64 * 1000.0 / pcm.sample_rate as f64;
+    Ok(FittedAudio {
+        path: output.path().to_path_buf(),
+        input_duration_ms,
+        duration_ms,
+        duration_display: format.duration(duration_ms),
+        ratio,
+        channels: fitted.channels,
+        analysis: quality::analyze(&fitted, thresholds),
+    })
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn fit_audio_to_duration(
+    app_handle: tauri::AppHandle,
+    settings: tauri::State<'_, SettingsStore>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    input_path: PathBuf,
+    target_ms: u64,
+    max_stretch_pct: f64,
+    channels: Option<u8>,
+    output_path: PathBuf,
+) -> Result<FittedAudio, AppError> {
+    access.check()?;
+    let channels = channels::check(channels)?;
+    let input = roots.validate_path(&input_path)?;
+    let output = roots.validate_path(&output_path)?;
+    let defaults = settings.get();
+    let format = DisplayFormat::for_locale(&defaults.ui_locale);
+    let fitted = tauri::async_runtime::spawn_blocking(move || {
+        fit(
+            &input,
+            target_ms,
+            max_stretch_pct,
+            channels,
+            &output,
+            &format,
+            &defaults.audio_quality,
+        )
+    })
+    .await
+    .map_err(|e| AppError::Io {
+        message: e.to_string(),
+    })??;
+    quality::warn(
+        &app_handle,
+        Some(fitted.path.display().to_string()),
+        &fitted.analysis,
+    );
+    Ok(fitted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A second of a 200 Hz tone at 16 kHz.
+    fn tone() -> Vec<u8> {
+        let pcm = Pcm {
+            sample_rate: 16_000,
+            channels: 1,
+            samples: (0..16_000)
+                .map(|i| {
+                    let t = i as f32 / 16_000.0;
+                    ((2.0 * std::f32::consts::PI * 200.0 * t).sin() * 10_000.0) as i16
+                })
+                .collect(),
+        };
+        channels::encode_wav(&pcm).unwrap()
+    }
+
+    /// Zero crossings per second, twice the frequency of a tone.
+    fn crossings_per_second(pcm: &Pcm) -> f64 {
+        let crossings = pcm
+            .samples
+            .windows(2)
+            .filter(|w| (w[0] < 0) != (w[1] < 0))
+            .count();
+        crossings as f64 * pcm.sample_rate as f64 / pcm.frames() as f64
+    }
+
+    #[test]
+    fn faster_rates_shorten_the_audio_and_keep_its_pitch() {
+        for rate in [0.5, 1.5, 2.0] {
+            let stretched = read_wav(&at_rate(tone(), rate).unwrap()).unwrap();
+            assert_eq!(stretched.frames(), (16_000.0 / rate).round() as usize);
+            let per_second = crossings_per_second(&stretched);
+            assert!(
+                (per_second - 400.0).abs() < 20.0,
+                "{} at {}",
+                per_second,
+                rate
+            );
+        }
+    }
+
+    #[test]
+    fn the_normal_rate_leaves_the_audio_alone() {
+        let audio = tone();
+        assert_eq!(at_rate(audio.clone(), 1.0).unwrap(), audio);
+    }
+
+    #[test]
+    fn refuses_rates_out_of_range() {
+        assert!(at_rate(tone(), 0.25).is_err());
+        assert!(at_rate(tone(), 3.0).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+// ID3v2 metadata for exported MP3s, so they are identifiable in an asset library
+use id3::frame::{Chapter as ChapterFrame, Comment, TableOfContents};
+use id3::{Frame, Tag, TagLike, Version};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use super::mp3;
+use crate::error::AppError;
+use crate::paths::roots::{AllowedRoots, Read};
+
+/// Description of the comment frame the app signs its output with.
+const GENERATOR_DESCRIPTION: &str = "SCLIP";
+const COMMENT_LANG: &str = "eng";
+const TOC_ID: &str = "toc";
+/// CHAP offsets meaning "use the millisecond times instead".
+const NO_OFFSET: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioTags {
+    /// Project or segment name.
+    pub title: Option<String>,
+    /// Voice name.
+    pub artist: Option<String>,
+    /// Project name.
+    pub album: Option<String>,
+    pub comment: Option<String>,
+    pub chapters: Vec<Chapter>,
+    /// The app's own "Generated by SCLIP" comment; filled in when reading,
+    /// ignored when writing.
+    pub generator: Option<String>,
+}
+
+/// A chapter marker, typically one per script segment, placed from the
+/// synthesis timepoints.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Chapter {
+    pub title: String,
+    pub start_ms: u32,
+    pub end_ms: u32,
+}
+
+fn tag_error(path: &str, e: id3::Error) -> AppError {
+    AppError::InvalidAudioTags {
+        path: path.to_string(),
+        message: e.to_string(),
+    }
+}
+
+fn build_tag(tags: &AudioTags) -> Tag {
+    let mut tag = Tag::new();
+    if let Some(title) = &tags.title {
+        tag.set_title(title.as_str());
+    }
+    if let Some(artist) = &tags.artist {
+        tag.set_artist(artist.as_str());
+    }
+    if let Some(album) = &tags.album {
+        tag.set_album(album.as_str());
+    }
+    tag.add_frame(Comment {
+        lang: COMMENT_LANG.to_string(),
+        description: GENERATOR_DESCRIPTION.to_string(),
+        text: format!("Generated by SCLIP {}", env!("CARGO_PKG_VERSION")),
+    });
+    if let Some(comment) = &tags.comment {
+        tag.add_frame(Comment {
+            lang: COMMENT_LANG.to_string(),
+            description: String::new(),
+            text: comment.clone(),
+        });
+    }
+
+    if !tags.chapters.is_empty() {
+        let ids: Vec<String> = (0..tags.chapters.len())
+            .map(|i| format!("chp{}", i))
+            .collect();
+        for (chapter, id) in tags.chapters.iter().zip(&ids) {
+            tag.add_frame(ChapterFrame {
+                element_id: id.clone(),
+                start_time: chapter.start_ms,
+                end_time: chapter.end_ms,
+                start_offset: NO_OFFSET,
+                end_offset: NO_OFFSET,
+                frames: vec![Frame::text("TIT2", chapter.title.as_str())],
+            });
+        }
+        // Players only list chapters reachable from a top-level table of contents
+        tag.add_frame(TableOfContents {
+            element_id: TOC_ID.to_string(),
+            top_level: true,
+            ordered: true,
+            elements: ids,
+            frames: Vec::new(),
+        });
+    }
+    tag
+}
+
+/// Returns `audio` with its leading ID3v2 tag, if any, replaced by one
+/// holding `tags`. Written as ID3v2.4 so text is stored as UTF-8.
+pub fn apply(audio: &[u8], tags: &AudioTags) -> Result<Vec<u8>, AppError> {
+    let offset = mp3::id3v2_len(audio).min(audio.len());
+    let mut out = Vec::with_capacity(audio.len() + 1024);
+    build_tag(tags)
+        .write_to(&mut out, Version::Id3v24)
+        .map_err(|e| tag_error("<memory>", e))?;
+    out.extend_from_slice(&audio[offset..]);
+    Ok(out)
+}
+
+fn chapter_title(frames: &[Frame]) -> String {
+    frames
+        .iter()
+        .find(|f| f.id() == "TIT2")
+        .and_then(|f| f.content().text())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Reads the tags `apply` writes. A file without an ID3v2 tag yields empty
+/// tags rather than an error.
+pub fn read(path: &Path) -> Result<AudioTags, AppError> {
+    let name = path.display().to_string();
+    let Some(tag) = id3::no_tag_ok(Tag::read_from_path(path)).map_err(|e| tag_error(&name, e))?
+    else {
+        return Ok(AudioTags::default());
+    };
+
+    let mut tags = AudioTags {
+        title: tag.title().map(String::from),
+        artist: tag.artist().map(String::from),
+        album: tag.album().map(String::from),
+        ..AudioTags::default()
+    };
+    for comment in tag.comments() {
+        if comment.description == GENERATOR_DESCRIPTION {
+            tags.generator = Some(comment.text.clone());
+        } else if comment.description.is_empty() && tags.comment.is_none() {
+            tags.comment = Some(comment.text.clone());
+        }
+    }
+
+    // Table of contents order if there is one, otherwise start time
+    let chapters: Vec<&ChapterFrame> = tag.chapters().collect();
+    let order: Vec<&ChapterFrame> = match tag.tables_of_contents().find(|t| t.top_level) {
+        Some(toc) => toc
+            .elements
+            .iter()
+            .filter_map(|id| chapters.iter().find(|c| &c.element_id == id).copied())
+            .collect(),
+        None => {
+            let mut sorted = chapters.clone();
+            sorted.sort_by_key(|c| c.start_time);
+            sorted
+        }
+    };
+    tags.chapters = order
+        .into_iter()
+        .map(|c| Chapter {
+            title: chapter_title(&c.frames),
+            start_ms: c.start_time,
+            end_ms: c.end_time,
+        })
+        .collect();
+    Ok(tags)
+}
+
+/// Reads back the metadata of an exported MP3, e.g. to verify tagging.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn read_audio_tags(
+    roots: tauri::State<'_, AllowedRoots>,
+    path: String,
+) -> Result<AudioTags, AppError> {
+    let path = roots.validate_path::<Read>(Path::new(&path))?;
+    read(path.path())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    /// Two MPEG-1 Layer III frames, 128 kbps at 44.1 kHz.
+    fn mp3() -> Vec<u8> {
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x00];
+        frame.resize(417, 0x55);
+        frame.repeat(2)
+    }
+
+    fn tags() -> AudioTags {
+        AudioTags {
+            title: Some("Chapter One — Café".to_string()),
+            artist: Some("en-US-Neural2-A".to_string()),
+            album: Some("Audiobook".to_string()),
+            comment: Some("First take".to_string()),
+            chapters: vec![
+                Chapter {
+                    title: "Intro".to_string(),
+                    start_ms: 0,
+                    end_ms: 1200,
+                },
+                Chapter {
+                    title: "Main".to_string(),
+                    start_ms: 1200,
+                    end_ms: 5000,
+                },
+            ],
+            generator: None,
+        }
+    }
+
+    fn read_back(scratch: &Scratch, audio: &[u8]) -> AudioTags {
+        let path = scratch.0.join("tagged.mp3");
+        std::fs::write(&path, audio).unwrap();
+        read(&path).unwrap()
+    }
+
+    #[test]
+    fn tags_read_back_as_written() {
+        let scratch = Scratch::new("round-trip");
+        let tagged = apply(&mp3(), &tags()).unwrap();
+        assert!(mp3::is_plausible_mp3(&tagged));
+        assert!(tagged.ends_with(&mp3()));
+
+        let read = read_back(&scratch, &tagged);
+        assert_eq!(
+            read.generator.as_deref(),
+            Some(concat!("Generated by SCLIP ", env!("CARGO_PKG_VERSION")))
+        );
+        assert_eq!(
+            read,
+            AudioTags {
+                generator: read.generator.clone(),
+                ..tags()
+            }
+        );
+    }
+
+    #[test]
+    fn tagging_again_replaces_the_tag() {
+        let scratch = Scratch::new("replace");
+        let once = apply(&mp3(), &tags()).unwrap();
+        let retitled = AudioTags {
+            title: Some("Chapter Two".to_string()),
+            chapters: Vec::new(),
+            ..tags()
+        };
+        let twice = apply(&once, &retitled).unwrap();
+        assert_eq!(&twice[mp3::id3v2_len(&twice)..], mp3().as_slice());
+
+        let read = read_back(&scratch, &twice);
+        assert_eq!(read.title.as_deref(), Some("Chapter Two"));
+        assert!(read.chapters.is_empty());
+    }
+
+    #[test]
+    fn untagged_files_read_as_empty_tags() {
+        let scratch = Scratch::new("untagged");
+        assert_eq!(read_back(&scratch, &mp3()), AudioTags::default());
+    }
+}
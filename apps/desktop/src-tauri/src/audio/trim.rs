@@ -0,0 +1,128 @@
+// Frame-accurate shortening of MP3s without decoding: whole frames are kept
+// up to the cut, and the last few are faded out by lowering the global gain
+// in their Layer III side info, which scales what the decoder outputs
+// without touching the Huffman-coded data
+use super::mp3::{self, FrameHeader, MpegVersion};
+
+/// Length of the fade-out at the end of a trimmed file.
+const FADE_MS: f64 = 250.0;
+/// Global gain steps the last frame is lowered by; each is 1.5 dB.
+const FADE_STEPS: u8 = 40;
+/// Longest frame of any MPEG version and layer: 576 samples at 8 kHz.
+/// A cut lands at most this far past the requested length.
+const MAX_FRAME_MS: f64 = 72.0;
+/// Bit offset of `global_gain` within one granule's side info.
+const GLOBAL_GAIN_OFFSET: usize = 21;
+
+fn frame_ms(header: &FrameHeader) -> f64 {
+    header.samples() as f64 * 1000.0 / header.sample_rate as f64
+}
+
+/// Bit offsets of every `global_gain` field in a Layer III frame's side
+/// info, counted from the end of the header and CRC.
+fn global_gain_bits(header: &FrameHeader) -> Vec<usize> {
+    let channels = if header.channel_mode == 0b11 { 1 } else { 2 };
+    match header.version {
+        // main_data_begin, private bits, scfsi; then 2 granules of 59 bits
+        // per channel
+        MpegVersion::Mpeg1 => {
+            let base = 9 + if channels == 1 { 5 } else { 3 } + 4 * channels;
+            (0..2 * channels)
+                .map(|i| base + 59 * i + GLOBAL_GAIN_OFFSET)
+                .collect()
+        }
+        // main_data_begin, private bits; then 1 granule of 63 bits per
+        // channel
+        MpegVersion::Mpeg2 | MpegVersion::Mpeg25 => {
+            let base = 8 + channels;
+            (0..channels)
+                .map(|i| base + 63 * i + GLOBAL_GAIN_OFFSET)
+                .collect()
+        }
+    }
+}
+
+fn read_bits(bytes: &[u8], bit: usize, count: usize) -> u32 {
+    (bit..bit + count).fold(0, |value, b| {
+        (value << 1) | ((bytes[b / 8] >> (7 - b % 8)) & 1) as u32
+    })
+}
+
+fn write_bits(bytes: &mut [u8], bit: usize, count: usize, value: u32) {
+    for (i, b) in (bit..bit + count).enumerate() {
+        let mask = 1 << (7 - b % 8);
+        if (value >> (count - 1 - i)) & 1 == 1 {
+            bytes[b / 8] |= mask;
+        } else {
+            bytes[b / 8] &= !mask;
+        }
+    }
+}
+
+/// Lowers every granule of a Layer III frame by `steps`. Frames with a CRC
+/// are left alone, as the changed side info would fail it.
+fn attenuate(frame: &mut [u8], header: &FrameHeader, steps: u8) {
+    let has_crc = frame[1] & 1 == 0;
+    if header.layer != 3 || has_crc {
+        return;
+    }
+    let side_info = &mut frame[4..];
+    for bit in global_gain_bits(header) {
+        if bit + 8 > side_info.len() * 8 {
+            return;
+        }
+        let gain = read_bits(side_info, bit, 8) as u8;
+        write_bits(side_info, bit, 8, gain.saturating_sub(steps) as u32);
+    }
+}
+
+/// The first `max_ms` of `bytes`, cut after the frame reaching it and faded
+/// out over its last `FADE_MS`; `None` when the audio is no longer than
+/// that or has no frames to cut.
+pub fn trim(bytes: &[u8], max_ms: u32) -> Option<Vec<u8>> {
+    let tag_len = mp3::id3v2_len(bytes).min(bytes.len());
+    let mut frames = mp3::frames(bytes, tag_len);
+    if frames
+        .first()
+        .is_some_and(|f| mp3::is_vbr_header(&bytes[f.start..f.start + f.len]))
+    {
+        frames.remove(0);
+    }
+
+    let max_ms = max_ms as f64;
+    let mut total = 0.0;
+    let mut kept = 0;
+    for frame in &frames {
+        if total >= max_ms {
+            break;
+        }
+        total += frame_ms(&frame.header);
+        kept += 1;
+    }
+    if kept == 0 || kept == frames.len() {
+        return None;
+    }
+
+    let mut trimmed = bytes[..tag_len].to_vec();
+    let frames = &frames[..kept];
+    let fade_frames = ((FADE_MS / frame_ms(&frames[0].header)).ceil() as usize).clamp(1, kept);
+    for (i, frame) in frames.iter().enumerate() {
+        let start = trimmed.len();
+        trimmed.extend_from_slice(&bytes[frame.start..frame.start + frame.len]);
+        let into_fade = (i + fade_frames + 1).saturating_sub(kept);
+        if into_fade > 0 {
+            let steps = (FADE_STEPS as usize * into_fade / fade_frames) as u8;
+            attenuate(&mut trimmed[start..], &frame.header, steps);
+        }
+    }
+    Some(trimmed)
+}
+
+/// Whether `trimmed` is what `trim` makes for `max_ms`: at least that long,
+/// and no more than a frame over.
+pub fn is_trim_of(trimmed: &[u8], max_ms: u32) -> bool {
+    mp3::duration_ms(trimmed).is_some_and(|ms| {
+        let max_ms = max_ms as f64;
+        ms >= max_ms && ms < max_ms + MAX_FRAME_MS
+    })
+}
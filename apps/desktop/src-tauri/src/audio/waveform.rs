@@ -0,0 +1,458 @@
+// Peak data for drawing segment waveforms on the timeline, decoded in
+// parallel and cached next to the other derived audio
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+use tauri::{Emitter, Manager};
+
+use crate::cache::write_atomic;
+use crate::cancellation::{CancellationRegistry, CancellationToken};
+use crate::error::AppError;
+use crate::paths::roots::{AllowedPath, AllowedRoots, Read};
+
+const MAX_SAMPLES_PER_PIXEL: u32 = 65_536;
+const MAX_FILES: usize = 1_000;
+/// Files decoded at once, whatever the CPU count; each holds its whole
+/// sample buffer until its peaks are done.
+const MAX_DECODES_IN_FLIGHT: usize = 8;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Waveform {
+    pub path: PathBuf,
+    pub samples_per_pixel: u32,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub duration_ms: f64,
+    /// `[min, max]` over every channel for each `samples_per_pixel` frames.
+    pub peaks: Vec<[i16; 2]>,
+}
+
+/// Emitted as `waveform-ready` when a file of a batch is done, in completion
+/// order rather than request order.
+#[derive(Debug, Clone, Serialize)]
+pub struct WaveformResult {
+    /// Position of the file in the `paths` of the request.
+    pub index: usize,
+    pub path: PathBuf,
+    /// Served from the peak cache rather than decoded.
+    pub cached: bool,
+    pub waveform: Option<Waveform>,
+    pub error: Option<AppError>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct SourceStamp {
+    len: u64,
+    modified_ns: u64,
+}
+
+/// A peak file; written for one source at one resolution and used only
+/// while the source is unchanged.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedPeaks {
+    source: SourceStamp,
+    waveform: Waveform,
+}
+
+fn stamp(path: &Path) -> Result<SourceStamp, AppError> {
+    let metadata = fs::metadata(path)?;
+    let modified_ns = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default();
+    Ok(SourceStamp {
+        len: metadata.len(),
+        modified_ns,
+    })
+}
+
+fn peak_file(cache_dir: &Path, path: &Path, samples_per_pixel: u32) -> PathBuf {
+    let mut hasher = Sha256::new();
+    hasher.update(path.as_os_str().as_encoded_bytes());
+    hasher.update(samples_per_pixel.to_le_bytes());
+    cache_dir.join(format!("{:x}.json", hasher.finalize()))
+}
+
+fn peaks(samples: &[i16], channels: u16, samples_per_pixel: u32) -> Vec<[i16; 2]> {
+    samples
+        .chunks(samples_per_pixel as usize * channels as usize)
+        .map(|chunk| {
+            chunk.iter().fold([i16::MAX, i16::MIN], |[min, max], &s| {
+                [min.min(s), max.max(s)]
+            })
+        })
+        .collect()
+}
+
+fn decode(path: &Path, samples_per_pixel: u32) -> Result<Waveform, AppError> {
+    let pcm = super::decode::decode(&fs::read(path)?)?;
+    Ok(Waveform {
+        path: path.to_path_buf(),
+        samples_per_pixel,
+        sample_rate: pcm.sample_rate,
+        channels: pcm.channels,
+        duration_ms: pcm.frames() as f64 * 1000.0 / pcm.sample_rate as f64,
+        peaks: peaks(&pcm.samples, pcm.channels, samples_per_pixel),
+    })
+}
+
+/// The cached peaks of `path` if its size and modification time still
+/// match, else freshly decoded ones, which are then cached. Returns whether
+/// the cache was used.
+fn load(
+    cache_dir: &Path,
+    path: &AllowedPath<Read>,
+    samples_per_pixel: u32,
+) -> Result<(Waveform, bool), AppError> {
+    let path = path.path();
+    let source = stamp(path)?;
+    let peak_path = peak_file(cache_dir, path, samples_per_pixel);
+    let cached = fs::read(&peak_path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<CachedPeaks>(&bytes).ok())
+        .filter(|c| c.source == source && c.waveform.path == path);
+    if let Some(cached) = cached {
+        return Ok((cached.waveform, true));
+    }
+
+    let waveform = decode(path, samples_per_pixel)?;
+    let entry = CachedPeaks { source, waveform };
+    match serde_json::to_vec(&entry) {
+        Ok(json) => {
+            if let Err(e) = write_atomic(&peak_path, &json) {
+                log::warn!("Failed to cache peaks of {}: {}", path.display(), e);
+            }
+        }
+        Err(e) => log::warn!("Failed to cache peaks of {}: {}", path.display(), e),
+    }
+    Ok((entry.waveform, false))
+}
+
+/// Runs `load` over `paths` on up to `workers` threads, each taking the next
+/// unclaimed file, and hands every result to `done` as it finishes. Results
+/// come back in request order; a path `roots` doesn't allow fails alone, and
+/// so do the files of a worker that panicked.
+fn load_all(
+    cache_dir: &Path,
+    roots: &AllowedRoots,
+    paths: &[PathBuf],
+    samples_per_pixel: u32,
+    workers: usize,
+    token: &CancellationToken,
+    done: &(dyn Fn(&WaveformResult) + Sync),
+) -> Result<Vec<WaveformResult>, AppError> {
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<WaveformResult> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers.clamp(1, paths.len().max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut finished = Vec::new();
+                    while !token.is_cancelled() {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = paths.get(index) else {
+                            break;
+                        };
+                        let (waveform, cached, error) = match roots
+                            .validate_path(path)
+                            .and_then(|path| load(cache_dir, &path, samples_per_pixel))
+                        {
+                            Ok((waveform, cached)) => (Some(waveform), cached, None),
+                            Err(e) => (None, false, Some(e)),
+                        };
+                        let result = WaveformResult {
+                            index,
+                            path: path.clone(),
+                            cached,
+                            waveform,
+                            error,
+                        };
+                        done(&result);
+                        finished.push(result);
+                    }
+                    finished
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|h| h.join().ok())
+            .flatten()
+            .collect()
+    });
+    token.check()?;
+    results.sort_by_key(|r| r.index);
+    // Claimed by a worker that panicked before handing them back
+    let claimed = next.into_inner().min(paths.len());
+    let mut missing = Vec::new();
+    let mut finished = results.iter().map(|r| r.index).peekable();
+    for index in 0..claimed {
+        if finished.next_if_eq(&index).is_none() {
+            missing.push(index);
+        }
+    }
+    for index in missing {
+        let result = WaveformResult {
+            index,
+            path: paths[index].clone(),
+            cached: false,
+            waveform: None,
+            error: Some(AppError::Io {
+                message: "the waveform worker stopped unexpectedly".to_string(),
+            }),
+        };
+        done(&result);
+        let at = results.partition_point(|r| r.index < index);
+        results.insert(at, result);
+    }
+    Ok(results)
+}
+
+/// Waveform peaks for every file in `paths`, decoded on a thread per CPU
+/// (at most `MAX_DECODES_IN_FLIGHT`) off the async runtime. Each file is
+/// emitted as `waveform-ready` when done so the timeline can draw as they
+/// arrive; a file that fails carries its error without failing the batch.
+/// Files can be 16-bit PCM WAVs or MP3s.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn get_waveforms_batch(
+    app_handle: tauri::AppHandle,
+    cancellation: tauri::State<'_, CancellationRegistry>,
+    paths: Vec<PathBuf>,
+    samples_per_pixel: u32,
+    cancel_token: Option<String>,
+) -> Result<Vec<WaveformResult>, AppError> {
+    if !(1..=MAX_SAMPLES_PER_PIXEL).contains(&samples_per_pixel) {
+        return Err(AppError::InvalidAudioRequest {
+            message: format!(
+                "samples_per_pixel must be between 1 and {}",
+                MAX_SAMPLES_PER_PIXEL
+            ),
+        });
+    }
+    if paths.len() > MAX_FILES {
+        return Err(AppError::InvalidAudioRequest {
+            message: format!("at most {} files per batch", MAX_FILES),
+        });
+    }
+    let token = cancellation.register(cancel_token, "get_waveforms_batch")?;
+    let cache_dir = app_handle.path().app_cache_dir()?.join("waveform_cache");
+    let workers = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(MAX_DECODES_IN_FLIGHT);
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let emit = |result: &WaveformResult| {
+            if let Err(e) = app_handle.emit("waveform-ready", result) {
+                log::warn!("Failed to emit waveform-ready: {}", e);
+            }
+        };
+        load_all(
+            &cache_dir,
+            &app_handle.state::<AllowedRoots>(),
+            &paths,
+            samples_per_pixel,
+            workers,
+            &token,
+            &emit,
+        )
+    })
+    .await
+    .map_err(|e| AppError::Io {
+        message: e.to_string(),
+    })?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancellation::CancellationRegistry;
+    use crate::scratch::Scratch;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::{Condvar, Mutex};
+    use std::time::{Duration, Instant};
+
+    /// A voice preview Google synthesized, bundled with the app.
+    const PREVIEW: &[u8] =
+        include_bytes!("../../../../../resources/preview_cache/voice_en-US-Standard-C.mp3");
+
+    /// A 16 kHz mono WAV of `seconds` of a sawtooth.
+    fn wav(scratch: &Scratch, name: &str, seconds: u64) -> PathBuf {
+        let frames = seconds * 16_000;
+        let mut wav = crate::audio::placeholder::wav_header(16_000, 1, frames)
+            .unwrap()
+            .to_vec();
+        for i in 0..frames {
+            wav.extend_from_slice(&((i % 2000) as i16 * 16 - 16_000).to_le_bytes());
+        }
+        let path = scratch.0.join(name);
+        fs::write(&path, wav).unwrap();
+        path
+    }
+
+    /// `load_all` with a peaks cache in `scratch`.
+    fn load_in(
+        scratch: &Scratch,
+        paths: &[PathBuf],
+        workers: usize,
+        done: &(dyn Fn(&WaveformResult) + Sync),
+    ) -> Vec<WaveformResult> {
+        let registry = CancellationRegistry::default();
+        let token = registry.register(None, "get_waveforms_batch").unwrap();
+        load_all(
+            &scratch.0.join("cache"),
+            &AllowedRoots::scratch(&scratch.0),
+            paths,
+            64,
+            workers,
+            &token,
+            done,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn draws_mp3s_and_wavs_and_caches_their_peaks() {
+        let scratch = Scratch::new("formats");
+        let mp3 = scratch.0.join("preview.mp3");
+        fs::write(&mp3, PREVIEW).unwrap();
+        let paths = [
+            mp3,
+            wav(&scratch, "tone.wav", 1),
+            scratch.0.join("missing.wav"),
+        ];
+        let results = load_in(&scratch, &paths, 2, &|_| {});
+        let indexes: Vec<usize> = results.iter().map(|r| r.index).collect();
+        assert_eq!(indexes, [0, 1, 2]);
+        let mp3 = results[0].waveform.as_ref().unwrap();
+        assert_eq!((mp3.sample_rate, mp3.channels), (24_000, 1));
+        assert!(mp3.peaks.iter().any(|[min, max]| max - min > 1000));
+        let wav = results[1].waveform.as_ref().unwrap();
+        assert_eq!(wav.peaks.len(), 250);
+        assert_eq!(wav.duration_ms, 1000.0);
+        assert!(results[2].error.is_some());
+
+        let again = load_in(&scratch, &paths[..2], 2, &|_| {});
+        assert!(again.iter().all(|r| r.cached));
+        assert_eq!(again[1].waveform.as_ref().unwrap().peaks, wav.peaks);
+    }
+
+    #[test]
+    fn workers_decode_files_at_once() {
+        let scratch = Scratch::new("overlap");
+        let paths: Vec<PathBuf> = (0..4)
+            .map(|i| wav(&scratch, &format!("{}.wav", i), 1))
+            .collect();
+        // Each result waits until four are in hand at the same time, which
+        // one worker at a time never gets to
+        let in_hand = Mutex::new(0);
+        let all_in_hand = Condvar::new();
+        let results = load_in(&scratch, &paths, 4, &|_| {
+            let mut count = in_hand.lock().unwrap();
+            *count += 1;
+            all_in_hand.notify_all();
+            let (count, timeout) = all_in_hand
+                .wait_timeout_while(count, Duration::from_secs(10), |count| *count < 4)
+                .unwrap();
+            assert!(!timeout.timed_out(), "only {} files in flight", *count);
+        });
+        assert!(results.iter().all(|r| r.waveform.is_some()));
+    }
+
+    #[test]
+    fn a_batch_beats_decoding_one_by_one() {
+        let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+        if workers < 2 {
+            eprintln!("skipped: one CPU decodes no faster in parallel");
+            return;
+        }
+        let workers = workers.min(MAX_DECODES_IN_FLIGHT);
+        let scratch = Scratch::new("speed");
+        let paths: Vec<PathBuf> = (0..workers * 2)
+            .map(|i| wav(&scratch, &format!("{}.wav", i), 30))
+            .collect();
+        let timed = |workers: usize| {
+            // A cache of its own, so neither run reads the other's peaks
+            let _ = fs::remove_dir_all(scratch.0.join("cache"));
+            fs::create_dir_all(scratch.0.join("cache")).unwrap();
+            let started = Instant::now();
+            let results = load_in(&scratch, &paths, workers, &|_| {});
+            assert!(results.iter().all(|r| r.waveform.is_some() && !r.cached));
+            started.elapsed()
+        };
+        let sequential = timed(1);
+        let batch = timed(workers);
+        assert!(
+            batch < sequential,
+            "{:?} with {} workers, {:?} with one",
+            batch,
+            workers,
+            sequential
+        );
+    }
+
+    #[test]
+    fn files_of_a_panicked_worker_fail_alone() {
+        let scratch = Scratch::new("panic");
+        let paths: Vec<PathBuf> = (0..6)
+            .map(|i| wav(&scratch, &format!("{}.wav", i), 1))
+            .collect();
+        let panicked = AtomicBool::new(false);
+        let reported = Mutex::new(Vec::new());
+        let results = load_in(&scratch, &paths, 2, &|result| {
+            reported.lock().unwrap().push(result.index);
+            if result.index == 1 && !panicked.swap(true, Ordering::Relaxed) {
+                panic!("a worker dies");
+            }
+        });
+        let indexes: Vec<usize> = results.iter().map(|r| r.index).collect();
+        assert_eq!(indexes, [0, 1, 2, 3, 4, 5]);
+        assert!(results[1].error.is_some());
+        // What the other worker decoded is kept
+        assert!(results.iter().filter(|r| r.waveform.is_some()).count() >= 3);
+        assert!(reported.lock().unwrap().contains(&1));
+    }
+
+    #[test]
+    fn a_cancel_after_a_file_keeps_its_peaks_and_reads_no_more() {
+        let scratch = Scratch::new("cancel");
+        let paths: Vec<PathBuf> = (0..4)
+            .map(|i| wav(&scratch, &format!("{}.wav", i), 1))
+            .collect();
+        let registry = CancellationRegistry::default();
+        let token = registry
+            .register(Some("peaks".into()), "get_waveforms_batch")
+            .unwrap();
+        let reported = Mutex::new(Vec::new());
+        // Lands as the first file's peaks have just been cached
+        let done = |result: &WaveformResult| {
+            reported.lock().unwrap().push(result.index);
+            registry.cancel("peaks");
+        };
+        let cache = scratch.0.join("cache");
+        let result = load_all(
+            &cache,
+            &AllowedRoots::scratch(&scratch.0),
+            &paths,
+            64,
+            1,
+            &token,
+            &done,
+        );
+
+        assert!(matches!(result, Err(AppError::Cancelled { .. })));
+        assert_eq!(*reported.lock().unwrap(), [0]);
+        let peak_files: Vec<PathBuf> = fs::read_dir(&cache)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(peak_files.len(), 1);
+        let cached: CachedPeaks =
+            serde_json::from_slice(&fs::read(&peak_files[0]).unwrap()).unwrap();
+        assert_eq!(cached.waveform.peaks.len(), 250);
+    }
+}
@@ -0,0 +1,304 @@
+// Which finished batch segments the current settings would synthesize
+// differently, and what redoing them would cost. A segment has drifted when
+// the request its manifest entry makes today, with the settings' pauses and
+// cost-saver policy, no longer fingerprints like the one that wrote its
+// file. `resynthesize_segments` then redoes a chosen subset as a batch job
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use super::{run_batch, BatchManifest, ManifestSegment, SegmentStatus};
+use crate::cancellation::CancellationRegistry;
+use crate::error::AppError;
+use crate::format::DisplayFormat;
+use crate::jobs::{JobKind, JobManager};
+use crate::paths::roots::{AllowedRoots, Read, Write};
+use crate::paths::ExportedPaths;
+use crate::read_only::WriteAccess;
+use crate::settings::{Settings, SettingsStore};
+use crate::tts::fingerprint::TtsRequestParams;
+use crate::tts::plan;
+use crate::tts::TtsService;
+use crate::voices::name::technology;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DriftedSegment {
+    pub id: String,
+    /// Voice the segment would be synthesized with now.
+    pub voice_name: String,
+    pub characters: usize,
+    pub estimated_cost_usd: f64,
+    /// The current request is already in the TTS cache, so redoing the
+    /// segment is free.
+    pub cached: bool,
+    /// What changed, e.g. "paragraph pause" or "voice"; empty when nothing
+    /// did.
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DriftGroup {
+    pub segments: Vec<DriftedSegment>,
+    pub characters: usize,
+    pub estimated_cost_usd: f64,
+    pub cost_display: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigDrift {
+    pub manifest_path: PathBuf,
+    pub unchanged: DriftGroup,
+    /// Drifted segments on Standard or WaveNet voices.
+    pub drifted_cheap: DriftGroup,
+    /// Drifted segments on Neural2, Studio and every other premium voice.
+    pub drifted_expensive: DriftGroup,
+    /// Ids of segments without a file yet, which any resume synthesizes
+    /// under the current settings anyway.
+    pub not_synthesized: Vec<String>,
+    /// The manifest-wide settings that differ, e.g. "paragraph pause 600 ms
+    /// -> 800 ms".
+    pub changes: Vec<String>,
+}
+
+impl DriftGroup {
+    fn push(&mut self, segment: DriftedSegment) {
+        self.characters += segment.characters;
+        self.estimated_cost_usd += segment.estimated_cost_usd;
+        self.segments.push(segment);
+    }
+
+    fn finish(&mut self, format: &DisplayFormat) {
+        self.cost_display = format.cost_usd(self.estimated_cost_usd);
+    }
+}
+
+/// Whether a voice bills at the lower rates: Standard and WaveNet, and the
+/// free on-device voices.
+fn is_cheap(voice_name: &str) -> bool {
+    matches!(
+        technology(voice_name).to_ascii_lowercase().as_str(),
+        "standard" | "wavenet"
+    ) || plan::price_per_million(voice_name) == 0.0
+}
+
+/// `manifest` as it would be run now: the settings' pauses and, if it saves
+/// cost, their cost-saver policy.
+async fn with_current_settings(
+    tts: &TtsService,
+    manifest: &BatchManifest,
+    settings: &Settings,
+) -> Result<BatchManifest, AppError> {
+    let mut current = manifest.clone();
+    current.paragraph_pause_ms = settings.paragraph_pause_ms;
+    current.line_pause_ms = settings.line_pause_ms;
+    current.breathing_room = settings.breathing_room;
+    if current.cost_saver {
+        let voices = tts.voices(false, None).await?;
+        current.apply_cost_saver(&settings.cost_saver, &voices);
+    }
+    Ok(current)
+}
+
+fn manifest_changes(before: &BatchManifest, now: &BatchManifest) -> Vec<String> {
+    let mut changes = Vec::new();
+    if before.paragraph_pause_ms != now.paragraph_pause_ms {
+        changes.push(format!(
+            "paragraph pause {} ms -> {} ms",
+            before.paragraph_pause_ms, now.paragraph_pause_ms
+        ));
+    }
+    if before.line_pause_ms != now.line_pause_ms {
+        changes.push(format!(
+            "line pause {} ms -> {} ms",
+            before.line_pause_ms, now.line_pause_ms
+        ));
+    }
+    if before.breathing_room != now.breathing_room {
+        changes.push(format!(
+            "breathing room {:?} -> {:?}",
+            before.breathing_room, now.breathing_room
+        ));
+    }
+    changes
+}
+
+fn segment_reasons(
+    before: &BatchManifest,
+    now: &BatchManifest,
+    old: &ManifestSegment,
+    new: &ManifestSegment,
+    hash: &str,
+) -> Vec<String> {
+    let mut reasons = Vec::new();
+    if old.voice_name != new.voice_name {
+        reasons.push("voice".to_string());
+    }
+    if before.input_for(old).content() != now.input_for(new).content() {
+        if before.paragraph_pause_ms != now.paragraph_pause_ms {
+            reasons.push("paragraph pause".to_string());
+        }
+        if before.line_pause_ms != now.line_pause_ms {
+            reasons.push("line pause".to_string());
+        }
+        if before.breathing_room != now.breathing_room {
+            reasons.push("breathing room".to_string());
+        }
+    }
+    // Same voice and input, yet another key: the request format changed
+    if reasons.is_empty() && hash != old.request_hash {
+        reasons.push("request format".to_string());
+    }
+    reasons
+}
+
+/// Compares every finished segment of the batch at `manifest_path` with
+/// what the current settings would request for it, grouped by whether it
+/// changed and how expensive its voice is, with what redoing each group
+/// costs at list price (nothing for requests already cached).
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn analyze_config_drift(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+    roots: tauri::State<'_, AllowedRoots>,
+    manifest_path: PathBuf,
+) -> Result<ConfigDrift, AppError> {
+    let manifest_path = roots.validate_path::<Read>(&manifest_path)?.into_path_buf();
+    let manifest = BatchManifest::load(&manifest_path)?;
+    let settings = settings.get();
+    let current = with_current_settings(&tts, &manifest, &settings).await?;
+    let cache = crate::cache::tts_cache(&app_handle)?;
+    let format = DisplayFormat::for_locale(&settings.ui_locale);
+
+    let mut drift = ConfigDrift {
+        manifest_path,
+        unchanged: DriftGroup::default(),
+        drifted_cheap: DriftGroup::default(),
+        drifted_expensive: DriftGroup::default(),
+        not_synthesized: Vec::new(),
+        changes: manifest_changes(&manifest, &current),
+    };
+    for (old, new) in manifest.segments.iter().zip(&current.segments) {
+        if old.status != SegmentStatus::Completed {
+            if old.status != SegmentStatus::Skipped {
+                drift.not_synthesized.push(old.id.clone());
+            }
+            continue;
+        }
+        let input = current.input_for(new);
+        let params = TtsRequestParams::speech(&new.voice_name, &new.language_code, &input);
+        let hash = params.fingerprint();
+        let characters = params.billable_characters();
+        let cached = cache.contains(&hash);
+        let estimated_cost_usd = if cached {
+            0.0
+        } else {
+            characters as f64 * plan::price_per_million(&new.voice_name) / 1_000_000.0
+        };
+        let segment = DriftedSegment {
+            id: new.id.clone(),
+            voice_name: new.voice_name.clone(),
+            characters,
+            estimated_cost_usd,
+            cached,
+            reasons: segment_reasons(&manifest, &current, old, new, &hash),
+        };
+        if hash == old.request_hash {
+            drift.unchanged.push(segment);
+        } else if is_cheap(&new.voice_name) {
+            drift.drifted_cheap.push(segment);
+        } else {
+            drift.drifted_expensive.push(segment);
+        }
+    }
+    for group in [
+        &mut drift.unchanged,
+        &mut drift.drifted_cheap,
+        &mut drift.drifted_expensive,
+    ] {
+        group.finish(&format);
+    }
+    Ok(drift)
+}
+
+/// Redoes the segments `segment_ids` of the batch at `manifest_path` under
+/// the current settings, as a batch job like `resume_batch`. Other finished
+/// segments keep their files and voices; as the pauses are manifest-wide, a
+/// later `resume_batch` brings those in line too. Segments without a file
+/// yet are synthesized along the way.
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn resynthesize_segments(
+    app_handle: tauri::AppHandle,
+    window: tauri::Window,
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+    jobs: tauri::State<'_, JobManager>,
+    cancellation: tauri::State<'_, CancellationRegistry>,
+    exported: tauri::State<'_, ExportedPaths>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    manifest_path: PathBuf,
+    segment_ids: Vec<String>,
+    cancel_token: Option<String>,
+    spend_token: Option<String>,
+) -> Result<BatchManifest, AppError> {
+    access.check()?;
+    let manifest_path = roots.validate_path::<Read>(&manifest_path)?.into_path_buf();
+    let before = BatchManifest::load(&manifest_path)?;
+    roots.validate_path::<Write>(&before.output_dir)?;
+    let selected: HashSet<&str> = segment_ids.iter().map(String::as_str).collect();
+    if let Some(unknown) = selected
+        .iter()
+        .find(|id| !before.segments.iter().any(|s| s.id == **id))
+    {
+        return Err(AppError::InvalidManifest {
+            manifest_path: manifest_path.display().to_string(),
+            message: format!("no segment with id \"{}\"", unknown),
+        });
+    }
+
+    crate::project::review::check_export(&app_handle, window.label(), selected.iter().copied())?;
+
+    for segment in &before.segments {
+        roots.validate_path::<Write>(&segment.path)?;
+    }
+    let mut manifest = with_current_settings(&tts, &before, &settings.get()).await?;
+    for (segment, old) in manifest.segments.iter_mut().zip(&before.segments) {
+        if selected.contains(segment.id.as_str()) {
+            segment.status = SegmentStatus::Pending;
+            segment.bytes = 0;
+            segment.reused = false;
+        } else if old.status == SegmentStatus::Completed {
+            // Still the voice its file was made with
+            segment.voice_name = old.voice_name.clone();
+            segment.substitution = old.substitution.clone();
+        }
+    }
+
+    let job = jobs.register(
+        JobKind::Batch,
+        manifest_path.display().to_string(),
+        Some(window.label()),
+    );
+    let _token =
+        cancellation.register_shared(cancel_token, "resynthesize_segments", job.cancel_handle())?;
+    let cache = crate::cache::tts_cache(&app_handle)?;
+    let approval = tts.spend().redeem(spend_token.as_deref())?;
+    let result = run_batch(
+        Some(&app_handle),
+        &tts,
+        &cache,
+        &mut manifest,
+        &manifest_path,
+        &job,
+        approval.as_ref(),
+        &settings.get().audio_quality,
+    )
+    .await;
+    exported.record(&manifest.output_dir);
+    result?;
+    Ok(manifest)
+}
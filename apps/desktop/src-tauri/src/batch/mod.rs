@@ -0,0 +1,1395 @@
+// Batch synthesis of script segments into individual audio files
+pub mod drift;
+pub mod naming;
+
+use gcloud_sdk::google::cloud::texttospeech::v1::Voice;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::Ordering;
+
+use crate::audio::presets::OutputFormat;
+use crate::audio::quality::{self, AudioAnalysis, QualitySummary, QualityThresholds};
+use crate::audio::tags::{self, AudioTags, Chapter};
+use crate::audio::{channels, mp3, stretch};
+use crate::cache::tts::TtsCache;
+use crate::cache::write_atomic;
+use crate::cancellation::CancellationRegistry;
+use crate::error::AppError;
+use crate::format::DisplayFormat;
+use crate::jobs::recovery::Resume;
+use crate::jobs::{JobGuard, JobKind, JobManager};
+use crate::long_path;
+use crate::paths::roots::{AllowedRoots, Read, Write};
+use crate::paths::ExportedPaths;
+use crate::read_only::WriteAccess;
+use crate::script::structure::{HeadingKind, ScriptSection, ScriptStructure};
+use crate::settings::SettingsStore;
+use crate::sidecar::handoff::{self, RegisteredAudio};
+use crate::ssml::BreathingRoom;
+use crate::tts::fingerprint::TtsRequestParams;
+use crate::tts::lanes::Lane;
+use crate::tts::placeholders::Placeholders;
+use crate::tts::plan;
+use crate::tts::speakable;
+use crate::tts::spend::SpendApproval;
+use crate::tts::synthesis::{SpeechInput, SynthesisOptions};
+use crate::tts::TtsService;
+use crate::voices::capabilities::CapabilityStore;
+use crate::voices::downgrade::{self, CostSaverPolicy, VoiceSubstitution};
+use crate::voices::remap::{self, VoiceRemap, VoiceRemapping};
+use naming::{NameContext, NamingTemplate, UniqueNames};
+
+/// Written into the output directory and rewritten after every segment so an
+/// interrupted batch can be resumed with `resume_batch`.
+pub const MANIFEST_FILE: &str = "batch_manifest.json";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchSegment {
+    pub id: String,
+    pub voice_name: String,
+    pub language_code: String,
+    pub text: String,
+    /// Chapter markers for this segment's file, e.g. from its timepoints.
+    #[serde(default)]
+    pub chapters: Vec<Chapter>,
+    /// Byte offset of the text in the script a batch `structure` was parsed
+    /// from; the file goes in the directory of the section there.
+    #[serde(default)]
+    pub source_offset: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SegmentStatus {
+    Pending,
+    Completed,
+    Failed,
+    /// Nothing to speak; see `warning`.
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestSegment {
+    pub id: String,
+    pub index: usize,
+    pub voice_name: String,
+    pub language_code: String,
+    pub text: String,
+    pub file_name: String,
+    pub path: PathBuf,
+    pub status: SegmentStatus,
+    pub bytes: usize,
+    /// Of the written file; Google speaks mono.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channels: Option<u16>,
+    /// Cache key of the request that produced (or will produce) the file.
+    pub request_hash: String,
+    /// Set by `resume_batch` when the segment's text differs from the run
+    /// that wrote the manifest and it had to be redone.
+    #[serde(default)]
+    pub text_changed: bool,
+    /// Set by `resume_batch` when an existing output was validated and kept.
+    #[serde(default)]
+    pub reused: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chapters: Vec<Chapter>,
+    /// `index` of the innermost section holding the segment, whose
+    /// directory its file is in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section: Option<usize>,
+    /// Set when the cost saver replaced the requested voice, which is then
+    /// `substitution.original_voice` while `voice_name` is the one used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub substitution: Option<VoiceSubstitution>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Why a skipped segment has no file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+    /// The backend's record of the file, once registered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend_asset: Option<RegisteredAudio>,
+    /// Why registering the file failed; `retry_registration` tries again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registration_warning: Option<String>,
+    /// Loudness and clipping of the written file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub analysis: Option<AudioAnalysis>,
+}
+
+/// A section of the script a batch was structured by, for the timeline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestSection {
+    /// 1-based, in script order, parents before their children.
+    pub index: usize,
+    pub title: String,
+    pub level: u8,
+    pub kind: HeadingKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scene_number: Option<u32>,
+    /// `index` of the enclosing section.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<usize>,
+    /// Relative to the output directory.
+    pub directory: PathBuf,
+    /// Byte range of the section in the script.
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchManifest {
+    pub output_dir: PathBuf,
+    pub naming_template: String,
+    pub paragraph_pause_ms: u32,
+    pub line_pause_ms: u32,
+    /// Manifests from before breathing room was added had none.
+    #[serde(default)]
+    pub breathing_room: BreathingRoom,
+    /// ID3 tags written into every output; `None` leaves files untagged.
+    #[serde(default)]
+    pub tags: Option<AudioTags>,
+    /// Format of every output; the default keeps the MP3s as synthesized.
+    /// One that needs PCM decodes them and writes them as WAVs.
+    #[serde(default)]
+    pub output: OutputFormat,
+    /// Long segments were moved to cheaper voices per the settings policy;
+    /// `resume_batch` keeps doing so.
+    #[serde(default)]
+    pub cost_saver: bool,
+    /// Billed characters draw down budget reservations; `resume_batch`
+    /// keeps doing so.
+    #[serde(default)]
+    pub use_reservation: bool,
+    /// Backend project completed segments are registered with; `None`
+    /// registers nothing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend_project_id: Option<String>,
+    /// Sections of the script, flattened; empty for an unstructured batch.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sections: Vec<ManifestSection>,
+    pub segments: Vec<ManifestSegment>,
+    /// The segments' analyses taken together, for the completed ones.
+    #[serde(default)]
+    pub quality: QualitySummary,
+}
+
+impl BatchManifest {
+    /// Reads the manifest at `path`. Each segment's `path` is rebuilt from
+    /// the output directory, its section's directory and its file name, so
+    /// an edited manifest can't send a file anywhere else.
+    pub(crate) fn load(path: &Path) -> Result<Self, AppError> {
+        let invalid = |message: String| AppError::InvalidManifest {
+            manifest_path: path.display().to_string(),
+            message,
+        };
+        let contents = fs::read_to_string(path)?;
+        let mut manifest: Self =
+            serde_json::from_str(&contents).map_err(|e| invalid(e.to_string()))?;
+        for i in 0..manifest.segments.len() {
+            let rebuilt = manifest
+                .output_path(&manifest.segments[i])
+                .map_err(|reason| {
+                    invalid(format!("segment {}: {}", manifest.segments[i].id, reason))
+                })?;
+            manifest.segments[i].path = rebuilt;
+        }
+        Ok(manifest)
+    }
+
+    /// Where `segment`'s file belongs: `file_name` in its section's
+    /// directory under `output_dir`. Both must stay inside it.
+    fn output_path(&self, segment: &ManifestSegment) -> Result<PathBuf, String> {
+        let inside = |path: &Path| {
+            path.components().next().is_some()
+                && path.components().all(|c| matches!(c, Component::Normal(_)))
+        };
+        let file_name = Path::new(&segment.file_name);
+        if !inside(file_name) || file_name.components().count() != 1 {
+            return Err(format!(
+                "file name \"{}\" isn't a plain name",
+                segment.file_name
+            ));
+        }
+        let mut dir = self.output_dir.clone();
+        if let Some(index) = segment.section {
+            let section = self
+                .sections
+                .iter()
+                .find(|s| s.index == index)
+                .ok_or_else(|| format!("no section {}", index))?;
+            if !inside(&section.directory) {
+                return Err(format!(
+                    "section directory {} leaves the output directory",
+                    section.directory.display()
+                ));
+            }
+            dir.push(&section.directory);
+        }
+        Ok(dir.join(file_name))
+    }
+
+    fn save(&self, path: &Path) -> Result<(), AppError> {
+        let json = serde_json::to_vec_pretty(self).map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })?;
+        write_atomic(path, &json)?;
+        Ok(())
+    }
+
+    /// Recomputes `quality` from the completed segments' analyses.
+    fn summarize_quality(&mut self) {
+        self.quality = QualitySummary::of(
+            self.segments
+                .iter()
+                .filter(|s| s.status == SegmentStatus::Completed)
+                .filter_map(|s| Some((s.id.as_str(), s.analysis.as_ref()?))),
+        );
+    }
+
+    /// Flattens `sections` into `self.sections`, each directory inside its
+    /// parent's.
+    fn add_sections(&mut self, sections: &[ScriptSection], parent: Option<&ManifestSection>) {
+        for (position, section) in sections.iter().enumerate() {
+            let directory = naming::section_directory(position + 1, &section.title);
+            let entry = ManifestSection {
+                index: self.sections.len() + 1,
+                title: section.title.clone(),
+                level: section.level,
+                kind: section.kind,
+                scene_number: section.scene_number,
+                parent: parent.map(|p| p.index),
+                directory: match parent {
+                    Some(parent) => parent.directory.join(directory),
+                    None => PathBuf::from(directory),
+                },
+                start: section.start,
+                end: section.end,
+            };
+            self.sections.push(entry.clone());
+            self.add_sections(&section.children, Some(&entry));
+        }
+    }
+
+    /// The innermost section holding byte `offset` of the script. Sections
+    /// are in order with parents first, so that's the last one holding it.
+    fn section_at(&self, offset: Option<usize>) -> Option<&ManifestSection> {
+        let offset = offset?;
+        self.sections
+            .iter()
+            .rev()
+            .find(|s| (s.start..s.end).contains(&offset))
+    }
+
+    /// Where the file of a segment at `offset` of the script goes, and the
+    /// index of its section.
+    fn segment_dir(&self, offset: Option<usize>) -> (PathBuf, Option<usize>) {
+        match self.section_at(offset) {
+            Some(section) => (
+                self.output_dir.join(&section.directory),
+                Some(section.index),
+            ),
+            None => (self.output_dir.clone(), None),
+        }
+    }
+
+    fn input_for(&self, segment: &ManifestSegment) -> SpeechInput {
+        crate::tts::synthesis::speech_input(
+            segment.text.clone(),
+            self.paragraph_pause_ms,
+            self.line_pause_ms,
+            self.breathing_room,
+        )
+    }
+
+    /// Applies the batch tags to a segment's audio. Title and artist default
+    /// to the segment id and voice, and the segment's own chapters win over
+    /// batch-wide ones.
+    fn tag(&self, segment: &ManifestSegment, audio: Vec<u8>) -> Result<Vec<u8>, AppError> {
+        let Some(batch_tags) = &self.tags else {
+            return Ok(audio);
+        };
+        let segment_tags = AudioTags {
+            title: batch_tags
+                .title
+                .clone()
+                .or_else(|| Some(segment.id.clone())),
+            artist: batch_tags
+                .artist
+                .clone()
+                .or_else(|| Some(segment.voice_name.clone())),
+            chapters: if segment.chapters.is_empty() {
+                batch_tags.chapters.clone()
+            } else {
+                segment.chapters.clone()
+            },
+            ..batch_tags.clone()
+        };
+        tags::apply(&audio, &segment_tags)
+    }
+
+    /// The synthesized MP3 `audio` as the batch writes it for `segment`:
+    /// tagged, or rendered in the output format.
+    fn finish(&self, segment: &ManifestSegment, audio: Vec<u8>) -> Result<Vec<u8>, AppError> {
+        if self.output.needs_pcm() {
+            self.output.render(&audio)
+        } else {
+            self.tag(segment, audio)
+        }
+    }
+
+    /// Re-decides every segment's voice from the voice it asked for, so
+    /// edited text can move a segment across a threshold in either direction.
+    fn apply_cost_saver(&mut self, policy: &CostSaverPolicy, voices: &[Voice]) {
+        for segment in &mut self.segments {
+            let requested = match segment.substitution.take() {
+                Some(previous) => previous.original_voice,
+                None => segment.voice_name.clone(),
+            };
+            segment.substitution = downgrade::substitute(
+                policy,
+                voices,
+                &requested,
+                &segment.language_code,
+                &segment.text,
+            );
+            segment.voice_name = match &segment.substitution {
+                Some(substitution) => substitution.voice.clone(),
+                None => requested,
+            };
+        }
+    }
+
+    /// Replaces the segment list with `segments`, matched by id: a kept
+    /// segment takes the new text, voice and language, flagged with
+    /// `text_changed` if its text differs, and keeps its file name. New
+    /// segments are filed by the sections at their `source_offset`.
+    fn replace_segments(&mut self, segments: Vec<BatchSegment>) -> Result<(), AppError> {
+        let template = NamingTemplate::parse(&self.naming_template)?;
+        let mut previous: HashMap<String, ManifestSegment> =
+            self.segments.drain(..).map(|s| (s.id.clone(), s)).collect();
+
+        let mut names = UniqueNames::default();
+        for existing in previous.values() {
+            names.claim_exact(&existing.file_name);
+        }
+
+        for (i, segment) in segments.into_iter().enumerate() {
+            let entry = match previous.remove(&segment.id) {
+                Some(mut entry) => {
+                    entry.index = i + 1;
+                    entry.text_changed = entry.text != segment.text;
+                    entry.text = segment.text;
+                    entry.voice_name = segment.voice_name;
+                    entry.language_code = segment.language_code;
+                    entry.chapters = segment.chapters;
+                    entry.substitution = None;
+                    entry
+                }
+                None => {
+                    let (dir, section) = self.segment_dir(segment.source_offset);
+                    let extension = self.output.extension();
+                    let stem = long_path::fit_stem(
+                        &dir,
+                        &render_stem(&template, i + 1, &segment),
+                        extension,
+                    )?;
+                    let file_name = names.claim(&stem, extension);
+                    pending_segment(i + 1, segment, file_name, &dir, section)
+                }
+            };
+            self.segments.push(entry);
+        }
+        Ok(())
+    }
+
+    /// Renames, per `remap`, the voices of segments without a file yet; a
+    /// finished segment keeps the voice its file was made with.
+    fn remap_voices(&mut self, remap: &VoiceRemap) -> Vec<VoiceRemapping> {
+        let mut changes = BTreeSet::new();
+        for segment in &mut self.segments {
+            if segment.status == SegmentStatus::Completed {
+                continue;
+            }
+            if let Some(to) = remap.resolve(&segment.voice_name) {
+                let from = std::mem::replace(&mut segment.voice_name, to.clone());
+                changes.insert(VoiceRemapping { from, to });
+            }
+        }
+        changes.into_iter().collect()
+    }
+
+    fn request_hash(&self, segment: &ManifestSegment) -> String {
+        let input = self.input_for(segment);
+        TtsRequestParams::speech(&segment.voice_name, &segment.language_code, &input).fingerprint()
+    }
+}
+
+fn render_stem(template: &NamingTemplate, index: usize, segment: &BatchSegment) -> String {
+    template.render(&NameContext {
+        index,
+        id: &segment.id,
+        voice_name: &segment.voice_name,
+        language_code: &segment.language_code,
+        text: &segment.text,
+    })
+}
+
+fn pending_segment(
+    index: usize,
+    segment: BatchSegment,
+    file_name: String,
+    dir: &Path,
+    section: Option<usize>,
+) -> ManifestSegment {
+    ManifestSegment {
+        path: dir.join(&file_name),
+        id: segment.id,
+        index,
+        voice_name: segment.voice_name,
+        language_code: segment.language_code,
+        text: segment.text,
+        chapters: segment.chapters,
+        section,
+        file_name,
+        status: SegmentStatus::Pending,
+        bytes: 0,
+        channels: None,
+        request_hash: String::new(),
+        text_changed: false,
+        reused: false,
+        substitution: None,
+        error: None,
+        warning: None,
+        backend_asset: None,
+        registration_warning: None,
+        analysis: None,
+    }
+}
+
+/// Checks that a completed segment's file is still the one the manifest
+/// describes: present, the recorded size, parseable audio, same request.
+fn output_is_valid(manifest: &BatchManifest, segment: &ManifestSegment) -> bool {
+    if segment.status != SegmentStatus::Completed
+        || segment.request_hash != manifest.request_hash(segment)
+    {
+        return false;
+    }
+    match fs::read(&segment.path) {
+        Ok(bytes) if manifest.output.needs_pcm() => {
+            bytes.len() == segment.bytes && stretch::wav_duration_ms(&bytes).is_some()
+        }
+        Ok(bytes) => bytes.len() == segment.bytes && mp3::is_plausible_mp3(&bytes),
+        Err(_) => false,
+    }
+}
+
+/// Synthesizes every segment that isn't completed, persisting the manifest
+/// after each one. Cancellation is checked between segments, so it never
+/// leaves a half-written file; the manifest stays resumable. Without an
+/// `app_handle` there is no backend to register segments with, and each is
+/// left with a registration warning for `retry_registration`. What's left
+/// to bill must fit the per-batch spend cap or `approval`. Each written
+/// file is analyzed against `thresholds`, and the manifest keeps the
+/// results of every segment and of the batch as a whole.
+#[allow(clippy::too_many_arguments)]
+async fn run_batch(
+    app_handle: Option<&tauri::AppHandle>,
+    tts: &TtsService,
+    cache: &TtsCache,
+    manifest: &mut BatchManifest,
+    manifest_path: &Path,
+    job: &JobGuard,
+    approval: Option<&SpendApproval>,
+    thresholds: &QualityThresholds,
+) -> Result<(), AppError> {
+    let cancel = job.cancel_flag();
+    let renamed = manifest.remap_voices(&tts.voice_remap());
+    match app_handle {
+        Some(app_handle) => remap::announce(app_handle, "batch", renamed),
+        None => {
+            for change in renamed {
+                log::info!(
+                    "Batch voice {} is retired, using {}",
+                    change.from,
+                    change.to
+                );
+            }
+        }
+    }
+    // A completed segment keeps the hash of the request that wrote its file
+    for i in 0..manifest.segments.len() {
+        if manifest.segments[i].status != SegmentStatus::Completed {
+            let hash = manifest.request_hash(&manifest.segments[i]);
+            manifest.segments[i].request_hash = hash;
+        }
+    }
+    // Cached audio is free, so only the rest counts against the cap
+    let (mut done, mut left) = (0, 0);
+    for segment in &manifest.segments {
+        let characters = TtsRequestParams::speech(
+            &segment.voice_name,
+            &segment.language_code,
+            &manifest.input_for(segment),
+        )
+        .billable_characters() as u64;
+        match segment.status {
+            SegmentStatus::Completed => done += characters,
+            SegmentStatus::Skipped => {}
+            _ if cache.entry_path(&segment.request_hash).is_file() => {}
+            _ => left += characters,
+        }
+    }
+    tts.spend().check_batch(left, done, approval)?;
+    manifest.save(manifest_path)?;
+    // The manifest has all a resume needs, so only its path is recorded
+    let fingerprint = Sha256::digest(
+        manifest
+            .segments
+            .iter()
+            .map(|s| s.request_hash.as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+    );
+    job.set_resume(
+        Resume::BatchManifest {
+            manifest_path: manifest_path.to_path_buf(),
+        },
+        format!("{:x}", fingerprint),
+    );
+    let completed = |manifest: &BatchManifest| {
+        manifest
+            .segments
+            .iter()
+            .filter(|s| matches!(s.status, SegmentStatus::Completed | SegmentStatus::Skipped))
+            .count()
+    };
+    job.set_progress(completed(manifest), manifest.segments.len());
+
+    for i in 0..manifest.segments.len() {
+        if manifest.segments[i].status == SegmentStatus::Completed {
+            continue;
+        }
+        if cancel.load(Ordering::Relaxed) {
+            manifest.save(manifest_path)?;
+            log::info!("Batch {} cancelled", manifest_path.display());
+            return Err(AppError::BatchCancelled {
+                manifest_path: manifest_path.display().to_string(),
+            });
+        }
+
+        // Checked on every run, so a resume with edited text picks it up
+        if let Some(reason) = speakable::nothing_to_speak(&manifest.segments[i].text) {
+            let segment = &mut manifest.segments[i];
+            log::warn!("Batch segment {} skipped: {}", segment.id, reason);
+            segment.status = SegmentStatus::Skipped;
+            segment.bytes = 0;
+            segment.channels = None;
+            segment.error = None;
+            segment.warning = Some(reason);
+            segment.analysis = None;
+            manifest.summarize_quality();
+            manifest.save(manifest_path)?;
+            job.set_progress(completed(manifest), manifest.segments.len());
+            continue;
+        }
+
+        let segment = &manifest.segments[i];
+        let input = manifest.input_for(segment);
+        let characters =
+            TtsRequestParams::speech(&segment.voice_name, &segment.language_code, &input)
+                .billable_characters();
+        let result = match crate::tts::synthesis::synthesize_cached_hit(
+            tts,
+            cache,
+            &segment.voice_name,
+            &segment.language_code,
+            input,
+            None,
+            Lane::Background,
+            approval,
+        )
+        .await
+        {
+            Ok(speech) => {
+                if manifest.use_reservation && !speech.hit {
+                    tts.draw_reservation(characters);
+                }
+                manifest.finish(segment, speech.audio).and_then(|audio| {
+                    write_atomic(&segment.path, &audio)
+                        .map(|_| audio)
+                        .map_err(AppError::from)
+                })
+            }
+            Err(e) => Err(e),
+        };
+        // Registration failures are recorded, never fail the segment
+        let registration = match (&result, &manifest.backend_project_id, app_handle) {
+            (Ok(audio), Some(project_id), Some(app_handle)) => Some(
+                handoff::register_segment(
+                    app_handle,
+                    project_id,
+                    &segment.id,
+                    &segment.path,
+                    &segment.request_hash,
+                    audio,
+                )
+                .await,
+            ),
+            (Ok(_), Some(_), None) => Some(Err(
+                "not registered with the backend: the batch ran without the app".to_string(),
+            )),
+            _ => None,
+        };
+
+        let segment = &mut manifest.segments[i];
+        match result {
+            Ok(audio) => {
+                segment.status = SegmentStatus::Completed;
+                segment.bytes = audio.len();
+                segment.channels = channels::count(&audio);
+                segment.error = None;
+                segment.warning = None;
+                segment.backend_asset = None;
+                segment.registration_warning = None;
+                match registration {
+                    Some(Ok(asset)) => segment.backend_asset = Some(asset),
+                    Some(Err(warning)) => segment.registration_warning = Some(warning),
+                    None => {}
+                }
+                segment.analysis = quality::analyze_audio(&audio, thresholds);
+                if let (Some(analysis), Some(app_handle)) = (&segment.analysis, app_handle) {
+                    quality::warn(
+                        app_handle,
+                        Some(segment.path.display().to_string()),
+                        analysis,
+                    );
+                }
+                manifest.summarize_quality();
+                manifest.save(manifest_path)?;
+                job.set_progress(completed(manifest), manifest.segments.len());
+            }
+            Err(e) => {
+                segment.status = SegmentStatus::Failed;
+                segment.error = Some(e.to_string());
+                segment.analysis = None;
+                let segment_id = segment.id.clone();
+                manifest.summarize_quality();
+                manifest.save(manifest_path)?;
+                return Err(AppError::BatchFailed {
+                    manifest_path: manifest_path.display().to_string(),
+                    segment_id,
+                    message: e.to_string(),
+                    cause: Box::new(e),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn synthesize_batch(
+    app_handle: tauri::AppHandle,
+    window: tauri::Window,
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+    jobs: tauri::State<'_, JobManager>,
+    capabilities: tauri::State<'_, CapabilityStore>,
+    cancellation: tauri::State<'_, CancellationRegistry>,
+    exported: tauri::State<'_, ExportedPaths>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    segments: Vec<BatchSegment>,
+    output_dir: PathBuf,
+    naming_template: Option<String>,
+    options: Option<SynthesisOptions>,
+    tags: Option<AudioTags>,
+    cancel_token: Option<String>,
+    backend_project_id: Option<String>,
+    structure: Option<ScriptStructure>,
+) -> Result<BatchManifest, AppError> {
+    access.check()?;
+    if let Some(project_id) = &backend_project_id {
+        handoff::check_id("backend_project_id", project_id)?;
+    }
+    let output_dir = roots.validate_path::<Write>(&output_dir)?.into_path_buf();
+    let options = options.unwrap_or_default();
+    // Segments are synthesized as cached MP3s either way, so a batch in
+    // another format costs no more than one kept as MP3
+    let output = options.output.resolve(&settings.get())?;
+    if output.needs_pcm() && tags.is_some() {
+        return Err(AppError::InvalidAudioRequest {
+            message: "ID3 tags are written into MP3s; choose an MP3 preset to tag a batch"
+                .to_string(),
+        });
+    }
+    crate::project::review::check_export(
+        &app_handle,
+        window.label(),
+        segments.iter().map(|s| s.id.as_str()),
+    )?;
+    let naming_template = naming_template.unwrap_or_else(|| NamingTemplate::DEFAULT.to_string());
+    // Resolve every file name up front so template problems surface before anything is billed
+    let template = NamingTemplate::parse(&naming_template)?;
+    let mut names = UniqueNames::default();
+
+    let defaults = settings.get();
+    let paragraph_pause_ms = options
+        .paragraph_pause_ms
+        .unwrap_or(defaults.paragraph_pause_ms);
+    let line_pause_ms = options.line_pause_ms.unwrap_or(defaults.line_pause_ms);
+    let breathing_room = options.breathing_room.unwrap_or(defaults.breathing_room);
+    if options.validate_plan == Some(true) {
+        let voices = tts.cached_voices().await;
+        plan::validate(
+            &segments,
+            paragraph_pause_ms,
+            line_pause_ms,
+            breathing_room,
+            voices.as_deref(),
+            &capabilities,
+            options
+                .unsupported_features
+                .unwrap_or(defaults.unsupported_features),
+            &Placeholders::new(&defaults.placeholders),
+            &DisplayFormat::for_locale(&defaults.ui_locale),
+        )
+        .check()?;
+    }
+
+    let mut manifest = BatchManifest {
+        output_dir: output_dir.clone(),
+        naming_template,
+        paragraph_pause_ms,
+        line_pause_ms,
+        breathing_room,
+        tags,
+        output,
+        cost_saver: options.cost_saver == Some(true),
+        use_reservation: options.use_reservation == Some(true),
+        backend_project_id,
+        sections: Vec::new(),
+        segments: Vec::with_capacity(segments.len()),
+        quality: QualitySummary::default(),
+    };
+    if let Some(structure) = &structure {
+        manifest.add_sections(&structure.sections, None);
+    }
+    for (i, segment) in segments.into_iter().enumerate() {
+        let (dir, section) = manifest.segment_dir(segment.source_offset);
+        let extension = manifest.output.extension();
+        let stem = long_path::fit_stem(&dir, &render_stem(&template, i + 1, &segment), extension)?;
+        let file_name = names.claim(&stem, extension);
+        manifest
+            .segments
+            .push(pending_segment(i + 1, segment, file_name, &dir, section));
+    }
+    if manifest.cost_saver {
+        let voices = tts.voices(false, None).await?;
+        manifest.apply_cost_saver(&defaults.cost_saver, &voices);
+    }
+
+    let job = jobs.register(
+        JobKind::Batch,
+        output_dir.display().to_string(),
+        Some(window.label()),
+    );
+    let _token =
+        cancellation.register_shared(cancel_token, "synthesize_batch", job.cancel_handle())?;
+    let cache = crate::cache::tts_cache(&app_handle)?;
+    let approval = tts.spend().redeem(options.spend_token.as_deref())?;
+    let result = run_batch(
+        Some(&app_handle),
+        &tts,
+        &cache,
+        &mut manifest,
+        &output_dir.join(MANIFEST_FILE),
+        &job,
+        approval.as_ref(),
+        &settings.get().audio_quality,
+    )
+    .await;
+    // Cancelled and failed batches leave files worth looking at too
+    exported.record(&output_dir);
+    result?;
+    Ok(manifest)
+}
+
+/// Continues a batch from its manifest, keeping outputs that still validate.
+/// When `segments` is given it replaces the manifest's segment list (matched
+/// by id) so edited script text is picked up; changed segments are redone and
+/// flagged with `text_changed`. New segments are filed by the manifest's
+/// sections at their `source_offset`; kept ones stay where they are.
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn resume_batch(
+    app_handle: tauri::AppHandle,
+    window: tauri::Window,
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+    jobs: tauri::State<'_, JobManager>,
+    cancellation: tauri::State<'_, CancellationRegistry>,
+    exported: tauri::State<'_, ExportedPaths>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    manifest_path: PathBuf,
+    segments: Option<Vec<BatchSegment>>,
+    cancel_token: Option<String>,
+    spend_token: Option<String>,
+) -> Result<BatchManifest, AppError> {
+    access.check()?;
+    let manifest_path = roots.validate_path::<Read>(&manifest_path)?.into_path_buf();
+    let mut manifest = BatchManifest::load(&manifest_path)?;
+    // The manifest names where its files go; that must be allowed too
+    roots.validate_path::<Write>(&manifest.output_dir)?;
+    if let Some(segments) = segments {
+        manifest.replace_segments(segments)?;
+    }
+
+    // Every file the batch may write, kept ones included
+    for segment in &manifest.segments {
+        roots.validate_path::<Write>(&segment.path)?;
+    }
+    crate::project::review::check_export(
+        &app_handle,
+        window.label(),
+        manifest.segments.iter().map(|s| s.id.as_str()),
+    )?;
+
+    let job = jobs.register(
+        JobKind::Batch,
+        manifest_path.display().to_string(),
+        Some(window.label()),
+    );
+    let _token = cancellation.register_shared(cancel_token, "resume_batch", job.cancel_handle())?;
+    let cache = crate::cache::tts_cache(&app_handle)?;
+    let approval = tts.spend().redeem(spend_token.as_deref())?;
+    let result = continue_batch(
+        Some(&app_handle),
+        &tts,
+        &cache,
+        &settings.get().cost_saver,
+        &mut manifest,
+        &manifest_path,
+        &job,
+        approval.as_ref(),
+        &settings.get().audio_quality,
+    )
+    .await;
+    exported.record(&manifest.output_dir);
+    result?;
+    Ok(manifest)
+}
+
+/// What `resume_batch` does once the manifest is loaded: applies the cost
+/// saver again, marks outputs that no longer validate pending and runs the
+/// batch. Also how the command line tool runs one, without `app_handle`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn continue_batch(
+    app_handle: Option<&tauri::AppHandle>,
+    tts: &TtsService,
+    cache: &TtsCache,
+    cost_saver: &CostSaverPolicy,
+    manifest: &mut BatchManifest,
+    manifest_path: &Path,
+    job: &JobGuard,
+    approval: Option<&SpendApproval>,
+    thresholds: &QualityThresholds,
+) -> Result<(), AppError> {
+    if manifest.cost_saver {
+        let voices = tts.voices(false, None).await?;
+        manifest.apply_cost_saver(cost_saver, &voices);
+    }
+
+    for i in 0..manifest.segments.len() {
+        let valid = output_is_valid(manifest, &manifest.segments[i]);
+        let segment = &mut manifest.segments[i];
+        segment.reused = valid;
+        if !valid {
+            segment.status = SegmentStatus::Pending;
+            segment.bytes = 0;
+        }
+    }
+    run_batch(
+        app_handle,
+        tts,
+        cache,
+        manifest,
+        manifest_path,
+        job,
+        approval,
+        thresholds,
+    )
+    .await
+}
+
+/// Registers the completed segments `segment_ids` of the batch at
+/// `manifest_path` with the backend again, e.g. after it was down during
+/// the batch; the manifest records each outcome as the batch would.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn retry_registration(
+    app_handle: tauri::AppHandle,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    manifest_path: PathBuf,
+    segment_ids: Vec<String>,
+) -> Result<BatchManifest, AppError> {
+    access.check()?;
+    let manifest_path = roots.validate_path::<Read>(&manifest_path)?.into_path_buf();
+    roots.validate_path::<Write>(&manifest_path)?;
+    let mut manifest = BatchManifest::load(&manifest_path)?;
+    let Some(project_id) = manifest.backend_project_id.clone() else {
+        return Err(AppError::InvalidBackendRequest {
+            message: "the batch wasn't registered with a backend project".to_string(),
+        });
+    };
+    for segment in manifest
+        .segments
+        .iter_mut()
+        .filter(|s| s.status == SegmentStatus::Completed && segment_ids.contains(&s.id))
+    {
+        let registration = match roots
+            .validate_path::<Read>(&segment.path)
+            .and_then(|path| path.read().map_err(AppError::from))
+        {
+            Ok(audio) => {
+                handoff::register_segment(
+                    &app_handle,
+                    &project_id,
+                    &segment.id,
+                    &segment.path,
+                    &segment.request_hash,
+                    &audio,
+                )
+                .await
+            }
+            Err(e) => Err(format!("not registered with the backend: {}", e)),
+        };
+        match registration {
+            Ok(asset) => {
+                segment.backend_asset = Some(asset);
+                segment.registration_warning = None;
+            }
+            Err(warning) => segment.registration_warning = Some(warning),
+        }
+    }
+    manifest.save(&manifest_path)?;
+    Ok(manifest)
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![
+        synthesize_batch,
+        resume_batch,
+        drift::analyze_config_drift,
+        drift::resynthesize_segments,
+        retry_registration,
+    ]
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::presets::OutputEncoding;
+    use crate::scratch::Scratch;
+    use crate::tts::mock::{self, Fault, Harness};
+    use crate::tts::synthesis::synthesize_cached_hit;
+    use gcloud_sdk::google::cloud::texttospeech::v1::synthesis_input::InputSource;
+
+    /// One MPEG-1 Layer III frame, 128 kbps at 44.1 kHz.
+    fn mp3() -> Vec<u8> {
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x00];
+        frame.resize(417, 0);
+        frame
+    }
+
+    fn manifest(dir: &Path) -> BatchManifest {
+        BatchManifest {
+            output_dir: dir.to_path_buf(),
+            naming_template: "{index}".to_string(),
+            paragraph_pause_ms: 800,
+            line_pause_ms: 0,
+            breathing_room: BreathingRoom::Off,
+            tags: None,
+            output: OutputFormat::default(),
+            cost_saver: false,
+            use_reservation: false,
+            backend_project_id: None,
+            sections: Vec::new(),
+            segments: Vec::new(),
+            quality: QualitySummary::default(),
+        }
+    }
+
+    /// A segment completed by an earlier run, its file written.
+    fn completed(manifest: &BatchManifest, audio: &[u8]) -> ManifestSegment {
+        let segment = BatchSegment {
+            id: "intro".to_string(),
+            voice_name: "en-US-Standard-C".to_string(),
+            language_code: "en-US".to_string(),
+            text: "Welcome.\n\nLet's begin.".to_string(),
+            chapters: Vec::new(),
+            source_offset: None,
+        };
+        let extension = manifest.output.extension();
+        let file_name = format!("001.{}", extension);
+        let mut segment = pending_segment(1, segment, file_name, &manifest.output_dir, None);
+        segment.request_hash = manifest.request_hash(&segment);
+        segment.status = SegmentStatus::Completed;
+        segment.bytes = audio.len();
+        fs::write(&segment.path, audio).unwrap();
+        segment
+    }
+
+    fn segment(id: &str, text: &str) -> BatchSegment {
+        BatchSegment {
+            id: id.to_string(),
+            voice_name: "en-US-Mock-A".to_string(),
+            language_code: "en-US".to_string(),
+            text: text.to_string(),
+            chapters: Vec::new(),
+            source_offset: None,
+        }
+    }
+
+    /// A batch of `segments` not yet run, writing to `dir`.
+    fn pending(dir: &Path, segments: &[(&str, &str)]) -> BatchManifest {
+        let mut manifest = manifest(dir);
+        manifest.segments = segments
+            .iter()
+            .enumerate()
+            .map(|(i, (id, text))| {
+                let file_name = format!("{:03}.mp3", i + 1);
+                pending_segment(i + 1, segment(id, text), file_name, dir, None)
+            })
+            .collect();
+        manifest
+    }
+
+    /// Runs what's left of `manifest` as the command line tool does.
+    async fn resume(
+        harness: &Harness,
+        manifest: &mut BatchManifest,
+        path: &Path,
+    ) -> Result<(), AppError> {
+        let jobs = JobManager::load(manifest.output_dir.join("jobs.json"));
+        let job = jobs.register(JobKind::Batch, path.display().to_string(), None);
+        continue_batch(
+            None,
+            &harness.tts,
+            &harness.cache,
+            &CostSaverPolicy::default(),
+            manifest,
+            path,
+            &job,
+            None,
+            &QualityThresholds::default(),
+        )
+        .await
+    }
+
+    /// Texts of the synthesis requests the mock got, in order.
+    fn requested(harness: &Harness) -> Vec<String> {
+        harness
+            .server
+            .received()
+            .into_iter()
+            .filter_map(|r| match r.request.input?.input_source? {
+                InputSource::Text(text) => Some(text),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn statuses(manifest: &BatchManifest) -> Vec<SegmentStatus> {
+        manifest.segments.iter().map(|s| s.status).collect()
+    }
+
+    #[tokio::test]
+    async fn a_resume_after_a_failed_segment_redoes_only_what_is_left() {
+        let harness = mock::harness().await;
+        let scratch = Scratch::new("fault");
+        let path = scratch.0.join(MANIFEST_FILE);
+        let mut manifest = pending(
+            &scratch.0,
+            &[("s1", "One."), ("s2", "Two."), ("s3", "Three.")],
+        );
+        // The first segment is cached, so the fault hits the second
+        synthesize_cached_hit(
+            &harness.tts,
+            &harness.cache,
+            "en-US-Mock-A",
+            "en-US",
+            manifest.input_for(&manifest.segments[0]),
+            None,
+            Lane::Background,
+            None,
+        )
+        .await
+        .unwrap();
+        harness.server.fail_next(Fault::Unavailable, 1);
+
+        let failed = resume(&harness, &mut manifest, &path).await;
+        match failed {
+            Err(AppError::BatchFailed {
+                segment_id, cause, ..
+            }) => {
+                assert_eq!(segment_id, "s2");
+                assert!(matches!(*cause, AppError::TtsOffline { .. }), "{:?}", cause);
+            }
+            other => panic!("expected BatchFailed, got {:?}", other),
+        }
+        let mut loaded = BatchManifest::load(&path).unwrap();
+        assert_eq!(
+            statuses(&loaded),
+            [
+                SegmentStatus::Completed,
+                SegmentStatus::Failed,
+                SegmentStatus::Pending
+            ]
+        );
+        assert!(loaded.segments[1].error.is_some());
+        let first = fs::read(&loaded.segments[0].path).unwrap();
+
+        resume(&harness, &mut loaded, &path).await.unwrap();
+        assert_eq!(statuses(&loaded), [SegmentStatus::Completed; 3]);
+        let reused: Vec<bool> = loaded.segments.iter().map(|s| s.reused).collect();
+        assert_eq!(reused, [true, false, false]);
+        assert_eq!(requested(&harness), ["One.", "Two.", "Two.", "Three."]);
+        assert_eq!(fs::read(&loaded.segments[0].path).unwrap(), first);
+        for segment in &loaded.segments {
+            assert!(output_is_valid(&loaded, segment), "{}", segment.id);
+        }
+        assert_eq!(BatchManifest::load(&path).unwrap().segments.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn a_resume_with_edited_text_redoes_the_edited_segments() {
+        let harness = mock::harness().await;
+        let scratch = Scratch::new("edited");
+        let path = scratch.0.join(MANIFEST_FILE);
+        let mut manifest = pending(&scratch.0, &[("s1", "One."), ("s2", "Two.")]);
+        resume(&harness, &mut manifest, &path).await.unwrap();
+        assert_eq!(requested(&harness), ["One.", "Two."]);
+
+        let mut loaded = BatchManifest::load(&path).unwrap();
+        loaded
+            .replace_segments(vec![
+                segment("s1", "One."),
+                segment("s2", "Two, edited."),
+                segment("s3", "Three."),
+            ])
+            .unwrap();
+        let changed: Vec<bool> = loaded.segments.iter().map(|s| s.text_changed).collect();
+        assert_eq!(changed, [false, true, false]);
+        assert_eq!(loaded.segments[1].file_name, "002.mp3");
+
+        resume(&harness, &mut loaded, &path).await.unwrap();
+        assert_eq!(statuses(&loaded), [SegmentStatus::Completed; 3]);
+        let reused: Vec<bool> = loaded.segments.iter().map(|s| s.reused).collect();
+        assert_eq!(reused, [true, false, false]);
+        assert_eq!(
+            requested(&harness),
+            ["One.", "Two.", "Two, edited.", "Three."]
+        );
+        let saved = BatchManifest::load(&path).unwrap();
+        assert_eq!(saved.segments[1].text, "Two, edited.");
+        assert!(saved.segments[1].text_changed);
+        assert!(output_is_valid(&saved, &saved.segments[1]));
+        assert_ne!(saved.segments[2].file_name, saved.segments[1].file_name);
+    }
+
+    #[test]
+    fn renames_only_the_voices_of_segments_without_a_file() {
+        let scratch = Scratch::new("remap");
+        let mut manifest = manifest(&scratch.0);
+        let mut finished = completed(&manifest, &mp3());
+        finished.voice_name = "en-US-Journey-D".to_string();
+        let mut pending = ManifestSegment {
+            id: "outro".to_string(),
+            status: SegmentStatus::Failed,
+            ..finished.clone()
+        };
+        pending.voice_name = "en-US-Journey-D".to_string();
+        manifest.segments = vec![finished, pending];
+
+        let remap = VoiceRemap::new(&Default::default());
+        assert_eq!(
+            manifest.remap_voices(&remap),
+            vec![VoiceRemapping {
+                from: "en-US-Journey-D".to_string(),
+                to: "en-US-Chirp-HD-D".to_string(),
+            }]
+        );
+        assert_eq!(manifest.segments[0].voice_name, "en-US-Journey-D");
+        assert_eq!(manifest.segments[1].voice_name, "en-US-Chirp-HD-D");
+        assert!(manifest.remap_voices(&remap).is_empty());
+    }
+
+    #[test]
+    fn keeps_outputs_that_still_validate() {
+        let scratch = Scratch::new("valid");
+        let manifest = manifest(&scratch.0);
+        let segment = completed(&manifest, &mp3());
+        assert!(output_is_valid(&manifest, &segment));
+
+        let failed = ManifestSegment {
+            status: SegmentStatus::Failed,
+            ..segment.clone()
+        };
+        assert!(!output_is_valid(&manifest, &failed));
+        // Edited text is a different request
+        let edited = ManifestSegment {
+            text: "Welcome back.".to_string(),
+            ..segment.clone()
+        };
+        assert!(!output_is_valid(&manifest, &edited));
+        let paused = BatchManifest {
+            paragraph_pause_ms: 1200,
+            ..manifest.clone()
+        };
+        assert!(!output_is_valid(&paused, &segment));
+    }
+
+    #[test]
+    fn redoes_outputs_changed_on_disk() {
+        let scratch = Scratch::new("changed");
+        let manifest = manifest(&scratch.0);
+        let segment = completed(&manifest, &mp3());
+
+        fs::write(&segment.path, &mp3()[..400]).unwrap();
+        assert!(!output_is_valid(&manifest, &segment));
+        fs::write(&segment.path, vec![0; 417]).unwrap();
+        assert!(!output_is_valid(&manifest, &segment));
+        fs::remove_file(&segment.path).unwrap();
+        assert!(!output_is_valid(&manifest, &segment));
+    }
+
+    #[test]
+    fn pcm_outputs_are_checked_as_wavs() {
+        let scratch = Scratch::new("wav");
+        let manifest = BatchManifest {
+            output: OutputFormat {
+                audio_encoding: OutputEncoding::Linear16,
+                ..OutputFormat::default()
+            },
+            ..manifest(&scratch.0)
+        };
+        let mut wav = crate::audio::placeholder::wav_header(24_000, 1, 2)
+            .unwrap()
+            .to_vec();
+        wav.extend_from_slice(&[1, 0, 2, 0]);
+        let segment = completed(&manifest, &wav);
+        assert_eq!(segment.file_name, "001.wav");
+        assert!(output_is_valid(&manifest, &segment));
+
+        let segment = completed(&manifest, &mp3());
+        assert!(!output_is_valid(&manifest, &segment));
+    }
+
+    #[test]
+    fn manifests_round_trip_and_old_ones_load() {
+        let scratch = Scratch::new("manifest");
+        let path = scratch.0.join(MANIFEST_FILE);
+        let mut saved = manifest(&scratch.0);
+        saved.segments.push(completed(&saved, &mp3()));
+        saved.save(&path).unwrap();
+        let loaded = BatchManifest::load(&path).unwrap();
+        assert_eq!(loaded.segments.len(), 1);
+        assert_eq!(
+            loaded.segments[0].request_hash,
+            saved.segments[0].request_hash
+        );
+        assert!(output_is_valid(&loaded, &loaded.segments[0]));
+
+        // Written before breathing room, tags, output formats and sections
+        let old = serde_json::json!({
+            "output_dir": scratch.0,
+            "naming_template": "{index}",
+            "paragraph_pause_ms": 800,
+            "line_pause_ms": 0,
+            "segments": [{
+                "id": "intro",
+                "index": 1,
+                "voice_name": "en-US-Standard-C",
+                "language_code": "en-US",
+                "text": "Welcome.",
+                "file_name": "001.mp3",
+                "path": scratch.0.join("001.mp3"),
+                "status": "completed",
+                "bytes": 417,
+                "request_hash": "abc"
+            }]
+        });
+        fs::write(&path, old.to_string()).unwrap();
+        let loaded = BatchManifest::load(&path).unwrap();
+        assert_eq!(loaded.breathing_room, BreathingRoom::Off);
+        assert_eq!(loaded.output, OutputFormat::default());
+        assert!(!loaded.segments[0].text_changed && !loaded.segments[0].reused);
+
+        fs::write(&path, "{\"segments\": 3}").unwrap();
+        assert!(matches!(
+            BatchManifest::load(&path),
+            Err(AppError::InvalidManifest { .. })
+        ));
+    }
+
+    #[test]
+    fn loading_rebuilds_segment_paths_inside_the_output_dir() {
+        let scratch = Scratch::new("paths");
+        let path = scratch.0.join(MANIFEST_FILE);
+        let mut saved = manifest(&scratch.0);
+        saved.sections.push(ManifestSection {
+            index: 1,
+            title: "Scene 1".to_string(),
+            level: 1,
+            kind: HeadingKind::Scene,
+            scene_number: Some(1),
+            parent: None,
+            directory: PathBuf::from("01 Scene 1"),
+            start: 0,
+            end: 10,
+        });
+        let mut segment = completed(&saved, &mp3());
+        segment.section = Some(1);
+        // Edited to point outside the batch
+        segment.path = std::env::temp_dir().join("elsewhere.mp3");
+        saved.segments.push(segment);
+        saved.save(&path).unwrap();
+        let loaded = BatchManifest::load(&path).unwrap();
+        assert_eq!(
+            loaded.segments[0].path,
+            scratch.0.join("01 Scene 1").join("001.mp3")
+        );
+
+        let refused = |edit: &dyn Fn(&mut BatchManifest)| {
+            let mut edited = saved.clone();
+            edit(&mut edited);
+            edited.save(&path).unwrap();
+            matches!(
+                BatchManifest::load(&path),
+                Err(AppError::InvalidManifest { .. })
+            )
+        };
+        assert!(refused(
+            &|m| m.segments[0].file_name = "../001.mp3".to_string()
+        ));
+        assert!(refused(
+            &|m| m.segments[0].file_name = "sub/001.mp3".to_string()
+        ));
+        assert!(refused(&|m| m.segments[0].file_name = String::new()));
+        assert!(refused(
+            &|m| m.sections[0].directory = PathBuf::from("../up")
+        ));
+        assert!(refused(&|m| m.sections[0].directory = std::env::temp_dir()));
+        assert!(refused(&|m| m.segments[0].section = Some(2)));
+    }
+}
@@ -0,0 +1,292 @@
+// Output file naming templates for batch synthesis, e.g. "{index:03}_{voice}_{slug}"
+
+use std::collections::HashSet;
+
+use crate::error::AppError;
+
+const DEFAULT_SLUG_LEN: usize = 32;
+const SLUG_WORDS: usize = 6;
+/// Cap on the generated file stem. Leaves room for collision suffixes and the
+/// extension within the 255-byte component limit of common filesystems.
+const MAX_STEM_BYTES: usize = 120;
+
+const WINDOWS_RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Field {
+    Index { width: usize, zero_pad: bool },
+    Id,
+    Voice,
+    Language,
+    Slug { max_len: usize },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(String),
+    Field(Field),
+}
+
+/// Values available to a template for one segment.
+pub struct NameContext<'a> {
+    pub index: usize,
+    pub id: &'a str,
+    pub voice_name: &'a str,
+    pub language_code: &'a str,
+    pub text: &'a str,
+}
+
+#[derive(Debug, Clone)]
+pub struct NamingTemplate {
+    tokens: Vec<Token>,
+}
+
+impl NamingTemplate {
+    pub const DEFAULT: &'static str = "{index:03}_{id}";
+
+    pub fn parse(template: &str) -> Result<Self, AppError> {
+        let invalid = |reason: String| AppError::InvalidTemplate {
+            template: template.to_string(),
+            reason,
+        };
+
+        let mut tokens = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    let mut spec = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => spec.push(c),
+                            None => return Err(invalid("unterminated '{'".to_string())),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        tokens.push(Token::Literal(std::mem::take(&mut literal)));
+                    }
+                    tokens.push(Token::Field(parse_field(&spec).map_err(invalid)?));
+                }
+                '}' => return Err(invalid("unmatched '}'".to_string())),
+                c if is_forbidden_filename_char(c) => {
+                    return Err(invalid(format!("'{}' is not allowed in file names", c)))
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(literal));
+        }
+
+        if !tokens.iter().any(|t| matches!(t, Token::Field(_))) {
+            return Err(invalid(
+                "template must contain at least one {field}".to_string(),
+            ));
+        }
+        Ok(Self { tokens })
+    }
+
+    /// Expands the template into a filesystem-safe file stem (no extension).
+    pub fn render(&self, ctx: &NameContext) -> String {
+        let mut name = String::new();
+        for token in &self.tokens {
+            match token {
+                Token::Literal(s) => name.push_str(s),
+                Token::Field(Field::Index { width, zero_pad }) => {
+                    if *zero_pad {
+                        name.push_str(&format!("{:0width$}", ctx.index, width = *width));
+                    } else {
+                        name.push_str(&format!("{:width$}", ctx.index, width = *width));
+                    }
+                }
+                Token::Field(Field::Id) => name.push_str(&sanitize_component(ctx.id)),
+                Token::Field(Field::Voice) => name.push_str(&sanitize_component(ctx.voice_name)),
+                Token::Field(Field::Language) => {
+                    name.push_str(&sanitize_component(ctx.language_code))
+                }
+                Token::Field(Field::Slug { max_len }) => {
+                    name.push_str(&slugify(ctx.text, *max_len))
+                }
+            }
+        }
+        finalize_stem(&name)
+    }
+}
+
+fn parse_field(spec: &str) -> Result<Field, String> {
+    let (name, format) = match spec.split_once(':') {
+        Some((name, format)) => (name.trim(), Some(format.trim())),
+        None => (spec.trim(), None),
+    };
+
+    let parse_width = |format: &str| -> Result<usize, String> {
+        format
+            .parse::<usize>()
+            .ok()
+            .filter(|w| (1..=32).contains(w))
+            .ok_or_else(|| format!("invalid width '{}' for {{{}}}", format, name))
+    };
+
+    match (name, format) {
+        ("index", None) => Ok(Field::Index {
+            width: 1,
+            zero_pad: false,
+        }),
+        ("index", Some(format)) => Ok(Field::Index {
+            width: parse_width(format)?,
+            zero_pad: format.starts_with('0'),
+        }),
+        ("slug", None) => Ok(Field::Slug {
+            max_len: DEFAULT_SLUG_LEN,
+        }),
+        ("slug", Some(format)) => Ok(Field::Slug {
+            max_len: parse_width(format)?,
+        }),
+        ("id" | "voice" | "language", Some(_)) => {
+            Err(format!("{{{}}} does not take a format", name))
+        }
+        ("id", None) => Ok(Field::Id),
+        ("voice", None) => Ok(Field::Voice),
+        ("language", None) => Ok(Field::Language),
+        _ => Err(format!(
+            "unknown field {{{}}}; expected index, id, voice, language or slug",
+            name
+        )),
+    }
+}
+
+fn is_forbidden_filename_char(c: char) -> bool {
+    matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control()
+}
+
+fn sanitize_component(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if is_forbidden_filename_char(c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Lowercased, hyphen-separated form of the first few words of `text`,
+/// keeping Unicode letters and digits and truncated to `max_len` characters.
+pub fn slugify(text: &str, max_len: usize) -> String {
+    let mut slug = String::new();
+    for word in text.split_whitespace().take(SLUG_WORDS) {
+        let cleaned: String = word
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect();
+        if cleaned.is_empty() {
+            continue;
+        }
+        if !slug.is_empty() {
+            slug.push('-');
+        }
+        slug.push_str(&cleaned);
+    }
+
+    let truncated: String = slug.chars().take(max_len).collect();
+    let truncated = truncated.trim_end_matches('-');
+    if truncated.is_empty() {
+        "segment".to_string()
+    } else {
+        truncated.to_string()
+    }
+}
+
+/// Directory of a section in a structured batch: its position among its
+/// siblings and its title, e.g. "02 Scene 3 – The Reveal".
+pub fn section_directory(position: usize, title: &str) -> String {
+    finalize_stem(&sanitize_component(&format!("{:02} {}", position, title)))
+}
+
+/// Applies the rules every generated stem must satisfy regardless of the
+/// template: byte-length cap, no trailing dots/spaces (Windows strips them),
+/// and no reserved device names.
+fn finalize_stem(name: &str) -> String {
+    let mut stem = String::new();
+    for c in name.chars() {
+        if stem.len() + c.len_utf8() > MAX_STEM_BYTES {
+            break;
+        }
+        stem.push(c);
+    }
+    let mut stem = stem.trim_end_matches(['.', ' ']).to_string();
+    if stem.is_empty() {
+        stem = "segment".to_string();
+    }
+
+    let device = stem.split('.').next().unwrap_or("").to_ascii_uppercase();
+    if WINDOWS_RESERVED.contains(&device.as_str()) {
+        stem.insert(0, '_');
+    }
+    stem
+}
+
+/// Hands out unique file names, appending -2, -3, ... on collisions. The
+/// comparison is case-insensitive because Windows and macOS filesystems are.
+#[derive(Default)]
+pub struct UniqueNames {
+    taken: HashSet<String>,
+}
+
+impl UniqueNames {
+    pub fn claim(&mut self, stem: &str, extension: &str) -> String {
+        let mut candidate = format!("{}.{}", stem, extension);
+        let mut n = 2;
+        while !self.taken.insert(candidate.to_lowercase()) {
+            candidate = format!("{}-{}.{}", stem, n, extension);
+            n += 1;
+        }
+        candidate
+    }
+
+    /// Reserves a name that is already in use, e.g. by an earlier run.
+    pub fn claim_exact(&mut self, file_name: &str) {
+        self.taken.insert(file_name.to_lowercase());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_unique_by_any_case() {
+        let mut names = UniqueNames::default();
+        assert_eq!(names.claim("intro", "mp3"), "intro.mp3");
+        assert_eq!(names.claim("Intro", "mp3"), "Intro-2.mp3");
+        assert_eq!(names.claim("intro", "wav"), "intro.wav");
+    }
+
+    #[test]
+    fn names_of_an_earlier_run_stay_taken() {
+        let mut names = UniqueNames::default();
+        names.claim_exact("001_Intro.mp3");
+        names.claim_exact("001_intro-2.mp3");
+        assert_eq!(names.claim("001_intro", "mp3"), "001_intro-3.mp3");
+    }
+
+    #[test]
+    fn section_directories_are_numbered_and_safe() {
+        assert_eq!(
+            section_directory(2, "Scene 3 – The Reveal"),
+            "02 Scene 3 – The Reveal"
+        );
+        assert_eq!(section_directory(12, "Act 1: Who?"), "12 Act 1_ Who_");
+        assert_eq!(section_directory(1, "The End..."), "01 The End");
+        let long = section_directory(3, &"word ".repeat(40));
+        assert!(long.len() <= MAX_STEM_BYTES && long.starts_with("03 word"));
+    }
+}
@@ -0,0 +1,4 @@
+// Local mock of Google's TextToSpeech service; see `desktop_lib::mock`
+fn main() -> std::process::ExitCode {
+    desktop_lib::mock::main()
+}
@@ -0,0 +1,4 @@
+// Headless synthesis from the command line; see `desktop_lib::cli`
+fn main() -> std::process::ExitCode {
+    desktop_lib::cli::main()
+}
@@ -0,0 +1,254 @@
+// On-disk caches for voice previews and synthesized speech
+pub mod preview;
+pub mod sharing;
+pub mod tts;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tauri::Manager;
+
+use crate::error::AppError;
+use crate::paths::roots::AllowedRoots;
+use crate::read_only::WriteAccess;
+use crate::settings::SettingsStore;
+use crate::trash::{Trash, TrashEntry};
+use crate::tts::history::SynthesisHistory;
+
+/// Writes `bytes` to a sibling temp file and renames it into place so readers
+/// never observe a half-written cache entry.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let path = &crate::long_path::extended(path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, path)
+}
+
+/// Google voice names, e.g. "en-US-Neural2-A": ASCII letters, digits and
+/// inner hyphens. Anything else could name a path outside a cache.
+pub fn is_voice_name(name: &str) -> bool {
+    (1..=64).contains(&name.len())
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Cache keys are request fingerprints: ASCII letters, digits, '-' and '_'.
+pub fn is_cache_key(key: &str) -> bool {
+    (1..=128).contains(&key.len())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// `path` resolved through symlinks, if it exists and is still inside `dir`.
+pub fn contained_path(dir: &Path, path: &Path) -> Option<PathBuf> {
+    let dir = fs::canonicalize(dir).ok()?;
+    let path = fs::canonicalize(path).ok()?;
+    path.starts_with(&dir).then_some(path)
+}
+
+/// The synthesis cache; in read-only mode one that stores nothing.
+pub fn tts_cache(app_handle: &tauri::AppHandle) -> Result<tts::TtsCache, AppError> {
+    let verify = app_handle
+        .try_state::<SettingsStore>()
+        .is_none_or(|settings| settings.get().verify_tts_cache);
+    Ok(tts_cache_in(
+        &app_handle.path().app_cache_dir()?,
+        crate::read_only::is_read_only(app_handle),
+        verify,
+    ))
+}
+
+/// The synthesis cache under the app cache directory `cache_dir`.
+pub fn tts_cache_in(cache_dir: &Path, read_only: bool, verify: bool) -> tts::TtsCache {
+    let dir = cache_dir.join("tts_cache");
+    if read_only {
+        return tts::TtsCache::disabled(dir);
+    }
+    if !verify {
+        return tts::TtsCache::new(dir).unverified();
+    }
+    tts::TtsCache::new(dir)
+}
+
+/// The preview cache: bundled previews, and regenerated ones in app data.
+pub fn preview_cache(app_handle: &tauri::AppHandle) -> Result<preview::PreviewCache, AppError> {
+    let resource_dir = app_handle.path().resource_dir()?;
+    let data_dir = app_handle.path().app_data_dir()?;
+    let cache = preview::PreviewCache::new(
+        resource_dir.join("preview_cache"),
+        preview::writable_dir_in(&data_dir),
+    );
+    if crate::read_only::is_read_only(app_handle) {
+        return Ok(cache.read_only());
+    }
+    Ok(cache)
+}
+
+/// Moves the synthesized speech cache to the trash, except the entries the
+/// synthesis history still lists.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn clear_tts_cache(
+    app_handle: tauri::AppHandle,
+    trash: tauri::State<'_, Trash>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    history: tauri::State<'_, SynthesisHistory>,
+) -> Result<Option<TrashEntry>, AppError> {
+    access.check()?;
+    let cache = crate::cache::tts_cache(&app_handle)?;
+    discard_tts_cache(&cache, &trash, &roots, &history)
+}
+
+/// `clear_tts_cache` for `cache`, without the read-only check.
+pub fn discard_tts_cache(
+    cache: &tts::TtsCache,
+    trash: &Trash,
+    roots: &AllowedRoots,
+    history: &SynthesisHistory,
+) -> Result<Option<TrashEntry>, AppError> {
+    let dir = cache.dir();
+    if !dir.exists() {
+        return Ok(None);
+    }
+    let protected = history.protected_keys();
+    if protected.is_empty() {
+        return trash.discard("clear_tts_cache", &[roots.validate_path(dir)?]);
+    }
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let key = path.file_name().map(|n| n.to_string_lossy()).and_then(|n| {
+            n.strip_suffix(".mp3")
+                .or_else(|| n.strip_suffix(".json"))
+                .map(str::to_string)
+        });
+        if !key.is_some_and(|key| protected.contains(&key)) {
+            paths.push(roots.validate_path(&path)?);
+        }
+    }
+    if paths.is_empty() {
+        return Ok(None);
+    }
+    trash.discard("clear_tts_cache", &paths)
+}
+
+/// Hashes every synthesized speech entry against the metadata stored with
+/// it, reporting edited, corrupt and orphaned ones without changing any.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn verify_tts_cache(app_handle: tauri::AppHandle) -> Result<tts::CacheAudit, AppError> {
+    let cache = crate::cache::tts_cache(&app_handle)?;
+    let audit = tauri::async_runtime::spawn_blocking(move || cache.audit())
+        .await
+        .map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })??;
+    Ok(audit)
+}
+
+/// Moves regenerated voice previews to the trash; the bundled ones are
+/// read-only and stay.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn clear_preview_cache(
+    app_handle: tauri::AppHandle,
+    trash: tauri::State<'_, Trash>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+) -> Result<Option<TrashEntry>, AppError> {
+    access.check()?;
+    let dir = crate::cache::preview_cache(&app_handle)?
+        .writable_dir()
+        .to_path_buf();
+    if !dir.exists() {
+        return Ok(None);
+    }
+    trash.discard("clear_preview_cache", &[roots.validate_path(&dir)?])
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![
+        sharing::export_preview_cache,
+        sharing::import_preview_cache,
+        sharing::cancel_preview_cache_archive,
+        clear_tts_cache,
+        verify_tts_cache,
+        clear_preview_cache,
+    ]
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    #[test]
+    fn voice_names_are_google_names_only() {
+        for name in [
+            "en-US-Neural2-A",
+            "cmn-CN-Wavenet-B",
+            "en-US-Chirp3-HD-Achernar",
+        ] {
+            assert!(is_voice_name(name), "{}", name);
+        }
+        for name in [
+            "",
+            "../secrets",
+            "en-US/Neural2-A",
+            "en-US\\Neural2-A",
+            "-en-US",
+            "en-US-",
+            "en US",
+            "en-US-Néural2-A",
+            &"a".repeat(65),
+        ] {
+            assert!(!is_voice_name(name), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn cache_keys_are_fingerprints_only() {
+        assert!(is_cache_key(&"0123456789abcdef".repeat(4)));
+        assert!(is_cache_key("legacy_key-1"));
+        for key in [
+            "",
+            "..",
+            "a/b",
+            "a\\b",
+            "key.mp3",
+            "C:key",
+            &"a".repeat(129),
+        ] {
+            assert!(!is_cache_key(key), "{:?}", key);
+        }
+    }
+
+    #[test]
+    fn contained_paths_stay_in_their_directory() {
+        let scratch = Scratch::new("cache");
+        let root = &scratch.0;
+        let dir = root.join("cache");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("entry.mp3"), b"inside").unwrap();
+        fs::write(root.join("outside.mp3"), b"outside").unwrap();
+
+        assert_eq!(
+            contained_path(&dir, &dir.join("entry.mp3")),
+            Some(fs::canonicalize(dir.join("entry.mp3")).unwrap())
+        );
+        assert_eq!(contained_path(&dir, &dir.join("../outside.mp3")), None);
+        assert_eq!(contained_path(&dir, &dir.join("missing.mp3")), None);
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("outside.mp3"), dir.join("link.mp3")).unwrap();
+            assert_eq!(contained_path(&dir, &dir.join("link.mp3")), None);
+        }
+    }
+}
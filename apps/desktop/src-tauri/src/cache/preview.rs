@@ -0,0 +1,225 @@
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{contained_path, is_voice_name, write_atomic};
+use crate::audio::trim;
+
+/// Sentence the bundled previews speak; matches
+/// scripts/setup/generate_voice_previews.py.
+pub const BUNDLED_SENTENCE: &str =
+    "Hello, this is a preview of my voice. I hope you like how I sound!";
+
+/// Layout of the writable previews: `preview_cache/v2/<voice>.mp3` in app
+/// data. Layout 1 kept `voice_<voice>.mp3` files in the app cache directory;
+/// `migrations` moves them over.
+pub const LAYOUT_VERSION: u32 = 2;
+
+/// The writable preview directory of the current layout under `data_dir`.
+pub fn writable_dir_in(data_dir: &Path) -> PathBuf {
+    data_dir
+        .join("preview_cache")
+        .join(format!("v{}", LAYOUT_VERSION))
+}
+
+/// Voice previews live in two places: the read-only set bundled with the app
+/// resources, and a writable directory for previews regenerated on demand.
+/// The writable copy always wins so a regenerated preview shadows a broken
+/// bundled one. Previews of other sentences than `BUNDLED_SENTENCE` are only
+/// ever writable, named by the sentence's hash so that changing it misses.
+pub struct PreviewCache {
+    bundled_dir: PathBuf,
+    writable_dir: PathBuf,
+    /// False in read-only mode, where regenerated previews are played but
+    /// not kept.
+    stores: bool,
+}
+
+impl PreviewCache {
+    pub fn new(bundled_dir: PathBuf, writable_dir: PathBuf) -> Self {
+        Self {
+            bundled_dir,
+            writable_dir,
+            stores: true,
+        }
+    }
+
+    /// Without storing anything: previews found are still read.
+    pub fn read_only(self) -> Self {
+        Self {
+            stores: false,
+            ..self
+        }
+    }
+
+    pub fn stores(&self) -> bool {
+        self.stores
+    }
+
+    pub fn writable_dir(&self) -> &Path {
+        &self.writable_dir
+    }
+
+    /// Name of a preview in the bundled resources, and in layout 1.
+    pub fn bundled_file_name(voice_name: &str) -> String {
+        format!("voice_{}.mp3", voice_name)
+    }
+
+    pub fn file_name(voice_name: &str, sentence: &str) -> String {
+        if sentence == BUNDLED_SENTENCE {
+            return format!("{}.mp3", voice_name);
+        }
+        let hash = format!("{:x}", Sha256::digest(sentence.as_bytes()));
+        format!("{}.{}.mp3", voice_name, &hash[..12])
+    }
+
+    /// The regenerated preview of `voice_name` speaking `sentence`, never a
+    /// bundled one.
+    pub fn locate_writable(&self, voice_name: &str, sentence: &str) -> Option<PathBuf> {
+        if !is_voice_name(voice_name) {
+            return None;
+        }
+        let file_name = Self::file_name(voice_name, sentence);
+        contained_path(&self.writable_dir, &self.writable_dir.join(file_name))
+    }
+
+    /// The preview of `voice_name` speaking `sentence`. Callers check
+    /// `is_voice_name` first; the containment check catches symlinks
+    /// pointing out of the cache.
+    pub fn locate(&self, voice_name: &str, sentence: &str) -> Option<PathBuf> {
+        let writable = self.locate_writable(voice_name, sentence);
+        if writable.is_some() || sentence != BUNDLED_SENTENCE || !is_voice_name(voice_name) {
+            return writable;
+        }
+        contained_path(
+            &self.bundled_dir,
+            &self.bundled_dir.join(Self::bundled_file_name(voice_name)),
+        )
+    }
+
+    /// When the preview `locate` finds was written, for showing its age.
+    pub fn generated_at(&self, voice_name: &str, sentence: &str) -> Option<DateTime<Utc>> {
+        let modified = fs::metadata(self.locate(voice_name, sentence)?)
+            .ok()?
+            .modified()
+            .ok()?;
+        Some(modified.into())
+    }
+
+    /// Name of the trimmed variant of the preview file at `source`, e.g.
+    /// `voice_X.trimmed.mp3` for the bundled `voice_X.mp3`.
+    fn trimmed_file_name(source: &Path) -> Option<String> {
+        let stem = source.file_name()?.to_str()?.strip_suffix(".mp3")?;
+        Some(format!("{}.trimmed.mp3", stem))
+    }
+
+    /// The first `max_ms` of the preview at `source`, read as `bytes`;
+    /// `None` when it is no longer than that, or `max_ms` is 0. The variant
+    /// is kept in the writable directory and made again once `source` is
+    /// newer than it or `max_ms` changed.
+    pub fn trimmed(&self, source: &Path, bytes: &[u8], max_ms: u32) -> Option<Vec<u8>> {
+        if max_ms == 0 {
+            return None;
+        }
+        let path = self.writable_dir.join(Self::trimmed_file_name(source)?);
+        let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+        if let (Some(kept), Some(source_modified)) = (modified(&path), modified(source)) {
+            if kept > source_modified {
+                if let Ok(existing) = fs::read(&path) {
+                    if trim::is_trim_of(&existing, max_ms) {
+                        return Some(existing);
+                    }
+                }
+            }
+        }
+        let trimmed = trim::trim(bytes, max_ms)?;
+        if self.stores {
+            if let Err(e) = write_atomic(&path, &trimmed) {
+                log::warn!("Failed to keep trimmed preview {}: {}", path.display(), e);
+            }
+        }
+        Some(trimmed)
+    }
+
+    pub fn store(&self, voice_name: &str, sentence: &str, bytes: &[u8]) -> io::Result<PathBuf> {
+        if !is_voice_name(voice_name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid voice name {:?}", voice_name),
+            ));
+        }
+        let file_name = Self::file_name(voice_name, sentence);
+        let path = self.writable_dir.join(file_name);
+        write_atomic(&path, bytes)?;
+        Ok(path)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    /// A cache over empty `bundled` and `writable` directories in `scratch`.
+    fn cache_in(scratch: &Scratch) -> PreviewCache {
+        let (bundled, writable) = (scratch.0.join("bundled"), scratch.0.join("writable"));
+        fs::create_dir_all(&bundled).unwrap();
+        fs::create_dir_all(&writable).unwrap();
+        PreviewCache::new(bundled, writable)
+    }
+
+    #[test]
+    fn names_previews_by_voice_and_sentence() {
+        assert_eq!(
+            PreviewCache::bundled_file_name("en-US-Standard-C"),
+            "voice_en-US-Standard-C.mp3"
+        );
+        assert_eq!(
+            PreviewCache::file_name("en-US-Standard-C", BUNDLED_SENTENCE),
+            "en-US-Standard-C.mp3"
+        );
+        let custom = PreviewCache::file_name("en-US-Standard-C", "Testing, one two.");
+        assert!(custom.starts_with("en-US-Standard-C.") && custom.ends_with(".mp3"));
+        assert_eq!(custom.len(), "en-US-Standard-C..mp3".len() + 12);
+        assert_ne!(
+            custom,
+            PreviewCache::file_name("en-US-Standard-C", "Testing, one two three.")
+        );
+    }
+
+    #[test]
+    fn regenerated_previews_shadow_bundled_ones() {
+        let scratch = Scratch::new("shadow");
+        let cache = cache_in(&scratch);
+        fs::write(
+            scratch.0.join("bundled/voice_en-US-Standard-C.mp3"),
+            b"bundled",
+        )
+        .unwrap();
+        let found = cache.locate("en-US-Standard-C", BUNDLED_SENTENCE).unwrap();
+        assert_eq!(fs::read(found).unwrap(), b"bundled");
+        // Only the bundled sentence has a bundled preview
+        assert_eq!(cache.locate("en-US-Standard-C", "Something else."), None);
+
+        cache
+            .store("en-US-Standard-C", BUNDLED_SENTENCE, b"regenerated")
+            .unwrap();
+        let found = cache.locate("en-US-Standard-C", BUNDLED_SENTENCE).unwrap();
+        assert_eq!(fs::read(found).unwrap(), b"regenerated");
+    }
+
+    #[test]
+    fn refuses_voice_names_that_leave_the_cache() {
+        let scratch = Scratch::new("traversal");
+        let cache = cache_in(&scratch);
+        fs::write(scratch.0.join("secret.mp3"), b"secret").unwrap();
+        for name in ["../secret", "../../secret", "/etc/passwd", "a/../../secret"] {
+            assert_eq!(cache.locate(name, BUNDLED_SENTENCE), None, "{}", name);
+            assert_eq!(cache.locate_writable(name, BUNDLED_SENTENCE), None);
+            let refused = cache.store(name, BUNDLED_SENTENCE, b"x").unwrap_err();
+            assert_eq!(refused.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert_eq!(fs::read(scratch.0.join("secret.mp3")).unwrap(), b"secret");
+    }
+}
@@ -0,0 +1,483 @@
+// Export and import of the regenerated voice previews, so editors sharing a
+// voice setup generate each preview once instead of once per machine
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Mutex;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use super::is_voice_name;
+use super::preview::PreviewCache;
+use crate::error::AppError;
+use crate::jobs::{JobKind, JobManager};
+use crate::long_path;
+use crate::paths::roots::{self, AllowedPath, AllowedRoots};
+use crate::paths::ExportedPaths;
+use crate::project::archive::{
+    copy_hashed, corrupt, safe_relative, sibling, ProgressFn, Tracker, MANIFEST_ENTRY,
+};
+use crate::read_only::WriteAccess;
+use crate::settings::SettingsStore;
+
+const FORMAT_VERSION: u32 = 1;
+/// Previews are stored under here, by the file name they have in the cache.
+const PREVIEWS_PREFIX: &str = "previews/";
+/// Total bytes an import may unpack; a full set of previews is a few
+/// hundred MB.
+const MAX_IMPORT_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedPreview {
+    pub voice_name: String,
+    /// Path inside the zip.
+    pub path: String,
+    /// SHA-256 of the sentence the preview speaks; an import skips previews
+    /// of other sentences than the importing machine's.
+    pub sentence_sha256: String,
+    pub generated_at: Option<DateTime<Utc>>,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+/// Stored as `manifest.json`, the last entry of the zip.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreviewCacheManifest {
+    pub format_version: u32,
+    pub app_version: String,
+    pub created_at: DateTime<Utc>,
+    pub previews: Vec<SharedPreview>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedPreview {
+    pub voice_name: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PreviewImportReport {
+    pub imported: Vec<String>,
+    pub skipped: Vec<SkippedPreview>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreviewArchiveOperation {
+    Export,
+    Import,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreviewArchiveState {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// Payload of `preview-cache-archive-progress`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PreviewArchiveProgress {
+    pub job_id: String,
+    pub operation: PreviewArchiveOperation,
+    pub state: PreviewArchiveState,
+    pub processed_bytes: u64,
+    pub total_bytes: u64,
+    pub entry: Option<String>,
+    /// The written zip, for exports.
+    pub output: Option<PathBuf>,
+    /// What was imported and skipped, for imports.
+    pub report: Option<PreviewImportReport>,
+    pub error: Option<AppError>,
+}
+
+fn sentence_sha256(sentence: &str) -> String {
+    format!("{:x}", Sha256::digest(sentence.as_bytes()))
+}
+
+/// The voice a writable preview file belongs to, if `file_name` is one as
+/// `PreviewCache::file_name` names them.
+fn voice_of(file_name: &str) -> Option<&str> {
+    let stem = file_name.strip_suffix(".mp3")?;
+    let voice = stem.split_once('.').map_or(stem, |(voice, _)| voice);
+    is_voice_name(voice).then_some(voice)
+}
+
+/// Writes every regenerated preview speaking its voice's current sentence
+/// into `output_zip`. Previews of sentences no longer configured are left
+/// out, as no machine with these settings would play them.
+pub fn export(
+    cache: &PreviewCache,
+    sentence_for: &dyn Fn(&str) -> String,
+    output: &AllowedPath<roots::Write>,
+    cancel: &AtomicBool,
+    progress: ProgressFn<'_>,
+) -> Result<PathBuf, AppError> {
+    let output_zip = output.path();
+    long_path::check_chosen(output_zip)?;
+    let mut files = Vec::new();
+    if let Ok(read_dir) = fs::read_dir(cache.writable_dir()) {
+        for entry in read_dir.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Some(voice) = voice_of(&name) else {
+                continue;
+            };
+            let sentence = sentence_for(voice);
+            if PreviewCache::file_name(voice, &sentence) != name || !entry.path().is_file() {
+                continue;
+            }
+            files.push((voice.to_string(), sentence, name, entry.path()));
+        }
+    }
+    files.sort_by(|a, b| a.2.cmp(&b.2));
+
+    let partial = sibling(output_zip, ".partial");
+    if let Some(parent) = output_zip.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let result = write_archive(cache, &files, &partial, cancel, progress);
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    fs::rename(&partial, output_zip)?;
+    log::info!(
+        "Exported {} voice previews to {}",
+        files.len(),
+        output_zip.display()
+    );
+    Ok(output_zip.to_path_buf())
+}
+
+fn write_archive(
+    cache: &PreviewCache,
+    files: &[(String, String, String, PathBuf)],
+    partial: &Path,
+    cancel: &AtomicBool,
+    progress: ProgressFn<'_>,
+) -> Result<(), AppError> {
+    let mut total = 0;
+    for (_, _, _, source) in files {
+        total += fs::metadata(source)?.len();
+    }
+    let mut tracker = Tracker::new(total, progress);
+    let mut zip = ZipWriter::new(fs::File::create(partial)?);
+    let mut previews = Vec::with_capacity(files.len());
+    for (voice_name, sentence, name, source) in files {
+        let path = format!("{}{}", PREVIEWS_PREFIX, name);
+        tracker.start(&path);
+        // MP3s don't compress further
+        zip.start_file(
+            path.as_str(),
+            SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+        )?;
+        let (bytes, sha256) = copy_hashed(
+            fs::File::open(source)?,
+            &mut zip,
+            cancel,
+            AppError::from,
+            |n| tracker.advance(n, &path),
+        )?;
+        previews.push(SharedPreview {
+            voice_name: voice_name.clone(),
+            path,
+            sentence_sha256: sentence_sha256(sentence),
+            generated_at: cache.generated_at(voice_name, sentence),
+            bytes,
+            sha256,
+        });
+    }
+
+    let manifest = PreviewCacheManifest {
+        format_version: FORMAT_VERSION,
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: Utc::now(),
+        previews,
+    };
+    let json = serde_json::to_vec_pretty(&manifest).map_err(|e| AppError::Io {
+        message: e.to_string(),
+    })?;
+    zip.start_file(
+        MANIFEST_ENTRY,
+        SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
+    )?;
+    zip.write_all(&json)?;
+    zip.finish()?.sync_all()?;
+    (progress)(total, total, MANIFEST_ENTRY);
+    Ok(())
+}
+
+fn read_manifest(archive: &mut ZipArchive<fs::File>) -> Result<PreviewCacheManifest, AppError> {
+    let mut file = archive
+        .by_name(MANIFEST_ENTRY)
+        .map_err(|e| corrupt(MANIFEST_ENTRY, e))?;
+    let mut json = String::new();
+    file.read_to_string(&mut json)
+        .map_err(|e| corrupt(MANIFEST_ENTRY, e))?;
+    let manifest: PreviewCacheManifest =
+        serde_json::from_str(&json).map_err(|e| corrupt(MANIFEST_ENTRY, e))?;
+    if manifest.format_version > FORMAT_VERSION {
+        return Err(corrupt(
+            MANIFEST_ENTRY,
+            format!(
+                "format version {} is newer than this app supports ({})",
+                manifest.format_version, FORMAT_VERSION
+            ),
+        ));
+    }
+    Ok(manifest)
+}
+
+/// Unpacks previews exported by `export` into the writable preview
+/// directory. Each preview is checked against the manifest and lands under
+/// the name the cache gives its voice and this machine's sentence, never
+/// under a name taken from the archive; previews of another sentence, and
+/// existing ones unless `overwrite`, are skipped.
+pub fn import(
+    cache: &PreviewCache,
+    sentence_for: &dyn Fn(&str) -> String,
+    zip: &AllowedPath<roots::Read>,
+    overwrite: bool,
+    cancel: &AtomicBool,
+    progress: ProgressFn<'_>,
+) -> Result<PreviewImportReport, AppError> {
+    let zip_path = zip.path();
+    let file = fs::File::open(zip_path)?;
+    let mut archive =
+        ZipArchive::new(file).map_err(|e| corrupt(&zip_path.display().to_string(), e))?;
+    for name in archive.file_names() {
+        safe_relative(name)?;
+    }
+    let manifest = read_manifest(&mut archive)?;
+    let total: u64 = manifest.previews.iter().map(|p| p.bytes).sum();
+    if total > MAX_IMPORT_BYTES {
+        return Err(AppError::ArchiveTooLarge {
+            bytes: total,
+            limit: MAX_IMPORT_BYTES,
+        });
+    }
+
+    let mut tracker = Tracker::new(total, progress);
+    let mut report = PreviewImportReport::default();
+    let mut skip = |voice_name: &str, reason: &str| {
+        report.skipped.push(SkippedPreview {
+            voice_name: voice_name.to_string(),
+            reason: reason.to_string(),
+        })
+    };
+    let mut imported = Vec::new();
+    fs::create_dir_all(cache.writable_dir())?;
+    for preview in &manifest.previews {
+        tracker.start(&preview.path);
+        if !is_voice_name(&preview.voice_name) {
+            skip(&preview.voice_name, "not a valid voice name");
+            continue;
+        }
+        let sentence = sentence_for(&preview.voice_name);
+        if preview.sentence_sha256 != sentence_sha256(&sentence) {
+            skip(&preview.voice_name, "speaks another preview sentence");
+            continue;
+        }
+        let target = cache
+            .writable_dir()
+            .join(PreviewCache::file_name(&preview.voice_name, &sentence));
+        if target.exists() && !overwrite {
+            skip(&preview.voice_name, "a preview already exists");
+            continue;
+        }
+
+        let reader = archive.by_name(&preview.path).map_err(|e| match e {
+            zip::result::ZipError::FileNotFound => corrupt(
+                &preview.path,
+                "listed in the manifest but missing from the archive",
+            ),
+            e => corrupt(&preview.path, e),
+        })?;
+        // Read no further than the manifest says, so an entry can't unpack
+        // past the size cap
+        let staged = sibling(&target, ".importing");
+        let copied = copy_hashed(
+            reader.take(preview.bytes + 1),
+            fs::File::create(&staged)?,
+            cancel,
+            |e| corrupt(&preview.path, e),
+            |n| tracker.advance(n, &preview.path),
+        );
+        let (bytes, sha256) = match copied {
+            Ok(copied) => copied,
+            Err(e) => {
+                let _ = fs::remove_file(&staged);
+                return Err(e);
+            }
+        };
+        if bytes != preview.bytes || sha256 != preview.sha256 {
+            let _ = fs::remove_file(&staged);
+            return Err(corrupt(
+                &preview.path,
+                format!(
+                    "checksum mismatch: expected {} ({} bytes), got {} ({} bytes)",
+                    preview.sha256, preview.bytes, sha256, bytes
+                ),
+            ));
+        }
+        fs::rename(&staged, &target)?;
+        imported.push(preview.voice_name.clone());
+    }
+    report.imported = imported;
+    (progress)(total, total, MANIFEST_ENTRY);
+    log::info!(
+        "Imported {} voice previews from {}, skipped {}",
+        report.imported.len(),
+        zip_path.display(),
+        report.skipped.len()
+    );
+    Ok(report)
+}
+
+/// Output of either operation, for the final event.
+enum Finished {
+    Exported(PathBuf),
+    Imported(PreviewImportReport),
+}
+
+/// Runs `work` on a blocking thread as a job of `window`, reporting through
+/// `preview-cache-archive-progress`; returns the job id at once.
+fn spawn_job(
+    app_handle: tauri::AppHandle,
+    window: &tauri::Window,
+    jobs: &JobManager,
+    operation: PreviewArchiveOperation,
+    label: String,
+    work: impl FnOnce(&AtomicBool, ProgressFn<'_>) -> Result<Finished, AppError> + Send + 'static,
+) -> String {
+    use tauri::Manager;
+
+    let job = jobs.register(JobKind::PreviewCacheArchive, label, Some(window.label()));
+    let job_id = job.id().to_string();
+    let id = job_id.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let emitter = job.emitter(app_handle.clone(), "preview-cache-archive-progress");
+        let last = Mutex::new((0u64, 0u64));
+        let result = work(job.cancel_flag(), &|processed, total, entry| {
+            *last.lock().unwrap() = (processed, total);
+            emitter.emit(&PreviewArchiveProgress {
+                job_id: id.clone(),
+                operation,
+                state: PreviewArchiveState::Running,
+                processed_bytes: processed,
+                total_bytes: total,
+                entry: Some(entry.to_string()),
+                output: None,
+                report: None,
+                error: None,
+            });
+        });
+
+        let (processed, total) = *last.lock().unwrap();
+        let mut event = PreviewArchiveProgress {
+            job_id: id,
+            operation,
+            state: PreviewArchiveState::Completed,
+            processed_bytes: processed,
+            total_bytes: total,
+            entry: None,
+            output: None,
+            report: None,
+            error: None,
+        };
+        match result {
+            Ok(Finished::Exported(output)) => {
+                app_handle.state::<ExportedPaths>().record(&output);
+                event.output = Some(output);
+            }
+            Ok(Finished::Imported(report)) => event.report = Some(report),
+            Err(AppError::ArchiveCancelled) => event.state = PreviewArchiveState::Cancelled,
+            Err(e) => {
+                log::error!("Preview cache {:?} failed: {}", operation, e);
+                event.state = PreviewArchiveState::Failed;
+                event.error = Some(e);
+            }
+        }
+        emitter.finish(&event);
+        // Only now, so the job never looks finished before its final event
+        drop(job);
+    });
+    job_id
+}
+
+/// Starts exporting the regenerated previews to `output_zip`; returns the
+/// job id used by progress events and `cancel_preview_cache_archive`.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn export_preview_cache(
+    app_handle: tauri::AppHandle,
+    window: tauri::Window,
+    jobs: tauri::State<'_, JobManager>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    output_zip: String,
+) -> Result<String, AppError> {
+    access.check()?;
+    let output = roots.validate_path(Path::new(&output_zip))?;
+    let cache = crate::cache::preview_cache(&app_handle)?;
+    let handle = app_handle.clone();
+    Ok(spawn_job(
+        app_handle,
+        &window,
+        &jobs,
+        PreviewArchiveOperation::Export,
+        output_zip.clone(),
+        move |cancel, progress| {
+            use tauri::Manager;
+
+            let settings = handle.state::<SettingsStore>();
+            let sentence_for = |voice: &str| crate::voices::sentences::for_voice(&settings, voice);
+            export(&cache, &sentence_for, &output, cancel, progress).map(Finished::Exported)
+        },
+    ))
+}
+
+/// Starts importing previews from `zip_path`; the final event's `report`
+/// lists what was imported and why the rest was skipped.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn import_preview_cache(
+    app_handle: tauri::AppHandle,
+    window: tauri::Window,
+    jobs: tauri::State<'_, JobManager>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    zip_path: String,
+    overwrite: bool,
+) -> Result<String, AppError> {
+    access.check()?;
+    let zip = roots.validate_path(Path::new(&zip_path))?;
+    let cache = crate::cache::preview_cache(&app_handle)?;
+    let handle = app_handle.clone();
+    Ok(spawn_job(
+        app_handle,
+        &window,
+        &jobs,
+        PreviewArchiveOperation::Import,
+        zip_path.clone(),
+        move |cancel, progress| {
+            use tauri::Manager;
+
+            let settings = handle.state::<SettingsStore>();
+            let sentence_for = |voice: &str| crate::voices::sentences::for_voice(&settings, voice);
+            import(&cache, &sentence_for, &zip, overwrite, cancel, progress).map(Finished::Imported)
+        },
+    ))
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn cancel_preview_cache_archive(jobs: tauri::State<'_, JobManager>, job_id: String) -> bool {
+    jobs.cancel(&job_id)
+}
@@ -0,0 +1,345 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{is_cache_key, write_atomic};
+use crate::audio::mp3;
+
+/// Where entries edited outside the app are moved, inside the cache
+/// directory, rather than deleted: the user may want their edit back.
+const MODIFIED_DIR: &str = "modified";
+
+/// Written next to each entry, as `<key>.json`, when it is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntryMeta {
+    pub fingerprint: String,
+    pub voice_name: String,
+    pub characters: usize,
+    /// Of the MP3 as synthesized; an entry no longer matching it was
+    /// edited.
+    pub sha256: String,
+    pub written_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum Lookup {
+    Hit(Vec<u8>),
+    Miss,
+    /// The entry was edited since it was stored. It was moved to
+    /// `moved_to` and counts as a miss.
+    Modified {
+        moved_to: PathBuf,
+    },
+}
+
+/// What `verify_tts_cache` found, by cache key.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CacheAudit {
+    pub entries: usize,
+    pub verified: usize,
+    /// No metadata, e.g. stored before it was written; can't be checked.
+    pub unverified: Vec<String>,
+    /// Valid MP3s that no longer match their metadata hash.
+    pub tampered: Vec<String>,
+    /// Not plausible MP3s; `get` deletes them.
+    pub corrupt: Vec<String>,
+    /// Metadata without its audio, and leftover temp files.
+    pub orphaned: Vec<String>,
+}
+
+/// Synthesized MP3s keyed by the request fingerprint
+/// (`TtsRequestParams::fingerprint`).
+pub struct TtsCache {
+    dir: PathBuf,
+    /// False in read-only mode: every lookup misses and nothing is stored.
+    enabled: bool,
+    /// Whether `get` hashes entries against their metadata; off for caches
+    /// too large to hash on every read.
+    verify: bool,
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+impl TtsCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            enabled: true,
+            verify: true,
+        }
+    }
+
+    /// A cache that passes every request through to the API.
+    pub fn disabled(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            enabled: false,
+            verify: false,
+        }
+    }
+
+    /// Serves entries without checking their hash.
+    pub fn unverified(self) -> Self {
+        Self {
+            verify: false,
+            ..self
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.mp3", key))
+    }
+
+    /// Whether `get` would likely hit for `key`, judged by the file alone;
+    /// for estimates that mustn't read every entry.
+    pub fn contains(&self, key: &str) -> bool {
+        self.enabled && is_cache_key(key) && self.entry_path(key).is_file()
+    }
+
+    fn meta_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    fn checked_path(&self, key: &str) -> io::Result<PathBuf> {
+        if is_cache_key(key) {
+            Ok(self.entry_path(key))
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid TTS cache key {:?}", key),
+            ))
+        }
+    }
+
+    fn read_meta(&self, key: &str) -> Option<EntryMeta> {
+        let contents = fs::read_to_string(self.meta_path(key)).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    /// Returns the cached audio for `key`. Entries that fail MP3 validation are
+    /// deleted and reported as a miss so the caller re-synthesizes them;
+    /// entries edited since they were stored are moved aside.
+    pub fn get(&self, key: &str) -> io::Result<Lookup> {
+        let path = self.checked_path(key)?;
+        if !self.enabled {
+            return Ok(Lookup::Miss);
+        }
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Lookup::Miss),
+            Err(e) => return Err(e),
+        };
+
+        if !mp3::is_plausible_mp3(&bytes) {
+            log::warn!("Discarding corrupt TTS cache entry {}", path.display());
+            fs::remove_file(&path)?;
+            let _ = fs::remove_file(self.meta_path(key));
+            return Ok(Lookup::Miss);
+        }
+        if !self.verify {
+            return Ok(Lookup::Hit(bytes));
+        }
+        match self.read_meta(key) {
+            Some(meta) if meta.sha256 != sha256_hex(&bytes) => {
+                let moved_to = self.move_modified(key, &path)?;
+                log::warn!(
+                    "TTS cache entry {} was edited outside the app, moved to {}",
+                    key,
+                    moved_to.display()
+                );
+                Ok(Lookup::Modified { moved_to })
+            }
+            _ => Ok(Lookup::Hit(bytes)),
+        }
+    }
+
+    fn move_modified(&self, key: &str, path: &Path) -> io::Result<PathBuf> {
+        let dir = self.dir.join(MODIFIED_DIR);
+        fs::create_dir_all(&dir)?;
+        let moved_to = dir.join(format!("{}-{}.mp3", key, Utc::now().format("%Y%m%d%H%M%S")));
+        fs::rename(path, &moved_to)?;
+        fs::remove_file(self.meta_path(key))?;
+        Ok(moved_to)
+    }
+
+    /// Stores `bytes` with the metadata `get` checks them against.
+    pub fn put(
+        &self,
+        key: &str,
+        bytes: &[u8],
+        voice_name: &str,
+        characters: usize,
+    ) -> io::Result<()> {
+        let path = self.checked_path(key)?;
+        if !self.enabled {
+            return Ok(());
+        }
+        write_atomic(&path, bytes)?;
+        let meta = EntryMeta {
+            fingerprint: key.to_string(),
+            voice_name: voice_name.to_string(),
+            characters,
+            sha256: sha256_hex(bytes),
+            written_at: Utc::now(),
+        };
+        let json = serde_json::to_vec_pretty(&meta).map_err(io::Error::other)?;
+        write_atomic(&self.meta_path(key), &json)
+    }
+
+    /// Checks every entry against its metadata, hashing all of them
+    /// whatever `verify` says; changes nothing.
+    pub fn audit(&self) -> io::Result<CacheAudit> {
+        let mut audit = CacheAudit::default();
+        let read_dir = match fs::read_dir(&self.dir) {
+            Ok(read_dir) => read_dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(audit),
+            Err(e) => return Err(e),
+        };
+        for entry in read_dir {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if name.ends_with(".tmp") {
+                audit.orphaned.push(name);
+                continue;
+            }
+            if let Some(key) = name.strip_suffix(".json") {
+                if !self.entry_path(key).is_file() {
+                    audit.orphaned.push(key.to_string());
+                }
+                continue;
+            }
+            let Some(key) = name.strip_suffix(".mp3").filter(|k| is_cache_key(k)) else {
+                continue;
+            };
+            audit.entries += 1;
+            let bytes = fs::read(&path)?;
+            if !mp3::is_plausible_mp3(&bytes) {
+                audit.corrupt.push(key.to_string());
+                continue;
+            }
+            match self.read_meta(key) {
+                Some(meta) if meta.sha256 == sha256_hex(&bytes) => audit.verified += 1,
+                Some(_) => audit.tampered.push(key.to_string()),
+                None => audit.unverified.push(key.to_string()),
+            }
+        }
+        Ok(audit)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    /// One MPEG-1 Layer III frame, 128 kbps at 44.1 kHz.
+    fn mp3() -> Vec<u8> {
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x00];
+        frame.resize(417, 0);
+        frame
+    }
+
+    #[test]
+    fn serves_what_it_stored() {
+        let scratch = Scratch::new("hit");
+        let cache = TtsCache::new(scratch.0.clone());
+        assert!(matches!(cache.get("key").unwrap(), Lookup::Miss));
+        cache.put("key", &mp3(), "en-US-Standard-C", 5).unwrap();
+        assert!(cache.contains("key"));
+        assert!(matches!(cache.get("key").unwrap(), Lookup::Hit(bytes) if bytes == mp3()));
+    }
+
+    #[test]
+    fn deletes_entries_that_arent_mp3s() {
+        let scratch = Scratch::new("corrupt");
+        let cache = TtsCache::new(scratch.0.clone());
+        cache.put("key", &mp3(), "en-US-Standard-C", 5).unwrap();
+        fs::write(cache.entry_path("key"), b"<html>error</html>").unwrap();
+        assert_eq!(cache.audit().unwrap().corrupt, ["key"]);
+        assert!(matches!(cache.get("key").unwrap(), Lookup::Miss));
+        assert!(!cache.entry_path("key").exists());
+        assert!(!cache.meta_path("key").exists());
+    }
+
+    #[test]
+    fn moves_edited_entries_aside() {
+        let scratch = Scratch::new("modified");
+        let cache = TtsCache::new(scratch.0.clone());
+        cache.put("key", &mp3(), "en-US-Standard-C", 5).unwrap();
+        let mut edited = mp3();
+        edited.extend_from_slice(&mp3());
+        fs::write(cache.entry_path("key"), &edited).unwrap();
+        assert_eq!(cache.audit().unwrap().tampered, ["key"]);
+
+        let Lookup::Modified { moved_to } = cache.get("key").unwrap() else {
+            panic!("an edited entry was served");
+        };
+        assert_eq!(fs::read(&moved_to).unwrap(), edited);
+        assert!(moved_to.starts_with(scratch.0.join(MODIFIED_DIR)));
+        assert!(!cache.contains("key"));
+    }
+
+    #[test]
+    fn unverified_caches_serve_edited_entries() {
+        let scratch = Scratch::new("unverified");
+        let cache = TtsCache::new(scratch.0.clone()).unverified();
+        cache.put("key", &mp3(), "en-US-Standard-C", 5).unwrap();
+        let mut edited = mp3();
+        edited.extend_from_slice(&mp3());
+        fs::write(cache.entry_path("key"), &edited).unwrap();
+        assert!(matches!(cache.get("key").unwrap(), Lookup::Hit(bytes) if bytes == edited));
+    }
+
+    #[test]
+    fn disabled_caches_store_nothing() {
+        let scratch = Scratch::new("disabled");
+        let cache = TtsCache::disabled(scratch.0.clone());
+        cache.put("key", &mp3(), "en-US-Standard-C", 5).unwrap();
+        assert!(!cache.entry_path("key").exists());
+        assert!(matches!(cache.get("key").unwrap(), Lookup::Miss));
+    }
+
+    #[test]
+    fn refuses_keys_that_could_name_other_paths() {
+        let scratch = Scratch::new("keys");
+        let cache = TtsCache::new(scratch.0.clone());
+        for key in ["../escape", "a/b", "", "key.mp3"] {
+            assert!(cache.get(key).is_err(), "{:?}", key);
+            assert!(cache.put(key, &mp3(), "en-US-Standard-C", 5).is_err());
+        }
+    }
+
+    #[test]
+    fn audits_each_kind_of_entry() {
+        let scratch = Scratch::new("audit");
+        let cache = TtsCache::new(scratch.0.clone());
+        cache
+            .put("verified", &mp3(), "en-US-Standard-C", 5)
+            .unwrap();
+        fs::write(cache.entry_path("unverified"), mp3()).unwrap();
+        fs::write(cache.meta_path("orphan"), "{}").unwrap();
+        fs::write(scratch.0.join("left.mp3.tmp"), b"").unwrap();
+
+        let audit = cache.audit().unwrap();
+        assert_eq!(audit.entries, 2);
+        assert_eq!(audit.verified, 1);
+        assert_eq!(audit.unverified, ["unverified"]);
+        let mut orphaned = audit.orphaned;
+        orphaned.sort();
+        assert_eq!(orphaned, ["left.mp3.tmp", "orphan"]);
+    }
+}
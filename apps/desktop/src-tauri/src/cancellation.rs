@@ -0,0 +1,358 @@
+// Caller-named cancellation: a command that takes a `cancel_token` registers
+// it here, and `cancel_operation` stops whichever command holds it
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+use crate::error::AppError;
+
+/// Commands taking a `cancel_token`, and where they stop. Cancelled ones
+/// return `Cancelled` unless noted.
+const CANCELLABLE_COMMANDS: &[(&str, &str)] = &[
+    (
+        "list_google_voices",
+        "before the request, or while waiting for it; the cached list is kept",
+    ),
+    ("list_google_voices_grouped", "as list_google_voices"),
+    (
+        "prefetch_previews",
+        "before each preview is generated; finished previews stay cached",
+    ),
+    (
+        "get_waveforms_batch",
+        "before each file is decoded; finished peak files stay cached",
+    ),
+    (
+        "concat_audio",
+        "before each input is read and before the output is written; nothing is written",
+    ),
+    (
+        "export_project_archive",
+        "between chunks of each entry; the job ends cancelled in project-archive-progress and no zip is left",
+    ),
+    (
+        "import_project_archive",
+        "between chunks of each entry; the job ends cancelled in project-archive-progress and nothing is imported",
+    ),
+    (
+        "synthesize_batch",
+        "after the segment being synthesized; returns BatchCancelled with the manifest to resume from",
+    ),
+    ("resume_batch", "as synthesize_batch"),
+    ("resynthesize_segments", "as synthesize_batch"),
+    (
+        "resume_job",
+        "a batch as resume_batch; an audio conform is stopped by cancel_audio_conform instead",
+    ),
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OperationInfo {
+    pub token_id: String,
+    pub command: &'static str,
+    pub started_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CancellableCommand {
+    pub command: &'static str,
+    /// Where the command checks for cancellation and what it leaves behind.
+    pub stops: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CancellableOperations {
+    pub commands: Vec<CancellableCommand>,
+    pub running: Vec<OperationInfo>,
+}
+
+struct Registered {
+    info: OperationInfo,
+    flag: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+}
+
+type Operations = Arc<Mutex<HashMap<String, Registered>>>;
+
+/// Managed state holding the token of every cancellable command in flight.
+#[derive(Default)]
+pub struct CancellationRegistry {
+    operations: Operations,
+}
+
+/// Held by a running command. Dropping it, however the command ends, frees
+/// the token id for reuse.
+pub struct CancellationToken {
+    id: Option<String>,
+    command: &'static str,
+    flag: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+    operations: Operations,
+}
+
+fn valid_token_id(id: &str) -> bool {
+    (1..=128).contains(&id.len())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl CancellationRegistry {
+    /// A token for `command`; without a `token_id` it can never be
+    /// cancelled, which keeps the token optional for callers.
+    pub fn register(
+        &self,
+        token_id: Option<String>,
+        command: &'static str,
+    ) -> Result<CancellationToken, AppError> {
+        self.register_shared(token_id, command, Arc::new(AtomicBool::new(false)))
+    }
+
+    /// `register` with the command's own cancel flag, such as a job's, so
+    /// both ways of cancelling set the same flag.
+    pub fn register_shared(
+        &self,
+        token_id: Option<String>,
+        command: &'static str,
+        flag: Arc<AtomicBool>,
+    ) -> Result<CancellationToken, AppError> {
+        let notify = Arc::new(Notify::new());
+        if let Some(id) = &token_id {
+            if !valid_token_id(id) {
+                return Err(AppError::InvalidCancelToken {
+                    token_id: id.clone(),
+                    reason: "use 1 to 128 ASCII letters, digits, '-' or '_'".to_string(),
+                });
+            }
+            let mut operations = self.operations.lock().unwrap();
+            if operations.contains_key(id) {
+                return Err(AppError::InvalidCancelToken {
+                    token_id: id.clone(),
+                    reason: "already held by a running command".to_string(),
+                });
+            }
+            let info = OperationInfo {
+                token_id: id.clone(),
+                command,
+                started_at: Utc::now(),
+            };
+            operations.insert(
+                id.clone(),
+                Registered {
+                    info,
+                    flag: flag.clone(),
+                    notify: notify.clone(),
+                },
+            );
+        }
+        Ok(CancellationToken {
+            id: token_id,
+            command,
+            flag,
+            notify,
+            operations: self.operations.clone(),
+        })
+    }
+
+    /// Returns false if no running command holds `token_id`.
+    pub fn cancel(&self, token_id: &str) -> bool {
+        match self.operations.lock().unwrap().get(token_id) {
+            Some(operation) => {
+                operation.flag.store(true, Ordering::Relaxed);
+                operation.notify.notify_waiters();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn running(&self) -> Vec<OperationInfo> {
+        let mut running: Vec<OperationInfo> = self
+            .operations
+            .lock()
+            .unwrap()
+            .values()
+            .map(|o| o.info.clone())
+            .collect();
+        running.sort_by_key(|o| o.started_at);
+        running
+    }
+}
+
+impl CancellationToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+
+    pub fn cancelled_error(&self) -> AppError {
+        AppError::Cancelled {
+            operation: self.command.to_string(),
+        }
+    }
+
+    /// `Cancelled` once cancelled; called between units of work.
+    pub fn check(&self) -> Result<(), AppError> {
+        if self.is_cancelled() {
+            Err(self.cancelled_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Runs `work` until it finishes or the token is cancelled, whichever is
+    /// first. A cancelled `work` is dropped, so it must not be half way
+    /// through anything that needs cleaning up.
+    pub async fn run<T>(
+        &self,
+        work: impl Future<Output = Result<T, AppError>>,
+    ) -> Result<T, AppError> {
+        let cancelled = self.notify.notified();
+        tokio::pin!(cancelled);
+        // Listening before the check, so a cancel in between still wakes us
+        cancelled.as_mut().enable();
+        self.check()?;
+        tokio::select! {
+            result = work => result,
+            _ = cancelled => Err(self.cancelled_error()),
+        }
+    }
+}
+
+impl Drop for CancellationToken {
+    fn drop(&mut self) {
+        if let Some(id) = &self.id {
+            self.operations.lock().unwrap().remove(id);
+        }
+    }
+}
+
+/// Cancels the command holding `token_id`; false if none does, e.g. because
+/// it already finished.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn cancel_operation(
+    registry: tauri::State<'_, CancellationRegistry>,
+    token_id: String,
+) -> bool {
+    registry.cancel(&token_id)
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn list_cancellable_operations(
+    registry: tauri::State<'_, CancellationRegistry>,
+) -> CancellableOperations {
+    CancellableOperations {
+        commands: CANCELLABLE_COMMANDS
+            .iter()
+            .map(|&(command, stops)| CancellableCommand { command, stops })
+            .collect(),
+        running: registry.running(),
+    }
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![cancel_operation, list_cancellable_operations]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Every command with a `cancel_token` argument, from the source.
+    fn commands_taking_a_token() -> Vec<String> {
+        let mut found = Vec::new();
+        let mut dirs = vec![PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src"))];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                if path.extension().is_none_or(|e| e != "rs") {
+                    continue;
+                }
+                let source = fs::read_to_string(&path).unwrap();
+                for command in source.split("#[tauri::command]\n").skip(1) {
+                    let signature = &command[..command.find('{').unwrap()];
+                    if !signature.contains("cancel_token:") {
+                        continue;
+                    }
+                    let name = signature.split("fn ").nth(1).unwrap();
+                    found.push(name[..name.find(['(', '<']).unwrap()].to_string());
+                }
+            }
+        }
+        found.sort_unstable();
+        found
+    }
+
+    #[test]
+    fn every_command_taking_a_token_is_listed() {
+        let mut listed: Vec<String> = CANCELLABLE_COMMANDS
+            .iter()
+            .map(|(command, _)| command.to_string())
+            .collect();
+        listed.sort_unstable();
+        assert_eq!(listed, commands_taking_a_token());
+    }
+
+    #[tokio::test]
+    async fn a_cancel_before_the_request_sends_nothing() {
+        let registry = CancellationRegistry::default();
+        let token = registry
+            .register(Some("voices".into()), "list_google_voices")
+            .unwrap();
+        assert!(registry.cancel("voices"));
+        let sent = AtomicUsize::new(0);
+        let result = token
+            .run(async {
+                sent.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+            .await;
+        assert!(
+            matches!(result, Err(AppError::Cancelled { operation }) if operation == "list_google_voices")
+        );
+        assert_eq!(sent.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn a_cancel_while_waiting_drops_the_request() {
+        let registry = Arc::new(CancellationRegistry::default());
+        let token = registry
+            .register(Some("voices".into()), "list_google_voices")
+            .unwrap();
+        let canceller = registry.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            canceller.cancel("voices");
+        });
+        // A response that never comes
+        let result = token.run(std::future::pending::<Result<(), _>>()).await;
+        assert!(matches!(result, Err(AppError::Cancelled { .. })));
+    }
+
+    #[test]
+    fn a_token_id_is_free_again_once_its_command_ends() {
+        let registry = CancellationRegistry::default();
+        let token = registry.register(Some("a".into()), "concat_audio").unwrap();
+        assert!(registry.register(Some("a".into()), "concat_audio").is_err());
+        assert_eq!(registry.running().len(), 1);
+        drop(token);
+        assert!(!registry.cancel("a"));
+        assert!(registry.register(Some("a".into()), "concat_audio").is_ok());
+        assert!(registry
+            .register(Some("no spaces".into()), "concat_audio")
+            .is_err());
+    }
+}
@@ -0,0 +1,723 @@
+// Command line entry point for headless synthesis, e.g. on a build server:
+// lists voices, synthesizes text or a batch manifest and clears the speech
+// cache with the app's own settings, credentials, caches and usage ledger,
+// without creating a window, so no display server is needed. The usage
+// journal and the cap on synthesis calls are shared with a running app, so
+// the spend caps count both, and the CLI has no way past them
+use gcloud_sdk::google::cloud::texttospeech::v1::SsmlVoiceGender;
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::Ordering;
+
+use crate::batch::{self, BatchManifest, SegmentStatus};
+use crate::cache::tts::TtsCache;
+use crate::error::AppError;
+use crate::jobs::{JobKind, JobManager};
+use crate::paths::roots::AllowedRoots;
+use crate::paths::AppPaths;
+use crate::read_only::WriteAccess;
+use crate::settings::SettingsStore;
+use crate::trash::Trash;
+use crate::tts::fingerprint::TtsRequestParams;
+use crate::tts::history::SynthesisHistory;
+use crate::tts::lanes::Lane;
+use crate::tts::placeholders::{self, Placeholders};
+use crate::tts::recording::Recorder;
+use crate::tts::synthesis;
+use crate::tts::usage::UsageLedger;
+use crate::tts::{voice_list, TtsService};
+use crate::voices::capabilities::CapabilityStore;
+use crate::voices::{name, remap};
+
+const USAGE: &str = "\
+usage: sclip-cli [--json] <command>
+
+commands:
+  voices list [--language CODE] [--refresh]
+  synthesize --voice NAME [--language CODE] (--text TEXT | --file PATH) --out PATH
+  batch --manifest PATH
+  cache clear
+
+--json prints results, and errors to stderr, as JSON.
+
+exit codes: 0 success, 1 other failure, 2 invalid arguments or input,
+3 credentials refused, 4 quota, budget or spend cap exhausted";
+
+/// Exit codes beside 0, so scripts can tell a bad call from a refused one.
+const EXIT_FAILURE: u8 = 1;
+const EXIT_INPUT: u8 = 2;
+const EXIT_AUTH: u8 = 3;
+const EXIT_QUOTA: u8 = 4;
+
+enum Text {
+    Inline(String),
+    File(PathBuf),
+}
+
+enum Command {
+    VoicesList {
+        language: Option<String>,
+        refresh: bool,
+    },
+    Synthesize {
+        voice: String,
+        language: Option<String>,
+        text: Text,
+        out: PathBuf,
+    },
+    Batch {
+        manifest: PathBuf,
+    },
+    CacheClear,
+}
+
+struct Args {
+    json: bool,
+    command: Command,
+}
+
+/// Arguments that don't make a command; printed with the usage.
+struct UsageError(String);
+
+fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, UsageError> {
+    let mut json = false;
+    let mut words = Vec::new();
+    let mut options = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--refresh" => options.push((arg, None)),
+            flag if flag.starts_with("--") => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| UsageError(format!("{} needs a value", flag)))?;
+                options.push((arg, Some(value)));
+            }
+            _ => words.push(arg),
+        }
+    }
+    let mut take = |flag: &str| {
+        options
+            .iter()
+            .position(|(f, _)| f == flag)
+            .map(|at| options.remove(at).1)
+    };
+    let required = |value: Option<Option<String>>, flag: &str| {
+        value
+            .flatten()
+            .ok_or_else(|| UsageError(format!("{} is required", flag)))
+    };
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    let command = match words.as_slice() {
+        ["voices", "list"] => Command::VoicesList {
+            language: take("--language").flatten(),
+            refresh: take("--refresh").is_some(),
+        },
+        ["synthesize"] => {
+            let text = match (take("--text").flatten(), take("--file").flatten()) {
+                (Some(text), None) => Text::Inline(text),
+                (None, Some(path)) => Text::File(PathBuf::from(path)),
+                _ => return Err(UsageError("give one of --text and --file".to_string())),
+            };
+            Command::Synthesize {
+                voice: required(take("--voice"), "--voice")?,
+                language: take("--language").flatten(),
+                text,
+                out: PathBuf::from(required(take("--out"), "--out")?),
+            }
+        }
+        ["batch"] => Command::Batch {
+            manifest: PathBuf::from(required(take("--manifest"), "--manifest")?),
+        },
+        ["cache", "clear"] => Command::CacheClear,
+        [] => return Err(UsageError("no command given".to_string())),
+        words => return Err(UsageError(format!("unknown command: {}", words.join(" ")))),
+    };
+    if let Some((flag, _)) = options.first() {
+        return Err(UsageError(format!("unexpected option {}", flag)));
+    }
+    Ok(Args { json, command })
+}
+
+fn exit_code(error: &AppError) -> u8 {
+    match error {
+        AppError::BatchFailed { cause, .. } => exit_code(cause),
+        AppError::Credentials { .. }
+        | AppError::ProfileNotFound { .. }
+        | AppError::TtsUnauthorized { .. }
+        | AppError::QuotaProjectDenied { .. } => EXIT_AUTH,
+        AppError::TtsQuotaExceeded { .. }
+        | AppError::BudgetExceeded { .. }
+        | AppError::SpendCapExceeded { .. } => EXIT_QUOTA,
+        AppError::EmptyInput { .. }
+        | AppError::InvalidVoiceName { .. }
+        | AppError::InvalidQuotaProject { .. }
+        | AppError::InvalidSynthesisPlan { .. }
+        | AppError::InvalidManifest { .. }
+        | AppError::InvalidTemplate { .. }
+        | AppError::InvalidAudioRequest { .. }
+        | AppError::VoiceLanguageMismatch { .. }
+        | AppError::PlaceholdersPresent { .. }
+        | AppError::VoiceCapabilities { .. }
+        | AppError::PathTooLong { .. } => EXIT_INPUT,
+        _ => EXIT_FAILURE,
+    }
+}
+
+/// What the desktop app would load at startup, minus the windows and the
+/// backend.
+struct Context {
+    paths: AppPaths,
+    access: WriteAccess,
+    settings: SettingsStore,
+    tts: TtsService,
+}
+
+impl Context {
+    fn load() -> Result<Self, AppError> {
+        Ok(Self::in_paths(
+            AppPaths::headless()?,
+            crate::tts::endpoint_override(),
+        ))
+    }
+
+    /// The state in `paths`, synthesizing at `endpoint` instead of Google's
+    /// if given.
+    fn in_paths(paths: AppPaths, endpoint: Option<String>) -> Self {
+        let access = WriteAccess::probe(&paths);
+        let settings = SettingsStore::load(paths.config_dir.join("settings.json"));
+        settings.set_read_only(access.is_read_only());
+        let defaults = settings.get();
+        let tts = TtsService::new(
+            crate::credentials::startup_credentials(&defaults),
+            defaults.quota_project.clone(),
+            Recorder::new(
+                paths.data_dir.join("tts_recordings"),
+                defaults.tts_recording,
+            ),
+            UsageLedger::load(paths.data_dir.join("usage.json")),
+            |_| {},
+        )
+        .with_voice_list(paths.data_dir.join(voice_list::FILE_NAME))
+        .with_shared_slots(paths.data_dir.join(crate::tts::SLOTS_DIR));
+        tts.set_ssml_policy(defaults.ssml_policy);
+        tts.set_voice_remap(remap::VoiceRemap::from_settings(&defaults));
+        tts.spend().set_caps(defaults.spend_caps);
+        let tts = match endpoint {
+            Some(endpoint) => tts.with_endpoint(endpoint),
+            None => tts,
+        };
+        Self {
+            paths,
+            access,
+            settings,
+            tts,
+        }
+    }
+
+    fn cache(&self) -> TtsCache {
+        crate::cache::tts_cache_in(
+            &self.paths.cache_dir,
+            self.access.is_read_only(),
+            self.settings.get().verify_tts_cache,
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct VoiceRow {
+    name: String,
+    language_codes: Vec<String>,
+    gender: String,
+    natural_sample_rate_hertz: i32,
+}
+
+#[derive(Serialize)]
+struct VoiceListing {
+    fetched_at: chrono::DateTime<chrono::Utc>,
+    from_cache: bool,
+    voices: Vec<VoiceRow>,
+}
+
+#[derive(Serialize)]
+struct Synthesized {
+    output_path: PathBuf,
+    voice_name: String,
+    language_code: String,
+    language_corrected: bool,
+    /// Came from the cache, so nothing was billed.
+    cached: bool,
+    billable_characters: usize,
+    bytes: usize,
+}
+
+#[derive(Serialize)]
+struct CacheCleared {
+    /// Trash entry the cache was moved to; `None` if it was already empty.
+    trash_entry_id: Option<String>,
+    bytes: u64,
+}
+
+/// A result, as JSON or as the lines a person reads.
+fn print<T: Serialize>(json: bool, value: &T, human: impl FnOnce(&T)) {
+    if json {
+        match serde_json::to_string_pretty(value) {
+            Ok(text) => println!("{}", text),
+            Err(e) => eprintln!("error: can't print the result: {}", e),
+        }
+    } else {
+        human(value);
+    }
+}
+
+async fn voices_list(
+    context: &Context,
+    json: bool,
+    language: Option<String>,
+    refresh: bool,
+) -> Result<(), AppError> {
+    let list = context.tts.voice_list(refresh, None).await?;
+    let mut voices: Vec<VoiceRow> = list
+        .voices
+        .into_iter()
+        .filter(|v| {
+            language.as_ref().is_none_or(|language| {
+                v.language_codes
+                    .iter()
+                    .any(|c| c.eq_ignore_ascii_case(language))
+            })
+        })
+        .map(|v| VoiceRow {
+            gender: SsmlVoiceGender::try_from(v.ssml_gender)
+                .map(|g| format!("{:?}", g))
+                .unwrap_or_else(|_| "Neutral".to_string()),
+            name: v.name,
+            language_codes: v.language_codes,
+            natural_sample_rate_hertz: v.natural_sample_rate_hertz,
+        })
+        .collect();
+    voices.sort_by(|a, b| a.name.cmp(&b.name));
+    let listing = VoiceListing {
+        fetched_at: list.fetched_at,
+        from_cache: list.from_cache,
+        voices,
+    };
+    print(json, &listing, |listing| {
+        for voice in &listing.voices {
+            println!(
+                "{}\t{}\t{}",
+                voice.name,
+                voice.language_codes.join(","),
+                voice.gender
+            );
+        }
+        if listing.from_cache {
+            eprintln!(
+                "voice list stored {}; --refresh fetches a new one",
+                listing.fetched_at
+            );
+        }
+    });
+    Ok(())
+}
+
+/// `synthesize_speech` as the app runs it, writing the MP3 to `out`; the
+/// synthesis history is the app's and isn't added to.
+async fn synthesize(
+    context: &Context,
+    json: bool,
+    voice: String,
+    language: Option<String>,
+    text: Text,
+    out: PathBuf,
+) -> Result<(), AppError> {
+    let text = match text {
+        Text::Inline(text) => text,
+        Text::File(path) => fs::read_to_string(&path)?,
+    };
+    let defaults = context.settings.get();
+    let voice_name = match context.tts.voice_remap().resolve(&voice) {
+        Some(renamed) => {
+            eprintln!("voice {} is retired, using {}", voice, renamed);
+            renamed
+        }
+        None => voice,
+    };
+    let placeholders = Placeholders::new(&defaults.placeholders);
+    for found in placeholders::refuse_strict(&placeholders, &text)? {
+        eprintln!(
+            "warning: placeholder {} at character {}",
+            found.text, found.offset
+        );
+    }
+    crate::tts::speakable::check(&text)?;
+    let language = language.unwrap_or_else(|| name::voice_language_code(&voice_name));
+    let (language_code, language_corrected) =
+        synthesis::resolve_language(&context.tts, &voice_name, language, false).await?;
+    let input = synthesis::speech_input(
+        text.clone(),
+        defaults.paragraph_pause_ms,
+        defaults.line_pause_ms,
+        defaults.breathing_room,
+    );
+    let capabilities =
+        CapabilityStore::load(context.paths.data_dir.join("voice_capabilities.json"));
+    let input = synthesis::check_and_strip(
+        &context.tts,
+        &capabilities,
+        defaults.unsupported_features,
+        &placeholders,
+        &voice_name,
+        &language_code,
+        &text,
+        input,
+    )
+    .await?;
+    let billable_characters =
+        TtsRequestParams::speech(&voice_name, &language_code, &input).billable_characters();
+    let speech = synthesis::synthesize_cached_hit(
+        &context.tts,
+        &context.cache(),
+        &voice_name,
+        &language_code,
+        input,
+        None,
+        Lane::Interactive,
+        None,
+    )
+    .await?;
+    crate::cache::write_atomic(&out, &speech.audio)?;
+    let result = Synthesized {
+        output_path: out,
+        voice_name,
+        language_code,
+        language_corrected,
+        cached: speech.hit,
+        billable_characters,
+        bytes: speech.audio.len(),
+    };
+    print(json, &result, |result| {
+        println!(
+            "{} ({} bytes, {} as {}{})",
+            result.output_path.display(),
+            result.bytes,
+            result.voice_name,
+            result.language_code,
+            if result.cached { ", cached" } else { "" }
+        );
+    });
+    Ok(())
+}
+
+/// `resume_batch` on `manifest_path` without new segments. Ctrl-C stops it
+/// between segments, leaving the manifest resumable.
+async fn resume_manifest(
+    context: &Context,
+    json: bool,
+    manifest_path: PathBuf,
+) -> Result<(), AppError> {
+    context.access.check()?;
+    let mut manifest = BatchManifest::load(&manifest_path)?;
+    let jobs = JobManager::default();
+    let job = jobs.register(JobKind::Batch, manifest_path.display().to_string(), None);
+    let cancel = job.cancel_handle();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("stopping after the current segment");
+            cancel.store(true, Ordering::Relaxed);
+        }
+    });
+    batch::continue_batch(
+        None,
+        &context.tts,
+        &context.cache(),
+        &context.settings.get().cost_saver,
+        &mut manifest,
+        &manifest_path,
+        &job,
+        None,
+        &context.settings.get().audio_quality,
+    )
+    .await?;
+    print(json, &manifest, |manifest: &BatchManifest| {
+        let count = |status| {
+            manifest
+                .segments
+                .iter()
+                .filter(|s| s.status == status)
+                .count()
+        };
+        println!(
+            "{} segment(s) completed, {} skipped, into {}",
+            count(SegmentStatus::Completed),
+            count(SegmentStatus::Skipped),
+            manifest.output_dir.display()
+        );
+    });
+    Ok(())
+}
+
+/// `clear_tts_cache`, into the app's trash, keeping what its history lists.
+fn cache_clear(context: &Context, json: bool) -> Result<(), AppError> {
+    context.access.check()?;
+    let entry = crate::cache::discard_tts_cache(
+        &context.cache(),
+        &Trash::load(context.paths.trash_dir.clone()),
+        &AllowedRoots::new(&context.paths, None),
+        &SynthesisHistory::load(context.paths.data_dir.join("synthesis_history.json")),
+    )?;
+    let cleared = CacheCleared {
+        bytes: entry.as_ref().map_or(0, |e| e.bytes),
+        trash_entry_id: entry.map(|e| e.id),
+    };
+    print(json, &cleared, |cleared| match &cleared.trash_entry_id {
+        Some(id) => println!("moved {} bytes to the trash ({})", cleared.bytes, id),
+        None => println!("the cache is already empty"),
+    });
+    Ok(())
+}
+
+async fn run(context: &Context, args: Args) -> Result<(), AppError> {
+    match args.command {
+        Command::VoicesList { language, refresh } => {
+            voices_list(context, args.json, language, refresh).await
+        }
+        Command::Synthesize {
+            voice,
+            language,
+            text,
+            out,
+        } => synthesize(context, args.json, voice, language, text, out).await,
+        Command::Batch { manifest } => resume_manifest(context, args.json, manifest).await,
+        Command::CacheClear => cache_clear(context, args.json),
+    }
+}
+
+/// Runs the command in the process arguments; what `sclip-cli` calls.
+pub fn main() -> ExitCode {
+    let args = match parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(UsageError(message)) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            return ExitCode::from(EXIT_INPUT);
+        }
+    };
+    crate::install_crypto_provider();
+    let json = args.json;
+    let result = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime.block_on(async { run(&Context::load()?, args).await }),
+        Err(e) => Err(AppError::from(e)),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            match serde_json::to_string(&e).ok().filter(|_| json) {
+                Some(text) => eprintln!("{}", text),
+                None => eprintln!("error: {}", e),
+            }
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+    use crate::tts::mock::{self, MockTts};
+    use crate::tts::spend::SpendCap;
+    use std::path::Path;
+
+    fn args(line: &str) -> Result<Args, UsageError> {
+        parse(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn parses_synthesize() {
+        let parsed = args("--json synthesize --voice en-US-Neural2-A --text hi --out a.mp3");
+        let Ok(Args {
+            json: true,
+            command: Command::Synthesize { voice, out, .. },
+        }) = parsed
+        else {
+            panic!("synthesize wasn't parsed");
+        };
+        assert_eq!(voice, "en-US-Neural2-A");
+        assert_eq!(out, PathBuf::from("a.mp3"));
+    }
+
+    #[test]
+    fn refuses_bad_arguments() {
+        for line in [
+            "",
+            "voices",
+            "synthesize --voice a --out b",
+            "synthesize --voice a --text t --file f --out b",
+            "batch",
+            "cache clear --refresh",
+            "voices list --language",
+        ] {
+            assert!(args(line).is_err(), "{:?} was accepted", line);
+        }
+    }
+
+    #[test]
+    fn spend_caps_exit_as_quota() {
+        let capped = AppError::SpendCapExceeded {
+            cap: SpendCap::Daily,
+            limit: 10,
+            attempted: 20,
+            used: 0,
+        };
+        assert_eq!(exit_code(&capped), EXIT_QUOTA);
+        let batch = AppError::BatchFailed {
+            manifest_path: "batch.json".to_string(),
+            segment_id: "s1".to_string(),
+            message: capped.to_string(),
+            cause: Box::new(capped),
+        };
+        assert_eq!(exit_code(&batch), EXIT_QUOTA);
+    }
+
+    /// What `sclip-cli` would load from `scratch`, synthesizing against
+    /// `server`.
+    fn context(scratch: &Scratch, server: &MockTts) -> Context {
+        Context::in_paths(scratch.app_paths(), Some(server.endpoint()))
+    }
+
+    /// A manifest of pending `(id, voice, text)` segments as a script would
+    /// write it, into `output_dir`.
+    fn write_manifest(path: &Path, output_dir: &Path, segments: &[(&str, &str, &str)]) {
+        let segments: Vec<serde_json::Value> = segments
+            .iter()
+            .enumerate()
+            .map(|(i, (id, voice, text))| {
+                let file_name = format!("{:03}.mp3", i + 1);
+                serde_json::json!({
+                    "id": id,
+                    "index": i + 1,
+                    "voice_name": voice,
+                    "language_code": "en-US",
+                    "text": text,
+                    "path": output_dir.join(&file_name),
+                    "file_name": file_name,
+                    "status": "pending",
+                    "bytes": 0,
+                    "request_hash": "",
+                })
+            })
+            .collect();
+        let manifest = serde_json::json!({
+            "output_dir": output_dir,
+            "naming_template": "{index}",
+            "paragraph_pause_ms": 800,
+            "line_pause_ms": 0,
+            "segments": segments,
+        });
+        fs::write(path, serde_json::to_vec_pretty(&manifest).unwrap()).unwrap();
+    }
+
+    async fn batch(context: &Context, manifest: &Path) -> Result<(), AppError> {
+        let line = ["batch", "--manifest", &manifest.display().to_string()];
+        let Ok(args) = parse(line.map(String::from)) else {
+            panic!("batch wasn't parsed");
+        };
+        run(context, args).await
+    }
+
+    fn statuses(manifest: &Path) -> Vec<SegmentStatus> {
+        let manifest = BatchManifest::load(manifest).unwrap();
+        manifest.segments.iter().map(|s| s.status).collect()
+    }
+
+    #[tokio::test]
+    async fn batch_synthesizes_every_segment_of_a_manifest() {
+        let server = mock::start_mock_tts().await.unwrap();
+        let scratch = Scratch::new("cli-batch");
+        let context = context(&scratch, &server);
+        let output_dir = scratch.0.join("out");
+        let manifest = scratch.0.join("batch_manifest.json");
+        write_manifest(
+            &manifest,
+            &output_dir,
+            &[
+                ("intro", "en-US-Mock-A", "Welcome to the show."),
+                ("outro", "en-US-Mock-B", "Thanks for listening."),
+            ],
+        );
+
+        batch(&context, &manifest).await.unwrap();
+        assert_eq!(
+            statuses(&manifest),
+            [SegmentStatus::Completed, SegmentStatus::Completed]
+        );
+        for file in ["001.mp3", "002.mp3"] {
+            assert!(fs::metadata(output_dir.join(file)).unwrap().len() > 0);
+        }
+        assert_eq!(server.synthesize_calls(), 2);
+        // Billed into the app's own usage ledger
+        let totals = UsageLedger::load(scratch.0.join("data/usage.json")).totals();
+        let months = || totals.values().flat_map(|months| months.values());
+        assert_eq!(months().map(|m| m.requests).sum::<u64>(), 2);
+        let texts = "Welcome to the show.".len() + "Thanks for listening.".len();
+        assert!(months().map(|m| m.characters).sum::<u64>() >= texts as u64);
+
+        // A finished batch has nothing left to synthesize
+        batch(&context, &manifest).await.unwrap();
+        assert_eq!(server.synthesize_calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_refused_batch_exits_as_auth_and_resumes_where_it_stopped() {
+        let server = mock::start_mock_tts().await.unwrap();
+        let scratch = Scratch::new("cli-refused");
+        let context = context(&scratch, &server);
+        let output_dir = scratch.0.join("out");
+        let manifest = scratch.0.join("batch_manifest.json");
+        let segments = [
+            ("intro", "en-US-Mock-A", "Welcome to the show."),
+            (
+                "outro",
+                "en-US-Mock-Unauthenticated",
+                "Thanks for listening.",
+            ),
+        ];
+        write_manifest(&manifest, &output_dir, &segments);
+
+        let error = batch(&context, &manifest).await.unwrap_err();
+        assert!(matches!(error, AppError::BatchFailed { .. }), "{:?}", error);
+        assert_eq!(exit_code(&error), EXIT_AUTH);
+        assert_eq!(
+            statuses(&manifest),
+            [SegmentStatus::Completed, SegmentStatus::Failed]
+        );
+        assert!(output_dir.join("001.mp3").exists());
+        let calls = server.synthesize_calls();
+
+        // The same command again redoes only the failed segment
+        let mut edited = BatchManifest::load(&manifest).unwrap();
+        edited.segments[1].voice_name = "en-US-Mock-B".to_string();
+        fs::write(&manifest, serde_json::to_vec(&edited).unwrap()).unwrap();
+        batch(&context, &manifest).await.unwrap();
+        assert_eq!(server.synthesize_calls(), calls + 1);
+        assert_eq!(
+            statuses(&manifest),
+            [SegmentStatus::Completed, SegmentStatus::Completed]
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_refuses_a_manifest_that_is_not_one() {
+        let server = mock::start_mock_tts().await.unwrap();
+        let scratch = Scratch::new("cli-invalid");
+        let context = context(&scratch, &server);
+        let manifest = scratch.0.join("batch_manifest.json");
+        fs::write(&manifest, "{\"segments\": 3}").unwrap();
+        let error = batch(&context, &manifest).await.unwrap_err();
+        assert_eq!(exit_code(&error), EXIT_INPUT, "{:?}", error);
+        assert_eq!(server.synthesize_calls(), 0);
+    }
+}
@@ -0,0 +1,319 @@
+// The commands the frontend can invoke. Each module lists its own in a
+// `commands()` group, composed here into the one invoke handler; the
+// app-wide ones live here
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use tauri::ipc::Invoke;
+
+use crate::read_only::WriteAccess;
+use crate::tts::recording::RecordingStatus;
+use crate::tts::TtsService;
+use crate::{
+    audio, batch, cache, cancellation, credentials, jobs, metrics, migrations, mini_player,
+    onboarding, paths, project, read_only, script, settings, shutdown, sidecar, spelling, support,
+    trash, tts, voices, watch_folder, window_scope,
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AppInfo {
+    name: &'static str,
+    version: &'static str,
+    os: &'static str,
+    arch: &'static str,
+    /// Shown in the UI whenever enabled, so debug recording is never on
+    /// unnoticed.
+    tts_recording: RecordingStatus,
+    /// Set when an app directory isn't writable; settings then aren't
+    /// saved and nothing is cached.
+    read_only: bool,
+    unwritable_path: Option<PathBuf>,
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_app_info(
+    tts: tauri::State<'_, TtsService>,
+    access: tauri::State<'_, WriteAccess>,
+) -> AppInfo {
+    app_info(&tts, &access)
+}
+
+/// What `get_app_info` answers, for callers other than the frontend.
+pub fn app_info(tts: &TtsService, access: &WriteAccess) -> AppInfo {
+    let access = access.status();
+    AppInfo {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        os: env::consts::OS,
+        arch: env::consts::ARCH,
+        tts_recording: tts.recording_status(),
+        read_only: access.read_only,
+        unwritable_path: access.unwritable_path,
+    }
+}
+
+// Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn greet(name: &str) -> String {
+    format!("Hello, {}! You've been greeted from Rust!", name)
+}
+
+/// The commands of one module and the invoke handler generated for them,
+/// from `command_group!`.
+pub struct CommandGroup {
+    /// Paths of the commands as listed, relative to the module.
+    paths: &'static [&'static str],
+    handler: Box<dyn Fn(Invoke) -> bool + Send + Sync>,
+}
+
+impl CommandGroup {
+    pub fn new(
+        paths: &'static [&'static str],
+        handler: Box<dyn Fn(Invoke) -> bool + Send + Sync>,
+    ) -> Self {
+        Self { paths, handler }
+    }
+
+    /// The names the frontend invokes the commands by: the last segment of
+    /// each path.
+    pub fn names(&self) -> impl Iterator<Item = &'static str> {
+        self.paths
+            .iter()
+            .map(|path| path.rsplit("::").next().unwrap_or(path).trim())
+    }
+}
+
+/// A `CommandGroup` of the commands listed, as `tauri::generate_handler!`
+/// takes them.
+macro_rules! command_group {
+    ($($($segment:ident)::+),* $(,)?) => {
+        $crate::commands::CommandGroup::new(
+            &[$(stringify!($($segment)::+)),*],
+            Box::new(tauri::generate_handler![$($($segment)::+),*]),
+        )
+    };
+}
+pub(crate) use command_group;
+
+fn app_commands() -> CommandGroup {
+    command_group![greet, get_app_info]
+}
+
+/// The command groups of every module; a module with commands of its own
+/// lists them in its `commands()` and is added here.
+fn groups() -> Vec<CommandGroup> {
+    vec![
+        app_commands(),
+        voices::commands(),
+        tts::commands(),
+        batch::commands(),
+        audio::commands(),
+        settings::commands(),
+        onboarding::commands(),
+        credentials::commands(),
+        sidecar::commands(),
+        project::commands(),
+        cache::commands(),
+        paths::commands(),
+        trash::commands(),
+        jobs::commands(),
+        script::commands(),
+        spelling::commands(),
+        migrations::commands(),
+        cancellation::commands(),
+        window_scope::commands(),
+        mini_player::commands(),
+        read_only::commands(),
+        metrics::commands(),
+        support::commands(),
+        shutdown::commands(),
+        watch_folder::commands(),
+    ]
+}
+
+/// The invoke handler for every command: routes each invoke by name to the
+/// handler of the group registering it, and answers unknown names as
+/// unhandled. Every invoke is counted and timed for the debug overlay.
+pub fn handler() -> impl Fn(Invoke) -> bool + Send + Sync + 'static {
+    let groups = groups();
+    let mut routes = HashMap::new();
+    for (index, group) in groups.iter().enumerate() {
+        for name in group.names() {
+            if routes.insert(name, index).is_some() {
+                log::warn!("Command {} is registered twice; the later one wins", name);
+            }
+        }
+    }
+    metrics::instrument(
+        move |invoke: Invoke| match routes.get(invoke.message.command()) {
+            Some(&index) => (groups[index].handler)(invoke),
+            None => false,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Names the frontend can invoke, sorted, one per line. Renaming or
+    /// removing one breaks callers, so the list changes only on purpose.
+    const SNAPSHOT: &str = include_str!("commands.snap");
+
+    fn names() -> Vec<&'static str> {
+        groups().iter().flat_map(CommandGroup::names).collect()
+    }
+
+    #[test]
+    fn command_names_match_the_snapshot() {
+        let mut names = names();
+        names.sort_unstable();
+        let snapshot: Vec<&str> = SNAPSHOT.lines().collect();
+        assert_eq!(names, snapshot, "update src/commands.snap with the change");
+    }
+
+    #[test]
+    fn command_names_are_registered_once() {
+        let mut names = names();
+        let registered = names.len();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), registered);
+    }
+
+    /// What commands answer for fixed inputs, keyed by a name for each
+    /// call. Like the names, an answer changes shape only on purpose.
+    const FIXTURES: &str = include_str!("commands.fixtures.json");
+
+    #[test]
+    fn command_answers_match_their_fixtures() {
+        use crate::cancellation::CancellationRegistry;
+        use crate::scratch::Scratch;
+        use crate::settings::SettingsStore;
+        use crate::tts::fingerprint::TtsRequestParams;
+        use crate::tts::usage::UsageLedger;
+        use crate::voices::capabilities::CapabilityStore;
+        use serde_json::{json, to_value, Value};
+        use std::collections::BTreeMap;
+        use tauri::Manager;
+
+        let scratch = Scratch::new("commands");
+        let dir = &scratch.0;
+        let app = tauri::test::mock_app();
+        app.manage(metrics::CommandStats::default());
+        app.manage(SettingsStore::load(dir.join("settings.json")));
+        app.manage(CancellationRegistry::default());
+        app.manage(CapabilityStore::load(dir.join("voice_capabilities.json")));
+        app.manage(TtsService::new(
+            None,
+            None,
+            tts::recording::Recorder::new(dir.join("tts_recordings"), Default::default()),
+            UsageLedger::load(dir.join("usage.json")),
+            |_| {},
+        ));
+        let stats = || app.state::<metrics::CommandStats>();
+        let settings = || app.state::<SettingsStore>();
+
+        let params: TtsRequestParams = serde_json::from_value(json!({
+            "voice_name": "en-US-Neural2-C",
+            "language_code": "en-us",
+            "input": "Hello.",
+        }))
+        .unwrap();
+        let answers: BTreeMap<&str, Value> = BTreeMap::from([
+            ("greet", to_value(greet("Ada", stats())).unwrap()),
+            (
+                "fingerprint_tts_request",
+                to_value(tts::fingerprint::fingerprint_tts_request(params, stats())).unwrap(),
+            ),
+            (
+                "measure_synthesis_input",
+                to_value(tts::length::measure_synthesis_input(
+                    "Caf\u{e9} & tea".to_string(),
+                    false,
+                    stats(),
+                ))
+                .unwrap(),
+            ),
+            (
+                "measure_synthesis_input (ssml)",
+                to_value(tts::length::measure_synthesis_input(
+                    "<speak>Caf\u{e9} &amp; tea</speak>".to_string(),
+                    true,
+                    stats(),
+                ))
+                .unwrap(),
+            ),
+            (
+                "parse_script_structure",
+                to_value(script::structure::parse_script_structure(
+                    "# Act 1\n\nINT. KITCHEN - DAY\n\nHello.\n".to_string(),
+                    stats(),
+                ))
+                .unwrap(),
+            ),
+            (
+                "get_voice_remap",
+                to_value(voices::remap::get_voice_remap(settings(), stats())).unwrap(),
+            ),
+            (
+                "list_output_presets",
+                to_value(audio::presets::list_output_presets(settings(), stats())).unwrap(),
+            ),
+            (
+                "get_voice_capabilities",
+                to_value(voices::capabilities::get_voice_capabilities(
+                    app.state(),
+                    "en-US-Studio-O".to_string(),
+                    stats(),
+                ))
+                .unwrap(),
+            ),
+            (
+                "list_cancellable_operations",
+                to_value(cancellation::list_cancellable_operations(
+                    app.state(),
+                    stats(),
+                ))
+                .unwrap(),
+            ),
+            (
+                "cancel_operation",
+                to_value(cancellation::cancel_operation(
+                    app.state(),
+                    "missing".to_string(),
+                    stats(),
+                ))
+                .unwrap(),
+            ),
+            (
+                "get_tts_queue_status",
+                to_value(tts::get_tts_queue_status(app.state(), stats())).unwrap(),
+            ),
+        ]);
+        let fixtures: BTreeMap<String, Value> = serde_json::from_str(FIXTURES).unwrap();
+        for (call, answer) in &answers {
+            assert_eq!(
+                fixtures.get(*call),
+                Some(answer),
+                "update src/commands.fixtures.json for {}: {}",
+                call,
+                answer
+            );
+        }
+        assert_eq!(fixtures.len(), answers.len(), "fixtures without a call");
+    }
+
+    #[test]
+    fn names_are_the_last_path_segment() {
+        let group = CommandGroup::new(
+            &["voices :: google :: list_google_voices", "greet"],
+            Box::new(|_| false),
+        );
+        let names: Vec<_> = group.names().collect();
+        assert_eq!(names, ["list_google_voices", "greet"]);
+    }
+}
@@ -0,0 +1,276 @@
+// Google Cloud credentials used by the TTS client: application-default
+// discovery and named per-client profiles
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::error::AppError;
+use crate::settings::{Settings, SettingsStore};
+use crate::tts::TtsService;
+
+/// Path of the credentials file the Google auth stack will pick up, if any:
+/// GOOGLE_APPLICATION_CREDENTIALS first, then the gcloud application-default
+/// credentials in the user's config directory.
+pub fn application_default_credentials() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("GOOGLE_APPLICATION_CREDENTIALS") {
+        let path = PathBuf::from(path);
+        return path.is_file().then_some(path);
+    }
+
+    let config_dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+    }?;
+    let path = config_dir
+        .join("gcloud")
+        .join("application_default_credentials.json");
+    path.is_file().then_some(path)
+}
+
+/// Keyring service under which profile key material is stored, one entry per
+/// profile name.
+const KEYRING_SERVICE: &str = "sclip-tts";
+
+/// Non-secret description of a named credential profile, kept in settings.
+/// The key JSON itself only ever lives in the OS keyring.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CredentialProfile {
+    pub name: String,
+    pub project_id: Option<String>,
+    pub client_email: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfilesState {
+    pub profiles: Vec<CredentialProfile>,
+    pub active_profile: Option<String>,
+    /// Profiles exist but none is active, e.g. after deleting the active one.
+    /// Synthesis falls back to application-default credentials until the user
+    /// picks one.
+    pub needs_selection: bool,
+}
+
+/// Credentials the shared TTS client is built from.
+#[derive(Clone)]
+pub struct ActiveCredentials {
+    pub profile: String,
+    pub key_json: String,
+}
+
+fn keyring_entry(profile: &str) -> Result<keyring::Entry, AppError> {
+    keyring::Entry::new(KEYRING_SERVICE, profile).map_err(keyring_error)
+}
+
+fn keyring_error(e: keyring::Error) -> AppError {
+    AppError::Credentials {
+        message: format!("keyring: {}", e),
+    }
+}
+
+pub fn load_profile_key(profile: &str) -> Result<String, AppError> {
+    keyring_entry(profile)?.get_password().map_err(|e| match e {
+        keyring::Error::NoEntry => AppError::ProfileNotFound {
+            name: profile.to_string(),
+        },
+        e => keyring_error(e),
+    })
+}
+
+fn validate_profile_name(name: &str) -> Result<(), AppError> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(AppError::Credentials {
+            message: format!(
+                "invalid profile name \"{}\": use up to 64 letters, digits, spaces, '-', '_' or '.'",
+                name
+            ),
+        })
+    }
+}
+
+/// Accepts either the key JSON itself or a path to a key file and returns the
+/// JSON after checking it is a usable Google credential.
+fn read_key(key_json_or_path: &str) -> Result<(String, serde_json::Value), AppError> {
+    let trimmed = key_json_or_path.trim();
+    let json = if trimmed.starts_with('{') {
+        trimmed.to_string()
+    } else {
+        fs::read_to_string(trimmed)?
+    };
+
+    let parsed: serde_json::Value =
+        serde_json::from_str(&json).map_err(|e| AppError::Credentials {
+            message: format!("key is not valid JSON: {}", e),
+        })?;
+    match parsed.get("type").and_then(|t| t.as_str()) {
+        Some("service_account") | Some("authorized_user") => Ok((json, parsed)),
+        other => Err(AppError::Credentials {
+            message: format!(
+                "unsupported credential type {:?}; expected a service account key",
+                other.unwrap_or("missing")
+            ),
+        }),
+    }
+}
+
+fn profiles_state(settings: &Settings) -> ProfilesState {
+    ProfilesState {
+        profiles: settings.credential_profiles.clone(),
+        active_profile: settings.active_profile.clone(),
+        needs_selection: settings.active_profile.is_none()
+            && !settings.credential_profiles.is_empty(),
+    }
+}
+
+/// Credentials to start the TTS client with, based on the persisted active
+/// profile. A profile whose key went missing from the keyring is logged and
+/// ignored rather than blocking startup.
+pub fn startup_credentials(settings: &Settings) -> Option<ActiveCredentials> {
+    let profile = settings.active_profile.clone()?;
+    match load_profile_key(&profile) {
+        Ok(key_json) => Some(ActiveCredentials { profile, key_json }),
+        Err(e) => {
+            log::error!("Active credential profile {} is unusable: {}", profile, e);
+            None
+        }
+    }
+}
+
+async fn activate(
+    store: &SettingsStore,
+    tts: &TtsService,
+    profile: Option<String>,
+) -> Result<ProfilesState, AppError> {
+    let credentials = match &profile {
+        Some(name) => Some(ActiveCredentials {
+            profile: name.clone(),
+            key_json: load_profile_key(name)?,
+        }),
+        None => None,
+    };
+    let settings = store.update(|settings| settings.active_profile = profile)?;
+    // Different projects can have different allowlisted voices, so the voice
+    // cache goes along with the client.
+    tts.set_credentials(credentials).await;
+    Ok(profiles_state(&settings))
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn add_credential_profile(
+    store: tauri::State<'_, SettingsStore>,
+    tts: tauri::State<'_, TtsService>,
+    name: String,
+    key_json_or_path: String,
+) -> Result<ProfilesState, AppError> {
+    validate_profile_name(&name)?;
+    if store
+        .get()
+        .credential_profiles
+        .iter()
+        .any(|p| p.name == name)
+    {
+        return Err(AppError::Credentials {
+            message: format!("a profile named \"{}\" already exists", name),
+        });
+    }
+
+    let (key_json, parsed) = read_key(&key_json_or_path)?;
+    keyring_entry(&name)?
+        .set_password(&key_json)
+        .map_err(keyring_error)?;
+
+    let field = |key: &str| parsed.get(key).and_then(|v| v.as_str()).map(String::from);
+    let profile = CredentialProfile {
+        name: name.clone(),
+        project_id: field("project_id").or_else(|| field("quota_project_id")),
+        client_email: field("client_email"),
+        created_at: Utc::now(),
+    };
+    let settings = store.update(|settings| settings.credential_profiles.push(profile))?;
+
+    if settings.active_profile.is_none() {
+        return activate(&store, &tts, Some(name)).await;
+    }
+    Ok(profiles_state(&settings))
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn list_credential_profiles(store: tauri::State<'_, SettingsStore>) -> ProfilesState {
+    profiles_state(&store.get())
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn set_active_profile(
+    store: tauri::State<'_, SettingsStore>,
+    tts: tauri::State<'_, TtsService>,
+    name: Option<String>,
+) -> Result<ProfilesState, AppError> {
+    if let Some(name) = &name {
+        if !store
+            .get()
+            .credential_profiles
+            .iter()
+            .any(|p| &p.name == name)
+        {
+            return Err(AppError::ProfileNotFound { name: name.clone() });
+        }
+    }
+    activate(&store, &tts, name).await
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn remove_credential_profile(
+    store: tauri::State<'_, SettingsStore>,
+    tts: tauri::State<'_, TtsService>,
+    name: String,
+) -> Result<ProfilesState, AppError> {
+    if !store
+        .get()
+        .credential_profiles
+        .iter()
+        .any(|p| p.name == name)
+    {
+        return Err(AppError::ProfileNotFound { name });
+    }
+
+    match keyring_entry(&name)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => {}
+        Err(e) => return Err(keyring_error(e)),
+    }
+
+    let was_active = store.get().active_profile.as_deref() == Some(name.as_str());
+    let settings = store.update(|settings| {
+        settings.credential_profiles.retain(|p| p.name != name);
+    })?;
+
+    if was_active {
+        // Don't silently pick a different client's project; the UI asks the
+        // user via needs_selection.
+        return activate(&store, &tts, None).await;
+    }
+    Ok(profiles_state(&settings))
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![
+        add_credential_profile,
+        list_credential_profiles,
+        set_active_profile,
+        remove_credential_profile,
+    ]
+}
@@ -0,0 +1,523 @@
+use serde::Serialize;
+
+use crate::ssml::policy::PolicyFinding;
+use crate::tts::placeholders::PlaceholderMatch;
+use crate::tts::spend::SpendCap;
+
+/// Errors returned to the frontend by Tauri commands.
+///
+/// Serialized as `{ "kind": "<Variant>", ...fields }` so the UI can branch on
+/// `kind` instead of parsing message strings.
+#[derive(Debug, Clone, thiserror::Error, Serialize)]
+#[serde(tag = "kind")]
+pub enum AppError {
+    #[error("I/O error: {message}")]
+    Io { message: String },
+
+    #[error("Text-to-speech request failed: {message}")]
+    Tts { message: String },
+
+    /// Google refused the credentials, or they may not use the API.
+    #[error("Text-to-speech credentials were rejected: {message}")]
+    TtsUnauthorized { message: String },
+
+    /// The project's request or character quota is used up.
+    #[error("Text-to-speech quota exceeded: {message}")]
+    TtsQuotaExceeded { message: String },
+
+    /// Google could not be reached at all, as opposed to rejecting the call.
+    #[error("Text-to-speech service is unreachable: {message}")]
+    TtsOffline { message: String },
+
+    #[error("Invalid quota project \"{project_id}\": {reason}")]
+    InvalidQuotaProject { project_id: String, reason: String },
+
+    /// The credentials may not bill usage to the quota project.
+    #[error("Not allowed to bill text-to-speech to project {project_id}: the service account needs serviceusage.services.use on it ({message})")]
+    QuotaProjectDenied { project_id: String, message: String },
+
+    #[error("Offline speech synthesis failed: {message}")]
+    NativeTts { message: String },
+
+    #[error("Synthesis plan has {errors} error(s), first: {message}")]
+    InvalidSynthesisPlan { errors: usize, message: String },
+
+    /// Empty, whitespace-only or punctuation-only text, refused before it
+    /// reaches Google.
+    #[error("Nothing to synthesize: {reason}")]
+    EmptyInput { reason: String },
+
+    /// Not a Google voice name, e.g. a path traversal attempt.
+    #[error("Invalid voice name: {voice_name}")]
+    InvalidVoiceName { voice_name: String },
+
+    #[error("Voice preview file not found: voice_{voice_name}.mp3")]
+    PreviewNotFound { voice_name: String },
+
+    #[error("Voice preview for {voice_name} is corrupt and could not be regenerated")]
+    CorruptPreview { voice_name: String },
+
+    #[error("Batch stopped at segment {segment_id}: {message}")]
+    BatchFailed {
+        manifest_path: String,
+        segment_id: String,
+        message: String,
+        /// What stopped it, for the command line tool's exit code.
+        #[serde(skip)]
+        cause: Box<AppError>,
+    },
+
+    #[error("Batch was cancelled; resume it from {manifest_path}")]
+    BatchCancelled { manifest_path: String },
+
+    #[error("Invalid batch manifest {manifest_path}: {message}")]
+    InvalidManifest {
+        manifest_path: String,
+        message: String,
+    },
+
+    #[error("Invalid naming template \"{template}\": {reason}")]
+    InvalidTemplate { template: String, reason: String },
+
+    #[error("Credentials error: {message}")]
+    Credentials { message: String },
+
+    #[error("Credential profile not found: {name}")]
+    ProfileNotFound { name: String },
+
+    #[error("Backend is not reachable on port {port}: {message}")]
+    BackendUnavailable { port: u16, message: String },
+
+    #[error("Backend did not respond within {timeout_ms} ms")]
+    BackendTimeout { timeout_ms: u64 },
+
+    #[error("Backend returned HTTP {status}: {message}")]
+    BackendError { status: u16, message: String },
+
+    #[error("Invalid backend request: {message}")]
+    InvalidBackendRequest { message: String },
+
+    #[error("Backend is restarting; retry the request")]
+    BackendRestarting,
+
+    #[error("Backend install failed: {message}")]
+    BackendInstall { message: String },
+
+    #[error("Downloaded backend archive is corrupt: SHA-256 {actual}, expected {expected}")]
+    ChecksumMismatch { expected: String, actual: String },
+
+    #[error("Invalid project {path}: {message}")]
+    InvalidProject { path: String, message: String },
+
+    #[error("Project {path} is already open in window {window}")]
+    ProjectOpenInOtherWindow { path: String, window: String },
+
+    #[error("Window {window} has no project open")]
+    NoActiveProject { window: String },
+
+    #[error("Running read-only because {path} is not writable")]
+    ReadOnlyMode { path: String },
+
+    #[error("Project archive entry {entry} is corrupt: {message}")]
+    CorruptArchive { entry: String, message: String },
+
+    #[error("Project archive entry {entry} would be extracted outside the destination")]
+    UnsafeArchiveEntry { entry: String },
+
+    #[error("Could not read or write audio tags for {path}: {message}")]
+    InvalidAudioTags { path: String, message: String },
+
+    #[error("Invalid audio request: {message}")]
+    InvalidAudioRequest { message: String },
+
+    #[error("Fitting {input_duration_ms:.0} ms of audio into {target_ms} ms needs a {required_ratio:.3}x stretch, over the {max_stretch_pct}% limit")]
+    StretchTooLarge {
+        required_ratio: f64,
+        max_stretch_pct: f64,
+        input_duration_ms: f64,
+        target_ms: u64,
+    },
+
+    #[error("Project archive job was cancelled")]
+    ArchiveCancelled,
+
+    #[error("Archive holds {bytes} bytes, over the {limit}-byte import limit")]
+    ArchiveTooLarge { bytes: u64, limit: u64 },
+
+    #[error("TTS recording not found: {entry_id}")]
+    RecordingNotFound { entry_id: String },
+
+    #[error("TTS recording {entry_id} can't be replayed: {reason}")]
+    RecordingNotReplayable { entry_id: String, reason: String },
+
+    /// With `strict_language`, a language code the voice doesn't speak.
+    #[error("Voice {voice_name} does not speak {language_code} (supports {})", .supported.join(", "))]
+    VoiceLanguageMismatch {
+        voice_name: String,
+        language_code: String,
+        supported: Vec<String>,
+    },
+
+    #[error("Mini player not found: {label}")]
+    MiniPlayerNotFound { label: String },
+
+    #[error("Invalid budget reservation: {reason}")]
+    InvalidReservation { reason: String },
+
+    #[error("Budget reservation not found: {reservation_id}")]
+    ReservationNotFound { reservation_id: String },
+
+    /// The monthly budget, after usage and other reservations, has less left
+    /// than was asked for.
+    #[error(
+        "Reserving {requested} characters exceeds the {remaining} left in this month's budget"
+    )]
+    BudgetExceeded { requested: u64, remaining: u64 },
+
+    /// A call would bill past one of the `spend_caps`; `used` is what
+    /// already counts against the cap. `confirm_spend` lets it through.
+    #[error(
+        "{attempted} more characters exceed the {limit}-character {} spend cap ({used} used)",
+        .cap.label()
+    )]
+    SpendCapExceeded {
+        cap: SpendCap,
+        limit: u64,
+        attempted: u64,
+        used: u64,
+    },
+
+    #[error("Invalid spend confirmation: {reason}")]
+    InvalidSpendConfirmation { reason: String },
+
+    #[error("Invalid voice shortlist {path}: {message}")]
+    InvalidShortlist { path: String, message: String },
+
+    #[error("Trash entry not found: {entry_id}")]
+    TrashEntryNotFound { entry_id: String },
+
+    #[error("Synthesis history entry not found: {entry_id}")]
+    HistoryEntryNotFound { entry_id: String },
+
+    /// The entry is listed but its audio is gone from the TTS cache.
+    #[error("Audio of synthesis history entry {entry_id} is no longer cached")]
+    HistoryAudioMissing { entry_id: String },
+
+    #[error("Voice capabilities could not be updated: {message}")]
+    VoiceCapabilities { message: String },
+
+    #[error("{operation} was cancelled")]
+    Cancelled { operation: String },
+
+    #[error("Invalid cancel token \"{token_id}\": {reason}")]
+    InvalidCancelToken { token_id: String, reason: String },
+
+    #[error("Invalid preview sentence for {language_code}: {reason}")]
+    InvalidPreviewSentence {
+        language_code: String,
+        reason: String,
+    },
+
+    #[error("No voice speaks {language_code} or another region of its language")]
+    NoMatchingVoice { language_code: String },
+
+    #[error("Path is {length} characters, over the {limit}-character limit: {path}")]
+    PathTooLong {
+        path: String,
+        length: usize,
+        limit: usize,
+    },
+
+    #[error("Cannot reveal {path}: {reason}")]
+    CannotReveal { path: String, reason: String },
+
+    #[error("Access to {path} is not allowed: {reason}")]
+    PathNotAllowed { path: String, reason: String },
+
+    /// A write outside the app's directories the user hasn't allowed with
+    /// `request_directory_access`, or declined there.
+    #[error("Writing to {path} was not granted: {reason}")]
+    AccessNotGranted { path: String, reason: String },
+
+    #[error("Job {job_id} cannot be resumed: {reason}")]
+    JobNotRecoverable { job_id: String, reason: String },
+
+    #[error("Download of {url} is not allowed: {reason}")]
+    DownloadNotAllowed { url: String, reason: String },
+
+    /// The `.partial` file is kept, so downloading again resumes.
+    #[error("Download of {url} failed: {message}")]
+    Download { url: String, message: String },
+
+    #[error("Download of {url} is {bytes} bytes, over the {limit}-byte limit")]
+    DownloadTooLarge { url: String, bytes: u64, limit: u64 },
+
+    #[error("Download of {url} is corrupt: SHA-256 {actual}, expected {expected}")]
+    DownloadChecksumMismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+
+    /// Strict placeholder mode found template fields left in the text.
+    #[error("Text contains {} unreplaced placeholder(s)", .matches.len())]
+    PlaceholdersPresent { matches: Vec<PlaceholderMatch> },
+
+    /// The SSML policy is set to reject and found elements or attributes
+    /// it doesn't allow, or the SSML is malformed.
+    #[error("SSML refused by the policy: {message}")]
+    SsmlRejected {
+        message: String,
+        findings: Vec<PolicyFinding>,
+    },
+
+    /// A settings patch was based on a revision another writer has since
+    /// replaced; re-read the settings and apply the change again.
+    #[error("Settings changed since they were read (now at revision {current_revision})")]
+    SettingsConflict { current_revision: u64 },
+
+    /// A settings patch doesn't produce valid settings.
+    #[error("Invalid settings: {message}")]
+    InvalidSettings { message: String },
+
+    /// One sample of an A/B preview couldn't be made; nothing was played.
+    #[error("A/B preview sample {side} ({voice_name}) failed: {message}")]
+    AbPreviewFailed {
+        side: String,
+        voice_name: String,
+        message: String,
+    },
+
+    /// A microphone recording couldn't start or finish, or none is running.
+    #[error("Recording failed: {message}")]
+    Recording { message: String },
+
+    /// The OS refused the app the microphone; `hint` says where to allow it.
+    #[error("Microphone access was denied: {message}. {hint}")]
+    MicrophonePermission { message: String, hint: String },
+
+    /// `enforce_review` is on and segments of the export aren't approved.
+    #[error("{} segment(s) not approved for export", .segment_ids.len())]
+    UnapprovedSegments { segment_ids: Vec<String> },
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(e: std::io::Error) -> Self {
+        AppError::Io {
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<zip::result::ZipError> for AppError {
+    fn from(e: zip::result::ZipError) -> Self {
+        AppError::Io {
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<tauri::Error> for AppError {
+    fn from(e: tauri::Error) -> Self {
+        AppError::Io {
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<gcloud_sdk::error::Error> for AppError {
+    fn from(e: gcloud_sdk::error::Error) -> Self {
+        use gcloud_sdk::error::ErrorKind;
+
+        let message = e.to_string();
+        match e.kind() {
+            ErrorKind::Http(http) if http.is_connect() || http.is_timeout() => {
+                AppError::TtsOffline { message }
+            }
+            ErrorKind::GrpcStatus(_) => AppError::TtsOffline { message },
+            ErrorKind::CredentialsJson(_)
+            | ErrorKind::CredentialsFile(_)
+            | ErrorKind::Jwt(_)
+            | ErrorKind::TokenSource
+            | ErrorKind::TokenData => AppError::Credentials { message },
+            _ => AppError::Tts { message },
+        }
+    }
+}
+
+impl From<gcloud_sdk::tonic::Status> for AppError {
+    fn from(e: gcloud_sdk::tonic::Status) -> Self {
+        use gcloud_sdk::tonic::Code;
+
+        let message = e.to_string();
+        match e.code() {
+            Code::Unavailable | Code::DeadlineExceeded => AppError::TtsOffline { message },
+            Code::Unauthenticated | Code::PermissionDenied => AppError::TtsUnauthorized { message },
+            Code::ResourceExhausted => AppError::TtsQuotaExceeded { message },
+            _ => AppError::Tts { message },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssml::policy::PolicyAction;
+    use serde_json::{json, Value};
+
+    /// The JSON the frontend gets, after it has been through text once.
+    fn sent(error: &AppError) -> Value {
+        let text = serde_json::to_string(error).unwrap();
+        serde_json::from_str(&text).unwrap()
+    }
+
+    #[test]
+    fn errors_serialize_as_their_kind_and_fields() {
+        let cases = [
+            (
+                AppError::Io {
+                    message: "disk full".to_string(),
+                },
+                json!({ "kind": "Io", "message": "disk full" }),
+            ),
+            (
+                AppError::ArchiveCancelled,
+                json!({ "kind": "ArchiveCancelled" }),
+            ),
+            (
+                AppError::BackendTimeout { timeout_ms: 30_000 },
+                json!({ "kind": "BackendTimeout", "timeout_ms": 30000 }),
+            ),
+            (
+                AppError::StretchTooLarge {
+                    required_ratio: 1.25,
+                    max_stretch_pct: 15.0,
+                    input_duration_ms: 5000.0,
+                    target_ms: 4000,
+                },
+                json!({
+                    "kind": "StretchTooLarge",
+                    "required_ratio": 1.25,
+                    "max_stretch_pct": 15.0,
+                    "input_duration_ms": 5000.0,
+                    "target_ms": 4000,
+                }),
+            ),
+            (
+                AppError::VoiceLanguageMismatch {
+                    voice_name: "de-DE-Neural2-A".to_string(),
+                    language_code: "en-US".to_string(),
+                    supported: vec!["de-DE".to_string()],
+                },
+                json!({
+                    "kind": "VoiceLanguageMismatch",
+                    "voice_name": "de-DE-Neural2-A",
+                    "language_code": "en-US",
+                    "supported": ["de-DE"],
+                }),
+            ),
+            (
+                AppError::SpendCapExceeded {
+                    cap: SpendCap::PerBatch,
+                    limit: 100_000,
+                    attempted: 120_000,
+                    used: 0,
+                },
+                json!({
+                    "kind": "SpendCapExceeded",
+                    "cap": "per_batch",
+                    "limit": 100000,
+                    "attempted": 120000,
+                    "used": 0,
+                }),
+            ),
+            (
+                AppError::PlaceholdersPresent {
+                    matches: vec![PlaceholderMatch {
+                        offset: 5,
+                        text: "{{name}}".to_string(),
+                    }],
+                },
+                json!({
+                    "kind": "PlaceholdersPresent",
+                    "matches": [{ "offset": 5, "text": "{{name}}" }],
+                }),
+            ),
+            (
+                AppError::SsmlRejected {
+                    message: "<audio> fetches a URL".to_string(),
+                    findings: vec![PolicyFinding {
+                        offset: 7,
+                        element: "audio".to_string(),
+                        attribute: None,
+                        action: PolicyAction::RemovedElement,
+                        reason: "<audio> fetches a URL".to_string(),
+                    }],
+                },
+                json!({
+                    "kind": "SsmlRejected",
+                    "message": "<audio> fetches a URL",
+                    "findings": [{
+                        "offset": 7,
+                        "element": "audio",
+                        "attribute": null,
+                        "action": "removed_element",
+                        "reason": "<audio> fetches a URL",
+                    }],
+                }),
+            ),
+            (
+                AppError::UnapprovedSegments {
+                    segment_ids: vec!["s1".to_string(), "s2".to_string()],
+                },
+                json!({ "kind": "UnapprovedSegments", "segment_ids": ["s1", "s2"] }),
+            ),
+        ];
+        for (error, expected) in cases {
+            assert_eq!(sent(&error), expected, "{}", error);
+        }
+    }
+
+    #[test]
+    fn a_batch_failure_keeps_its_cause_out_of_the_json() {
+        let error = AppError::BatchFailed {
+            manifest_path: "/out/batch_manifest.json".to_string(),
+            segment_id: "s3".to_string(),
+            message: "quota exceeded".to_string(),
+            cause: Box::new(AppError::TtsQuotaExceeded {
+                message: "quota exceeded".to_string(),
+            }),
+        };
+        assert_eq!(
+            sent(&error),
+            json!({
+                "kind": "BatchFailed",
+                "manifest_path": "/out/batch_manifest.json",
+                "segment_id": "s3",
+                "message": "quota exceeded",
+            })
+        );
+        assert_eq!(
+            error.to_string(),
+            "Batch stopped at segment s3: quota exceeded"
+        );
+    }
+
+    #[test]
+    fn messages_read_their_fields() {
+        let error = AppError::UnapprovedSegments {
+            segment_ids: vec!["s1".to_string(), "s2".to_string()],
+        };
+        assert_eq!(error.to_string(), "2 segment(s) not approved for export");
+        let error = AppError::SpendCapExceeded {
+            cap: SpendCap::Daily,
+            limit: 1000,
+            attempted: 1500,
+            used: 200,
+        };
+        assert_eq!(
+            error.to_string(),
+            "1500 more characters exceed the 1000-character 24-hour spend cap (200 used)"
+        );
+    }
+}
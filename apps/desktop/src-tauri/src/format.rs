@@ -0,0 +1,219 @@
+// Display strings for the durations, sizes and costs commands return, so the
+// UI shows the same rounding everywhere. The raw values stay alongside them
+// and remain what the UI computes with
+
+/// Separators and currency placement for a UI locale. Only what the display
+/// strings need; unknown languages format like en-US.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayFormat {
+    decimal: char,
+    group: char,
+    /// "0,12 $" rather than "$0.12".
+    currency_after: bool,
+}
+
+impl Default for DisplayFormat {
+    fn default() -> Self {
+        Self {
+            decimal: '.',
+            group: ',',
+            currency_after: false,
+        }
+    }
+}
+
+impl DisplayFormat {
+    pub fn for_locale(locale: &str) -> Self {
+        let mut parts = locale.split(['-', '_']);
+        let language = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let region = parts
+            .find(|p| p.len() == 2 || (p.len() == 3 && p.chars().all(|c| c.is_ascii_digit())))
+            .unwrap_or_default()
+            .to_ascii_uppercase();
+
+        let comma = |group| Self {
+            decimal: ',',
+            group,
+            currency_after: true,
+        };
+        match (language.as_str(), region.as_str()) {
+            // Swiss German and Italian, and Spanish in the Americas, use a
+            // decimal point
+            ("de" | "it", "CH" | "LI") => Self {
+                decimal: '.',
+                group: '’',
+                currency_after: false,
+            },
+            ("es", "MX" | "US" | "419") => Self::default(),
+            (
+                "de" | "es" | "it" | "nl" | "pt" | "id" | "da" | "tr" | "ro" | "el" | "hr" | "sl"
+                | "ca" | "vi",
+                _,
+            ) => comma('.'),
+            (
+                "fr" | "ru" | "uk" | "pl" | "cs" | "sk" | "sv" | "fi" | "nb" | "no" | "nn" | "bg"
+                | "hu" | "lt" | "lv" | "et",
+                _,
+            ) => comma('\u{a0}'),
+            _ => Self::default(),
+        }
+    }
+
+    /// `value` with `places` decimals and grouped thousands.
+    fn number(&self, value: f64, places: usize) -> String {
+        let fixed = format!("{:.*}", places, value.abs());
+        let (whole, fraction) = fixed.split_once('.').unwrap_or((&fixed, ""));
+        let mut out = String::new();
+        if value < 0.0 && fixed.chars().any(|c| c.is_ascii_digit() && c != '0') {
+            out.push('-');
+        }
+        for (i, digit) in whole.chars().enumerate() {
+            if i > 0 && (whole.len() - i).is_multiple_of(3) {
+                out.push(self.group);
+            }
+            out.push(digit);
+        }
+        if !fraction.is_empty() {
+            out.push(self.decimal);
+            out.push_str(fraction);
+        }
+        out
+    }
+
+    /// "850 ms", "4.2 s", "34 s", "2 min 34 s", "1 h 5 min". One decimal
+    /// below 10 s, whole units above; zero trailing units are left out.
+    pub fn duration(&self, ms: f64) -> String {
+        let ms = ms.max(0.0);
+        if ms.round() < 1000.0 {
+            return format!("{} ms", ms.round());
+        }
+        let tenths = (ms / 100.0).round();
+        if tenths < 100.0 {
+            return format!("{} s", self.number(tenths / 10.0, 1));
+        }
+        let seconds = (ms / 1000.0).round() as u64;
+        let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+        match (hours, minutes, seconds) {
+            (0, 0, s) => format!("{} s", s),
+            (0, m, 0) => format!("{} min", m),
+            (0, m, s) => format!("{} min {} s", m, s),
+            (h, 0, _) => format!("{} h", h),
+            // Seconds don't matter at this length
+            (h, m, _) => format!("{} h {} min", h, m),
+        }
+    }
+
+    /// "512 B", "3.4 MB", "34 MB": decimal units (1 kB = 1000 B) as the
+    /// macOS Finder shows them, one decimal below 10.
+    pub fn size(&self, bytes: u64) -> String {
+        const UNITS: [&str; 4] = ["kB", "MB", "GB", "TB"];
+        if bytes < 1000 {
+            return format!("{} B", bytes);
+        }
+        let mut value = bytes as f64 / 1000.0;
+        let mut unit = 0;
+        // Rounding up to 1000 moves to the next unit
+        while value.round() >= 1000.0 && unit < UNITS.len() - 1 {
+            value /= 1000.0;
+            unit += 1;
+        }
+        let places = if (value * 10.0).round() < 100.0 { 1 } else { 0 };
+        format!("{} {}", self.number(value, places), UNITS[unit])
+    }
+
+    /// US dollars to the cent, e.g. "$0.12" or "0,12 $"; "<$0.01" for any
+    /// amount that would show as zero.
+    pub fn cost_usd(&self, usd: f64) -> String {
+        let (prefix, amount) = if usd > 0.0 && usd < 0.005 {
+            ("<", 0.01)
+        } else {
+            ("", usd)
+        };
+        let amount = self.number(amount, 2);
+        if self.currency_after {
+            format!("{}{}\u{a0}$", prefix, amount)
+        } else {
+            format!("{}${}", prefix, amount)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_are_in_decimal_units() {
+        let format = DisplayFormat::default();
+        assert_eq!(format.size(999), "999 B");
+        assert_eq!(format.size(1000), "1.0 kB");
+        assert_eq!(format.size(1_000_000), "1.0 MB");
+        // A binary MiB and GiB are a little more than a decimal MB and GB
+        assert_eq!(format.size(1 << 20), "1.0 MB");
+        assert_eq!(format.size(1 << 30), "1.1 GB");
+        assert_eq!(format.size(3_400_000), "3.4 MB");
+        assert_eq!(format.size(34_000_000), "34 MB");
+        assert_eq!(format.size(5_000_000_000_000_000), "5,000 TB");
+    }
+
+    #[test]
+    fn sizes_round_into_the_next_unit_or_decimal() {
+        let format = DisplayFormat::default();
+        assert_eq!(format.size(999_499), "999 kB");
+        assert_eq!(format.size(999_500), "1.0 MB");
+        assert_eq!(format.size(9_949_999), "9.9 MB");
+        assert_eq!(format.size(9_950_000), "10 MB");
+        assert_eq!(format.size(10_499_999), "10 MB");
+    }
+
+    #[test]
+    fn durations_round_per_range() {
+        let format = DisplayFormat::default();
+        assert_eq!(format.duration(-5.0), "0 ms");
+        assert_eq!(format.duration(850.0), "850 ms");
+        assert_eq!(format.duration(999.4), "999 ms");
+        assert_eq!(format.duration(999.6), "1.0 s");
+        assert_eq!(format.duration(4_240.0), "4.2 s");
+        assert_eq!(format.duration(9_949.0), "9.9 s");
+        assert_eq!(format.duration(9_950.0), "10 s");
+        assert_eq!(format.duration(34_000.0), "34 s");
+        assert_eq!(format.duration(120_000.0), "2 min");
+        assert_eq!(format.duration(154_000.0), "2 min 34 s");
+        assert_eq!(format.duration(3_600_000.0), "1 h");
+        assert_eq!(format.duration(3_934_000.0), "1 h 5 min");
+    }
+
+    #[test]
+    fn costs_are_to_the_cent() {
+        let format = DisplayFormat::default();
+        assert_eq!(format.cost_usd(0.0), "$0.00");
+        assert_eq!(format.cost_usd(0.004), "<$0.01");
+        assert_eq!(format.cost_usd(0.126), "$0.13");
+        assert_eq!(format.cost_usd(1234.5), "$1,234.50");
+        // No minus on an amount that rounds to zero
+        assert_eq!(format.cost_usd(-0.001), "$0.00");
+    }
+
+    #[test]
+    fn separators_follow_the_locale() {
+        let de = DisplayFormat::for_locale("de-DE");
+        assert_eq!(de.size(3_400_000), "3,4 MB");
+        assert_eq!(de.cost_usd(1234.5), "1.234,50\u{a0}$");
+        assert_eq!(de.duration(4_240.0), "4,2 s");
+        let fr = DisplayFormat::for_locale("fr_FR");
+        assert_eq!(fr.cost_usd(1234.5), "1\u{a0}234,50\u{a0}$");
+        let swiss = DisplayFormat::for_locale("de-CH");
+        assert_eq!(swiss.cost_usd(1234.5), "$1’234.50");
+        assert_eq!(
+            DisplayFormat::for_locale("es-419"),
+            DisplayFormat::default()
+        );
+        assert_eq!(
+            DisplayFormat::for_locale("es-Latn-MX"),
+            DisplayFormat::default()
+        );
+        assert_eq!(DisplayFormat::for_locale("es"), de);
+        assert_eq!(DisplayFormat::for_locale("xx"), DisplayFormat::default());
+        assert_eq!(DisplayFormat::for_locale(""), DisplayFormat::default());
+    }
+}
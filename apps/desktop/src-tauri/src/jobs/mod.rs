@@ -0,0 +1,874 @@
+// Registry of long-running background jobs, so closing a window can cancel
+// them and wait for their cleanup instead of killing them mid-write
+pub mod recovery;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, Notify};
+
+use crate::settings::SettingsStore;
+use recovery::{JobDescriptor, JobProgress, JobStore, Resume};
+
+/// How long a close waits for the frontend to confirm before going ahead.
+pub const CONFIRM_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long cancelled jobs get to clean up before the app exits anyway.
+pub const CLEANUP_TIMEOUT: Duration = Duration::from_secs(30);
+/// Finished jobs whose last event `get_job_final_state` can still return.
+const MAX_FINAL_STATES: usize = 100;
+/// Least time between two progress events of a job, about 15 a second,
+/// unless the debug settings change it.
+pub const DEFAULT_PROGRESS_INTERVAL_MS: u32 = 66;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Batch,
+    ProjectArchive,
+    PreviewPrefetch,
+    AssetDownload,
+    PreviewCacheArchive,
+    AudioConform,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub id: String,
+    pub kind: JobKind,
+    /// What the job works on, e.g. the batch output directory.
+    pub label: String,
+    /// Label of the window that started the job, which alone gets its events
+    /// and whose closing cancels it. `None` for app-wide jobs such as
+    /// preview prefetch.
+    pub window: Option<String>,
+    pub started_at: DateTime<Utc>,
+}
+
+/// Payload of `close-requested-with-active-jobs`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CloseRequest {
+    pub jobs: Vec<JobInfo>,
+    /// The close proceeds on its own after this long without `confirm_close`.
+    pub timeout_ms: u64,
+}
+
+struct ActiveJob {
+    info: JobInfo,
+    cancel: Arc<AtomicBool>,
+}
+
+/// A job event as emitted: the job's own payload plus its place in the
+/// job's event stream.
+#[derive(Serialize)]
+struct Sequenced<'a, T: Serialize> {
+    /// 0 for a job's first event, one more for each after it.
+    seq: u64,
+    /// Set on the job's last event; nothing is emitted for the job after it.
+    terminal: bool,
+    #[serde(flatten)]
+    payload: &'a T,
+}
+
+/// The terminal event of a finished job, for a frontend that may have missed
+/// it, e.g. while reloading.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobFinalState {
+    pub job_id: String,
+    pub kind: JobKind,
+    pub event: &'static str,
+    pub finished_at: DateTime<Utc>,
+    /// The event exactly as it was emitted, `seq` and `terminal` included.
+    pub payload: serde_json::Value,
+}
+
+#[derive(Default)]
+struct Registry {
+    jobs: Mutex<HashMap<String, ActiveJob>>,
+    /// Woken whenever a job finishes.
+    finished: Notify,
+    /// Closes waiting for the frontend's decision, by window label.
+    pending_close: Mutex<HashMap<String, oneshot::Sender<bool>>>,
+    shutting_down: AtomicBool,
+    /// Oldest first.
+    final_states: Mutex<VecDeque<JobFinalState>>,
+    store: JobStore,
+}
+
+/// Managed state tracking every running job.
+#[derive(Default)]
+pub struct JobManager {
+    registry: Arc<Registry>,
+}
+
+/// Held by a running job. Dropping it, however the job ends, takes the job
+/// off the active list.
+pub struct JobGuard {
+    id: String,
+    kind: JobKind,
+    window: Option<String>,
+    cancel: Arc<AtomicBool>,
+    registry: Arc<Registry>,
+}
+
+/// Emits a job's events in order. Events are numbered as they go out, and
+/// once the terminal one has, later ones are logged and dropped, so the
+/// frontend never sees progress after completion. Progress is throttled:
+/// the first event goes out at once, then at most one per interval with the
+/// newest value, and the terminal event flushes any held back before it.
+pub struct JobEmitter {
+    inner: Arc<EmitterInner>,
+}
+
+/// Where a job's events are delivered.
+enum Sink {
+    App(tauri::AppHandle),
+    /// Kept in order instead, for tests.
+    #[cfg(test)]
+    Recorded(Arc<Mutex<Vec<serde_json::Value>>>),
+}
+
+struct EmitterInner {
+    sink: Sink,
+    event: &'static str,
+    job_id: String,
+    kind: JobKind,
+    window: Option<String>,
+    registry: Arc<Registry>,
+    /// Least time between two progress events; zero sends every one.
+    interval: Duration,
+    /// Locked while emitting so events from several threads leave in `seq`
+    /// order.
+    state: Mutex<EmitState>,
+}
+
+struct EmitState {
+    /// The next `seq`, or `None` after the terminal event.
+    next_seq: Option<u64>,
+    last_sent: Option<Instant>,
+    /// The newest progress held back by the throttle.
+    pending: Option<serde_json::Value>,
+    flush_scheduled: bool,
+}
+
+impl JobGuard {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The flag the job polls between units of work.
+    pub fn cancel_flag(&self) -> &AtomicBool {
+        &self.cancel
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    /// The same flag, for sharing with a `CancellationToken`.
+    pub fn cancel_handle(&self) -> Arc<AtomicBool> {
+        self.cancel.clone()
+    }
+
+    /// Records how the job can be continued should the app stop before it
+    /// finishes, and a hash of what it was asked to do.
+    pub fn set_resume(&self, resume: Resume, params_fingerprint: String) {
+        self.registry.store.update(&self.id, |d| {
+            d.resume = resume;
+            d.params_fingerprint = Some(params_fingerprint);
+        });
+    }
+
+    pub fn set_progress(&self, completed: usize, total: usize) {
+        self.registry.store.update(&self.id, |d| {
+            d.progress = Some(JobProgress { completed, total });
+        });
+    }
+
+    /// The emitter every event of this job must go through, throttled as
+    /// the debug settings say.
+    pub fn emitter(&self, app_handle: tauri::AppHandle, event: &'static str) -> JobEmitter {
+        use tauri::Manager;
+
+        let interval_ms = app_handle
+            .try_state::<SettingsStore>()
+            .map_or(DEFAULT_PROGRESS_INTERVAL_MS, |settings| {
+                settings.get().debug.progress_event_interval_ms
+            });
+        self.emitter_to(
+            Sink::App(app_handle),
+            event,
+            Duration::from_millis(interval_ms.into()),
+        )
+    }
+
+    fn emitter_to(&self, sink: Sink, event: &'static str, interval: Duration) -> JobEmitter {
+        JobEmitter {
+            inner: Arc::new(EmitterInner {
+                sink,
+                event,
+                job_id: self.id.clone(),
+                kind: self.kind,
+                window: self.window.clone(),
+                registry: self.registry.clone(),
+                interval,
+                state: Mutex::new(EmitState {
+                    next_seq: Some(0),
+                    last_sent: None,
+                    pending: None,
+                    flush_scheduled: false,
+                }),
+            }),
+        }
+    }
+}
+
+impl JobEmitter {
+    /// Emits progress, or holds it back if the last event went out less
+    /// than the interval ago; a held-back value is replaced by newer ones
+    /// and sent once the interval is up.
+    pub fn emit<T: Serialize>(&self, payload: &T) {
+        let inner = &self.inner;
+        let mut state = inner.state.lock().unwrap();
+        if state.next_seq.is_none() {
+            inner.drop_late();
+            return;
+        }
+        let wait = state.last_sent.map_or(Duration::ZERO, |sent| {
+            inner.interval.saturating_sub(sent.elapsed())
+        });
+        if wait.is_zero() {
+            state.pending = None;
+            inner.send(&mut state, payload, false);
+            return;
+        }
+        match serde_json::to_value(payload) {
+            Ok(value) => state.pending = Some(value),
+            Err(e) => log::warn!("Failed to serialize {}: {}", inner.event, e),
+        }
+        if !state.flush_scheduled {
+            state.flush_scheduled = true;
+            let inner = inner.clone();
+            tauri::async_runtime::spawn(async move {
+                tokio::time::sleep(wait).await;
+                let mut state = inner.state.lock().unwrap();
+                state.flush_scheduled = false;
+                if let Some(pending) = state.pending.take() {
+                    inner.send(&mut state, &pending, false);
+                }
+            });
+        }
+    }
+
+    /// Emits the job's last event, never throttled, and keeps it for
+    /// `get_job_final_state`. Progress still held back goes out first, so
+    /// the last progress the frontend sees is exact.
+    pub fn finish<T: Serialize>(&self, payload: &T) {
+        let inner = &self.inner;
+        let mut state = inner.state.lock().unwrap();
+        if state.next_seq.is_none() {
+            inner.drop_late();
+            return;
+        }
+        if let Some(pending) = state.pending.take() {
+            inner.send(&mut state, &pending, false);
+        }
+        inner.send(&mut state, payload, true);
+    }
+}
+
+impl EmitterInner {
+    fn drop_late(&self) {
+        log::warn!(
+            "Dropping {} for job {} after its terminal event",
+            self.event,
+            self.job_id
+        );
+    }
+
+    fn send<T: Serialize>(&self, state: &mut EmitState, payload: &T, terminal: bool) {
+        use tauri::Emitter;
+
+        let Some(seq) = state.next_seq else {
+            self.drop_late();
+            return;
+        };
+        state.next_seq = if terminal { None } else { Some(seq + 1) };
+        state.last_sent = Some(Instant::now());
+
+        let event = Sequenced {
+            seq,
+            terminal,
+            payload,
+        };
+        if terminal {
+            // Kept before emitting, so a frontend asking right after it sees
+            // the event can also look it up
+            match serde_json::to_value(&event) {
+                Ok(payload) => {
+                    let mut final_states = self.registry.final_states.lock().unwrap();
+                    if final_states.len() == MAX_FINAL_STATES {
+                        final_states.pop_front();
+                    }
+                    final_states.push_back(JobFinalState {
+                        job_id: self.job_id.clone(),
+                        kind: self.kind,
+                        event: self.event,
+                        finished_at: Utc::now(),
+                        payload,
+                    });
+                }
+                Err(e) => log::warn!("Failed to keep final state of job {}: {}", self.job_id, e),
+            }
+        }
+        let emitted = match (&self.sink, &self.window) {
+            (Sink::App(app_handle), Some(window)) => {
+                app_handle.emit_to(window.as_str(), self.event, &event)
+            }
+            (Sink::App(app_handle), None) => app_handle.emit(self.event, &event),
+            #[cfg(test)]
+            (Sink::Recorded(events), _) => {
+                events
+                    .lock()
+                    .unwrap()
+                    .push(serde_json::to_value(&event).unwrap());
+                Ok(())
+            }
+        };
+        if let Err(e) = emitted {
+            log::warn!("Failed to emit {}: {}", self.event, e);
+        }
+    }
+}
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        self.registry.jobs.lock().unwrap().remove(&self.id);
+        self.registry.store.finish(
+            &self.id,
+            self.registry.shutting_down.load(Ordering::Relaxed),
+        );
+        self.registry.finished.notify_waiters();
+    }
+}
+
+impl JobManager {
+    /// A manager persisting job descriptors to `path`; jobs found there
+    /// were interrupted and are offered through `recoverable`.
+    pub fn load(path: PathBuf) -> Self {
+        Self {
+            registry: Arc::new(Registry {
+                store: JobStore::load(path),
+                ..Registry::default()
+            }),
+        }
+    }
+
+    /// `window` is the label of the window starting the job, or `None` for
+    /// a job that belongs to the app.
+    pub fn register(
+        &self,
+        kind: JobKind,
+        label: impl Into<String>,
+        window: Option<&str>,
+    ) -> JobGuard {
+        let id = uuid::Uuid::new_v4().to_string();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let window = window.map(str::to_string);
+        let info = JobInfo {
+            id: id.clone(),
+            kind,
+            label: label.into(),
+            window: window.clone(),
+            started_at: Utc::now(),
+        };
+        self.registry.store.insert(JobDescriptor {
+            id: id.clone(),
+            kind,
+            label: info.label.clone(),
+            window: window.clone(),
+            started_at: info.started_at,
+            updated_at: info.started_at,
+            params_fingerprint: None,
+            resume: Resume::None,
+            progress: None,
+        });
+        self.registry.jobs.lock().unwrap().insert(
+            id.clone(),
+            ActiveJob {
+                info,
+                cancel: cancel.clone(),
+            },
+        );
+        JobGuard {
+            id,
+            kind,
+            window,
+            cancel,
+            registry: self.registry.clone(),
+        }
+    }
+
+    pub fn active(&self) -> Vec<JobInfo> {
+        let mut jobs: Vec<JobInfo> = self
+            .registry
+            .jobs
+            .lock()
+            .unwrap()
+            .values()
+            .map(|j| j.info.clone())
+            .collect();
+        jobs.sort_by_key(|j| j.started_at);
+        jobs
+    }
+
+    /// The running jobs `window` started.
+    pub fn active_for_window(&self, window: &str) -> Vec<JobInfo> {
+        let mut jobs = self.active();
+        jobs.retain(|j| j.window.as_deref() == Some(window));
+        jobs
+    }
+
+    /// Returns false if no such job is running.
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.registry.jobs.lock().unwrap().get(id) {
+            Some(job) => {
+                job.cancel.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn cancel_all(&self) -> usize {
+        let jobs = self.registry.jobs.lock().unwrap();
+        for job in jobs.values() {
+            job.cancel.store(true, Ordering::Relaxed);
+        }
+        jobs.len()
+    }
+
+    /// Cancels the jobs `window` started, leaving other windows' running.
+    pub fn cancel_window(&self, window: &str) -> usize {
+        let jobs = self.registry.jobs.lock().unwrap();
+        let owned: Vec<&ActiveJob> = jobs
+            .values()
+            .filter(|j| j.info.window.as_deref() == Some(window))
+            .collect();
+        for job in &owned {
+            job.cancel.store(true, Ordering::Relaxed);
+        }
+        owned.len()
+    }
+
+    /// Waits until no job is running; false if `timeout` passed first.
+    pub async fn wait_idle(&self, timeout: Duration) -> bool {
+        self.wait_until(timeout, |_| true).await
+    }
+
+    /// Waits until none of the jobs `window` started is running.
+    pub async fn wait_window_idle(&self, window: &str, timeout: Duration) -> bool {
+        self.wait_until(timeout, |job| job.info.window.as_deref() == Some(window))
+            .await
+    }
+
+    /// Waits until no job `counts` is running; false if `timeout` passed
+    /// first.
+    async fn wait_until(&self, timeout: Duration, counts: impl Fn(&ActiveJob) -> bool) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            // Registered before checking, so a job ending in between still wakes us
+            let finished = self.registry.finished.notified();
+            if !self.registry.jobs.lock().unwrap().values().any(&counts) {
+                return true;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if tokio::time::timeout(remaining, finished).await.is_err() {
+                return false;
+            }
+        }
+    }
+
+    /// The terminal event of `id`; `None` while it runs or once it is too
+    /// old to be kept.
+    pub fn final_state(&self, id: &str) -> Option<JobFinalState> {
+        self.registry
+            .final_states
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|f| f.job_id == id)
+            .cloned()
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.registry.shutting_down.load(Ordering::Relaxed)
+    }
+
+    /// Starts waiting for the frontend's decision on closing `window`;
+    /// `None` if a close of it is already waiting for one.
+    fn begin_close(&self, window: &str) -> Option<oneshot::Receiver<bool>> {
+        let mut pending = self.registry.pending_close.lock().unwrap();
+        if pending.get(window).is_some_and(|tx| !tx.is_closed()) {
+            return None;
+        }
+        let (tx, rx) = oneshot::channel();
+        pending.insert(window.to_string(), tx);
+        Some(rx)
+    }
+
+    fn decide_close(&self, window: &str, proceed: bool) -> bool {
+        match self.registry.pending_close.lock().unwrap().remove(window) {
+            Some(tx) => tx.send(proceed).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Cancels every job, gives them `CLEANUP_TIMEOUT` to delete partial
+    /// files and finalize manifests, then exits.
+    pub async fn shutdown(&self, app_handle: &tauri::AppHandle) {
+        self.wind_down(CLEANUP_TIMEOUT).await;
+        app_handle.exit(0);
+    }
+
+    /// Cancels every job for exit and waits up to `timeout` for them to
+    /// finish; false if some were still cleaning up.
+    async fn wind_down(&self, timeout: Duration) -> bool {
+        self.registry.shutting_down.store(true, Ordering::Relaxed);
+        let cancelled = self.cancel_all();
+        if cancelled == 0 {
+            return true;
+        }
+        log::info!("Cancelling {} job(s) before exit", cancelled);
+        let idle = self.wait_idle(timeout).await;
+        if !idle {
+            log::warn!(
+                "Exiting with {} job(s) still cleaning up",
+                self.active().len()
+            );
+        }
+        idle
+    }
+}
+
+/// Whether closing `window` closes the app, as it does for the last window.
+fn is_last_window(window: &tauri::Window) -> bool {
+    use tauri::Manager;
+
+    !crate::mini_player::is_mini_player(window.label())
+        && window
+            .app_handle()
+            .webview_windows()
+            .keys()
+            .filter(|label| !crate::mini_player::is_mini_player(label))
+            .all(|label| label == window.label())
+}
+
+/// The jobs a close of `window` would cancel: its own, or every job when it
+/// is the last window. Mini players don't count as windows here.
+pub fn jobs_closing_cancels(window: &tauri::Window) -> Vec<JobInfo> {
+    use tauri::Manager;
+
+    let jobs = window.state::<JobManager>();
+    if is_last_window(window) {
+        jobs.active()
+    } else {
+        jobs.active_for_window(window.label())
+    }
+}
+
+/// Called for a window close while jobs it would cancel are running: asks
+/// that window via `close-requested-with-active-jobs`, then goes ahead on
+/// confirmation or after `CONFIRM_TIMEOUT`. The last window shuts the app
+/// down; any other cancels only its own jobs, waits for their cleanup and
+/// closes. `confirm_close(false)` keeps the window open.
+pub fn close_with_active_jobs(window: tauri::Window) {
+    use tauri::{Emitter, Manager};
+
+    let jobs = window.state::<JobManager>();
+    let Some(decision) = jobs.begin_close(window.label()) else {
+        return;
+    };
+    let request = CloseRequest {
+        jobs: jobs_closing_cancels(&window),
+        timeout_ms: CONFIRM_TIMEOUT.as_millis() as u64,
+    };
+    if let Err(e) = window.emit_to(window.label(), "close-requested-with-active-jobs", request) {
+        log::warn!("Failed to emit close-requested-with-active-jobs: {}", e);
+    }
+
+    tauri::async_runtime::spawn(async move {
+        let proceed = match tokio::time::timeout(CONFIRM_TIMEOUT, decision).await {
+            Ok(Ok(proceed)) => proceed,
+            Ok(Err(_)) => true,
+            Err(_) => {
+                log::info!("No answer to close request, closing anyway");
+                true
+            }
+        };
+        if !proceed {
+            return;
+        }
+        let app_handle = window.app_handle().clone();
+        let jobs = app_handle.state::<JobManager>();
+        if is_last_window(&window) {
+            jobs.shutdown(&app_handle).await;
+            return;
+        }
+        let label = window.label().to_string();
+        let cancelled = jobs.cancel_window(&label);
+        log::info!("Cancelling {} job(s) of window {}", cancelled, label);
+        if !jobs.wait_window_idle(&label, CLEANUP_TIMEOUT).await {
+            log::warn!("Closing window {} with jobs still cleaning up", label);
+        }
+        if let Err(e) = window.destroy() {
+            log::warn!("Failed to close window {}: {}", label, e);
+        }
+    });
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn list_active_jobs(jobs: tauri::State<'_, JobManager>) -> Vec<JobInfo> {
+    jobs.active()
+}
+
+/// For reconciling after missing events: the last event of a finished job.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_job_final_state(
+    jobs: tauri::State<'_, JobManager>,
+    job_id: String,
+) -> Option<JobFinalState> {
+    jobs.final_state(&job_id)
+}
+
+/// A window's answer to `close-requested-with-active-jobs`: true cancels
+/// the jobs and closes it, false keeps it open.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn confirm_close(
+    window: tauri::Window,
+    jobs: tauri::State<'_, JobManager>,
+    proceed: bool,
+) -> bool {
+    jobs.decide_close(window.label(), proceed)
+}
+
+/// Exits at once: jobs are told to cancel but not waited for.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn force_quit(app_handle: tauri::AppHandle, jobs: tauri::State<'_, JobManager>) {
+    jobs.registry.shutting_down.store(true, Ordering::Relaxed);
+    jobs.cancel_all();
+    app_handle.exit(0);
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![
+        list_active_jobs,
+        get_job_final_state,
+        recovery::list_recoverable_jobs,
+        recovery::resume_job,
+        recovery::discard_job,
+        confirm_close,
+        force_quit,
+    ]
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+    use serde_json::{json, Value};
+
+    type Events = Arc<Mutex<Vec<Value>>>;
+
+    fn recording(guard: &JobGuard, interval: Duration) -> (JobEmitter, Events) {
+        let events = Events::default();
+        let emitter = guard.emitter_to(Sink::Recorded(events.clone()), "batch-progress", interval);
+        (emitter, events)
+    }
+
+    fn recorded(events: &Events) -> Vec<Value> {
+        events.lock().unwrap().clone()
+    }
+
+    /// A job running until cancelled, then taking `cleanup` to finish; the
+    /// flag is set once its cleanup has run.
+    fn fake_job(guard: JobGuard, cleanup: Duration) -> Arc<AtomicBool> {
+        let cleaned = Arc::new(AtomicBool::new(false));
+        let flag = cleaned.clone();
+        tokio::spawn(async move {
+            while !guard.is_cancelled() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            tokio::time::sleep(cleanup).await;
+            flag.store(true, Ordering::Relaxed);
+            drop(guard);
+        });
+        cleaned
+    }
+
+    #[tokio::test]
+    async fn exit_waits_for_cancelled_jobs_to_clean_up() {
+        let scratch = Scratch::new("jobs");
+        let manifest_path = scratch.0.join("batch_manifest.json");
+        std::fs::write(&manifest_path, "{}").unwrap();
+        let jobs = JobManager::load(scratch.0.join("jobs.json"));
+        let batch = jobs.register(JobKind::Batch, "out", Some("main"));
+        let batch_id = batch.id().to_string();
+        batch.set_resume(
+            Resume::BatchManifest {
+                manifest_path: manifest_path.clone(),
+            },
+            "params".to_string(),
+        );
+        let batch = fake_job(batch, Duration::from_millis(50));
+        let prefetch = fake_job(
+            jobs.register(JobKind::PreviewPrefetch, "previews", None),
+            Duration::ZERO,
+        );
+
+        assert!(jobs.wind_down(Duration::from_secs(5)).await);
+        assert!(batch.load(Ordering::Relaxed));
+        assert!(prefetch.load(Ordering::Relaxed));
+        assert!(jobs.active().is_empty());
+        assert!(jobs.is_shutting_down());
+
+        // The batch stopped by the exit is offered again; the prefetch isn't
+        let next = JobManager::load(scratch.0.join("jobs.json"));
+        let recoverable = next.recoverable();
+        assert_eq!(recoverable.len(), 1);
+        assert_eq!(recoverable[0].job.id, batch_id);
+    }
+
+    #[tokio::test]
+    async fn exit_gives_up_on_a_job_cleaning_up_too_long() {
+        let jobs = JobManager::default();
+        let slow = fake_job(
+            jobs.register(JobKind::Batch, "out", None),
+            Duration::from_secs(3600),
+        );
+        assert!(!jobs.wind_down(Duration::from_millis(20)).await);
+        assert!(!slow.load(Ordering::Relaxed));
+        assert_eq!(jobs.active().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn closing_a_window_waits_only_for_its_own_jobs() {
+        let jobs = JobManager::default();
+        let closing = fake_job(
+            jobs.register(JobKind::Batch, "out", Some("main")),
+            Duration::from_millis(20),
+        );
+        let other = jobs.register(JobKind::Batch, "other", Some("second"));
+        let app_wide = jobs.register(JobKind::PreviewPrefetch, "previews", None);
+
+        assert_eq!(jobs.cancel_window("main"), 1);
+        assert!(jobs.wait_window_idle("main", Duration::from_secs(5)).await);
+        assert!(closing.load(Ordering::Relaxed));
+        assert!(!other.is_cancelled());
+        assert!(!app_wide.is_cancelled());
+        let ids: Vec<String> = jobs.active().into_iter().map(|j| j.id).collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&other.id().to_string()));
+        assert!(!jobs.wait_idle(Duration::from_millis(10)).await);
+    }
+
+    #[test]
+    fn numbers_events_and_drops_any_after_the_terminal_one() {
+        let jobs = JobManager::default();
+        let guard = jobs.register(JobKind::Batch, "out", Some("main"));
+        let (emitter, events) = recording(&guard, Duration::ZERO);
+        emitter.emit(&json!({ "completed": 1 }));
+        emitter.emit(&json!({ "completed": 2 }));
+        emitter.finish(&json!({ "status": "completed" }));
+        emitter.emit(&json!({ "completed": 3 }));
+        emitter.finish(&json!({ "status": "cancelled" }));
+        assert_eq!(
+            recorded(&events),
+            [
+                json!({ "seq": 0, "terminal": false, "completed": 1 }),
+                json!({ "seq": 1, "terminal": false, "completed": 2 }),
+                json!({ "seq": 2, "terminal": true, "status": "completed" }),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_the_terminal_event_for_a_frontend_that_missed_it() {
+        let jobs = JobManager::default();
+        let guard = jobs.register(JobKind::ProjectArchive, "project.sclip", None);
+        let (emitter, events) = recording(&guard, Duration::ZERO);
+        emitter.emit(&json!({ "completed": 1 }));
+        assert!(jobs.final_state(guard.id()).is_none());
+
+        emitter.finish(&json!({ "status": "completed" }));
+        let state = jobs.final_state(guard.id()).unwrap();
+        assert_eq!(state.job_id, guard.id());
+        assert_eq!(state.kind, JobKind::ProjectArchive);
+        assert_eq!(state.event, "batch-progress");
+        assert_eq!(&state.payload, recorded(&events).last().unwrap());
+        assert!(jobs.final_state("no-such-job").is_none());
+    }
+
+    #[test]
+    fn forgets_the_oldest_final_states() {
+        let jobs = JobManager::default();
+        let ids: Vec<String> = (0..=MAX_FINAL_STATES)
+            .map(|_| {
+                let guard = jobs.register(JobKind::Batch, "out", None);
+                recording(&guard, Duration::ZERO).0.finish(&json!({}));
+                guard.id().to_string()
+            })
+            .collect();
+        assert!(jobs.final_state(&ids[0]).is_none());
+        assert!(jobs.final_state(&ids[1]).is_some());
+        assert!(jobs.final_state(&ids[MAX_FINAL_STATES]).is_some());
+    }
+
+    #[test]
+    fn holds_back_progress_and_flushes_the_newest_before_the_end() {
+        let jobs = JobManager::default();
+        let guard = jobs.register(JobKind::Batch, "out", Some("main"));
+        let (emitter, events) = recording(&guard, Duration::from_secs(3600));
+        for completed in 1..=3 {
+            emitter.emit(&json!({ "completed": completed }));
+        }
+        assert_eq!(recorded(&events).len(), 1);
+        emitter.finish(&json!({ "status": "completed" }));
+        assert_eq!(
+            recorded(&events),
+            [
+                json!({ "seq": 0, "terminal": false, "completed": 1 }),
+                json!({ "seq": 1, "terminal": false, "completed": 3 }),
+                json!({ "seq": 2, "terminal": true, "status": "completed" }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_held_back_progress_once_the_interval_is_up() {
+        let jobs = JobManager::default();
+        let guard = jobs.register(JobKind::Batch, "out", Some("main"));
+        let (emitter, events) = recording(&guard, Duration::from_millis(20));
+        for completed in 1..=3 {
+            emitter.emit(&json!({ "completed": completed }));
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let completed: Vec<Value> = recorded(&events)
+            .iter()
+            .map(|e| e["completed"].clone())
+            .collect();
+        assert_eq!(completed, [1, 3]);
+
+        // Long enough after the last one goes out at once
+        emitter.emit(&json!({ "completed": 4 }));
+        assert_eq!(recorded(&events).len(), 3);
+        assert_eq!(recorded(&events)[2]["seq"], 2);
+    }
+}
@@ -0,0 +1,353 @@
+// Job descriptors kept on disk while jobs run, so a batch cut short by a
+// crash or a close can be found and resumed on the next launch
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tauri::Manager;
+
+use super::{JobKind, JobManager};
+use crate::batch::BatchManifest;
+use crate::cache::write_atomic;
+use crate::cancellation::CancellationRegistry;
+use crate::error::AppError;
+use crate::paths::roots::{AllowedRoots, Write};
+use crate::paths::ExportedPaths;
+use crate::read_only::WriteAccess;
+use crate::settings::SettingsStore;
+use crate::tts::TtsService;
+
+/// How an interrupted job can be continued.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Resume {
+    /// Nothing to continue from, e.g. a pure network call; reported as
+    /// failed after a restart.
+    None,
+    /// Continued by `resume_batch` from its manifest.
+    BatchManifest { manifest_path: PathBuf },
+    /// Started again by `resume_job`, which keeps the copies already made.
+    AudioConform {
+        project_path: PathBuf,
+        sample_rate: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobProgress {
+    pub completed: usize,
+    pub total: usize,
+}
+
+/// What is persisted about a job. Only labels, paths and hashes: never
+/// request text, credentials or URLs with their query.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobDescriptor {
+    pub id: String,
+    pub kind: JobKind,
+    pub label: String,
+    pub window: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Hash of what the job was asked to do, so a resume can tell whether
+    /// the work changed since.
+    pub params_fingerprint: Option<String>,
+    pub resume: Resume,
+    pub progress: Option<JobProgress>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecoveryState {
+    /// `resume_job` can continue it.
+    Resumable,
+    /// Lost with the previous session; only reported.
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecoverableJob {
+    pub job: JobDescriptor,
+    pub state: RecoveryState,
+    pub reason: Option<String>,
+}
+
+/// Payload of `jobs-recoverable`.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobsRecoverable {
+    pub jobs: Vec<RecoverableJob>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ResumedJob {
+    Batch { manifest: Box<BatchManifest> },
+    AudioConform { job_id: String },
+}
+
+#[derive(Default)]
+struct Descriptors {
+    /// Jobs running now.
+    active: BTreeMap<String, JobDescriptor>,
+    /// Left by the last session and not yet resumed or discarded.
+    recoverable: BTreeMap<String, JobDescriptor>,
+    /// Left by the last session but not resumable; kept for this session
+    /// only.
+    failed: Vec<RecoverableJob>,
+}
+
+/// jobs.json: every job that would need recovering if the app stopped now.
+/// Without a path, as in `JobManager::default`, nothing is persisted.
+#[derive(Default)]
+pub(super) struct JobStore {
+    path: Option<PathBuf>,
+    descriptors: Mutex<Descriptors>,
+}
+
+/// Why `descriptor` can't be resumed, if it can't.
+fn not_resumable(descriptor: &JobDescriptor) -> Option<String> {
+    match &descriptor.resume {
+        Resume::None => Some("the job can't be resumed and has to be started again".to_string()),
+        Resume::BatchManifest { manifest_path } if !manifest_path.is_file() => Some(format!(
+            "batch manifest {} no longer exists",
+            manifest_path.display()
+        )),
+        Resume::AudioConform { project_path, .. } if !project_path.is_file() => Some(format!(
+            "project {} no longer exists",
+            project_path.display()
+        )),
+        Resume::BatchManifest { .. } | Resume::AudioConform { .. } => None,
+    }
+}
+
+impl JobStore {
+    /// Reads what the last session left: every job in the file was
+    /// interrupted, since finished jobs are removed from it.
+    pub(super) fn load(path: PathBuf) -> Self {
+        let left: Vec<JobDescriptor> = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                log::warn!("Ignoring unreadable job file {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        let mut descriptors = Descriptors::default();
+        for job in left {
+            match not_resumable(&job) {
+                Some(reason) => descriptors.failed.push(RecoverableJob {
+                    job,
+                    state: RecoveryState::Failed,
+                    reason: Some(reason),
+                }),
+                None => {
+                    descriptors.recoverable.insert(job.id.clone(), job);
+                }
+            }
+        }
+        if !descriptors.recoverable.is_empty() || !descriptors.failed.is_empty() {
+            log::info!(
+                "{} interrupted job(s) can be resumed, {} were lost",
+                descriptors.recoverable.len(),
+                descriptors.failed.len()
+            );
+        }
+        let store = Self {
+            path: Some(path),
+            descriptors: Mutex::new(descriptors),
+        };
+        store.save(&store.descriptors.lock().unwrap());
+        store
+    }
+
+    fn save(&self, descriptors: &Descriptors) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let all: Vec<&JobDescriptor> = descriptors
+            .recoverable
+            .values()
+            .chain(descriptors.active.values())
+            .collect();
+        let written = serde_json::to_vec_pretty(&all)
+            .map_err(std::io::Error::other)
+            .and_then(|json| write_atomic(path, &json));
+        if let Err(e) = written {
+            log::warn!("Failed to write job file {}: {}", path.display(), e);
+        }
+    }
+
+    pub(super) fn insert(&self, descriptor: JobDescriptor) {
+        let mut descriptors = self.descriptors.lock().unwrap();
+        descriptors.active.insert(descriptor.id.clone(), descriptor);
+        self.save(&descriptors);
+    }
+
+    pub(super) fn update(&self, id: &str, f: impl FnOnce(&mut JobDescriptor)) {
+        let mut descriptors = self.descriptors.lock().unwrap();
+        if let Some(descriptor) = descriptors.active.get_mut(id) {
+            f(descriptor);
+            descriptor.updated_at = Utc::now();
+            self.save(&descriptors);
+        }
+    }
+
+    /// Forgets a finished job. During shutdown a resumable job is kept
+    /// instead, so the next launch offers to resume it.
+    pub(super) fn finish(&self, id: &str, shutting_down: bool) {
+        let mut descriptors = self.descriptors.lock().unwrap();
+        let keep = shutting_down
+            && descriptors
+                .active
+                .get(id)
+                .is_some_and(|d| not_resumable(d).is_none());
+        if !keep && descriptors.active.remove(id).is_some() {
+            self.save(&descriptors);
+        }
+    }
+
+    fn recoverable(&self) -> Vec<RecoverableJob> {
+        let descriptors = self.descriptors.lock().unwrap();
+        let resumable = descriptors.recoverable.values().map(|job| RecoverableJob {
+            job: job.clone(),
+            state: RecoveryState::Resumable,
+            reason: None,
+        });
+        resumable
+            .chain(descriptors.failed.iter().cloned())
+            .collect()
+    }
+
+    fn take(&self, id: &str) -> Option<JobDescriptor> {
+        let mut descriptors = self.descriptors.lock().unwrap();
+        let taken = descriptors.recoverable.remove(id);
+        if taken.is_some() {
+            self.save(&descriptors);
+        }
+        taken
+    }
+
+    fn discard(&self, id: &str) -> bool {
+        if self.take(id).is_some() {
+            return true;
+        }
+        let mut descriptors = self.descriptors.lock().unwrap();
+        let before = descriptors.failed.len();
+        descriptors.failed.retain(|f| f.job.id != id);
+        descriptors.failed.len() < before
+    }
+}
+
+impl JobManager {
+    /// Jobs the last session left unfinished, resumable ones first.
+    pub fn recoverable(&self) -> Vec<RecoverableJob> {
+        self.registry.store.recoverable()
+    }
+}
+
+/// Tells the frontend about interrupted jobs at startup; a window that
+/// loads later gets the same list from `list_recoverable_jobs`.
+pub fn announce(app_handle: &tauri::AppHandle, jobs: &JobManager) {
+    use tauri::Emitter;
+
+    let recoverable = jobs.recoverable();
+    if recoverable.is_empty() {
+        return;
+    }
+    let payload = JobsRecoverable { jobs: recoverable };
+    if let Err(e) = app_handle.emit("jobs-recoverable", payload) {
+        log::warn!("Failed to emit jobs-recoverable: {}", e);
+    }
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn list_recoverable_jobs(jobs: tauri::State<'_, JobManager>) -> Vec<RecoverableJob> {
+    jobs.recoverable()
+}
+
+/// Continues an interrupted job the way its kind resumes, as a job of the
+/// calling window; a batch goes through `resume_batch` with its manifest,
+/// an audio conform starts over and skips the copies it already made.
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn resume_job(
+    app_handle: tauri::AppHandle,
+    window: tauri::Window,
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+    jobs: tauri::State<'_, JobManager>,
+    cancellation: tauri::State<'_, CancellationRegistry>,
+    exported: tauri::State<'_, ExportedPaths>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    job_id: String,
+    cancel_token: Option<String>,
+) -> Result<ResumedJob, AppError> {
+    let not_recoverable = |reason: String| AppError::JobNotRecoverable {
+        job_id: job_id.clone(),
+        reason,
+    };
+    let descriptor = jobs
+        .registry
+        .store
+        .take(&job_id)
+        .ok_or_else(|| not_recoverable("no interrupted job with this id".to_string()))?;
+    match descriptor.resume {
+        Resume::BatchManifest { manifest_path } => {
+            log::info!("Resuming batch {} from {}", job_id, manifest_path.display());
+            let manifest = crate::batch::resume_batch(
+                app_handle.clone(),
+                window,
+                tts,
+                settings,
+                jobs,
+                cancellation,
+                exported,
+                access,
+                roots,
+                manifest_path,
+                None,
+                cancel_token,
+                None,
+                app_handle.state(),
+            )
+            .await?;
+            Ok(ResumedJob::Batch {
+                manifest: Box::new(manifest),
+            })
+        }
+        Resume::AudioConform {
+            project_path,
+            sample_rate,
+        } => {
+            access.check()?;
+            log::info!(
+                "Resuming conform of {} to {} Hz",
+                project_path.display(),
+                sample_rate
+            );
+            let project_path = roots.validate_path::<Write>(&project_path)?.into_path_buf();
+            let job_id = crate::project::audit::start_conform(
+                app_handle,
+                &window,
+                &jobs,
+                project_path,
+                sample_rate,
+            )?;
+            Ok(ResumedJob::AudioConform { job_id })
+        }
+        Resume::None => Err(not_recoverable(
+            "the job can't be resumed and has to be started again".to_string(),
+        )),
+    }
+}
+
+/// Forgets an interrupted job; false if there is none with this id.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn discard_job(jobs: tauri::State<'_, JobManager>, job_id: String) -> bool {
+    jobs.registry.store.discard(&job_id)
+}
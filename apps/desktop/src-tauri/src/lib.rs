@@ -3,268 +3,316 @@
     windows_subsystem = "windows"
 )]
 
-use gcloud_sdk::google::cloud::texttospeech::v1::{
-    text_to_speech_client::TextToSpeechClient, AudioConfig, AudioEncoding, ListVoicesRequest,
-    SsmlVoiceGender, SynthesisInput, SynthesizeSpeechRequest, VoiceSelectionParams,
-};
-use gcloud_sdk::{GoogleApi, GoogleAuthMiddleware};
-use std::env;
+use tauri::{Emitter, Manager};
 
-use tauri::Manager;
+mod assets;
+mod audio;
+mod batch;
+mod cache;
+mod cancellation;
+pub mod cli;
+mod commands;
+mod credentials;
+mod error;
+mod format;
+mod jobs;
+mod long_path;
+mod metrics;
+mod migrations;
+mod mini_player;
+mod onboarding;
+mod paths;
+mod project;
+mod read_only;
+#[cfg(test)]
+mod scratch;
+mod script;
+mod settings;
+mod shutdown;
+mod sidecar;
+mod spelling;
+mod ssml;
+mod support;
+mod trash;
+mod tts;
+mod voices;
+mod watch_folder;
+mod window_scope;
+
+/// The mock TTS server, for frontend work without credentials.
+#[cfg(feature = "mock-tts")]
+pub use tts::mock;
+
+use cancellation::CancellationRegistry;
+use jobs::JobManager;
+use read_only::WriteAccess;
+use settings::SettingsStore;
+use shutdown::ShutdownController;
+use sidecar::events::EventBridge;
+use sidecar::install::BackendInstaller;
+use sidecar::{SidecarLaunch, SidecarManager};
+use trash::Trash;
+use tts::usage::UsageLedger;
+use tts::TtsService;
+use voices::capabilities::CapabilityStore;
+use voices::changes::VoiceCatalog;
+use window_scope::WindowScopes;
 
 // Tools module moved to Python backend
 // All AI orchestration is now handled by the sidecar Python backend
 
-#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
-struct GoogleVoice {
-    name: String,
-    display_name: String,
-    language_codes: Vec<String>,
-    language_name: String,
-    gender: String,
-    technology: String,
-    preview_path: String,
-}
-
-// Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
-#[tauri::command]
-fn greet(name: &str) -> String {
-    format!("Hello, {}! You've been greeted from Rust!", name)
+/// Installs the default crypto provider for rustls
+fn install_crypto_provider() {
+    rustls::crypto::ring::default_provider()
+        .install_default()
+        .expect("Failed to install rustls crypto provider");
 }
 
-// AI Orchestrator commands moved to Python backend
-// These commands are now handled by the sidecar Python backend with SclipBrain orchestrator
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    install_crypto_provider();
 
-fn get_language_display_name(lang_code: &str) -> String {
-    match lang_code {
-        "af-ZA" => "Afrikaans (South Africa)".to_string(),
-        "ar-XA" => "Arabic".to_string(),
-        "eu-ES" => "Basque (Spain)".to_string(),
-        "bn-IN" => "Bengali (India)".to_string(),
-        "bg-BG" => "Bulgarian (Bulgaria)".to_string(),
-        "ca-ES" => "Catalan (Spain)".to_string(),
-        "yue-HK" => "Chinese (Hong Kong)".to_string(),
-        "cs-CZ" => "Czech (Czech Republic)".to_string(),
-        "da-DK" => "Danish (Denmark)".to_string(),
-        "nl-BE" => "Dutch (Belgium)".to_string(),
-        "nl-NL" => "Dutch (Netherlands)".to_string(),
-        "en-AU" => "English (Australia)".to_string(),
-        "en-IN" => "English (India)".to_string(),
-        "en-GB" => "English (UK)".to_string(),
-        "en-US" => "English (US)".to_string(),
-        "fil-PH" => "Filipino (Philippines)".to_string(),
-        "fi-FI" => "Finnish (Finland)".to_string(),
-        "fr-CA" => "French (Canada)".to_string(),
-        "fr-FR" => "French (France)".to_string(),
-        "gl-ES" => "Galician (Spain)".to_string(),
-        "de-DE" => "German (Germany)".to_string(),
-        "el-GR" => "Greek (Greece)".to_string(),
-        "gu-IN" => "Gujarati (India)".to_string(),
-        "he-IL" => "Hebrew (Israel)".to_string(),
-        "hi-IN" => "Hindi (India)".to_string(),
-        "hu-HU" => "Hungarian (Hungary)".to_string(),
-        "is-IS" => "Icelandic (Iceland)".to_string(),
-        "id-ID" => "Indonesian (Indonesia)".to_string(),
-        "it-IT" => "Italian (Italy)".to_string(),
-        "ja-JP" => "Japanese (Japan)".to_string(),
-        "kn-IN" => "Kannada (India)".to_string(),
-        "ko-KR" => "Korean (South Korea)".to_string(),
-        "lv-LV" => "Latvian (Latvia)".to_string(),
-        "lt-LT" => "Lithuanian (Lithuania)".to_string(),
-        "ms-MY" => "Malay (Malaysia)".to_string(),
-        "ml-IN" => "Malayalam (India)".to_string(),
-        "cmn-CN" => "Mandarin Chinese (China)".to_string(),
-        "cmn-TW" => "Mandarin Chinese (Taiwan)".to_string(),
-        "mr-IN" => "Marathi (India)".to_string(),
-        "nb-NO" => "Norwegian (Norway)".to_string(),
-        "pl-PL" => "Polish (Poland)".to_string(),
-        "pt-BR" => "Portuguese (Brazil)".to_string(),
-        "pt-PT" => "Portuguese (Portugal)".to_string(),
-        "pa-IN" => "Punjabi (India)".to_string(),
-        "ro-RO" => "Romanian (Romania)".to_string(),
-        "ru-RU" => "Russian (Russia)".to_string(),
-        "sr-RS" => "Serbian (Serbia)".to_string(),
-        "sk-SK" => "Slovak (Slovakia)".to_string(),
-        "es-ES" => "Spanish (Spain)".to_string(),
-        "es-US" => "Spanish (US)".to_string(),
-        "sv-SE" => "Swedish (Sweden)".to_string(),
-        "ta-IN" => "Tamil (India)".to_string(),
-        "te-IN" => "Telugu (India)".to_string(),
-        "th-TH" => "Thai (Thailand)".to_string(),
-        "tr-TR" => "Turkish (Turkey)".to_string(),
-        "uk-UA" => "Ukrainian (Ukraine)".to_string(),
-        "vi-VN" => "Vietnamese (Vietnam)".to_string(),
-        _ => lang_code.to_string(),
-    }
-}
+    tauri::Builder::default()
+        .plugin(tauri_plugin_log::Builder::new().build())
+        .plugin(tauri_plugin_opener::init())
+        .register_uri_scheme_protocol(assets::SCHEME, assets::handle)
+        .setup(|app| {
+            let config_dir = app.path().app_config_dir()?;
+            let data_dir = app.path().app_data_dir()?;
+            app.manage(ShutdownController::default());
+            let app_paths = paths::AppPaths::resolve(app.handle())?;
+            let access = WriteAccess::probe(&app_paths);
+            let read_only = access.is_read_only();
+            app.manage(access);
+            app.manage(paths::roots::AllowedRoots::new(
+                &app_paths,
+                app.path().resource_dir().ok().as_deref(),
+            ));
+            // Before anything reads the caches, so they only see the current layout
+            app.manage(migrations::Migrations::run(
+                data_dir.join("migrations.json"),
+                &migrations::MigrationContext {
+                    data_dir: data_dir.clone(),
+                    cache_dir: app.path().app_cache_dir()?,
+                    resource_dir: app.path().resource_dir().ok(),
+                },
+            ));
+            let settings = SettingsStore::load(config_dir.join("settings.json"));
+            settings.set_read_only(read_only);
+            let remap = voices::remap::VoiceRemap::from_settings(&settings.get());
+            let mut remapped = settings.get();
+            let changes = remap.apply_to_settings(&mut remapped);
+            if !changes.is_empty() {
+                if let Err(e) = settings.update(|s| *s = remapped) {
+                    log::warn!("Failed to save remapped voices: {}", e);
+                }
+                voices::remap::announce(app.handle(), "settings", changes);
+            }
+            let credentials = credentials::startup_credentials(&settings.get());
+            let quota_project = settings.get().quota_project;
+            let recorder = tts::recording::Recorder::new(
+                data_dir.join("tts_recordings"),
+                settings.get().tts_recording,
+            );
+            let ssml_policy = settings.get().ssml_policy;
+            let spend_caps = settings.get().spend_caps;
+            let backend_performance = settings.get().backend_performance;
+            let backend_extra_env = settings.get().backend_extra_env;
+            paths::grants::restore(
+                &app.state::<paths::roots::AllowedRoots>(),
+                &settings.get().directory_grants,
+            );
+            let watcher = watch_folder::FolderWatcher::default();
+            watcher.apply(app.handle(), &settings.get().watch_folder);
+            app.manage(watcher);
+            let app_handle = app.handle().clone();
+            settings.set_edit_listener(move |edit| settings::announce(&app_handle, edit));
+            app.manage(settings);
 
-#[tauri::command]
-async fn list_google_voices(app_handle: tauri::AppHandle) -> Result<Vec<GoogleVoice>, String> {
-    // This assumes you have set up application-default credentials.
-    // Typically, this means pointing the GOOGLE_APPLICATION_CREDENTIALS
-    // environment variable to your service account key file.
-    let client: GoogleApi<TextToSpeechClient<GoogleAuthMiddleware>> =
-        GoogleApi::from_function(
-            TextToSpeechClient::new,
-            "https://texttospeech.googleapis.com",
-            None,
-        )
-        .await
-        .map_err(|e| e.to_string())?;
+            let installer = BackendInstaller::new(data_dir.join("backend"));
+            let sidecar = SidecarManager::new(
+                sidecar::DEFAULT_PORT,
+                SidecarLaunch::locate(
+                    app.path().resource_dir().ok().as_deref(),
+                    installer.installed_dir().as_deref(),
+                ),
+                backend_performance,
+                app_paths.cache_dir.join(sidecar::proxy::SPOOL_DIR),
+            );
+            sidecar.set_extra_env(backend_extra_env);
+            if !read_only {
+                match sidecar::proxy::sweep_spool(sidecar.spool_dir()) {
+                    Ok(swept) if swept > 0 => log::info!(
+                        "Deleted {} backend responses spooled by an earlier session",
+                        swept
+                    ),
+                    Ok(_) => {}
+                    Err(e) => log::warn!("Backend spool sweep failed: {}", e),
+                }
+            }
+            app.manage(installer);
+            app.manage(sidecar);
+            let jobs = JobManager::load(data_dir.join("jobs.json"));
+            jobs::recovery::announce(app.handle(), &jobs);
+            app.manage(jobs);
+            app.manage(CancellationRegistry::default());
+            app.manage(metrics::CommandStats::default());
+            app.manage(paths::ExportedPaths::default());
+            app.manage(WindowScopes::default());
+            app.manage(audio::arbitration::PlaybackArbiter::default());
+            app.manage(audio::ab_preview::AbPreviewPlayer::default());
+            app.manage(audio::record::Recorder::default());
+            app.manage(mini_player::MiniPlayers::load(
+                data_dir.join("mini_player.json"),
+            ));
+            app.manage(voices::prefetch::PrefetchJobs::default());
+            app.manage(spelling::dictionaries::DictionaryStore::new(
+                data_dir.join("dictionaries"),
+                app.path()
+                    .resource_dir()
+                    .ok()
+                    .map(|dir| dir.join(spelling::dictionaries::BUNDLED_DIR)),
+            ));
+            app.manage(tts::history::SynthesisHistory::load(
+                data_dir.join("synthesis_history.json"),
+            ));
+            app.manage(VoiceCatalog::load(data_dir.join("voice_catalog.json")));
+            app.manage(CapabilityStore::load(
+                data_dir.join("voice_capabilities.json"),
+            ));
+            let trash = Trash::load(data_dir.join("trash"));
+            // Sweeping deletes, which read-only mode can't
+            if !read_only {
+                match trash.enforce_cap(trash::MAX_TRASH_BYTES) {
+                    Ok(swept) if swept.entries > 0 => log::info!(
+                        "Trash over its size cap: deleted {} oldest entries ({} bytes)",
+                        swept.entries,
+                        swept.bytes
+                    ),
+                    Ok(_) => {}
+                    Err(e) => log::warn!("Trash sweep failed: {}", e),
+                }
+            }
+            app.manage(trash);
+            let app_handle = app.handle().clone();
+            app.manage(EventBridge::new(move |event, payload| {
+                if let Err(e) = app_handle.emit(event, payload) {
+                    log::warn!("Failed to emit {}: {}", event, e);
+                }
+            }));
+            let app_handle = app.handle().clone();
+            let shutdown = app.state::<ShutdownController>();
+            shutdown.spawn("backend supervisor", |mut signal| async move {
+                let sidecar = app_handle.state::<SidecarManager>();
+                if let Err(e) = sidecar.start().await {
+                    log::error!("Failed to start backend: {}", e);
+                }
+                let watchdog = sidecar::watchdog::run(
+                    &sidecar,
+                    || app_handle.state::<SettingsStore>().get().backend_watchdog,
+                    |event, payload| {
+                        if let Err(e) = app_handle.emit(event, payload) {
+                            log::warn!("Failed to emit {}: {}", event, e);
+                        }
+                    },
+                );
+                let bridge = app_handle.state::<EventBridge>();
+                // The backend itself is stopped by the exit handler
+                tokio::select! {
+                    _ = async { tokio::join!(bridge.run(&sidecar), watchdog) } => {}
+                    _ = signal.triggered() => {}
+                }
+            });
 
-    let response_result = client
-        .get()
-        .list_voices(ListVoicesRequest {
-            ..Default::default()
+            let app_handle = app.handle().clone();
+            let queue_handle = app.handle().clone();
+            let tts_service = TtsService::new(
+                credentials,
+                quota_project,
+                recorder,
+                UsageLedger::load(data_dir.join("usage.json")),
+                move |report| {
+                    if let Err(e) = app_handle.emit("tts-connection-status", report) {
+                        log::warn!("Failed to emit tts-connection-status: {}", e);
+                    }
+                },
+            )
+            .with_voice_list(data_dir.join(tts::voice_list::FILE_NAME))
+            .with_shared_slots(data_dir.join(tts::SLOTS_DIR))
+            .with_queue_listener(move |status| {
+                if let Err(e) = queue_handle.emit("tts-queue-status", status) {
+                    log::warn!("Failed to emit tts-queue-status: {}", e);
+                }
+            });
+            tts_service.set_ssml_policy(ssml_policy);
+            tts_service.set_voice_remap(remap);
+            tts_service.spend().set_caps(spend_caps);
+            app.manage(match tts::endpoint_override() {
+                Some(endpoint) => tts_service.with_endpoint(endpoint),
+                None => tts_service,
+            });
+            // A stored list, e.g. the starter snapshot on a first run, is shown
+            // right away and replaced once a fresh one arrives
+            let app_handle = app.handle().clone();
+            shutdown.spawn("voice list refresh", |mut signal| async move {
+                let tts = app_handle.state::<TtsService>();
+                let refreshed = tokio::select! {
+                    refreshed = tts.refresh_stored_voices() => refreshed,
+                    _ = signal.triggered() => return,
+                };
+                match refreshed {
+                    Ok(true) => {
+                        if let Err(e) = app_handle.emit("voices-refreshed", ()) {
+                            log::warn!("Failed to emit voices-refreshed: {}", e);
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(e) => log::warn!("Background voice list refresh failed: {}", e),
+                }
+            });
+            let app_handle = app.handle().clone();
+            shutdown.spawn("settings file watch", |signal| {
+                settings::watch_file(app_handle, signal)
+            });
+            Ok(())
         })
-        .await;
-
-    match response_result {
-        Ok(response) => {
-            let voices = response.into_inner().voices;
-            let filtered_voices = voices
-                .into_iter()
-                .filter(|v| {
-                    let name_lower = v.name.to_lowercase();
-                    name_lower.contains("neural2") ||
-                    name_lower.contains("wavenet") ||
-                    name_lower.contains("polyglot") ||
-                    name_lower.contains("standard")
-                })
-                .map(|v| {
-                    let name_parts: Vec<&str> = v.name.split('-').collect();
-                    let technology = name_parts.get(2).cloned().unwrap_or("Standard").to_string();
-                    let language_code = v.language_codes.first().cloned().unwrap_or_default();
-                    let language_name = get_language_display_name(&language_code);
-                    let display_name = format!("{} {}", language_name.split('(').next().unwrap_or("").trim(), name_parts.last().cloned().unwrap_or(""));
-
-                    let gender = SsmlVoiceGender::try_from(v.ssml_gender)
-                        .map(|g| format!("{:?}", g))
-                        .unwrap_or_else(|_| "Neutral".to_string());
-
-                    let preview_path = app_handle
-                        .path()
-                        .resolve(
-                            format!("../resources/preview_cache/voice_{}.mp3", v.name),
-                            tauri::path::BaseDirectory::Resource,
-                        )
-                        .map_or_else(
-                            |_| String::new(),
-                            |p| p.to_string_lossy().to_string(),
+        .on_window_event(|window, event| {
+            mini_player::on_window_event(window, event);
+            match event {
+                tauri::WindowEvent::CloseRequested { api, .. } => {
+                    let jobs = window.state::<JobManager>();
+                    if !jobs.is_shutting_down() && !jobs::jobs_closing_cancels(window).is_empty() {
+                        api.prevent_close();
+                        jobs::close_with_active_jobs(window.clone());
+                    }
+                }
+                tauri::WindowEvent::Destroyed => {
+                    // Only this window's state and jobs; other windows keep theirs
+                    window.state::<WindowScopes>().remove(window.label());
+                    let cancelled = window.state::<JobManager>().cancel_window(window.label());
+                    if cancelled > 0 {
+                        log::info!(
+                            "Cancelled {} job(s) of closed window {}",
+                            cancelled,
+                            window.label()
                         );
-
-                    GoogleVoice {
-                        display_name,
-                        language_name,
-                        technology,
-                        name: v.name,
-                        language_codes: v.language_codes,
-                        gender,
-                        preview_path,
                     }
-                })
-                .collect();
-            Ok(filtered_voices)
-        }
-        Err(e) => {
-            return Err(e.to_string());
-        }
-    }
-}
-
-#[tauri::command]
-async fn synthesize_speech(
-    voice_name: String, 
-    language_code: String, 
-    text: String
-) -> Result<Vec<u8>, String> {
-    let client: GoogleApi<TextToSpeechClient<GoogleAuthMiddleware>> =
-        GoogleApi::from_function(
-            TextToSpeechClient::new,
-            "https://texttospeech.googleapis.com",
-            None,
-        )
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let synthesis_input = SynthesisInput {
-        input_source: Some(gcloud_sdk::google::cloud::texttospeech::v1::synthesis_input::InputSource::Text(text)),
-        custom_pronunciations: None,
-    };
-
-    let voice = VoiceSelectionParams {
-        language_code,
-        name: voice_name,
-        ssml_gender: SsmlVoiceGender::Unspecified as i32,
-        custom_voice: None,
-        voice_clone: None,
-    };
-
-    let audio_config = AudioConfig {
-        audio_encoding: AudioEncoding::Mp3 as i32,
-        speaking_rate: 1.0,
-        pitch: 0.0,
-        volume_gain_db: 0.0,
-        sample_rate_hertz: 0,
-        effects_profile_id: vec![],
-    };
-
-    let request = SynthesizeSpeechRequest {
-        input: Some(synthesis_input),
-        voice: Some(voice),
-        audio_config: Some(audio_config),
-        advanced_voice_options: None,
-    };
-
-    let response_result = client
-        .get()
-        .synthesize_speech(request)
-        .await;
-
-    match response_result {
-        Ok(response) => {
-            let audio_content = response.into_inner().audio_content;
-            Ok(audio_content)
-        }
-        Err(e) => {
-            Err(e.to_string())
-        }
-    }
-}
-
-
-
-#[tauri::command]
-async fn get_voice_preview_audio(app_handle: tauri::AppHandle, voice_name: String) -> Result<Vec<u8>, String> {
-    use std::fs;
-    
-    // Look for static voice preview files in the resources/preview_cache directory
-    let resource_dir = app_handle.path().resource_dir().map_err(|e| e.to_string())?;
-    let cache_dir = resource_dir.join("preview_cache");
-    let file_path = cache_dir.join(format!("voice_{}.mp3", voice_name));
-    
-    if file_path.exists() {
-        fs::read(&file_path).map_err(|e| e.to_string())
-    } else {
-        Err(format!("Voice preview file not found: voice_{}.mp3", voice_name))
-    }
-}
-
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    // Install the default crypto provider for rustls
-    rustls::crypto::ring::default_provider()
-        .install_default()
-        .expect("Failed to install rustls crypto provider");
-
-    tauri::Builder::default()
-        .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![
-            greet,
-            list_google_voices,
-            synthesize_speech,
-            get_voice_preview_audio
-        ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+                }
+                _ => {}
+            }
+        })
+        .invoke_handler(commands::handler())
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app_handle, event| {
+            if let tauri::RunEvent::Exit = event {
+                tauri::async_runtime::block_on(async {
+                    app_handle
+                        .state::<ShutdownController>()
+                        .shutdown(shutdown::TASK_BUDGET)
+                        .await;
+                    app_handle.state::<SidecarManager>().stop().await;
+                });
+            }
+        });
 }
@@ -0,0 +1,274 @@
+// Staying inside path length limits: Windows' 260-character MAX_PATH, which
+// deep OneDrive folders run into, and the 255-unit file name limit everywhere
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+use crate::error::AppError;
+
+/// Longest file name (one path component) any supported filesystem allows.
+pub const MAX_COMPONENT_LEN: usize = 255;
+
+/// Longest whole path Explorer and most Windows programs can open: MAX_PATH
+/// less the terminating NUL.
+#[cfg(windows)]
+const WINDOWS_MAX_PATH: usize = 259;
+
+/// Room left for the suffixes written next to a file: ".tmp" by
+/// `write_atomic`, ".partial" by exports, "-99" by `UniqueNames`.
+const SUFFIX_RESERVE: usize = 8;
+
+/// Shortened names keep at least this much of the original before the hash.
+const MIN_KEPT: usize = 8;
+const HASH_LEN: usize = 8;
+
+/// Length as the limits count it. UTF-8 bytes rather than UTF-16 units,
+/// which is never less, so a name that fits here fits on Windows too.
+fn len(path: &Path) -> usize {
+    path.as_os_str().len()
+}
+
+/// The whole-path limit of this platform, if it has one anything enforces.
+fn path_limit() -> Option<usize> {
+    #[cfg(windows)]
+    return Some(WINDOWS_MAX_PATH);
+    #[cfg(not(windows))]
+    None
+}
+
+/// `path` as Windows file APIs need it once it reaches MAX_PATH: absolute,
+/// normalized, with the `\\?\` extended-length prefix. Unchanged on other
+/// platforms, and for shorter or relative paths.
+pub fn extended(path: &Path) -> PathBuf {
+    #[cfg(windows)]
+    {
+        use std::ffi::OsString;
+        use std::path::Component;
+
+        // CreateDirectory stops at 248, short of MAX_PATH
+        const PREFIX_FROM: usize = 248;
+        let raw = path.as_os_str().to_string_lossy();
+        if len(path) < PREFIX_FROM || !path.is_absolute() || raw.starts_with(r"\\?\") {
+            return path.to_path_buf();
+        }
+        // The prefix turns off Windows' own normalization, so '/', '.' and
+        // '..' have to be resolved here
+        let mut normalized = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    normalized.pop();
+                }
+                other => normalized.push(other),
+            }
+        }
+        let normalized = normalized.as_os_str().to_string_lossy().replace('/', r"\");
+        let prefixed = match normalized.strip_prefix(r"\\") {
+            Some(unc) => format!(r"\\?\UNC\{}", unc),
+            None => format!(r"\\?\{}", normalized),
+        };
+        PathBuf::from(OsString::from(prefixed))
+    }
+    #[cfg(not(windows))]
+    {
+        path.to_path_buf()
+    }
+}
+
+fn too_long(path: &Path, length: usize, limit: usize) -> AppError {
+    AppError::PathTooLong {
+        path: path.display().to_string(),
+        length,
+        limit,
+    }
+}
+
+/// Checks a path the user chose, which can't be shortened for them: its
+/// file name and, on Windows, the whole path must fit with room for the
+/// temp suffixes written next to it.
+pub fn check_chosen(path: &Path) -> Result<(), AppError> {
+    if let Some(name) = path.file_name() {
+        let name_len = name.len() + SUFFIX_RESERVE;
+        if name_len > MAX_COMPONENT_LEN {
+            return Err(too_long(path, name_len, MAX_COMPONENT_LEN));
+        }
+    }
+    let path_len = len(path) + SUFFIX_RESERVE;
+    match path_limit() {
+        Some(limit) if path_len > limit => Err(too_long(path, path_len, limit)),
+        _ => Ok(()),
+    }
+}
+
+/// `stem` shortened, if needed, so `<dir>/<stem>.<extension>` fits the
+/// limits: cut on a character boundary and ended with "~" and a hash of the
+/// full stem, so different long names stay different. Fails only when even
+/// a few characters plus the hash would not fit in `dir`.
+pub fn fit_stem(dir: &Path, stem: &str, extension: &str) -> Result<String, AppError> {
+    let extension_len = extension.len() + 1;
+    let mut max_stem = MAX_COMPONENT_LEN - extension_len - SUFFIX_RESERVE;
+    let full = || dir.join(format!("{}.{}", stem, extension));
+    let limit = path_limit();
+    if let Some(limit) = limit {
+        // The separator after the directory counts too
+        let fixed = len(dir) + 1 + extension_len + SUFFIX_RESERVE;
+        max_stem = max_stem.min(limit.saturating_sub(fixed));
+    }
+    if stem.len() <= max_stem {
+        return Ok(stem.to_string());
+    }
+    if max_stem < MIN_KEPT + 1 + HASH_LEN {
+        let length = len(&full()) + SUFFIX_RESERVE;
+        return Err(too_long(
+            &full(),
+            length,
+            limit.unwrap_or(MAX_COMPONENT_LEN),
+        ));
+    }
+
+    let mut keep = max_stem - 1 - HASH_LEN;
+    while !stem.is_char_boundary(keep) {
+        keep -= 1;
+    }
+    let hash = format!("{:x}", Sha256::digest(stem.as_bytes()));
+    let shortened = format!(
+        "{}~{}",
+        stem[..keep].trim_end_matches(['-', '_', ' ', '.']),
+        &hash[..HASH_LEN]
+    );
+    log::info!(
+        "Shortened {} to {}.{}",
+        full().display(),
+        shortened,
+        extension
+    );
+    Ok(shortened)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The longest stem with an extension of `extension_len` bytes that
+    /// needs no shortening, wherever the path limit doesn't bind.
+    fn max_stem(extension_len: usize) -> usize {
+        MAX_COMPONENT_LEN - extension_len - 1 - SUFFIX_RESERVE
+    }
+
+    fn dir() -> PathBuf {
+        Path::new("exports").join("project")
+    }
+
+    #[test]
+    fn keeps_a_stem_that_fits() {
+        let stem = "a".repeat(max_stem(3));
+        assert_eq!(fit_stem(&dir(), &stem, "mp3").unwrap(), stem);
+        assert_eq!(fit_stem(&dir(), "", "mp3").unwrap(), "");
+    }
+
+    #[test]
+    fn shortens_a_stem_one_past_the_limit_to_the_limit() {
+        let stem = "a".repeat(max_stem(3) + 1);
+        let fitted = fit_stem(&dir(), &stem, "mp3").unwrap();
+        assert_eq!(fitted.len(), max_stem(3));
+        let (kept, hash) = fitted.rsplit_once('~').unwrap();
+        assert!(stem.starts_with(kept));
+        assert_eq!(hash.len(), HASH_LEN);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+        // The same name always shortens the same way
+        assert_eq!(fit_stem(&dir(), &stem, "mp3").unwrap(), fitted);
+        // A longer extension leaves less of the stem
+        assert_eq!(fit_stem(&dir(), &stem, "json").unwrap().len(), max_stem(4));
+    }
+
+    #[test]
+    fn different_long_names_stay_different() {
+        let base = "b".repeat(300);
+        let a = fit_stem(&dir(), &format!("{}-one", base), "wav").unwrap();
+        let b = fit_stem(&dir(), &format!("{}-two", base), "wav").unwrap();
+        assert_ne!(a, b);
+        assert_eq!(a.len(), b.len());
+    }
+
+    #[test]
+    fn cuts_on_a_character_boundary() {
+        // Kept is max - 1 - HASH_LEN bytes; a two-byte character straddles it
+        let keep = max_stem(3) - 1 - HASH_LEN;
+        let stem = format!("{}é{}", "c".repeat(keep - 1), "c".repeat(50));
+        let fitted = fit_stem(&dir(), &stem, "mp3").unwrap();
+        let (kept, _) = fitted.rsplit_once('~').unwrap();
+        assert_eq!(kept, "c".repeat(keep - 1));
+        assert!(fitted.len() < max_stem(3));
+    }
+
+    #[test]
+    fn drops_separators_before_the_hash() {
+        let keep = max_stem(3) - 1 - HASH_LEN;
+        let stem = format!("{}- _.{}", "d".repeat(keep - 4), "d".repeat(50));
+        let fitted = fit_stem(&dir(), &stem, "mp3").unwrap();
+        assert!(
+            fitted.starts_with(&format!("{}~", "d".repeat(keep - 4))),
+            "{}",
+            fitted
+        );
+    }
+
+    #[test]
+    fn checks_a_chosen_file_name_with_room_for_suffixes() {
+        let fits = dir().join("e".repeat(MAX_COMPONENT_LEN - SUFFIX_RESERVE));
+        assert!(check_chosen(&fits).is_ok());
+        let over = dir().join("e".repeat(MAX_COMPONENT_LEN - SUFFIX_RESERVE + 1));
+        match check_chosen(&over) {
+            Err(AppError::PathTooLong { length, limit, .. }) => {
+                assert_eq!((length, limit), (MAX_COMPONENT_LEN + 1, MAX_COMPONENT_LEN));
+            }
+            other => panic!("expected PathTooLong, got {:?}", other),
+        }
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn leaves_paths_unprefixed_off_windows() {
+        let deep = Path::new("/").join("f".repeat(200)).join("g".repeat(200));
+        assert_eq!(extended(&deep), deep);
+        assert!(check_chosen(&deep.join("name.mp3")).is_ok());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn prefixes_long_absolute_paths_on_windows() {
+        let short = Path::new(r"C:\Users\me\a.mp3");
+        assert_eq!(extended(short), short);
+        let long = format!(r"C:\Users\me\{}\.\sub\..\a.mp3", "f".repeat(250));
+        assert_eq!(
+            extended(Path::new(&long)),
+            PathBuf::from(format!(r"\\?\C:\Users\me\{}\a.mp3", "f".repeat(250)))
+        );
+        let unc = format!(r"\\server\share\{}\a.mp3", "f".repeat(250));
+        assert_eq!(
+            extended(Path::new(&unc)),
+            PathBuf::from(format!(r"\\?\UNC\server\share\{}\a.mp3", "f".repeat(250)))
+        );
+        let prefixed = PathBuf::from(format!(r"\\?\C:\{}", "f".repeat(250)));
+        assert_eq!(extended(&prefixed), prefixed);
+        let relative = PathBuf::from("f".repeat(300));
+        assert_eq!(extended(&relative), relative);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn fits_stems_under_max_path_on_windows() {
+        let dir = PathBuf::from(format!(r"C:\{}", "h".repeat(200)));
+        let stem = "i".repeat(100);
+        let fitted = fit_stem(&dir, &stem, "mp3").unwrap();
+        let path_len = len(&dir.join(format!("{}.mp3", fitted))) + SUFFIX_RESERVE;
+        assert!(path_len <= WINDOWS_MAX_PATH, "{}", path_len);
+        // No room for even a few characters and the hash
+        let deep = PathBuf::from(format!(r"C:\{}", "h".repeat(240)));
+        assert!(matches!(
+            fit_stem(&deep, &stem, "mp3"),
+            Err(AppError::PathTooLong { .. })
+        ));
+        assert!(check_chosen(&deep.join("name.mp3")).is_err());
+    }
+}
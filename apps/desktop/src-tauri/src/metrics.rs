@@ -0,0 +1,575 @@
+// Call counts and timings of every command, for the frontend's debug
+// overlay. All invokes go through `dispatch`, which counts them and their
+// payload and times the handler into a fixed ring of samples, so the hot
+// path only takes a lock and writes a slot. Tauri answers async commands
+// from a task of its own that the handler only sees spawned, so those time
+// themselves, from inside their future; and no answer passes back through
+// the handler, so every command is marked `#[timed]` and measures its own
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::io;
+use std::sync::Mutex;
+use std::time::Instant;
+use tauri::ipc::{Invoke, InvokeBody};
+
+pub(crate) use desktop_macros::timed;
+
+/// Samples kept across all commands; older ones are overwritten.
+const RING_SIZE: usize = 4096;
+/// `Sample::command` of a slot whose sample was dropped.
+const VACANT: u16 = u16::MAX;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Sample {
+    command: u16,
+    micros: u32,
+}
+
+struct Counter {
+    name: String,
+    calls: u64,
+    request_bytes: u64,
+    response_bytes: u64,
+    last_called_at: Option<DateTime<Utc>>,
+    /// The command is `#[timed]`, so its samples come from its future and
+    /// not from `dispatch`.
+    timed: bool,
+}
+
+struct Ring {
+    commands: HashMap<String, u16>,
+    counters: Vec<Counter>,
+    samples: Vec<Sample>,
+    /// Next slot of `samples` to write.
+    next: usize,
+    /// Whether `samples` has wrapped around once.
+    full: bool,
+    last_error: Option<CommandError>,
+    since: DateTime<Utc>,
+    /// Changes on reset, so a call that started before one isn't counted
+    /// under an index that no longer means its command.
+    generation: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandMetric {
+    pub command: String,
+    pub calls: u64,
+    pub request_bytes: u64,
+    /// Of the answers as JSON, errors included.
+    pub response_bytes: u64,
+    /// Over the samples still in the ring.
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+    pub last_called_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandMetrics {
+    pub since: DateTime<Utc>,
+    /// Samples the percentiles are taken from, of at most `RING_SIZE`.
+    pub samples: usize,
+    pub commands: Vec<CommandMetric>,
+    pub last_error: Option<CommandError>,
+}
+
+/// The error a command failed with most recently, of any command.
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandError {
+    pub command: String,
+    pub message: String,
+    pub at: DateTime<Utc>,
+}
+
+/// What a command answered, as `#[timed]` measures it.
+pub struct Answer {
+    bytes: u64,
+    error: Option<String>,
+}
+
+impl Answer {
+    pub fn of(value: &impl Serialize) -> Self {
+        Self {
+            bytes: json_bytes(value),
+            error: None,
+        }
+    }
+
+    /// The value or the error of `result`, whichever Tauri answers with.
+    pub fn of_result<T: Serialize, E: Serialize + Display>(result: &Result<T, E>) -> Self {
+        match result {
+            Ok(value) => Self::of(value),
+            Err(error) => Self {
+                bytes: json_bytes(error),
+                error: Some(error.to_string()),
+            },
+        }
+    }
+}
+
+/// `future`, typed as giving a `T`, which an async block can't be on its
+/// own. For `#[timed]`.
+pub fn returning<T, F: Future<Output = T>>(future: F) -> F {
+    future
+}
+
+/// Managed state holding the ring.
+pub struct CommandStats {
+    ring: Mutex<Ring>,
+}
+
+impl Default for CommandStats {
+    fn default() -> Self {
+        Self {
+            ring: Mutex::new(Ring::new()),
+        }
+    }
+}
+
+impl Ring {
+    fn new() -> Self {
+        Self {
+            commands: HashMap::new(),
+            counters: Vec::new(),
+            samples: vec![Sample::default(); RING_SIZE],
+            next: 0,
+            full: false,
+            last_error: None,
+            since: Utc::now(),
+            generation: 0,
+        }
+    }
+
+    /// Index of a command seen for the first time.
+    fn add(&mut self, name: String) -> u16 {
+        let index = self.counters.len() as u16;
+        self.counters.push(Counter {
+            name: name.clone(),
+            calls: 0,
+            request_bytes: 0,
+            response_bytes: 0,
+            last_called_at: None,
+            timed: false,
+        });
+        self.commands.insert(name, index);
+        index
+    }
+
+    fn index(&mut self, name: &str) -> u16 {
+        match self.commands.get(name) {
+            Some(&index) => index,
+            None => self.add(name.to_string()),
+        }
+    }
+
+    fn count(&mut self, command: u16, request_bytes: u64) {
+        let counter = &mut self.counters[command as usize];
+        counter.calls += 1;
+        counter.request_bytes += request_bytes;
+        counter.last_called_at = Some(Utc::now());
+    }
+
+    fn answer(&mut self, command: u16, answer: Answer) {
+        let counter = &mut self.counters[command as usize];
+        counter.response_bytes += answer.bytes;
+        if let Some(message) = answer.error {
+            self.last_error = Some(CommandError {
+                command: counter.name.clone(),
+                message,
+                at: Utc::now(),
+            });
+        }
+    }
+
+    fn sample(&mut self, command: u16, micros: u32) {
+        self.samples[self.next] = Sample { command, micros };
+        self.next = (self.next + 1) % RING_SIZE;
+        self.full |= self.next == 0;
+    }
+
+    /// Marks `command` as timed by its future, dropping the samples
+    /// `dispatch` took of it before it was known to be.
+    fn set_timed(&mut self, command: u16) {
+        if std::mem::replace(&mut self.counters[command as usize].timed, true) {
+            return;
+        }
+        for sample in &mut self.samples {
+            if sample.command == command {
+                sample.command = VACANT;
+            }
+        }
+    }
+
+    fn samples(&self) -> impl Iterator<Item = &Sample> {
+        let taken = if self.full {
+            &self.samples[..]
+        } else {
+            &self.samples[..self.next]
+        };
+        taken.iter().filter(|s| s.command != VACANT)
+    }
+}
+
+/// A run of a `#[timed]` command, sampled when dropped.
+pub struct CommandTimer<'a> {
+    stats: &'a CommandStats,
+    command: u16,
+    generation: u64,
+    started: Instant,
+}
+
+impl CommandTimer<'_> {
+    /// Records what the command answered, unless the stats were reset
+    /// since it started.
+    pub fn answered(&self, answer: Answer) {
+        let mut ring = self.stats.ring.lock().unwrap();
+        if ring.generation == self.generation {
+            ring.answer(self.command, answer);
+        }
+    }
+}
+
+impl Drop for CommandTimer<'_> {
+    fn drop(&mut self) {
+        let micros = elapsed_micros(self.started);
+        let mut ring = self.stats.ring.lock().unwrap();
+        if ring.generation == self.generation {
+            ring.sample(self.command, micros);
+        }
+    }
+}
+
+fn elapsed_micros(started: Instant) -> u32 {
+    started.elapsed().as_micros().min(u32::MAX as u128) as u32
+}
+
+/// Counts bytes written to it without keeping them.
+struct ByteCount(u64);
+
+impl io::Write for ByteCount {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn json_bytes(value: &impl Serialize) -> u64 {
+    let mut count = ByteCount(0);
+    // Serializing into a counter allocates nothing
+    let _ = serde_json::to_writer(&mut count, value);
+    count.0
+}
+
+fn payload_bytes(body: &InvokeBody) -> u64 {
+    match body {
+        InvokeBody::Raw(bytes) => bytes.len() as u64,
+        InvokeBody::Json(value) => json_bytes(value),
+    }
+}
+
+fn percentile(sorted: &[u32], p: f64) -> f64 {
+    let rank = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len());
+    sorted[rank - 1] as f64 / 1000.0
+}
+
+impl CommandStats {
+    pub fn snapshot(&self) -> CommandMetrics {
+        let ring = self.ring.lock().unwrap();
+        let mut micros: Vec<Vec<u32>> = vec![Vec::new(); ring.counters.len()];
+        for sample in ring.samples() {
+            micros[sample.command as usize].push(sample.micros);
+        }
+        let mut commands: Vec<CommandMetric> = ring
+            .counters
+            .iter()
+            .zip(micros)
+            .map(|(counter, mut micros)| {
+                micros.sort_unstable();
+                let (p50_ms, p95_ms, max_ms) = match micros.last() {
+                    Some(&max) => (
+                        percentile(&micros, 0.5),
+                        percentile(&micros, 0.95),
+                        max as f64 / 1000.0,
+                    ),
+                    None => (0.0, 0.0, 0.0),
+                };
+                CommandMetric {
+                    command: counter.name.clone(),
+                    calls: counter.calls,
+                    request_bytes: counter.request_bytes,
+                    response_bytes: counter.response_bytes,
+                    p50_ms,
+                    p95_ms,
+                    max_ms,
+                    last_called_at: counter.last_called_at,
+                }
+            })
+            .collect();
+        commands.sort_by(|a, b| {
+            b.calls
+                .cmp(&a.calls)
+                .then_with(|| a.command.cmp(&b.command))
+        });
+        CommandMetrics {
+            since: ring.since,
+            samples: ring.samples().count(),
+            commands,
+            last_error: ring.last_error.clone(),
+        }
+    }
+
+    pub fn reset(&self) {
+        let mut ring = self.ring.lock().unwrap();
+        let generation = ring.generation + 1;
+        *ring = Ring {
+            generation,
+            ..Ring::new()
+        };
+    }
+
+    /// Starts timing a run of the `#[timed]` command `name`, which lasts
+    /// until the timer is dropped.
+    pub fn time(&self, name: &'static str) -> CommandTimer<'_> {
+        let mut ring = self.ring.lock().unwrap();
+        let command = ring.index(name);
+        ring.set_timed(command);
+        CommandTimer {
+            stats: self,
+            command,
+            generation: ring.generation,
+            started: Instant::now(),
+        }
+    }
+
+    /// Records what the synchronous command `name` answered; `dispatch`
+    /// records the rest of its call once it returns.
+    pub fn answered(&self, name: &'static str, answer: Answer) {
+        let mut ring = self.ring.lock().unwrap();
+        let command = ring.index(name);
+        ring.answer(command, answer);
+    }
+
+    /// What `dispatch` looks up before running a command: its index, or
+    /// its name to add it under if it turns out to exist.
+    fn lookup(&self, name: &str) -> (Result<u16, String>, u64) {
+        let ring = self.ring.lock().unwrap();
+        let command = match ring.commands.get(name) {
+            Some(&index) => Ok(index),
+            None => Err(name.to_string()),
+        };
+        (command, ring.generation)
+    }
+
+    /// Records a call `dispatch` made, and its time unless the command
+    /// times itself.
+    fn record(
+        &self,
+        (command, generation): (Result<u16, String>, u64),
+        handled: bool,
+        request_bytes: u64,
+        micros: u32,
+    ) {
+        let mut ring = self.ring.lock().unwrap();
+        if ring.generation != generation {
+            return;
+        }
+        let command = match command {
+            Ok(index) => index,
+            // The command may have added it meanwhile
+            Err(name) if handled => match ring.commands.get(&name) {
+                Some(&index) => index,
+                None => ring.add(name),
+            },
+            Err(_) => return,
+        };
+        ring.count(command, request_bytes);
+        if !ring.counters[command as usize].timed {
+            ring.sample(command, micros);
+        }
+    }
+}
+
+/// `handler`, the generated invoke handler, recording every call.
+pub fn instrument(
+    handler: impl Fn(Invoke) -> bool + Send + Sync + 'static,
+) -> impl Fn(Invoke) -> bool + Send + Sync + 'static {
+    move |invoke| dispatch(&handler, invoke)
+}
+
+fn dispatch(handler: &dyn Fn(Invoke) -> bool, invoke: Invoke) -> bool {
+    let state = invoke.message.state();
+    let Some(stats) = state.try_get::<CommandStats>() else {
+        return handler(invoke);
+    };
+    let request_bytes = payload_bytes(invoke.message.payload());
+    // A name is only copied the first time; unknown commands aren't kept, so
+    // a misbehaving frontend can't grow the table
+    let command = stats.lookup(invoke.message.command());
+    let started = Instant::now();
+    let handled = handler(invoke);
+    stats.record(command, handled, request_bytes, elapsed_micros(started));
+    handled
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_command_metrics(stats: tauri::State<'_, CommandStats>) -> CommandMetrics {
+    stats.snapshot()
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn reset_command_metrics(stats: tauri::State<'_, CommandStats>) {
+    stats.reset();
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![get_command_metrics, reset_command_metrics]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AppError;
+
+    /// A call as `dispatch` records it.
+    fn dispatched(stats: &CommandStats, name: &str, handled: bool, micros: u32) {
+        let command = stats.lookup(name);
+        stats.record(command, handled, 10, micros);
+    }
+
+    fn metric(stats: &CommandStats, name: &str) -> Option<CommandMetric> {
+        stats
+            .snapshot()
+            .commands
+            .into_iter()
+            .find(|c| c.command == name)
+    }
+
+    #[test]
+    fn records_dispatched_calls() {
+        let stats = CommandStats::default();
+        dispatched(&stats, "get_settings", true, 1_000);
+        dispatched(&stats, "get_settings", true, 3_000);
+        dispatched(&stats, "no_such_command", false, 5);
+
+        let metrics = stats.snapshot();
+        assert_eq!(metrics.samples, 2);
+        assert_eq!(metrics.commands.len(), 1);
+        let metric = &metrics.commands[0];
+        assert_eq!(metric.command, "get_settings");
+        assert_eq!(metric.calls, 2);
+        assert_eq!(metric.request_bytes, 20);
+        assert_eq!(metric.p50_ms, 1.0);
+        assert_eq!(metric.max_ms, 3.0);
+        assert!(metric.last_called_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn timed_commands_are_sampled_from_their_future() {
+        let stats = CommandStats::default();
+        // The spawn `dispatch` saw, before the command's future started
+        dispatched(&stats, "synthesize_batch", true, 5);
+        {
+            let _timer = stats.time("synthesize_batch");
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        dispatched(&stats, "synthesize_batch", true, 5);
+
+        let metric = metric(&stats, "synthesize_batch").unwrap();
+        assert_eq!(metric.calls, 2);
+        assert_eq!(stats.snapshot().samples, 1);
+        assert!(metric.p50_ms >= 20.0, "{}", metric.p50_ms);
+    }
+
+    #[test]
+    fn a_future_started_before_its_dispatch_was_recorded_counts_once() {
+        let stats = CommandStats::default();
+        let command = stats.lookup("export_project_archive");
+        drop(stats.time("export_project_archive"));
+        stats.record(command, true, 0, 5);
+        assert_eq!(stats.snapshot().commands.len(), 1);
+        assert_eq!(metric(&stats, "export_project_archive").unwrap().calls, 1);
+        assert_eq!(stats.snapshot().samples, 1);
+    }
+
+    #[test]
+    fn records_answer_sizes_and_the_last_error() {
+        let stats = CommandStats::default();
+        let settings: Result<&str, AppError> = Ok("saved");
+        stats.answered("update_settings", Answer::of_result(&settings));
+        dispatched(&stats, "update_settings", true, 5);
+        stats.answered("get_app_info", Answer::of(&[1, 2, 3]));
+        dispatched(&stats, "get_app_info", true, 5);
+        let failed: Result<(), AppError> = Err(AppError::Io {
+            message: "disk full".into(),
+        });
+        stats.answered("update_settings", Answer::of_result(&failed));
+        dispatched(&stats, "update_settings", true, 5);
+
+        let metric = metric(&stats, "update_settings").unwrap();
+        assert_eq!(metric.calls, 2);
+        let error_bytes = serde_json::to_string(&failed.as_ref().unwrap_err())
+            .unwrap()
+            .len() as u64;
+        assert_eq!(
+            metric.response_bytes,
+            "\"saved\"".len() as u64 + error_bytes
+        );
+        assert_eq!(metric_bytes(&stats, "get_app_info"), "[1,2,3]".len() as u64);
+        let error = stats.snapshot().last_error.unwrap();
+        assert_eq!(error.command, "update_settings");
+        assert_eq!(error.message, "I/O error: disk full");
+    }
+
+    fn metric_bytes(stats: &CommandStats, name: &str) -> u64 {
+        metric(stats, name).unwrap().response_bytes
+    }
+
+    #[tokio::test]
+    async fn timed_commands_answer_from_their_future() {
+        let stats = CommandStats::default();
+        let failed: Result<(), AppError> = Err(AppError::Tts {
+            message: "quota".into(),
+        });
+        let timer = stats.time("synthesize_batch");
+        timer.answered(Answer::of_result(&failed));
+        drop(timer);
+        assert!(metric_bytes(&stats, "synthesize_batch") > 0);
+        assert_eq!(
+            stats.snapshot().last_error.unwrap().command,
+            "synthesize_batch"
+        );
+
+        // An answer from before a reset isn't kept
+        let timer = stats.time("synthesize_batch");
+        stats.reset();
+        timer.answered(Answer::of_result(&failed));
+        drop(timer);
+        let metrics = stats.snapshot();
+        assert!(metrics.last_error.is_none());
+        assert!(metrics.commands.is_empty());
+    }
+
+    #[test]
+    fn reset_drops_calls_in_flight() {
+        let stats = CommandStats::default();
+        let command = stats.lookup("get_settings");
+        let timer = stats.time("resume_batch");
+        stats.reset();
+        stats.record(command, true, 0, 5);
+        drop(timer);
+        let metrics = stats.snapshot();
+        assert!(metrics.commands.is_empty());
+        assert_eq!(metrics.samples, 0);
+    }
+}
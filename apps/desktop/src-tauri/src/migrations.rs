@@ -0,0 +1,556 @@
+// One-time migrations of on-disk layouts, run in order at startup. A step is
+// recorded in migrations.json only once it has finished, and every step is
+// safe to rerun, so one interrupted by a crash simply runs again
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::audio::music;
+use crate::cache::preview::{self, PreviewCache};
+use crate::cache::{is_voice_name, write_atomic};
+use crate::error::AppError;
+use crate::tts::voice_list;
+use crate::voices::starter;
+
+/// Where a step finds the old layout and puts the new one.
+pub struct MigrationContext {
+    pub data_dir: PathBuf,
+    pub cache_dir: PathBuf,
+    pub resource_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StepReport {
+    pub moved: usize,
+    pub copied: usize,
+    /// Already in the new layout, e.g. from an interrupted earlier run.
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// A layout change. Later changes append a step; steps never change once
+/// shipped, since installs may have recorded them as done.
+struct Step {
+    id: &'static str,
+    description: &'static str,
+    run: fn(&MigrationContext) -> Result<StepReport, AppError>,
+}
+
+const STEPS: &[Step] = &[
+    Step {
+        id: "preview-cache-v2",
+        description: "Move voice previews from the app cache and resources into app data",
+        run: migrate_preview_cache_v2,
+    },
+    Step {
+        id: "starter-snapshot-v1",
+        description: "Copy the voice list and previews bundled with the installer into app data",
+        run: install_starter_snapshot_v1,
+    },
+    Step {
+        id: "music-beds-v1",
+        description: "Copy the music beds bundled with the installer into app data",
+        run: install_music_beds_v1,
+    },
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CompletedStep {
+    id: String,
+    completed_at: DateTime<Utc>,
+    report: StepReport,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Marker {
+    completed: Vec<CompletedStep>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StepStatus {
+    pub id: &'static str,
+    pub description: &'static str,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub report: Option<StepReport>,
+    /// Why the step didn't finish this launch; it is retried on the next.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationStatus {
+    pub marker_path: PathBuf,
+    pub steps: Vec<StepStatus>,
+}
+
+/// Managed state recording what the startup run did.
+pub struct Migrations {
+    marker_path: PathBuf,
+    errors: Mutex<HashMap<&'static str, String>>,
+}
+
+fn load_marker(path: &Path) -> Marker {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+impl Migrations {
+    /// Runs every step not yet recorded, in order, stopping at the first
+    /// that fails so later steps never see a half-migrated layout.
+    pub fn run(marker_path: PathBuf, ctx: &MigrationContext) -> Self {
+        let mut marker = load_marker(&marker_path);
+        let mut errors = HashMap::new();
+        for step in STEPS {
+            if marker.completed.iter().any(|c| c.id == step.id) {
+                continue;
+            }
+            let result = (step.run)(ctx).and_then(|report| match report.failed {
+                0 => Ok(report),
+                failed => Err(AppError::Io {
+                    message: format!("{} file(s) could not be migrated", failed),
+                }),
+            });
+            match result {
+                Ok(report) => {
+                    log::info!(
+                        "Migration {}: {} moved, {} copied, {} already migrated",
+                        step.id,
+                        report.moved,
+                        report.copied,
+                        report.skipped
+                    );
+                    marker.completed.push(CompletedStep {
+                        id: step.id.to_string(),
+                        completed_at: Utc::now(),
+                        report,
+                    });
+                    let saved = serde_json::to_vec_pretty(&marker)
+                        .map_err(io::Error::other)
+                        .and_then(|json| write_atomic(&marker_path, &json));
+                    if let Err(e) = saved {
+                        log::warn!("Failed to record migration {}: {}", step.id, e);
+                    }
+                }
+                Err(e) => {
+                    log::error!("Migration {} failed, will retry: {}", step.id, e);
+                    errors.insert(step.id, e.to_string());
+                    break;
+                }
+            }
+        }
+        Self {
+            marker_path,
+            errors: Mutex::new(errors),
+        }
+    }
+
+    pub fn status(&self) -> MigrationStatus {
+        let marker = load_marker(&self.marker_path);
+        let errors = self.errors.lock().unwrap();
+        MigrationStatus {
+            marker_path: self.marker_path.clone(),
+            steps: STEPS
+                .iter()
+                .map(|step| {
+                    let completed = marker.completed.iter().find(|c| c.id == step.id);
+                    StepStatus {
+                        id: step.id,
+                        description: step.description,
+                        completed_at: completed.map(|c| c.completed_at),
+                        report: completed.map(|c| c.report.clone()),
+                        error: errors.get(step.id).cloned(),
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+enum Transfer {
+    Moved,
+    Copied,
+    Skipped,
+}
+
+/// Puts `from` at `to` through a temp file and rename, so `to` is either
+/// absent or complete. An existing `to` is a finished earlier transfer, so
+/// only the leftover source is dropped. With `keep_source` the source is
+/// copied, e.g. out of read-only resources.
+fn transfer(from: &Path, to: &Path, keep_source: bool) -> io::Result<Transfer> {
+    if to.exists() {
+        if !keep_source {
+            fs::remove_file(from)?;
+        }
+        return Ok(Transfer::Skipped);
+    }
+    if !keep_source && fs::rename(from, to).is_ok() {
+        return Ok(Transfer::Moved);
+    }
+    // Copy for resources, and for moves across volumes
+    write_atomic(to, &fs::read(from)?)?;
+    if keep_source {
+        Ok(Transfer::Copied)
+    } else {
+        fs::remove_file(from)?;
+        Ok(Transfer::Moved)
+    }
+}
+
+/// Layout 1 to 2: `voice_<voice>.mp3` in the app cache (regenerated) and
+/// the resources (bundled) become `<voice>.mp3` under app data. Regenerated
+/// previews go first so they keep shadowing the bundled ones.
+fn migrate_preview_cache_v2(ctx: &MigrationContext) -> Result<StepReport, AppError> {
+    let target = preview::writable_dir_in(&ctx.data_dir);
+    let old_cache = ctx.cache_dir.join("preview_cache");
+    let mut sources = vec![(old_cache.clone(), false)];
+    if let Some(resource_dir) = &ctx.resource_dir {
+        sources.push((resource_dir.join("preview_cache"), true));
+    }
+
+    let mut report = StepReport::default();
+    for (dir, keep_source) in sources {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        fs::create_dir_all(&target)?;
+        let mut files: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+        files.sort();
+        for from in files {
+            let Some(voice_name) = from
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_prefix("voice_")?.strip_suffix(".mp3"))
+                .filter(|v| is_voice_name(v))
+            else {
+                continue;
+            };
+            let to = target.join(PreviewCache::file_name(
+                voice_name,
+                preview::BUNDLED_SENTENCE,
+            ));
+            match transfer(&from, &to, keep_source) {
+                Ok(Transfer::Moved) => report.moved += 1,
+                Ok(Transfer::Copied) => report.copied += 1,
+                Ok(Transfer::Skipped) => report.skipped += 1,
+                Err(e) => {
+                    log::warn!("Failed to migrate {}: {}", from.display(), e);
+                    report.failed += 1;
+                }
+            }
+        }
+    }
+    // Only succeeds once nothing else is left in it
+    let _ = fs::remove_dir(&old_cache);
+    Ok(report)
+}
+
+/// First run: the starter snapshot from the resources fills the voice list
+/// and the writable previews, so the voice picker works before the first
+/// fetch. Installs that already have either keep theirs; an install
+/// without a snapshot, e.g. a development build, has nothing to copy.
+fn install_starter_snapshot_v1(ctx: &MigrationContext) -> Result<StepReport, AppError> {
+    let mut report = StepReport::default();
+    let Some(snapshot) = ctx
+        .resource_dir
+        .as_ref()
+        .map(|dir| dir.join(starter::SNAPSHOT_DIR))
+    else {
+        return Ok(report);
+    };
+    let mut copies = Vec::new();
+    let list = snapshot.join(voice_list::FILE_NAME);
+    if list.is_file() {
+        copies.push((list, ctx.data_dir.join(voice_list::FILE_NAME)));
+    }
+    if let Ok(entries) = fs::read_dir(snapshot.join(starter::PREVIEWS_DIR)) {
+        let target = preview::writable_dir_in(&ctx.data_dir);
+        let mut files: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+        files.sort();
+        for from in files {
+            // `<voice>.mp3` or `<voice>.<sentence hash>.mp3`, as the cache names them
+            let Some(file_name) = from.file_name().and_then(|n| n.to_str()).filter(|n| {
+                n.strip_suffix(".mp3")
+                    .and_then(|stem| stem.split('.').next())
+                    .is_some_and(is_voice_name)
+            }) else {
+                continue;
+            };
+            copies.push((from.clone(), target.join(file_name)));
+        }
+    }
+
+    for (from, to) in copies {
+        match transfer(&from, &to, true) {
+            Ok(Transfer::Copied) => report.copied += 1,
+            Ok(Transfer::Moved) => report.moved += 1,
+            Ok(Transfer::Skipped) => report.skipped += 1,
+            Err(e) => {
+                log::warn!("Failed to install {}: {}", from.display(), e);
+                report.failed += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// First run: the WAV music beds in the resources, for
+/// `preview_with_music`. Beds already in app data are kept.
+fn install_music_beds_v1(ctx: &MigrationContext) -> Result<StepReport, AppError> {
+    let mut report = StepReport::default();
+    let Some(Ok(entries)) = ctx
+        .resource_dir
+        .as_ref()
+        .map(|dir| fs::read_dir(dir.join(music::MUSIC_DIR)))
+    else {
+        return Ok(report);
+    };
+    let target = ctx.data_dir.join(music::MUSIC_DIR);
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "wav"))
+        .collect();
+    files.sort();
+    for from in files {
+        let Some(file_name) = from.file_name() else {
+            continue;
+        };
+        match transfer(&from, &target.join(file_name), true) {
+            Ok(Transfer::Copied) => report.copied += 1,
+            Ok(Transfer::Moved) => report.moved += 1,
+            Ok(Transfer::Skipped) => report.skipped += 1,
+            Err(e) => {
+                log::warn!("Failed to install {}: {}", from.display(), e);
+                report.failed += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_migration_status(migrations: tauri::State<'_, Migrations>) -> MigrationStatus {
+    migrations.status()
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![get_migration_status]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    fn context(scratch: &Scratch) -> MigrationContext {
+        MigrationContext {
+            data_dir: scratch.0.join("data"),
+            cache_dir: scratch.0.join("cache"),
+            resource_dir: Some(scratch.0.join("resources")),
+        }
+    }
+
+    fn write(scratch: &Scratch, path: &str, contents: &str) {
+        let path = scratch.0.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn read(scratch: &Scratch, path: &str) -> Option<String> {
+        fs::read_to_string(scratch.0.join(path)).ok()
+    }
+
+    /// What a 1.x install left: previews it regenerated in the app cache,
+    /// the ones it bundled, and installer resources not yet copied.
+    fn old_layout(scratch: &Scratch) {
+        write(
+            scratch,
+            "cache/preview_cache/voice_en-US-Neural2-C.mp3",
+            "regenerated",
+        );
+        write(scratch, "cache/preview_cache/notes.txt", "not a preview");
+        write(
+            scratch,
+            "resources/preview_cache/voice_en-US-Neural2-C.mp3",
+            "bundled",
+        );
+        write(
+            scratch,
+            "resources/preview_cache/voice_de-DE-Wavenet-A.mp3",
+            "bundled",
+        );
+        write(scratch, "resources/starter_snapshot/voice_list.json", "[]");
+        write(
+            scratch,
+            "resources/starter_snapshot/previews/en-GB-Neural2-A.mp3",
+            "starter",
+        );
+        write(
+            scratch,
+            "resources/starter_snapshot/previews/readme.md",
+            "skipped",
+        );
+        write(scratch, "resources/music/calm.wav", "bed");
+        write(scratch, "resources/music/license.txt", "skipped");
+    }
+
+    fn run(scratch: &Scratch) -> Migrations {
+        Migrations::run(scratch.0.join("data/migrations.json"), &context(scratch))
+    }
+
+    fn report(status: &MigrationStatus, id: &str) -> StepReport {
+        let step = status.steps.iter().find(|s| s.id == id).unwrap();
+        step.report
+            .clone()
+            .unwrap_or_else(|| panic!("{} not done", id))
+    }
+
+    #[test]
+    fn migrates_an_old_layout() {
+        let scratch = Scratch::new("old");
+        old_layout(&scratch);
+        let status = run(&scratch).status();
+        assert!(status.steps.iter().all(|s| s.error.is_none()));
+
+        // The regenerated preview wins over the bundled one of the same voice
+        assert_eq!(
+            read(&scratch, "data/preview_cache/v2/en-US-Neural2-C.mp3").as_deref(),
+            Some("regenerated")
+        );
+        assert_eq!(
+            read(&scratch, "data/preview_cache/v2/de-DE-Wavenet-A.mp3").as_deref(),
+            Some("bundled")
+        );
+        let previews = report(&status, "preview-cache-v2");
+        assert_eq!(
+            (previews.moved, previews.copied, previews.skipped),
+            (1, 1, 1)
+        );
+        // Resources are read-only, and the old cache keeps what isn't a preview
+        assert!(read(
+            &scratch,
+            "resources/preview_cache/voice_en-US-Neural2-C.mp3"
+        )
+        .is_some());
+        assert!(read(&scratch, "cache/preview_cache/voice_en-US-Neural2-C.mp3").is_none());
+        assert!(read(&scratch, "cache/preview_cache/notes.txt").is_some());
+
+        assert_eq!(
+            read(&scratch, "data/voice_list.json").as_deref(),
+            Some("[]")
+        );
+        assert_eq!(
+            read(&scratch, "data/preview_cache/v2/en-GB-Neural2-A.mp3").as_deref(),
+            Some("starter")
+        );
+        assert!(read(&scratch, "data/preview_cache/v2/readme.md").is_none());
+        assert_eq!(report(&status, "starter-snapshot-v1").copied, 2);
+        assert_eq!(
+            read(&scratch, "data/music/calm.wav").as_deref(),
+            Some("bed")
+        );
+        assert!(read(&scratch, "data/music/license.txt").is_none());
+        assert_eq!(report(&status, "music-beds-v1").copied, 1);
+    }
+
+    #[test]
+    fn runs_each_step_once() {
+        let scratch = Scratch::new("once");
+        old_layout(&scratch);
+        let first = run(&scratch).status();
+        // A later preview in the old place is no longer picked up
+        write(
+            &scratch,
+            "cache/preview_cache/voice_en-US-Wavenet-D.mp3",
+            "late",
+        );
+        let second = run(&scratch).status();
+        for (a, b) in first.steps.iter().zip(&second.steps) {
+            assert_eq!(a.completed_at, b.completed_at, "{}", a.id);
+        }
+        assert!(read(&scratch, "data/preview_cache/v2/en-US-Wavenet-D.mp3").is_none());
+    }
+
+    #[test]
+    fn finishes_a_move_an_earlier_run_left_half_done() {
+        let scratch = Scratch::new("interrupted");
+        old_layout(&scratch);
+        // Copied over, but the crash came before the source was deleted
+        write(
+            &scratch,
+            "data/preview_cache/v2/en-US-Neural2-C.mp3",
+            "regenerated",
+        );
+        write(&scratch, "data/voice_list.json", "[\"kept\"]");
+        let status = run(&scratch).status();
+
+        let previews = report(&status, "preview-cache-v2");
+        assert_eq!(
+            (previews.moved, previews.copied, previews.skipped),
+            (0, 1, 2)
+        );
+        assert!(read(&scratch, "cache/preview_cache/voice_en-US-Neural2-C.mp3").is_none());
+        assert_eq!(
+            read(&scratch, "data/voice_list.json").as_deref(),
+            Some("[\"kept\"]")
+        );
+        assert_eq!(report(&status, "starter-snapshot-v1").skipped, 1);
+    }
+
+    #[test]
+    fn stops_at_a_failed_step_and_retries_it_next_launch() {
+        let scratch = Scratch::new("failed");
+        old_layout(&scratch);
+        // A file where the new preview directory goes
+        write(&scratch, "data/preview_cache/v2", "in the way");
+        let status = run(&scratch).status();
+        let failed = &status.steps[0];
+        assert_eq!(failed.id, "preview-cache-v2");
+        assert!(failed.error.is_some() && failed.completed_at.is_none());
+        // Later steps wait for it
+        assert!(status.steps[1..]
+            .iter()
+            .all(|s| s.completed_at.is_none() && s.error.is_none()));
+        assert!(read(&scratch, "data/voice_list.json").is_none());
+        assert!(read(&scratch, "cache/preview_cache/voice_en-US-Neural2-C.mp3").is_some());
+
+        fs::remove_file(scratch.0.join("data/preview_cache/v2")).unwrap();
+        let status = run(&scratch).status();
+        assert!(status
+            .steps
+            .iter()
+            .all(|s| s.completed_at.is_some() && s.error.is_none()));
+        assert_eq!(
+            read(&scratch, "data/preview_cache/v2/en-US-Neural2-C.mp3").as_deref(),
+            Some("regenerated")
+        );
+    }
+
+    #[test]
+    fn a_fresh_install_without_resources_has_nothing_to_do() {
+        let scratch = Scratch::new("fresh");
+        let ctx = MigrationContext {
+            resource_dir: None,
+            ..context(&scratch)
+        };
+        let status = Migrations::run(scratch.0.join("data/migrations.json"), &ctx).status();
+        for step in &status.steps {
+            let report = step.report.as_ref().unwrap();
+            assert_eq!(
+                (report.moved, report.copied, report.skipped, report.failed),
+                (0, 0, 0, 0),
+                "{}",
+                step.id
+            );
+        }
+        assert!(!scratch.0.join("cache/preview_cache").exists());
+    }
+}
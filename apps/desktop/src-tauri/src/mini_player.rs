@@ -0,0 +1,456 @@
+// Floating always-on-top mini player for narration review: a small window
+// scoped to one audio file. As with the A/B preview, the window's webview
+// does the playing; play, pause and seek go through `control_mini_player`,
+// which only accepts that file, and reach the window as
+// "mini-player-transport". Its position is kept per monitor as an offset in
+// logical pixels from the monitor's corner, so it lands in the same spot at
+// any scale factor, and on the primary monitor once its own is gone
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{Emitter, Manager, Monitor, PhysicalPosition};
+
+use crate::audio::arbitration::PlaybackArbiter;
+use crate::audio::{mp3, stretch};
+use crate::cache::{is_cache_key, write_atomic};
+use crate::error::AppError;
+use crate::paths::roots::{AllowedRoots, Read};
+
+const LABEL_PREFIX: &str = "mini-player-";
+const PAGE: &str = "index.html#/mini-player";
+/// Window size in logical pixels.
+const WIDTH: f64 = 360.0;
+const HEIGHT: f64 = 120.0;
+/// Distance from the primary monitor's bottom-right corner, in logical
+/// pixels, of a mini player with no remembered position.
+const MARGIN: f64 = 24.0;
+
+/// What `open_mini_player` plays: a file in an allowed root, or a TTS cache
+/// entry such as a segment's take.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum MiniPlayerSource {
+    File { path: PathBuf },
+    Cached { fingerprint: String },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportState {
+    #[default]
+    Paused,
+    Playing,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "action")]
+pub enum TransportAction {
+    Play,
+    Pause,
+    Seek { position_ms: f64 },
+}
+
+/// A mini player window and what it plays; also the payload of
+/// "mini-player-transport".
+#[derive(Debug, Clone, Serialize)]
+pub struct MiniPlayer {
+    pub label: String,
+    /// The window that opened it, which takes it along when closed.
+    pub owner: String,
+    pub path: PathBuf,
+    pub mime_type: &'static str,
+    pub duration_ms: Option<f64>,
+    pub state: TransportState,
+    pub position_ms: f64,
+}
+
+/// Offset in logical pixels from a monitor's top-left corner.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Offset {
+    x: f64,
+    y: f64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct PositionsFile {
+    /// By monitor name.
+    by_monitor: BTreeMap<String, Offset>,
+    /// The monitor a mini player was last on.
+    last_monitor: Option<String>,
+}
+
+pub fn is_mini_player(label: &str) -> bool {
+    label.starts_with(LABEL_PREFIX)
+}
+
+fn mime_type(path: &Path) -> Result<&'static str, AppError> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("mp3") => Ok("audio/mpeg"),
+        Some("wav") => Ok("audio/wav"),
+        _ => Err(AppError::InvalidAudioRequest {
+            message: format!("{} is not an MP3 or WAV file", path.display()),
+        }),
+    }
+}
+
+/// The monitor's logical size.
+fn logical_size(monitor: &Monitor) -> (f64, f64) {
+    let scale = monitor.scale_factor();
+    (
+        f64::from(monitor.size().width) / scale,
+        f64::from(monitor.size().height) / scale,
+    )
+}
+
+/// Where on `monitor` a window at `offset` goes, kept fully on it.
+fn on_monitor(monitor: &Monitor, offset: Offset) -> PhysicalPosition<i32> {
+    let scale = monitor.scale_factor();
+    let (width, height) = logical_size(monitor);
+    let x = offset.x.clamp(0.0, (width - WIDTH).max(0.0));
+    let y = offset.y.clamp(0.0, (height - HEIGHT).max(0.0));
+    PhysicalPosition::new(
+        monitor.position().x + (x * scale).round() as i32,
+        monitor.position().y + (y * scale).round() as i32,
+    )
+}
+
+fn default_offset(monitor: &Monitor) -> Offset {
+    let (width, height) = logical_size(monitor);
+    Offset {
+        x: width - WIDTH - MARGIN,
+        y: height - HEIGHT - MARGIN,
+    }
+}
+
+/// Managed state holding the open mini players, with their positions
+/// persisted as mini_player.json in app data.
+pub struct MiniPlayers {
+    path: PathBuf,
+    positions: Mutex<PositionsFile>,
+    players: Mutex<HashMap<String, MiniPlayer>>,
+    next_id: AtomicU64,
+}
+
+impl MiniPlayers {
+    pub fn load(path: PathBuf) -> Self {
+        let positions = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| match serde_json::from_str(&contents) {
+                Ok(positions) => Some(positions),
+                Err(e) => {
+                    log::warn!(
+                        "Ignoring unreadable mini player positions {}: {}",
+                        path.display(),
+                        e
+                    );
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Self {
+            path,
+            positions: Mutex::new(positions),
+            players: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    fn save_positions(&self) {
+        let positions = self.positions.lock().unwrap();
+        let result = serde_json::to_vec_pretty(&*positions)
+            .map_err(std::io::Error::other)
+            .and_then(|json| write_atomic(&self.path, &json));
+        if let Err(e) = result {
+            log::warn!(
+                "Failed to write mini player positions {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    pub fn get(&self, label: &str) -> Option<MiniPlayer> {
+        self.players.lock().unwrap().get(label).cloned()
+    }
+
+    /// The remembered spot if its monitor is still connected, else the
+    /// default spot on the primary monitor.
+    fn placement(&self, app_handle: &tauri::AppHandle) -> Option<PhysicalPosition<i32>> {
+        let monitors = app_handle.available_monitors().unwrap_or_default();
+        let positions = self.positions.lock().unwrap();
+        let remembered = positions.last_monitor.as_ref().and_then(|name| {
+            let monitor = monitors.iter().find(|m| m.name() == Some(name))?;
+            Some(on_monitor(monitor, *positions.by_monitor.get(name)?))
+        });
+        if remembered.is_some() {
+            return remembered;
+        }
+        let primary = app_handle.primary_monitor().ok().flatten();
+        let monitor = primary.as_ref().or(monitors.first())?;
+        Some(on_monitor(monitor, default_offset(monitor)))
+    }
+
+    /// Remembers where the window moved to, relative to the monitor it's on.
+    fn moved(&self, window: &tauri::Window, position: PhysicalPosition<i32>) {
+        let Ok(Some(monitor)) = window.current_monitor() else {
+            return;
+        };
+        let Some(name) = monitor.name().cloned() else {
+            return;
+        };
+        let scale = monitor.scale_factor();
+        let offset = Offset {
+            x: f64::from(position.x - monitor.position().x) / scale,
+            y: f64::from(position.y - monitor.position().y) / scale,
+        };
+        let mut positions = self.positions.lock().unwrap();
+        positions.by_monitor.insert(name.clone(), offset);
+        positions.last_monitor = Some(name);
+    }
+
+    /// Moves the window back onto a connected monitor if it's on none, e.g.
+    /// after its monitor was unplugged.
+    fn ensure_visible(&self, window: &tauri::Window) {
+        if matches!(window.current_monitor(), Ok(Some(_))) {
+            return;
+        }
+        let app_handle = window.app_handle();
+        let primary = app_handle.primary_monitor().ok().flatten();
+        if let Some(monitor) = primary {
+            log::info!(
+                "Mini player {} is off-screen, moving it to the primary monitor",
+                window.label()
+            );
+            if let Err(e) = window.set_position(on_monitor(&monitor, default_offset(&monitor))) {
+                log::warn!("Failed to move mini player {}: {}", window.label(), e);
+            }
+        }
+    }
+
+    fn closed(&self, app_handle: &tauri::AppHandle, label: &str) {
+        if self.players.lock().unwrap().remove(label).is_some() {
+            app_handle
+                .state::<PlaybackArbiter>()
+                .stop_preview(app_handle, label);
+            self.save_positions();
+        }
+    }
+
+    /// Closes the mini players `owner` opened.
+    fn close_owned_by(&self, app_handle: &tauri::AppHandle, owner: &str) {
+        let owned: Vec<String> = self
+            .players
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|p| p.owner == owner)
+            .map(|p| p.label.clone())
+            .collect();
+        for label in owned {
+            if let Some(window) = app_handle.get_webview_window(&label) {
+                if let Err(e) = window.close() {
+                    log::warn!("Failed to close mini player {}: {}", label, e);
+                }
+            }
+        }
+    }
+
+    fn apply(&self, label: &str, action: &TransportAction) -> Option<MiniPlayer> {
+        let mut players = self.players.lock().unwrap();
+        let player = players.get_mut(label)?;
+        match action {
+            TransportAction::Play => player.state = TransportState::Playing,
+            TransportAction::Pause => player.state = TransportState::Paused,
+            TransportAction::Seek { position_ms } => {
+                let end = player.duration_ms.unwrap_or(f64::MAX);
+                player.position_ms = position_ms.clamp(0.0, end);
+            }
+        }
+        Some(player.clone())
+    }
+}
+
+/// Called for every window event: tracks mini player positions, and closes
+/// a window's mini players with it.
+pub fn on_window_event(window: &tauri::Window, event: &tauri::WindowEvent) {
+    let Some(players) = window.try_state::<MiniPlayers>() else {
+        return;
+    };
+    let label = window.label();
+    match event {
+        tauri::WindowEvent::Moved(position) if is_mini_player(label) => {
+            players.moved(window, *position)
+        }
+        tauri::WindowEvent::ScaleFactorChanged { .. } | tauri::WindowEvent::Focused(true)
+            if is_mini_player(label) =>
+        {
+            players.ensure_visible(window)
+        }
+        tauri::WindowEvent::Destroyed => {
+            players.closed(window.app_handle(), label);
+            players.close_owned_by(window.app_handle(), label);
+        }
+        _ => {}
+    }
+}
+
+/// Opens a mini player for `source`, hidden until it's placed. It stays
+/// above other windows and closes with the window that opened it.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn open_mini_player(
+    app_handle: tauri::AppHandle,
+    window: tauri::Window,
+    players: tauri::State<'_, MiniPlayers>,
+    roots: tauri::State<'_, AllowedRoots>,
+    source: MiniPlayerSource,
+) -> Result<MiniPlayer, AppError> {
+    let path = match source {
+        MiniPlayerSource::File { path } => path,
+        MiniPlayerSource::Cached { fingerprint } => {
+            if !is_cache_key(&fingerprint) {
+                return Err(AppError::InvalidAudioRequest {
+                    message: format!("invalid TTS cache key {:?}", fingerprint),
+                });
+            }
+            crate::cache::tts_cache(&app_handle)?.entry_path(&fingerprint)
+        }
+    };
+    let file = roots.validate_path::<Read>(&path)?;
+    let mime_type = mime_type(file.path())?;
+    let bytes = file.read()?;
+    let duration_ms = match mime_type {
+        "audio/wav" => stretch::wav_duration_ms(&bytes),
+        _ => mp3::duration_ms(&bytes),
+    };
+
+    let label = format!(
+        "{}{}",
+        LABEL_PREFIX,
+        players.next_id.fetch_add(1, Ordering::Relaxed)
+    );
+    let player = MiniPlayer {
+        label: label.clone(),
+        owner: window.label().to_string(),
+        path: file.into_path_buf(),
+        mime_type,
+        duration_ms,
+        state: TransportState::Paused,
+        position_ms: 0.0,
+    };
+    // Registered first: the page asks for its player as soon as it loads
+    players
+        .players
+        .lock()
+        .unwrap()
+        .insert(label.clone(), player.clone());
+    let built =
+        tauri::WebviewWindowBuilder::new(&app_handle, &label, tauri::WebviewUrl::App(PAGE.into()))
+            .title("Mini player")
+            .inner_size(WIDTH, HEIGHT)
+            .resizable(false)
+            .always_on_top(true)
+            .skip_taskbar(true)
+            .visible(false)
+            .build();
+    let mini = match built {
+        Ok(mini) => mini,
+        Err(e) => {
+            players.players.lock().unwrap().remove(&label);
+            return Err(e.into());
+        }
+    };
+    if let Some(position) = players.placement(&app_handle) {
+        if let Err(e) = mini.set_position(position) {
+            log::warn!("Failed to place mini player {}: {}", label, e);
+        }
+    }
+    mini.show()?;
+    Ok(player)
+}
+
+/// The calling mini player's file and transport state.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_mini_player(
+    window: tauri::Window,
+    players: tauri::State<'_, MiniPlayers>,
+) -> Option<MiniPlayer> {
+    players.get(window.label())
+}
+
+/// The audio of the calling mini player's file; no other file can be read
+/// this way.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_mini_player_audio(
+    window: tauri::Window,
+    players: tauri::State<'_, MiniPlayers>,
+    roots: tauri::State<'_, AllowedRoots>,
+) -> Result<Vec<u8>, AppError> {
+    let player = players
+        .get(window.label())
+        .ok_or_else(|| AppError::MiniPlayerNotFound {
+            label: window.label().to_string(),
+        })?;
+    Ok(roots.validate_path::<Read>(&player.path)?.read()?)
+}
+
+/// Plays, pauses or seeks mini player `label`, from the player itself or
+/// another window. `path` must be the file it was opened for. While it
+/// plays, main playback gives way as it does for a preview.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn control_mini_player(
+    app_handle: tauri::AppHandle,
+    players: tauri::State<'_, MiniPlayers>,
+    arbiter: tauri::State<'_, PlaybackArbiter>,
+    label: String,
+    path: PathBuf,
+    action: TransportAction,
+) -> Result<MiniPlayer, AppError> {
+    let not_found = || AppError::MiniPlayerNotFound {
+        label: label.clone(),
+    };
+    let scoped = players.get(&label).ok_or_else(not_found)?;
+    if fs::canonicalize(&path).ok().as_deref() != Some(scoped.path.as_path()) {
+        return Err(AppError::PathNotAllowed {
+            path: path.display().to_string(),
+            reason: format!("mini player {} only plays {}", label, scoped.path.display()),
+        });
+    }
+    let player = players.apply(&label, &action).ok_or_else(not_found)?;
+    match player.state {
+        TransportState::Playing => {
+            let remaining = player
+                .duration_ms
+                .map(|d| Duration::from_secs_f64((d - player.position_ms).max(0.0) / 1000.0));
+            arbiter.start_preview(&app_handle, label.clone(), remaining);
+        }
+        TransportState::Paused => {
+            arbiter.stop_preview(&app_handle, &label);
+        }
+    }
+    if let Err(e) = app_handle.emit_to(label.as_str(), "mini-player-transport", &player) {
+        log::warn!("Failed to emit mini-player-transport: {}", e);
+    }
+    Ok(player)
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![
+        open_mini_player,
+        get_mini_player,
+        get_mini_player_audio,
+        control_mini_player,
+    ]
+}
@@ -0,0 +1,147 @@
+// Backend support for the first-run wizard
+use serde::{Deserialize, Serialize};
+
+use crate::assets::{asset_url, AssetKind};
+use crate::error::AppError;
+use crate::settings::SettingsStore;
+use crate::tts::fingerprint::TtsRequestParams;
+use crate::tts::lanes::Lane;
+use crate::tts::synthesis::SpeechInput;
+use crate::tts::TtsService;
+
+/// Standard voices are the cheapest tier; this one also ships a bundled preview.
+const SAMPLE_VOICE: &str = "en-US-Standard-C";
+const SAMPLE_LANGUAGE: &str = "en-US";
+const SAMPLE_TEXT: &str = "Hello from SCLIP.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnboardingStep {
+    Credentials,
+    Backend,
+    SampleSynthesis,
+    Finished,
+}
+
+/// Wizard progress persisted in settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OnboardingProgress {
+    pub completed_steps: Vec<OnboardingStep>,
+    pub skipped: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OnboardingState {
+    pub first_launch: bool,
+    pub credentials_configured: bool,
+    pub backend_running: bool,
+    pub sample_synthesis_succeeded: bool,
+    pub completed_steps: Vec<OnboardingStep>,
+    pub finished: bool,
+    pub skipped: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SampleSynthesis {
+    pub voice_name: String,
+    pub text: String,
+    pub url: String,
+}
+
+fn mark_complete(store: &SettingsStore, step: OnboardingStep) -> Result<(), AppError> {
+    store.update(|settings| {
+        if !settings.onboarding.completed_steps.contains(&step) {
+            settings.onboarding.completed_steps.push(step);
+        }
+    })?;
+    Ok(())
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn get_onboarding_state(
+    store: tauri::State<'_, SettingsStore>,
+) -> Result<OnboardingState, AppError> {
+    let settings = store.get();
+    let progress = settings.onboarding;
+    Ok(OnboardingState {
+        first_launch: store.is_first_launch(),
+        credentials_configured: settings.active_profile.is_some()
+            || crate::credentials::application_default_credentials().is_some(),
+        backend_running: crate::sidecar::is_healthy(crate::sidecar::DEFAULT_PORT).await,
+        sample_synthesis_succeeded: progress
+            .completed_steps
+            .contains(&OnboardingStep::SampleSynthesis),
+        finished: progress.completed_steps.contains(&OnboardingStep::Finished),
+        skipped: progress.skipped,
+        completed_steps: progress.completed_steps,
+    })
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn set_onboarding_step_complete(
+    store: tauri::State<'_, SettingsStore>,
+    step: OnboardingStep,
+) -> Result<(), AppError> {
+    mark_complete(&store, step)
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn skip_onboarding(store: tauri::State<'_, SettingsStore>) -> Result<(), AppError> {
+    store.update(|settings| settings.onboarding.skipped = true)?;
+    Ok(())
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn reset_onboarding(store: tauri::State<'_, SettingsStore>) -> Result<(), AppError> {
+    store.update(|settings| settings.onboarding = OnboardingProgress::default())?;
+    Ok(())
+}
+
+/// Synthesizes a tiny fixed phrase so the wizard can prove credentials work
+/// end to end. The result goes through the TTS cache, so repeated runs are free.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn run_sample_synthesis(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    store: tauri::State<'_, SettingsStore>,
+) -> Result<SampleSynthesis, AppError> {
+    let input = SpeechInput::Text(SAMPLE_TEXT.to_string());
+    let key = TtsRequestParams::speech(SAMPLE_VOICE, SAMPLE_LANGUAGE, &input).fingerprint();
+
+    let cache = crate::cache::tts_cache(&app_handle)?;
+    crate::tts::synthesis::synthesize_cached(
+        &tts,
+        &cache,
+        SAMPLE_VOICE,
+        SAMPLE_LANGUAGE,
+        input,
+        None,
+        Lane::Interactive,
+        None,
+    )
+    .await?;
+    mark_complete(&store, OnboardingStep::SampleSynthesis)?;
+
+    Ok(SampleSynthesis {
+        voice_name: SAMPLE_VOICE.to_string(),
+        text: SAMPLE_TEXT.to_string(),
+        url: asset_url(AssetKind::Tts, &key),
+    })
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![
+        get_onboarding_state,
+        set_onboarding_step_complete,
+        skip_onboarding,
+        reset_onboarding,
+        run_sample_synthesis,
+    ]
+}
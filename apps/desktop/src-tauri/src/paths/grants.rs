@@ -0,0 +1,216 @@
+// Directories outside the app's own that the user allowed writes to. The
+// webview can only ask: `request_directory_access` shows a native
+// confirmation naming the directory, and only a "yes" there adds it to the
+// allowed roots. Grants are kept in the settings, restored at startup and
+// revocable; writes anywhere else outside the app fail with
+// `AccessNotGranted`
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::Command;
+
+use super::roots::{AllowedRoots, RootKind, Write};
+use crate::error::AppError;
+use crate::read_only::WriteAccess;
+use crate::settings::SettingsStore;
+
+const DIALOG_TITLE: &str = "SCLIP";
+/// Reasons are shortened to this in the dialog.
+const MAX_REASON_CHARS: usize = 200;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryGrant {
+    /// Resolved, as matched against written paths.
+    pub dir: PathBuf,
+    /// What the frontend said it needed the directory for.
+    pub reason: String,
+    pub granted_at: DateTime<Utc>,
+}
+
+fn not_granted(path: &Path, reason: impl Into<String>) -> AppError {
+    AppError::AccessNotGranted {
+        path: path.display().to_string(),
+        reason: reason.into(),
+    }
+}
+
+fn dialog_text(dir: &Path, reason: &str) -> String {
+    let reason: String = reason.trim().chars().take(MAX_REASON_CHARS).collect();
+    format!(
+        "Allow SCLIP to save files in this folder and its subfolders?\n\n{}\n\nRequested for: {}",
+        dir.display(),
+        if reason.is_empty() {
+            "(no reason given)"
+        } else {
+            &reason
+        }
+    )
+}
+
+/// Runs a dialog process; its exit status is the answer. `Ok(None)` when
+/// the program isn't there.
+async fn ask(command: &mut Command) -> Result<Option<bool>, AppError> {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+    match command.status().await {
+        Ok(status) => Ok(Some(status.success())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(target_os = "macos")]
+async fn confirm(text: &str) -> Result<Option<bool>, AppError> {
+    // Read from the environment so the text is never parsed as AppleScript
+    const SCRIPT: &str = "display dialog (system attribute \"SCLIP_DIALOG_TEXT\") \
+        with title (system attribute \"SCLIP_DIALOG_TITLE\") \
+        buttons {\"Don't Allow\", \"Allow\"} default button \"Don't Allow\" \
+        cancel button \"Don't Allow\" with icon caution";
+    ask(Command::new("osascript")
+        .args(["-e", SCRIPT])
+        .env("SCLIP_DIALOG_TEXT", text)
+        .env("SCLIP_DIALOG_TITLE", DIALOG_TITLE))
+    .await
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+async fn confirm(text: &str) -> Result<Option<bool>, AppError> {
+    let zenity = ask(Command::new("zenity").args([
+        "--question",
+        "--default-cancel",
+        "--no-markup",
+        "--title",
+        DIALOG_TITLE,
+        "--text",
+        text,
+    ]))
+    .await?;
+    if zenity.is_some() {
+        return Ok(zenity);
+    }
+    ask(Command::new("kdialog").args(["--title", DIALOG_TITLE, "--warningyesno", text])).await
+}
+
+#[cfg(windows)]
+async fn confirm(text: &str) -> Result<Option<bool>, AppError> {
+    const SCRIPT: &str = "Add-Type -AssemblyName System.Windows.Forms; \
+        $r = [System.Windows.Forms.MessageBox]::Show($env:SCLIP_DIALOG_TEXT, \
+        $env:SCLIP_DIALOG_TITLE, 'YesNo', 'Warning', 'Button2'); \
+        if ($r -eq 'Yes') { exit 0 } else { exit 1 }";
+    let mut command = Command::new("powershell");
+    command
+        .args(["-NoProfile", "-NonInteractive", "-Command", SCRIPT])
+        .env("SCLIP_DIALOG_TEXT", text)
+        .env("SCLIP_DIALOG_TITLE", DIALOG_TITLE);
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    command.creation_flags(CREATE_NO_WINDOW);
+    ask(&mut command).await
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn confirm(_text: &str) -> Result<Option<bool>, AppError> {
+    Ok(None)
+}
+
+/// Adds the persisted grants to `roots`, each checked as when it was
+/// given. One that fails is left out and logged; it stays in the settings,
+/// so a directory on an unplugged drive is allowed again from the next
+/// launch after it's back.
+pub fn restore(roots: &AllowedRoots, grants: &[DirectoryGrant]) {
+    for grant in grants {
+        if let Err(e) = roots.restore(RootKind::Export, grant.dir.clone()) {
+            log::warn!("Not restoring the grant for {}: {}", grant.dir.display(), e);
+        }
+    }
+}
+
+/// `dir` resolved, asking the user first unless writes there are already
+/// allowed. A grant the user gives is persisted.
+pub async fn ensure(
+    settings: &SettingsStore,
+    roots: &AllowedRoots,
+    dir: &Path,
+    reason: &str,
+) -> Result<PathBuf, AppError> {
+    if let Ok(allowed) = roots.validate_path::<Write>(dir) {
+        if allowed.path().is_dir() {
+            return Ok(allowed.into_path_buf());
+        }
+    }
+    let resolved = dir
+        .canonicalize()
+        .map_err(|e| not_granted(dir, e.to_string()))?;
+    if !resolved.is_dir() {
+        return Err(not_granted(dir, "not a directory"));
+    }
+    match confirm(&dialog_text(&resolved, reason)).await? {
+        Some(true) => {}
+        Some(false) => return Err(not_granted(dir, "the user declined")),
+        None => {
+            return Err(not_granted(
+                dir,
+                "no confirmation dialog is available on this system",
+            ))
+        }
+    }
+    let resolved = roots.grant(RootKind::Export, &resolved)?;
+    let grant = DirectoryGrant {
+        dir: resolved.clone(),
+        reason: reason.trim().to_string(),
+        granted_at: Utc::now(),
+    };
+    settings.update(|s| {
+        s.directory_grants.retain(|g| g.dir != grant.dir);
+        s.directory_grants.push(grant.clone());
+    })?;
+    log::info!("User granted writes to {}", resolved.display());
+    Ok(resolved)
+}
+
+/// Asks the user, in a native dialog, to allow writes to `path` and its
+/// subdirectories; nothing is asked for a directory already allowed.
+/// Declining fails with `AccessNotGranted`.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn request_directory_access(
+    settings: tauri::State<'_, SettingsStore>,
+    roots: tauri::State<'_, AllowedRoots>,
+    access: tauri::State<'_, WriteAccess>,
+    path: PathBuf,
+    reason: String,
+) -> Result<PathBuf, AppError> {
+    access.check()?;
+    ensure(&settings, &roots, &path, &reason).await
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn list_directory_grants(settings: tauri::State<'_, SettingsStore>) -> Vec<DirectoryGrant> {
+    settings.get().directory_grants
+}
+
+/// Forgets the grant for `dir`; writes there fail again until asked anew.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn revoke_directory_grant(
+    settings: tauri::State<'_, SettingsStore>,
+    roots: tauri::State<'_, AllowedRoots>,
+    access: tauri::State<'_, WriteAccess>,
+    dir: PathBuf,
+) -> Result<(), AppError> {
+    access.check()?;
+    let grants = settings.get().directory_grants;
+    let grant = grants
+        .iter()
+        .find(|g| g.dir == dir || dir.canonicalize().is_ok_and(|resolved| g.dir == resolved))
+        .ok_or_else(|| not_granted(&dir, "no grant for this directory"))?;
+    let revoked = grant.dir.clone();
+    settings.update(|s| s.directory_grants.retain(|g| g.dir != revoked))?;
+    roots.revoke(RootKind::Export, &revoked);
+    log::info!("Revoked the grant for {}", revoked.display());
+    Ok(())
+}
@@ -0,0 +1,256 @@
+// Where the app keeps its files, and opening them in the OS file manager so
+// users can find them without knowing the per-platform locations
+pub mod grants;
+pub mod roots;
+
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::Manager;
+
+use crate::error::AppError;
+use roots::{AllowedRoots, Read};
+
+/// Openers tried in order on Linux when the file manager can't be asked over
+/// D-Bus, e.g. on minimal window managers.
+#[cfg(target_os = "linux")]
+const LINUX_OPENERS: &[&[&str]] = &[
+    &["xdg-open"],
+    &["gio", "open"],
+    &["kde-open5"],
+    &["kde-open"],
+    &["gnome-open"],
+    &["exo-open"],
+];
+
+/// The `identifier` in tauri.conf.json, which names the app's directories.
+const APP_IDENTIFIER: &str = "desktop";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AppPaths {
+    /// settings.json.
+    pub config_dir: PathBuf,
+    /// Catalogs, usage, recordings and the installed backend.
+    pub data_dir: PathBuf,
+    pub log_dir: PathBuf,
+    /// Synthesis and waveform caches; safe to delete.
+    pub cache_dir: PathBuf,
+    pub preview_dir: PathBuf,
+    pub autosave_dir: PathBuf,
+    pub trash_dir: PathBuf,
+}
+
+impl AppPaths {
+    pub fn resolve(app_handle: &tauri::AppHandle) -> Result<Self, AppError> {
+        let path = app_handle.path();
+        Ok(Self::in_dirs(
+            path.app_config_dir()?,
+            path.app_data_dir()?,
+            path.app_log_dir()?,
+            path.app_cache_dir()?,
+        ))
+    }
+
+    /// The directories Tauri would resolve for the app, found without one,
+    /// e.g. by the command line tool.
+    pub fn headless() -> Result<Self, AppError> {
+        let missing = |which: &str| AppError::Io {
+            message: format!("can't find the user's {} directory", which),
+        };
+        let config_dir = dirs::config_dir().ok_or_else(|| missing("config"))?;
+        let data_dir = dirs::data_dir().ok_or_else(|| missing("data"))?;
+        let cache_dir = dirs::cache_dir().ok_or_else(|| missing("cache"))?;
+        #[cfg(target_os = "macos")]
+        let log_dir = dirs::home_dir()
+            .ok_or_else(|| missing("home"))?
+            .join("Library/Logs")
+            .join(APP_IDENTIFIER);
+        #[cfg(not(target_os = "macos"))]
+        let log_dir = dirs::data_local_dir()
+            .ok_or_else(|| missing("local data"))?
+            .join(APP_IDENTIFIER)
+            .join("logs");
+        Ok(Self::in_dirs(
+            config_dir.join(APP_IDENTIFIER),
+            data_dir.join(APP_IDENTIFIER),
+            log_dir,
+            cache_dir.join(APP_IDENTIFIER),
+        ))
+    }
+
+    pub(crate) fn in_dirs(
+        config_dir: PathBuf,
+        data_dir: PathBuf,
+        log_dir: PathBuf,
+        cache_dir: PathBuf,
+    ) -> Self {
+        Self {
+            config_dir,
+            log_dir,
+            cache_dir,
+            preview_dir: crate::cache::preview::writable_dir_in(&data_dir),
+            autosave_dir: data_dir.join("autosave"),
+            trash_dir: data_dir.join("trash"),
+            data_dir,
+        }
+    }
+
+    fn managed(&self) -> [&Path; 7] {
+        [
+            &self.config_dir,
+            &self.data_dir,
+            &self.log_dir,
+            &self.cache_dir,
+            &self.preview_dir,
+            &self.autosave_dir,
+            &self.trash_dir,
+        ]
+    }
+}
+
+/// Managed state remembering what export commands wrote this session, the
+/// only paths outside the app's own directories that may be revealed.
+#[derive(Default)]
+pub struct ExportedPaths {
+    paths: Mutex<HashSet<PathBuf>>,
+}
+
+impl ExportedPaths {
+    /// Records `path` once it exists; everything under a directory counts.
+    pub fn record(&self, path: &Path) {
+        match path.canonicalize() {
+            Ok(path) => {
+                self.paths.lock().unwrap().insert(path);
+            }
+            Err(e) => log::warn!("Not recording export {}: {}", path.display(), e),
+        }
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        self.paths
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|exported| path.starts_with(exported))
+    }
+}
+
+/// `path` resolved, if it is inside an allowed root or an export.
+fn revealable(
+    paths: &AppPaths,
+    roots: &AllowedRoots,
+    exported: &ExportedPaths,
+    path: &Path,
+) -> Result<PathBuf, AppError> {
+    let cannot = |reason: &str| AppError::CannotReveal {
+        path: path.display().to_string(),
+        reason: reason.to_string(),
+    };
+    // App directories are created on first use; revealing one before that
+    // should still open it
+    if let Some(dir) = paths.managed().into_iter().find(|dir| *dir == path) {
+        fs::create_dir_all(dir)?;
+    }
+    let denied = match roots.validate_path::<Read>(path) {
+        Ok(allowed) => return Ok(allowed.into_path_buf()),
+        Err(e) => e,
+    };
+    // Exports of this session may be anywhere the user chose
+    let resolved = path.canonicalize().map_err(|e| cannot(&e.to_string()))?;
+    if exported.contains(&resolved) {
+        Ok(resolved)
+    } else {
+        Err(cannot(&denied.to_string()))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_dir(dir: &Path) -> Result<(), AppError> {
+    let mut failures = Vec::new();
+    for opener in LINUX_OPENERS {
+        let status = std::process::Command::new(opener[0])
+            .args(&opener[1..])
+            .arg(dir)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status();
+        match status {
+            Ok(status) if status.success() => return Ok(()),
+            Ok(status) => failures.push(format!("{} exited with {}", opener.join(" "), status)),
+            // Not installed; try the next one
+            Err(e) => failures.push(format!("{}: {}", opener.join(" "), e)),
+        }
+    }
+    Err(AppError::CannotReveal {
+        path: dir.display().to_string(),
+        reason: failures.join("; "),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_dir(dir: &Path) -> Result<(), AppError> {
+    tauri_plugin_opener::open_path(dir, None::<&str>).map_err(|e| AppError::CannotReveal {
+        path: dir.display().to_string(),
+        reason: e.to_string(),
+    })
+}
+
+/// Opens a directory itself, and shows a file selected in its folder.
+fn reveal(path: &Path) -> Result<(), AppError> {
+    if path.is_dir() {
+        return open_dir(path);
+    }
+    match tauri_plugin_opener::reveal_item_in_dir(path) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            log::warn!(
+                "Failed to reveal {}, opening its folder: {}",
+                path.display(),
+                e
+            );
+            open_dir(path.parent().unwrap_or(path))
+        }
+    }
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_app_paths(app_handle: tauri::AppHandle) -> Result<AppPaths, AppError> {
+    AppPaths::resolve(&app_handle)
+}
+
+/// Opens `path` in the OS file manager. Only what `AllowedRoots` allows
+/// reading, and the outputs of export commands run this session, can be
+/// revealed.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn reveal_in_file_manager(
+    app_handle: tauri::AppHandle,
+    roots: tauri::State<'_, AllowedRoots>,
+    exported: tauri::State<'_, ExportedPaths>,
+    path: PathBuf,
+) -> Result<(), AppError> {
+    let paths = AppPaths::resolve(&app_handle)?;
+    let resolved = revealable(&paths, &roots, &exported, &path)?;
+    tauri::async_runtime::spawn_blocking(move || reveal(&resolved))
+        .await
+        .map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })?
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![
+        roots::grant_export_dir,
+        roots::list_allowed_roots,
+        get_app_paths,
+        reveal_in_file_manager,
+        grants::request_directory_access,
+        grants::list_directory_grants,
+        grants::revoke_directory_grant,
+    ]
+}
@@ -0,0 +1,635 @@
+// The directories commands may touch on behalf of the frontend. Every path
+// a command receives is checked here once, resolved (symlinks included) and
+// matched against the roots allowing what the command does with it; the
+// `AllowedPath` that comes back is what the file helpers take
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::marker::PhantomData;
+use std::path::{Component, Path, PathBuf};
+use std::sync::RwLock;
+
+use super::AppPaths;
+use crate::error::AppError;
+use crate::long_path::MAX_COMPONENT_LEN;
+use crate::read_only::WriteAccess;
+use crate::settings::SettingsStore;
+
+/// Names Windows maps to devices in every directory, with any extension.
+const WINDOWS_DEVICE_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9", "CONIN$",
+    "CONOUT$",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PathIntent {
+    Read,
+    Write,
+    Delete,
+}
+
+/// Marker types naming the intent an `AllowedPath` was checked for.
+pub trait Intent {
+    const INTENT: PathIntent;
+}
+
+#[derive(Debug)]
+pub enum Read {}
+#[derive(Debug)]
+pub enum Write {}
+#[derive(Debug)]
+pub enum Delete {}
+
+impl Intent for Read {
+    const INTENT: PathIntent = PathIntent::Read;
+}
+impl Intent for Write {
+    const INTENT: PathIntent = PathIntent::Write;
+}
+impl Intent for Delete {
+    const INTENT: PathIntent = PathIntent::Delete;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RootKind {
+    AppData,
+    Config,
+    Cache,
+    /// The bundled resources; never written.
+    Resources,
+    /// The directory of a project opened in a window.
+    Project,
+    /// A directory the user picked to export to or import from.
+    Export,
+}
+
+impl RootKind {
+    fn permits(self, intent: PathIntent) -> bool {
+        match (self, intent) {
+            // settings.json is written by the settings store alone
+            (RootKind::Resources | RootKind::Config, PathIntent::Read) => true,
+            (RootKind::Resources | RootKind::Config, _) => false,
+            // Exports are replaced, never removed through a command
+            (RootKind::Export, PathIntent::Delete) => false,
+            _ => true,
+        }
+    }
+}
+
+/// A path checked by `AllowedRoots::validate_path` for intent `I`: absolute,
+/// with symlinks resolved, inside a root allowing `I`. Only that function
+/// makes one.
+#[derive(Debug)]
+pub struct AllowedPath<I: Intent> {
+    path: PathBuf,
+    _intent: PhantomData<I>,
+}
+
+impl<I: Intent> AllowedPath<I> {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn into_path_buf(self) -> PathBuf {
+        self.path
+    }
+}
+
+impl AllowedPath<Read> {
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        fs::read(crate::long_path::extended(&self.path))
+    }
+}
+
+impl AllowedPath<Write> {
+    /// Writes through a sibling temp file, creating the parent directory.
+    pub fn write_atomic(&self, bytes: &[u8]) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        crate::cache::write_atomic(&self.path, bytes)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AllowedRoot {
+    pub kind: RootKind,
+    pub dir: PathBuf,
+}
+
+/// Managed registry of the directories commands may touch: the app's own,
+/// the bundled resources, the directories of opened projects and those the
+/// user granted through `paths::grants`.
+pub struct AllowedRoots {
+    roots: RwLock<Vec<AllowedRoot>>,
+}
+
+fn not_allowed(path: &Path, reason: impl Into<String>) -> AppError {
+    AppError::PathNotAllowed {
+        path: path.display().to_string(),
+        reason: reason.into(),
+    }
+}
+
+/// Rejects what resolving can't make safe: relative paths, "..", UNC and
+/// device paths, overlong names and, on Windows, names the filesystem
+/// reinterprets (alternate data streams, trailing dots, device names).
+fn check_lexical(path: &Path) -> Result<(), AppError> {
+    let raw = path.as_os_str().to_string_lossy();
+    if raw.is_empty() {
+        return Err(not_allowed(path, "empty path"));
+    }
+    if raw.contains('\0') {
+        return Err(not_allowed(path, "contains a NUL character"));
+    }
+    // `\\server\share`, `\\?\` and `\\.\` on Windows; `//` may name a
+    // network root on POSIX
+    if raw.starts_with(r"\\") || raw.starts_with("//") {
+        return Err(not_allowed(path, "UNC and device paths are not allowed"));
+    }
+    if !path.is_absolute() {
+        return Err(not_allowed(path, "must be absolute"));
+    }
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                return Err(not_allowed(path, "must not contain \"..\""));
+            }
+            Component::Normal(name) => {
+                if name.len() > MAX_COMPONENT_LEN {
+                    return Err(not_allowed(
+                        path,
+                        format!("a name is longer than {} bytes", MAX_COMPONENT_LEN),
+                    ));
+                }
+                if cfg!(windows) {
+                    check_windows_name(path, &name.to_string_lossy())?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn check_windows_name(path: &Path, name: &str) -> Result<(), AppError> {
+    if name.contains(':') {
+        return Err(not_allowed(path, "alternate data streams are not allowed"));
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Err(not_allowed(path, "names must not end with a dot or space"));
+    }
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    if WINDOWS_DEVICE_NAMES
+        .iter()
+        .any(|device| device.eq_ignore_ascii_case(stem))
+    {
+        return Err(not_allowed(path, format!("{} is a device name", stem)));
+    }
+    Ok(())
+}
+
+/// `path` with symlinks resolved as far as it exists, and the missing rest
+/// appended as given, for paths about to be created.
+fn resolve_existing_prefix(path: &Path) -> io::Result<PathBuf> {
+    let mut missing = Vec::new();
+    let mut existing = path;
+    loop {
+        match existing.canonicalize() {
+            Ok(resolved) => {
+                return Ok(missing
+                    .iter()
+                    .rev()
+                    .fold(resolved, |dir, name| dir.join(name)))
+            }
+            // A dangling symlink would be created at its target
+            Err(e)
+                if e.kind() == io::ErrorKind::NotFound
+                    && fs::symlink_metadata(existing).is_ok() =>
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is a dangling symlink", existing.display()),
+                ));
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+                    return Err(e);
+                };
+                missing.push(name);
+                existing = parent;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Where `path` leads for `intent`. Reads and writes follow a symlink to its
+/// target, so the target is what must be inside a root; a delete removes
+/// the link itself.
+fn resolve(path: &Path, intent: PathIntent) -> Result<PathBuf, AppError> {
+    let failed = |e: io::Error| not_allowed(path, e.to_string());
+    match intent {
+        PathIntent::Read => path.canonicalize().map_err(failed),
+        PathIntent::Write => resolve_existing_prefix(path).map_err(failed),
+        PathIntent::Delete => {
+            fs::symlink_metadata(path).map_err(failed)?;
+            match (path.parent(), path.file_name()) {
+                (Some(parent), Some(name)) => Ok(parent.canonicalize().map_err(failed)?.join(name)),
+                _ => Err(not_allowed(path, "can't delete a filesystem root")),
+            }
+        }
+    }
+}
+
+/// `dir` resolved, if it's an existing directory other than a filesystem
+/// root.
+fn grantable(dir: &Path) -> Result<PathBuf, AppError> {
+    check_lexical(dir)?;
+    let resolved = dir
+        .canonicalize()
+        .map_err(|e| not_allowed(dir, e.to_string()))?;
+    if !resolved.is_dir() {
+        return Err(not_allowed(dir, "not a directory"));
+    }
+    if resolved.parent().is_none() {
+        return Err(not_allowed(dir, "can't grant a whole drive"));
+    }
+    Ok(resolved)
+}
+
+impl AllowedRoots {
+    /// The app's own directories, and `resource_dir` when known.
+    pub fn new(paths: &AppPaths, resource_dir: Option<&Path>) -> Self {
+        let mut roots = vec![
+            AllowedRoot {
+                kind: RootKind::Config,
+                dir: paths.config_dir.clone(),
+            },
+            AllowedRoot {
+                kind: RootKind::AppData,
+                dir: paths.data_dir.clone(),
+            },
+            AllowedRoot {
+                kind: RootKind::AppData,
+                dir: paths.log_dir.clone(),
+            },
+            AllowedRoot {
+                kind: RootKind::Cache,
+                dir: paths.cache_dir.clone(),
+            },
+        ];
+        if let Some(dir) = resource_dir {
+            roots.push(AllowedRoot {
+                kind: RootKind::Resources,
+                dir: dir.to_path_buf(),
+            });
+        }
+        Self {
+            roots: RwLock::new(roots),
+        }
+    }
+
+    /// Adds `dir`, which must be an existing directory other than a
+    /// filesystem root, and returns it resolved.
+    pub fn grant(&self, kind: RootKind, dir: &Path) -> Result<PathBuf, AppError> {
+        let resolved = grantable(dir)?;
+        let mut roots = self.roots.write().unwrap();
+        if !roots.iter().any(|r| r.kind == kind && r.dir == resolved) {
+            log::info!(
+                "Granted access to {:?} directory {}",
+                kind,
+                resolved.display()
+            );
+            roots.push(AllowedRoot {
+                kind,
+                dir: resolved.clone(),
+            });
+        }
+        Ok(resolved)
+    }
+
+    /// Adds `dir` as resolved when it was granted, checked as `grant`
+    /// checks it; fails too when it resolves somewhere else now, e.g.
+    /// through a symlink swapped in since.
+    pub fn restore(&self, kind: RootKind, dir: PathBuf) -> Result<(), AppError> {
+        if grantable(&dir)? != dir {
+            return Err(not_allowed(&dir, "resolves to another directory now"));
+        }
+        let mut roots = self.roots.write().unwrap();
+        if !roots.iter().any(|r| r.kind == kind && r.dir == dir) {
+            roots.push(AllowedRoot { kind, dir });
+        }
+        Ok(())
+    }
+
+    pub fn revoke(&self, kind: RootKind, dir: &Path) {
+        self.roots
+            .write()
+            .unwrap()
+            .retain(|r| !(r.kind == kind && r.dir == dir));
+    }
+
+    pub fn list(&self) -> Vec<AllowedRoot> {
+        self.roots.read().unwrap().clone()
+    }
+
+    /// Checks `path` for intent `I` and resolves it. Fails with
+    /// `PathNotAllowed` unless it lands inside a root allowing `I`, or with
+    /// `AccessNotGranted` for a write outside every root, which the user
+    /// can allow with `request_directory_access`; a root itself can't be
+    /// deleted.
+    pub fn validate_path<I: Intent>(&self, path: &Path) -> Result<AllowedPath<I>, AppError> {
+        let intent = I::INTENT;
+        check_lexical(path)?;
+        let resolved = resolve(path, intent)?;
+        let roots = self.roots.read().unwrap();
+        let mut outside = true;
+        for root in roots.iter() {
+            // Roots may not exist yet; resolved the same way as the path
+            let Ok(dir) = resolve_existing_prefix(&root.dir) else {
+                continue;
+            };
+            if !resolved.starts_with(&dir) {
+                continue;
+            }
+            outside = false;
+            // Where the config directory is the data directory too, as on
+            // macOS and Windows, the files directly in it stay unwritable
+            if root.kind == RootKind::Config
+                && intent != PathIntent::Read
+                && resolved.parent() == Some(dir.as_path())
+            {
+                break;
+            }
+            if root.kind.permits(intent) && !(intent == PathIntent::Delete && resolved == dir) {
+                return Ok(AllowedPath {
+                    path: resolved,
+                    _intent: PhantomData,
+                });
+            }
+        }
+        if outside && intent == PathIntent::Write {
+            return Err(AppError::AccessNotGranted {
+                path: path.display().to_string(),
+                reason: "outside the app and project directories, and not granted".to_string(),
+            });
+        }
+        Err(not_allowed(
+            path,
+            if outside {
+                "outside the app, project and granted directories".to_string()
+            } else {
+                format!("{:?} is not allowed there", intent).to_lowercase()
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+impl AllowedRoots {
+    /// Roots holding only `dir`, as a project directory, for the tests of
+    /// modules that take checked paths.
+    pub(crate) fn scratch(dir: &Path) -> Self {
+        Self {
+            roots: RwLock::new(vec![AllowedRoot {
+                kind: RootKind::Project,
+                dir: dir.to_path_buf(),
+            }]),
+        }
+    }
+}
+
+/// Grants the commands access to `dir`, a directory the user picked in a
+/// file dialog, for reading and writing; returns it resolved. Asks for
+/// confirmation like `request_directory_access` unless already granted.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn grant_export_dir(
+    settings: tauri::State<'_, SettingsStore>,
+    roots: tauri::State<'_, AllowedRoots>,
+    access: tauri::State<'_, WriteAccess>,
+    dir: PathBuf,
+) -> Result<PathBuf, AppError> {
+    access.check()?;
+    super::grants::ensure(&settings, &roots, &dir, "Export").await
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn list_allowed_roots(roots: tauri::State<'_, AllowedRoots>) -> Vec<AllowedRoot> {
+    roots.list()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::scratch::Scratch;
+
+    /// The app's directories in `scratch`, created, with the config and
+    /// data ones under the names given.
+    fn app_paths(scratch: &Scratch, config: &str, data: &str) -> AppPaths {
+        let dirs = [config, data, "logs", "cache"].map(|name| scratch.0.join(name));
+        for dir in &dirs {
+            fs::create_dir_all(dir).unwrap();
+        }
+        let [config_dir, data_dir, log_dir, cache_dir] = dirs;
+        AppPaths::in_dirs(config_dir, data_dir, log_dir, cache_dir)
+    }
+
+    #[test]
+    fn config_root_is_read_only() {
+        let scratch = Scratch::new("roots");
+        let paths = app_paths(&scratch, "config", "data");
+        let roots = AllowedRoots::new(&paths, None);
+        let settings = paths.config_dir.join("settings.json");
+        fs::write(&settings, "{}").unwrap();
+
+        assert!(roots.validate_path::<Read>(&settings).is_ok());
+        assert!(matches!(
+            roots.validate_path::<Write>(&settings),
+            Err(AppError::PathNotAllowed { .. })
+        ));
+        assert!(roots
+            .validate_path::<Write>(&paths.config_dir.join("sub/file.txt"))
+            .is_err());
+        assert!(roots
+            .validate_path::<Write>(&paths.data_dir.join("file.txt"))
+            .is_ok());
+    }
+
+    #[test]
+    fn shared_config_dir_keeps_its_files_unwritable() {
+        let scratch = Scratch::new("roots");
+        let paths = app_paths(&scratch, "support", "support");
+        let roots = AllowedRoots::new(&paths, None);
+
+        assert!(roots
+            .validate_path::<Write>(&paths.config_dir.join("settings.json"))
+            .is_err());
+        assert!(roots
+            .validate_path::<Write>(&paths.data_dir.join("recordings/take.wav"))
+            .is_ok());
+    }
+
+    #[test]
+    fn grant_and_restore_refuse_files_and_drives() {
+        let scratch = Scratch::new("roots");
+        let roots = AllowedRoots::new(&app_paths(&scratch, "config", "data"), None);
+        let file = scratch.0.join("file.txt");
+        fs::write(&file, "").unwrap();
+        let drive = PathBuf::from(if cfg!(windows) { r"C:\" } else { "/" });
+
+        for dir in [&file, &drive, &scratch.0.join("missing")] {
+            assert!(roots.grant(RootKind::Export, dir).is_err());
+            assert!(roots.restore(RootKind::Export, dir.clone()).is_err());
+        }
+        assert!(roots
+            .restore(RootKind::Export, scratch.0.join("config/../data"))
+            .is_err());
+        assert!(roots
+            .list()
+            .iter()
+            .all(|root| root.kind != RootKind::Export));
+
+        let export = scratch.0.join("export");
+        fs::create_dir(&export).unwrap();
+        roots.restore(RootKind::Export, export.clone()).unwrap();
+        assert!(roots
+            .validate_path::<Write>(&export.join("take.mp3"))
+            .is_ok());
+    }
+
+    fn reason(result: Result<impl std::fmt::Debug, AppError>) -> String {
+        match result {
+            Err(AppError::PathNotAllowed { reason, .. }) => reason,
+            other => panic!("expected PathNotAllowed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn paths_resolving_cant_make_safe_are_refused() {
+        let scratch = Scratch::new("roots");
+        let roots = AllowedRoots::new(&app_paths(&scratch, "config", "data"), None);
+        let data = scratch.0.join("data");
+
+        assert_eq!(
+            reason(roots.validate_path::<Read>(Path::new(""))),
+            "empty path"
+        );
+        assert_eq!(
+            reason(roots.validate_path::<Read>(Path::new("data/file.txt"))),
+            "must be absolute"
+        );
+        assert_eq!(
+            reason(roots.validate_path::<Write>(&data.join("../config/settings.json"))),
+            "must not contain \"..\""
+        );
+        assert_eq!(
+            reason(roots.validate_path::<Read>(Path::new("//server/share/file.txt"))),
+            "UNC and device paths are not allowed"
+        );
+        assert_eq!(
+            reason(roots.validate_path::<Write>(&data.join("a\0b"))),
+            "contains a NUL character"
+        );
+        assert!(
+            reason(roots.validate_path::<Write>(&data.join("a".repeat(256))))
+                .starts_with("a name is longer than")
+        );
+    }
+
+    #[test]
+    fn intents_are_checked_against_each_kind_of_root() {
+        let scratch = Scratch::new("roots");
+        let roots = AllowedRoots::new(&app_paths(&scratch, "config", "data"), None);
+        let export = scratch.0.join("export");
+        fs::create_dir(&export).unwrap();
+        fs::write(export.join("take.mp3"), b"").unwrap();
+        roots.grant(RootKind::Export, &export).unwrap();
+
+        assert!(roots
+            .validate_path::<Write>(&export.join("take.mp3"))
+            .is_ok());
+        assert_eq!(
+            reason(roots.validate_path::<Delete>(&export.join("take.mp3"))),
+            "delete is not allowed there"
+        );
+        // A root itself can't be deleted, only what's in it
+        let data = scratch.0.join("data");
+        assert!(roots.validate_path::<Delete>(&data).is_err());
+        fs::write(data.join("old.json"), b"").unwrap();
+        assert!(roots
+            .validate_path::<Delete>(&data.join("old.json"))
+            .is_ok());
+
+        // Writes elsewhere can be granted; reads can't
+        let elsewhere = scratch.0.join("elsewhere.txt");
+        assert!(matches!(
+            roots.validate_path::<Write>(&elsewhere),
+            Err(AppError::AccessNotGranted { .. })
+        ));
+        fs::write(&elsewhere, b"").unwrap();
+        assert_eq!(
+            reason(roots.validate_path::<Read>(&elsewhere)),
+            "outside the app, project and granted directories"
+        );
+
+        // Missing directories are resolved as far as they exist
+        let nested = roots
+            .validate_path::<Write>(&data.join("a/b/c.txt"))
+            .unwrap();
+        assert_eq!(nested.path(), data.join("a/b/c.txt"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_checked_where_they_lead() {
+        use std::os::unix::fs::symlink;
+
+        let scratch = Scratch::new("roots");
+        let roots = AllowedRoots::new(&app_paths(&scratch, "config", "data"), None);
+        let data = scratch.0.join("data");
+        let secret = scratch.0.join("secret.txt");
+        fs::write(&secret, b"secret").unwrap();
+        symlink(&secret, data.join("link.txt")).unwrap();
+        symlink(&scratch.0, data.join("up")).unwrap();
+
+        assert!(roots.validate_path::<Read>(&data.join("link.txt")).is_err());
+        assert!(matches!(
+            roots.validate_path::<Write>(&data.join("link.txt")),
+            Err(AppError::AccessNotGranted { .. })
+        ));
+        assert!(roots
+            .validate_path::<Read>(&data.join("up/secret.txt"))
+            .is_err());
+        assert!(roots
+            .validate_path::<Write>(&data.join("up/new.txt"))
+            .is_err());
+        // Removing the link removes nothing outside
+        let link = roots
+            .validate_path::<Delete>(&data.join("link.txt"))
+            .unwrap();
+        assert_eq!(link.path(), data.join("link.txt"));
+
+        // A link from outside into a root is where it leads
+        let inside = data.join("notes.txt");
+        fs::write(&inside, b"").unwrap();
+        symlink(&inside, scratch.0.join("shortcut.txt")).unwrap();
+        let read = roots
+            .validate_path::<Read>(&scratch.0.join("shortcut.txt"))
+            .unwrap();
+        assert_eq!(read.path(), inside);
+
+        symlink(data.join("missing.txt"), data.join("dangling.txt")).unwrap();
+        assert!(roots
+            .validate_path::<Write>(&data.join("dangling.txt"))
+            .unwrap_err()
+            .to_string()
+            .contains("dangling symlink"));
+    }
+}
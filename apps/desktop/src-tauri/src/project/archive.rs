@@ -0,0 +1,789 @@
+// Zip export and import of a whole project, for moving it to another machine
+// or archiving it
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use super::{rewrite_strings, ProjectLocation, RESOURCES_DIR};
+use crate::cancellation::CancellationRegistry;
+use crate::error::AppError;
+use crate::jobs::{JobKind, JobManager};
+use crate::long_path;
+use crate::paths::roots::{self, AllowedPath, AllowedRoots};
+use crate::paths::ExportedPaths;
+use crate::read_only::WriteAccess;
+
+pub const MANIFEST_ENTRY: &str = "manifest.json";
+const FORMAT_VERSION: u32 = 1;
+/// Files inside the project directory keep their relative path under here.
+const PROJECT_PREFIX: &str = "project/";
+/// Referenced files from elsewhere on disk are stored under here, one
+/// numbered directory each so equal file names don't collide.
+const EXTERNAL_PREFIX: &str = "external/";
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Bytes between progress events.
+const PROGRESS_STEP: u64 = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    Project,
+    Dictionary,
+    Media,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    /// Path inside the zip.
+    pub path: String,
+    pub kind: EntryKind,
+    pub bytes: u64,
+    pub sha256: String,
+    /// Strings in the project file that referred to this file, rewritten on
+    /// import.
+    #[serde(default)]
+    pub references: Vec<String>,
+}
+
+/// Stored as `manifest.json`, the last entry of the zip.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    pub format_version: u32,
+    pub app_version: String,
+    pub created_at: DateTime<Utc>,
+    /// The project's own `version` field, if it has one.
+    pub project_version: Option<String>,
+    /// Zip path of the project file.
+    pub project_file: String,
+    pub entries: Vec<ArchiveEntry>,
+}
+
+struct PlannedFile {
+    source: PathBuf,
+    path: String,
+    kind: EntryKind,
+    bytes: u64,
+    references: Vec<String>,
+}
+
+#[derive(Default)]
+struct Plan {
+    files: Vec<PlannedFile>,
+    by_source: HashMap<PathBuf, usize>,
+    external: usize,
+}
+
+impl Plan {
+    /// Adds `source` (canonical) unless it is already planned, in which case
+    /// only its references are merged.
+    fn add(
+        &mut self,
+        project_dir: &Path,
+        source: PathBuf,
+        kind: EntryKind,
+        references: Vec<String>,
+    ) -> Result<(), AppError> {
+        if let Some(&index) = self.by_source.get(&source) {
+            let planned = &mut self.files[index];
+            for reference in references {
+                if !planned.references.contains(&reference) {
+                    planned.references.push(reference);
+                }
+            }
+            return Ok(());
+        }
+        let path = match source.strip_prefix(project_dir) {
+            Ok(relative) => format!("{}{}", PROJECT_PREFIX, zip_path(relative, &source)?),
+            Err(_) => {
+                self.external += 1;
+                let name = source
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .ok_or_else(|| unsupported_name(&source))?;
+                format!("{}{}/{}", EXTERNAL_PREFIX, self.external, name)
+            }
+        };
+        let bytes = fs::metadata(&source)?.len();
+        self.by_source.insert(source.clone(), self.files.len());
+        self.files.push(PlannedFile {
+            source,
+            path,
+            kind,
+            bytes,
+            references,
+        });
+        Ok(())
+    }
+}
+
+fn unsupported_name(path: &Path) -> AppError {
+    AppError::InvalidProject {
+        path: path.display().to_string(),
+        message: "file name is not valid UTF-8".to_string(),
+    }
+}
+
+/// `/`-separated form of a relative path, as zip entries are named.
+fn zip_path(relative: &Path, source: &Path) -> Result<String, AppError> {
+    let parts: Option<Vec<&str>> = relative
+        .components()
+        .map(|c| match c {
+            Component::Normal(part) => part.to_str(),
+            _ => None,
+        })
+        .collect();
+    Ok(parts.ok_or_else(|| unsupported_name(source))?.join("/"))
+}
+
+/// Regular files under `dir`, recursively and in a stable order. Symlinks
+/// are skipped so a link loop can't make the export run forever.
+fn collect_files(dir: &Path, out: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let mut entries: Vec<fs::DirEntry> = fs::read_dir(dir)?.flatten().collect();
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(&entry.path(), out)?;
+        } else if file_type.is_file() {
+            out.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+fn plan_export(location: &ProjectLocation) -> Result<(Plan, serde_json::Value), AppError> {
+    let project = location.read()?;
+    let dir = fs::canonicalize(&location.dir)?;
+    let mut plan = Plan::default();
+
+    plan.add(
+        &dir,
+        fs::canonicalize(&location.file)?,
+        EntryKind::Project,
+        Vec::new(),
+    )?;
+    if let Some(dictionary) = location.pronunciation_file() {
+        plan.add(
+            &dir,
+            fs::canonicalize(dictionary)?,
+            EntryKind::Dictionary,
+            Vec::new(),
+        )?;
+    }
+    // Backend projects keep their media here without listing it in
+    // project.json
+    let resources = dir.join(RESOURCES_DIR);
+    if resources.is_dir() {
+        let mut files = Vec::new();
+        collect_files(&resources, &mut files)?;
+        for file in files {
+            plan.add(&dir, file, EntryKind::Media, Vec::new())?;
+        }
+    }
+    for (source, references) in location.media_references(&project) {
+        plan.add(&dir, source, EntryKind::Media, references)?;
+    }
+    Ok((plan, project))
+}
+
+/// Streams `reader` into `writer` in fixed-size chunks, hashing as it goes.
+/// Read errors go through `read_error` so callers can name the entry.
+pub(crate) fn copy_hashed(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    cancel: &AtomicBool,
+    read_error: impl Fn(std::io::Error) -> AppError,
+    mut on_chunk: impl FnMut(u64),
+) -> Result<(u64, String), AppError> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut copied = 0u64;
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(AppError::ArchiveCancelled);
+        }
+        let read = reader.read(&mut buffer).map_err(&read_error)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        writer.write_all(&buffer[..read])?;
+        copied += read as u64;
+        on_chunk(read as u64);
+    }
+    let digest = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Ok((copied, digest))
+}
+
+/// `(processed_bytes, total_bytes, current_entry)`
+pub type ProgressFn<'a> = &'a dyn Fn(u64, u64, &str);
+
+/// Reports the start of each entry and then every `PROGRESS_STEP` bytes.
+pub(crate) struct Tracker<'a> {
+    total: u64,
+    processed: u64,
+    reported: u64,
+    progress: ProgressFn<'a>,
+}
+
+impl<'a> Tracker<'a> {
+    pub(crate) fn new(total: u64, progress: ProgressFn<'a>) -> Self {
+        Self {
+            total,
+            processed: 0,
+            reported: 0,
+            progress,
+        }
+    }
+
+    pub(crate) fn start(&mut self, entry: &str) {
+        self.reported = self.processed;
+        (self.progress)(self.processed, self.total, entry);
+    }
+
+    pub(crate) fn advance(&mut self, bytes: u64, entry: &str) {
+        self.processed += bytes;
+        if self.processed - self.reported >= PROGRESS_STEP {
+            self.start(entry);
+        }
+    }
+}
+
+pub(crate) fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Writes the project, its pronunciation dictionary and every media file it
+/// uses into `output_zip`. Media is stored uncompressed and streamed, so
+/// memory use doesn't grow with project size.
+pub fn export(
+    project: &AllowedPath<roots::Read>,
+    output: &AllowedPath<roots::Write>,
+    cancel: &AtomicBool,
+    progress: ProgressFn<'_>,
+) -> Result<PathBuf, AppError> {
+    let (project_path, output_zip) = (project.path(), output.path());
+    long_path::check_chosen(output_zip)?;
+    let location = ProjectLocation::locate(project_path)?;
+    let (plan, project) = plan_export(&location)?;
+
+    let partial = sibling(output_zip, ".partial");
+    if let Some(parent) = output_zip.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let result = write_archive(&plan, &project, &partial, cancel, progress);
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    fs::rename(&partial, output_zip)?;
+    log::info!(
+        "Exported project {} ({} files) to {}",
+        location.file.display(),
+        plan.files.len(),
+        output_zip.display()
+    );
+    Ok(output_zip.to_path_buf())
+}
+
+fn write_archive(
+    plan: &Plan,
+    project: &serde_json::Value,
+    partial: &Path,
+    cancel: &AtomicBool,
+    progress: ProgressFn<'_>,
+) -> Result<(), AppError> {
+    let mut zip = ZipWriter::new(fs::File::create(partial)?);
+    let mut tracker = Tracker::new(plan.files.iter().map(|f| f.bytes).sum(), progress);
+    let mut entries = Vec::with_capacity(plan.files.len());
+
+    for file in &plan.files {
+        tracker.start(&file.path);
+        let method = match file.kind {
+            // Audio and video are already compressed
+            EntryKind::Media => CompressionMethod::Stored,
+            EntryKind::Project | EntryKind::Dictionary => CompressionMethod::Deflated,
+        };
+        let options = SimpleFileOptions::default()
+            .compression_method(method)
+            .large_file(file.bytes >= u32::MAX as u64);
+        zip.start_file(file.path.as_str(), options)?;
+        let (bytes, sha256) = copy_hashed(
+            fs::File::open(&file.source)?,
+            &mut zip,
+            cancel,
+            AppError::from,
+            |n| tracker.advance(n, &file.path),
+        )?;
+        entries.push(ArchiveEntry {
+            path: file.path.clone(),
+            kind: file.kind,
+            bytes,
+            sha256,
+            references: file.references.clone(),
+        });
+    }
+
+    let manifest = ArchiveManifest {
+        format_version: FORMAT_VERSION,
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: Utc::now(),
+        project_version: project
+            .get("version")
+            .and_then(|v| v.as_str())
+            .map(String::from),
+        project_file: plan.files[0].path.clone(),
+        entries,
+    };
+    let json = serde_json::to_vec_pretty(&manifest).map_err(|e| AppError::Io {
+        message: e.to_string(),
+    })?;
+    zip.start_file(
+        MANIFEST_ENTRY,
+        SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
+    )?;
+    zip.write_all(&json)?;
+    zip.finish()?.sync_all()?;
+    (progress)(tracker.total, tracker.total, MANIFEST_ENTRY);
+    Ok(())
+}
+
+pub(crate) fn corrupt(entry: &str, message: impl ToString) -> AppError {
+    AppError::CorruptArchive {
+        entry: entry.to_string(),
+        message: message.to_string(),
+    }
+}
+
+/// Relative extraction path for a zip entry name, refusing anything that
+/// could land outside the destination.
+pub(crate) fn safe_relative(name: &str) -> Result<PathBuf, AppError> {
+    let unsafe_entry = || AppError::UnsafeArchiveEntry {
+        entry: name.to_string(),
+    };
+    if name.contains('\\') {
+        return Err(unsafe_entry());
+    }
+    let mut relative = PathBuf::new();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(unsafe_entry())
+            }
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        return Err(unsafe_entry());
+    }
+    Ok(relative)
+}
+
+/// Where an archived entry goes, relative to the destination directory.
+fn import_target(entry: &str) -> Result<PathBuf, AppError> {
+    if let Some(rest) = entry.strip_prefix(PROJECT_PREFIX) {
+        safe_relative(rest)
+    } else if entry.starts_with(EXTERNAL_PREFIX) {
+        safe_relative(entry)
+    } else {
+        Err(corrupt(entry, "entry is outside project/ and external/"))
+    }
+}
+
+fn read_manifest(archive: &mut ZipArchive<fs::File>) -> Result<ArchiveManifest, AppError> {
+    let mut file = archive
+        .by_name(MANIFEST_ENTRY)
+        .map_err(|e| corrupt(MANIFEST_ENTRY, e))?;
+    let mut json = String::new();
+    file.read_to_string(&mut json)
+        .map_err(|e| corrupt(MANIFEST_ENTRY, e))?;
+    let manifest: ArchiveManifest =
+        serde_json::from_str(&json).map_err(|e| corrupt(MANIFEST_ENTRY, e))?;
+    if manifest.format_version > FORMAT_VERSION {
+        return Err(corrupt(
+            MANIFEST_ENTRY,
+            format!(
+                "format version {} is newer than this app supports ({})",
+                manifest.format_version, FORMAT_VERSION
+            ),
+        ));
+    }
+    if !manifest
+        .entries
+        .iter()
+        .any(|e| e.path == manifest.project_file && e.kind == EntryKind::Project)
+    {
+        return Err(corrupt(
+            MANIFEST_ENTRY,
+            format!("project file {} is not listed", manifest.project_file),
+        ));
+    }
+    Ok(manifest)
+}
+
+/// Unpacks an archive made by `export` into `destination_dir`, which must not
+/// exist yet or be empty, verifying every entry against the manifest and
+/// pointing the project's media references at the extracted copies. Returns
+/// the path of the imported project file.
+pub fn import(
+    zip: &AllowedPath<roots::Read>,
+    destination: &AllowedPath<roots::Write>,
+    cancel: &AtomicBool,
+    progress: ProgressFn<'_>,
+) -> Result<PathBuf, AppError> {
+    let (zip_path, destination_dir) = (zip.path(), destination.path());
+    if destination_dir.exists() && fs::read_dir(destination_dir)?.next().is_some() {
+        return Err(AppError::InvalidProject {
+            path: destination_dir.display().to_string(),
+            message: "destination directory is not empty".to_string(),
+        });
+    }
+    let file = fs::File::open(zip_path)?;
+    let mut archive =
+        ZipArchive::new(file).map_err(|e| corrupt(&zip_path.display().to_string(), e))?;
+    for name in archive.file_names() {
+        safe_relative(name)?;
+    }
+    let manifest = read_manifest(&mut archive)?;
+
+    // Extract beside the destination and rename into place, so a failed
+    // import never leaves a half-populated project behind
+    let staging = sibling(destination_dir, ".importing");
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    fs::create_dir_all(&staging)?;
+    let result = extract(
+        &mut archive,
+        &manifest,
+        &staging,
+        destination_dir,
+        cancel,
+        progress,
+    );
+    let project_file = match result {
+        Ok(project_file) => project_file,
+        Err(e) => {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e);
+        }
+    };
+    if destination_dir.exists() {
+        fs::remove_dir(destination_dir)?;
+    }
+    fs::rename(&staging, destination_dir)?;
+    log::info!(
+        "Imported project archive {} into {}",
+        zip_path.display(),
+        destination_dir.display()
+    );
+    Ok(project_file)
+}
+
+fn extract(
+    archive: &mut ZipArchive<fs::File>,
+    manifest: &ArchiveManifest,
+    staging: &Path,
+    destination_dir: &Path,
+    cancel: &AtomicBool,
+    progress: ProgressFn<'_>,
+) -> Result<PathBuf, AppError> {
+    let mut tracker = Tracker::new(manifest.entries.iter().map(|e| e.bytes).sum(), progress);
+    let mut replacements = BTreeMap::new();
+
+    for entry in &manifest.entries {
+        let relative = import_target(&entry.path)?;
+        tracker.start(&entry.path);
+        let target = staging.join(&relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let reader = archive.by_name(&entry.path).map_err(|e| match e {
+            zip::result::ZipError::FileNotFound => corrupt(
+                &entry.path,
+                "listed in the manifest but missing from the archive",
+            ),
+            e => corrupt(&entry.path, e),
+        })?;
+        // The zip reader checks each entry's CRC once it reaches the end
+        let (bytes, sha256) = copy_hashed(
+            reader,
+            fs::File::create(&target)?,
+            cancel,
+            |e| corrupt(&entry.path, e),
+            |n| tracker.advance(n, &entry.path),
+        )?;
+        if bytes != entry.bytes || sha256 != entry.sha256 {
+            return Err(corrupt(
+                &entry.path,
+                format!(
+                    "checksum mismatch: expected {} ({} bytes), got {} ({} bytes)",
+                    entry.sha256, entry.bytes, sha256, bytes
+                ),
+            ));
+        }
+
+        let inside_project = entry.path.starts_with(PROJECT_PREFIX);
+        let new_location = destination_dir.join(&relative).display().to_string();
+        for reference in &entry.references {
+            // Relative references into the project directory still resolve
+            if inside_project && Path::new(reference).is_relative() {
+                continue;
+            }
+            replacements.insert(reference.clone(), new_location.clone());
+        }
+    }
+
+    let relative = import_target(&manifest.project_file)?;
+    let staged_project = staging.join(&relative);
+    if !replacements.is_empty() {
+        let contents = fs::read_to_string(&staged_project)?;
+        let mut project: serde_json::Value =
+            serde_json::from_str(&contents).map_err(|e| corrupt(&manifest.project_file, e))?;
+        let rewritten = rewrite_strings(&mut project, &replacements);
+        let json = serde_json::to_vec_pretty(&project).map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })?;
+        fs::write(&staged_project, json)?;
+        log::info!(
+            "Rewrote {} media references in the imported project",
+            rewritten
+        );
+    }
+    (progress)(tracker.total, tracker.total, &manifest.project_file);
+    Ok(destination_dir.join(relative))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveOperation {
+    Export,
+    Import,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveJobState {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// Payload of `project-archive-progress`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveProgress {
+    pub job_id: String,
+    pub operation: ArchiveOperation,
+    pub state: ArchiveJobState,
+    pub processed_bytes: u64,
+    pub total_bytes: u64,
+    pub entry: Option<String>,
+    /// The written zip for exports, the imported project file for imports.
+    pub output: Option<PathBuf>,
+    pub error: Option<AppError>,
+}
+
+/// Runs `work` on a blocking thread as a job of `window`, reporting to it
+/// through `project-archive-progress`; returns the job id at once. The job
+/// is cancelled by its id or by `cancel_token`, registered for `command`.
+#[allow(clippy::too_many_arguments)]
+fn spawn_job(
+    app_handle: tauri::AppHandle,
+    window: &tauri::Window,
+    jobs: &JobManager,
+    cancellation: &CancellationRegistry,
+    command: &'static str,
+    cancel_token: Option<String>,
+    operation: ArchiveOperation,
+    label: String,
+    work: impl FnOnce(&AtomicBool, ProgressFn<'_>) -> Result<PathBuf, AppError> + Send + 'static,
+) -> Result<String, AppError> {
+    use tauri::Manager;
+
+    let job = jobs.register(JobKind::ProjectArchive, label, Some(window.label()));
+    let token = cancellation.register_shared(cancel_token, command, job.cancel_handle())?;
+    let job_id = job.id().to_string();
+    let id = job_id.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let emitter = job.emitter(app_handle.clone(), "project-archive-progress");
+        let last = Mutex::new((0u64, 0u64));
+        let result = work(job.cancel_flag(), &|processed, total, entry| {
+            *last.lock().unwrap() = (processed, total);
+            emitter.emit(&ArchiveProgress {
+                job_id: id.clone(),
+                operation,
+                state: ArchiveJobState::Running,
+                processed_bytes: processed,
+                total_bytes: total,
+                entry: Some(entry.to_string()),
+                output: None,
+                error: None,
+            });
+        });
+
+        let (processed, total) = *last.lock().unwrap();
+        let (state, output, error) = match result {
+            Ok(output) => {
+                if operation == ArchiveOperation::Export {
+                    app_handle.state::<ExportedPaths>().record(&output);
+                }
+                (ArchiveJobState::Completed, Some(output), None)
+            }
+            Err(AppError::ArchiveCancelled) => (ArchiveJobState::Cancelled, None, None),
+            Err(e) => {
+                log::error!("Project archive {:?} failed: {}", operation, e);
+                (ArchiveJobState::Failed, None, Some(e))
+            }
+        };
+        emitter.finish(&ArchiveProgress {
+            job_id: id,
+            operation,
+            state,
+            processed_bytes: processed,
+            total_bytes: total,
+            entry: None,
+            output,
+            error,
+        });
+        // Only now, so the job never looks finished before its final event
+        drop(token);
+        drop(job);
+    });
+    Ok(job_id)
+}
+
+/// Starts exporting `project_path` (a `.sclip` file or a project directory)
+/// to `output_zip`; returns the job id used by progress events and
+/// `cancel_project_archive`.
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub fn export_project_archive(
+    app_handle: tauri::AppHandle,
+    window: tauri::Window,
+    jobs: tauri::State<'_, JobManager>,
+    cancellation: tauri::State<'_, CancellationRegistry>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    project_path: String,
+    output_zip: String,
+    cancel_token: Option<String>,
+) -> Result<String, AppError> {
+    access.check()?;
+    let project = roots.validate_path(Path::new(&project_path))?;
+    let output = roots.validate_path(Path::new(&output_zip))?;
+    spawn_job(
+        app_handle,
+        &window,
+        &jobs,
+        &cancellation,
+        "export_project_archive",
+        cancel_token,
+        ArchiveOperation::Export,
+        project_path.clone(),
+        move |cancel, progress| export(&project, &output, cancel, progress),
+    )
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub fn import_project_archive(
+    app_handle: tauri::AppHandle,
+    window: tauri::Window,
+    jobs: tauri::State<'_, JobManager>,
+    cancellation: tauri::State<'_, CancellationRegistry>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    zip_path: String,
+    destination_dir: String,
+    cancel_token: Option<String>,
+) -> Result<String, AppError> {
+    access.check()?;
+    let zip = roots.validate_path(Path::new(&zip_path))?;
+    let destination = roots.validate_path(Path::new(&destination_dir))?;
+    spawn_job(
+        app_handle,
+        &window,
+        &jobs,
+        &cancellation,
+        "import_project_archive",
+        cancel_token,
+        ArchiveOperation::Import,
+        zip_path.clone(),
+        move |cancel, progress| import(&zip, &destination, cancel, progress),
+    )
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn cancel_project_archive(jobs: tauri::State<'_, JobManager>, job_id: String) -> bool {
+    jobs.cancel(&job_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_names_stay_inside_the_destination() {
+        assert_eq!(
+            safe_relative("audio/./take 1.mp3").unwrap(),
+            Path::new("audio").join("take 1.mp3")
+        );
+        for name in [
+            "",
+            ".",
+            "../escape.txt",
+            "audio/../../escape.txt",
+            "/etc/passwd",
+            "audio\\..\\escape.txt",
+            "C:\\Windows\\win.ini",
+        ] {
+            assert!(
+                matches!(
+                    safe_relative(name),
+                    Err(AppError::UnsafeArchiveEntry { .. })
+                ),
+                "{:?}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn entries_go_under_project_or_external_only() {
+        assert_eq!(
+            import_target("project/script.json").unwrap(),
+            Path::new("script.json")
+        );
+        assert_eq!(
+            import_target("external/0/music.wav").unwrap(),
+            Path::new("external").join("0").join("music.wav")
+        );
+        assert!(matches!(
+            import_target("project/../escape.txt"),
+            Err(AppError::UnsafeArchiveEntry { .. })
+        ));
+        assert!(matches!(
+            import_target("elsewhere/file.txt"),
+            Err(AppError::CorruptArchive { .. })
+        ));
+    }
+}
@@ -0,0 +1,402 @@
+// Sample rates of a project's audio against its output format, since a
+// 24 kHz voice, 44.1 kHz music and a 48 kHz recording in one project play
+// at the wrong pitch once the renderer assumes a single rate. Conforming
+// writes resampled copies into the project's media directory and points
+// the project at them; the originals are never touched
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+
+use super::{rewrite_strings, ProjectLocation, RESOURCES_DIR};
+use crate::audio::presets::OutputOptions;
+use crate::audio::probe::{self, AudioProbe, Codec};
+use crate::audio::resample;
+use crate::cache::write_atomic;
+use crate::error::AppError;
+use crate::jobs::recovery::Resume;
+use crate::jobs::{JobKind, JobManager};
+use crate::paths::roots::{AllowedRoots, Read, Write};
+use crate::read_only::WriteAccess;
+use crate::settings::SettingsStore;
+
+/// Under the project's resources directory.
+const CONFORMED_DIR: &str = "conformed";
+/// Project field naming the output preset the project renders with.
+const OUTPUT_PRESET_FIELD: &str = "output_preset";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssetStatus {
+    /// At the target rate.
+    Conforms,
+    /// At another rate.
+    Mismatched,
+    /// The header doesn't give a rate, or there is no target to compare to.
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AssetAudit {
+    pub path: PathBuf,
+    /// How the project refers to the file.
+    pub references: Vec<String>,
+    #[serde(flatten)]
+    pub probe: AudioProbe,
+    pub status: AssetStatus,
+    /// Whether conforming can resample it: only 16-bit PCM WAVs can, as
+    /// other codecs would need decoding.
+    pub conformable: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProjectAudioAudit {
+    pub project_file: PathBuf,
+    /// The output format's rate, or the most common one among the assets
+    /// when the format keeps the voice's own.
+    pub target_sample_rate: Option<u32>,
+    /// Whether `target_sample_rate` came from the output format.
+    pub target_from_preset: bool,
+    pub assets: Vec<AssetAudit>,
+    pub mismatched: usize,
+    /// Mismatched assets conforming can't fix.
+    pub unconformable: usize,
+    /// The job started by `auto_conform`, reporting through
+    /// `project-audio-conform-progress`.
+    pub conform_job_id: Option<String>,
+}
+
+fn audit_asset(path: PathBuf, references: Vec<String>, target: Option<u32>) -> AssetAudit {
+    let probe = probe::probe_file(&path).unwrap_or_else(|e| {
+        log::warn!("Failed to probe {}: {}", path.display(), e);
+        AudioProbe::unknown()
+    });
+    let status = match (probe.sample_rate, target) {
+        (Some(rate), Some(target)) if rate == target => AssetStatus::Conforms,
+        (Some(_), Some(_)) => AssetStatus::Mismatched,
+        _ => AssetStatus::Unknown,
+    };
+    AssetAudit {
+        path,
+        references,
+        conformable: probe.codec == Codec::Pcm && probe.bits_per_sample == Some(16),
+        probe,
+        status,
+    }
+}
+
+/// Probes every audio file `location` references. With no rate in the
+/// output format, the rate most assets share is the target.
+fn audit(
+    location: &ProjectLocation,
+    target: Option<u32>,
+) -> Result<(Vec<AssetAudit>, Option<u32>), AppError> {
+    let project = location.read()?;
+    let referenced: Vec<(PathBuf, Vec<String>)> = location
+        .media_references(&project)
+        .into_iter()
+        .filter(|(path, _)| probe::is_audio_file(path))
+        .collect();
+    let mut assets: Vec<AssetAudit> = referenced
+        .into_iter()
+        .map(|(path, references)| audit_asset(path, references, target))
+        .collect();
+    if target.is_some() {
+        return Ok((assets, target));
+    }
+
+    let mut counts: BTreeMap<u32, usize> = BTreeMap::new();
+    for rate in assets.iter().filter_map(|a| a.probe.sample_rate) {
+        *counts.entry(rate).or_default() += 1;
+    }
+    // Ties go to the higher rate, which loses nothing when conformed to
+    let majority = counts
+        .into_iter()
+        .max_by_key(|&(rate, count)| (count, rate))
+        .map(|(rate, _)| rate);
+    for asset in &mut assets {
+        asset.status = match (asset.probe.sample_rate, majority) {
+            (Some(rate), Some(target)) if rate == target => AssetStatus::Conforms,
+            (Some(_), Some(_)) => AssetStatus::Mismatched,
+            _ => AssetStatus::Unknown,
+        };
+    }
+    Ok((assets, majority))
+}
+
+/// Where the conformed copy of `source` goes: named after the original and
+/// a hash of its path, so equal names from different folders don't collide
+/// and a resumed job finds what it already wrote.
+fn conformed_path(location: &ProjectLocation, source: &Path, sample_rate: u32) -> PathBuf {
+    let stem = source
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let hash = format!("{:x}", Sha256::digest(source.to_string_lossy().as_bytes()));
+    location
+        .dir
+        .join(RESOURCES_DIR)
+        .join(CONFORMED_DIR)
+        .join(format!("{}-{}-{}.wav", stem, &hash[..8], sample_rate))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConformState {
+    Running,
+    Completed,
+    /// Copies made so far are kept; resuming the job continues after them.
+    Cancelled,
+    Failed,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConformResult {
+    /// Assets resampled by this run.
+    pub conformed: usize,
+    /// Copies an interrupted run had already made.
+    pub reused: usize,
+    /// Mismatched assets left as they are, as they aren't 16-bit PCM WAVs.
+    pub skipped: Vec<PathBuf>,
+    pub references_rewritten: usize,
+}
+
+/// Payload of `project-audio-conform-progress`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConformProgress {
+    pub job_id: String,
+    pub state: ConformState,
+    pub completed: usize,
+    pub total: usize,
+    pub asset: Option<PathBuf>,
+    pub sample_rate: u32,
+    pub result: Option<ConformResult>,
+    pub error: Option<AppError>,
+}
+
+/// Resamples the project's mismatched WAVs to `sample_rate` and, once all
+/// are done, rewrites the project's references to the copies.
+fn conform(
+    location: &ProjectLocation,
+    sample_rate: u32,
+    cancel: &AtomicBool,
+    progress: &dyn Fn(usize, usize, &Path),
+) -> Result<ConformResult, AppError> {
+    let cancelled = || AppError::Cancelled {
+        operation: "conform_project_audio".to_string(),
+    };
+    let (assets, _) = audit(location, Some(sample_rate))?;
+    let (work, skipped): (Vec<AssetAudit>, Vec<AssetAudit>) = assets
+        .into_iter()
+        .filter(|a| a.status == AssetStatus::Mismatched)
+        .partition(|a| a.conformable);
+    let mut result = ConformResult {
+        skipped: skipped.into_iter().map(|a| a.path).collect(),
+        ..ConformResult::default()
+    };
+
+    let mut replacements = BTreeMap::new();
+    for (i, asset) in work.iter().enumerate() {
+        if cancel.load(std::sync::atomic::Ordering::Relaxed) {
+            return Err(cancelled());
+        }
+        progress(i, work.len(), &asset.path);
+        let target = conformed_path(location, &asset.path, sample_rate);
+        // Written atomically, so a copy that exists is complete
+        let done = target.is_file()
+            && probe::probe_file(&target).is_ok_and(|p| p.sample_rate == Some(sample_rate));
+        if done {
+            result.reused += 1;
+        } else {
+            let wav = fs::read(&asset.path)?;
+            let resampled =
+                resample::resample_wav(&wav, sample_rate, cancel)?.ok_or_else(cancelled)?;
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            write_atomic(&target, &resampled)?;
+            result.conformed += 1;
+        }
+        let relative = target.strip_prefix(&location.dir).unwrap_or(&target);
+        for reference in &asset.references {
+            let new = if Path::new(reference).is_relative() {
+                relative
+            } else {
+                &target
+            };
+            replacements.insert(reference.clone(), new.display().to_string());
+        }
+    }
+
+    if !replacements.is_empty() {
+        // Read again, in case the project was saved while this ran
+        let mut project = location.read()?;
+        result.references_rewritten = rewrite_strings(&mut project, &replacements);
+        let json = serde_json::to_vec_pretty(&project).map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })?;
+        write_atomic(&location.file, &json)?;
+    }
+    progress(work.len(), work.len(), &location.file);
+    log::info!(
+        "Conformed {} asset(s) of {} to {} Hz ({} reused, {} skipped)",
+        result.conformed,
+        location.file.display(),
+        sample_rate,
+        result.reused,
+        result.skipped.len()
+    );
+    Ok(result)
+}
+
+/// Starts conforming the project at `project_file` to `sample_rate` as a
+/// job of `window`, resumable after a restart through `resume_job`;
+/// returns the job id.
+pub fn start_conform(
+    app_handle: tauri::AppHandle,
+    window: &tauri::Window,
+    jobs: &JobManager,
+    project_file: PathBuf,
+    sample_rate: u32,
+) -> Result<String, AppError> {
+    let location = ProjectLocation::locate(&project_file)?;
+    let job = jobs.register(
+        JobKind::AudioConform,
+        location.file.display().to_string(),
+        Some(window.label()),
+    );
+    job.set_resume(
+        Resume::AudioConform {
+            project_path: location.file.clone(),
+            sample_rate,
+        },
+        format!(
+            "{:x}",
+            Sha256::digest(format!("{}\n{}", location.file.display(), sample_rate))
+        ),
+    );
+    let job_id = job.id().to_string();
+    let id = job_id.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let emitter = job.emitter(app_handle, "project-audio-conform-progress");
+        let progress = |state, completed, total, asset, result, error| ConformProgress {
+            job_id: id.clone(),
+            state,
+            completed,
+            total,
+            asset,
+            sample_rate,
+            result,
+            error,
+        };
+        let last = std::sync::Mutex::new((0, 0));
+        let result = conform(
+            &location,
+            sample_rate,
+            job.cancel_flag(),
+            &|done, total, asset| {
+                *last.lock().unwrap() = (done, total);
+                job.set_progress(done, total);
+                emitter.emit(&progress(
+                    ConformState::Running,
+                    done,
+                    total,
+                    Some(asset.to_path_buf()),
+                    None,
+                    None,
+                ));
+            },
+        );
+
+        let (done, total) = *last.lock().unwrap();
+        let (state, result, error) = match result {
+            Ok(result) => (ConformState::Completed, Some(result), None),
+            Err(AppError::Cancelled { .. }) => (ConformState::Cancelled, None, None),
+            Err(e) => {
+                log::error!("Conforming {} failed: {}", location.file.display(), e);
+                (ConformState::Failed, None, Some(e))
+            }
+        };
+        emitter.finish(&progress(state, done, total, None, result, error));
+        // Only now, so the job never looks finished before its final event
+        drop(job);
+    });
+    Ok(job_id)
+}
+
+/// Probes the headers of every audio file the project references and flags
+/// those whose sample rate differs from the output format's. The format is
+/// `output`, else the preset the project names in "output_preset". With
+/// `auto_conform`, mismatched WAVs are then resampled into the project's
+/// resources directory by a job whose id is returned.
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub fn audit_project_audio(
+    app_handle: tauri::AppHandle,
+    window: tauri::Window,
+    jobs: tauri::State<'_, JobManager>,
+    settings: tauri::State<'_, SettingsStore>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    project_path: PathBuf,
+    output: Option<OutputOptions>,
+    auto_conform: Option<bool>,
+) -> Result<ProjectAudioAudit, AppError> {
+    let project_path = roots.validate_path::<Read>(&project_path)?;
+    let location = ProjectLocation::locate(project_path.path())?;
+    let output = match output {
+        Some(output) => output,
+        None => OutputOptions {
+            preset: location
+                .read()?
+                .get(OUTPUT_PRESET_FIELD)
+                .and_then(|p| p.as_str())
+                .map(String::from),
+            ..OutputOptions::default()
+        },
+    };
+    let preset_rate = output.resolve(&settings.get())?.sample_rate_hertz;
+    let (assets, target) = audit(&location, preset_rate)?;
+    let mismatched = assets
+        .iter()
+        .filter(|a| a.status == AssetStatus::Mismatched)
+        .count();
+    let unconformable = assets
+        .iter()
+        .filter(|a| a.status == AssetStatus::Mismatched && !a.conformable)
+        .count();
+
+    let conform_job_id = match target {
+        Some(rate) if auto_conform == Some(true) && mismatched > unconformable => {
+            access.check()?;
+            roots.validate_path::<Write>(&location.file)?;
+            roots.validate_path::<Write>(&location.dir.join(RESOURCES_DIR))?;
+            Some(start_conform(
+                app_handle,
+                &window,
+                &jobs,
+                location.file.clone(),
+                rate,
+            )?)
+        }
+        _ => None,
+    };
+    Ok(ProjectAudioAudit {
+        project_file: location.file,
+        target_sample_rate: target,
+        target_from_preset: preset_rate.is_some(),
+        assets,
+        mismatched,
+        unconformable,
+        conform_job_id,
+    })
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn cancel_audio_conform(jobs: tauri::State<'_, JobManager>, job_id: String) -> bool {
+    jobs.cancel(&job_id)
+}
@@ -0,0 +1,663 @@
+// Downloads of remote assets projects reference by URL, such as music beds
+// handed over by the backend: resumable, size-capped and limited to https
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+use crate::error::AppError;
+use crate::jobs::{JobGuard, JobKind, JobManager};
+use crate::paths::roots::{AllowedRoots, Write};
+use crate::read_only::WriteAccess;
+use crate::settings::SettingsStore;
+
+/// Bytes between progress events.
+const PROGRESS_STEP: u64 = 512 * 1024;
+const MAX_REDIRECTS: usize = 10;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DownloadSettings {
+    /// Largest asset that may be downloaded.
+    pub max_bytes: u64,
+    /// Hosts assets may come from, each including its subdomains; empty
+    /// allows any https host.
+    pub allowed_domains: Vec<String>,
+}
+
+impl Default for DownloadSettings {
+    fn default() -> Self {
+        Self {
+            max_bytes: 2 * 1024 * 1024 * 1024,
+            allowed_domains: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadState {
+    Running,
+    Completed,
+    /// The `.partial` file is kept, so downloading again resumes.
+    Cancelled,
+    Failed,
+}
+
+/// Payload of `asset-download-progress`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadProgress {
+    pub job_id: String,
+    pub state: DownloadState,
+    pub url: String,
+    pub destination: PathBuf,
+    pub downloaded_bytes: u64,
+    pub total_bytes: Option<u64>,
+    /// Bytes an earlier attempt had left that this one continued from.
+    pub resumed_from: u64,
+    pub error: Option<AppError>,
+}
+
+fn not_allowed(url: &reqwest::Url, reason: &str) -> AppError {
+    AppError::DownloadNotAllowed {
+        url: url.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// Checks `url`, and every redirect, against the https-only rule and the
+/// domain allowlist.
+fn check_url(url: &reqwest::Url, allowed_domains: &[String]) -> Result<(), AppError> {
+    if url.scheme() != "https" {
+        return Err(not_allowed(url, "only https URLs can be downloaded"));
+    }
+    let Some(host) = url.host_str() else {
+        return Err(not_allowed(url, "URL has no host"));
+    };
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let allowed = allowed_domains.is_empty()
+        || allowed_domains.iter().any(|domain| {
+            let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+            host == domain || host.ends_with(&format!(".{}", domain))
+        });
+    if !allowed {
+        return Err(not_allowed(url, "host is not in the download allowlist"));
+    }
+    Ok(())
+}
+
+/// The client for one download: redirects are followed only while they stay
+/// within what `check_url` allows.
+fn client(url: &reqwest::Url, allowed_domains: &[String]) -> Result<reqwest::Client, AppError> {
+    let allowed = allowed_domains.to_vec();
+    let policy = reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error(format!("more than {} redirects", MAX_REDIRECTS))
+        } else if let Err(e) = check_url(attempt.url(), &allowed) {
+            attempt.error(e.to_string())
+        } else {
+            attempt.follow()
+        }
+    });
+    reqwest::Client::builder()
+        .redirect(policy)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .build()
+        .map_err(|e| AppError::Download {
+            url: url.to_string(),
+            message: e.to_string(),
+        })
+}
+
+/// `destination` with ".partial" added to its file name.
+fn partial_path(destination: &Path) -> PathBuf {
+    let mut name = destination.file_name().unwrap_or_default().to_owned();
+    name.push(".partial");
+    destination.with_file_name(name)
+}
+
+/// The start offset of a 206 response, from "bytes <start>-<end>/<total>".
+fn range_start(response: &reqwest::Response) -> Option<u64> {
+    let range = response
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?;
+    range
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// The full length from a 416 response's "bytes */<total>".
+fn unsatisfied_total(response: &reqwest::Response) -> Option<u64> {
+    let range = response
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?;
+    range.strip_prefix("bytes */")?.trim().parse().ok()
+}
+
+struct Download {
+    url: reqwest::Url,
+    destination: PathBuf,
+    expected_sha256: Option<String>,
+    settings: DownloadSettings,
+}
+
+impl Download {
+    fn error(&self, message: impl std::fmt::Display) -> AppError {
+        AppError::Download {
+            url: self.url.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    /// Fetches into the `.partial` file, continuing an earlier attempt when
+    /// the server honours the range request and starting over when it
+    /// doesn't. Returns (downloaded, total) once the body has ended;
+    /// `AppError::Cancelled` leaves the partial file for the next attempt.
+    async fn fetch(
+        &self,
+        job: &JobGuard,
+        partial: &Path,
+        on_progress: impl Fn(u64, Option<u64>, u64),
+    ) -> Result<(u64, Option<u64>), AppError> {
+        let http = client(&self.url, &self.settings.allowed_domains)?;
+        let existing = fs::metadata(partial).map(|m| m.len()).unwrap_or(0);
+        let mut request = http.get(self.url.clone());
+        if existing > 0 {
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", existing));
+        }
+        let mut response = request.send().await.map_err(|e| self.error(e))?;
+
+        let status = response.status();
+        let resumed = status == reqwest::StatusCode::PARTIAL_CONTENT
+            && existing > 0
+            && range_start(&response) == Some(existing);
+        let (mut file, mut downloaded) = if resumed {
+            log::info!("Resuming download of {} at {} bytes", self.url, existing);
+            let file = tokio::fs::OpenOptions::new()
+                .append(true)
+                .open(partial)
+                .await?;
+            (file, existing)
+        } else if status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+            if unsatisfied_total(&response) == Some(existing) {
+                // An earlier attempt got everything but the rename
+                on_progress(existing, Some(existing), existing);
+                return Ok((existing, Some(existing)));
+            }
+            // The remote file changed size; the next attempt starts over
+            let _ = fs::remove_file(partial);
+            return Err(self.error("the file changed since the download started; retry"));
+        } else if status == reqwest::StatusCode::PARTIAL_CONTENT {
+            // A range other than the one asked for can't be appended
+            let _ = fs::remove_file(partial);
+            return Err(self.error("the server resumed at the wrong offset; retry"));
+        } else if status.is_success() {
+            (tokio::fs::File::create(partial).await?, 0)
+        } else {
+            return Err(self.error(format!("HTTP {}", status)));
+        };
+        let resumed_from = downloaded;
+        let total = response.content_length().map(|len| len + downloaded);
+        if let Some(total) = total.filter(|&t| t > self.settings.max_bytes) {
+            return Err(AppError::DownloadTooLarge {
+                url: self.url.to_string(),
+                bytes: total,
+                limit: self.settings.max_bytes,
+            });
+        }
+
+        on_progress(downloaded, total, resumed_from);
+        let mut reported = downloaded;
+        loop {
+            if job.is_cancelled() {
+                file.flush().await?;
+                return Err(AppError::Cancelled {
+                    operation: "download_asset".to_string(),
+                });
+            }
+            let chunk = match response.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(e) => {
+                    // What arrived is kept for the next attempt to resume from
+                    file.flush().await?;
+                    return Err(self.error(e));
+                }
+            };
+            file.write_all(&chunk).await?;
+            downloaded += chunk.len() as u64;
+            // Servers may not send a length, or send a wrong one
+            if downloaded > self.settings.max_bytes {
+                drop(file);
+                let _ = fs::remove_file(partial);
+                return Err(AppError::DownloadTooLarge {
+                    url: self.url.to_string(),
+                    bytes: downloaded,
+                    limit: self.settings.max_bytes,
+                });
+            }
+            if downloaded - reported >= PROGRESS_STEP {
+                on_progress(downloaded, total, resumed_from);
+                reported = downloaded;
+            }
+        }
+        file.sync_all().await?;
+        Ok((downloaded, total))
+    }
+
+    /// Checks the finished `.partial` file against `expected_sha256`, then
+    /// renames it to the destination.
+    async fn finish(&self, partial: PathBuf) -> Result<(), AppError> {
+        let expected = self.expected_sha256.clone();
+        let destination = self.destination.clone();
+        let url = self.url.to_string();
+        tauri::async_runtime::spawn_blocking(move || {
+            if let Some(expected) = expected {
+                let actual = crate::sidecar::install::sha256_file(&partial)?;
+                if !actual.eq_ignore_ascii_case(expected.trim()) {
+                    // Not resumable: the bytes are wrong, not missing
+                    let _ = fs::remove_file(&partial);
+                    return Err(AppError::DownloadChecksumMismatch {
+                        url,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+            fs::rename(&partial, &destination)?;
+            Ok(())
+        })
+        .await
+        .map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })?
+    }
+}
+
+/// Starts downloading `url` to `destination` as a job of the calling window
+/// and returns its id; progress and the outcome arrive as
+/// `asset-download-progress`. The bytes go to `<destination>.partial` until
+/// complete and verified, so calling again after a failure or cancellation
+/// resumes where it stopped.
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub fn download_asset(
+    app_handle: tauri::AppHandle,
+    window: tauri::Window,
+    jobs: tauri::State<'_, JobManager>,
+    settings: tauri::State<'_, SettingsStore>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    url: String,
+    destination: PathBuf,
+    expected_sha256: Option<String>,
+) -> Result<String, AppError> {
+    access.check()?;
+    let destination = roots.validate_path::<Write>(&destination)?.into_path_buf();
+    let settings = settings.get().downloads;
+    let parsed = reqwest::Url::parse(&url).map_err(|e| AppError::DownloadNotAllowed {
+        url: url.clone(),
+        reason: e.to_string(),
+    })?;
+    check_url(&parsed, &settings.allowed_domains)?;
+    crate::long_path::check_chosen(&destination)?;
+    if let Some(parent) = destination.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+
+    let download = Arc::new(Download {
+        url: parsed,
+        destination,
+        expected_sha256,
+        settings,
+    });
+    // Signed URLs carry credentials in the query, which job labels are
+    // persisted without
+    let mut label = download.url.clone();
+    label.set_query(None);
+    label.set_fragment(None);
+    let job = jobs.register(
+        JobKind::AssetDownload,
+        label.to_string(),
+        Some(window.label()),
+    );
+    let job_id = job.id().to_string();
+    let id = job_id.clone();
+    tauri::async_runtime::spawn(async move {
+        let emitter = job.emitter(app_handle, "asset-download-progress");
+        let progress =
+            |state, downloaded_bytes, total_bytes, resumed_from, error| DownloadProgress {
+                job_id: id.clone(),
+                state,
+                url: download.url.to_string(),
+                destination: download.destination.clone(),
+                downloaded_bytes,
+                total_bytes,
+                resumed_from,
+                error,
+            };
+        let partial = partial_path(&download.destination);
+        let last = std::sync::Mutex::new((0, None, 0));
+        let result = match download
+            .fetch(&job, &partial, |downloaded, total, resumed_from| {
+                *last.lock().unwrap() = (downloaded, total, resumed_from);
+                emitter.emit(&progress(
+                    DownloadState::Running,
+                    downloaded,
+                    total,
+                    resumed_from,
+                    None,
+                ));
+            })
+            .await
+        {
+            Ok((downloaded, total)) => {
+                {
+                    let mut last = last.lock().unwrap();
+                    last.0 = downloaded;
+                    last.1 = total;
+                }
+                download.finish(partial).await
+            }
+            Err(e) => Err(e),
+        };
+
+        let (downloaded, total, resumed_from) = *last.lock().unwrap();
+        let (state, error) = match result {
+            Ok(()) => (DownloadState::Completed, None),
+            Err(AppError::Cancelled { .. }) => (DownloadState::Cancelled, None),
+            Err(e) => {
+                log::error!("Download of {} failed: {}", download.url, e);
+                (DownloadState::Failed, Some(e))
+            }
+        };
+        emitter.finish(&progress(state, downloaded, total, resumed_from, error));
+        // Only now, so the job never looks finished before its final event
+        drop(job);
+    });
+    Ok(job_id)
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn cancel_asset_download(jobs: tauri::State<'_, JobManager>, job_id: String) -> bool {
+    jobs.cancel(&job_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+    use sha2::{Digest, Sha256};
+    use std::sync::Mutex;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    const BODY_LEN: usize = 200_000;
+
+    fn body() -> Vec<u8> {
+        (0..BODY_LEN).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// A local HTTP server closing each connection after one answer, which
+    /// `respond` writes raw from the request's number and Range header.
+    /// Returns the URL it serves and the Range header of each request.
+    async fn serve(
+        respond: impl Fn(usize, Option<&str>) -> Vec<u8> + Send + Sync + 'static,
+    ) -> (reqwest::Url, Arc<Mutex<Vec<Option<String>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/bed.wav", listener.local_addr().unwrap());
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let seen = ranges.clone();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let mut head = Vec::new();
+                let mut buf = [0; 1024];
+                while !head.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => head.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let head = String::from_utf8_lossy(&head).to_string();
+                let range = head.lines().find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("range")
+                        .then(|| value.trim().to_string())
+                });
+                let number = {
+                    let mut seen = seen.lock().unwrap();
+                    seen.push(range.clone());
+                    seen.len() - 1
+                };
+                let _ = stream.write_all(&respond(number, range.as_deref())).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+        (reqwest::Url::parse(&url).unwrap(), ranges)
+    }
+
+    fn response(status: &str, headers: &[String], body: &[u8]) -> Vec<u8> {
+        let mut out = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+        for header in headers {
+            out.push_str(header);
+            out.push_str("\r\n");
+        }
+        out.push_str("\r\n");
+        let mut out = out.into_bytes();
+        out.extend_from_slice(body);
+        out
+    }
+
+    /// The whole body, or the rest of it from a "bytes=<start>-" range.
+    fn ranged(range: Option<&str>) -> Vec<u8> {
+        let body = body();
+        match range.and_then(|r| r.strip_prefix("bytes=")?.strip_suffix('-')?.parse().ok()) {
+            Some(start) => response(
+                "206 Partial Content",
+                &[
+                    format!("Content-Length: {}", BODY_LEN - start),
+                    format!(
+                        "Content-Range: bytes {}-{}/{}",
+                        start,
+                        BODY_LEN - 1,
+                        BODY_LEN
+                    ),
+                ],
+                &body[start..],
+            ),
+            None => response("200 OK", &[format!("Content-Length: {}", BODY_LEN)], &body),
+        }
+    }
+
+    fn download(scratch: &Scratch, url: reqwest::Url) -> Download {
+        Download {
+            url,
+            destination: scratch.0.join("bed.wav"),
+            expected_sha256: Some(format!("{:x}", Sha256::digest(body()))),
+            settings: DownloadSettings::default(),
+        }
+    }
+
+    /// Fetches and finishes `download` as `download_asset` does, returning
+    /// what was fetched and the `resumed_from` reported.
+    async fn run(download: &Download) -> Result<(u64, Option<u64>, u64), AppError> {
+        let jobs = JobManager::default();
+        let job = jobs.register(JobKind::AssetDownload, "bed.wav", None);
+        let partial = partial_path(&download.destination);
+        let resumed = Mutex::new(0);
+        let (downloaded, total) = download
+            .fetch(&job, &partial, |_, _, from| *resumed.lock().unwrap() = from)
+            .await?;
+        download.finish(partial).await?;
+        let resumed_from = *resumed.lock().unwrap();
+        Ok((downloaded, total, resumed_from))
+    }
+
+    #[tokio::test]
+    async fn resumes_where_an_interrupted_download_stopped() {
+        const CUT: usize = 70_000;
+        let (url, ranges) = serve(|number, range| match number {
+            // The connection drops partway through the body
+            0 => response(
+                "200 OK",
+                &[format!("Content-Length: {}", BODY_LEN)],
+                &body()[..CUT],
+            ),
+            _ => ranged(range),
+        })
+        .await;
+        let scratch = Scratch::new("resume");
+        let download = download(&scratch, url);
+        let partial = partial_path(&download.destination);
+
+        assert!(matches!(
+            run(&download).await,
+            Err(AppError::Download { .. })
+        ));
+        assert_eq!(fs::metadata(&partial).unwrap().len(), CUT as u64);
+        assert!(!download.destination.exists());
+
+        let (downloaded, total, resumed_from) = run(&download).await.unwrap();
+        assert_eq!(
+            (downloaded, total),
+            (BODY_LEN as u64, Some(BODY_LEN as u64))
+        );
+        assert_eq!(resumed_from, CUT as u64);
+        assert_eq!(fs::read(&download.destination).unwrap(), body());
+        assert!(!partial.exists());
+        assert_eq!(
+            *ranges.lock().unwrap(),
+            [None, Some(format!("bytes={}-", CUT))]
+        );
+    }
+
+    #[tokio::test]
+    async fn starts_over_when_the_server_ignores_the_range() {
+        let (url, _) = serve(|_, _| ranged(None)).await;
+        let scratch = Scratch::new("restart");
+        let download = download(&scratch, url);
+        fs::write(partial_path(&download.destination), b"stale bytes").unwrap();
+        let (downloaded, _, resumed_from) = run(&download).await.unwrap();
+        assert_eq!((downloaded, resumed_from), (BODY_LEN as u64, 0));
+        assert_eq!(fs::read(&download.destination).unwrap(), body());
+    }
+
+    #[tokio::test]
+    async fn finishes_a_download_that_only_missed_the_rename() {
+        let (url, _) = serve(|_, _| {
+            response(
+                "416 Range Not Satisfiable",
+                &[
+                    "Content-Length: 0".to_string(),
+                    format!("Content-Range: bytes */{}", BODY_LEN),
+                ],
+                b"",
+            )
+        })
+        .await;
+        let scratch = Scratch::new("complete");
+        let download = download(&scratch, url);
+        fs::write(partial_path(&download.destination), body()).unwrap();
+        run(&download).await.unwrap();
+        assert_eq!(fs::read(&download.destination).unwrap(), body());
+    }
+
+    #[tokio::test]
+    async fn drops_a_download_failing_its_checksum() {
+        let (url, _) = serve(|_, range| ranged(range)).await;
+        let scratch = Scratch::new("checksum");
+        let download = Download {
+            expected_sha256: Some("00".repeat(32)),
+            ..download(&scratch, url)
+        };
+        assert!(matches!(
+            run(&download).await,
+            Err(AppError::DownloadChecksumMismatch { .. })
+        ));
+        assert!(!download.destination.exists());
+        assert!(!partial_path(&download.destination).exists());
+    }
+
+    #[tokio::test]
+    async fn refuses_a_body_over_the_size_limit() {
+        let (url, _) = serve(|_, range| ranged(range)).await;
+        let scratch = Scratch::new("too-large");
+        let download = Download {
+            settings: DownloadSettings {
+                max_bytes: 1000,
+                ..DownloadSettings::default()
+            },
+            ..download(&scratch, url)
+        };
+        match run(&download).await {
+            Err(AppError::DownloadTooLarge { bytes, limit, .. }) => {
+                assert_eq!((bytes, limit), (BODY_LEN as u64, 1000));
+            }
+            other => panic!("expected DownloadTooLarge, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_to_follow_a_redirect_off_https() {
+        let (url, ranges) = serve(|number, range| match number {
+            0 => response(
+                "302 Found",
+                &[
+                    "Location: /moved.wav".to_string(),
+                    "Content-Length: 0".to_string(),
+                ],
+                b"",
+            ),
+            _ => ranged(range),
+        })
+        .await;
+        let scratch = Scratch::new("redirect");
+        let download = download(&scratch, url);
+        match run(&download).await {
+            Err(AppError::Download { message, .. }) => {
+                assert!(message.contains("redirect"), "{}", message)
+            }
+            other => panic!("expected a refused redirect, got {:?}", other),
+        }
+        assert_eq!(ranges.lock().unwrap().len(), 1);
+        assert!(!download.destination.exists());
+    }
+
+    #[test]
+    fn allows_only_https_hosts_on_the_allowlist() {
+        let check = |url: &str, allowed: &[&str]| {
+            let allowed: Vec<String> = allowed.iter().map(|d| d.to_string()).collect();
+            check_url(&reqwest::Url::parse(url).unwrap(), &allowed).is_ok()
+        };
+        assert!(check("https://anything.test/bed.wav", &[]));
+        assert!(!check("http://example.com/bed.wav", &[]));
+        assert!(!check("file:///etc/passwd", &[]));
+        assert!(check("https://example.com/bed.wav", &["example.com"]));
+        assert!(check(
+            "https://cdn.Example.com./bed.wav",
+            &[" example.com. "]
+        ));
+        assert!(!check("https://badexample.com/bed.wav", &["example.com"]));
+        assert!(!check(
+            "https://example.com.evil.test/bed.wav",
+            &["example.com"]
+        ));
+        assert!(check("https://b.test/x", &["a.test", "b.test"]));
+    }
+}
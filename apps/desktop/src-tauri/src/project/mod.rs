@@ -0,0 +1,224 @@
+// Locating a project on disk and the media files it references
+pub mod archive;
+pub mod audit;
+pub mod download;
+pub mod pronunciation;
+pub mod review;
+pub mod timed_script;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::AppError;
+use crate::paths::roots::{AllowedRoots, Read};
+use crate::read_only::WriteAccess;
+use crate::trash::{Trash, TrashEntry};
+
+/// Extension of a standalone project file.
+pub const PROJECT_EXTENSION: &str = "sclip";
+/// Metadata file the backend keeps in each project directory.
+pub const PROJECT_INFO_FILE: &str = "project.json";
+/// Per-project pronunciation overrides, stored next to the project file.
+pub const PRONUNCIATION_FILE: &str = "pronunciations.json";
+/// Review status of the project's segments, stored next to the project file.
+pub const REVIEW_FILE: &str = "review.json";
+/// Directory the backend keeps a project's imported and generated media in.
+pub const RESOURCES_DIR: &str = "resources";
+
+/// Strings longer than this are script text, not paths.
+const MAX_REFERENCE_LEN: usize = 4096;
+
+#[derive(Debug, Clone)]
+pub struct ProjectLocation {
+    /// The `.sclip` file, or `project.json` for a backend project directory.
+    pub file: PathBuf,
+    /// Directory relative references are resolved against.
+    pub dir: PathBuf,
+}
+
+impl ProjectLocation {
+    /// Accepts either the project file itself or a directory holding one; a
+    /// `.sclip` file wins over `project.json`.
+    pub fn locate(path: &Path) -> Result<Self, AppError> {
+        let invalid = |message: &str| AppError::InvalidProject {
+            path: path.display().to_string(),
+            message: message.to_string(),
+        };
+        if path.is_file() {
+            let dir = path
+                .parent()
+                .ok_or_else(|| invalid("has no parent directory"))?;
+            return Ok(Self {
+                file: path.to_path_buf(),
+                dir: dir.to_path_buf(),
+            });
+        }
+        if !path.is_dir() {
+            return Err(invalid("does not exist"));
+        }
+
+        let mut sclip: Vec<PathBuf> = fs::read_dir(path)?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == PROJECT_EXTENSION))
+            .collect();
+        sclip.sort();
+        let file = match sclip.into_iter().next() {
+            Some(file) => file,
+            None if path.join(PROJECT_INFO_FILE).is_file() => path.join(PROJECT_INFO_FILE),
+            None => {
+                return Err(invalid(&format!(
+                    "no .{} or {} file found",
+                    PROJECT_EXTENSION, PROJECT_INFO_FILE
+                )))
+            }
+        };
+        Ok(Self {
+            file,
+            dir: path.to_path_buf(),
+        })
+    }
+
+    pub fn read(&self) -> Result<serde_json::Value, AppError> {
+        let contents = fs::read_to_string(&self.file)?;
+        serde_json::from_str(&contents).map_err(|e| AppError::InvalidProject {
+            path: self.file.display().to_string(),
+            message: e.to_string(),
+        })
+    }
+
+    pub fn pronunciation_file(&self) -> Option<PathBuf> {
+        Some(self.dir.join(PRONUNCIATION_FILE)).filter(|p| p.is_file())
+    }
+
+    pub fn review_file(&self) -> PathBuf {
+        self.dir.join(REVIEW_FILE)
+    }
+
+    /// Every string in the project that names an existing file, grouped by
+    /// the canonical file so a clip used twice is only counted once.
+    pub fn media_references(&self, project: &serde_json::Value) -> BTreeMap<PathBuf, Vec<String>> {
+        let own = fs::canonicalize(&self.file).ok();
+        let mut references: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+        visit_strings(project, &mut |value| {
+            if value.is_empty()
+                || value.len() > MAX_REFERENCE_LEN
+                || value.contains('\n')
+                || value.contains("://")
+            {
+                return;
+            }
+            let path = Path::new(value);
+            let path = if path.is_absolute() {
+                path.to_path_buf()
+            } else {
+                self.dir.join(path)
+            };
+            let Ok(canonical) = fs::canonicalize(&path) else {
+                return;
+            };
+            if !canonical.is_file() || Some(&canonical) == own.as_ref() {
+                return;
+            }
+            let refs = references.entry(canonical).or_default();
+            if !refs.iter().any(|r| r == value) {
+                refs.push(value.to_string());
+            }
+        });
+        references
+    }
+}
+
+/// Calls `f` on every string value in `value`, depth first.
+pub fn visit_strings(value: &serde_json::Value, f: &mut impl FnMut(&str)) {
+    match value {
+        serde_json::Value::String(s) => f(s),
+        serde_json::Value::Array(items) => items.iter().for_each(|v| visit_strings(v, f)),
+        serde_json::Value::Object(map) => map.values().for_each(|v| visit_strings(v, f)),
+        _ => {}
+    }
+}
+
+/// Replaces string values found in `replacements`; returns how many were
+/// changed.
+pub fn rewrite_strings(
+    value: &mut serde_json::Value,
+    replacements: &BTreeMap<String, String>,
+) -> usize {
+    match value {
+        serde_json::Value::String(s) => match replacements.get(s.as_str()) {
+            Some(new) => {
+                *s = new.clone();
+                1
+            }
+            None => 0,
+        },
+        serde_json::Value::Array(items) => items
+            .iter_mut()
+            .map(|v| rewrite_strings(v, replacements))
+            .sum(),
+        serde_json::Value::Object(map) => map
+            .values_mut()
+            .map(|v| rewrite_strings(v, replacements))
+            .sum(),
+        _ => 0,
+    }
+}
+
+/// Moves media files of a project to the trash. Only files inside the
+/// project directory or referenced by the project can be deleted this way.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn delete_project_media(
+    trash: tauri::State<'_, Trash>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    project_path: PathBuf,
+    paths: Vec<PathBuf>,
+) -> Result<Option<TrashEntry>, AppError> {
+    access.check()?;
+    let project_path = roots.validate_path::<Read>(&project_path)?;
+    let location = ProjectLocation::locate(project_path.path())?;
+    let references = location.media_references(&location.read()?);
+    let project_dir = fs::canonicalize(&location.dir)?;
+    let project_file = fs::canonicalize(&location.file)?;
+
+    let mut media = Vec::with_capacity(paths.len());
+    for path in paths {
+        let path = if path.is_absolute() {
+            path
+        } else {
+            location.dir.join(path)
+        };
+        let media_path = roots.validate_path(&path)?;
+        let canonical = media_path.path();
+        let allowed = canonical != project_file
+            && (canonical.starts_with(&project_dir) || references.contains_key(canonical));
+        if !allowed {
+            return Err(AppError::InvalidProject {
+                path: location.file.display().to_string(),
+                message: format!("{} is not media of this project", path.display()),
+            });
+        }
+        media.push(media_path);
+    }
+    trash.discard("delete_project_media", &media)
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![
+        archive::export_project_archive,
+        archive::import_project_archive,
+        archive::cancel_project_archive,
+        audit::audit_project_audio,
+        audit::cancel_audio_conform,
+        download::download_asset,
+        download::cancel_asset_download,
+        delete_project_media,
+        review::set_segment_status,
+        review::get_review_summary,
+        timed_script::export_timed_script,
+    ]
+}
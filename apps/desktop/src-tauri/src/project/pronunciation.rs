@@ -0,0 +1,70 @@
+// The per-project pronunciation dictionary kept in pronunciations.json
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::error::AppError;
+use crate::ssml::escape;
+
+/// How one word should be read: by IPA, or by a spelled-out alias such as
+/// "ess clip" for "SCLIP". IPA wins when both are set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pronunciation {
+    pub word: String,
+    #[serde(default)]
+    pub ipa: Option<String>,
+    #[serde(default)]
+    pub alias: Option<String>,
+    /// Matches only the exact spelling, e.g. for acronyms that are also words.
+    #[serde(default)]
+    pub case_sensitive: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Dictionary {
+    pub entries: Vec<Pronunciation>,
+}
+
+impl Dictionary {
+    pub fn load(path: &Path) -> Result<Self, AppError> {
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents).map_err(|e| AppError::InvalidProject {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        })
+    }
+
+    /// The entry for `word`; an exact-case match wins over a case-insensitive one.
+    pub fn lookup(&self, word: &str) -> Option<&Pronunciation> {
+        self.entries.iter().find(|e| e.word == word).or_else(|| {
+            self.entries
+                .iter()
+                .find(|e| !e.case_sensitive && e.word.to_lowercase() == word.to_lowercase())
+        })
+    }
+}
+
+impl Pronunciation {
+    /// SSML speaking `word` this way, or `None` if the entry sets neither
+    /// IPA nor an alias.
+    pub fn to_ssml(&self, word: &str) -> Option<String> {
+        if let Some(ipa) = self.ipa.as_deref().filter(|s| !s.trim().is_empty()) {
+            return Some(format!(
+                "<phoneme alphabet=\"ipa\" ph=\"{}\">{}</phoneme>",
+                escape(ipa.trim()),
+                escape(word)
+            ));
+        }
+        self.alias
+            .as_deref()
+            .filter(|s| !s.trim().is_empty())
+            .map(|alias| {
+                format!(
+                    "<sub alias=\"{}\">{}</sub>",
+                    escape(alias.trim()),
+                    escape(word)
+                )
+            })
+    }
+}
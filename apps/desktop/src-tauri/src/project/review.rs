@@ -0,0 +1,258 @@
+// Per-segment review status kept in review.json next to the project file,
+// with every change and note timestamped. Segments without an entry, which
+// is every segment of a project from before reviews, are drafts
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use tauri::Manager;
+
+use super::ProjectLocation;
+use crate::cache::write_atomic;
+use crate::error::AppError;
+use crate::paths::roots::{AllowedRoots, Read, Write};
+use crate::read_only::WriteAccess;
+use crate::settings::SettingsStore;
+use crate::window_scope::WindowScopes;
+
+/// Bumped when the file layout changes.
+pub const REVIEW_VERSION: u32 = 1;
+/// Longest reviewer note kept.
+const MAX_NOTE_CHARS: usize = 4000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewStatus {
+    #[default]
+    Draft,
+    NeedsReview,
+    Approved,
+}
+
+/// One status change, with what the reviewer wrote about it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewNote {
+    pub status: ReviewStatus,
+    #[serde(default)]
+    pub note: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SegmentReview {
+    pub review_status: ReviewStatus,
+    /// Oldest first.
+    pub notes: Vec<ReviewNote>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Review {
+    pub version: u32,
+    /// By segment id.
+    pub segments: BTreeMap<String, SegmentReview>,
+}
+
+impl Default for Review {
+    fn default() -> Self {
+        Self {
+            version: REVIEW_VERSION,
+            segments: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SegmentReviewEntry {
+    pub id: String,
+    #[serde(flatten)]
+    pub review: SegmentReview,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReviewSummary {
+    pub total: usize,
+    pub draft: usize,
+    pub needs_review: usize,
+    pub approved: usize,
+    /// Drafts and segments needing review, in project order.
+    pub unapproved: Vec<String>,
+    pub segments: Vec<SegmentReviewEntry>,
+}
+
+impl Review {
+    /// The review of the project at `location`; a project never reviewed
+    /// has every segment in draft.
+    pub fn load(location: &ProjectLocation) -> Result<Self, AppError> {
+        let path = location.review_file();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_str(&contents).map_err(|e| AppError::InvalidProject {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        })
+    }
+
+    fn save(&self, location: &ProjectLocation) -> Result<(), AppError> {
+        let json = serde_json::to_vec_pretty(self).map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })?;
+        write_atomic(&location.review_file(), &json)?;
+        Ok(())
+    }
+
+    pub fn status(&self, segment_id: &str) -> ReviewStatus {
+        self.segments
+            .get(segment_id)
+            .map(|s| s.review_status)
+            .unwrap_or_default()
+    }
+
+    /// Those of `segment_ids` not approved, in the order given.
+    pub fn unapproved<'a>(&self, segment_ids: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+        segment_ids
+            .into_iter()
+            .filter(|id| self.status(id) != ReviewStatus::Approved)
+            .map(String::from)
+            .collect()
+    }
+}
+
+/// Ids of the segments in the project's `segments` list, in order.
+pub fn segment_ids(project: &serde_json::Value) -> Vec<String> {
+    project
+        .get("segments")
+        .and_then(|s| s.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|s| s.get("id").and_then(|id| id.as_str()))
+        .map(String::from)
+        .collect()
+}
+
+/// Fails with `UnapprovedSegments` when any of `segment_ids` isn't
+/// approved in the review of the project at `location`.
+pub fn check_approved<'a>(
+    location: &ProjectLocation,
+    segment_ids: impl IntoIterator<Item = &'a str>,
+) -> Result<(), AppError> {
+    let unapproved = Review::load(location)?.unapproved(segment_ids);
+    if unapproved.is_empty() {
+        return Ok(());
+    }
+    Err(AppError::UnapprovedSegments {
+        segment_ids: unapproved,
+    })
+}
+
+/// `check_approved` for an export started from `window`, against its
+/// active project, when the `enforce_review` setting is on. An export from
+/// a window without a project has no review to enforce.
+pub fn check_export<'a>(
+    app_handle: &tauri::AppHandle,
+    window: &str,
+    segment_ids: impl IntoIterator<Item = &'a str>,
+) -> Result<(), AppError> {
+    let enforce = app_handle
+        .try_state::<SettingsStore>()
+        .is_some_and(|settings| settings.get().enforce_review);
+    let project = app_handle
+        .try_state::<WindowScopes>()
+        .and_then(|scopes| scopes.get(window));
+    match project {
+        Some(project) if enforce => check_approved(
+            &ProjectLocation {
+                file: project.file,
+                dir: project.dir,
+            },
+            segment_ids,
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Moves segment `segment_id` of `project_path` to `status`, recording
+/// `note` with the time.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn set_segment_status(
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    project_path: PathBuf,
+    segment_id: String,
+    status: ReviewStatus,
+    note: Option<String>,
+) -> Result<SegmentReview, AppError> {
+    access.check()?;
+    let project_path = roots.validate_path::<Write>(&project_path)?.into_path_buf();
+    let location = ProjectLocation::locate(&project_path)?;
+    if segment_id.trim().is_empty() {
+        return Err(AppError::InvalidProject {
+            path: location.file.display().to_string(),
+            message: "segment id is empty".to_string(),
+        });
+    }
+    let note = note
+        .map(|n| n.trim().chars().take(MAX_NOTE_CHARS).collect::<String>())
+        .filter(|n| !n.is_empty());
+    let mut review = Review::load(&location)?;
+    review.version = REVIEW_VERSION;
+    let segment = review.segments.entry(segment_id).or_default();
+    segment.review_status = status;
+    segment.notes.push(ReviewNote {
+        status,
+        note,
+        at: Utc::now(),
+    });
+    let updated = segment.clone();
+    review.save(&location)?;
+    Ok(updated)
+}
+
+/// Counts and lists the review status of every segment of `project_path`:
+/// those in its `segments` list, then any reviewed segment no longer there.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_review_summary(
+    roots: tauri::State<'_, AllowedRoots>,
+    project_path: PathBuf,
+) -> Result<ReviewSummary, AppError> {
+    let project_path = roots.validate_path::<Read>(&project_path)?.into_path_buf();
+    let location = ProjectLocation::locate(&project_path)?;
+    let review = Review::load(&location)?;
+    let mut ids = segment_ids(&location.read()?);
+    let listed: HashSet<String> = ids.iter().cloned().collect();
+    ids.extend(
+        review
+            .segments
+            .keys()
+            .filter(|id| !listed.contains(*id))
+            .cloned(),
+    );
+
+    let mut summary = ReviewSummary {
+        total: ids.len(),
+        draft: 0,
+        needs_review: 0,
+        approved: 0,
+        unapproved: review.unapproved(ids.iter().map(String::as_str)),
+        segments: Vec::with_capacity(ids.len()),
+    };
+    for id in ids {
+        let entry = review.segments.get(&id).cloned().unwrap_or_default();
+        match entry.review_status {
+            ReviewStatus::Draft => summary.draft += 1,
+            ReviewStatus::NeedsReview => summary.needs_review += 1,
+            ReviewStatus::Approved => summary.approved += 1,
+        }
+        summary
+            .segments
+            .push(SegmentReviewEntry { id, review: entry });
+    }
+    Ok(summary)
+}
@@ -0,0 +1,514 @@
+// The script with timestamps as one document, for motion graphics driven
+// by when each line is spoken. Offsets lay the segments' audio end to end,
+// as `concat_audio` joins them; a segment without audio yet has no timing,
+// and neither has anything after it
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use super::review::{Review, ReviewStatus};
+use super::ProjectLocation;
+use crate::audio::probe;
+use crate::batch::{BatchManifest, SegmentStatus, MANIFEST_FILE};
+use crate::error::AppError;
+use crate::paths::roots::{AllowedRoots, Read, Write};
+use crate::paths::ExportedPaths;
+use crate::read_only::WriteAccess;
+
+/// Bumped when a field changes meaning or is removed; added fields don't.
+pub const TIMED_SCRIPT_VERSION: u32 = 1;
+/// Column order of the CSV export.
+const CSV_COLUMNS: &[&str] = &[
+    "index",
+    "id",
+    "voice_name",
+    "language_code",
+    "start_ms",
+    "end_ms",
+    "duration_ms",
+    "review_status",
+    "audio_path",
+    "text",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimedScriptFormat {
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimedScriptSource {
+    Project,
+    BatchManifest,
+    /// Recorded narration aligned with its script; see `audio::align`.
+    Alignment,
+}
+
+/// One segment of the JSON export, and one row of the CSV. Missing values
+/// are `null` in JSON and empty cells in CSV.
+#[derive(Debug, Clone, Serialize)]
+pub struct TimedSegment {
+    pub index: usize,
+    pub id: String,
+    pub voice_name: Option<String>,
+    pub language_code: Option<String>,
+    /// From the start of the first segment's audio.
+    pub start_ms: Option<f64>,
+    pub end_ms: Option<f64>,
+    pub duration_ms: Option<f64>,
+    /// `None` for a batch manifest, which has no review.
+    pub review_status: Option<ReviewStatus>,
+    pub audio_path: Option<PathBuf>,
+    pub text: String,
+}
+
+/// The JSON export, schema version `TIMED_SCRIPT_VERSION`.
+#[derive(Debug, Clone, Serialize)]
+pub struct TimedScript {
+    pub version: u32,
+    pub source: TimedScriptSource,
+    pub source_path: PathBuf,
+    pub generated_at: DateTime<Utc>,
+    pub total_duration_ms: Option<f64>,
+    /// Segments whose start and end are `null`.
+    pub missing_timing: usize,
+    pub warnings: Vec<String>,
+    pub segments: Vec<TimedSegment>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimedScriptExport {
+    pub output_path: PathBuf,
+    pub segments: usize,
+    pub missing_timing: usize,
+    pub warnings: Vec<String>,
+}
+
+/// A segment before timing: what it says and where its audio is. `None`
+/// audio with `silent` is a segment that has nothing to speak.
+struct Untimed {
+    id: String,
+    voice_name: Option<String>,
+    language_code: Option<String>,
+    review_status: Option<ReviewStatus>,
+    audio_path: Option<PathBuf>,
+    silent: bool,
+    text: String,
+}
+
+fn duration_ms(path: &Path) -> Option<f64> {
+    probe::probe_file(path).ok()?.duration_ms
+}
+
+/// Lays the segments out end to end. The first without a duration breaks
+/// the timeline: it and every later segment have no start or end, though
+/// later ones still report their own duration.
+fn time(segments: Vec<Untimed>, warnings: &mut Vec<String>) -> (Vec<TimedSegment>, Option<f64>) {
+    let mut at = Some(0.0);
+    let mut timed = Vec::with_capacity(segments.len());
+    for (index, segment) in segments.into_iter().enumerate() {
+        let duration = match &segment.audio_path {
+            Some(path) => {
+                let duration = duration_ms(path);
+                if duration.is_none() {
+                    warnings.push(format!(
+                        "segment {}: can't read the duration of {}",
+                        segment.id,
+                        path.display()
+                    ));
+                }
+                duration
+            }
+            None if segment.silent => Some(0.0),
+            None => {
+                warnings.push(format!("segment {}: not synthesized yet", segment.id));
+                None
+            }
+        };
+        let start = at.filter(|_| duration.is_some());
+        let end = start
+            .zip(duration)
+            .map(|(start, duration)| start + duration);
+        at = end;
+        timed.push(TimedSegment {
+            index,
+            id: segment.id,
+            voice_name: segment.voice_name,
+            language_code: segment.language_code,
+            start_ms: start,
+            end_ms: end,
+            duration_ms: duration,
+            review_status: segment.review_status,
+            audio_path: segment.audio_path,
+            text: segment.text,
+        });
+    }
+    (timed, at)
+}
+
+fn string_field(value: &serde_json::Value, keys: &[&str]) -> Option<String> {
+    keys.iter()
+        .find_map(|key| value.get(*key).and_then(|v| v.as_str()))
+        .map(String::from)
+}
+
+/// The project's `segments`, with audio from the first of their audio
+/// fields that names an existing file.
+fn project_segments(location: &ProjectLocation) -> Result<Vec<Untimed>, AppError> {
+    let project = location.read()?;
+    let review = Review::load(location)?;
+    let segments = project
+        .get("segments")
+        .and_then(|s| s.as_array())
+        .cloned()
+        .unwrap_or_default();
+    Ok(segments
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| {
+            let id = string_field(segment, &["id"]).unwrap_or_else(|| i.to_string());
+            let audio_path = ["audio_path", "audio_file", "audio", "path"]
+                .iter()
+                .filter_map(|key| segment.get(*key).and_then(|v| v.as_str()))
+                .map(|p| location.dir.join(p))
+                .find(|p| p.is_file());
+            Untimed {
+                review_status: Some(review.status(&id)),
+                id,
+                voice_name: string_field(segment, &["voice_name", "voice"]),
+                language_code: string_field(segment, &["language_code", "language"]),
+                audio_path,
+                silent: false,
+                text: string_field(segment, &["text"]).unwrap_or_default(),
+            }
+        })
+        .collect())
+}
+
+fn manifest_segments(manifest: BatchManifest) -> Vec<Untimed> {
+    manifest
+        .segments
+        .into_iter()
+        .map(|segment| Untimed {
+            id: segment.id,
+            voice_name: Some(segment.voice_name),
+            language_code: Some(segment.language_code),
+            review_status: None,
+            audio_path: Some(segment.path).filter(|_| segment.status == SegmentStatus::Completed),
+            silent: segment.status == SegmentStatus::Skipped,
+            text: segment.text,
+        })
+        .collect()
+}
+
+/// Reads `path` as a batch manifest when it is one, or a directory with one
+/// and no project, and as a project otherwise.
+pub fn build(path: &Path) -> Result<TimedScript, AppError> {
+    let manifest_path = if path.is_dir() {
+        Some(path.join(MANIFEST_FILE))
+            .filter(|m| m.is_file() && ProjectLocation::locate(path).is_err())
+    } else {
+        Some(path.to_path_buf()).filter(|p| p.file_name().is_some_and(|n| n == MANIFEST_FILE))
+    };
+    let (source, source_path, segments) = match manifest_path {
+        Some(manifest_path) => (
+            TimedScriptSource::BatchManifest,
+            manifest_path.clone(),
+            manifest_segments(BatchManifest::load(&manifest_path)?),
+        ),
+        None => {
+            let location = ProjectLocation::locate(path)?;
+            (
+                TimedScriptSource::Project,
+                location.file.clone(),
+                project_segments(&location)?,
+            )
+        }
+    };
+    let mut warnings = Vec::new();
+    let (segments, total_duration_ms) = time(segments, &mut warnings);
+    Ok(TimedScript {
+        version: TIMED_SCRIPT_VERSION,
+        source,
+        source_path,
+        generated_at: Utc::now(),
+        total_duration_ms,
+        missing_timing: segments.iter().filter(|s| s.start_ms.is_none()).count(),
+        warnings,
+        segments,
+    })
+}
+
+/// A CSV field, quoted when it holds a comma, quote or line break, with
+/// quotes doubled, as RFC 4180 has it.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_number(value: Option<f64>) -> String {
+    value.map(|v| format!("{:.3}", v)).unwrap_or_default()
+}
+
+/// The segments as CSV with a header row and CRLF line ends.
+pub fn to_csv(script: &TimedScript) -> String {
+    let mut csv = CSV_COLUMNS.join(",");
+    csv.push_str("\r\n");
+    for segment in &script.segments {
+        let review_status = segment.review_status.map(|s| {
+            serde_json::to_value(s)
+                .ok()
+                .and_then(|v| v.as_str().map(String::from))
+                .unwrap_or_default()
+        });
+        let row = [
+            segment.index.to_string(),
+            segment.id.clone(),
+            segment.voice_name.clone().unwrap_or_default(),
+            segment.language_code.clone().unwrap_or_default(),
+            csv_number(segment.start_ms),
+            csv_number(segment.end_ms),
+            csv_number(segment.duration_ms),
+            review_status.unwrap_or_default(),
+            segment
+                .audio_path
+                .as_ref()
+                .map(|p| p.display().to_string())
+                .unwrap_or_default(),
+            segment.text.clone(),
+        ];
+        let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// `script` as the file `export_timed_script` writes.
+pub fn encode(script: &TimedScript, format: TimedScriptFormat) -> Result<Vec<u8>, AppError> {
+    match format {
+        TimedScriptFormat::Json => serde_json::to_vec_pretty(script).map_err(|e| AppError::Io {
+            message: e.to_string(),
+        }),
+        TimedScriptFormat::Csv => Ok(to_csv(script).into_bytes()),
+    }
+}
+
+/// Writes the text, voice, start and end of every segment of a project or
+/// batch manifest to `output_path` as JSON or CSV. Segments not synthesized
+/// yet are included without timing and counted in `missing_timing`.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn export_timed_script(
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    exported: tauri::State<'_, ExportedPaths>,
+    project_or_manifest: PathBuf,
+    format: TimedScriptFormat,
+    output_path: PathBuf,
+) -> Result<TimedScriptExport, AppError> {
+    access.check()?;
+    let source = roots.validate_path::<Read>(&project_or_manifest)?;
+    let output = roots.validate_path::<Write>(&output_path)?;
+    let script = build(source.path())?;
+    output.write_atomic(&encode(&script, format)?)?;
+    exported.record(output.path());
+    if script.missing_timing > 0 {
+        log::info!(
+            "Timed script {} has {} segment(s) without timing",
+            output.path().display(),
+            script.missing_timing
+        );
+    }
+    Ok(TimedScriptExport {
+        output_path: output.path().to_path_buf(),
+        segments: script.segments.len(),
+        missing_timing: script.missing_timing,
+        warnings: script.warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+    use std::fs;
+
+    /// A silent 16 kHz mono WAV of `ms` milliseconds.
+    fn wav(scratch: &Scratch, name: &str, ms: u64) -> PathBuf {
+        let frames = ms * 16;
+        let mut wav = crate::audio::placeholder::wav_header(16_000, 1, frames)
+            .unwrap()
+            .to_vec();
+        wav.resize(wav.len() + frames as usize * 2, 0);
+        let path = scratch.0.join(name);
+        fs::write(&path, wav).unwrap();
+        path
+    }
+
+    fn untimed(id: &str, audio_path: Option<PathBuf>, silent: bool) -> Untimed {
+        Untimed {
+            id: id.to_string(),
+            voice_name: None,
+            language_code: None,
+            review_status: None,
+            audio_path,
+            silent,
+            text: String::new(),
+        }
+    }
+
+    fn timing(segments: &[TimedSegment]) -> Vec<(Option<f64>, Option<f64>, Option<f64>)> {
+        segments
+            .iter()
+            .map(|s| (s.start_ms, s.end_ms, s.duration_ms))
+            .collect()
+    }
+
+    #[test]
+    fn lays_segments_end_to_end() {
+        let scratch = Scratch::new("end-to-end");
+        let mut warnings = Vec::new();
+        let (segments, total) = time(
+            vec![
+                untimed("a", Some(wav(&scratch, "a.wav", 250)), false),
+                untimed("pause", None, true),
+                untimed("b", Some(wav(&scratch, "b.wav", 500)), false),
+            ],
+            &mut warnings,
+        );
+        assert_eq!(
+            timing(&segments),
+            [
+                (Some(0.0), Some(250.0), Some(250.0)),
+                (Some(250.0), Some(250.0), Some(0.0)),
+                (Some(250.0), Some(750.0), Some(500.0)),
+            ]
+        );
+        assert_eq!(total, Some(750.0));
+        assert!(warnings.is_empty());
+        assert_eq!(segments[2].index, 2);
+    }
+
+    #[test]
+    fn a_segment_without_a_duration_ends_the_timeline() {
+        let scratch = Scratch::new("broken");
+        let unreadable = scratch.0.join("unreadable.wav");
+        fs::write(&unreadable, b"not audio").unwrap();
+        let mut warnings = Vec::new();
+        let (segments, total) = time(
+            vec![
+                untimed("a", Some(wav(&scratch, "a.wav", 250)), false),
+                untimed("missing", None, false),
+                untimed("b", Some(wav(&scratch, "b.wav", 100)), false),
+                untimed("unreadable", Some(unreadable), false),
+            ],
+            &mut warnings,
+        );
+        // Later segments still report their own duration
+        assert_eq!(
+            timing(&segments),
+            [
+                (Some(0.0), Some(250.0), Some(250.0)),
+                (None, None, None),
+                (None, None, Some(100.0)),
+                (None, None, None),
+            ]
+        );
+        assert_eq!(total, None);
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0], "segment missing: not synthesized yet");
+        assert!(warnings[1].starts_with("segment unreadable: can't read the duration"));
+    }
+
+    #[test]
+    fn builds_from_a_project() {
+        let scratch = Scratch::new("project");
+        wav(&scratch, "first.wav", 400);
+        let project = serde_json::json!({
+            "segments": [
+                {
+                    "id": "intro",
+                    "voice": "en-US-Neural2-A",
+                    "language_code": "en-US",
+                    "text": "Hello",
+                    "audio_file": "gone.wav",
+                    "audio_path": "first.wav",
+                },
+                { "text": "Not synthesized" },
+            ]
+        });
+        fs::write(scratch.0.join("project.json"), project.to_string()).unwrap();
+
+        let script = build(&scratch.0).unwrap();
+        assert_eq!(script.version, TIMED_SCRIPT_VERSION);
+        assert_eq!(script.source, TimedScriptSource::Project);
+        assert_eq!(script.source_path, scratch.0.join("project.json"));
+        assert_eq!(script.missing_timing, 1);
+        assert_eq!(script.total_duration_ms, None);
+
+        let [intro, second] = &script.segments[..] else {
+            panic!("expected two segments");
+        };
+        assert_eq!(intro.voice_name.as_deref(), Some("en-US-Neural2-A"));
+        assert_eq!(intro.language_code.as_deref(), Some("en-US"));
+        // The first audio field naming a file wins
+        assert_eq!(intro.audio_path, Some(scratch.0.join("first.wav")));
+        assert_eq!(intro.end_ms, Some(400.0));
+        assert_eq!(intro.review_status, Some(ReviewStatus::Draft));
+        // A segment without an id goes by its index
+        assert_eq!(second.id, "1");
+        assert_eq!(second.audio_path, None);
+    }
+
+    #[test]
+    fn csv_quotes_fields_and_leaves_missing_values_empty() {
+        let segment = |index: usize, text: &str, end_ms: Option<f64>| TimedSegment {
+            index,
+            id: format!("s{}", index),
+            voice_name: Some("en-US-Neural2-A".to_string()),
+            language_code: None,
+            start_ms: end_ms.map(|_| 0.0),
+            end_ms,
+            duration_ms: end_ms,
+            review_status: Some(ReviewStatus::NeedsReview).filter(|_| index == 0),
+            audio_path: None,
+            text: text.to_string(),
+        };
+        let script = TimedScript {
+            version: TIMED_SCRIPT_VERSION,
+            source: TimedScriptSource::Project,
+            source_path: PathBuf::from("project.json"),
+            generated_at: Utc::now(),
+            total_duration_ms: None,
+            missing_timing: 1,
+            warnings: Vec::new(),
+            segments: vec![
+                segment(0, "Well, she said \"hi\"", Some(1234.5)),
+                segment(1, "two\nlines", None),
+            ],
+        };
+        let csv = String::from_utf8(encode(&script, TimedScriptFormat::Csv).unwrap()).unwrap();
+        let rows: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(
+            rows,
+            [
+                CSV_COLUMNS.join(",").as_str(),
+                "0,s0,en-US-Neural2-A,,0.000,1234.500,1234.500,needs_review,,\"Well, she said \"\"hi\"\"\"",
+                "1,s1,en-US-Neural2-A,,,,,,,\"two\nlines\"",
+                "",
+            ]
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&encode(&script, TimedScriptFormat::Json).unwrap()).unwrap();
+        assert_eq!(json["segments"][1]["start_ms"], serde_json::Value::Null);
+        assert_eq!(json["source"], "project");
+    }
+}
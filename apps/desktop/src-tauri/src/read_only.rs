@@ -0,0 +1,207 @@
+// Read-only mode, entered when the config, data or cache directory can't be
+// written, e.g. an AppImage on a read-only mount or a locked-down profile.
+// Settings then live in memory, disk caches are bypassed and commands that
+// produce files fail up front with `ReadOnlyMode` instead of scattered IO
+// errors
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use tauri::Manager;
+
+use crate::error::AppError;
+use crate::paths::AppPaths;
+use crate::settings::SettingsStore;
+
+/// Written and removed again to find out whether a directory is writable;
+/// permissions alone don't tell, e.g. on a read-only mount.
+const PROBE_FILE: &str = ".sclip-write-probe";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WriteAccessStatus {
+    pub read_only: bool,
+    /// The first app directory that couldn't be written.
+    pub unwritable_path: Option<PathBuf>,
+}
+
+/// Managed state recording whether the app runs read-only.
+pub struct WriteAccess {
+    unwritable: RwLock<Option<PathBuf>>,
+}
+
+fn is_writable(dir: &Path) -> bool {
+    let probe = dir.join(PROBE_FILE);
+    let result = fs::create_dir_all(dir)
+        .and_then(|()| fs::write(&probe, b""))
+        .and_then(|()| fs::remove_file(&probe));
+    if let Err(e) = &result {
+        log::warn!("{} is not writable: {}", dir.display(), e);
+    }
+    result.is_ok()
+}
+
+/// The first of `dirs` that can't be written, if any.
+pub fn first_unwritable(dirs: &[&Path]) -> Option<PathBuf> {
+    dirs.iter()
+        .find(|dir| !is_writable(dir))
+        .map(|dir| dir.to_path_buf())
+}
+
+fn probed_dirs(paths: &AppPaths) -> [&Path; 3] {
+    [&paths.config_dir, &paths.data_dir, &paths.cache_dir]
+}
+
+impl WriteAccess {
+    /// Probes the config, data and cache directories of `paths`.
+    pub fn probe(paths: &AppPaths) -> Self {
+        let unwritable = first_unwritable(&probed_dirs(paths));
+        if let Some(path) = &unwritable {
+            log::warn!(
+                "Running read-only: {} is not writable; settings won't be saved and caches are off",
+                path.display()
+            );
+        }
+        Self {
+            unwritable: RwLock::new(unwritable),
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.unwritable.read().unwrap().is_some()
+    }
+
+    pub fn status(&self) -> WriteAccessStatus {
+        let unwritable = self.unwritable.read().unwrap().clone();
+        WriteAccessStatus {
+            read_only: unwritable.is_some(),
+            unwritable_path: unwritable,
+        }
+    }
+
+    /// Fails with `ReadOnlyMode` in read-only mode; called first by every
+    /// command that produces files.
+    pub fn check(&self) -> Result<(), AppError> {
+        match &*self.unwritable.read().unwrap() {
+            Some(path) => Err(AppError::ReadOnlyMode {
+                path: path.display().to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Probes again, e.g. after the user fixed permissions.
+    pub fn recheck(&self, paths: &AppPaths) -> WriteAccessStatus {
+        let unwritable = first_unwritable(&probed_dirs(paths));
+        *self.unwritable.write().unwrap() = unwritable;
+        self.status()
+    }
+}
+
+/// Probes the app directories again. Leaving read-only mode saves the
+/// settings changed meanwhile; caches are used again from the next request.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn retry_writable_check(
+    app_handle: tauri::AppHandle,
+    access: tauri::State<'_, WriteAccess>,
+    settings: tauri::State<'_, SettingsStore>,
+) -> Result<WriteAccessStatus, AppError> {
+    let was_read_only = access.is_read_only();
+    let status = access.recheck(&AppPaths::resolve(&app_handle)?);
+    settings.set_read_only(status.read_only);
+    if was_read_only && !status.read_only {
+        log::info!("App directories are writable again, leaving read-only mode");
+        settings.persist()?;
+    }
+    Ok(status)
+}
+
+/// Whether the app runs read-only; `false` before that's known.
+pub fn is_read_only(app_handle: &tauri::AppHandle) -> bool {
+    app_handle
+        .try_state::<WriteAccess>()
+        .is_some_and(|access| access.is_read_only())
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![retry_writable_check]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    #[test]
+    fn writable_directories_are_created_and_left_clean() {
+        let scratch = Scratch::new("writable");
+        let access = WriteAccess::probe(&scratch.app_paths());
+        assert!(!access.is_read_only());
+        assert!(access.check().is_ok());
+        for dir in probed_dirs(&scratch.app_paths()) {
+            assert!(dir.is_dir());
+            assert!(!dir.join(PROBE_FILE).exists());
+        }
+    }
+
+    #[test]
+    fn an_unwritable_directory_turns_on_read_only_mode_until_fixed() {
+        let scratch = Scratch::new("blocked");
+        let paths = scratch.app_paths();
+        // A file where the data directory goes can't be written into by
+        // anyone, root included
+        fs::write(&paths.data_dir, "in the way").unwrap();
+        let access = WriteAccess::probe(&paths);
+        let status = access.status();
+        assert!(status.read_only);
+        assert_eq!(status.unwritable_path, Some(paths.data_dir.clone()));
+        match access.check() {
+            Err(AppError::ReadOnlyMode { path }) => {
+                assert_eq!(path, paths.data_dir.display().to_string())
+            }
+            other => panic!("expected ReadOnlyMode, got {:?}", other),
+        }
+
+        fs::remove_file(&paths.data_dir).unwrap();
+        assert!(!access.recheck(&paths).read_only);
+        assert!(access.check().is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_read_only_directory_turns_on_read_only_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let scratch = Scratch::new("permissions");
+        let paths = scratch.app_paths();
+        fs::create_dir_all(&paths.data_dir).unwrap();
+        fs::set_permissions(&paths.data_dir, fs::Permissions::from_mode(0o555)).unwrap();
+        // Root writes regardless of permissions, so there's nothing to see
+        if fs::write(paths.data_dir.join("root"), b"").is_ok() {
+            return;
+        }
+        let access = WriteAccess::probe(&paths);
+        assert_eq!(
+            access.status().unwritable_path,
+            Some(paths.data_dir.clone())
+        );
+    }
+
+    #[test]
+    fn settings_changed_while_read_only_are_kept_in_memory() {
+        let scratch = Scratch::new("settings");
+        let path = scratch.0.join("settings.json");
+        let settings = SettingsStore::load(path.clone());
+        settings.set_read_only(true);
+        settings
+            .update(|s| s.ui_locale = "de-DE".to_string())
+            .unwrap();
+        assert_eq!(settings.get().ui_locale, "de-DE");
+        assert!(!path.exists());
+
+        settings.set_read_only(false);
+        settings.persist().unwrap();
+        assert_eq!(SettingsStore::load(path).get().ui_locale, "de-DE");
+    }
+}
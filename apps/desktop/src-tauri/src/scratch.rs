@@ -0,0 +1,51 @@
+// Scratch directories for the tests that touch the file system, one per
+// test so they run in parallel, removed with everything in them on drop
+use std::fs;
+use std::path::PathBuf;
+
+use crate::paths::AppPaths;
+
+/// A fresh directory under the system temp dir, canonicalized so it
+/// compares equal to the paths `AllowedRoots` validates.
+pub(crate) struct Scratch(pub PathBuf);
+
+impl Scratch {
+    /// `name` tells the directories apart, e.g. one a crashed test left.
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("sclip-{}-{}", name, uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir.canonicalize().unwrap())
+    }
+
+    /// The app's directories inside it, laid out as on a real install and
+    /// not yet created.
+    pub fn app_paths(&self) -> AppPaths {
+        AppPaths::in_dirs(
+            self.0.join("config"),
+            self.0.join("data"),
+            self.0.join("logs"),
+            self.0.join("cache"),
+        )
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        make_writable(&self.0);
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Gives back the write permission a test took from a directory, which
+/// removing what's in it needs.
+#[cfg(unix)]
+fn make_writable(dir: &std::path::Path) {
+    use std::os::unix::fs::PermissionsExt;
+    let _ = fs::set_permissions(dir, fs::Permissions::from_mode(0o755));
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            make_writable(&entry.path());
+        }
+    }
+}
@@ -0,0 +1,225 @@
+// Import of script text files in whatever encoding the editor saved them
+pub mod structure;
+
+use encoding_rs::{DecoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use serde::Serialize;
+use std::path::PathBuf;
+
+use crate::error::AppError;
+use crate::paths::roots::{AllowedRoots, Read};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptFile {
+    pub path: PathBuf,
+    /// UTF-8 text with the BOM removed and `\n` line endings.
+    pub text: String,
+    /// "UTF-8", "UTF-16LE", "UTF-16BE" or "windows-1252".
+    pub encoding: &'static str,
+    pub had_bom: bool,
+    /// Undecodable byte sequences, each replaced with U+FFFD.
+    pub replaced_characters: usize,
+    pub warnings: Vec<String>,
+}
+
+/// UTF-16 without a BOM: in text that is mostly ASCII, every other byte is
+/// zero. Which half holds the zeros gives the byte order.
+fn sniff_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    if bytes.len() < 4 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let pairs = bytes.len() / 2;
+    let even_zeros = bytes.iter().step_by(2).filter(|&&b| b == 0).count();
+    let odd_zeros = bytes.iter().skip(1).step_by(2).filter(|&&b| b == 0).count();
+    if odd_zeros * 10 >= pairs * 3 && even_zeros * 10 < pairs {
+        Some(UTF_16LE)
+    } else if even_zeros * 10 >= pairs * 3 && odd_zeros * 10 < pairs {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// Decodes without a BOM, replacing each malformed sequence with U+FFFD.
+/// Returns the text and how many replacements were made.
+fn decode(encoding: &'static Encoding, bytes: &[u8]) -> (String, usize) {
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut text = String::with_capacity(
+        decoder
+            .max_utf8_buffer_length_without_replacement(bytes.len())
+            .unwrap_or(bytes.len() * 3),
+    );
+    let mut replaced = 0;
+    let mut rest = bytes;
+    loop {
+        let (result, read) = decoder.decode_to_string_without_replacement(rest, &mut text, true);
+        rest = &rest[read..];
+        match result {
+            DecoderResult::InputEmpty => break,
+            DecoderResult::Malformed(_, _) => {
+                text.push(char::REPLACEMENT_CHARACTER);
+                replaced += 1;
+            }
+            DecoderResult::OutputFull => text.reserve(rest.len() * 3 + 4),
+        }
+    }
+    (text, replaced)
+}
+
+/// Picks the encoding of `bytes`: a BOM wins, then BOM-less UTF-16, then
+/// UTF-8. Invalid UTF-8 still counts as UTF-8 when it has more valid
+/// multi-byte characters than errors, a damaged UTF-8 file rather than a
+/// legacy one; otherwise it is read as Windows-1252, which decodes any byte.
+fn detect(bytes: &[u8]) -> (&'static Encoding, usize) {
+    if let Some((encoding, bom_len)) = Encoding::for_bom(bytes) {
+        return (encoding, bom_len);
+    }
+    if let Some(encoding) = sniff_utf16(bytes) {
+        return (encoding, 0);
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return (UTF_8, 0);
+    }
+    let (text, replaced) = decode(UTF_8, bytes);
+    let multibyte = text
+        .chars()
+        .filter(|&c| !c.is_ascii() && c != char::REPLACEMENT_CHARACTER)
+        .count();
+    if multibyte > replaced {
+        (UTF_8, 0)
+    } else {
+        (WINDOWS_1252, 0)
+    }
+}
+
+/// Decodes the bytes of the script file at `path`.
+pub fn decode_script(path: PathBuf, bytes: &[u8]) -> ScriptFile {
+    let (encoding, bom_len) = detect(bytes);
+    let (text, replaced_characters) = decode(encoding, &bytes[bom_len..]);
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+
+    let mut warnings = Vec::new();
+    if replaced_characters > 0 {
+        warnings.push(format!(
+            "{} undecodable character(s) were replaced with \u{FFFD}",
+            replaced_characters
+        ));
+    }
+    if encoding == WINDOWS_1252 {
+        warnings.push("not valid Unicode; read as Windows-1252".to_string());
+    }
+    ScriptFile {
+        path,
+        text,
+        encoding: encoding.name(),
+        had_bom: bom_len > 0,
+        replaced_characters,
+        warnings,
+    }
+}
+
+/// Reads a script file dropped on or opened in the editor.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn read_script_file(
+    roots: tauri::State<'_, AllowedRoots>,
+    path: PathBuf,
+) -> Result<ScriptFile, AppError> {
+    let bytes = roots.validate_path::<Read>(&path)?.read()?;
+    let script = decode_script(path, &bytes);
+    if script.encoding != "UTF-8" || !script.warnings.is_empty() {
+        log::info!(
+            "Imported {} as {} ({} replaced)",
+            script.path.display(),
+            script.encoding,
+            script.replaced_characters
+        );
+    }
+    Ok(script)
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![read_script_file, structure::parse_script_structure]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoded(bytes: &[u8]) -> ScriptFile {
+        decode_script(PathBuf::from("script.txt"), bytes)
+    }
+
+    fn utf16(text: &str, big_endian: bool) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|unit| {
+                if big_endian {
+                    unit.to_be_bytes()
+                } else {
+                    unit.to_le_bytes()
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn strips_a_utf8_bom_and_unifies_line_endings() {
+        let script = decoded(b"\xEF\xBB\xBFScene one\r\nNarrator: Hi\rEnd\n");
+        assert_eq!(script.encoding, "UTF-8");
+        assert!(script.had_bom);
+        assert_eq!(script.text, "Scene one\nNarrator: Hi\nEnd\n");
+        assert!(script.warnings.is_empty());
+
+        let plain = decoded("Caf\u{e9} \u{2014} na\u{ef}ve".as_bytes());
+        assert_eq!(plain.encoding, "UTF-8");
+        assert!(!plain.had_bom);
+        assert_eq!(plain.text, "Caf\u{e9} \u{2014} na\u{ef}ve");
+    }
+
+    #[test]
+    fn reads_utf16_with_and_without_a_bom() {
+        for big_endian in [false, true] {
+            let expected = if big_endian { "UTF-16BE" } else { "UTF-16LE" };
+            let mut with_bom = if big_endian {
+                vec![0xFE, 0xFF]
+            } else {
+                vec![0xFF, 0xFE]
+            };
+            with_bom.extend(utf16("Caf\u{e9}\r\nline two", big_endian));
+            let script = decoded(&with_bom);
+            assert_eq!(script.encoding, expected);
+            assert!(script.had_bom);
+            assert_eq!(script.text, "Caf\u{e9}\nline two");
+
+            let script = decoded(&utf16("Mostly ASCII text", big_endian));
+            assert_eq!(script.encoding, expected);
+            assert!(!script.had_bom);
+            assert_eq!(script.text, "Mostly ASCII text");
+        }
+    }
+
+    #[test]
+    fn reads_legacy_bytes_as_windows_1252() {
+        // "Café – naïve" as Notepad saved it before UTF-8
+        let script = decoded(b"Caf\xE9 \x96 na\xEFve");
+        assert_eq!(script.encoding, "windows-1252");
+        assert_eq!(script.text, "Caf\u{e9} \u{2013} na\u{ef}ve");
+        assert_eq!(script.replaced_characters, 0);
+        assert_eq!(script.warnings, ["not valid Unicode; read as Windows-1252"]);
+    }
+
+    #[test]
+    fn keeps_damaged_utf8_as_utf8_and_counts_replacements() {
+        let mut bytes = "\u{e9}t\u{e9} \u{e0} l'\u{ee}le ".as_bytes().to_vec();
+        bytes.push(0xFF);
+        bytes.extend_from_slice(b"end");
+        let script = decoded(&bytes);
+        assert_eq!(script.encoding, "UTF-8");
+        assert_eq!(script.replaced_characters, 1);
+        assert_eq!(script.text, "\u{e9}t\u{e9} \u{e0} l'\u{ee}le \u{FFFD}end");
+        assert_eq!(
+            script.warnings,
+            ["1 undecodable character(s) were replaced with \u{FFFD}"]
+        );
+    }
+}
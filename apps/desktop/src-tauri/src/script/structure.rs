@@ -0,0 +1,357 @@
+// Chapters and scenes of a script, from its headings: markdown `#` lines,
+// "Scene N" lines and ALL-CAPS lines standing alone. Fenced and indented
+// code is never read as a heading, and caps lines ending like a sentence
+// are taken for shouting rather than titles
+use serde::{Deserialize, Serialize};
+
+/// Longest line, in characters, taken for a bare "Scene N" or caps heading.
+const MAX_HEADING_CHARS: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeadingKind {
+    /// `#` to `######`, the level being the number of `#`.
+    Markdown,
+    /// "Scene 3", "Scene 3 – The Reveal" on a line of its own: at the level
+    /// of the scene before it, else one below the heading before it.
+    Scene,
+    /// An ALL-CAPS line with blank lines around it, e.g. "ACT ONE": one
+    /// below the `#` heading before it, else 1.
+    Caps,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScriptSection {
+    pub title: String,
+    /// 1 for the outermost headings; see `HeadingKind`.
+    pub level: u8,
+    pub kind: HeadingKind,
+    /// N of a title starting "Scene N", however the heading was written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scene_number: Option<u32>,
+    /// Byte range of the section in the script, its heading included, up to
+    /// the next heading at its level or above.
+    pub start: usize,
+    pub end: usize,
+    /// Where the text after the heading line starts.
+    pub body_start: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<ScriptSection>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScriptStructure {
+    /// End of the text before the first heading; 0 when the script opens
+    /// with one.
+    pub preamble_end: usize,
+    pub sections: Vec<ScriptSection>,
+}
+
+/// N of "Scene N", and the title after it and its separator, if any.
+fn scene(title: &str) -> Option<(u32, &str)> {
+    let prefix = title.get(..5)?;
+    if !prefix.eq_ignore_ascii_case("scene") {
+        return None;
+    }
+    let rest = &title[5..];
+    let digits = rest.trim_start();
+    if digits.len() == rest.len() {
+        return None;
+    }
+    let len = digits
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(digits.len());
+    let number = digits[..len].parse().ok()?;
+    let after = digits[len..].trim_start();
+    if after.is_empty() {
+        return Some((number, after));
+    }
+    let separator = after.chars().next()?;
+    if !matches!(separator, ':' | '-' | '–' | '—' | '.' | ')') {
+        return None;
+    }
+    Some((number, after[separator.len_utf8()..].trim()))
+}
+
+fn ends_like_sentence(line: &str) -> bool {
+    line.ends_with(['.', '!', '?', ',', ';', ':', '…'])
+}
+
+/// The level and title of a markdown heading line.
+fn markdown(line: &str) -> Option<(u8, String)> {
+    let hashes = line.len() - line.trim_start_matches('#').len();
+    let rest = &line[hashes..];
+    if !(1..=6).contains(&hashes) || !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        return None;
+    }
+    let mut title = rest.trim();
+    // A closing run of `#` is dropped only when set apart, so "C#" stays
+    let unclosed = title.trim_end_matches('#');
+    if unclosed.is_empty() || unclosed.ends_with([' ', '\t']) {
+        title = unclosed.trim_end();
+    }
+    (!title.is_empty()).then(|| (hashes as u8, title.to_string()))
+}
+
+fn is_caps(line: &str) -> bool {
+    let letters = line.chars().filter(|c| c.is_alphabetic()).count();
+    letters >= 2
+        && !line.chars().any(char::is_lowercase)
+        && line.chars().any(char::is_uppercase)
+        && !ends_like_sentence(line)
+}
+
+/// A fence opening or closing a code block: three or more backticks or
+/// tildes.
+fn fence(line: &str) -> Option<(char, usize)> {
+    let marker = line.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let len = line.len() - line.trim_start_matches(marker).len();
+    (len >= 3).then_some((marker, len))
+}
+
+struct Heading {
+    start: usize,
+    body_start: usize,
+    level: u8,
+    kind: HeadingKind,
+    title: String,
+}
+
+fn headings(text: &str) -> Vec<Heading> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        lines.push((
+            offset,
+            offset + line.len(),
+            line.trim_end_matches(['\n', '\r']),
+        ));
+        offset += line.len();
+    }
+    let blank = |i: usize| lines.get(i).is_some_and(|(_, _, l)| l.trim().is_empty());
+
+    let mut headings = Vec::new();
+    let mut open_fence: Option<(char, usize)> = None;
+    let mut markdown_level = 0;
+    let mut outer_level = 0;
+    let mut scene_level = None;
+    for (i, &(start, body_start, line)) in lines.iter().enumerate() {
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        if line.starts_with('\t') || indent >= 4 {
+            continue;
+        }
+        let line = line.trim();
+        if let Some((marker, len)) = fence(line) {
+            open_fence = match open_fence {
+                None => Some((marker, len)),
+                Some((open, open_len))
+                    if marker == open
+                        && len >= open_len
+                        && line.trim_start_matches(marker).trim().is_empty() =>
+                {
+                    None
+                }
+                still_open => still_open,
+            };
+            continue;
+        }
+        if open_fence.is_some() {
+            continue;
+        }
+
+        let heading = |level, kind, title: &str| Heading {
+            start,
+            body_start,
+            level,
+            kind,
+            title: title.to_string(),
+        };
+        if let Some((level, title)) = markdown(line) {
+            markdown_level = level;
+            outer_level = level;
+            if scene(&title).is_some() {
+                scene_level = Some(level);
+            }
+            headings.push(heading(level, HeadingKind::Markdown, &title));
+            continue;
+        }
+        if line.chars().count() > MAX_HEADING_CHARS {
+            continue;
+        }
+        if let Some((_, rest)) = scene(line) {
+            if !ends_like_sentence(rest) {
+                let level = *scene_level.get_or_insert(outer_level + 1);
+                headings.push(heading(level, HeadingKind::Scene, line));
+            }
+        } else if is_caps(line) && (i == 0 || blank(i - 1)) && blank(i + 1) {
+            outer_level = markdown_level + 1;
+            headings.push(heading(outer_level, HeadingKind::Caps, line));
+        }
+    }
+    headings
+}
+
+/// Finds the headings of `text` and nests them into sections by level. A
+/// section runs to the next heading at its level or above, so a deeper
+/// heading after it starts a child; byte ranges are of the UTF-8 text.
+pub fn parse(text: &str) -> ScriptStructure {
+    let headings = headings(text);
+    let mut structure = ScriptStructure {
+        preamble_end: headings.first().map_or(text.len(), |h| h.start),
+        sections: Vec::new(),
+    };
+    let mut open: Vec<ScriptSection> = Vec::new();
+    let close = |open: &mut Vec<ScriptSection>, roots: &mut Vec<ScriptSection>, end| {
+        let mut section = open.pop()?;
+        section.end = end;
+        match open.last_mut() {
+            Some(parent) => parent.children.push(section),
+            None => roots.push(section),
+        }
+        Some(())
+    };
+    for heading in headings {
+        while open.last().is_some_and(|s| s.level >= heading.level) {
+            close(&mut open, &mut structure.sections, heading.start);
+        }
+        open.push(ScriptSection {
+            scene_number: scene(&heading.title).map(|(number, _)| number),
+            title: heading.title,
+            level: heading.level,
+            kind: heading.kind,
+            start: heading.start,
+            end: text.len(),
+            body_start: heading.body_start,
+            children: Vec::new(),
+        });
+    }
+    while close(&mut open, &mut structure.sections, text.len()).is_some() {}
+    structure
+}
+
+/// The chapters and scenes of a script, for the outline and for
+/// `synthesize_batch` to file segments by section.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn parse_script_structure(text: String) -> ScriptStructure {
+    parse(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Title, level and kind of each section, depth first.
+    fn outline(sections: &[ScriptSection]) -> Vec<(String, u8, HeadingKind)> {
+        sections
+            .iter()
+            .flat_map(|s| {
+                std::iter::once((s.title.clone(), s.level, s.kind)).chain(outline(&s.children))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nests_markdown_headings_by_level() {
+        let text = "Intro\n# Part One\nText\n## Scene 1: Arrival\nBody\n## Scene 2\nMore\n# Part Two\nEnd\n";
+        let structure = parse(text);
+        assert_eq!(structure.preamble_end, text.find("# Part One").unwrap());
+        let [one, two] = &structure.sections[..] else {
+            panic!("expected two parts, got {:?}", structure.sections);
+        };
+        assert_eq!(one.start, structure.preamble_end);
+        assert_eq!(one.body_start, text.find("Text").unwrap());
+        assert_eq!(one.end, text.find("# Part Two").unwrap());
+        assert_eq!(two.end, text.len());
+
+        let [arrival, second] = &one.children[..] else {
+            panic!("expected two scenes, got {:?}", one.children);
+        };
+        assert_eq!(arrival.title, "Scene 1: Arrival");
+        assert_eq!(arrival.scene_number, Some(1));
+        assert_eq!(arrival.end, second.start);
+        assert_eq!(second.scene_number, Some(2));
+        assert_eq!(second.end, one.end);
+        assert!(two.children.is_empty());
+        assert_eq!(two.scene_number, None);
+    }
+
+    #[test]
+    fn finds_caps_and_scene_lines_standing_alone() {
+        let text =
+            "ACT ONE\n\nScene 1 – The Call\nHello.\nSCENE 2\nBye.\n\nACT TWO\n\nScene 3\nEnd.\n";
+        let structure = parse(text);
+        assert_eq!(structure.preamble_end, 0);
+        assert_eq!(
+            outline(&structure.sections),
+            [
+                ("ACT ONE".to_string(), 1, HeadingKind::Caps),
+                ("Scene 1 – The Call".to_string(), 2, HeadingKind::Scene),
+                ("SCENE 2".to_string(), 2, HeadingKind::Scene),
+                ("ACT TWO".to_string(), 1, HeadingKind::Caps),
+                ("Scene 3".to_string(), 2, HeadingKind::Scene),
+            ]
+        );
+
+        // Below a markdown heading, caps lines are one level deeper
+        let structure = parse("# Book\n\nPART ONE\n\nScene 1\nText.\n");
+        assert_eq!(
+            outline(&structure.sections),
+            [
+                ("Book".to_string(), 1, HeadingKind::Markdown),
+                ("PART ONE".to_string(), 2, HeadingKind::Caps),
+                ("Scene 1".to_string(), 3, HeadingKind::Scene),
+            ]
+        );
+    }
+
+    #[test]
+    fn code_shouting_and_prose_are_not_headings() {
+        let text = concat!(
+            "Opening line.\n",
+            "```\n# not a heading\n```\n",
+            "    # indented code\n",
+            "\nSTOP THAT!\n\n",
+            "Text\nNOT ALONE\nText\n",
+            "Scene 5 was great.\n",
+            "Scenery.\n",
+        );
+        let structure = parse(text);
+        assert!(structure.sections.is_empty());
+        assert_eq!(structure.preamble_end, text.len());
+
+        let long = format!("\n{}\n\n", "LONG ".repeat(20));
+        assert!(parse(&long).sections.is_empty());
+        // A fence closes only with a marker as long as the one that opened it
+        let structure = parse("````\n```\n# inside\n````\n# Outside\n");
+        assert_eq!(outline(&structure.sections).len(), 1);
+        assert_eq!(structure.sections[0].title, "Outside");
+    }
+
+    #[test]
+    fn reads_scene_numbers_and_titles() {
+        assert_eq!(scene("Scene 3"), Some((3, "")));
+        assert_eq!(scene("scene 12: The End"), Some((12, "The End")));
+        assert_eq!(scene("SCENE 3 – The Reveal"), Some((3, "The Reveal")));
+        assert_eq!(scene("Scene 4)"), Some((4, "")));
+        for title in [
+            "Scenery",
+            "Scene3",
+            "Scene three",
+            "Scene 3 and more",
+            "Sce",
+        ] {
+            assert_eq!(scene(title), None, "{}", title);
+        }
+    }
+
+    #[test]
+    fn reads_markdown_heading_lines() {
+        assert_eq!(markdown("# Title"), Some((1, "Title".to_string())));
+        assert_eq!(markdown("### Title ###"), Some((3, "Title".to_string())));
+        assert_eq!(markdown("## C#"), Some((2, "C#".to_string())));
+        for line in ["#NoSpace", "####### Seven", "#", "##   ##", "Not # one"] {
+            assert_eq!(markdown(line), None, "{}", line);
+        }
+    }
+}
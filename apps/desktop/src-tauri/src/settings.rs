@@ -0,0 +1,1025 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tauri::{Emitter, Manager};
+
+use crate::audio::arbitration::PreviewArbitrationSettings;
+use crate::audio::presets::OutputFormat;
+use crate::audio::quality::QualityThresholds;
+use crate::cache::write_atomic;
+use crate::credentials::CredentialProfile;
+use crate::error::AppError;
+use crate::jobs::DEFAULT_PROGRESS_INTERVAL_MS;
+use crate::onboarding::OnboardingProgress;
+use crate::paths::grants::DirectoryGrant;
+use crate::project::download::DownloadSettings;
+use crate::shutdown::ShutdownSignal;
+use crate::sidecar::priority::BackendPerformance;
+use crate::sidecar::watchdog::WatchdogConfig;
+use crate::sidecar::SidecarManager;
+use crate::spelling::dictionaries::SpellingSettings;
+use crate::ssml::policy::SsmlPolicy;
+use crate::ssml::BreathingRoom;
+use crate::tts::history::HistorySettings;
+use crate::tts::placeholders::PlaceholderSettings;
+use crate::tts::recording::RecordingSettings;
+use crate::tts::spend::SpendCaps;
+use crate::tts::usage::UsageBudget;
+use crate::tts::TtsService;
+use crate::voices::capabilities::UnsupportedFeaturePolicy;
+use crate::voices::downgrade::CostSaverPolicy;
+use crate::voices::shortlist::VoicePreset;
+use crate::watch_folder::WatchFolderSettings;
+
+/// User-editable application settings, persisted as settings.json in the app
+/// config directory. Unknown or missing fields fall back to their defaults so
+/// older files keep loading.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Pause inserted between paragraphs (blank-line separated) of plain-text
+    /// input. 0 disables the conversion.
+    pub paragraph_pause_ms: u32,
+    /// Pause inserted at single line breaks within a paragraph. 0 reads them as
+    /// ordinary spaces.
+    pub line_pause_ms: u32,
+    /// Pauses added at punctuation within lines of plain-text input.
+    pub breathing_room: BreathingRoom,
+    /// BCP-47 locale of the UI, used for collation of language names.
+    pub ui_locale: String,
+    pub onboarding: OnboardingProgress,
+    /// Named service-account profiles; key material is in the OS keyring.
+    pub credential_profiles: Vec<CredentialProfile>,
+    /// Profile the TTS client authenticates as. `None` uses
+    /// application-default credentials.
+    pub active_profile: Option<String>,
+    /// CPU priority and thread budget of the Python backend.
+    pub backend_performance: BackendPerformance,
+    pub backend_watchdog: WatchdogConfig,
+    /// Added to the backend's otherwise minimal environment from its next
+    /// start, for advanced setups; the app's own `SCLIP_*` variables win.
+    pub backend_extra_env: BTreeMap<String, String>,
+    /// Voice names starred in the voice picker.
+    pub favorite_voices: Vec<String>,
+    /// Voice preselected for new projects.
+    pub default_voice: Option<String>,
+    /// Output preset, rate and pitch the voice picker applies with a voice,
+    /// by voice name.
+    pub voice_presets: BTreeMap<String, VoicePreset>,
+    /// Renamed voice ids, old to new, on top of the built-in renames and
+    /// winning over them; an empty new id cancels a built-in rename.
+    pub voice_remap: BTreeMap<String, String>,
+    /// Voice downgrades batch synthesis makes when asked to save cost.
+    pub cost_saver: CostSaverPolicy,
+    /// GCP project TTS usage is billed to, sent as x-goog-user-project.
+    /// `None` bills the credentials' own project.
+    pub quota_project: Option<String>,
+    /// Monthly character budget, which reservations count against.
+    pub usage_budget: UsageBudget,
+    /// Characters a call, a batch and a day may bill without confirming
+    /// through `confirm_spend`; no caps by default.
+    pub spend_caps: SpendCaps,
+    /// Debug recording of synthesis calls; off by default.
+    pub tts_recording: RecordingSettings,
+    /// How much of the text synthesis history entries keep.
+    pub synthesis_history: HistorySettings,
+    /// Handling of SSML, pitch or rate sent to a voice that doesn't support
+    /// it.
+    pub unsupported_features: UnsupportedFeaturePolicy,
+    /// Elements, attributes, nesting and sizes allowed in SSML sent to
+    /// Google, and whether the rest is stripped or the request refused.
+    pub ssml_policy: SsmlPolicy,
+    /// Voice preview sentences set by the user, by base language or full
+    /// language code; see `voices::sentences`.
+    pub preview_sentences: BTreeMap<String, String>,
+    /// Whether template placeholders left in a script block synthesis, and
+    /// patterns to look for besides the built-in ones.
+    pub placeholders: PlaceholderSettings,
+    /// Size cap and host allowlist of `download_asset`.
+    pub downloads: DownloadSettings,
+    /// Whether a voice preview pauses or ducks the project's playback.
+    pub preview_arbitration: PreviewArbitrationSettings,
+    /// Previews longer than this are played trimmed to it, with a short
+    /// fade-out; 0 plays them in full.
+    pub max_preview_ms: u32,
+    /// Speed previews play at, from 0.5 to 2.0, pitch kept; changing it
+    /// doesn't re-synthesize anything.
+    pub preview_playback_rate: f32,
+    /// Hash cached speech against its metadata on every read, to catch
+    /// files edited outside the app; off for caches too large to hash.
+    pub verify_tts_cache: bool,
+    /// Batch exports of the active project refuse segments not approved in
+    /// its review.
+    pub enforce_review: bool,
+    /// Levels at which finished audio is reported as silent or clipped.
+    pub audio_quality: QualityThresholds,
+    /// User output presets by name, beside the built-in ones.
+    pub output_presets: BTreeMap<String, OutputFormat>,
+    /// Directories outside the app the user allowed writes to.
+    pub directory_grants: Vec<DirectoryGrant>,
+    /// Where spelling dictionaries come from.
+    pub spelling: SpellingSettings,
+    /// Folder new script files are imported from as they appear.
+    pub watch_folder: WatchFolderSettings,
+    /// Throttling of progress events and other diagnostics knobs.
+    pub debug: DebugSettings,
+}
+
+/// Tuning knobs for diagnosing the app, not shown in the regular settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DebugSettings {
+    /// Least time between two progress events of one job; 0 sends every
+    /// event.
+    pub progress_event_interval_ms: u32,
+}
+
+impl Default for DebugSettings {
+    fn default() -> Self {
+        Self {
+            progress_event_interval_ms: DEFAULT_PROGRESS_INTERVAL_MS,
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            paragraph_pause_ms: 600,
+            line_pause_ms: 0,
+            breathing_room: BreathingRoom::default(),
+            ui_locale: "en-US".to_string(),
+            onboarding: OnboardingProgress::default(),
+            credential_profiles: Vec::new(),
+            active_profile: None,
+            backend_performance: BackendPerformance::default(),
+            backend_watchdog: WatchdogConfig::default(),
+            backend_extra_env: BTreeMap::new(),
+            favorite_voices: Vec::new(),
+            default_voice: None,
+            voice_presets: BTreeMap::new(),
+            voice_remap: BTreeMap::new(),
+            cost_saver: CostSaverPolicy::default(),
+            quota_project: None,
+            usage_budget: UsageBudget::default(),
+            spend_caps: SpendCaps::default(),
+            tts_recording: RecordingSettings::default(),
+            synthesis_history: HistorySettings::default(),
+            unsupported_features: UnsupportedFeaturePolicy::default(),
+            ssml_policy: SsmlPolicy::default(),
+            preview_sentences: BTreeMap::new(),
+            placeholders: PlaceholderSettings::default(),
+            downloads: DownloadSettings::default(),
+            preview_arbitration: PreviewArbitrationSettings::default(),
+            max_preview_ms: 8000,
+            preview_playback_rate: 1.0,
+            verify_tts_cache: true,
+            enforce_review: false,
+            audio_quality: QualityThresholds::default(),
+            output_presets: BTreeMap::new(),
+            directory_grants: Vec::new(),
+            spelling: SpellingSettings::default(),
+            watch_folder: WatchFolderSettings::default(),
+            debug: DebugSettings::default(),
+        }
+    }
+}
+
+/// Settings as of one revision.
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingsSnapshot {
+    /// Goes up with every change since launch; what `update_settings`
+    /// checks a patch's `base_revision` against.
+    pub revision: u64,
+    #[serde(flatten)]
+    pub settings: Settings,
+}
+
+/// How often the settings file is checked for edits made outside the app,
+/// e.g. by hand or by sclip-cli.
+const FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long an edited file has to stay unchanged before it's read, so a
+/// save still in progress isn't parsed half-written.
+const FILE_SETTLE_TIME: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingChange {
+    pub field: String,
+    pub from: Value,
+    pub to: Value,
+}
+
+/// Payload of the "settings-changed" event.
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingsChanged {
+    pub revision: u64,
+    pub changes: Vec<SettingChange>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingConflict {
+    pub field: String,
+    /// The in-memory value, which wins.
+    pub kept: Value,
+    /// The value in the file, which was ignored.
+    pub external: Value,
+}
+
+/// Payload of the "settings-conflict" event: fields edited in the file
+/// while the app had unsaved changes to them, e.g. in read-only mode.
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingsConflicts {
+    pub conflicts: Vec<SettingConflict>,
+}
+
+/// Payload of the "settings-parse-error" event. The file is left alone and
+/// the settings in memory stay as they were.
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingsParseError {
+    pub path: PathBuf,
+    pub message: String,
+    /// 1-based; `None` when the JSON parsed but a value was refused.
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+/// What reading an externally edited settings file found.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum ExternalEdit {
+    Changed(SettingsChanged),
+    Conflict(SettingsConflicts),
+    ParseError(SettingsParseError),
+}
+
+impl ExternalEdit {
+    pub fn event(&self) -> &'static str {
+        match self {
+            ExternalEdit::Changed(_) => "settings-changed",
+            ExternalEdit::Conflict(_) => "settings-conflict",
+            ExternalEdit::ParseError(_) => "settings-parse-error",
+        }
+    }
+}
+
+/// Fields only the app's own writers change. A directory grant is added
+/// by `grants::ensure` after the native confirmation and removed by
+/// `revoke_directory_grant`, so neither a patch nor an edit of the file can
+/// add one.
+const APP_ONLY_FIELDS: &[&str] = &["directory_grants"];
+
+type EditListener = Box<dyn Fn(ExternalEdit) + Send + Sync>;
+
+/// The settings file as the app last read or wrote it: what an external
+/// edit is diffed against.
+#[derive(Default)]
+struct OnDisk {
+    digest: Option<Vec<u8>>,
+    /// `None` when the file was unreadable at launch.
+    value: Option<Value>,
+}
+
+/// The settings shared by the frontend, the sidecar proxy and the app's own
+/// writers. Every change goes through the write lock and bumps the revision:
+/// internal writers use `update` with a closure that only touches its own
+/// fields, and the frontend sends merge patches through `update_settings`,
+/// so neither overwrites what the other just changed.
+pub struct SettingsStore {
+    path: PathBuf,
+    settings: RwLock<Settings>,
+    /// Only changed while holding the write lock on `settings`.
+    revision: AtomicU64,
+    first_launch: bool,
+    /// Keeps changes in memory only, while the config directory can't be
+    /// written.
+    read_only: AtomicBool,
+    on_disk: Mutex<OnDisk>,
+    on_external_edit: Mutex<Option<EditListener>>,
+}
+
+/// Applies an RFC 7396 merge patch: objects merge key by key, `null`
+/// removes a key (here: resets the field to its default) and anything else
+/// replaces the value.
+fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let Value::Object(target) = target else {
+        return;
+    };
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+impl SettingsStore {
+    pub fn load(path: PathBuf) -> Self {
+        let first_launch = !path.exists();
+        let mut on_disk = OnDisk::default();
+        let settings = match fs::read(&path) {
+            Ok(contents) => {
+                on_disk.digest = Some(Sha256::digest(&contents).to_vec());
+                match serde_json::from_slice::<Settings>(&contents) {
+                    Ok(settings) => {
+                        on_disk.value = serde_json::to_value(&settings).ok();
+                        settings
+                    }
+                    Err(e) => {
+                        log::warn!(
+                            "Ignoring unreadable settings file {}: {}",
+                            path.display(),
+                            e
+                        );
+                        Settings::default()
+                    }
+                }
+            }
+            Err(_) => Settings::default(),
+        };
+        Self {
+            path,
+            settings: RwLock::new(settings),
+            revision: AtomicU64::new(0),
+            first_launch,
+            read_only: AtomicBool::new(false),
+            on_disk: Mutex::new(on_disk),
+            on_external_edit: Mutex::new(None),
+        }
+    }
+
+    /// Reports what `reload` and the writers find when the file was edited
+    /// outside the app to `listener`.
+    pub fn set_edit_listener(&self, listener: impl Fn(ExternalEdit) + Send + Sync + 'static) {
+        *self.on_external_edit.lock().unwrap() = Some(Box::new(listener));
+    }
+
+    /// True if no settings file existed when the app started.
+    pub fn is_first_launch(&self) -> bool {
+        self.first_launch
+    }
+
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Relaxed);
+    }
+
+    /// Writes the current settings, e.g. the in-memory ones once read-only
+    /// mode ends.
+    pub fn persist(&self) -> Result<(), AppError> {
+        self.save(&self.settings.read().unwrap())
+    }
+
+    pub fn get(&self) -> Settings {
+        self.settings.read().unwrap().clone()
+    }
+
+    /// The current settings with their revision, read together.
+    pub fn snapshot(&self) -> SettingsSnapshot {
+        let current = self.settings.read().unwrap();
+        SettingsSnapshot {
+            revision: self.revision.load(Ordering::Acquire),
+            settings: current.clone(),
+        }
+    }
+
+    /// Applies `f` to the current settings and persists the result. An
+    /// edit to the file not read yet is merged in first, so saving doesn't
+    /// overwrite it.
+    pub fn update(&self, f: impl FnOnce(&mut Settings)) -> Result<Settings, AppError> {
+        let mut current = self.settings.write().unwrap();
+        let edits = self.absorb_external(&mut current);
+        let mut updated = current.clone();
+        f(&mut updated);
+        let result = self.save(&updated).map(|()| {
+            *current = updated.clone();
+            self.revision.fetch_add(1, Ordering::Release);
+            updated
+        });
+        drop(current);
+        self.notify(edits);
+        result
+    }
+
+    /// Reads the file again if it changed since the app last read or wrote
+    /// it, and merges the edit into the settings in memory.
+    pub fn reload(&self) {
+        let mut current = self.settings.write().unwrap();
+        let edits = self.absorb_external(&mut current);
+        drop(current);
+        self.notify(edits);
+    }
+
+    fn notify(&self, edits: Vec<ExternalEdit>) {
+        if let Some(listener) = self.on_external_edit.lock().unwrap().as_ref() {
+            edits.into_iter().for_each(listener);
+        }
+    }
+
+    /// Merges an external edit of the file into `current`, under the
+    /// caller's write lock. Field by field against the file as last seen:
+    /// one edited only in the file takes the file's value, one edited in
+    /// memory too keeps the in-memory value and is reported as a conflict.
+    /// A file that doesn't parse or validate changes nothing.
+    fn absorb_external(&self, current: &mut Settings) -> Vec<ExternalEdit> {
+        let Ok(contents) = fs::read(&self.path) else {
+            return Vec::new();
+        };
+        let digest = Sha256::digest(&contents).to_vec();
+        let mut on_disk = self.on_disk.lock().unwrap();
+        if on_disk.digest.as_ref() == Some(&digest) {
+            return Vec::new();
+        }
+        // Recorded even when unusable, so the same bad file is reported once
+        on_disk.digest = Some(digest);
+        let parse_error = |message: String, line: Option<usize>, column: Option<usize>| {
+            log::warn!(
+                "Ignoring edited settings file {}: {}",
+                self.path.display(),
+                message
+            );
+            vec![ExternalEdit::ParseError(SettingsParseError {
+                path: self.path.clone(),
+                message,
+                line,
+                column,
+            })]
+        };
+        let external: Settings = match serde_json::from_slice(&contents) {
+            Ok(settings) => settings,
+            Err(e) => return parse_error(e.to_string(), Some(e.line()), Some(e.column())),
+        };
+        if let Err(e) = validate(&external) {
+            return parse_error(e.to_string(), None, None);
+        }
+        let (Ok(external), Ok(ours)) = (
+            serde_json::to_value(&external),
+            serde_json::to_value(&*current),
+        ) else {
+            return Vec::new();
+        };
+        let base = on_disk
+            .value
+            .replace(external.clone())
+            .unwrap_or_else(|| ours.clone());
+
+        let mut merged = ours.clone();
+        let mut changes = Vec::new();
+        let mut conflicts = Vec::new();
+        if let (Some(fields), Some(merged_fields)) = (external.as_object(), merged.as_object_mut())
+        {
+            for (field, theirs) in fields {
+                let before = base.get(field).unwrap_or(&Value::Null);
+                let now = ours.get(field).unwrap_or(&Value::Null);
+                if theirs == before || theirs == now {
+                    continue;
+                }
+                if APP_ONLY_FIELDS.contains(&field.as_str()) {
+                    log::warn!("Ignoring {} edited in the settings file", field);
+                    continue;
+                }
+                if now != before {
+                    conflicts.push(SettingConflict {
+                        field: field.clone(),
+                        kept: now.clone(),
+                        external: theirs.clone(),
+                    });
+                    continue;
+                }
+                changes.push(SettingChange {
+                    field: field.clone(),
+                    from: now.clone(),
+                    to: theirs.clone(),
+                });
+                merged_fields.insert(field.clone(), theirs.clone());
+            }
+        }
+
+        let mut edits = Vec::new();
+        if !changes.is_empty() {
+            match serde_json::from_value(merged) {
+                Ok(merged) => {
+                    *current = merged;
+                    let revision = self.revision.fetch_add(1, Ordering::Release) + 1;
+                    log::info!(
+                        "Settings file edited outside the app: {} field(s) reloaded",
+                        changes.len()
+                    );
+                    edits.push(ExternalEdit::Changed(SettingsChanged { revision, changes }));
+                }
+                Err(e) => return parse_error(e.to_string(), None, None),
+            }
+        }
+        if !conflicts.is_empty() {
+            edits.push(ExternalEdit::Conflict(SettingsConflicts { conflicts }));
+        }
+        edits
+    }
+
+    /// Merges `patch` into the current settings, checks the result with
+    /// `validate` and persists it, all under the write lock. With a
+    /// `base_revision`, fails with `SettingsConflict` if the settings changed
+    /// since the caller read that revision.
+    pub fn patch(
+        &self,
+        patch: &Value,
+        base_revision: Option<u64>,
+        validate: impl FnOnce(&Settings) -> Result<(), AppError>,
+    ) -> Result<SettingsSnapshot, AppError> {
+        let mut current = self.settings.write().unwrap();
+        // An edit of the file counts as a change since `base_revision`
+        let edits = self.absorb_external(&mut current);
+        let result = self.patch_locked(&mut current, patch, base_revision, validate);
+        drop(current);
+        self.notify(edits);
+        result
+    }
+
+    fn patch_locked(
+        &self,
+        current: &mut Settings,
+        patch: &Value,
+        base_revision: Option<u64>,
+        validate: impl FnOnce(&Settings) -> Result<(), AppError>,
+    ) -> Result<SettingsSnapshot, AppError> {
+        let revision = self.revision.load(Ordering::Acquire);
+        if base_revision.is_some_and(|base| base != revision) {
+            return Err(AppError::SettingsConflict {
+                current_revision: revision,
+            });
+        }
+        if !patch.is_object() {
+            return Err(AppError::InvalidSettings {
+                message: "the patch must be a JSON object".to_string(),
+            });
+        }
+        if let Some(field) = APP_ONLY_FIELDS.iter().find(|f| patch.get(**f).is_some()) {
+            return Err(AppError::InvalidSettings {
+                message: format!("{} can't be patched; the app changes it itself", field),
+            });
+        }
+        let mut merged = serde_json::to_value(&*current).map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })?;
+        merge_patch(&mut merged, patch);
+        let updated: Settings =
+            serde_json::from_value(merged).map_err(|e| AppError::InvalidSettings {
+                message: e.to_string(),
+            })?;
+        validate(&updated)?;
+        self.save(&updated)?;
+        *current = updated.clone();
+        self.revision.store(revision + 1, Ordering::Release);
+        Ok(SettingsSnapshot {
+            revision: revision + 1,
+            settings: updated,
+        })
+    }
+
+    fn save(&self, settings: &Settings) -> Result<(), AppError> {
+        if self.read_only.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let json = serde_json::to_vec_pretty(settings).map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })?;
+        write_atomic(&self.path, &json)?;
+        *self.on_disk.lock().unwrap() = OnDisk {
+            digest: Some(Sha256::digest(&json).to_vec()),
+            value: serde_json::to_value(settings).ok(),
+        };
+        Ok(())
+    }
+}
+
+/// What `update_settings` refuses, and an edited file too.
+fn validate(settings: &Settings) -> Result<(), AppError> {
+    if let Some(project_id) = &settings.quota_project {
+        crate::tts::quota::validate_project_id(project_id)?;
+    }
+    settings
+        .spend_caps
+        .validate()
+        .map_err(|message| AppError::InvalidSettings {
+            message: format!("spend_caps.{}", message),
+        })?;
+    for name in settings.backend_extra_env.keys() {
+        crate::sidecar::environment::check_extra_name(name).map_err(|message| {
+            AppError::InvalidSettings {
+                message: format!("backend_extra_env: {}", message),
+            }
+        })?;
+    }
+    crate::audio::arbitration::check_playback_rate(settings.preview_playback_rate).map_err(
+        |message| AppError::InvalidSettings {
+            message: format!("preview_playback_rate: {}", message),
+        },
+    )?;
+    Ok(())
+}
+
+/// Hands the settings that services keep their own copy of to them.
+fn apply_to_services(settings: &Settings, tts: &TtsService, sidecar: &SidecarManager) {
+    sidecar.set_extra_env(settings.backend_extra_env.clone());
+    tts.set_quota_project(settings.quota_project.clone());
+    tts.recorder().set_settings(settings.tts_recording);
+    tts.set_ssml_policy(settings.ssml_policy);
+    tts.set_voice_remap(crate::voices::remap::VoiceRemap::from_settings(settings));
+    tts.spend().set_caps(settings.spend_caps);
+}
+
+/// Emits an external edit as its event, after handing reloaded settings to
+/// the services.
+pub fn announce(app_handle: &tauri::AppHandle, edit: ExternalEdit) {
+    if let (ExternalEdit::Changed(_), Some(store), Some(tts), Some(sidecar)) = (
+        &edit,
+        app_handle.try_state::<SettingsStore>(),
+        app_handle.try_state::<TtsService>(),
+        app_handle.try_state::<SidecarManager>(),
+    ) {
+        apply_to_services(&store.get(), &tts, &sidecar);
+    }
+    if let Err(e) = app_handle.emit(edit.event(), &edit) {
+        log::warn!("Failed to emit {}: {}", edit.event(), e);
+    }
+}
+
+fn file_stamp(path: &Path) -> Option<(Option<SystemTime>, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok(), metadata.len()))
+}
+
+/// Polls the settings file until `signal` fires, reloading it once an edit
+/// has settled. The app's own saves are recognized and skipped by `reload`.
+pub async fn watch_file(app_handle: tauri::AppHandle, mut signal: ShutdownSignal) {
+    let store = app_handle.state::<SettingsStore>();
+    let mut seen = file_stamp(&store.path);
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(FILE_POLL_INTERVAL) => {}
+            _ = signal.triggered() => return,
+        }
+        let stamp = file_stamp(&store.path);
+        if stamp == seen {
+            continue;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(FILE_SETTLE_TIME) => {}
+            _ = signal.triggered() => return,
+        }
+        // Still being written; the next poll looks again
+        if file_stamp(&store.path) != stamp {
+            continue;
+        }
+        seen = stamp;
+        store.reload();
+    }
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_settings(store: tauri::State<'_, SettingsStore>) -> SettingsSnapshot {
+    store.snapshot()
+}
+
+/// Merges `patch` (an RFC 7396 merge patch of `Settings`) into the current
+/// settings. Send only the fields being changed, and the `revision` they
+/// were read at as `base_revision` to be refused with `SettingsConflict`,
+/// rather than overwrite, when something else changed them in between.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn update_settings(
+    store: tauri::State<'_, SettingsStore>,
+    tts: tauri::State<'_, TtsService>,
+    sidecar: tauri::State<'_, SidecarManager>,
+    patch: Value,
+    base_revision: Option<u64>,
+) -> Result<SettingsSnapshot, AppError> {
+    let snapshot = store.patch(&patch, base_revision, validate)?;
+    apply_to_services(&snapshot.settings, &tts, &sidecar);
+    Ok(snapshot)
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![get_settings, update_settings]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    /// A store on a settings file of its own, removed on drop.
+    struct ScratchStore {
+        store: SettingsStore,
+        dir: Scratch,
+    }
+
+    impl ScratchStore {
+        fn new() -> Self {
+            let dir = Scratch::new("settings");
+            let store = SettingsStore::load(dir.0.join("settings.json"));
+            store.persist().unwrap();
+            Self { store, dir }
+        }
+
+        fn path(&self) -> PathBuf {
+            self.dir.0.join("settings.json")
+        }
+
+        /// Rewrites the file as an editor outside the app would.
+        fn edit_file(&self, edit: impl FnOnce(&mut Value)) {
+            let path = self.path();
+            let mut value: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+            edit(&mut value);
+            fs::write(&path, serde_json::to_vec_pretty(&value).unwrap()).unwrap();
+        }
+    }
+
+    fn grant_value() -> Value {
+        json!([{
+            "dir": std::env::temp_dir(),
+            "reason": "Export",
+            "granted_at": "2026-01-01T00:00:00Z",
+        }])
+    }
+
+    #[test]
+    fn patch_refuses_directory_grants() {
+        let scratch = ScratchStore::new();
+        let result = scratch.store.patch(
+            &json!({ "directory_grants": grant_value() }),
+            None,
+            validate,
+        );
+        assert!(matches!(result, Err(AppError::InvalidSettings { .. })));
+        assert!(scratch.store.get().directory_grants.is_empty());
+
+        let result = scratch
+            .store
+            .patch(&json!({ "directory_grants": null }), None, validate);
+        assert!(matches!(result, Err(AppError::InvalidSettings { .. })));
+
+        let snapshot = scratch
+            .store
+            .patch(&json!({ "line_pause_ms": 250 }), None, validate)
+            .unwrap();
+        assert_eq!(snapshot.settings.line_pause_ms, 250);
+    }
+
+    #[test]
+    fn external_edit_keeps_directory_grants() {
+        let scratch = ScratchStore::new();
+        scratch.edit_file(|value| {
+            value["directory_grants"] = grant_value();
+            value["line_pause_ms"] = json!(400);
+        });
+        scratch.store.reload();
+
+        let settings = scratch.store.get();
+        assert!(settings.directory_grants.is_empty());
+        assert_eq!(settings.line_pause_ms, 400);
+    }
+
+    #[test]
+    fn backend_extra_env_refuses_loader_variables() {
+        let scratch = ScratchStore::new();
+        for name in ["LD_PRELOAD", "DYLD_INSERT_LIBRARIES", "PYTHONPATH", "PATH"] {
+            let patch = json!({ "backend_extra_env": { name: "/tmp/x" } });
+            let result = scratch.store.patch(&patch, None, validate);
+            assert!(
+                matches!(result, Err(AppError::InvalidSettings { .. })),
+                "{} was accepted",
+                name
+            );
+        }
+        assert!(scratch.store.get().backend_extra_env.is_empty());
+
+        let patch = json!({ "backend_extra_env": { "HTTPS_PROXY": "http://proxy:8080" } });
+        let snapshot = scratch.store.patch(&patch, None, validate).unwrap();
+        assert_eq!(snapshot.settings.backend_extra_env.len(), 1);
+    }
+
+    #[test]
+    fn merge_patch_resets_null_fields() {
+        let mut target = json!({ "a": 1, "b": { "c": 2, "d": 3 } });
+        merge_patch(&mut target, &json!({ "a": null, "b": { "c": 4 } }));
+        assert_eq!(target, json!({ "b": { "c": 4, "d": 3 } }));
+    }
+
+    #[test]
+    fn patches_bump_the_revision_and_refuse_stale_bases() {
+        let scratch = ScratchStore::new();
+        assert_eq!(scratch.store.snapshot().revision, 0);
+        let first = scratch
+            .store
+            .patch(&json!({ "line_pause_ms": 100 }), Some(0), validate)
+            .unwrap();
+        assert_eq!(first.revision, 1);
+
+        // Based on what the caller saw before the first patch
+        let stale = scratch
+            .store
+            .patch(&json!({ "line_pause_ms": 200 }), Some(0), validate);
+        assert!(matches!(
+            stale,
+            Err(AppError::SettingsConflict {
+                current_revision: 1
+            })
+        ));
+        assert_eq!(scratch.store.get().line_pause_ms, 100);
+
+        let second = scratch
+            .store
+            .patch(&json!({ "paragraph_pause_ms": 900 }), None, validate)
+            .unwrap();
+        assert_eq!(second.revision, 2);
+        assert_eq!(second.settings.line_pause_ms, 100);
+        let saved: Settings = serde_json::from_slice(&fs::read(scratch.path()).unwrap()).unwrap();
+        assert_eq!((saved.line_pause_ms, saved.paragraph_pause_ms), (100, 900));
+    }
+
+    #[test]
+    fn refused_patches_change_nothing() {
+        let scratch = ScratchStore::new();
+        let path = scratch.path();
+        let before = fs::read(&path).unwrap();
+        for patch in [
+            json!([{ "line_pause_ms": 100 }]),
+            json!({ "line_pause_ms": "long" }),
+            json!({ "line_pause_ms": 100, "preview_playback_rate": 9.0 }),
+        ] {
+            assert!(
+                matches!(
+                    scratch.store.patch(&patch, None, validate),
+                    Err(AppError::InvalidSettings { .. })
+                ),
+                "{} was accepted",
+                patch
+            );
+        }
+        assert_eq!(scratch.store.snapshot().revision, 0);
+        assert_eq!(scratch.store.get().line_pause_ms, 0);
+        assert_eq!(fs::read(&path).unwrap(), before);
+    }
+
+    #[test]
+    fn updates_and_patches_keep_each_others_fields() {
+        let scratch = ScratchStore::new();
+        scratch
+            .store
+            .update(|s| s.favorite_voices.push("en-US-Neural2-A".to_string()))
+            .unwrap();
+        let snapshot = scratch
+            .store
+            .patch(&json!({ "line_pause_ms": 300 }), Some(1), validate)
+            .unwrap();
+        assert_eq!(snapshot.revision, 2);
+        assert_eq!(snapshot.settings.favorite_voices, ["en-US-Neural2-A"]);
+
+        scratch.store.update(|s| s.max_preview_ms = 0).unwrap();
+        let current = scratch.store.snapshot();
+        assert_eq!(current.revision, 3);
+        assert_eq!(current.settings.line_pause_ms, 300);
+        assert_eq!(current.settings.max_preview_ms, 0);
+        assert_eq!(current.settings.favorite_voices, ["en-US-Neural2-A"]);
+    }
+
+    /// Collects what the store reports about edits of its file.
+    fn listen(store: &SettingsStore) -> Arc<Mutex<Vec<ExternalEdit>>> {
+        let edits = Arc::new(Mutex::new(Vec::new()));
+        let sink = edits.clone();
+        store.set_edit_listener(move |edit| sink.lock().unwrap().push(edit));
+        edits
+    }
+
+    #[test]
+    fn external_edits_are_reloaded_and_reported_once() {
+        let scratch = ScratchStore::new();
+        let edits = listen(&scratch.store);
+        scratch.edit_file(|value| value["line_pause_ms"] = json!(400));
+        scratch.store.reload();
+        scratch.store.reload();
+
+        let edits = edits.lock().unwrap();
+        let [ExternalEdit::Changed(changed)] = &edits[..] else {
+            panic!("expected one change, got {:?}", edits);
+        };
+        assert_eq!(changed.revision, 1);
+        assert_eq!(changed.changes.len(), 1);
+        assert_eq!(changed.changes[0].field, "line_pause_ms");
+        assert_eq!(changed.changes[0].to, json!(400));
+        assert_eq!(edits[0].event(), "settings-changed");
+        assert_eq!(scratch.store.snapshot().revision, 1);
+        assert_eq!(scratch.store.get().line_pause_ms, 400);
+    }
+
+    #[test]
+    fn fields_changed_in_memory_too_keep_the_in_memory_value() {
+        let scratch = ScratchStore::new();
+        let edits = listen(&scratch.store);
+        scratch.store.set_read_only(true);
+        scratch.store.update(|s| s.line_pause_ms = 100).unwrap();
+        scratch.edit_file(|value| {
+            value["line_pause_ms"] = json!(200);
+            value["paragraph_pause_ms"] = json!(900);
+        });
+        scratch.store.reload();
+
+        let edits = edits.lock().unwrap();
+        let [ExternalEdit::Changed(changed), ExternalEdit::Conflict(conflicts)] = &edits[..] else {
+            panic!("expected a change and a conflict, got {:?}", edits);
+        };
+        assert_eq!(changed.revision, 2);
+        assert_eq!(changed.changes[0].field, "paragraph_pause_ms");
+        assert_eq!(conflicts.conflicts.len(), 1);
+        assert_eq!(conflicts.conflicts[0].field, "line_pause_ms");
+        assert_eq!(conflicts.conflicts[0].kept, json!(100));
+        assert_eq!(conflicts.conflicts[0].external, json!(200));
+        let settings = scratch.store.get();
+        assert_eq!(
+            (settings.line_pause_ms, settings.paragraph_pause_ms),
+            (100, 900)
+        );
+    }
+
+    #[test]
+    fn unusable_edits_change_nothing_and_are_reported_once() {
+        let scratch = ScratchStore::new();
+        let edits = listen(&scratch.store);
+        let path = scratch.path();
+        fs::write(&path, "{\n  \"line_pause_ms\": 400,\n  oops\n}").unwrap();
+        scratch.store.reload();
+        scratch.store.reload();
+        {
+            let edits = edits.lock().unwrap();
+            let [ExternalEdit::ParseError(error)] = &edits[..] else {
+                panic!("expected one parse error, got {:?}", edits);
+            };
+            assert_eq!(error.path, path);
+            assert_eq!(error.line, Some(3));
+            assert!(error.column.is_some());
+        }
+
+        // Parses, but `validate` refuses it
+        let mut value = serde_json::to_value(Settings::default()).unwrap();
+        value["line_pause_ms"] = json!(400);
+        value["preview_playback_rate"] = json!(9.0);
+        fs::write(&path, serde_json::to_vec_pretty(&value).unwrap()).unwrap();
+        scratch.store.reload();
+        let edits = edits.lock().unwrap();
+        let [_, ExternalEdit::ParseError(error)] = &edits[..] else {
+            panic!("expected a second parse error, got {:?}", edits);
+        };
+        assert_eq!((error.line, error.column), (None, None));
+        assert_eq!(scratch.store.snapshot().revision, 0);
+        assert_eq!(scratch.store.get().line_pause_ms, 0);
+    }
+
+    #[test]
+    fn writers_merge_an_edit_not_read_yet() {
+        let scratch = ScratchStore::new();
+        let edits = listen(&scratch.store);
+        scratch.edit_file(|value| value["line_pause_ms"] = json!(400));
+        let updated = scratch
+            .store
+            .update(|s| s.paragraph_pause_ms = 900)
+            .unwrap();
+        assert_eq!(
+            (updated.line_pause_ms, updated.paragraph_pause_ms),
+            (400, 900)
+        );
+        assert_eq!(scratch.store.snapshot().revision, 2);
+        let saved: Settings = serde_json::from_slice(&fs::read(scratch.path()).unwrap()).unwrap();
+        assert_eq!((saved.line_pause_ms, saved.paragraph_pause_ms), (400, 900));
+        assert_eq!(edits.lock().unwrap().len(), 1);
+
+        // A patch based on the revision before the edit is stale
+        scratch.edit_file(|value| value["line_pause_ms"] = json!(500));
+        let stale = scratch
+            .store
+            .patch(&json!({ "line_pause_ms": 100 }), Some(2), validate);
+        assert!(matches!(
+            stale,
+            Err(AppError::SettingsConflict {
+                current_revision: 3
+            })
+        ));
+        assert_eq!(scratch.store.get().line_pause_ms, 500);
+    }
+}
@@ -0,0 +1,290 @@
+// Coordinated stop of the long-lived background tasks: the backend event
+// bridge and watchdog, the watch folder and the startup voice refresh.
+// Each is spawned through `ShutdownController` with a signal it selects on;
+// at exit every signal fires and the tasks get `TASK_BUDGET` to finish
+// before the ones still running are aborted and logged
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Notify};
+
+/// How long tasks get to stop once signalled, together.
+pub const TASK_BUDGET: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TaskId(u64);
+
+/// What a background task selects on: fires when the app shuts down or
+/// when the task alone is stopped.
+pub struct ShutdownSignal {
+    rx: watch::Receiver<bool>,
+}
+
+impl ShutdownSignal {
+    pub fn is_triggered(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Resolves once the task should stop; at once if it already should.
+    pub async fn triggered(&mut self) {
+        // An error means the controller is gone, which is a stop too
+        let _ = self.rx.wait_for(|stop| *stop).await;
+    }
+}
+
+struct Task {
+    name: String,
+    started_at: DateTime<Utc>,
+    stop: watch::Sender<bool>,
+    handle: tauri::async_runtime::JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct Inner {
+    tasks: Mutex<HashMap<TaskId, Task>>,
+    /// Woken whenever a task ends.
+    finished: Notify,
+    shutting_down: AtomicBool,
+    next_id: AtomicU64,
+}
+
+/// Managed state holding every long-lived task the app spawned.
+#[derive(Default)]
+pub struct ShutdownController {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackgroundTask {
+    pub name: String,
+    pub started_at: DateTime<Utc>,
+    pub age_ms: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ShutdownReport {
+    pub stopped: usize,
+    /// Tasks still running at the end of the budget, which were aborted.
+    pub aborted: Vec<String>,
+    pub elapsed_ms: u64,
+}
+
+impl ShutdownController {
+    /// Spawns `task` with its signal. A task spawned during shutdown gets
+    /// a signal that has already fired.
+    pub fn spawn<F, Fut>(&self, name: impl Into<String>, task: F) -> TaskId
+    where
+        F: FnOnce(ShutdownSignal) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let id = TaskId(self.inner.next_id.fetch_add(1, Ordering::Relaxed));
+        let (stop, rx) = watch::channel(self.inner.shutting_down.load(Ordering::Relaxed));
+        let future = task(ShutdownSignal { rx });
+        let inner = self.inner.clone();
+        // Held across the spawn, so a task ending at once still finds its
+        // entry to remove
+        let mut tasks = self.inner.tasks.lock().unwrap();
+        let handle = tauri::async_runtime::spawn(async move {
+            future.await;
+            inner.tasks.lock().unwrap().remove(&id);
+            inner.finished.notify_waiters();
+        });
+        tasks.insert(
+            id,
+            Task {
+                name: name.into(),
+                started_at: Utc::now(),
+                stop,
+                handle,
+            },
+        );
+        id
+    }
+
+    /// Signals one task to stop, leaving the others running; it ends in
+    /// its own time.
+    pub fn stop(&self, id: TaskId) {
+        if let Some(task) = self.inner.tasks.lock().unwrap().get(&id) {
+            let _ = task.stop.send(true);
+        }
+    }
+
+    /// Live tasks, oldest first.
+    pub fn tasks(&self) -> Vec<BackgroundTask> {
+        let now = Utc::now();
+        let mut tasks: Vec<BackgroundTask> = self
+            .inner
+            .tasks
+            .lock()
+            .unwrap()
+            .values()
+            .map(|t| BackgroundTask {
+                name: t.name.clone(),
+                started_at: t.started_at,
+                age_ms: (now - t.started_at).num_milliseconds(),
+            })
+            .collect();
+        tasks.sort_by_key(|t| t.started_at);
+        tasks
+    }
+
+    /// Signals every task, waits up to `budget` for them to end, then aborts
+    /// and logs the ones still running.
+    pub async fn shutdown(&self, budget: Duration) -> ShutdownReport {
+        let started = Instant::now();
+        self.inner.shutting_down.store(true, Ordering::Relaxed);
+        let signalled = {
+            let tasks = self.inner.tasks.lock().unwrap();
+            for task in tasks.values() {
+                let _ = task.stop.send(true);
+            }
+            tasks.len()
+        };
+        let deadline = started + budget;
+        loop {
+            // Registered before checking, so a task ending in between still wakes us
+            let finished = self.inner.finished.notified();
+            if self.inner.tasks.lock().unwrap().is_empty() {
+                break;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if tokio::time::timeout(remaining, finished).await.is_err() {
+                break;
+            }
+        }
+
+        let mut aborted = Vec::new();
+        for (_, task) in self.inner.tasks.lock().unwrap().drain() {
+            log::warn!(
+                "Background task {} didn't stop within {:?} (running since {}), aborting it",
+                task.name,
+                budget,
+                task.started_at
+            );
+            task.handle.abort();
+            aborted.push(task.name);
+        }
+        let report = ShutdownReport {
+            stopped: signalled.saturating_sub(aborted.len()),
+            aborted,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+        };
+        log::info!(
+            "Stopped {} background task(s) in {} ms, aborted {}",
+            report.stopped,
+            report.elapsed_ms,
+            report.aborted.len()
+        );
+        report
+    }
+}
+
+/// The long-lived background tasks running now, with how long each has
+/// been running, for the debug overlay.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn list_background_tasks(
+    shutdown: tauri::State<'_, ShutdownController>,
+) -> Vec<BackgroundTask> {
+    shutdown.tasks()
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![list_background_tasks]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Log = Arc<Mutex<Vec<&'static str>>>;
+
+    /// A task waiting for its signal, then taking `cleanup` to stop; it's
+    /// logged once stopped.
+    fn fake_task(
+        shutdown: &ShutdownController,
+        name: &'static str,
+        cleanup: Duration,
+        log: &Log,
+    ) -> TaskId {
+        let log = log.clone();
+        shutdown.spawn(name, move |mut signal| async move {
+            signal.triggered().await;
+            tokio::time::sleep(cleanup).await;
+            log.lock().unwrap().push(name);
+        })
+    }
+
+    /// Until `shutdown` has `count` tasks left.
+    async fn wait_for_tasks(shutdown: &ShutdownController, count: usize) {
+        while shutdown.tasks().len() != count {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn tasks_stop_in_the_order_they_finish_within_the_budget() {
+        let shutdown = ShutdownController::default();
+        let log = Log::default();
+        fake_task(&shutdown, "bridge", Duration::from_millis(60), &log);
+        fake_task(&shutdown, "watchdog", Duration::ZERO, &log);
+        fake_task(&shutdown, "refresh", Duration::from_millis(30), &log);
+        let names: Vec<String> = shutdown.tasks().into_iter().map(|t| t.name).collect();
+        assert_eq!(names.len(), 3);
+        assert!(["bridge", "watchdog", "refresh"]
+            .iter()
+            .all(|n| names.iter().any(|m| m == n)));
+
+        let report = shutdown.shutdown(TASK_BUDGET).await;
+        assert_eq!(report.stopped, 3);
+        assert!(report.aborted.is_empty());
+        // Signalled together, so it takes as long as the slowest, not the sum
+        assert!(report.elapsed_ms >= 60, "{}", report.elapsed_ms);
+        assert!(report.elapsed_ms < 1000, "{}", report.elapsed_ms);
+        assert_eq!(*log.lock().unwrap(), ["watchdog", "refresh", "bridge"]);
+        assert!(shutdown.tasks().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tasks_still_running_at_the_end_of_the_budget_are_aborted() {
+        let shutdown = ShutdownController::default();
+        let log = Log::default();
+        fake_task(&shutdown, "quick", Duration::ZERO, &log);
+        fake_task(&shutdown, "stuck", Duration::from_secs(3600), &log);
+        let report = shutdown.shutdown(Duration::from_millis(50)).await;
+        assert_eq!(report.stopped, 1);
+        assert_eq!(report.aborted, ["stuck"]);
+        assert!(report.elapsed_ms >= 50, "{}", report.elapsed_ms);
+        assert_eq!(*log.lock().unwrap(), ["quick"]);
+        assert!(shutdown.tasks().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stopping_one_task_leaves_the_others_running() {
+        let shutdown = ShutdownController::default();
+        let log = Log::default();
+        let watch = fake_task(&shutdown, "watch folder", Duration::ZERO, &log);
+        fake_task(&shutdown, "bridge", Duration::ZERO, &log);
+        shutdown.stop(watch);
+        wait_for_tasks(&shutdown, 1).await;
+        assert_eq!(shutdown.tasks()[0].name, "bridge");
+        assert_eq!(*log.lock().unwrap(), ["watch folder"]);
+    }
+
+    #[tokio::test]
+    async fn a_task_spawned_during_shutdown_is_signalled_at_once() {
+        let shutdown = ShutdownController::default();
+        assert_eq!(shutdown.shutdown(TASK_BUDGET).await.stopped, 0);
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        shutdown.spawn("late", move |signal| async move {
+            let _ = tx.send(signal.is_triggered());
+        });
+        assert!(rx.await.unwrap());
+        wait_for_tasks(&shutdown, 0).await;
+    }
+}
@@ -0,0 +1,348 @@
+// The backend's environment, built from scratch rather than inherited, so
+// a conda or virtualenv activation, a stray PYTHONPATH or an odd PATH in the
+// user's shell can't change which packages the backend imports
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Variables of the OS and its runtime libraries the backend still needs:
+/// home and temp directories, locale and time zone.
+const UNIX_INHERITED: &[&str] = &[
+    "HOME", "USER", "LOGNAME", "LANG", "LC_ALL", "LC_CTYPE", "TMPDIR", "TZ",
+];
+
+/// Without SYSTEMROOT Python can't even open a socket on Windows.
+const WINDOWS_INHERITED: &[&str] = &[
+    "SYSTEMROOT",
+    "SYSTEMDRIVE",
+    "WINDIR",
+    "COMSPEC",
+    "PATHEXT",
+    "TEMP",
+    "TMP",
+    "USERPROFILE",
+    "USERNAME",
+    "APPDATA",
+    "LOCALAPPDATA",
+    "HOMEDRIVE",
+    "HOMEPATH",
+    "NUMBER_OF_PROCESSORS",
+    "PROCESSOR_ARCHITECTURE",
+];
+
+/// Configuration the backend reads from the environment (apps/sidecar/
+/// config.py), kept for users who set their API keys there rather than in
+/// the backend's .env file.
+const BACKEND_INHERITED: &[&str] = &[
+    "GEMINI_API_KEY",
+    "GEMINI_MODEL",
+    "GOOGLE_APPLICATION_CREDENTIALS",
+    "GOOGLE_CUSTOM_SEARCH_API_KEY",
+    "GOOGLE_CUSTOM_SEARCH_ENGINE_ID",
+    "PEXELS_API_KEY",
+    "RUNWARE_API_KEY",
+    "YOUTUBE_DATA_API_KEY",
+];
+
+/// Variables `backend_extra_env` can't set: they make the dynamic loader or
+/// the interpreter run code other than the backend's, e.g. a preloaded
+/// library or a startup script, or change where it's looked up.
+const EXTRA_DENIED: &[&str] = &[
+    "PATH",
+    "LD_PRELOAD",
+    "LD_LIBRARY_PATH",
+    "LD_AUDIT",
+    "GCONV_PATH",
+    "PYTHONPATH",
+    "PYTHONHOME",
+    "PYTHONSTARTUP",
+    "PYTHONUSERBASE",
+    "PYTHONPLATLIBDIR",
+    "PYTHONEXECUTABLE",
+    "PYTHONBREAKPOINT",
+    "PYTHONWARNINGS",
+    "PYTHONINSPECT",
+    "BASH_ENV",
+    "ENV",
+    "COMSPEC",
+];
+
+/// Prefixes of variables denied like `EXTRA_DENIED`, whatever follows.
+const EXTRA_DENIED_PREFIXES: &[&str] = &["DYLD_", "LD_"];
+
+/// Parts of a variable name whose value is logged as `<redacted>`.
+const SECRET_MARKERS: &[&str] = &["KEY", "TOKEN", "SECRET", "PASSWORD", "CREDENTIAL"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Windows,
+    MacOs,
+    /// Linux and the other Unixes.
+    Unix,
+}
+
+impl Platform {
+    pub fn current() -> Self {
+        if cfg!(windows) {
+            Platform::Windows
+        } else if cfg!(target_os = "macos") {
+            Platform::MacOs
+        } else {
+            Platform::Unix
+        }
+    }
+
+    fn path_separator(self) -> char {
+        match self {
+            Platform::Windows => ';',
+            Platform::MacOs | Platform::Unix => ':',
+        }
+    }
+
+    fn inherited(self) -> &'static [&'static str] {
+        match self {
+            Platform::Windows => WINDOWS_INHERITED,
+            Platform::MacOs | Platform::Unix => UNIX_INHERITED,
+        }
+    }
+
+    /// Where the tools the backend shells out to (ffmpeg, ffprobe) are
+    /// installed by the OS or its usual package managers.
+    fn system_path(self, lookup: &dyn Fn(&str) -> Option<String>) -> Vec<PathBuf> {
+        match self {
+            Platform::Windows => {
+                let root = lookup("SYSTEMROOT").unwrap_or_else(|| r"C:\Windows".to_string());
+                let root = PathBuf::from(root);
+                vec![
+                    root.join("System32"),
+                    root.clone(),
+                    root.join("System32").join("Wbem"),
+                ]
+            }
+            Platform::MacOs => [
+                "/opt/homebrew/bin",
+                "/usr/local/bin",
+                "/usr/bin",
+                "/bin",
+                "/usr/sbin",
+                "/sbin",
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect(),
+            Platform::Unix => ["/usr/local/bin", "/usr/bin", "/bin", "/usr/sbin", "/sbin"]
+                .iter()
+                .map(PathBuf::from)
+                .collect(),
+        }
+    }
+}
+
+/// The directory of `python`, found on the inherited PATH when it is a bare
+/// command name as in the development fallback.
+fn runtime_dir(platform: Platform, python: &Path, inherited_path: Option<&str>) -> Option<PathBuf> {
+    if python.components().count() > 1 {
+        return python.parent().map(Path::to_path_buf);
+    }
+    let name = match platform {
+        Platform::Windows if python.extension().is_none() => python.with_extension("exe"),
+        _ => python.to_path_buf(),
+    };
+    inherited_path?
+        .split(platform.path_separator())
+        .map(PathBuf::from)
+        .find(|dir| dir.join(&name).is_file())
+}
+
+/// The PATH handed to the backend: the directory of its interpreter, then
+/// the OS's.
+pub fn controlled_path(
+    platform: Platform,
+    python: &Path,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> String {
+    let mut dirs = Vec::new();
+    if let Some(dir) = runtime_dir(platform, python, lookup("PATH").as_deref()) {
+        dirs.push(dir);
+    }
+    for dir in platform.system_path(lookup) {
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    dirs.iter()
+        .map(|dir| dir.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(&platform.path_separator().to_string())
+}
+
+/// Why `name` can't be set through `backend_extra_env`, if it can't. Names
+/// are compared uppercased, as Windows does.
+pub fn check_extra_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.contains(['=', '\0']) {
+        return Err(format!("{:?} is not a variable name", name));
+    }
+    let upper = name.to_ascii_uppercase();
+    if EXTRA_DENIED.contains(&upper.as_str())
+        || EXTRA_DENIED_PREFIXES
+            .iter()
+            .any(|prefix| upper.starts_with(prefix))
+    {
+        return Err(format!(
+            "{} changes what the backend's loader or interpreter runs",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// The whole environment of a newly spawned backend. `lookup` reads the
+/// app's own environment; `app` holds the `SCLIP_*` variables and thread
+/// limits set by the app, which `extra` from settings can't override; nor
+/// can it set what `check_extra_name` refuses.
+pub fn build(
+    platform: Platform,
+    python: &Path,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    app: &[(&str, String)],
+    extra: &BTreeMap<String, String>,
+) -> BTreeMap<String, String> {
+    let mut vars = BTreeMap::new();
+    for name in platform.inherited().iter().chain(BACKEND_INHERITED) {
+        if let Some(value) = lookup(name) {
+            vars.insert(name.to_string(), value);
+        }
+    }
+    vars.insert(
+        "PATH".to_string(),
+        controlled_path(platform, python, lookup),
+    );
+    // Unbuffered, so backend output reaches the log as it happens
+    vars.insert("PYTHONUNBUFFERED".to_string(), "1".to_string());
+    for (name, value) in extra {
+        if let Err(reason) = check_extra_name(name) {
+            log::warn!("Ignoring backend environment variable: {}", reason);
+        } else if app.iter().any(|(app_name, _)| app_name == name) {
+            log::warn!(
+                "Backend environment variable {} is set by the app, ignoring the settings value",
+                name
+            );
+        } else {
+            vars.insert(name.clone(), value.clone());
+        }
+    }
+    for (name, value) in app {
+        vars.insert(name.to_string(), value.clone());
+    }
+    vars
+}
+
+fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    SECRET_MARKERS.iter().any(|marker| name.contains(marker))
+}
+
+/// `vars` for the log, with the values of secrets left out.
+pub fn redacted(vars: &BTreeMap<String, String>) -> String {
+    vars.iter()
+        .map(|(name, value)| {
+            if is_secret(name) {
+                format!("{}=<redacted>", name)
+            } else {
+                format!("{}={}", name, value)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/user".to_string()),
+            "PATH" => Some("/usr/bin".to_string()),
+            "PYTHONPATH" => Some("/home/user/lib".to_string()),
+            _ => None,
+        }
+    }
+
+    fn extra(vars: &[(&str, &str)]) -> BTreeMap<String, String> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn extra_env_can_not_set_loader_or_interpreter_variables() {
+        let extra = extra(&[
+            ("LD_PRELOAD", "/tmp/hook.so"),
+            ("ld_library_path", "/tmp"),
+            ("DYLD_INSERT_LIBRARIES", "/tmp/hook.dylib"),
+            ("PYTHONPATH", "/tmp"),
+            ("PYTHONSTARTUP", "/tmp/startup.py"),
+            ("PATH", "/tmp"),
+            ("HTTPS_PROXY", "http://proxy:8080"),
+        ]);
+        let vars = build(
+            Platform::Unix,
+            Path::new("/opt/sclip/python/bin/python3"),
+            &lookup,
+            &[],
+            &extra,
+        );
+
+        for name in [
+            "LD_PRELOAD",
+            "ld_library_path",
+            "DYLD_INSERT_LIBRARIES",
+            "PYTHONPATH",
+            "PYTHONSTARTUP",
+        ] {
+            assert!(!vars.contains_key(name), "{} was set", name);
+        }
+        assert_eq!(
+            vars["PATH"],
+            "/opt/sclip/python/bin:/usr/local/bin:/usr/bin:/bin:/usr/sbin:/sbin"
+        );
+        assert_eq!(vars["HTTPS_PROXY"], "http://proxy:8080");
+        assert_eq!(vars["HOME"], "/home/user");
+    }
+
+    #[test]
+    fn app_variables_win_over_extra_env() {
+        let vars = build(
+            Platform::Unix,
+            Path::new("/opt/sclip/python/bin/python3"),
+            &lookup,
+            &[("SCLIP_PORT", "8001".to_string())],
+            &extra(&[("SCLIP_PORT", "9000"), ("", "x"), ("A=B", "x")]),
+        );
+        assert_eq!(vars["SCLIP_PORT"], "8001");
+        assert!(!vars.contains_key("") && !vars.contains_key("A=B"));
+    }
+
+    #[test]
+    fn check_extra_name_refuses_denied_names() {
+        for name in [
+            "LD_PRELOAD",
+            "LD_AUDIT",
+            "DYLD_LIBRARY_PATH",
+            "PythonPath",
+            "PATH",
+            "Path",
+        ] {
+            assert!(check_extra_name(name).is_err(), "{} was allowed", name);
+        }
+        for name in ["HTTPS_PROXY", "PYTHONIOENCODING", "OMP_NUM_THREADS"] {
+            assert!(check_extra_name(name).is_ok(), "{} was refused", name);
+        }
+    }
+
+    #[test]
+    fn secrets_are_redacted() {
+        let vars = extra(&[("GEMINI_API_KEY", "abc"), ("LANG", "C")]);
+        assert_eq!(redacted(&vars), "GEMINI_API_KEY=<redacted> LANG=C");
+    }
+}
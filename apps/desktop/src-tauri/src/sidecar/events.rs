@@ -0,0 +1,315 @@
+// Bridge from the backend's event stream (GET /api/events, Server-Sent Events)
+// to Tauri events, so windows don't each hold a fragile WebSocket
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::{base_url, SidecarManager, TOKEN_HEADER};
+
+/// Events kept for `get_last_backend_events`.
+const HISTORY_SIZE: usize = 500;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// The backend sends a keepalive every 15 s; three missed ones means the
+/// connection is dead even if the socket hasn't noticed.
+const READ_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// One backend message as re-emitted to the frontend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendEvent {
+    pub seq: u64,
+    pub boot_id: String,
+    pub session_id: Option<String>,
+    /// The backend message's own `type`, e.g. "workflow_progress".
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub payload: serde_json::Value,
+}
+
+impl BackendEvent {
+    /// Tauri event name this is re-emitted under.
+    pub fn channel(&self) -> &'static str {
+        let kind = self.kind.as_str();
+        if kind == "error" || kind.ends_with("_error") {
+            "backend-error"
+        } else if matches!(
+            kind,
+            "asset_ready"
+                | "voiceover_update"
+                | "video_preview_update"
+                | "video_preview_final_update"
+                | "audio_only_video"
+        ) {
+            "backend-asset-ready"
+        } else {
+            "backend-progress"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BridgeState {
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+/// Payload of the `backend-events-connection` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct BridgeStatus {
+    pub state: BridgeState,
+    /// Consecutive failed attempts since the last successful connection.
+    pub attempt: u32,
+    pub retry_in_ms: Option<u64>,
+    pub last_error: Option<String>,
+    pub changed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+struct SseFrame {
+    event: Option<String>,
+    data: String,
+}
+
+/// Incremental text/event-stream parser; feed it raw chunks, get back
+/// complete frames. Comment lines (keepalives) are dropped.
+#[derive(Debug, Default)]
+struct SseParser {
+    buffer: String,
+    frame: SseFrame,
+}
+
+impl SseParser {
+    fn push(&mut self, chunk: &[u8]) -> Vec<SseFrame> {
+        self.buffer.push_str(&String::from_utf8_lossy(chunk));
+        let mut frames = Vec::new();
+        while let Some(end) = self.buffer.find('\n') {
+            let line: String = self.buffer.drain(..=end).collect();
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                if !self.frame.data.is_empty() {
+                    frames.push(std::mem::take(&mut self.frame));
+                }
+                self.frame = SseFrame::default();
+                continue;
+            }
+            if line.starts_with(':') {
+                continue;
+            }
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => self.frame.event = Some(value.to_string()),
+                "data" => {
+                    if !self.frame.data.is_empty() {
+                        self.frame.data.push('\n');
+                    }
+                    self.frame.data.push_str(value);
+                }
+                _ => {}
+            }
+        }
+        frames
+    }
+}
+
+#[derive(Deserialize)]
+struct Hello {
+    boot_id: String,
+}
+
+struct Cursor {
+    boot_id: Option<String>,
+    last_seq: u64,
+}
+
+type Emit = Box<dyn Fn(&'static str, serde_json::Value) + Send + Sync>;
+
+/// Managed state holding the recent event history and replay cursor.
+pub struct EventBridge {
+    cursor: Mutex<Cursor>,
+    history: Mutex<VecDeque<BackendEvent>>,
+    status: Mutex<BridgeStatus>,
+    emit: Emit,
+}
+
+impl EventBridge {
+    pub fn new(emit: impl Fn(&'static str, serde_json::Value) + Send + Sync + 'static) -> Self {
+        Self {
+            cursor: Mutex::new(Cursor {
+                boot_id: None,
+                last_seq: 0,
+            }),
+            history: Mutex::new(VecDeque::with_capacity(HISTORY_SIZE)),
+            status: Mutex::new(BridgeStatus {
+                state: BridgeState::Disconnected,
+                attempt: 0,
+                retry_in_ms: None,
+                last_error: None,
+                changed_at: Utc::now(),
+            }),
+            emit: Box::new(emit),
+        }
+    }
+
+    pub fn last_events(&self, n: usize) -> Vec<BackendEvent> {
+        let history = self.history.lock().unwrap();
+        history
+            .iter()
+            .skip(history.len().saturating_sub(n))
+            .cloned()
+            .collect()
+    }
+
+    pub fn status(&self) -> BridgeStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    fn set_status(
+        &self,
+        state: BridgeState,
+        attempt: u32,
+        retry_in: Option<Duration>,
+        error: Option<String>,
+    ) {
+        let status = BridgeStatus {
+            state,
+            attempt,
+            retry_in_ms: retry_in.map(|d| d.as_millis() as u64),
+            last_error: error,
+            changed_at: Utc::now(),
+        };
+        *self.status.lock().unwrap() = status.clone();
+        if let Ok(payload) = serde_json::to_value(status) {
+            (self.emit)("backend-events-connection", payload);
+        }
+    }
+
+    fn handle_frame(&self, frame: SseFrame) {
+        match frame.event.as_deref() {
+            Some("hello") => {
+                if let Ok(hello) = serde_json::from_str::<Hello>(&frame.data) {
+                    let mut cursor = self.cursor.lock().unwrap();
+                    if cursor.boot_id.as_deref() != Some(hello.boot_id.as_str()) {
+                        // Backend restarted: its sequence numbers start over
+                        cursor.boot_id = Some(hello.boot_id);
+                        cursor.last_seq = 0;
+                    }
+                }
+            }
+            Some("message") | None => {
+                let event: BackendEvent = match serde_json::from_str(&frame.data) {
+                    Ok(event) => event,
+                    Err(e) => {
+                        log::warn!("Ignoring malformed backend event: {}", e);
+                        return;
+                    }
+                };
+                {
+                    let mut cursor = self.cursor.lock().unwrap();
+                    if cursor.boot_id.as_deref() == Some(event.boot_id.as_str())
+                        && event.seq <= cursor.last_seq
+                    {
+                        // Replayed after a reconnect; already delivered
+                        return;
+                    }
+                    cursor.boot_id = Some(event.boot_id.clone());
+                    cursor.last_seq = event.seq;
+                }
+                {
+                    let mut history = self.history.lock().unwrap();
+                    if history.len() == HISTORY_SIZE {
+                        history.pop_front();
+                    }
+                    history.push_back(event.clone());
+                }
+                let channel = event.channel();
+                if let Ok(payload) = serde_json::to_value(event) {
+                    (self.emit)(channel, payload);
+                }
+            }
+            Some(_) => {}
+        }
+    }
+
+    /// Streams until the connection fails; the error says why.
+    async fn connect_once(&self, sidecar: &SidecarManager) -> Result<Infallible, String> {
+        let (since_seq, boot_id) = {
+            let cursor = self.cursor.lock().unwrap();
+            (cursor.last_seq, cursor.boot_id.clone().unwrap_or_default())
+        };
+        let mut response = sidecar
+            .http()
+            .get(format!("{}/api/events", base_url(sidecar.port())))
+            .query(&[("since_seq", since_seq.to_string()), ("boot_id", boot_id)])
+            .header(TOKEN_HEADER, sidecar.token())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status()));
+        }
+
+        self.set_status(BridgeState::Connected, 0, None, None);
+        let mut parser = SseParser::default();
+        loop {
+            let chunk = tokio::time::timeout(READ_TIMEOUT, response.chunk())
+                .await
+                .map_err(|_| "no data or keepalive within 45 s".to_string())?
+                .map_err(|e| e.to_string())?;
+            let Some(chunk) = chunk else {
+                return Err("stream closed by backend".to_string());
+            };
+            for frame in parser.push(&chunk) {
+                self.handle_frame(frame);
+            }
+        }
+    }
+
+    /// Follows the backend's event stream forever, reconnecting with
+    /// exponential backoff that resets once a connection succeeds.
+    pub async fn run(&self, sidecar: &SidecarManager) {
+        let mut attempt: u32 = 0;
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            self.set_status(BridgeState::Connecting, attempt, None, None);
+            let error = match self.connect_once(sidecar).await {
+                Ok(never) => match never {},
+                Err(e) => e,
+            };
+            if self.status().state == BridgeState::Connected {
+                log::warn!("Backend event stream dropped: {}", error);
+                attempt = 0;
+                backoff = INITIAL_BACKOFF;
+            }
+            attempt += 1;
+            self.set_status(
+                BridgeState::Disconnected,
+                attempt,
+                Some(backoff),
+                Some(error),
+            );
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_last_backend_events(
+    bridge: tauri::State<'_, EventBridge>,
+    n: Option<usize>,
+) -> Vec<BackendEvent> {
+    bridge.last_events(n.unwrap_or(50).min(HISTORY_SIZE))
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_backend_events_status(bridge: tauri::State<'_, EventBridge>) -> BridgeStatus {
+    bridge.status()
+}
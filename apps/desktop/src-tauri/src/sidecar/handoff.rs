@@ -0,0 +1,205 @@
+// Hands audio the app synthesized to the backend, so the orchestrator uses
+// these files instead of synthesizing its own. A registration carries the
+// file's path, hash and duration; the backend answers with the id it keeps
+// the asset under
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tauri::Manager;
+
+use super::proxy::BackendResponse;
+use super::SidecarManager;
+use crate::audio::{mp3, stretch};
+use crate::cache::is_cache_key;
+use crate::error::AppError;
+use crate::paths::roots::{AllowedRoots, Read};
+
+const REGISTER_TIMEOUT_MS: u64 = 15_000;
+
+/// What the backend is sent.
+#[derive(Debug, Clone, Serialize)]
+struct AudioRegistration<'a> {
+    segment_id: &'a str,
+    path: &'a Path,
+    /// Set when the audio is a TTS cache entry.
+    cache_key: Option<&'a str>,
+    sha256: String,
+    bytes: usize,
+    mime_type: &'static str,
+    duration_ms: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegisteredAudio {
+    pub asset_id: String,
+    pub project_id: String,
+    pub segment_id: String,
+    pub sha256: String,
+    pub duration_ms: Option<f64>,
+    pub registered_at: DateTime<Utc>,
+}
+
+fn invalid(message: impl Into<String>) -> AppError {
+    AppError::InvalidBackendRequest {
+        message: message.into(),
+    }
+}
+
+/// Project and segment ids go into the route and the body as they are, so
+/// only plain ids are accepted.
+pub fn check_id(what: &str, id: &str) -> Result<(), AppError> {
+    let plain = !id.is_empty()
+        && id.len() <= 128
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        && id != "."
+        && id != "..";
+    if plain {
+        Ok(())
+    } else {
+        Err(invalid(format!(
+            "{} must be letters, digits, '-', '_' or '.', got {:?}",
+            what, id
+        )))
+    }
+}
+
+/// Registers `audio`, stored at `path`, as the audio of `segment_id`.
+pub async fn register(
+    sidecar: &SidecarManager,
+    project_id: &str,
+    segment_id: &str,
+    path: &Path,
+    cache_key: Option<&str>,
+    audio: &[u8],
+) -> Result<RegisteredAudio, AppError> {
+    check_id("project_id", project_id)?;
+    check_id("segment_id", segment_id)?;
+    let (mime_type, duration_ms) = if mp3::is_plausible_mp3(audio) {
+        ("audio/mpeg", mp3::duration_ms(audio))
+    } else if audio.starts_with(b"RIFF") {
+        ("audio/wav", stretch::wav_duration_ms(audio))
+    } else {
+        return Err(AppError::InvalidAudioRequest {
+            message: format!("{} is not MP3 or WAV audio", path.display()),
+        });
+    };
+    let sha256 = format!("{:x}", Sha256::digest(audio));
+    let registration = AudioRegistration {
+        segment_id,
+        path,
+        cache_key,
+        sha256: sha256.clone(),
+        bytes: audio.len(),
+        mime_type,
+        duration_ms,
+    };
+    let body = serde_json::to_value(&registration).map_err(|e| invalid(e.to_string()))?;
+    let response = sidecar
+        .request(
+            "POST",
+            &format!("/api/projects/{}/assets", project_id),
+            Some(body),
+            Some(REGISTER_TIMEOUT_MS),
+        )
+        .await?;
+    let (status, body) = match response {
+        BackendResponse::Json { status, body } => (status, body),
+        BackendResponse::File { status, .. } => (status, serde_json::Value::Null),
+    };
+    let asset_id = body
+        .get("asset_id")
+        .or_else(|| body.get("id"))
+        .and_then(|id| match id {
+            serde_json::Value::String(id) => Some(id.clone()),
+            serde_json::Value::Number(id) => Some(id.to_string()),
+            _ => None,
+        })
+        .ok_or_else(|| AppError::BackendError {
+            status,
+            message: "registration response has no asset_id".to_string(),
+        })?;
+    log::info!(
+        "Registered {} for segment {} of project {} as asset {}",
+        path.display(),
+        segment_id,
+        project_id,
+        asset_id
+    );
+    Ok(RegisteredAudio {
+        asset_id,
+        project_id: project_id.to_string(),
+        segment_id: segment_id.to_string(),
+        sha256,
+        duration_ms,
+        registered_at: Utc::now(),
+    })
+}
+
+/// Validates the audio at `path_or_cache_key`, a file or a TTS cache key,
+/// and registers it with the backend as `segment_id` of `project_id`;
+/// returns the backend's asset id with what was sent.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn register_audio_with_backend(
+    app_handle: tauri::AppHandle,
+    sidecar: tauri::State<'_, SidecarManager>,
+    roots: tauri::State<'_, AllowedRoots>,
+    project_id: String,
+    segment_id: String,
+    path_or_cache_key: String,
+) -> Result<RegisteredAudio, AppError> {
+    let (path, cache_key): (PathBuf, Option<&str>) = if is_cache_key(&path_or_cache_key) {
+        let cache = crate::cache::tts_cache(&app_handle)?;
+        if !cache.contains(&path_or_cache_key) {
+            return Err(AppError::InvalidAudioRequest {
+                message: format!("no cached speech with key {}", path_or_cache_key),
+            });
+        }
+        (
+            cache.entry_path(&path_or_cache_key),
+            Some(path_or_cache_key.as_str()),
+        )
+    } else {
+        (PathBuf::from(&path_or_cache_key), None)
+    };
+    let allowed = roots.validate_path::<Read>(&path)?;
+    let audio = allowed.read()?;
+    register(
+        &sidecar,
+        &project_id,
+        &segment_id,
+        allowed.path(),
+        cache_key,
+        &audio,
+    )
+    .await
+}
+
+/// Registers `audio` for a batch segment; a failure is only logged and
+/// returned as the warning the manifest records.
+pub async fn register_segment(
+    app_handle: &tauri::AppHandle,
+    project_id: &str,
+    segment_id: &str,
+    path: &Path,
+    cache_key: &str,
+    audio: &[u8],
+) -> Result<RegisteredAudio, String> {
+    let sidecar = app_handle.state::<SidecarManager>();
+    register(
+        &sidecar,
+        project_id,
+        segment_id,
+        path,
+        Some(cache_key),
+        audio,
+    )
+    .await
+    .map_err(|e| {
+        log::warn!("Registering batch segment {} failed: {}", segment_id, e);
+        format!("not registered with the backend: {}", e)
+    })
+}
@@ -0,0 +1,417 @@
+// Download-and-install of a pinned backend archive for platforms where the
+// Python backend isn't bundled
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::io::AsyncWriteExt;
+
+use super::{SidecarLaunch, SidecarManager};
+use crate::cache::write_atomic;
+use crate::error::AppError;
+use crate::read_only::WriteAccess;
+
+/// Pinned at build time, e.g. by the release pipeline:
+/// SCLIP_BACKEND_VERSION, SCLIP_BACKEND_ARCHIVE_URL (a .tar.gz whose root is
+/// the backend directory, with a standalone interpreter under python/) and
+/// SCLIP_BACKEND_ARCHIVE_SHA256.
+const PINNED_VERSION: Option<&str> = option_env!("SCLIP_BACKEND_VERSION");
+const PINNED_URL: Option<&str> = option_env!("SCLIP_BACKEND_ARCHIVE_URL");
+const PINNED_SHA256: Option<&str> = option_env!("SCLIP_BACKEND_ARCHIVE_SHA256");
+
+const INSTALLED_FILE: &str = "installed.json";
+const PARTIAL_FILE: &str = "download.partial";
+/// Bytes between progress events.
+const PROGRESS_STEP: u64 = 512 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallState {
+    NotInstalled,
+    Downloading,
+    Verifying,
+    Unpacking,
+    Installed,
+    Failed,
+}
+
+/// Written to app data once an install completes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledBackend {
+    pub version: String,
+    pub sha256: String,
+    pub dir: PathBuf,
+    pub installed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackendInstallStatus {
+    pub state: InstallState,
+    pub pinned_version: Option<String>,
+    pub installed: Option<InstalledBackend>,
+    pub downloaded_bytes: u64,
+    pub total_bytes: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Payload of `backend-install-progress`.
+#[derive(Debug, Clone, Serialize)]
+pub struct InstallProgress {
+    pub state: InstallState,
+    pub downloaded_bytes: u64,
+    pub total_bytes: Option<u64>,
+}
+
+struct Pinned {
+    version: &'static str,
+    url: &'static str,
+    sha256: &'static str,
+}
+
+fn pinned() -> Option<Pinned> {
+    Some(Pinned {
+        version: PINNED_VERSION?,
+        url: PINNED_URL?,
+        sha256: PINNED_SHA256?,
+    })
+}
+
+/// Managed state tracking the install job.
+pub struct BackendInstaller {
+    root: PathBuf,
+    status: Mutex<BackendInstallStatus>,
+}
+
+impl BackendInstaller {
+    /// `root` is the directory installs live under, normally
+    /// `<app data>/backend`.
+    pub fn new(root: PathBuf) -> Self {
+        let installed = read_installed(&root);
+        let state = if installed.is_some() {
+            InstallState::Installed
+        } else {
+            InstallState::NotInstalled
+        };
+        Self {
+            root,
+            status: Mutex::new(BackendInstallStatus {
+                state,
+                pinned_version: PINNED_VERSION.map(String::from),
+                installed,
+                downloaded_bytes: 0,
+                total_bytes: None,
+                error: None,
+            }),
+        }
+    }
+
+    /// Directory of the installed backend, if any.
+    pub fn installed_dir(&self) -> Option<PathBuf> {
+        self.status
+            .lock()
+            .unwrap()
+            .installed
+            .as_ref()
+            .map(|i| i.dir.clone())
+    }
+
+    pub fn status(&self) -> BackendInstallStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    fn is_busy(&self) -> bool {
+        matches!(
+            self.status.lock().unwrap().state,
+            InstallState::Downloading | InstallState::Verifying | InstallState::Unpacking
+        )
+    }
+
+    fn update(
+        &self,
+        progress: &impl Fn(InstallProgress),
+        f: impl FnOnce(&mut BackendInstallStatus),
+    ) {
+        let report = {
+            let mut status = self.status.lock().unwrap();
+            f(&mut status);
+            InstallProgress {
+                state: status.state,
+                downloaded_bytes: status.downloaded_bytes,
+                total_bytes: status.total_bytes,
+            }
+        };
+        progress(report);
+    }
+
+    /// Downloads, verifies and unpacks the pinned archive, then points the
+    /// sidecar at it and starts it.
+    pub async fn install(
+        &self,
+        http: &reqwest::Client,
+        sidecar: &SidecarManager,
+        progress: impl Fn(InstallProgress),
+    ) -> Result<InstalledBackend, AppError> {
+        let result = self.run(http, sidecar, &progress).await;
+        if let Err(e) = &result {
+            log::error!("Backend install failed: {}", e);
+            self.update(&progress, |status| {
+                status.state = InstallState::Failed;
+                status.error = Some(e.to_string());
+            });
+        }
+        result
+    }
+
+    async fn run(
+        &self,
+        http: &reqwest::Client,
+        sidecar: &SidecarManager,
+        progress: &impl Fn(InstallProgress),
+    ) -> Result<InstalledBackend, AppError> {
+        let pinned = pinned().ok_or_else(|| AppError::BackendInstall {
+            message: "this build has no pinned backend archive".to_string(),
+        })?;
+        fs::create_dir_all(&self.root)?;
+        let partial = self.root.join(PARTIAL_FILE);
+
+        self.update(progress, |status| {
+            status.state = InstallState::Downloading;
+            status.error = None;
+        });
+        download(http, pinned.url, &partial, |downloaded, total| {
+            self.update(progress, |status| {
+                status.downloaded_bytes = downloaded;
+                status.total_bytes = total;
+            });
+        })
+        .await?;
+
+        self.update(progress, |status| status.state = InstallState::Verifying);
+        let actual = {
+            let partial = partial.clone();
+            tokio::task::spawn_blocking(move || sha256_file(&partial))
+                .await
+                .map_err(|e| AppError::BackendInstall {
+                    message: e.to_string(),
+                })??
+        };
+        if !actual.eq_ignore_ascii_case(pinned.sha256) {
+            let _ = fs::remove_file(&partial);
+            return Err(AppError::ChecksumMismatch {
+                expected: pinned.sha256.to_string(),
+                actual,
+            });
+        }
+
+        self.update(progress, |status| status.state = InstallState::Unpacking);
+        let dir = self.root.join(pinned.version);
+        {
+            let (partial, dir) = (partial.clone(), dir.clone());
+            tokio::task::spawn_blocking(move || unpack(&partial, &dir))
+                .await
+                .map_err(|e| AppError::BackendInstall {
+                    message: e.to_string(),
+                })??;
+        }
+        let _ = fs::remove_file(&partial);
+
+        let launch =
+            SidecarLaunch::from_dir(dir.clone()).ok_or_else(|| AppError::BackendInstall {
+                message: format!("archive has no app/main.py at {}", dir.display()),
+            })?;
+        let installed = InstalledBackend {
+            version: pinned.version.to_string(),
+            sha256: actual,
+            dir,
+            installed_at: Utc::now(),
+        };
+        let json = serde_json::to_vec_pretty(&installed).map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })?;
+        write_atomic(&self.root.join(INSTALLED_FILE), &json)?;
+        self.remove_other_versions(pinned.version);
+        log::info!(
+            "Installed backend {} into {}",
+            installed.version,
+            installed.dir.display()
+        );
+
+        self.update(progress, |status| {
+            status.state = InstallState::Installed;
+            status.installed = Some(installed.clone());
+        });
+        sidecar.set_launch(launch);
+        sidecar.restart().await?;
+        Ok(installed)
+    }
+
+    fn remove_other_versions(&self, keep: &str) {
+        let Ok(entries) = fs::read_dir(&self.root) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() && entry.file_name() != keep {
+                if let Err(e) = fs::remove_dir_all(&path) {
+                    log::warn!("Could not remove old backend {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+fn read_installed(root: &Path) -> Option<InstalledBackend> {
+    let contents = fs::read_to_string(root.join(INSTALLED_FILE)).ok()?;
+    let installed: InstalledBackend = serde_json::from_str(&contents).ok()?;
+    installed.dir.is_dir().then_some(installed)
+}
+
+/// Downloads `url` into `partial`, continuing from whatever an earlier
+/// attempt left behind when the server honours the range request and
+/// starting over when it doesn't.
+async fn download(
+    http: &reqwest::Client,
+    url: &str,
+    partial: &Path,
+    on_progress: impl Fn(u64, Option<u64>),
+) -> Result<(), AppError> {
+    let existing = fs::metadata(partial).map(|m| m.len()).unwrap_or(0);
+    let mut request = http.get(url);
+    if existing > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", existing));
+    }
+    let mut response = request.send().await.map_err(download_error)?;
+
+    let status = response.status();
+    let (mut file, mut downloaded) = if status == reqwest::StatusCode::PARTIAL_CONTENT {
+        log::info!("Resuming backend download at {} bytes", existing);
+        let file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(partial)
+            .await?;
+        (file, existing)
+    } else if status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+        // Already complete; the hash check decides whether it's usable
+        on_progress(existing, Some(existing));
+        return Ok(());
+    } else if status.is_success() {
+        (tokio::fs::File::create(partial).await?, 0)
+    } else {
+        return Err(AppError::BackendInstall {
+            message: format!("download failed with HTTP {}", status),
+        });
+    };
+    let total = response.content_length().map(|len| len + downloaded);
+
+    on_progress(downloaded, total);
+    let mut reported = downloaded;
+    while let Some(chunk) = response.chunk().await.map_err(download_error)? {
+        file.write_all(&chunk).await?;
+        downloaded += chunk.len() as u64;
+        if downloaded - reported >= PROGRESS_STEP {
+            on_progress(downloaded, total);
+            reported = downloaded;
+        }
+    }
+    file.flush().await?;
+    on_progress(downloaded, total);
+    Ok(())
+}
+
+fn download_error(e: reqwest::Error) -> AppError {
+    AppError::BackendInstall {
+        message: format!("download interrupted: {}", e),
+    }
+}
+
+pub fn sha256_file(path: &Path) -> Result<String, AppError> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Extracts into a sibling temp directory and renames it into place, so a
+/// failed unpack never leaves a half-populated install behind.
+fn unpack(archive: &Path, dir: &Path) -> Result<(), AppError> {
+    let mut staging = dir.as_os_str().to_owned();
+    staging.push(".unpacking");
+    let staging = PathBuf::from(staging);
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    fs::create_dir_all(&staging)?;
+
+    let file = fs::File::open(archive)?;
+    // `unpack` refuses entries that would escape the destination
+    let result = tar::Archive::new(flate2::read::GzDecoder::new(file)).unpack(&staging);
+    if let Err(e) = result {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(AppError::BackendInstall {
+            message: format!("could not unpack archive: {}", e),
+        });
+    }
+
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    fs::rename(&staging, dir)?;
+    Ok(())
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_backend_install_status(
+    installer: tauri::State<'_, BackendInstaller>,
+) -> BackendInstallStatus {
+    installer.status()
+}
+
+/// Starts the install in the background; progress arrives as
+/// `backend-install-progress` events and the final state via
+/// `get_backend_install_status`.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn install_backend(
+    app_handle: tauri::AppHandle,
+    installer: tauri::State<'_, BackendInstaller>,
+    access: tauri::State<'_, WriteAccess>,
+) -> Result<BackendInstallStatus, AppError> {
+    use tauri::{Emitter, Manager};
+
+    access.check()?;
+    if installer.is_busy() {
+        return Ok(installer.status());
+    }
+    installer.update(&|_| {}, |status| {
+        status.state = InstallState::Downloading;
+        status.error = None;
+    });
+
+    tauri::async_runtime::spawn(async move {
+        let installer = app_handle.state::<BackendInstaller>();
+        let sidecar = app_handle.state::<SidecarManager>();
+        let _ = installer
+            .install(sidecar.http(), &sidecar, |progress| {
+                if let Err(e) = app_handle.emit("backend-install-progress", progress) {
+                    log::warn!("Failed to emit backend-install-progress: {}", e);
+                }
+            })
+            .await;
+    });
+    Ok(installer.status())
+}
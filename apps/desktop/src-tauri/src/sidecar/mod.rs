@@ -0,0 +1,510 @@
+// Python backend (sidecar) process management and connectivity
+pub mod environment;
+pub mod events;
+pub mod handoff;
+pub mod install;
+pub mod priority;
+pub mod proxy;
+pub mod watchdog;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::process::{Child, Command};
+use tokio::sync::{watch, Mutex};
+
+use crate::error::AppError;
+use crate::settings::SettingsStore;
+use priority::BackendPerformance;
+
+/// Port the FastAPI backend listens on (see apps/sidecar/config.py).
+pub const DEFAULT_PORT: u16 = 8000;
+
+/// Header carrying the per-session secret on every proxied request.
+pub const TOKEN_HEADER: &str = "X-Sclip-Token";
+/// Environment variable the backend reads the secret from.
+pub const TOKEN_ENV: &str = "SCLIP_BACKEND_TOKEN";
+/// A websocket from the webview, which can't set headers, offers the secret
+/// as the subprotocol `sclip-token.<token>` instead.
+pub const TOKEN_SUBPROTOCOL_PREFIX: &str = "sclip-token.";
+
+pub fn base_url(port: u16) -> String {
+    format!("http://127.0.0.1:{}", port)
+}
+
+/// Returns true if the backend answers its health endpoint within a second.
+pub async fn is_healthy(port: u16) -> bool {
+    health_check(port, Duration::from_secs(1)).await
+}
+
+pub async fn health_check(port: u16, timeout: Duration) -> bool {
+    let client = reqwest::Client::new();
+    match client
+        .get(format!("{}/api/health", base_url(port)))
+        .timeout(timeout)
+        .send()
+        .await
+    {
+        Ok(response) => response.status().is_success(),
+        Err(_) => false,
+    }
+}
+
+/// Where the backend sources live and which interpreter runs them.
+#[derive(Debug, Clone)]
+pub struct SidecarLaunch {
+    pub dir: PathBuf,
+    pub python: PathBuf,
+}
+
+impl SidecarLaunch {
+    /// Finds the backend: SCLIP_SIDECAR_DIR, then the bundled resources, then
+    /// a copy installed by `install_backend`, then (in debug builds) the
+    /// apps/sidecar checkout next to this crate.
+    pub fn locate(resource_dir: Option<&Path>, installed_dir: Option<&Path>) -> Option<Self> {
+        let mut candidates: Vec<PathBuf> = Vec::new();
+        if let Some(dir) = env::var_os("SCLIP_SIDECAR_DIR") {
+            candidates.push(dir.into());
+        }
+        if let Some(resource_dir) = resource_dir {
+            candidates.push(resource_dir.join("sidecar"));
+        }
+        if let Some(installed_dir) = installed_dir {
+            candidates.push(installed_dir.to_path_buf());
+        }
+        if cfg!(debug_assertions) {
+            candidates.push(Path::new(env!("CARGO_MANIFEST_DIR")).join("../../sidecar"));
+        }
+        candidates.into_iter().find_map(Self::from_dir)
+    }
+
+    /// Uses `dir` if it contains the backend. The interpreter is
+    /// SCLIP_PYTHON, the backend's own venv, a standalone Python shipped
+    /// alongside it (as in installed archives), or python on PATH.
+    pub fn from_dir(dir: PathBuf) -> Option<Self> {
+        if !dir.join("app").join("main.py").is_file() {
+            return None;
+        }
+        let bundled = if cfg!(windows) {
+            [
+                dir.join(".venv").join("Scripts").join("python.exe"),
+                dir.join("python").join("python.exe"),
+            ]
+        } else {
+            [
+                dir.join(".venv").join("bin").join("python"),
+                dir.join("python").join("bin").join("python3"),
+            ]
+        };
+        let python = env::var_os("SCLIP_PYTHON")
+            .map(PathBuf::from)
+            .or_else(|| bundled.into_iter().find(|path| path.is_file()))
+            .unwrap_or_else(|| PathBuf::from(if cfg!(windows) { "python" } else { "python3" }));
+        Some(Self { dir, python })
+    }
+}
+
+fn generate_token() -> String {
+    format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+struct SessionToken {
+    value: String,
+    generation: u64,
+    issued_at: DateTime<Utc>,
+}
+
+impl SessionToken {
+    fn issue(generation: u64) -> Self {
+        Self {
+            value: generate_token(),
+            generation,
+            issued_at: Utc::now(),
+        }
+    }
+}
+
+/// Outcome of presenting the current token to the backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthProbe {
+    Accepted,
+    Rejected,
+    /// The backend answered but doesn't check tokens, i.e. it wasn't
+    /// spawned by this app.
+    NotEnforced,
+    Unreachable,
+}
+
+/// Diagnostics for the shared secret. Never includes the secret itself.
+#[derive(Debug, Clone, Serialize)]
+pub struct BackendAuthStatus {
+    pub spawned_by_app: bool,
+    /// Incremented on every spawn; 0 means no backend was spawned yet.
+    pub token_generation: u64,
+    pub token_issued_at: DateTime<Utc>,
+    /// First 8 hex digits of the token's SHA-256, for matching against
+    /// backend logs.
+    pub token_fingerprint: String,
+    pub probe: AuthProbe,
+}
+
+/// Managed state owning the backend process and the secret shared with it.
+///
+/// The secret is generated fresh for every spawn and handed to the process
+/// through its environment; it never leaves the Rust side otherwise, so the
+/// frontend can only reach the backend through `backend_request`.
+pub struct SidecarManager {
+    port: u16,
+    launch: RwLock<Option<SidecarLaunch>>,
+    token: RwLock<SessionToken>,
+    performance: RwLock<BackendPerformance>,
+    /// `backend_extra_env` from settings, added on the next spawn.
+    extra_env: RwLock<BTreeMap<String, String>>,
+    /// Where `backend_request` spools large bodies.
+    spool_dir: PathBuf,
+    child: Mutex<Option<Child>>,
+    stack_dump_file: RwLock<Option<PathBuf>>,
+    /// Bumped on every restart so in-flight proxied requests can give up.
+    restarts: watch::Sender<u64>,
+    restarting: AtomicBool,
+    http: reqwest::Client,
+}
+
+/// Where a `set_backend_performance` change took effect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PerformanceApplied {
+    /// Priority changed on the running process; thread limits still wait for
+    /// the next restart.
+    RunningProcess,
+    /// The platform refused the change (e.g. raising priority needs
+    /// privileges); it is used from the next restart.
+    NextRestart,
+    /// No backend spawned by the app is running; used when one starts.
+    NotRunning,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PerformanceReport {
+    pub mode: BackendPerformance,
+    pub applied: PerformanceApplied,
+    pub thread_limit: Option<usize>,
+    pub message: Option<String>,
+}
+
+impl SidecarManager {
+    pub fn new(
+        port: u16,
+        launch: Option<SidecarLaunch>,
+        performance: BackendPerformance,
+        spool_dir: PathBuf,
+    ) -> Self {
+        Self {
+            port,
+            launch: RwLock::new(launch),
+            token: RwLock::new(SessionToken::issue(0)),
+            performance: RwLock::new(performance),
+            extra_env: RwLock::new(BTreeMap::new()),
+            spool_dir,
+            child: Mutex::new(None),
+            stack_dump_file: RwLock::new(None),
+            restarts: watch::Sender::new(0),
+            restarting: AtomicBool::new(false),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub(crate) fn token(&self) -> String {
+        self.token.read().unwrap().value.clone()
+    }
+
+    pub(crate) fn spool_dir(&self) -> &Path {
+        &self.spool_dir
+    }
+
+    pub(crate) fn http(&self) -> &reqwest::Client {
+        &self.http
+    }
+
+    pub(crate) fn restart_signal(&self) -> watch::Receiver<u64> {
+        self.restarts.subscribe()
+    }
+
+    pub(crate) fn is_restarting(&self) -> bool {
+        self.restarting.load(Ordering::SeqCst)
+    }
+
+    /// Pid of the backend if this app spawned it and it is still running.
+    pub async fn child_pid(&self) -> Option<u32> {
+        self.child
+            .lock()
+            .await
+            .as_ref()
+            .and_then(|child| child.id())
+    }
+
+    /// Reaps a spawned backend that has exited, returning how it ended.
+    pub async fn exited(&self) -> Option<std::process::ExitStatus> {
+        let mut slot = self.child.lock().await;
+        let status = slot.as_mut()?.try_wait().ok()??;
+        *slot = None;
+        Some(status)
+    }
+
+    /// Points future spawns at a different backend, e.g. a fresh install.
+    pub fn set_launch(&self, launch: SidecarLaunch) {
+        *self.launch.write().unwrap() = Some(launch);
+    }
+
+    /// Variables added to the backend's environment from its next spawn.
+    pub fn set_extra_env(&self, extra_env: BTreeMap<String, String>) {
+        *self.extra_env.write().unwrap() = extra_env;
+    }
+
+    pub fn stack_dump_file(&self) -> Option<PathBuf> {
+        self.stack_dump_file.read().unwrap().clone()
+    }
+
+    /// Spawns the backend unless one is already answering on the port (e.g.
+    /// started by hand during development).
+    pub async fn start(&self) -> std::io::Result<()> {
+        if is_healthy(self.port).await {
+            log::info!(
+                "Backend already running on port {}, not spawning one",
+                self.port
+            );
+            return Ok(());
+        }
+        let Some(launch) = self.launch.read().unwrap().clone() else {
+            log::warn!(
+                "Backend sources not found; start it manually on port {}",
+                self.port
+            );
+            return Ok(());
+        };
+
+        // A fresh secret per spawn, so a restart also rotates it
+        let token = SessionToken::issue(self.token.read().unwrap().generation + 1);
+        let performance = *self.performance.read().unwrap();
+        let cpus = priority::available_cpus();
+        let stack_dump_file = env::temp_dir()
+            .join("sclip-backend")
+            .join(format!("stack-{}.txt", token.generation));
+        if let Some(parent) = stack_dump_file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut app_env = vec![
+            (TOKEN_ENV, token.value.clone()),
+            (
+                watchdog::STACK_DUMP_ENV,
+                stack_dump_file.to_string_lossy().into_owned(),
+            ),
+        ];
+        app_env.extend(performance.thread_env(cpus));
+        let vars = environment::build(
+            environment::Platform::current(),
+            &launch.python,
+            &|name| env::var(name).ok(),
+            &app_env,
+            &self.extra_env.read().unwrap(),
+        );
+        log::info!("Backend environment: {}", environment::redacted(&vars));
+        let mut command = Command::new(&launch.python);
+        command
+            .args(["-m", "app.main"])
+            .current_dir(&launch.dir)
+            .env_clear()
+            .envs(&vars)
+            .stdin(Stdio::null())
+            .kill_on_drop(true);
+        #[cfg(windows)]
+        command.creation_flags(priority::priority_class(performance));
+        let child = command.spawn()?;
+        log::info!(
+            "Spawned backend (pid {:?}) from {} with {}, token generation {}",
+            child.id(),
+            launch.dir.display(),
+            launch.python.display(),
+            token.generation
+        );
+
+        #[cfg(unix)]
+        let priority = match child
+            .id()
+            .map(|pid| priority::apply_priority(pid, performance))
+        {
+            Some(Err(e)) => format!("nice {} failed: {}", performance.nice(), e),
+            _ => format!("nice {}", performance.nice()),
+        };
+        #[cfg(not(unix))]
+        let priority = format!("{:?} priority class", performance);
+        log::info!(
+            "Backend performance {:?}: {}, thread limit {:?} of {} CPUs",
+            performance,
+            priority,
+            performance.thread_limit(cpus),
+            cpus
+        );
+
+        *self.token.write().unwrap() = token;
+        *self.stack_dump_file.write().unwrap() = Some(stack_dump_file);
+        *self.child.lock().await = Some(child);
+        Ok(())
+    }
+
+    /// Kills the backend if this process spawned it.
+    pub async fn stop(&self) {
+        if let Some(mut child) = self.child.lock().await.take() {
+            if let Err(e) = child.kill().await {
+                log::warn!("Failed to stop backend: {}", e);
+            }
+        }
+    }
+
+    /// Records the mode for future spawns and applies its priority to the
+    /// running backend where the platform allows.
+    pub async fn set_performance(&self, mode: BackendPerformance) -> PerformanceReport {
+        *self.performance.write().unwrap() = mode;
+        let pid = self
+            .child
+            .lock()
+            .await
+            .as_ref()
+            .and_then(|child| child.id());
+
+        let (applied, message) = match pid {
+            None => (PerformanceApplied::NotRunning, None),
+            Some(pid) => match priority::apply_priority(pid, mode) {
+                Ok(()) => (PerformanceApplied::RunningProcess, None),
+                Err(e) => (PerformanceApplied::NextRestart, Some(e.to_string())),
+            },
+        };
+        log::info!(
+            "Backend performance set to {:?}: {:?}{}",
+            mode,
+            applied,
+            message
+                .as_deref()
+                .map(|m| format!(" ({})", m))
+                .unwrap_or_default()
+        );
+        PerformanceReport {
+            mode,
+            applied,
+            thread_limit: mode.thread_limit(priority::available_cpus()),
+            message,
+        }
+    }
+
+    /// Kills and respawns the backend. Proxied requests in flight fail with
+    /// `BackendRestarting`, as do new ones until the spawn finished.
+    pub async fn restart(&self) -> std::io::Result<()> {
+        self.restarting.store(true, Ordering::SeqCst);
+        self.restarts.send_modify(|n| *n += 1);
+        self.stop().await;
+        let result = self.start().await;
+        self.restarting.store(false, Ordering::SeqCst);
+        result
+    }
+
+    pub async fn auth_status(&self) -> BackendAuthStatus {
+        let probe = match self
+            .request("GET", "/api/auth/status", None, Some(2000))
+            .await
+        {
+            Ok(proxy::BackendResponse::Json { body, .. }) => {
+                if body.get("enforced").and_then(|v| v.as_bool()) == Some(true) {
+                    AuthProbe::Accepted
+                } else {
+                    AuthProbe::NotEnforced
+                }
+            }
+            // Backends predating the status route, or something else on the port
+            Ok(proxy::BackendResponse::File { .. }) => AuthProbe::NotEnforced,
+            Err(AppError::BackendError { status: 401, .. }) => AuthProbe::Rejected,
+            Err(AppError::BackendError { status: 404, .. }) => AuthProbe::NotEnforced,
+            Err(_) => AuthProbe::Unreachable,
+        };
+
+        let spawned_by_app = self.child.lock().await.is_some();
+        let token = self.token.read().unwrap();
+        let digest = Sha256::digest(token.value.as_bytes());
+        BackendAuthStatus {
+            spawned_by_app,
+            token_generation: token.generation,
+            token_issued_at: token.issued_at,
+            token_fingerprint: digest[..4].iter().map(|b| format!("{:02x}", b)).collect(),
+            probe,
+        }
+    }
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn backend_auth_status(
+    sidecar: tauri::State<'_, SidecarManager>,
+) -> Result<BackendAuthStatus, AppError> {
+    Ok(sidecar.auth_status().await)
+}
+
+/// The subprotocol the webview offers when opening a backend websocket, so
+/// the backend accepts it; changes whenever the backend restarts.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn backend_stream_protocol(sidecar: tauri::State<'_, SidecarManager>) -> String {
+    format!("{}{}", TOKEN_SUBPROTOCOL_PREFIX, sidecar.token())
+}
+
+/// Restarts the backend this app spawned, rotating the shared secret.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn restart_backend(
+    sidecar: tauri::State<'_, SidecarManager>,
+) -> Result<BackendAuthStatus, AppError> {
+    sidecar.restart().await?;
+    Ok(sidecar.auth_status().await)
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn set_backend_performance(
+    store: tauri::State<'_, SettingsStore>,
+    sidecar: tauri::State<'_, SidecarManager>,
+    mode: BackendPerformance,
+) -> Result<PerformanceReport, AppError> {
+    store.update(|settings| settings.backend_performance = mode)?;
+    Ok(sidecar.set_performance(mode).await)
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![
+        proxy::backend_request,
+        proxy::take_backend_file,
+        backend_auth_status,
+        backend_stream_protocol,
+        restart_backend,
+        set_backend_performance,
+        install::install_backend,
+        install::get_backend_install_status,
+        events::get_last_backend_events,
+        events::get_backend_events_status,
+        handoff::register_audio_with_backend,
+    ]
+}
@@ -0,0 +1,130 @@
+// CPU priority and thread limits for the backend process
+use serde::{Deserialize, Serialize};
+
+/// How much of the machine the backend may use while rendering.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendPerformance {
+    /// Lowest priority and a quarter of the cores; the UI always wins.
+    Low,
+    /// Below-normal priority, one core left free for the UI.
+    #[default]
+    Balanced,
+    /// Normal priority, no thread limit.
+    Max,
+}
+
+/// Thread-count variables honoured by the native libraries the backend uses
+/// (numpy/BLAS, OpenCV's OpenMP builds, numexpr).
+const THREAD_ENV_VARS: [&str; 4] = [
+    "OMP_NUM_THREADS",
+    "OPENBLAS_NUM_THREADS",
+    "MKL_NUM_THREADS",
+    "NUMEXPR_NUM_THREADS",
+];
+
+impl BackendPerformance {
+    /// Unix nice value. Raising it is always allowed; lowering it again on a
+    /// running process usually needs privileges.
+    pub fn nice(self) -> i32 {
+        match self {
+            BackendPerformance::Low => 15,
+            BackendPerformance::Balanced => 5,
+            BackendPerformance::Max => 0,
+        }
+    }
+
+    pub fn thread_limit(self, cpus: usize) -> Option<usize> {
+        match self {
+            BackendPerformance::Low => Some((cpus / 4).max(1)),
+            BackendPerformance::Balanced => Some(cpus.saturating_sub(1).max(1)),
+            BackendPerformance::Max => None,
+        }
+    }
+
+    /// Environment for a newly spawned backend. Thread pools are sized at
+    /// import time, so these only take effect on the next spawn.
+    pub fn thread_env(self, cpus: usize) -> Vec<(&'static str, String)> {
+        match self.thread_limit(cpus) {
+            Some(limit) => THREAD_ENV_VARS
+                .iter()
+                .map(|name| (*name, limit.to_string()))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+pub fn available_cpus() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Sets the scheduling priority of a running process. On Linux niceness is
+/// per thread, so every thread currently in the process is adjusted; threads
+/// and child processes created later inherit from whichever thread spawns
+/// them.
+#[cfg(unix)]
+pub fn apply_priority(pid: u32, mode: BackendPerformance) -> std::io::Result<()> {
+    #[cfg(target_os = "linux")]
+    let ids: Vec<u32> = std::fs::read_dir(format!("/proc/{}/task", pid))
+        .map(|tasks| {
+            tasks
+                .filter_map(|task| task.ok()?.file_name().to_str()?.parse().ok())
+                .collect()
+        })
+        .unwrap_or_else(|_| vec![pid]);
+    #[cfg(not(target_os = "linux"))]
+    let ids = vec![pid];
+
+    for id in ids {
+        // SAFETY: setpriority has no memory-safety preconditions.
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, id as libc::id_t, mode.nice()) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn priority_class(mode: BackendPerformance) -> u32 {
+    use windows_sys::Win32::System::Threading::{
+        BELOW_NORMAL_PRIORITY_CLASS, IDLE_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS,
+    };
+    match mode {
+        BackendPerformance::Low => IDLE_PRIORITY_CLASS,
+        BackendPerformance::Balanced => BELOW_NORMAL_PRIORITY_CLASS,
+        BackendPerformance::Max => NORMAL_PRIORITY_CLASS,
+    }
+}
+
+#[cfg(windows)]
+pub fn apply_priority(pid: u32, mode: BackendPerformance) -> std::io::Result<()> {
+    use windows_sys::Win32::Foundation::CloseHandle;
+    use windows_sys::Win32::System::Threading::{
+        OpenProcess, SetPriorityClass, PROCESS_SET_INFORMATION,
+    };
+
+    // SAFETY: the handle is checked for null and closed before returning.
+    unsafe {
+        let handle = OpenProcess(PROCESS_SET_INFORMATION, 0, pid);
+        if handle.is_null() {
+            return Err(std::io::Error::last_os_error());
+        }
+        let ok = SetPriorityClass(handle, priority_class(mode));
+        let result = if ok == 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(())
+        };
+        CloseHandle(handle);
+        result
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn apply_priority(_pid: u32, _mode: BackendPerformance) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "process priority is not supported on this platform",
+    ))
+}
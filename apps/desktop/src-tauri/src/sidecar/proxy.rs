@@ -0,0 +1,368 @@
+// Single choke point for HTTP traffic from the frontend to the backend
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::{base_url, SidecarManager, TOKEN_HEADER};
+use crate::error::AppError;
+
+/// Bodies larger than this are spooled to a file instead of being passed
+/// through IPC.
+const MAX_INLINE_BYTES: usize = 2 * 1024 * 1024;
+/// Where bodies are spooled, in the cache root. Each file is deleted once
+/// `take_backend_file` reads it, and any left over at the next start.
+pub const SPOOL_DIR: &str = "backend_spool";
+const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+/// Long enough for a render kick-off, short enough that a wedged backend
+/// doesn't hold a command forever.
+const MAX_TIMEOUT_MS: u64 = 10 * 60 * 1000;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BackendResponse {
+    Json {
+        status: u16,
+        body: serde_json::Value,
+    },
+    /// Large or non-JSON bodies, spooled to `path` for `take_backend_file`.
+    File {
+        status: u16,
+        path: PathBuf,
+        bytes: u64,
+        content_type: Option<String>,
+    },
+}
+
+fn parse_method(method: &str) -> Result<reqwest::Method, AppError> {
+    match method.to_ascii_uppercase().as_str() {
+        "GET" => Ok(reqwest::Method::GET),
+        "POST" => Ok(reqwest::Method::POST),
+        "PUT" => Ok(reqwest::Method::PUT),
+        "PATCH" => Ok(reqwest::Method::PATCH),
+        "DELETE" => Ok(reqwest::Method::DELETE),
+        other => Err(AppError::InvalidBackendRequest {
+            message: format!("unsupported method {}", other),
+        }),
+    }
+}
+
+/// Only origin-relative paths are forwarded, so the command can't be used to
+/// reach anything but the local backend.
+fn validate_path(path: &str) -> Result<(), AppError> {
+    if !path.starts_with('/') || path.starts_with("//") || path.contains('\\') {
+        return Err(AppError::InvalidBackendRequest {
+            message: format!("path must be absolute on the backend, got {}", path),
+        });
+    }
+    let route = path.split(['?', '#']).next().unwrap_or(path);
+    if route.split('/').any(|segment| segment == "..") {
+        return Err(AppError::InvalidBackendRequest {
+            message: format!("path must not contain '..': {}", path),
+        });
+    }
+    Ok(())
+}
+
+fn map_send_error(e: reqwest::Error, port: u16, timeout_ms: u64) -> AppError {
+    if e.is_timeout() {
+        AppError::BackendTimeout { timeout_ms }
+    } else if e.is_connect() {
+        AppError::BackendUnavailable {
+            port,
+            message: e.to_string(),
+        }
+    } else {
+        AppError::BackendUnavailable {
+            port,
+            message: format!("request failed: {}", e),
+        }
+    }
+}
+
+/// FastAPI errors look like `{"detail": "..."}`; fall back to the raw body.
+fn error_message(body: &[u8]) -> String {
+    let parsed: Option<serde_json::Value> = serde_json::from_slice(body).ok();
+    match parsed.as_ref().and_then(|v| v.get("detail")) {
+        Some(serde_json::Value::String(detail)) => detail.clone(),
+        Some(detail) => detail.to_string(),
+        None => String::from_utf8_lossy(body).chars().take(500).collect(),
+    }
+}
+
+async fn spool(
+    dir: &Path,
+    mut response: reqwest::Response,
+    buffered: Vec<u8>,
+    content_type: Option<String>,
+    port: u16,
+    timeout_ms: u64,
+) -> Result<BackendResponse, AppError> {
+    use tokio::io::AsyncWriteExt;
+
+    let status = response.status().as_u16();
+    tokio::fs::create_dir_all(dir).await?;
+    let path = dir.join(format!("{}.bin", uuid::Uuid::new_v4()));
+
+    let mut file = tokio::fs::File::create(&path).await?;
+    let mut bytes = buffered.len() as u64;
+    file.write_all(&buffered).await?;
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
+                bytes += chunk.len() as u64;
+                file.write_all(&chunk).await?;
+            }
+            Ok(None) => break,
+            Err(e) => {
+                drop(file);
+                let _ = tokio::fs::remove_file(&path).await;
+                return Err(map_send_error(e, port, timeout_ms));
+            }
+        }
+    }
+    file.flush().await?;
+
+    Ok(BackendResponse::File {
+        status,
+        path,
+        bytes,
+        content_type,
+    })
+}
+
+/// Whether `path` names a file `spool` wrote to `dir`.
+fn is_spooled(dir: &Path, path: &Path) -> bool {
+    let spooled_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .and_then(|n| n.strip_suffix(".bin"))
+        .is_some_and(|stem| uuid::Uuid::parse_str(stem).is_ok());
+    let in_dir = match (path.parent().map(fs::canonicalize), fs::canonicalize(dir)) {
+        (Some(Ok(parent)), Ok(dir)) => parent == dir,
+        _ => false,
+    };
+    spooled_name && in_dir
+}
+
+/// The body spooled to `path`, deleting its file.
+fn take(dir: &Path, path: &Path) -> Result<Vec<u8>, AppError> {
+    if !is_spooled(dir, path) {
+        return Err(AppError::InvalidBackendRequest {
+            message: format!("{} is not a spooled backend response", path.display()),
+        });
+    }
+    let bytes = fs::read(path)?;
+    if let Err(e) = fs::remove_file(path) {
+        log::warn!("Failed to delete spooled {}: {}", path.display(), e);
+    }
+    Ok(bytes)
+}
+
+/// Deletes whatever earlier sessions spooled to `dir` and never took,
+/// returning how many files that was. Run at startup, before any request
+/// can be spooling.
+pub fn sweep_spool(dir: &Path) -> std::io::Result<usize> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut swept = 0;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_file() {
+            fs::remove_file(&path)?;
+            swept += 1;
+        }
+    }
+    Ok(swept)
+}
+
+impl SidecarManager {
+    /// Forwards one request to the backend with the session secret attached.
+    /// The timeout covers the whole exchange including reading the body.
+    pub async fn request(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<serde_json::Value>,
+        timeout_ms: Option<u64>,
+    ) -> Result<BackendResponse, AppError> {
+        let method = parse_method(method)?;
+        validate_path(path)?;
+        let timeout_ms = timeout_ms
+            .unwrap_or(DEFAULT_TIMEOUT_MS)
+            .clamp(1, MAX_TIMEOUT_MS);
+
+        let mut restarts = self.restart_signal();
+        if self.is_restarting() {
+            return Err(AppError::BackendRestarting);
+        }
+        tokio::select! {
+            result = self.exchange(method, path, body, timeout_ms) => result,
+            _ = restarts.changed() => Err(AppError::BackendRestarting),
+        }
+    }
+
+    async fn exchange(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+        timeout_ms: u64,
+    ) -> Result<BackendResponse, AppError> {
+        let port = self.port();
+
+        let mut request = self
+            .http()
+            .request(method, format!("{}{}", base_url(port), path))
+            .header(TOKEN_HEADER, self.token())
+            .timeout(Duration::from_millis(timeout_ms));
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+
+        let mut response = request
+            .send()
+            .await
+            .map_err(|e| map_send_error(e, port, timeout_ms))?;
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        let is_json = content_type
+            .as_deref()
+            .is_some_and(|t| t.starts_with("application/json"));
+
+        let mut buffered = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| map_send_error(e, port, timeout_ms))?
+        {
+            buffered.extend_from_slice(&chunk);
+            if buffered.len() > MAX_INLINE_BYTES {
+                if !status.is_success() {
+                    // Enough to extract a message from
+                    break;
+                }
+                return spool(
+                    self.spool_dir(),
+                    response,
+                    buffered,
+                    content_type,
+                    port,
+                    timeout_ms,
+                )
+                .await;
+            }
+        }
+
+        if !status.is_success() {
+            return Err(AppError::BackendError {
+                status: status.as_u16(),
+                message: error_message(&buffered),
+            });
+        }
+        if !is_json && !buffered.is_empty() {
+            return spool(
+                self.spool_dir(),
+                response,
+                buffered,
+                content_type,
+                port,
+                timeout_ms,
+            )
+            .await;
+        }
+
+        let body = if buffered.is_empty() {
+            serde_json::Value::Null
+        } else {
+            serde_json::from_slice(&buffered).map_err(|e| AppError::BackendError {
+                status: status.as_u16(),
+                message: format!("invalid JSON in response: {}", e),
+            })?
+        };
+        Ok(BackendResponse::Json {
+            status: status.as_u16(),
+            body,
+        })
+    }
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn backend_request(
+    sidecar: tauri::State<'_, SidecarManager>,
+    method: String,
+    path: String,
+    body_json: Option<serde_json::Value>,
+    timeout_ms: Option<u64>,
+) -> Result<BackendResponse, AppError> {
+    sidecar.request(&method, &path, body_json, timeout_ms).await
+}
+
+/// Reads the body a `backend_request` spooled to `path` and deletes its
+/// file, so each one can be taken once.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn take_backend_file(
+    sidecar: tauri::State<'_, SidecarManager>,
+    path: PathBuf,
+) -> Result<Vec<u8>, AppError> {
+    let dir = sidecar.spool_dir().to_path_buf();
+    tauri::async_runtime::spawn_blocking(move || take(&dir, &path))
+        .await
+        .map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    fn spooled(scratch: &Scratch) -> PathBuf {
+        let path = scratch.0.join(format!("{}.bin", uuid::Uuid::new_v4()));
+        fs::write(&path, b"body").unwrap();
+        path
+    }
+
+    #[test]
+    fn a_spooled_body_is_taken_once() {
+        let scratch = Scratch::new("take");
+        let path = spooled(&scratch);
+        assert_eq!(take(&scratch.0, &path).unwrap(), b"body");
+        assert!(!path.exists());
+        assert!(take(&scratch.0, &path).is_err());
+    }
+
+    #[test]
+    fn only_spooled_files_can_be_taken() {
+        let scratch = Scratch::new("refuse");
+        let settings = scratch.0.join("settings.json");
+        fs::write(&settings, b"{}").unwrap();
+        assert!(take(&scratch.0, &settings).is_err());
+        assert!(settings.exists());
+
+        let elsewhere = Scratch::new("elsewhere");
+        let path = spooled(&elsewhere);
+        assert!(take(&scratch.0, &path).is_err());
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn sweeps_whatever_was_left_behind() {
+        let scratch = Scratch::new("sweep");
+        spooled(&scratch);
+        spooled(&scratch);
+        assert_eq!(sweep_spool(&scratch.0).unwrap(), 2);
+        assert_eq!(fs::read_dir(&scratch.0).unwrap().count(), 0);
+        assert_eq!(sweep_spool(&scratch.0.join("missing")).unwrap(), 0);
+    }
+}
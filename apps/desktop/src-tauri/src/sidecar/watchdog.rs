@@ -0,0 +1,320 @@
+// Detection of a backend that is alive but no longer answering, and of one
+// that exited
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::SidecarManager;
+
+/// Watchdog thresholds, stored in settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    pub interval_secs: u64,
+    /// Health probe timeout; a backend busy rendering may be slow but should
+    /// still answer within this.
+    pub probe_timeout_ms: u64,
+    /// Consecutive failed probes before `backend-unresponsive` is emitted.
+    pub unresponsive_after: u32,
+    /// Consecutive failed probes before the backend is killed and respawned.
+    /// 0 disables forced restarts.
+    pub restart_after: u32,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 15,
+            probe_timeout_ms: 5000,
+            unresponsive_after: 3,
+            restart_after: 6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchdogState {
+    Healthy,
+    Unresponsive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogAction {
+    EmitUnresponsive,
+    EmitRecovered,
+    Restart,
+}
+
+/// Pure failure-counting state machine driven by probe results.
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    pub state: WatchdogState,
+    pub consecutive_failures: u32,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self {
+            state: WatchdogState::Healthy,
+            consecutive_failures: 0,
+        }
+    }
+}
+
+impl Watchdog {
+    pub fn observe(&mut self, healthy: bool, config: &WatchdogConfig) -> Option<WatchdogAction> {
+        if healthy {
+            self.consecutive_failures = 0;
+            return match std::mem::replace(&mut self.state, WatchdogState::Healthy) {
+                WatchdogState::Unresponsive => Some(WatchdogAction::EmitRecovered),
+                WatchdogState::Healthy => None,
+            };
+        }
+
+        self.consecutive_failures += 1;
+        if config.restart_after > 0 && self.consecutive_failures >= config.restart_after {
+            // The restart gives the backend a clean slate
+            *self = Self::default();
+            return Some(WatchdogAction::Restart);
+        }
+        if self.state == WatchdogState::Healthy
+            && self.consecutive_failures >= config.unresponsive_after.max(1)
+        {
+            self.state = WatchdogState::Unresponsive;
+            return Some(WatchdogAction::EmitUnresponsive);
+        }
+        None
+    }
+}
+
+/// Payload of `backend-unresponsive`, `backend-recovered` and `backend-restarted`.
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchdogEvent {
+    pub consecutive_failures: u32,
+    pub reason: Option<String>,
+}
+
+/// Environment variable naming the file the backend's faulthandler writes
+/// stack dumps to.
+pub const STACK_DUMP_ENV: &str = "SCLIP_STACK_DUMP_FILE";
+
+/// Best-effort capture of the backend's Python stacks before it is killed:
+/// py-spy if installed, otherwise SIGQUIT on Unix, which the backend's
+/// faulthandler answers by writing every thread's traceback to its dump file.
+async fn dump_stacks(pid: u32, dump_file: Option<PathBuf>) -> Option<String> {
+    let py_spy = tokio::process::Command::new("py-spy")
+        .args(["dump", "--pid", &pid.to_string()])
+        .output();
+    if let Ok(Ok(output)) = tokio::time::timeout(Duration::from_secs(10), py_spy).await {
+        if output.status.success() {
+            return Some(String::from_utf8_lossy(&output.stdout).into_owned());
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(dump_file) = dump_file {
+        // SAFETY: kill has no memory-safety preconditions.
+        if unsafe { libc::kill(pid as libc::pid_t, libc::SIGQUIT) } == 0 {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            if let Ok(dump) = tokio::fs::read_to_string(&dump_file).await {
+                return Some(dump);
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = dump_file;
+    None
+}
+
+/// Probes the backend forever, emitting state changes and restarting it when
+/// it stays unresponsive or exits. Only a backend this app spawned is ever
+/// restarted.
+pub async fn run(
+    sidecar: &SidecarManager,
+    config: impl Fn() -> WatchdogConfig,
+    emit: impl Fn(&'static str, WatchdogEvent),
+) {
+    let mut watchdog = Watchdog::default();
+    loop {
+        let config = config();
+        tokio::time::sleep(Duration::from_secs(config.interval_secs.max(1))).await;
+
+        if let Some(status) = sidecar.exited().await {
+            log::error!("Backend exited unexpectedly ({}), respawning", status);
+            restart(sidecar, &emit, 0, format!("backend exited ({})", status)).await;
+            watchdog = Watchdog::default();
+            continue;
+        }
+
+        let healthy = super::health_check(
+            sidecar.port(),
+            Duration::from_millis(config.probe_timeout_ms),
+        )
+        .await;
+        let failures = watchdog.consecutive_failures + 1;
+        match watchdog.observe(healthy, &config) {
+            Some(WatchdogAction::EmitUnresponsive) => {
+                log::warn!(
+                    "Backend failed {} consecutive health checks",
+                    watchdog.consecutive_failures
+                );
+                emit(
+                    "backend-unresponsive",
+                    WatchdogEvent {
+                        consecutive_failures: watchdog.consecutive_failures,
+                        reason: None,
+                    },
+                );
+            }
+            Some(WatchdogAction::EmitRecovered) => {
+                log::info!("Backend is answering health checks again");
+                emit(
+                    "backend-recovered",
+                    WatchdogEvent {
+                        consecutive_failures: 0,
+                        reason: None,
+                    },
+                );
+            }
+            Some(WatchdogAction::Restart) => {
+                if sidecar.child_pid().await.is_none() {
+                    log::warn!(
+                        "Backend is unresponsive but was not spawned by the app; not restarting"
+                    );
+                    continue;
+                }
+                restart(
+                    sidecar,
+                    &emit,
+                    failures,
+                    format!("no health response for {} checks", failures),
+                )
+                .await;
+            }
+            None => {}
+        }
+    }
+}
+
+async fn restart(
+    sidecar: &SidecarManager,
+    emit: &impl Fn(&'static str, WatchdogEvent),
+    consecutive_failures: u32,
+    reason: String,
+) {
+    if let Some(pid) = sidecar.child_pid().await {
+        match dump_stacks(pid, sidecar.stack_dump_file()).await {
+            Some(dump) => log::error!("Backend stacks before restart:\n{}", dump),
+            None => log::warn!("Could not capture backend stacks before restart"),
+        }
+    }
+    if let Err(e) = sidecar.restart().await {
+        log::error!("Failed to restart backend: {}", e);
+    }
+    emit(
+        "backend-restarted",
+        WatchdogEvent {
+            consecutive_failures,
+            reason: Some(reason),
+        },
+    );
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(unresponsive_after: u32, restart_after: u32) -> WatchdogConfig {
+        WatchdogConfig {
+            unresponsive_after,
+            restart_after,
+            ..WatchdogConfig::default()
+        }
+    }
+
+    fn observe(
+        watchdog: &mut Watchdog,
+        probes: &[bool],
+        config: &WatchdogConfig,
+    ) -> Vec<Option<WatchdogAction>> {
+        probes
+            .iter()
+            .map(|&healthy| watchdog.observe(healthy, config))
+            .collect()
+    }
+
+    #[test]
+    fn reports_a_hang_once_then_the_recovery() {
+        let config = config(3, 0);
+        let mut watchdog = Watchdog::default();
+        assert_eq!(
+            observe(
+                &mut watchdog,
+                &[true, false, false, false, false, false],
+                &config
+            ),
+            [
+                None,
+                None,
+                None,
+                Some(WatchdogAction::EmitUnresponsive),
+                None,
+                None
+            ]
+        );
+        assert_eq!(watchdog.state, WatchdogState::Unresponsive);
+        assert_eq!(watchdog.consecutive_failures, 5);
+        assert_eq!(
+            observe(&mut watchdog, &[true, true], &config),
+            [Some(WatchdogAction::EmitRecovered), None]
+        );
+        assert_eq!(watchdog.consecutive_failures, 0);
+    }
+
+    #[test]
+    fn a_passing_probe_resets_the_count() {
+        let config = config(3, 6);
+        let mut watchdog = Watchdog::default();
+        let actions = observe(
+            &mut watchdog,
+            &[false, false, true, false, false, true],
+            &config,
+        );
+        assert!(actions.iter().all(Option::is_none));
+        assert_eq!(watchdog.state, WatchdogState::Healthy);
+    }
+
+    #[test]
+    fn restarts_after_enough_failures_and_starts_over() {
+        let config = config(3, 6);
+        let mut watchdog = Watchdog::default();
+        let actions = observe(&mut watchdog, &[false; 6], &config);
+        assert_eq!(actions[2], Some(WatchdogAction::EmitUnresponsive));
+        assert_eq!(actions[5], Some(WatchdogAction::Restart));
+        assert_eq!(watchdog.state, WatchdogState::Healthy);
+        assert_eq!(watchdog.consecutive_failures, 0);
+        // The restarted backend gets the full count again
+        assert_eq!(
+            observe(&mut watchdog, &[false; 3], &config)[2],
+            Some(WatchdogAction::EmitUnresponsive)
+        );
+    }
+
+    #[test]
+    fn restarts_can_be_turned_off_and_zero_reports_on_the_first_failure() {
+        let mut watchdog = Watchdog::default();
+        let actions = observe(&mut watchdog, &[false; 50], &config(0, 0));
+        assert_eq!(actions[0], Some(WatchdogAction::EmitUnresponsive));
+        assert!(actions[1..].iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn settings_without_a_watchdog_use_the_defaults() {
+        let config: WatchdogConfig = serde_json::from_str(r#"{"restart_after": 0}"#).unwrap();
+        assert_eq!(config.restart_after, 0);
+        assert_eq!(
+            config.interval_secs,
+            WatchdogConfig::default().interval_secs
+        );
+    }
+}
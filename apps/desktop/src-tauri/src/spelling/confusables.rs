@@ -0,0 +1,68 @@
+// Words a voice reads the same as another spelling, so a typo among them
+// can't be heard. Kept short: only pairs people actually mix up
+const ENGLISH: &[&[&str]] = &[
+    &["their", "there", "they're"],
+    &["your", "you're"],
+    &["its", "it's"],
+    &["whose", "who's"],
+    &["to", "too", "two"],
+    &["then", "than"],
+    &["affect", "effect"],
+    &["accept", "except"],
+    &["lose", "loose"],
+    &["weather", "whether"],
+    &["principal", "principle"],
+    &["complement", "compliment"],
+    &["stationary", "stationery"],
+    &["break", "brake"],
+    &["peak", "peek", "pique"],
+    &["site", "sight", "cite"],
+    &["wear", "where"],
+    &["hear", "here"],
+    &["role", "roll"],
+    &["led", "lead"],
+];
+
+const FRENCH: &[&[&str]] = &[
+    &["a", "à"],
+    &["ou", "où"],
+    &["ce", "se"],
+    &["ces", "ses"],
+    &["et", "est"],
+    &["son", "sont"],
+    &["on", "ont"],
+];
+
+const SPANISH: &[&[&str]] = &[
+    &["haber", "a ver"],
+    &["hay", "ahí", "ay"],
+    &["echo", "hecho"],
+    &["vaya", "valla", "baya"],
+];
+
+const GERMAN: &[&[&str]] = &[&["das", "dass"], &["seid", "seit"], &["wieder", "wider"]];
+
+fn groups(language_code: &str) -> &'static [&'static [&'static str]] {
+    match language_code
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+        .as_str()
+    {
+        "en" => ENGLISH,
+        "fr" => FRENCH,
+        "es" => SPANISH,
+        "de" => GERMAN,
+        _ => &[],
+    }
+}
+
+/// The words `word` sounds like in `language_code`, if it's on the list.
+pub fn sounds_like(language_code: &str, word: &str) -> Option<Vec<&'static str>> {
+    let word = word.replace('\u{2019}', "'").to_lowercase();
+    let group = groups(language_code)
+        .iter()
+        .find(|group| group.contains(&word.as_str()))?;
+    Some(group.iter().copied().filter(|w| *w != word).collect())
+}
@@ -0,0 +1,326 @@
+// Spelling dictionaries, downloaded per language the first time a script in
+// it is checked rather than bundled, except en-US under resources/dictionaries.
+// A manifest at the `spelling` settings URL lists each language's .aff and
+// .dic with their SHA-256; files are verified against it when downloaded and
+// again whenever they're loaded, so a damaged or altered file is fetched anew
+// instead of used. The bundled dictionaries have a manifest of their own,
+// used for a language the configured one doesn't have or can't be fetched
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use super::hunspell::Dictionary;
+use crate::cache::write_atomic;
+use crate::error::AppError;
+
+const MANIFEST_FILE: &str = "manifest.json";
+/// Directory of the bundled dictionaries in the resources.
+pub const BUNDLED_DIR: &str = "dictionaries";
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+/// The largest Hunspell dictionaries are around 20 MB.
+const MAX_FILE_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpellingSettings {
+    /// Where the dictionary manifest is downloaded from; `None` leaves every
+    /// language without a dictionary unchecked.
+    pub manifest_url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DictionaryFile {
+    /// In the bundled manifest, a file name in its directory.
+    pub url: String,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DictionaryEntry {
+    pub aff: DictionaryFile,
+    pub dic: DictionaryFile,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    /// By language code, such as "en-US", or base language, such as "de".
+    pub dictionaries: BTreeMap<String, DictionaryEntry>,
+}
+
+impl Manifest {
+    /// The entry for `language_code`: its own, else the base language's,
+    /// else the first of another region of it.
+    fn find(&self, language_code: &str) -> Option<(&String, &DictionaryEntry)> {
+        let base = language_code.split(['-', '_']).next().unwrap_or_default();
+        let same_base = |key: &str| {
+            key.split(['-', '_'])
+                .next()
+                .is_some_and(|b| b.eq_ignore_ascii_case(base))
+        };
+        self.dictionaries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(language_code))
+            .or_else(|| {
+                self.dictionaries
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case(base))
+            })
+            .or_else(|| self.dictionaries.iter().find(|(key, _)| same_base(key)))
+    }
+}
+
+/// A dictionary ready for checking, or why there is none.
+pub enum Lookup {
+    Found {
+        /// The manifest key used, e.g. "en-GB" for an "en-AU" script.
+        language: String,
+        dictionary: Arc<Dictionary>,
+    },
+    Unavailable(String),
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn download_error(url: &str, message: impl Into<String>) -> AppError {
+    AppError::Download {
+        url: url.to_string(),
+        message: message.into(),
+    }
+}
+
+async fn download(url: &str) -> Result<Vec<u8>, AppError> {
+    if !url.starts_with("https://") {
+        return Err(AppError::DownloadNotAllowed {
+            url: url.to_string(),
+            reason: "only https URLs can be downloaded".to_string(),
+        });
+    }
+    let response = reqwest::Client::new()
+        .get(url)
+        .timeout(DOWNLOAD_TIMEOUT)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| download_error(url, e.to_string()))?;
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|e| download_error(url, e.to_string()))?;
+    if bytes.len() > MAX_FILE_BYTES {
+        return Err(AppError::DownloadTooLarge {
+            url: url.to_string(),
+            bytes: bytes.len() as u64,
+            limit: MAX_FILE_BYTES as u64,
+        });
+    }
+    Ok(bytes.to_vec())
+}
+
+/// Manifest keys become file names.
+fn is_safe_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= 32
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Keys of the bundled dictionaries in `DictionaryStore::loaded`, apart
+/// from downloaded ones of the same language.
+fn bundled_key(key: &str) -> String {
+    format!("bundled:{}", key)
+}
+
+fn parse(
+    loaded: &mut HashMap<String, Arc<Dictionary>>,
+    key: &str,
+    loaded_key: String,
+    aff: &[u8],
+    dic: &[u8],
+) -> Lookup {
+    match Dictionary::parse(aff, dic) {
+        Ok(dictionary) => {
+            let dictionary = Arc::new(dictionary);
+            loaded.insert(loaded_key, dictionary.clone());
+            log::info!("Loaded the {} spelling dictionary", key);
+            Lookup::Found {
+                language: key.to_string(),
+                dictionary,
+            }
+        }
+        Err(e) => Lookup::Unavailable(format!("the {} dictionary is unreadable: {}", key, e)),
+    }
+}
+
+pub struct DictionaryStore {
+    dir: PathBuf,
+    /// The bundled dictionaries, when the resources are found.
+    bundled_dir: Option<PathBuf>,
+    /// Parsed dictionaries by manifest key; held while one loads, so two
+    /// checks don't download the same files.
+    loaded: Mutex<HashMap<String, Arc<Dictionary>>>,
+}
+
+impl DictionaryStore {
+    pub fn new(dir: PathBuf, bundled_dir: Option<PathBuf>) -> Self {
+        Self {
+            dir,
+            bundled_dir,
+            loaded: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The bundled dictionary for `language_code`, if there is one that
+    /// loads.
+    fn bundled(
+        &self,
+        loaded: &mut HashMap<String, Arc<Dictionary>>,
+        language_code: &str,
+    ) -> Option<Lookup> {
+        let dir = self.bundled_dir.as_ref()?;
+        let contents = fs::read(dir.join(MANIFEST_FILE)).ok()?;
+        let manifest: Manifest = serde_json::from_slice(&contents)
+            .map_err(|e| log::warn!("Ignoring the bundled dictionary manifest: {}", e))
+            .ok()?;
+        let (key, entry) = manifest.find(language_code)?;
+        if let Some(dictionary) = loaded.get(&bundled_key(key)) {
+            return Some(Lookup::Found {
+                language: key.clone(),
+                dictionary: dictionary.clone(),
+            });
+        }
+        let read = |file: &DictionaryFile| {
+            if file.url.contains(['/', '\\']) || file.url.starts_with('.') {
+                return Err(format!("{} is not a file name", file.url));
+            }
+            let bytes = fs::read(dir.join(&file.url)).map_err(|e| e.to_string())?;
+            if !sha256_hex(&bytes).eq_ignore_ascii_case(&file.sha256) {
+                return Err(format!("{} doesn't match its SHA-256", file.url));
+            }
+            Ok(bytes)
+        };
+        match read(&entry.aff).and_then(|aff| Ok((aff, read(&entry.dic)?))) {
+            Ok((aff, dic)) => Some(parse(loaded, key, bundled_key(key), &aff, &dic)),
+            Err(e) => {
+                log::warn!("The bundled {} dictionary is unusable: {}", key, e);
+                None
+            }
+        }
+    }
+
+    fn read_manifest(&self) -> Option<Manifest> {
+        let contents = fs::read(self.dir.join(MANIFEST_FILE)).ok()?;
+        serde_json::from_slice(&contents)
+            .map_err(|e| log::warn!("Ignoring the stored dictionary manifest: {}", e))
+            .ok()
+    }
+
+    async fn fetch_manifest(&self, url: &str, store: bool) -> Result<Manifest, AppError> {
+        let bytes = download(url).await?;
+        let manifest: Manifest = serde_json::from_slice(&bytes)
+            .map_err(|e| download_error(url, format!("not a dictionary manifest: {}", e)))?;
+        if store {
+            write_atomic(&self.dir.join(MANIFEST_FILE), &bytes)?;
+        }
+        Ok(manifest)
+    }
+
+    /// The file's bytes from disk if they still match the manifest, else
+    /// downloaded and verified.
+    async fn file(
+        &self,
+        key: &str,
+        extension: &str,
+        file: &DictionaryFile,
+        store: bool,
+    ) -> Result<Vec<u8>, AppError> {
+        let path = self.dir.join(format!("{}.{}", key, extension));
+        if let Ok(bytes) = fs::read(&path) {
+            if sha256_hex(&bytes).eq_ignore_ascii_case(&file.sha256) {
+                return Ok(bytes);
+            }
+            log::warn!(
+                "Dictionary file {} doesn't match its checksum, downloading it again",
+                path.display()
+            );
+        }
+        let bytes = download(&file.url).await?;
+        if !sha256_hex(&bytes).eq_ignore_ascii_case(&file.sha256) {
+            return Err(download_error(
+                &file.url,
+                "the file doesn't match the manifest's SHA-256",
+            ));
+        }
+        if store {
+            write_atomic(&path, &bytes)?;
+        }
+        Ok(bytes)
+    }
+
+    /// The dictionary for `language_code`, downloading what's missing, else
+    /// the bundled one. In read-only mode downloads are used but not kept.
+    pub async fn lookup(
+        &self,
+        settings: &SpellingSettings,
+        language_code: &str,
+        read_only: bool,
+    ) -> Lookup {
+        let store = !read_only;
+        let mut loaded = self.loaded.lock().await;
+        let mut manifest = self.read_manifest().unwrap_or_default();
+        if manifest.find(language_code).is_none() {
+            let fetched = match &settings.manifest_url {
+                Some(url) => self
+                    .fetch_manifest(url, store)
+                    .await
+                    .map_err(|e| e.to_string()),
+                None => Err(format!(
+                    "no spelling dictionary for {} is installed and no dictionary source is configured",
+                    language_code
+                )),
+            };
+            let reason = match fetched {
+                Ok(fetched) if fetched.find(language_code).is_some() => {
+                    manifest = fetched;
+                    None
+                }
+                Ok(_) => Some(format!("no spelling dictionary for {}", language_code)),
+                Err(reason) => Some(reason),
+            };
+            if let Some(reason) = reason {
+                return self
+                    .bundled(&mut loaded, language_code)
+                    .unwrap_or(Lookup::Unavailable(reason));
+            }
+        }
+        let Some((key, entry)) = manifest.find(language_code) else {
+            return Lookup::Unavailable(format!("no spelling dictionary for {}", language_code));
+        };
+        if !is_safe_key(key) {
+            return Lookup::Unavailable(format!("invalid dictionary name \"{}\"", key));
+        }
+        if let Some(dictionary) = loaded.get(key) {
+            return Lookup::Found {
+                language: key.clone(),
+                dictionary: dictionary.clone(),
+            };
+        }
+        let files = async {
+            let aff = self.file(key, "aff", &entry.aff, store).await?;
+            let dic = self.file(key, "dic", &entry.dic, store).await?;
+            Ok::<_, AppError>((aff, dic))
+        };
+        let (aff, dic) = match files.await {
+            Ok(files) => files,
+            Err(e) => return Lookup::Unavailable(e.to_string()),
+        };
+        parse(&mut loaded, key, key.clone(), &aff, &dic)
+    }
+}
@@ -0,0 +1,425 @@
+// A reader for Hunspell dictionaries (.aff and .dic), enough to check the
+// words of a script: prefixes and suffixes with their conditions and cross
+// products, forbidden words, case variants, and REP and TRY for
+// suggestions. Compounding and morphology aren't supported, so in
+// compound-heavy languages such as German some correct compounds are
+// reported
+use std::collections::{HashMap, HashSet};
+
+type Flag = u32;
+
+/// Characters tried in suggestions when the .aff has no TRY line.
+const DEFAULT_TRY: &str = "esianrtolcdugmphbyfvkwzxjq";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FlagMode {
+    /// One character per flag, the default.
+    Char,
+    /// Two characters per flag.
+    Long,
+    /// Comma-separated numbers.
+    Num,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CondPart {
+    Any,
+    Char(char),
+    Set { chars: Vec<char>, negated: bool },
+}
+
+impl CondPart {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Char(expected) => c == *expected,
+            Self::Set { chars, negated } => chars.contains(&c) != *negated,
+        }
+    }
+}
+
+fn parse_condition(condition: &str) -> Vec<CondPart> {
+    let mut parts = Vec::new();
+    let mut chars = condition.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '.' => parts.push(CondPart::Any),
+            '[' => {
+                let mut set = Vec::new();
+                let mut negated = false;
+                for c in chars.by_ref() {
+                    match c {
+                        ']' => break,
+                        '^' if set.is_empty() && !negated => negated = true,
+                        c => set.push(c),
+                    }
+                }
+                parts.push(CondPart::Set {
+                    chars: set,
+                    negated,
+                });
+            }
+            c => parts.push(CondPart::Char(c)),
+        }
+    }
+    parts
+}
+
+#[derive(Debug, Clone)]
+struct Affix {
+    flag: Flag,
+    /// Combines with affixes of the other kind.
+    cross: bool,
+    strip: String,
+    add: String,
+    condition: Vec<CondPart>,
+}
+
+impl Affix {
+    /// The root `word` is made of with this suffix, if it is.
+    fn root_of_suffixed(&self, word: &str) -> Option<String> {
+        let base = word.strip_suffix(self.add.as_str())?;
+        if base.is_empty() && self.strip.is_empty() {
+            return None;
+        }
+        let root = format!("{}{}", base, self.strip);
+        let chars: Vec<char> = root.chars().collect();
+        let n = self.condition.len();
+        let matches = chars.len() >= n
+            && self
+                .condition
+                .iter()
+                .zip(&chars[chars.len() - n..])
+                .all(|(part, &c)| part.matches(c));
+        matches.then_some(root)
+    }
+
+    /// The root `word` is made of with this prefix, if it is.
+    fn root_of_prefixed(&self, word: &str) -> Option<String> {
+        let base = word.strip_prefix(self.add.as_str())?;
+        if base.is_empty() && self.strip.is_empty() {
+            return None;
+        }
+        let root = format!("{}{}", self.strip, base);
+        let matches = root.chars().count() >= self.condition.len()
+            && self
+                .condition
+                .iter()
+                .zip(root.chars())
+                .all(|(part, c)| part.matches(c));
+        matches.then_some(root)
+    }
+}
+
+pub struct Dictionary {
+    words: HashMap<String, Vec<Flag>>,
+    prefixes: Vec<Affix>,
+    suffixes: Vec<Affix>,
+    /// Suffixes by the last character they add; `None` for those adding
+    /// nothing.
+    suffixes_by_end: HashMap<Option<char>, Vec<usize>>,
+    prefixes_by_start: HashMap<Option<char>, Vec<usize>>,
+    forbidden: Option<Flag>,
+    need_affix: Option<Flag>,
+    no_suggest: Option<Flag>,
+    try_chars: Vec<char>,
+    rep: Vec<(String, String)>,
+}
+
+/// `bytes` decoded as the .aff's SET names; only the encodings the
+/// LibreOffice dictionaries for the supported languages use.
+fn decode(bytes: &[u8], encoding: &str) -> Result<String, String> {
+    match encoding.to_ascii_uppercase().as_str() {
+        "UTF-8" | "UTF8" => Ok(String::from_utf8_lossy(bytes).into_owned()),
+        "ISO8859-1" | "ISO-8859-1" => Ok(bytes.iter().map(|&b| b as char).collect()),
+        "ISO8859-15" | "ISO-8859-15" => Ok(bytes
+            .iter()
+            .map(|&b| match b {
+                0xA4 => '€',
+                0xA6 => 'Š',
+                0xA8 => 'š',
+                0xB4 => 'Ž',
+                0xB8 => 'ž',
+                0xBC => 'Œ',
+                0xBD => 'œ',
+                0xBE => 'Ÿ',
+                b => b as char,
+            })
+            .collect()),
+        other => Err(format!("unsupported dictionary encoding {}", other)),
+    }
+}
+
+fn parse_flags(flags: &str, mode: FlagMode) -> Vec<Flag> {
+    match mode {
+        FlagMode::Char => flags.chars().map(|c| c as Flag).collect(),
+        FlagMode::Long => flags
+            .chars()
+            .collect::<Vec<_>>()
+            .chunks(2)
+            .map(|pair| pair.iter().fold(0, |flag, &c| (flag << 16) | c as Flag))
+            .collect(),
+        FlagMode::Num => flags
+            .split(',')
+            .filter_map(|n| n.trim().parse().ok())
+            .collect(),
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+impl Dictionary {
+    pub fn parse(aff: &[u8], dic: &[u8]) -> Result<Self, String> {
+        // SET is ASCII, so it can be read before the encoding is known
+        let encoding = aff
+            .split(|&b| b == b'\n')
+            .map(|line| String::from_utf8_lossy(line).trim().to_string())
+            .find_map(|line| line.strip_prefix("SET ").map(|e| e.trim().to_string()))
+            .unwrap_or_else(|| "ISO8859-1".to_string());
+        let aff = decode(aff, &encoding)?;
+        let dic = decode(dic, &encoding)?;
+
+        let mut mode = FlagMode::Char;
+        let mut flag_lines = Vec::new();
+        let mut try_chars: Vec<char> = DEFAULT_TRY.chars().collect();
+        let mut rep = Vec::new();
+        let mut prefixes = Vec::new();
+        let mut suffixes = Vec::new();
+        let mut cross = HashMap::new();
+        for line in aff.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["FLAG", "long", ..] => mode = FlagMode::Long,
+                ["FLAG", "num", ..] => mode = FlagMode::Num,
+                ["FORBIDDENWORD" | "NEEDAFFIX" | "NOSUGGEST", flag, ..] => {
+                    flag_lines.push((fields[0], flag.to_string()))
+                }
+                ["TRY", chars, ..] => try_chars = chars.chars().collect(),
+                ["REP", from, to, ..] => rep.push((from.replace('_', " "), to.replace('_', " "))),
+                [kind @ ("PFX" | "SFX"), flag, combines @ ("Y" | "N"), count]
+                    if count.parse::<usize>().is_ok() =>
+                {
+                    cross.insert((*kind, flag.to_string()), *combines == "Y");
+                }
+                [kind @ ("PFX" | "SFX"), flag, strip, add, rest @ ..] => {
+                    let Some(&cross) = cross.get(&(*kind, flag.to_string())) else {
+                        continue;
+                    };
+                    // Continuation classes after the "/" aren't supported
+                    let add = add.split('/').next().unwrap_or_default();
+                    let affix = Affix {
+                        flag: parse_flags(flag, mode).first().copied().unwrap_or_default(),
+                        cross,
+                        strip: if *strip == "0" { "" } else { strip }.to_string(),
+                        add: if add == "0" { "" } else { add }.to_string(),
+                        condition: parse_condition(rest.first().copied().unwrap_or(".")),
+                    };
+                    if *kind == "PFX" {
+                        prefixes.push(affix);
+                    } else {
+                        suffixes.push(affix);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let flag_of = |name: &str| {
+            flag_lines
+                .iter()
+                .find(|(line, _)| *line == name)
+                .and_then(|(_, flag)| parse_flags(flag, mode).first().copied())
+        };
+
+        let mut words: HashMap<String, Vec<Flag>> = HashMap::new();
+        // The first line is the word count
+        for line in dic.lines().skip(1) {
+            // Morphological fields follow a tab or space
+            let entry = line.split(['\t', ' ']).next().unwrap_or_default();
+            if entry.is_empty() {
+                continue;
+            }
+            let (word, flags) = match entry.find('/').filter(|&i| i > 0) {
+                Some(i) => (&entry[..i], parse_flags(&entry[i + 1..], mode)),
+                None => (entry, Vec::new()),
+            };
+            words
+                .entry(word.replace("\\/", "/"))
+                .or_default()
+                .extend(flags);
+        }
+        if words.is_empty() {
+            return Err("the dictionary has no words".to_string());
+        }
+
+        let mut suffixes_by_end: HashMap<Option<char>, Vec<usize>> = HashMap::new();
+        for (i, suffix) in suffixes.iter().enumerate() {
+            suffixes_by_end
+                .entry(suffix.add.chars().last())
+                .or_default()
+                .push(i);
+        }
+        let mut prefixes_by_start: HashMap<Option<char>, Vec<usize>> = HashMap::new();
+        for (i, prefix) in prefixes.iter().enumerate() {
+            prefixes_by_start
+                .entry(prefix.add.chars().next())
+                .or_default()
+                .push(i);
+        }
+        Ok(Self {
+            forbidden: flag_of("FORBIDDENWORD"),
+            need_affix: flag_of("NEEDAFFIX"),
+            no_suggest: flag_of("NOSUGGEST"),
+            words,
+            prefixes,
+            suffixes,
+            suffixes_by_end,
+            prefixes_by_start,
+            try_chars,
+            rep,
+        })
+    }
+
+    fn root_has(&self, root: &str, flags: &[Flag]) -> bool {
+        self.words.get(root).is_some_and(|root_flags| {
+            flags.iter().all(|f| root_flags.contains(f))
+                && !self.forbidden.is_some_and(|f| root_flags.contains(&f))
+        })
+    }
+
+    fn candidates<'a>(
+        &'a self,
+        index: &'a HashMap<Option<char>, Vec<usize>>,
+        key: Option<char>,
+    ) -> impl Iterator<Item = usize> + 'a {
+        index
+            .get(&None)
+            .into_iter()
+            .chain(key.and_then(|c| index.get(&Some(c))))
+            .flatten()
+            .copied()
+    }
+
+    /// Whether `word` is a root with one suffix, one prefix, or both.
+    fn affixed(&self, word: &str) -> bool {
+        for i in self.candidates(&self.suffixes_by_end, word.chars().last()) {
+            let suffix = &self.suffixes[i];
+            let Some(root) = suffix.root_of_suffixed(word) else {
+                continue;
+            };
+            if self.root_has(&root, &[suffix.flag]) {
+                return true;
+            }
+            if suffix.cross {
+                for j in self.candidates(&self.prefixes_by_start, root.chars().next()) {
+                    let prefix = &self.prefixes[j];
+                    if prefix.cross
+                        && prefix
+                            .root_of_prefixed(&root)
+                            .is_some_and(|r| self.root_has(&r, &[prefix.flag, suffix.flag]))
+                    {
+                        return true;
+                    }
+                }
+            }
+        }
+        self.candidates(&self.prefixes_by_start, word.chars().next())
+            .any(|i| {
+                let prefix = &self.prefixes[i];
+                prefix
+                    .root_of_prefixed(word)
+                    .is_some_and(|root| self.root_has(&root, &[prefix.flag]))
+            })
+    }
+
+    /// `word` as spelled, case included.
+    fn known(&self, word: &str) -> bool {
+        if let Some(flags) = self.words.get(word) {
+            if self.forbidden.is_some_and(|f| flags.contains(&f)) {
+                return false;
+            }
+            if !self.need_affix.is_some_and(|f| flags.contains(&f)) {
+                return true;
+            }
+        }
+        self.affixed(word)
+    }
+
+    /// Whether `word` is spelled correctly. A capitalized word may also be
+    /// a lowercase one, as at the start of a sentence, and one in capitals
+    /// either.
+    pub fn check(&self, word: &str) -> bool {
+        let word = word.replace('\u{2019}', "'");
+        if self.known(&word) {
+            return true;
+        }
+        if !word.chars().next().is_some_and(char::is_uppercase) {
+            return false;
+        }
+        let lower = word.to_lowercase();
+        if self.known(&lower) {
+            return true;
+        }
+        let all_upper = word
+            .chars()
+            .filter(|c| c.is_alphabetic())
+            .all(char::is_uppercase);
+        all_upper && self.known(&capitalize(&lower))
+    }
+
+    /// Up to `max` correct words one edit from `word`, the dictionary's
+    /// REP replacements first.
+    pub fn suggest(&self, word: &str, max: usize) -> Vec<String> {
+        let mut suggestions = Vec::new();
+        let mut seen = HashSet::new();
+        let mut consider = |candidate: String| {
+            if suggestions.len() < max
+                && candidate != word
+                && seen.insert(candidate.clone())
+                && self.check(&candidate)
+                && !self
+                    .no_suggest
+                    .is_some_and(|f| self.words.get(&candidate).is_some_and(|fl| fl.contains(&f)))
+            {
+                suggestions.push(candidate);
+            }
+        };
+        for (from, to) in &self.rep {
+            for (i, _) in word.match_indices(from.as_str()) {
+                consider(format!("{}{}{}", &word[..i], to, &word[i + from.len()..]));
+            }
+        }
+        let chars: Vec<char> = word.chars().collect();
+        let join = |chars: &[char]| chars.iter().collect::<String>();
+        for i in 0..chars.len().saturating_sub(1) {
+            let mut swapped = chars.clone();
+            swapped.swap(i, i + 1);
+            consider(join(&swapped));
+        }
+        for i in 0..chars.len() {
+            let mut removed = chars.clone();
+            removed.remove(i);
+            consider(join(&removed));
+        }
+        for i in 0..chars.len() {
+            for &c in &self.try_chars {
+                let mut replaced = chars.clone();
+                replaced[i] = c;
+                consider(join(&replaced));
+            }
+        }
+        for i in 0..=chars.len() {
+            for &c in &self.try_chars {
+                let mut inserted = chars.clone();
+                inserted.insert(i, c);
+                consider(join(&inserted));
+            }
+        }
+        suggestions
+    }
+}
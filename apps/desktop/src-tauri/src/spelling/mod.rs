@@ -0,0 +1,294 @@
+// Spelling and homophone checks of a script before it's synthesized: a
+// voice reads "there" for "their" without a stumble, so nobody hears the
+// typo until the upload. Issues are informational and never block
+pub mod confusables;
+pub mod dictionaries;
+pub mod hunspell;
+
+use serde::Serialize;
+use std::ops::Range;
+
+use crate::error::AppError;
+use crate::settings::SettingsStore;
+use dictionaries::{DictionaryStore, Lookup, SpellingSettings};
+
+/// Suggestions per misspelled word.
+const MAX_SUGGESTIONS: usize = 5;
+/// Misspellings reported per check; past this a script is more likely in
+/// another language than riddled with typos.
+const MAX_MISSPELLINGS: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpellingIssueKind {
+    Misspelled,
+    /// Spelled right but sounds like another word, which may be the one
+    /// meant.
+    Homophone,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SpellingIssue {
+    pub kind: SpellingIssueKind,
+    /// Byte range of the word in the text.
+    pub start: usize,
+    pub end: usize,
+    pub word: String,
+    pub suggestions: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status")]
+pub enum SpellingStatus {
+    /// Checked against the dictionary for `dictionary`.
+    Checked { dictionary: String },
+    /// There is no dictionary for the language, so misspellings weren't
+    /// looked for; homophones still were.
+    Unchecked { reason: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptCheck {
+    pub language_code: String,
+    pub spelling: SpellingStatus,
+    pub issues: Vec<SpellingIssue>,
+}
+
+/// Byte ranges of `text` that aren't prose: SSML tags, "{{...}}" fields,
+/// and whitespace-separated runs that look like URLs or email addresses.
+fn skipped(text: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    for (open, close) in [("<", ">"), ("{{", "}}")] {
+        let mut from = 0;
+        while let Some(start) = text[from..].find(open).map(|i| from + i) {
+            let Some(end) = text[start..].find(close).map(|i| start + i + close.len()) else {
+                break;
+            };
+            ranges.push(start..end);
+            from = end;
+        }
+    }
+    let mut start = None;
+    for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                let run = &text[s..i];
+                if run.contains("://") || run.contains('@') || run.starts_with("www.") {
+                    ranges.push(s..i);
+                }
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    ranges
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric()
+        || (!c.is_ascii()
+            && !c.is_whitespace()
+            && !matches!(c, '\u{2000}'..='\u{206F}' | '\u{3000}'..='\u{303F}' | '«' | '»' | '¡' | '¿' | '·'))
+}
+
+fn is_apostrophe(c: char) -> bool {
+    matches!(c, '\'' | '\u{2019}')
+}
+
+/// Byte ranges of the words in `text`. Apostrophes within a word belong to
+/// it; hyphens and other punctuation separate words.
+fn words(text: &str) -> Vec<Range<usize>> {
+    let skipped = skipped(text);
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut words = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if !is_word_char(chars[i].1) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < chars.len()
+            && (is_word_char(chars[i].1)
+                || (is_apostrophe(chars[i].1)
+                    && chars.get(i + 1).is_some_and(|&(_, c)| is_word_char(c))))
+        {
+            i += 1;
+        }
+        let range = chars[start].0..chars.get(i).map_or(text.len(), |&(b, _)| b);
+        if !skipped
+            .iter()
+            .any(|s| s.start < range.end && range.start < s.end)
+        {
+            words.push(range);
+        }
+    }
+    words
+}
+
+/// Whether the dictionary should see `word`: not numbers, single letters,
+/// acronyms or names with capitals inside, such as "YouTube".
+fn is_checkable(word: &str) -> bool {
+    let mut rest = word.chars().skip(1);
+    word.chars().count() > 1
+        && !word.chars().any(char::is_numeric)
+        && rest.all(|c| !c.is_uppercase())
+}
+
+/// Checks `text` as written in `language_code`.
+pub async fn check(
+    store: &DictionaryStore,
+    settings: &SpellingSettings,
+    text: &str,
+    language_code: &str,
+    read_only: bool,
+) -> ScriptCheck {
+    let (spelling, dictionary) = match store.lookup(settings, language_code, read_only).await {
+        Lookup::Found {
+            language,
+            dictionary,
+        } => (
+            SpellingStatus::Checked {
+                dictionary: language,
+            },
+            Some(dictionary),
+        ),
+        Lookup::Unavailable(reason) => {
+            log::info!("Spelling of {} unchecked: {}", language_code, reason);
+            (SpellingStatus::Unchecked { reason }, None)
+        }
+    };
+
+    let mut issues = Vec::new();
+    let mut misspellings = 0;
+    for range in words(text) {
+        let word = &text[range.clone()];
+        if let Some(dictionary) = &dictionary {
+            if misspellings < MAX_MISSPELLINGS && is_checkable(word) && !dictionary.check(word) {
+                misspellings += 1;
+                issues.push(SpellingIssue {
+                    kind: SpellingIssueKind::Misspelled,
+                    start: range.start,
+                    end: range.end,
+                    word: word.to_string(),
+                    suggestions: dictionary.suggest(word, MAX_SUGGESTIONS),
+                });
+                continue;
+            }
+        }
+        if let Some(alternatives) = confusables::sounds_like(language_code, word) {
+            issues.push(SpellingIssue {
+                kind: SpellingIssueKind::Homophone,
+                start: range.start,
+                end: range.end,
+                word: word.to_string(),
+                suggestions: alternatives.into_iter().map(str::to_string).collect(),
+            });
+        }
+    }
+    ScriptCheck {
+        language_code: language_code.to_string(),
+        spelling,
+        issues,
+    }
+}
+
+/// Finds misspelled words and homophones in `text`. The dictionary for
+/// `language_code` is downloaded the first time; a language without one
+/// reports its spelling as unchecked rather than clean.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn check_script(
+    app_handle: tauri::AppHandle,
+    store: tauri::State<'_, DictionaryStore>,
+    settings: tauri::State<'_, SettingsStore>,
+    text: String,
+    language_code: String,
+) -> Result<ScriptCheck, AppError> {
+    Ok(check(
+        &store,
+        &settings.get().spelling,
+        &text,
+        &language_code,
+        crate::read_only::is_read_only(&app_handle),
+    )
+    .await)
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![check_script]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A store with only the bundled dictionaries; checks are read-only, so
+    /// its data directory is never made.
+    fn bundled_only() -> DictionaryStore {
+        let resources = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../../resources");
+        DictionaryStore::new(
+            std::env::temp_dir().join(format!("sclip-spelling-{}", uuid::Uuid::new_v4())),
+            Some(resources.join(dictionaries::BUNDLED_DIR)),
+        )
+    }
+
+    #[tokio::test]
+    async fn flags_a_misspelling_with_the_bundled_dictionary() {
+        let text = "Teh weather is nice today.";
+        let checked = check(
+            &bundled_only(),
+            &SpellingSettings::default(),
+            text,
+            "en-US",
+            true,
+        )
+        .await;
+        assert_eq!(
+            checked.spelling,
+            SpellingStatus::Checked {
+                dictionary: "en-US".to_string()
+            }
+        );
+        let misspelled: Vec<&SpellingIssue> = checked
+            .issues
+            .iter()
+            .filter(|i| i.kind == SpellingIssueKind::Misspelled)
+            .collect();
+        assert_eq!(misspelled.len(), 1);
+        assert_eq!((misspelled[0].start, misspelled[0].end), (0, 3));
+        assert!(misspelled[0].suggestions.contains(&"The".to_string()));
+        // Spelled right, but a voice reads it as "whether" just the same
+        assert!(checked
+            .issues
+            .iter()
+            .any(|i| i.kind == SpellingIssueKind::Homophone && i.word == "weather"));
+    }
+
+    #[tokio::test]
+    async fn a_language_without_a_dictionary_is_unchecked() {
+        let checked = check(
+            &bundled_only(),
+            &SpellingSettings::default(),
+            "Ich weiß, dass das stimmt.",
+            "de-DE",
+            true,
+        )
+        .await;
+        assert!(matches!(checked.spelling, SpellingStatus::Unchecked { .. }));
+        assert!(checked
+            .issues
+            .iter()
+            .all(|i| i.kind == SpellingIssueKind::Homophone));
+    }
+
+    #[test]
+    fn skips_tags_fields_and_links() {
+        let text = "Visit <break time=\"1s\"/> {{name}} at https://example.com today";
+        let found: Vec<&str> = words(text).into_iter().map(|r| &text[r]).collect();
+        assert_eq!(found, ["Visit", "at", "today"]);
+    }
+}
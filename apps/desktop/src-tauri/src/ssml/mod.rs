@@ -0,0 +1,647 @@
+// Conversion of plain-text scripts into SSML
+pub mod policy;
+
+use serde::{Deserialize, Serialize};
+
+/// Google rejects `<break>` durations longer than 10 seconds.
+const MAX_BREAK_MS: u32 = 10_000;
+/// Words ending in a period that don't end a sentence.
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "st", "jr", "sr", "vs", "etc", "e.g", "i.e", "no", "approx",
+];
+/// Sentences this long get the intensity's sentence pause as it is;
+/// shorter ones get less and longer ones more.
+const TYPICAL_SENTENCE_WORDS: f64 = 12.0;
+/// Clauses shorter than this, like "Yes," get half the comma pause.
+const SHORT_CLAUSE_WORDS: usize = 3;
+/// More commas per word than this reads as a list, whose commas get half
+/// the pause.
+const LIST_COMMA_DENSITY: f64 = 0.25;
+/// Closing quotes and brackets that may follow punctuation.
+const CLOSERS: &[char] = &['"', '\'', ')', ']', '\u{201D}', '\u{2019}', '\u{00BB}'];
+const OPENERS: &[char] = &['"', '\'', '(', '[', '\u{201C}', '\u{2018}', '\u{00AB}'];
+
+/// Pauses added at punctuation so dense scripts don't run together.
+/// Where they go depends only on the text, so the same script always
+/// makes the same SSML and stays cached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreathingRoom {
+    #[default]
+    Off,
+    Subtle,
+    Normal,
+    Relaxed,
+}
+
+impl BreathingRoom {
+    /// The pause after a typical sentence and after a comma.
+    fn pauses(self) -> Option<(u32, u32)> {
+        match self {
+            Self::Off => None,
+            Self::Subtle => Some((150, 60)),
+            Self::Normal => Some((300, 120)),
+            Self::Relaxed => Some((500, 200)),
+        }
+    }
+}
+
+/// A pause `BreathingRoom` added, as the dry run reports it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InsertedPause {
+    /// Character offset in the script, just after the punctuation.
+    pub offset: usize,
+    /// The word the pause follows, punctuation included.
+    pub after: String,
+    pub ms: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    None,
+    Comma,
+    /// Semicolons, colons and dashes.
+    Clause,
+    Sentence,
+}
+
+/// Escapes `text` for use as SSML character data or a quoted attribute value.
+///
+/// Characters that are not allowed anywhere in an XML 1.0 document (most C0
+/// controls, U+FFFE, U+FFFF) are dropped: they can't be escaped, and any one
+/// of them makes Google reject the whole request with INVALID_ARGUMENT.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if is_xml_char(c) => escaped.push(c),
+            _ => {}
+        }
+    }
+    escaped
+}
+
+fn is_xml_char(c: char) -> bool {
+    matches!(c, '\u{20}'..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..='\u{10FFFF}')
+}
+
+pub fn break_tag(ms: u32) -> String {
+    format!("<break time=\"{}ms\"/>", ms.min(MAX_BREAK_MS))
+}
+
+/// The words of `line` with their byte offsets.
+fn words(line: &str) -> Vec<(usize, &str)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in line.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                words.push((s, &line[s..i]));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        words.push((s, &line[s..]));
+    }
+    words
+}
+
+/// The punctuation `word` ends with. A period ends a sentence unless it
+/// follows an abbreviation or an initial, or the next word is lowercase.
+fn mark(word: &str, next: Option<&str>) -> Mark {
+    let bare = word.trim_end_matches(CLOSERS);
+    if matches!(bare, "-" | "\u{2013}" | "\u{2014}") || bare.ends_with('\u{2014}') {
+        return Mark::Clause;
+    }
+    match bare.chars().last() {
+        Some(',') => Mark::Comma,
+        Some(';' | ':') => Mark::Clause,
+        Some('!' | '?' | '\u{2026}') => Mark::Sentence,
+        Some('.') => {
+            let stem = bare
+                .trim_end_matches('.')
+                .trim_start_matches(OPENERS)
+                .to_lowercase();
+            let abbreviation = stem.chars().count() == 1 || ABBREVIATIONS.contains(&stem.as_str());
+            let next_starts_sentence = next
+                .and_then(|n| n.trim_start_matches(OPENERS).chars().next())
+                .is_some_and(|c| !c.is_lowercase());
+            if !abbreviation && next_starts_sentence {
+                Mark::Sentence
+            } else {
+                Mark::None
+            }
+        }
+        _ => Mark::None,
+    }
+}
+
+fn round_ms(ms: f64) -> u32 {
+    ((ms / 10.0).round() * 10.0) as u32
+}
+
+/// Where `room` adds pauses within `line`: the byte offset just after each
+/// punctuation mark, and the pause. Longer sentences earn a longer pause
+/// after them; commas in lists and after very short clauses get less. The
+/// end of the line is left to the line and paragraph pauses.
+fn breaths(line: &str, room: BreathingRoom) -> Vec<(usize, u32)> {
+    let Some((sentence_ms, comma_ms)) = room.pauses() else {
+        return Vec::new();
+    };
+    let words = words(line);
+    let marks: Vec<Mark> = (0..words.len())
+        .map(|i| mark(words[i].1, words.get(i + 1).map(|w| w.1)))
+        .collect();
+    let end = |i: usize| words[i].0 + words[i].1.len();
+
+    let mut pauses = Vec::new();
+    let mut sentence_start = 0;
+    for (i, &word_mark) in marks.iter().enumerate() {
+        let last = i + 1 == words.len();
+        if word_mark != Mark::Sentence && !last {
+            continue;
+        }
+        let length = i + 1 - sentence_start;
+        let commas = marks[sentence_start..=i]
+            .iter()
+            .filter(|&&m| m == Mark::Comma)
+            .count();
+        let list = commas as f64 / length as f64 > LIST_COMMA_DENSITY;
+        let mut clause_start = sentence_start;
+        for (j, &clause_mark) in marks.iter().enumerate().take(i).skip(sentence_start) {
+            let weight = match clause_mark {
+                Mark::Comma => 1.0,
+                Mark::Clause => 1.5,
+                _ => continue,
+            };
+            let mut ms = comma_ms as f64 * weight;
+            if list || j + 1 - clause_start < SHORT_CLAUSE_WORDS {
+                ms /= 2.0;
+            }
+            clause_start = j + 1;
+            pauses.push((end(j), round_ms(ms)));
+        }
+        if !last {
+            let scale = (length as f64 / TYPICAL_SENTENCE_WORDS).clamp(0.75, 1.5);
+            pauses.push((end(i), round_ms(sentence_ms as f64 * scale)));
+        }
+        sentence_start = i + 1;
+    }
+    pauses
+}
+
+/// `line` escaped, with the pauses `room` adds as `<break>` tags, and
+/// whether it added any.
+fn escape_breathing(line: &str, room: BreathingRoom) -> (String, bool) {
+    let pauses = breaths(line, room);
+    let mut escaped = String::with_capacity(line.len());
+    let mut from = 0;
+    for &(at, ms) in &pauses {
+        escaped.push_str(&escape(&line[from..at]));
+        escaped.push(' ');
+        escaped.push_str(&break_tag(ms));
+        from = at;
+    }
+    escaped.push_str(&escape(&line[from..]));
+    (escaped, !pauses.is_empty())
+}
+
+/// The pauses `text_with_pauses` adds to `text` for `room`.
+pub fn inserted_pauses(text: &str, room: BreathingRoom) -> Vec<InsertedPause> {
+    let mut inserted = Vec::new();
+    let mut line_start = 0;
+    for raw in text.split(['\r', '\n']) {
+        let line = raw.trim();
+        let lead = raw.len() - raw.trim_start().len();
+        for (at, ms) in breaths(line, room) {
+            let byte = line_start + lead + at;
+            inserted.push(InsertedPause {
+                offset: text[..byte].chars().count(),
+                after: line[..at]
+                    .split_whitespace()
+                    .last()
+                    .unwrap_or_default()
+                    .to_string(),
+                ms,
+            });
+        }
+        line_start += raw.len() + 1;
+    }
+    inserted
+}
+
+/// Upgrades plain text to SSML so paragraph (and optionally line) breaks are
+/// spoken as pauses, along with any `breathing` pauses within the lines.
+/// Returns `None` when the text has no breaks that would be affected, so
+/// callers can keep sending it as plain text.
+pub fn text_with_pauses(
+    text: &str,
+    paragraph_pause_ms: u32,
+    line_pause_ms: u32,
+    breathing: BreathingRoom,
+) -> Option<String> {
+    let normalized = text.replace("\r\n", "\n").replace('\r', "\n");
+
+    let mut paragraphs: Vec<Vec<&str>> = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    for line in normalized.split('\n') {
+        let line = line.trim();
+        if line.is_empty() {
+            if !current.is_empty() {
+                paragraphs.push(std::mem::take(&mut current));
+            }
+        } else {
+            current.push(line);
+        }
+    }
+    if !current.is_empty() {
+        paragraphs.push(current);
+    }
+
+    let escaped: Vec<Vec<(String, bool)>> = paragraphs
+        .iter()
+        .map(|lines| {
+            lines
+                .iter()
+                .map(|line| escape_breathing(line, breathing))
+                .collect()
+        })
+        .collect();
+    let has_paragraph_breaks = paragraph_pause_ms > 0 && paragraphs.len() > 1;
+    let has_line_breaks = line_pause_ms > 0 && paragraphs.iter().any(|p| p.len() > 1);
+    let has_breaths = escaped.iter().flatten().any(|(_, breathed)| *breathed);
+    if !has_paragraph_breaks && !has_line_breaks && !has_breaths {
+        return None;
+    }
+
+    let line_separator = if line_pause_ms > 0 {
+        format!(" {} ", break_tag(line_pause_ms))
+    } else {
+        " ".to_string()
+    };
+    let paragraph_separator = if paragraph_pause_ms > 0 {
+        format!(" {} ", break_tag(paragraph_pause_ms))
+    } else {
+        " ".to_string()
+    };
+
+    let body = escaped
+        .iter()
+        .map(|lines| {
+            lines
+                .iter()
+                .map(|(line, _)| line.as_str())
+                .collect::<Vec<_>>()
+                .join(&line_separator)
+        })
+        .collect::<Vec<_>>()
+        .join(&paragraph_separator);
+
+    Some(format!("<speak>{}</speak>", body))
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ':')
+}
+
+/// Validates `&...;` at the start of `rest`, returning its length.
+fn entity_len(rest: &str) -> Result<usize, String> {
+    let end = rest
+        .find(';')
+        .filter(|&end| end <= 12)
+        .ok_or_else(|| "unterminated '&'; write &amp; for a literal ampersand".to_string())?;
+    let name = &rest[1..end];
+    let valid = match name.strip_prefix('#') {
+        Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16).is_ok(),
+        Some(dec) => dec.parse::<u32>().is_ok(),
+        None => matches!(name, "amp" | "lt" | "gt" | "quot" | "apos"),
+    };
+    if valid {
+        Ok(end + 1)
+    } else {
+        Err(format!("unknown entity &{};", name))
+    }
+}
+
+/// Checks that `ssml` is one well-formed `<speak>` element: tags balanced
+/// and properly nested, attributes quoted, entities known. Returns the first
+/// problem found. It does not check which elements Google supports.
+pub fn check_well_formed(ssml: &str) -> Result<(), String> {
+    let mut open: Vec<&str> = Vec::new();
+    let mut closed_root = false;
+    let mut rest = ssml.trim();
+    if !rest.starts_with("<speak") {
+        return Err("SSML must start with <speak>".to_string());
+    }
+
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '<' => {
+                let end = rest
+                    .find('>')
+                    .ok_or_else(|| "unterminated tag".to_string())?;
+                let tag = &rest[1..end];
+                rest = &rest[end + 1..];
+                if closed_root {
+                    return Err("content after </speak>".to_string());
+                }
+
+                if let Some(name) = tag.strip_prefix('/') {
+                    let name = name.trim_end();
+                    match open.pop() {
+                        Some(expected) if expected == name => {}
+                        Some(expected) => {
+                            return Err(format!("</{}> closes <{}>", name, expected));
+                        }
+                        None => return Err(format!("</{}> has no opening tag", name)),
+                    }
+                    closed_root = open.is_empty();
+                    continue;
+                }
+
+                let self_closing = tag.ends_with('/');
+                let tag = tag.trim_end_matches('/');
+                let name_len = tag.find(|c: char| !is_name_char(c)).unwrap_or(tag.len());
+                let (name, mut attrs) = tag.split_at(name_len);
+                if name.is_empty() {
+                    return Err(format!("malformed tag <{}>", tag));
+                }
+                // name="value" pairs, with either quote style
+                loop {
+                    attrs = attrs.trim_start();
+                    if attrs.is_empty() {
+                        break;
+                    }
+                    let (attr, value) = attrs
+                        .split_once('=')
+                        .ok_or_else(|| format!("attribute without a value in <{}>", name))?;
+                    let attr = attr.trim();
+                    if attr.is_empty() || !attr.chars().all(is_name_char) {
+                        return Err(format!("malformed attribute in <{}>", name));
+                    }
+                    let value = value.trim_start();
+                    let quote = value
+                        .chars()
+                        .next()
+                        .filter(|q| matches!(q, '"' | '\''))
+                        .ok_or_else(|| format!("unquoted value for {} in <{}>", attr, name))?;
+                    let close = value[1..]
+                        .find(quote)
+                        .ok_or_else(|| format!("unterminated value for {} in <{}>", attr, name))?;
+                    attrs = &value[close + 2..];
+                }
+
+                if open.is_empty() && name != "speak" {
+                    return Err(format!("<{}> outside <speak>", name));
+                }
+                if !self_closing {
+                    open.push(name);
+                } else if open.is_empty() {
+                    closed_root = true;
+                }
+            }
+            '&' => {
+                let len = entity_len(rest)?;
+                rest = &rest[len..];
+            }
+            c => {
+                if closed_root && !c.is_whitespace() {
+                    return Err("content after </speak>".to_string());
+                }
+                if !is_xml_char(c) && !matches!(c, '\t' | '\n' | '\r') {
+                    return Err(format!(
+                        "character U+{:04X} is not allowed in SSML",
+                        c as u32
+                    ));
+                }
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+
+    match open.last() {
+        Some(name) => Err(format!("<{}> is never closed", name)),
+        None => Ok(()),
+    }
+}
+
+/// The text an SSML document speaks, for voices that take no SSML: tags
+/// become spaces, entities are decoded and whitespace runs collapse to one.
+pub fn to_plain_text(ssml: &str) -> String {
+    let mut text = String::with_capacity(ssml.len());
+    let mut rest = ssml;
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '<' => {
+                let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+                text.push(' ');
+                rest = &rest[end..];
+            }
+            '&' => {
+                let decoded = entity_len(rest).ok().and_then(|len| {
+                    let name = &rest[1..len - 1];
+                    let c = match name.strip_prefix('#') {
+                        Some(hex) if hex.starts_with(['x', 'X']) => {
+                            u32::from_str_radix(&hex[1..], 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                        }
+                        Some(dec) => dec.parse().ok().and_then(char::from_u32),
+                        None => match name {
+                            "amp" => Some('&'),
+                            "lt" => Some('<'),
+                            "gt" => Some('>'),
+                            "quot" => Some('"'),
+                            "apos" => Some('\''),
+                            _ => None,
+                        },
+                    }?;
+                    Some((c, len))
+                });
+                let (c, len) = decoded.unwrap_or(('&', 1));
+                text.push(c);
+                rest = &rest[len..];
+            }
+            c => {
+                text.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pauses(text: &str, paragraph_ms: u32, line_ms: u32) -> Option<String> {
+        text_with_pauses(text, paragraph_ms, line_ms, BreathingRoom::Off)
+    }
+
+    #[test]
+    fn escapes_markup_and_drops_what_xml_forbids() {
+        assert_eq!(
+            escape("a<b>&\"'\u{0}\u{FFFE}\tz"),
+            "a&lt;b&gt;&amp;&quot;&apos;\tz"
+        );
+        assert_eq!(escape("plain text"), "plain text");
+    }
+
+    #[test]
+    fn paragraphs_become_breaks() {
+        assert_eq!(
+            pauses("One.\n\nTwo.", 800, 0).unwrap(),
+            r#"<speak>One. <break time="800ms"/> Two.</speak>"#
+        );
+        // Windows line endings and blank lines of spaces are the same break
+        assert_eq!(
+            pauses("One.\r\n  \r\n\r\nTwo.", 800, 0),
+            pauses("One.\n\nTwo.", 800, 0)
+        );
+    }
+
+    #[test]
+    fn lines_break_only_when_asked() {
+        assert_eq!(
+            pauses("A\nB\n\nC", 800, 300).unwrap(),
+            r#"<speak>A <break time="300ms"/> B <break time="800ms"/> C</speak>"#
+        );
+        assert_eq!(
+            pauses("A\nB\n\nC", 800, 0).unwrap(),
+            r#"<speak>A B <break time="800ms"/> C</speak>"#
+        );
+    }
+
+    #[test]
+    fn text_without_breaks_stays_plain() {
+        assert_eq!(pauses("Just one line.", 800, 300), None);
+        assert_eq!(pauses("A\n\nB", 0, 0), None);
+        assert_eq!(pauses("A\nB", 800, 0), None);
+    }
+
+    #[test]
+    fn the_ssml_is_escaped_and_within_googles_limits() {
+        let ssml = pauses("Fish & chips\n\n<b>", 20_000, 0).unwrap();
+        assert_eq!(
+            ssml,
+            r#"<speak>Fish &amp; chips <break time="10000ms"/> &lt;b&gt;</speak>"#
+        );
+        assert_eq!(check_well_formed(&ssml), Ok(()));
+    }
+
+    #[test]
+    fn checks_well_formedness() {
+        assert_eq!(
+            check_well_formed("<speak>Hi <break time='1s'/></speak>"),
+            Ok(())
+        );
+        for bad in [
+            "Hi",
+            "<speak>Hi",
+            "<speak><p>Hi</speak></p>",
+            "<speak>Fish & chips</speak>",
+            "<speak><break time=1s/></speak>",
+            "<speak>Hi</speak> more",
+        ] {
+            assert!(check_well_formed(bad).is_err(), "{}", bad);
+        }
+    }
+
+    /// Abbreviations, an initial, a list, a short clause, semicolons and
+    /// dashes, quotes, and a sentence ending its line.
+    const BREATHING_SCRIPT: &str = "Dr. Smith arrived at 9 a.m. sharp. We bought apples, pears, \
+        plums, figs and grapes. Wait, what? Then, after a very long and winding walk through the \
+        old part of town, we finally rested; it was late \u{2014} too late.\n\"Yes.\" She left.";
+
+    /// `BREATHING_SCRIPT` as sent for each intensity, with the pauses after
+    /// "sharp.", the commas of the list, "grapes.", "Wait,", "what?",
+    /// "Then,", "town,", "rested;", the dash and "\"Yes.\"".
+    fn breathing_fixture(sentence: u32, comma: u32, long_comma: u32, clause: u32) -> String {
+        format!(
+            "<speak>Dr. Smith arrived at 9 a.m. sharp. {s} We bought apples, {c} pears, {c} plums, \
+             {c} figs and grapes. {s} Wait, {c} what? {s} Then, {c} after a very long and winding \
+             walk through the old part of town, {l} we finally rested; {k} it was late \u{2014} \
+             {k} too late. &quot;Yes.&quot; {s} She left.</speak>",
+            s = break_tag(sentence),
+            c = break_tag(comma),
+            l = break_tag(long_comma),
+            k = break_tag(clause),
+        )
+    }
+
+    #[test]
+    fn breathing_room_adds_pauses_per_intensity() {
+        let fixtures = [
+            (BreathingRoom::Subtle, breathing_fixture(110, 30, 60, 90)),
+            (BreathingRoom::Normal, breathing_fixture(230, 60, 120, 180)),
+            (
+                BreathingRoom::Relaxed,
+                breathing_fixture(380, 100, 200, 300),
+            ),
+        ];
+        for (room, expected) in fixtures {
+            let ssml = text_with_pauses(BREATHING_SCRIPT, 800, 0, room).unwrap();
+            assert_eq!(ssml, expected, "{:?}", room);
+            assert_eq!(check_well_formed(&ssml), Ok(()));
+        }
+        assert_eq!(
+            text_with_pauses(BREATHING_SCRIPT, 800, 0, BreathingRoom::Off),
+            None
+        );
+        assert!(inserted_pauses(BREATHING_SCRIPT, BreathingRoom::Off).is_empty());
+    }
+
+    #[test]
+    fn longer_sentences_earn_longer_pauses() {
+        let short = "Go now. Stop.";
+        let long = "We walked for hours and hours along the winding river until night slowly fell \
+            over the quiet hills. Stop.";
+        let after_first = |text: &str| inserted_pauses(text, BreathingRoom::Normal)[0].ms;
+        // A quarter shorter below a typical sentence, half longer far above it
+        assert_eq!(after_first(short), 230);
+        assert_eq!(after_first(long), 450);
+    }
+
+    #[test]
+    fn reported_pauses_match_the_script() {
+        let pauses = inserted_pauses(BREATHING_SCRIPT, BreathingRoom::Normal);
+        let after: Vec<&str> = pauses.iter().map(|p| p.after.as_str()).collect();
+        assert_eq!(
+            after,
+            [
+                "sharp.", "apples,", "pears,", "plums,", "grapes.", "Wait,", "what?", "Then,",
+                "town,", "rested;", "\u{2014}", "\"Yes.\""
+            ]
+        );
+        for pause in &pauses {
+            let before: String = BREATHING_SCRIPT.chars().take(pause.offset).collect();
+            assert!(before.ends_with(&pause.after), "{:?}", pause);
+        }
+    }
+
+    #[test]
+    fn breathing_room_is_deterministic() {
+        for room in [
+            BreathingRoom::Subtle,
+            BreathingRoom::Normal,
+            BreathingRoom::Relaxed,
+        ] {
+            let first = text_with_pauses(BREATHING_SCRIPT, 800, 300, room);
+            for _ in 0..20 {
+                assert_eq!(text_with_pauses(BREATHING_SCRIPT, 800, 300, room), first);
+            }
+            // Line endings don't move the pauses
+            let crlf = BREATHING_SCRIPT.replace('\n', "\r\n");
+            assert_eq!(text_with_pauses(&crlf, 800, 300, room), first);
+        }
+    }
+}
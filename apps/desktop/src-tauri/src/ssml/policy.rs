@@ -0,0 +1,691 @@
+// What SSML may reach Google: an element and attribute allowlist, a nesting
+// depth limit and a size limit on attribute values. `<audio>` and
+// `<lexicon>` would fetch arbitrary URLs, and deep nesting bloats requests;
+// both are stripped with a finding per change, or refused outright
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+
+/// Elements that may be sent, with the attributes each may carry.
+const ALLOWED: &[(&str, &[&str])] = &[
+    ("speak", &["version", "xmlns", "xml:lang"]),
+    ("p", &[]),
+    ("s", &[]),
+    ("break", &["time", "strength"]),
+    ("prosody", &["rate", "pitch", "volume"]),
+    ("emphasis", &["level"]),
+    ("say-as", &["interpret-as", "format", "detail"]),
+    ("sub", &["alias"]),
+    ("phoneme", &["alphabet", "ph"]),
+    ("mark", &["name"]),
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SsmlPolicyMode {
+    /// Remove what the policy doesn't allow and send the rest, logging each
+    /// removal.
+    #[default]
+    Strip,
+    /// Refuse the request with `SsmlRejected` if anything would be removed.
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SsmlPolicy {
+    pub mode: SsmlPolicyMode,
+    /// Deepest element nesting allowed, `<speak>` being 1. Elements below
+    /// it are unwrapped, keeping their text.
+    pub max_depth: usize,
+    /// Longest attribute value allowed, in bytes, e.g. of a `<sub>` alias.
+    pub max_attribute_bytes: usize,
+}
+
+impl Default for SsmlPolicy {
+    fn default() -> Self {
+        Self {
+            mode: SsmlPolicyMode::Strip,
+            max_depth: 8,
+            max_attribute_bytes: 256,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyAction {
+    /// The element and everything in it.
+    RemovedElement,
+    /// The element's tags, keeping what it contains.
+    UnwrappedElement,
+    RemovedAttribute,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PolicyFinding {
+    /// Of the tag, in characters from the start of the SSML.
+    pub offset: usize,
+    pub element: String,
+    pub attribute: Option<String>,
+    pub action: PolicyAction,
+    pub reason: String,
+}
+
+/// The SSML as the policy leaves it, and what it changed.
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyReport {
+    pub mode: SsmlPolicyMode,
+    pub ssml: String,
+    pub findings: Vec<PolicyFinding>,
+    /// Set in `Reject` mode when there are findings; the request isn't sent.
+    pub rejected: bool,
+}
+
+struct Open<'a> {
+    name: &'a str,
+    /// Its tags are in the output.
+    kept: bool,
+    /// Nothing inside it is.
+    drops_content: bool,
+}
+
+/// `name="value"` pairs of a tag, with the value as written, quotes and
+/// all. Stops at the first malformed pair, which `check_well_formed` has
+/// already refused.
+fn attributes(mut attrs: &str) -> Vec<(&str, &str)> {
+    let mut pairs = Vec::new();
+    loop {
+        attrs = attrs.trim_start();
+        let Some((attr, value)) = attrs.split_once('=') else {
+            return pairs;
+        };
+        let value = value.trim_start();
+        let Some(quote) = value.chars().next().filter(|q| matches!(q, '"' | '\'')) else {
+            return pairs;
+        };
+        let Some(close) = value[1..].find(quote) else {
+            return pairs;
+        };
+        pairs.push((attr.trim(), &value[..close + 2]));
+        attrs = &value[close + 2..];
+    }
+}
+
+/// Converts the findings' byte offsets, in order, to character offsets.
+fn to_char_offsets(ssml: &str, findings: &mut [PolicyFinding]) {
+    let mut chars = 0;
+    let mut bytes = 0;
+    for finding in findings {
+        chars += ssml[bytes..finding.offset].chars().count();
+        bytes = finding.offset;
+        finding.offset = chars;
+    }
+}
+
+/// Applies `policy` to `ssml`. Fails with the reason when `ssml` isn't
+/// well-formed, as it can't be changed safely then. The output is never
+/// longer than the input.
+pub fn apply(ssml: &str, policy: &SsmlPolicy) -> Result<PolicyReport, String> {
+    super::check_well_formed(ssml)?;
+    let mut out = String::with_capacity(ssml.len());
+    let mut findings = Vec::new();
+    let mut open: Vec<Open> = Vec::new();
+    // Of `open`: the ones kept, and the ones dropping their content
+    let (mut kept, mut dropping) = (0, 0);
+    let mut rest = ssml;
+
+    while let Some(c) = rest.chars().next() {
+        let offset = ssml.len() - rest.len();
+        let suppressed = dropping > 0;
+        if c != '<' {
+            if !suppressed {
+                out.push(c);
+            }
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if tag.starts_with('/') {
+            if let Some(element) = open.pop() {
+                kept -= usize::from(element.kept);
+                dropping -= usize::from(element.drops_content);
+                if element.kept && dropping == 0 {
+                    out.push_str("</");
+                    out.push_str(element.name);
+                    out.push('>');
+                }
+            }
+            continue;
+        }
+
+        let self_closing = tag.ends_with('/');
+        let tag = tag.trim_end_matches('/');
+        let name_len = tag
+            .find(|c: char| !super::is_name_char(c))
+            .unwrap_or(tag.len());
+        let (name, attrs) = tag.split_at(name_len);
+        let depth = kept + 1;
+        let mut element = Open {
+            name,
+            kept: false,
+            drops_content: false,
+        };
+        let mut finding = |action, attribute: Option<&str>, reason: String| {
+            findings.push(PolicyFinding {
+                offset,
+                element: name.to_string(),
+                attribute: attribute.map(str::to_string),
+                action,
+                reason,
+            })
+        };
+        let allowed = ALLOWED.iter().find(|(allowed, _)| *allowed == name);
+        match allowed {
+            _ if suppressed => {}
+            None if name == "audio" || name == "lexicon" => {
+                let source = attributes(attrs)
+                    .into_iter()
+                    .find(|(attr, _)| matches!(*attr, "src" | "uri"))
+                    .map(|(_, value)| value.trim_matches(['"', '\'']).to_string())
+                    .unwrap_or_default();
+                // An audio element's content is the text spoken in its place
+                element.drops_content = name == "lexicon";
+                finding(
+                    if element.drops_content {
+                        PolicyAction::RemovedElement
+                    } else {
+                        PolicyAction::UnwrappedElement
+                    },
+                    None,
+                    format!("<{}> references to {} aren't allowed", name, source),
+                );
+            }
+            None => finding(
+                PolicyAction::UnwrappedElement,
+                None,
+                format!("<{}> isn't allowed", name),
+            ),
+            Some(_) if depth > policy.max_depth && depth > 1 => finding(
+                PolicyAction::UnwrappedElement,
+                None,
+                format!("nested deeper than {} elements", policy.max_depth),
+            ),
+            Some((_, allowed_attributes)) => {
+                element.kept = true;
+                out.push('<');
+                out.push_str(name);
+                for (attr, value) in attributes(attrs) {
+                    let bytes = value.len().saturating_sub(2);
+                    if !allowed_attributes.contains(&attr) {
+                        finding(
+                            PolicyAction::RemovedAttribute,
+                            Some(attr),
+                            format!("{} isn't allowed on <{}>", attr, name),
+                        );
+                    } else if bytes > policy.max_attribute_bytes {
+                        finding(
+                            PolicyAction::RemovedAttribute,
+                            Some(attr),
+                            format!(
+                                "{} is {} bytes, over the {}-byte limit",
+                                attr, bytes, policy.max_attribute_bytes
+                            ),
+                        );
+                    } else {
+                        out.push(' ');
+                        out.push_str(attr);
+                        out.push('=');
+                        out.push_str(value);
+                    }
+                }
+                out.push_str(if self_closing { "/>" } else { ">" });
+            }
+        }
+        if !self_closing {
+            kept += usize::from(element.kept);
+            dropping += usize::from(element.drops_content);
+            open.push(element);
+        }
+    }
+
+    to_char_offsets(ssml, &mut findings);
+    Ok(PolicyReport {
+        mode: policy.mode,
+        rejected: policy.mode == SsmlPolicyMode::Reject && !findings.is_empty(),
+        ssml: out,
+        findings,
+    })
+}
+
+/// The SSML to send in place of `ssml`: stripped and logged, or refused
+/// with `SsmlRejected`, per the policy's mode.
+pub fn enforce(ssml: &str, policy: &SsmlPolicy) -> Result<String, AppError> {
+    let report = apply(ssml, policy).map_err(|reason| AppError::SsmlRejected {
+        message: format!("SSML is malformed: {}", reason),
+        findings: Vec::new(),
+    })?;
+    if report.rejected {
+        return Err(AppError::SsmlRejected {
+            message: report.findings[0].reason.clone(),
+            findings: report.findings,
+        });
+    }
+    for finding in &report.findings {
+        log::warn!(
+            "SSML policy: {} at character {}",
+            finding.reason,
+            finding.offset
+        );
+    }
+    Ok(report.ssml)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(ssml: &str) -> PolicyReport {
+        apply(ssml, &SsmlPolicy::default()).unwrap()
+    }
+
+    fn actions(report: &PolicyReport) -> Vec<(&str, Option<&str>, PolicyAction)> {
+        report
+            .findings
+            .iter()
+            .map(|f| (f.element.as_str(), f.attribute.as_deref(), f.action))
+            .collect()
+    }
+
+    #[test]
+    fn leaves_allowed_ssml_as_it_is() {
+        let ssml = "<speak xml:lang=\"en-US\"><p><s>Hi <break time=\"1s\"/> \
+                    <emphasis level='strong'>there</emphasis>, \
+                    <say-as interpret-as=\"characters\">SSML</say-as></s></p></speak>";
+        let report = strip(ssml);
+        assert_eq!(report.ssml, ssml);
+        assert!(report.findings.is_empty());
+        assert!(!report.rejected);
+    }
+
+    #[test]
+    fn strips_what_the_policy_does_not_allow() {
+        let report = strip(
+            "<speak><audio src=\"https://example.com/a.mp3\">a chime</audio>\
+             <lexicon uri=\"https://example.com/l.pls\">ignored</lexicon>\
+             <voice name=\"x\">Hello</voice> \
+             <prosody rate=\"slow\" onload=\"x\">there</prosody></speak>",
+        );
+        assert_eq!(
+            report.ssml,
+            "<speak>a chimeHello <prosody rate=\"slow\">there</prosody></speak>"
+        );
+        assert_eq!(
+            actions(&report),
+            [
+                ("audio", None, PolicyAction::UnwrappedElement),
+                ("lexicon", None, PolicyAction::RemovedElement),
+                ("voice", None, PolicyAction::UnwrappedElement),
+                ("prosody", Some("onload"), PolicyAction::RemovedAttribute),
+            ]
+        );
+        assert_eq!(
+            report.findings[0].reason,
+            "<audio> references to https://example.com/a.mp3 aren't allowed"
+        );
+    }
+
+    #[test]
+    fn unwraps_elements_nested_too_deep() {
+        let policy = SsmlPolicy {
+            max_depth: 2,
+            ..SsmlPolicy::default()
+        };
+        let report = apply(
+            "<speak><p><s><emphasis>deep</emphasis></s></p></speak>",
+            &policy,
+        )
+        .unwrap();
+        assert_eq!(report.ssml, "<speak><p>deep</p></speak>");
+        assert_eq!(
+            actions(&report),
+            [
+                ("s", None, PolicyAction::UnwrappedElement),
+                ("emphasis", None, PolicyAction::UnwrappedElement),
+            ]
+        );
+        // The root is kept whatever the limit
+        let policy = SsmlPolicy {
+            max_depth: 0,
+            ..SsmlPolicy::default()
+        };
+        let report = apply("<speak><p>hi</p></speak>", &policy).unwrap();
+        assert_eq!(report.ssml, "<speak>hi</speak>");
+    }
+
+    #[test]
+    fn removes_long_attribute_values() {
+        let policy = SsmlPolicy {
+            max_attribute_bytes: 4,
+            ..SsmlPolicy::default()
+        };
+        let report = apply(
+            "<speak><sub alias=\"four\">4</sub><sub alias=\"fives\">5</sub></speak>",
+            &policy,
+        )
+        .unwrap();
+        assert_eq!(
+            report.ssml,
+            "<speak><sub alias=\"four\">4</sub><sub>5</sub></speak>"
+        );
+        assert_eq!(
+            report.findings[0].reason,
+            "alias is 5 bytes, over the 4-byte limit"
+        );
+    }
+
+    #[test]
+    fn findings_are_at_character_offsets() {
+        let report = strip("<speak>héllo wörld <voice>x</voice> <font>y</font></speak>");
+        let offsets: Vec<usize> = report.findings.iter().map(|f| f.offset).collect();
+        assert_eq!(offsets, [19, 36]);
+    }
+
+    #[test]
+    fn reject_mode_refuses_what_strip_mode_sends() {
+        let ssml = "<speak><audio src=\"https://example.com/a.mp3\"/>Hi</speak>";
+        assert_eq!(
+            enforce(ssml, &SsmlPolicy::default()).unwrap(),
+            "<speak>Hi</speak>"
+        );
+
+        let reject = SsmlPolicy {
+            mode: SsmlPolicyMode::Reject,
+            ..SsmlPolicy::default()
+        };
+        assert!(apply(ssml, &reject).unwrap().rejected);
+        match enforce(ssml, &reject) {
+            Err(AppError::SsmlRejected { message, findings }) => {
+                assert_eq!(findings.len(), 1);
+                assert_eq!(message, findings[0].reason);
+            }
+            other => panic!("expected SsmlRejected, got {:?}", other),
+        }
+        assert_eq!(
+            enforce("<speak>Hi</speak>", &reject).unwrap(),
+            "<speak>Hi</speak>"
+        );
+        // Malformed SSML is refused whatever the mode
+        assert!(matches!(
+            enforce("<speak><p>Hi</speak>", &SsmlPolicy::default()),
+            Err(AppError::SsmlRejected { findings, .. }) if findings.is_empty()
+        ));
+    }
+
+    /// A seeded xorshift generator, so a failing case reruns from its seed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn new(seed: u64) -> Self {
+            Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+
+        fn chance(&mut self, percent: usize) -> bool {
+            self.below(100) < percent
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.below(items.len())]
+        }
+    }
+
+    const NAMES: &[&str] = &[
+        "speak", "p", "s", "break", "prosody", "emphasis", "say-as", "sub", "phoneme", "mark",
+        "audio", "lexicon", "voice", "font", "x:y",
+    ];
+    const ATTRIBUTES: &[&str] = &[
+        "time", "rate", "level", "alias", "name", "ph", "xml:lang", "src", "uri", "onload",
+    ];
+    const TEXT: &[&str] = &[
+        "Hello",
+        " ",
+        "héllo",
+        "日本語",
+        "🙂",
+        "&amp;",
+        "&lt;",
+        "\n",
+        ", ",
+        ".",
+    ];
+    /// Breaks well-formed markup in the ways hand-written SSML does.
+    const BREAKAGE: &[&str] = &[
+        "<",
+        ">",
+        "</p>",
+        "<p>",
+        "<speak>",
+        "</speak>",
+        "&",
+        "\"",
+        "'",
+        "/>",
+        "<>",
+        "</>",
+        "<p a=\"1>",
+        "<p a=1>",
+        "<p a>",
+        "<!-- x -->",
+        "<?xml?>",
+        "é",
+    ];
+
+    fn attribute(rng: &mut Rng) -> String {
+        let quote = rng.pick(&["\"", "'"]);
+        let value = rng.pick(&["x", "é", "1s"]).repeat(rng.below(300));
+        format!(" {}={}{}{}", rng.pick(ATTRIBUTES), quote, value, quote)
+    }
+
+    /// Random well-formed SSML, nested up to 12 deep.
+    fn markup(rng: &mut Rng) -> String {
+        let max_depth = 1 + rng.below(12);
+        let mut out = String::from("<speak>");
+        let mut open = vec!["speak"];
+        for _ in 0..rng.below(80) {
+            match rng.below(4) {
+                0 if open.len() < max_depth => {
+                    let name = rng.pick(NAMES);
+                    out.push('<');
+                    out.push_str(name);
+                    for _ in 0..rng.below(3) {
+                        out.push_str(&attribute(rng));
+                    }
+                    if rng.chance(20) {
+                        out.push_str("/>");
+                    } else {
+                        out.push('>');
+                        open.push(name);
+                    }
+                }
+                1 if open.len() > 1 => {
+                    out.push_str(&format!("</{}>", open.pop().unwrap()));
+                }
+                _ => out.push_str(rng.pick(TEXT)),
+            }
+        }
+        while let Some(name) = open.pop() {
+            out.push_str(&format!("</{}>", name));
+        }
+        out
+    }
+
+    /// `ssml` with a few insertions, deletions and maybe a cut, at
+    /// character boundaries.
+    fn break_up(rng: &mut Rng, ssml: &str) -> String {
+        let mut chars: Vec<char> = ssml.chars().collect();
+        for _ in 0..1 + rng.below(4) {
+            let at = rng.below(chars.len() + 1);
+            match rng.below(3) {
+                0 => {
+                    let end = (at + rng.below(8)).min(chars.len());
+                    chars.drain(at..end);
+                }
+                1 if rng.chance(30) => chars.truncate(at),
+                _ => {
+                    let inserted: Vec<char> = rng.pick(BREAKAGE).chars().collect();
+                    chars.splice(at..at, inserted);
+                }
+            }
+        }
+        chars.into_iter().collect()
+    }
+
+    /// Tags and text in any order, balanced or not.
+    fn soup(rng: &mut Rng) -> String {
+        let mut out = String::new();
+        if rng.chance(80) {
+            out.push_str("<speak>");
+        }
+        for _ in 0..rng.below(40) {
+            match rng.below(4) {
+                0 => out.push_str(&format!("<{}>", rng.pick(NAMES))),
+                1 => out.push_str(&format!("</{}>", rng.pick(NAMES))),
+                2 => out.push_str(rng.pick(BREAKAGE)),
+                _ => out.push_str(rng.pick(TEXT)),
+            }
+        }
+        out
+    }
+
+    /// Deepest element nesting of well-formed `ssml`.
+    fn depth(ssml: &str) -> usize {
+        let (mut depth, mut deepest) = (0usize, 0);
+        for tag in ssml.split('<').skip(1) {
+            let tag = &tag[..tag.find('>').unwrap()];
+            if tag.starts_with('/') {
+                depth -= 1;
+            } else if !tag.ends_with('/') {
+                depth += 1;
+                deepest = deepest.max(depth);
+            }
+        }
+        deepest
+    }
+
+    /// What holds for any input: no panic, and for SSML the policy accepts,
+    /// well-formed output no longer than the input, within the depth limit,
+    /// that the policy leaves alone.
+    fn check(seed: u64, ssml: &str, policy: &SsmlPolicy) -> bool {
+        let Ok(report) = apply(ssml, policy) else {
+            return false;
+        };
+        let case = format!("seed {}: {:?} -> {:?}", seed, ssml, report.ssml);
+        assert!(report.ssml.len() <= ssml.len(), "{}", case);
+        if let Err(e) = crate::ssml::check_well_formed(&report.ssml) {
+            panic!("{}: {}", case, e);
+        }
+        assert!(depth(&report.ssml) <= policy.max_depth.max(1), "{}", case);
+        assert!(
+            report.findings.len() <= ssml.matches(['<', '=']).count(),
+            "{}",
+            case
+        );
+        let chars = ssml.chars().count();
+        let offsets: Vec<usize> = report.findings.iter().map(|f| f.offset).collect();
+        assert!(offsets.windows(2).all(|w| w[0] <= w[1]), "{}", case);
+        assert!(offsets.iter().all(|&o| o < chars), "{}", case);
+
+        let again = apply(&report.ssml, policy).unwrap();
+        assert_eq!(again.ssml, report.ssml, "{}", case);
+        assert!(again.findings.is_empty(), "{}: {:?}", case, again.findings);
+
+        let reject = SsmlPolicy {
+            mode: SsmlPolicyMode::Reject,
+            ..*policy
+        };
+        let rejected = apply(ssml, &reject).unwrap();
+        assert_eq!(rejected.rejected, !report.findings.is_empty(), "{}", case);
+        assert_eq!(rejected.findings, report.findings, "{}", case);
+        true
+    }
+
+    fn policies() -> [SsmlPolicy; 3] {
+        [
+            SsmlPolicy::default(),
+            SsmlPolicy {
+                max_depth: 2,
+                max_attribute_bytes: 8,
+                ..SsmlPolicy::default()
+            },
+            SsmlPolicy {
+                max_depth: 0,
+                max_attribute_bytes: 0,
+                ..SsmlPolicy::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn random_well_formed_markup_keeps_the_invariants() {
+        for seed in 0..1000 {
+            let mut rng = Rng::new(seed);
+            let ssml = markup(&mut rng);
+            for policy in &policies() {
+                assert!(check(seed, &ssml, policy), "seed {}: {:?}", seed, ssml);
+            }
+        }
+    }
+
+    #[test]
+    fn random_unbalanced_markup_is_refused_or_kept_in_bounds() {
+        let mut accepted = 0;
+        for seed in 0..3000 {
+            let mut rng = Rng::new(seed);
+            let ssml = if rng.chance(50) {
+                let well_formed = markup(&mut rng);
+                break_up(&mut rng, &well_formed)
+            } else {
+                soup(&mut rng)
+            };
+            for policy in &policies() {
+                accepted += usize::from(check(seed, &ssml, policy));
+            }
+        }
+        // Some breakage leaves well-formed SSML, e.g. a deleted text run
+        assert!(accepted > 0);
+    }
+
+    #[test]
+    fn deep_and_long_markup_stays_in_bounds() {
+        let deep = format!(
+            "<speak>{}x{}</speak>",
+            "<p>".repeat(20_000),
+            "</p>".repeat(20_000)
+        );
+        let lexicons = format!(
+            "<speak>{}x{}</speak>",
+            "<lexicon uri='a'>".repeat(20_000),
+            "</lexicon>".repeat(20_000)
+        );
+        let long = format!("<speak>{}</speak>", "<voice>a</voice>".repeat(20_000));
+        for (seed, ssml) in [deep, lexicons, long].iter().enumerate() {
+            for policy in &policies() {
+                assert!(check(seed as u64, ssml, policy));
+            }
+        }
+        let unclosed = format!("<speak>{}", "<p>".repeat(20_000));
+        assert!(apply(&unclosed, &SsmlPolicy::default()).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+// A support bundle: one zip of what a bug report needs, gathered by the user
+// with one click. Holds the app's build and state, its settings with the
+// extra backend environment redacted (it may carry tokens), the command
+// metrics of the session and the ends of the log files
+use serde::Serialize;
+use std::fs;
+use std::io::{Cursor, Read as _, Seek, SeekFrom, Write as _};
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+use crate::error::AppError;
+use crate::metrics::{CommandMetrics, CommandStats};
+use crate::paths::roots::{AllowedRoots, Write};
+use crate::paths::{AppPaths, ExportedPaths};
+use crate::read_only::WriteAccess;
+use crate::settings::{Settings, SettingsStore};
+use crate::tts::TtsService;
+
+/// Most of each log file kept, from its end.
+const MAX_LOG_BYTES: u64 = 4 * 1024 * 1024;
+/// Stands in for each value of `backend_extra_env`.
+const REDACTED: &str = "<redacted>";
+
+/// What goes into a bundle, gathered before it's written.
+struct Contents {
+    /// `get_app_info`, as JSON.
+    app: Vec<u8>,
+    settings: Settings,
+    metrics: CommandMetrics,
+    log_dir: PathBuf,
+}
+
+fn json(value: &impl Serialize) -> Result<Vec<u8>, AppError> {
+    serde_json::to_vec_pretty(value).map_err(|e| AppError::Io {
+        message: e.to_string(),
+    })
+}
+
+/// The last `MAX_LOG_BYTES` of the file at `path`.
+fn log_tail(path: &Path) -> Result<Vec<u8>, AppError> {
+    let mut file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(MAX_LOG_BYTES)))?;
+    let mut tail = Vec::with_capacity(len.min(MAX_LOG_BYTES) as usize);
+    file.read_to_end(&mut tail)?;
+    Ok(tail)
+}
+
+fn bundle(contents: Contents) -> Result<Vec<u8>, AppError> {
+    let Contents {
+        app,
+        mut settings,
+        metrics,
+        log_dir,
+    } = contents;
+    for value in settings.backend_extra_env.values_mut() {
+        *value = REDACTED.to_string();
+    }
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+    for (name, bytes) in [
+        ("app.json", app),
+        ("settings.json", json(&settings)?),
+        ("command_metrics.json", json(&metrics)?),
+    ] {
+        zip.start_file(name, options)?;
+        zip.write_all(&bytes)?;
+    }
+    // No logs yet is no reason to fail
+    let mut logs: Vec<PathBuf> = match fs::read_dir(&log_dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.is_file())
+            .collect(),
+        Err(e) => {
+            log::warn!(
+                "Support bundle without logs from {}: {}",
+                log_dir.display(),
+                e
+            );
+            Vec::new()
+        }
+    };
+    logs.sort();
+    for path in logs {
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        match log_tail(&path) {
+            Ok(tail) => {
+                zip.start_file(format!("logs/{}", name), options)?;
+                zip.write_all(&tail)?;
+            }
+            Err(e) => log::warn!(
+                "Leaving {} out of the support bundle: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+/// Writes a support bundle to `output_path` and returns where it went.
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn export_support_bundle(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+    stats: tauri::State<'_, CommandStats>,
+    exported: tauri::State<'_, ExportedPaths>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    output_path: PathBuf,
+) -> Result<PathBuf, AppError> {
+    access.check()?;
+    let output = roots.validate_path::<Write>(&output_path)?;
+    let contents = Contents {
+        settings: settings.get(),
+        metrics: stats.snapshot(),
+        log_dir: AppPaths::resolve(&app_handle)?.log_dir,
+        app: json(&crate::commands::app_info(&tts, &access))?,
+    };
+    let output_path = tauri::async_runtime::spawn_blocking(move || {
+        output.write_atomic(&bundle(contents)?)?;
+        Ok::<_, AppError>(output.path().to_path_buf())
+    })
+    .await
+    .map_err(|e| AppError::Io {
+        message: e.to_string(),
+    })??;
+    log::info!("Wrote a support bundle to {}", output_path.display());
+    exported.record(&output_path);
+    Ok(output_path)
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![export_support_bundle]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+    use std::collections::BTreeMap;
+    use zip::ZipArchive;
+
+    fn entries(bundle: Vec<u8>) -> BTreeMap<String, String> {
+        let mut zip = ZipArchive::new(Cursor::new(bundle)).unwrap();
+        (0..zip.len())
+            .map(|i| {
+                let mut file = zip.by_index(i).unwrap();
+                let mut text = String::new();
+                file.read_to_string(&mut text).unwrap();
+                (file.name().to_string(), text)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bundles_metrics_settings_and_logs() {
+        let scratch = Scratch::new("bundle");
+        fs::write(scratch.0.join("desktop.log"), "started\n").unwrap();
+        let stats = CommandStats::default();
+        drop(stats.time("synthesize_batch"));
+        let mut settings = Settings::default();
+        settings
+            .backend_extra_env
+            .insert("HF_TOKEN".to_string(), "hf_secret".to_string());
+
+        let entries = entries(
+            bundle(Contents {
+                app: b"{}".to_vec(),
+                settings,
+                metrics: stats.snapshot(),
+                log_dir: scratch.0.clone(),
+            })
+            .unwrap(),
+        );
+        assert_eq!(
+            entries.keys().collect::<Vec<_>>(),
+            [
+                "app.json",
+                "command_metrics.json",
+                "logs/desktop.log",
+                "settings.json"
+            ]
+        );
+        assert!(entries["command_metrics.json"].contains("\"synthesize_batch\""));
+        assert!(entries["settings.json"].contains("HF_TOKEN"));
+        assert!(!entries["settings.json"].contains("hf_secret"));
+        assert_eq!(entries["logs/desktop.log"], "started\n");
+    }
+
+    #[test]
+    fn keeps_the_end_of_long_logs() {
+        let scratch = Scratch::new("tail");
+        let path = scratch.0.join("desktop.log");
+        let mut log = vec![b'a'; MAX_LOG_BYTES as usize];
+        log.extend_from_slice(b"last line\n");
+        fs::write(&path, &log).unwrap();
+        let tail = log_tail(&path).unwrap();
+        assert_eq!(tail.len() as u64, MAX_LOG_BYTES);
+        assert!(tail.ends_with(b"last line\n"));
+    }
+}
@@ -0,0 +1,400 @@
+// App-managed trash: destructive operations move files here with a journal
+// entry, and only `empty_trash` or the size cap really deletes them
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::cache::write_atomic;
+use crate::error::AppError;
+use crate::format::DisplayFormat;
+use crate::paths::roots::{AllowedPath, Delete};
+use crate::read_only::WriteAccess;
+use crate::settings::SettingsStore;
+
+const JOURNAL_FILE: &str = "journal.json";
+/// The startup sweep drops the oldest entries beyond this.
+pub const MAX_TRASH_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashItem {
+    pub original_path: PathBuf,
+    /// Location inside the entry's directory.
+    pub stored_name: String,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashEntry {
+    pub id: String,
+    /// Command that discarded the files, e.g. "clear_tts_cache".
+    pub operation: String,
+    pub created_at: DateTime<Utc>,
+    pub items: Vec<TrashItem>,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoredItem {
+    pub original_path: PathBuf,
+    pub restored_path: PathBuf,
+    /// The original path was taken, so the item was restored alongside it.
+    pub renamed: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoreReport {
+    pub entry_id: String,
+    pub items: Vec<RestoredItem>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct EmptyReport {
+    pub entries: usize,
+    pub bytes: u64,
+    /// `bytes` for display; filled in by `empty_trash`.
+    pub size_display: String,
+}
+
+fn path_size(path: &Path) -> u64 {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::read_dir(path)
+            .map(|entries| entries.flatten().map(|e| path_size(&e.path())).sum())
+            .unwrap_or(0),
+        Ok(meta) => meta.len(),
+        Err(_) => 0,
+    }
+}
+
+fn copy_recursive(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(from)?.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+fn remove_path(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Renames, falling back to copy-then-remove when `from` and `to` are on
+/// different volumes.
+fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(_) if from.exists() => {
+            if let Err(e) = copy_recursive(from, to) {
+                let _ = remove_path(to);
+                return Err(e);
+            }
+            remove_path(from)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// `name (restored).ext`, then `name (restored 2).ext`, ... until free.
+fn free_alongside(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| {
+            let suffix = if n == 1 {
+                "restored".to_string()
+            } else {
+                format!("restored {}", n)
+            };
+            path.with_file_name(format!("{} ({}){}", stem, suffix, extension))
+        })
+        .find(|candidate| !candidate.exists())
+        .expect("unbounded search")
+}
+
+/// Managed state owning the trash directory and its journal, which lists
+/// entries oldest first.
+pub struct Trash {
+    dir: PathBuf,
+    journal: Mutex<Vec<TrashEntry>>,
+}
+
+impl Trash {
+    pub fn load(dir: PathBuf) -> Self {
+        let path = dir.join(JOURNAL_FILE);
+        let journal = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| match serde_json::from_str(&contents) {
+                Ok(journal) => Some(journal),
+                Err(e) => {
+                    log::warn!(
+                        "Ignoring unreadable trash journal {}: {}",
+                        path.display(),
+                        e
+                    );
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Self {
+            dir,
+            journal: Mutex::new(journal),
+        }
+    }
+
+    fn entry_dir(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    fn save(&self, journal: &[TrashEntry]) -> Result<(), AppError> {
+        let json = serde_json::to_vec_pretty(journal).map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })?;
+        write_atomic(&self.dir.join(JOURNAL_FILE), &json)?;
+        Ok(())
+    }
+
+    /// Moves `paths` into a new trash entry. Paths that don't exist are
+    /// skipped; `None` if there was nothing to move.
+    pub fn discard(
+        &self,
+        operation: &str,
+        paths: &[AllowedPath<Delete>],
+    ) -> Result<Option<TrashEntry>, AppError> {
+        let id = format!(
+            "{}-{}",
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            &uuid::Uuid::new_v4().simple().to_string()[..8]
+        );
+        let entry_dir = self.entry_dir(&id);
+        let mut entry = TrashEntry {
+            id,
+            operation: operation.to_string(),
+            created_at: Utc::now(),
+            items: Vec::new(),
+            bytes: 0,
+        };
+
+        let mut journal = self.journal.lock().unwrap();
+        for path in paths.iter().map(AllowedPath::path).filter(|p| p.exists()) {
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "item".to_string());
+            // Numbered so two files with the same name can share an entry
+            let stored_name = format!("{}-{}", entry.items.len(), name);
+            let bytes = path_size(path);
+            if let Err(e) = move_path(path, &entry_dir.join(&stored_name)) {
+                // Keep what was already moved restorable
+                if !entry.items.is_empty() {
+                    journal.push(entry);
+                    self.save(&journal)?;
+                }
+                return Err(e.into());
+            }
+            entry.items.push(TrashItem {
+                original_path: path.to_path_buf(),
+                stored_name,
+                bytes,
+            });
+            entry.bytes += bytes;
+        }
+        if entry.items.is_empty() {
+            return Ok(None);
+        }
+
+        log::info!(
+            "Moved {} item(s), {} bytes, to trash entry {} ({})",
+            entry.items.len(),
+            entry.bytes,
+            entry.id,
+            operation
+        );
+        journal.push(entry.clone());
+        self.save(&journal)?;
+        Ok(Some(entry))
+    }
+
+    /// Newest first.
+    pub fn list(&self) -> Vec<TrashEntry> {
+        self.journal.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    /// Puts an entry's items back. An item whose original path is now taken
+    /// is restored next to it under a "(restored)" name instead.
+    pub fn restore(&self, entry_id: &str) -> Result<RestoreReport, AppError> {
+        let mut journal = self.journal.lock().unwrap();
+        let index = journal
+            .iter()
+            .position(|e| e.id == entry_id)
+            .ok_or_else(|| AppError::TrashEntryNotFound {
+                entry_id: entry_id.to_string(),
+            })?;
+        let entry_dir = self.entry_dir(entry_id);
+
+        let mut report = RestoreReport {
+            entry_id: entry_id.to_string(),
+            items: Vec::new(),
+        };
+        let mut remaining = Vec::new();
+        let mut failure = None;
+        for item in journal[index].items.clone() {
+            let renamed = item.original_path.exists();
+            let target = if renamed {
+                free_alongside(&item.original_path)
+            } else {
+                item.original_path.clone()
+            };
+            match move_path(&entry_dir.join(&item.stored_name), &target) {
+                Ok(()) => report.items.push(RestoredItem {
+                    original_path: item.original_path,
+                    restored_path: target,
+                    renamed,
+                }),
+                Err(e) => {
+                    failure.get_or_insert(e);
+                    remaining.push(item);
+                }
+            }
+        }
+
+        if remaining.is_empty() {
+            journal.remove(index);
+            let _ = fs::remove_dir_all(&entry_dir);
+        } else {
+            let entry = &mut journal[index];
+            entry.bytes = remaining.iter().map(|i| i.bytes).sum();
+            entry.items = remaining;
+        }
+        self.save(&journal)?;
+        match failure {
+            Some(e) => Err(e.into()),
+            None => Ok(report),
+        }
+    }
+
+    /// Deletes the entries matching `expired` for good.
+    fn purge(
+        &self,
+        journal: &mut Vec<TrashEntry>,
+        mut expired: impl FnMut(&TrashEntry) -> bool,
+    ) -> Result<EmptyReport, AppError> {
+        let mut report = EmptyReport::default();
+        let mut kept = Vec::with_capacity(journal.len());
+        for entry in journal.drain(..) {
+            if !expired(&entry) {
+                kept.push(entry);
+                continue;
+            }
+            match fs::remove_dir_all(self.entry_dir(&entry.id)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    log::warn!("Failed to delete trash entry {}: {}", entry.id, e);
+                    kept.push(entry);
+                    continue;
+                }
+            }
+            report.entries += 1;
+            report.bytes += entry.bytes;
+        }
+        *journal = kept;
+        self.save(journal)?;
+        Ok(report)
+    }
+
+    /// Deletes entries older than `older_than_days`, or all of them.
+    pub fn empty(&self, older_than_days: Option<u32>) -> Result<EmptyReport, AppError> {
+        let cutoff = older_than_days.map(|days| Utc::now() - Duration::days(days as i64));
+        let mut journal = self.journal.lock().unwrap();
+        self.purge(&mut journal, |entry| {
+            cutoff.is_none_or(|cutoff| entry.created_at < cutoff)
+        })
+    }
+
+    /// Startup sweep: deletes the oldest entries until the trash fits in
+    /// `max_bytes`, and directories the journal doesn't know about.
+    pub fn enforce_cap(&self, max_bytes: u64) -> Result<EmptyReport, AppError> {
+        let mut journal = self.journal.lock().unwrap();
+        if let Ok(dirs) = fs::read_dir(&self.dir) {
+            for dir in dirs.flatten().filter(|d| d.path().is_dir()) {
+                let name = dir.file_name().to_string_lossy().into_owned();
+                if !journal.iter().any(|e| e.id == name) {
+                    log::info!("Removing orphaned trash directory {}", name);
+                    let _ = fs::remove_dir_all(dir.path());
+                }
+            }
+        }
+
+        let mut total: u64 = journal.iter().map(|e| e.bytes).sum();
+        if total <= max_bytes {
+            return Ok(EmptyReport::default());
+        }
+        // Journal order is oldest first, so the oldest go until under the cap
+        self.purge(&mut journal, |entry| {
+            if total <= max_bytes {
+                return false;
+            }
+            total -= entry.bytes;
+            true
+        })
+    }
+}
+
+/// Trash entries, newest first.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn list_trash(trash: tauri::State<'_, Trash>) -> Vec<TrashEntry> {
+    trash.list()
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn restore_from_trash(
+    trash: tauri::State<'_, Trash>,
+    access: tauri::State<'_, WriteAccess>,
+    entry_id: String,
+) -> Result<RestoreReport, AppError> {
+    access.check()?;
+    trash.restore(&entry_id)
+}
+
+/// The only command that permanently deletes files; without
+/// `older_than_days` the whole trash is emptied.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn empty_trash(
+    trash: tauri::State<'_, Trash>,
+    settings: tauri::State<'_, SettingsStore>,
+    access: tauri::State<'_, WriteAccess>,
+    older_than_days: Option<u32>,
+) -> Result<EmptyReport, AppError> {
+    access.check()?;
+    let mut report = trash.empty(older_than_days)?;
+    report.size_display = DisplayFormat::for_locale(&settings.get().ui_locale).size(report.bytes);
+    Ok(report)
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![list_trash, restore_from_trash, empty_trash]
+}
@@ -0,0 +1,574 @@
+// Synthesis of scripts longer than one request, split into chunks that are
+// cached one by one, so editing a sentence only re-bills the chunk holding it
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{Emitter, Manager};
+use tokio::task::JoinSet;
+
+use super::fingerprint::TtsRequestParams;
+use super::lanes::Lane;
+use super::length;
+use super::placeholders::Placeholders;
+use super::spend::SpendApproval;
+use super::synthesis::{SpeechInput, SynthesisOptions};
+use super::TtsService;
+use crate::audio::concat;
+use crate::cache::tts::TtsCache;
+use crate::error::AppError;
+use crate::settings::SettingsStore;
+use crate::ssml::{self, BreathingRoom};
+use crate::voices::capabilities::CapabilityStore;
+
+/// Text bytes per chunk, so edits rarely move chunk boundaries; a chunk
+/// must also measure within Google's limit once escaped and given its
+/// pauses, which multi-byte scripts and markup-heavy text can exceed first.
+const MAX_CHUNK_BYTES: usize = 3500;
+
+/// Tries per chunk while Google is unreachable, the first included.
+const MAX_CHUNK_ATTEMPTS: u32 = 3;
+/// Wait before the first retry, doubled before each later one.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Ends a sentence when followed by whitespace.
+const SENTENCE_ENDS: &[char] = &['.', '!', '?', '…', '。', '！', '？'];
+
+#[derive(Debug, Clone)]
+struct Chunk {
+    text: String,
+    /// Ends a paragraph that another chunk follows, so the paragraph pause
+    /// is spoken at its end.
+    pause_after: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChunkReport {
+    pub index: usize,
+    pub characters: usize,
+    /// Served from the cache, so not billed again.
+    pub reused: bool,
+    /// Where this chunk's cache entry was moved, having been edited
+    /// outside the app.
+    pub modified_cache_entry: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LongSpeech {
+    pub audio: Vec<u8>,
+    pub chunks: Vec<ChunkReport>,
+    pub reused_chunks: usize,
+    pub synthesized_chunks: usize,
+    pub billed_characters: usize,
+}
+
+/// Payload of "long-speech-progress", sent as each chunk finishes.
+#[derive(Debug, Clone, Serialize)]
+pub struct LongSpeechProgress {
+    pub completed_chunks: usize,
+    pub total_chunks: usize,
+    /// The chunk that just finished; chunks finish out of order.
+    pub index: usize,
+    pub reused: bool,
+}
+
+/// Splits `paragraph` after sentence ends and line breaks; the pieces joined
+/// give `paragraph` back.
+fn sentences(paragraph: &str) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut chars = paragraph.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, next)| next);
+        let ends =
+            c == '\n' || (SENTENCE_ENDS.contains(&c) && next.is_none_or(char::is_whitespace));
+        if ends {
+            // Trailing whitespace up to the line break stays with the
+            // sentence it follows
+            let mut end = i + c.len_utf8();
+            let mut line_ended = c == '\n';
+            while let Some(&(j, w)) = chars.peek() {
+                if line_ended || !w.is_whitespace() {
+                    break;
+                }
+                line_ended = w == '\n';
+                end = j + w.len_utf8();
+                chars.next();
+            }
+            pieces.push(&paragraph[start..end]);
+            start = end;
+        }
+    }
+    if start < paragraph.len() {
+        pieces.push(&paragraph[start..]);
+    }
+    pieces
+}
+
+/// Cuts a sentence that doesn't fit one chunk before the last space of the
+/// longest prefix that does, or after that prefix if it has no space.
+fn split_long<'a>(sentence: &'a str, fits: &impl Fn(&str) -> bool) -> Vec<&'a str> {
+    let mut pieces = Vec::new();
+    let mut rest = sentence;
+    while !fits(rest) {
+        // A prefix fits whenever a longer one does, so bisect on char ends
+        let ends: Vec<usize> = rest.char_indices().map(|(i, c)| i + c.len_utf8()).collect();
+        let fitting = ends.partition_point(|&end| fits(&rest[..end]));
+        let limit = ends[fitting.saturating_sub(1)];
+        let cut = rest[..limit]
+            .rfind(char::is_whitespace)
+            .filter(|&i| i > 0)
+            .map_or(limit, |i| i + 1);
+        pieces.push(&rest[..cut]);
+        rest = &rest[cut..];
+    }
+    pieces.push(rest);
+    pieces
+}
+
+/// The paragraphs of `text`, split at blank lines, with their lines trimmed.
+fn paragraphs(text: &str) -> Vec<String> {
+    let normalized = text.replace("\r\n", "\n").replace('\r', "\n");
+    let mut paragraphs: Vec<String> = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    for line in normalized.split('\n') {
+        let line = line.trim();
+        if line.is_empty() {
+            if !current.is_empty() {
+                paragraphs.push(std::mem::take(&mut current).join("\n"));
+            }
+        } else {
+            current.push(line);
+        }
+    }
+    if !current.is_empty() {
+        paragraphs.push(current.join("\n"));
+    }
+    paragraphs
+}
+
+/// The sentences of `text` in order, trimmed, as chunking splits them.
+pub(crate) fn script_sentences(text: &str) -> Vec<String> {
+    paragraphs(text)
+        .iter()
+        .flat_map(|paragraph| {
+            sentences(paragraph)
+                .into_iter()
+                .map(|s| s.trim().to_string())
+                .collect::<Vec<_>>()
+        })
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Splits `text` into chunks. Every paragraph starts a chunk, so a change
+/// only moves chunk boundaries inside its own paragraph; a paragraph that
+/// doesn't `fit` is packed sentence by sentence.
+fn chunk(text: &str, fits: impl Fn(&str) -> bool) -> Vec<Chunk> {
+    let paragraphs = paragraphs(text);
+    let mut chunks = Vec::new();
+    let last_paragraph = paragraphs.len().saturating_sub(1);
+    for (p, paragraph) in paragraphs.iter().enumerate() {
+        let mut texts = Vec::new();
+        let mut current = String::new();
+        for piece in sentences(paragraph)
+            .into_iter()
+            .flat_map(|s| split_long(s, &fits))
+        {
+            if !current.is_empty() && !fits(&format!("{}{}", current, piece)) {
+                texts.push(std::mem::take(&mut current));
+            }
+            current.push_str(piece);
+        }
+        texts.push(current);
+
+        let last_text = texts.len() - 1;
+        chunks.extend(texts.into_iter().enumerate().map(|(i, text)| Chunk {
+            text: text.trim().to_string(),
+            pause_after: i == last_text && p < last_paragraph,
+        }));
+    }
+    chunks.retain(|c| !c.text.is_empty());
+    chunks
+}
+
+/// The request input for `chunk`, as `synthesize_speech` would send its
+/// text, plus the paragraph pause it ends with.
+fn chunk_input(
+    chunk: &Chunk,
+    paragraph_pause_ms: u32,
+    line_pause_ms: u32,
+    breathing: BreathingRoom,
+) -> SpeechInput {
+    let input = super::synthesis::speech_input(
+        chunk.text.clone(),
+        paragraph_pause_ms,
+        line_pause_ms,
+        breathing,
+    );
+    if !chunk.pause_after || paragraph_pause_ms == 0 {
+        return input;
+    }
+    let body = match &input {
+        SpeechInput::Ssml(ssml) => ssml
+            .trim_start_matches("<speak>")
+            .trim_end_matches("</speak>")
+            .to_string(),
+        SpeechInput::Text(text) => ssml::escape(text),
+    };
+    SpeechInput::Ssml(format!(
+        "<speak>{} {}</speak>",
+        body,
+        ssml::break_tag(paragraph_pause_ms)
+    ))
+}
+
+/// `synthesize_cached_hit` for one chunk, retried on its own while Google
+/// is unreachable.
+pub(super) async fn synthesize_chunk(
+    tts: &TtsService,
+    cache: &TtsCache,
+    voice_name: &str,
+    language_code: &str,
+    input: SpeechInput,
+    quota_project: Option<&str>,
+    approval: Option<&SpendApproval>,
+) -> Result<super::synthesis::CachedSpeech, AppError> {
+    let mut attempt = 1;
+    loop {
+        let result = super::synthesis::synthesize_cached_hit(
+            tts,
+            cache,
+            voice_name,
+            language_code,
+            input.clone(),
+            quota_project,
+            Lane::Interactive,
+            approval,
+        )
+        .await;
+        match result {
+            Err(AppError::TtsOffline { message }) if attempt < MAX_CHUNK_ATTEMPTS => {
+                let delay = RETRY_DELAY * 2u32.pow(attempt - 1);
+                log::warn!(
+                    "Chunk synthesis attempt {} failed, retrying in {:?}: {}",
+                    attempt,
+                    delay,
+                    message
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Synthesizes `text` of any length as one MP3. Each chunk is cached under
+/// its own fingerprint, which covers the voice and audio settings, so only
+/// chunks whose text or settings changed are billed; the report says how
+/// many were reused. Every chunk is checked before any is synthesized; the
+/// chunks are then requested at once, as many running as the TTS service's
+/// call slots allow, and joined in text order whatever order they finish
+/// in. "long-speech-progress" reports each finished chunk. A chunk that
+/// still fails once retried fails the whole call and cancels the rest.
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn synthesize_long_speech(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+    capabilities: tauri::State<'_, CapabilityStore>,
+    voice_name: String,
+    language_code: String,
+    text: String,
+    options: Option<SynthesisOptions>,
+) -> Result<LongSpeech, AppError> {
+    if super::native::voice_id(&voice_name).is_some() {
+        return Err(AppError::InvalidAudioRequest {
+            message: "offline voices have no length limit; use synthesize_speech_audio".to_string(),
+        });
+    }
+    let options = options.unwrap_or_default();
+    let defaults = settings.get();
+    let paragraph_pause_ms = options
+        .paragraph_pause_ms
+        .unwrap_or(defaults.paragraph_pause_ms);
+    let line_pause_ms = options.line_pause_ms.unwrap_or(defaults.line_pause_ms);
+    let breathing = options.breathing_room.unwrap_or(defaults.breathing_room);
+    let policy = options
+        .unsupported_features
+        .unwrap_or(defaults.unsupported_features);
+
+    let placeholders = Placeholders::new(&defaults.placeholders);
+    super::placeholders::check(&app_handle, &placeholders, &text)?;
+    super::speakable::check(&text)?;
+
+    let chunks = chunk(&text, |text| {
+        let candidate = Chunk {
+            text: text.trim().to_string(),
+            pause_after: true,
+        };
+        text.len() <= MAX_CHUNK_BYTES
+            && length::fits(&chunk_input(
+                &candidate,
+                paragraph_pause_ms,
+                line_pause_ms,
+                breathing,
+            ))
+    });
+    if chunks.is_empty() {
+        return Err(AppError::InvalidAudioRequest {
+            message: "text is empty".to_string(),
+        });
+    }
+    let mut inputs = Vec::with_capacity(chunks.len());
+    for chunk in &chunks {
+        let input = chunk_input(chunk, paragraph_pause_ms, line_pause_ms, breathing);
+        inputs.push(
+            super::synthesis::check_and_strip(
+                &tts,
+                &capabilities,
+                policy,
+                &placeholders,
+                &voice_name,
+                &language_code,
+                &chunk.text,
+                input,
+            )
+            .await?,
+        );
+    }
+
+    let cache = Arc::new(crate::cache::tts_cache(&app_handle)?);
+    // Shared by the chunks, which draw on it as they're sent
+    let approval = tts
+        .spend()
+        .redeem(options.spend_token.as_deref())?
+        .map(Arc::new);
+    let total = inputs.len();
+    let mut tasks = JoinSet::new();
+    for (index, input) in inputs.into_iter().enumerate() {
+        let app_handle = app_handle.clone();
+        let cache = cache.clone();
+        let voice_name = voice_name.clone();
+        let language_code = language_code.clone();
+        let quota_project = options.quota_project.clone();
+        let approval = approval.clone();
+        tasks.spawn(async move {
+            let characters =
+                TtsRequestParams::speech(&voice_name, &language_code, &input).billable_characters();
+            let tts = app_handle.state::<TtsService>();
+            let speech = synthesize_chunk(
+                &tts,
+                &cache,
+                &voice_name,
+                &language_code,
+                input,
+                quota_project.as_deref(),
+                approval.as_deref(),
+            )
+            .await;
+            (index, characters, speech)
+        });
+    }
+
+    // Chunks finish in any order; each waits in its slot until assembly
+    let mut finished: Vec<Option<(Vec<u8>, ChunkReport)>> = vec![None; total];
+    let mut completed_chunks = 0;
+    while let Some(joined) = tasks.join_next().await {
+        let (index, characters, speech) = joined.map_err(|e| AppError::Tts {
+            message: format!("chunk task failed: {}", e),
+        })?;
+        // Returning drops `tasks`, which aborts the chunks still running
+        let speech = speech?;
+        completed_chunks += 1;
+        let report = ChunkReport {
+            index,
+            characters,
+            reused: speech.hit,
+            modified_cache_entry: speech.modified_entry,
+        };
+        let progress = LongSpeechProgress {
+            completed_chunks,
+            total_chunks: total,
+            index,
+            reused: report.reused,
+        };
+        if let Err(e) = app_handle.emit("long-speech-progress", progress) {
+            log::warn!("Failed to emit long-speech-progress: {}", e);
+        }
+        finished[index] = Some((speech.audio, report));
+    }
+
+    let mut parts = Vec::with_capacity(total);
+    let mut reports = Vec::with_capacity(total);
+    let mut billed_characters = 0;
+    for (audio, report) in finished.into_iter().flatten() {
+        if !report.reused {
+            billed_characters += report.characters;
+        }
+        parts.push(audio);
+        reports.push(report);
+    }
+
+    let reused_chunks = reports.iter().filter(|r| r.reused).count();
+    let joined = concat::concat(&parts)?;
+    Ok(LongSpeech {
+        audio: joined.audio,
+        synthesized_chunks: reports.len() - reused_chunks,
+        reused_chunks,
+        chunks: reports,
+        billed_characters,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::mp3;
+    use crate::tts::mock::{self, Fault};
+
+    const VOICE: &str = "en-US-Mock-A";
+
+    async fn speak(harness: &mock::Harness, text: &str) -> Result<(Vec<u8>, bool), AppError> {
+        synthesize_chunk(
+            &harness.tts,
+            &harness.cache,
+            VOICE,
+            "en-US",
+            SpeechInput::Text(text.to_string()),
+            None,
+            None,
+        )
+        .await
+        .map(|speech| (speech.audio, speech.hit))
+    }
+
+    #[test]
+    fn chunks_start_at_paragraphs_and_keep_the_text() {
+        let text = "One. Two!\n\nThree? Four.";
+        let chunks = chunk(text, |t| t.len() <= 10);
+        let texts: Vec<_> = chunks.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, ["One. Two!", "Three?", "Four."]);
+        assert!(chunks[0].pause_after);
+        assert!(!chunks[1].pause_after && !chunks[2].pause_after);
+    }
+
+    #[test]
+    fn a_sentence_longer_than_a_chunk_is_cut_at_spaces() {
+        let pieces = split_long("aaaa bbbb cccc", &|t: &str| t.len() <= 6);
+        assert_eq!(pieces, ["aaaa ", "bbbb ", "cccc"]);
+    }
+
+    #[tokio::test]
+    async fn unreachable_google_is_retried() {
+        let harness = mock::harness().await;
+        harness
+            .server
+            .fail_next(Fault::Unavailable, MAX_CHUNK_ATTEMPTS as usize - 1);
+        let (audio, hit) = speak(&harness, "Third time lucky.").await.unwrap();
+        assert!(!hit && mp3::is_plausible_mp3(&audio));
+        assert_eq!(
+            harness.server.synthesize_calls(),
+            MAX_CHUNK_ATTEMPTS as usize
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_give_up_and_other_errors_are_not_retried() {
+        let harness = mock::harness().await;
+        harness
+            .server
+            .fail_next(Fault::Unavailable, MAX_CHUNK_ATTEMPTS as usize);
+        assert!(matches!(
+            speak(&harness, "Never arrives.").await,
+            Err(AppError::TtsOffline { .. })
+        ));
+        assert_eq!(
+            harness.server.synthesize_calls(),
+            MAX_CHUNK_ATTEMPTS as usize
+        );
+
+        harness.server.fail_next(Fault::ResourceExhausted, 1);
+        assert!(matches!(
+            speak(&harness, "Out of quota.").await,
+            Err(AppError::TtsQuotaExceeded { .. })
+        ));
+        assert_eq!(
+            harness.server.synthesize_calls(),
+            MAX_CHUNK_ATTEMPTS as usize + 1
+        );
+    }
+
+    #[tokio::test]
+    async fn long_text_is_synthesized_chunk_by_chunk_and_reused() {
+        let harness = mock::harness().await;
+        let paragraph = "A sentence of some length to fill the chunk. ".repeat(100);
+        let text = format!("{}\n\n{}", paragraph, paragraph.replace("fill", "pad"));
+        let chunks = chunk(&text, |t| t.len() <= MAX_CHUNK_BYTES);
+        assert!(chunks.len() >= 4);
+
+        let mut parts = Vec::new();
+        for chunk in &chunks {
+            let (audio, hit) = speak(&harness, &chunk.text).await.unwrap();
+            assert!(!hit);
+            parts.push(audio);
+        }
+        let joined = concat::concat(&parts).unwrap();
+        let total: f64 = parts.iter().filter_map(|p| mp3::duration_ms(p)).sum();
+        assert!((mp3::duration_ms(&joined.audio).unwrap() - total).abs() < 1.0);
+
+        // Editing the second paragraph leaves the first one's chunks cached
+        let edited = text.replace("pad", "stretch");
+        let calls = harness.server.synthesize_calls();
+        let mut reused = 0;
+        for chunk in chunk(&edited, |t| t.len() <= MAX_CHUNK_BYTES) {
+            reused += speak(&harness, &chunk.text).await.unwrap().1 as usize;
+        }
+        assert!(reused >= 2);
+        assert_eq!(
+            harness.server.synthesize_calls(),
+            calls + chunks.len() - reused
+        );
+    }
+
+    #[tokio::test]
+    async fn chunks_synthesize_at_once_up_to_the_call_slots() {
+        let harness = mock::harness().await;
+        let delay = Duration::from_millis(150);
+        harness.server.set_delay(delay);
+        let texts: Vec<String> = (1..=8)
+            .map(|n| format!("Chunk {}.", "word ".repeat(n * 20)))
+            .collect();
+
+        let started = std::time::Instant::now();
+        let (a, b, c, d, e, f, g, h) = tokio::join!(
+            speak(&harness, &texts[0]),
+            speak(&harness, &texts[1]),
+            speak(&harness, &texts[2]),
+            speak(&harness, &texts[3]),
+            speak(&harness, &texts[4]),
+            speak(&harness, &texts[5]),
+            speak(&harness, &texts[6]),
+            speak(&harness, &texts[7]),
+        );
+        let elapsed = started.elapsed();
+        // One at a time would take eight delays
+        let rounds = texts.len().div_ceil(super::super::MAX_CONCURRENT_SYNTHESES) as u32;
+        assert!(
+            elapsed >= delay * rounds && elapsed < delay * (rounds + 2),
+            "took {:?}",
+            elapsed
+        );
+
+        let parts: Vec<Vec<u8>> = [a, b, c, d, e, f, g, h]
+            .into_iter()
+            .map(|speech| speech.unwrap().0)
+            .collect();
+        // Each came back as its own text's audio, whatever order they ended in
+        assert!(parts.windows(2).all(|pair| pair[0].len() < pair[1].len()));
+        assert_eq!(harness.server.synthesize_calls(), texts.len());
+    }
+}
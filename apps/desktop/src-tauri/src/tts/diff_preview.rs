@@ -0,0 +1,331 @@
+// Previews of an edit: only the sentences that changed since the last take,
+// each change with a little of the unchanged script around it. Sentences are
+// synthesized one by one through the TTS cache, so the context and repeated
+// previews are free once heard
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use super::fingerprint::TtsRequestParams;
+use super::placeholders::Placeholders;
+use super::synthesis::SynthesisOptions;
+use super::TtsService;
+use crate::assets::{asset_url, AssetKind};
+use crate::audio::concat;
+use crate::error::AppError;
+use crate::settings::SettingsStore;
+use crate::voices::capabilities::CapabilityStore;
+
+/// Unchanged sentences spoken either side of a change by default.
+const DEFAULT_CONTEXT_SENTENCES: usize = 1;
+/// Largest table the sentence diff builds; past it the changed middle of the
+/// script is treated as one change.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// One clip of changed sentences, with its context.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangePreview {
+    pub change_index: usize,
+    /// `None` for a removal with no context left to speak.
+    pub url: Option<String>,
+    /// The sentences as they were, without context; empty for an insertion.
+    pub old_text: String,
+    /// The sentences as they are now, without context; empty for a removal.
+    pub new_text: String,
+    /// Everything the clip speaks, context included.
+    pub clip_text: String,
+    /// Sentences of the clip that weren't in the cache.
+    pub synthesized_sentences: usize,
+    pub billed_characters: usize,
+}
+
+/// `old[old_start..old_end]` replaced by `new[new_start..new_end]`; either
+/// range may be empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Hunk {
+    old_start: usize,
+    old_end: usize,
+    new_start: usize,
+    new_end: usize,
+}
+
+/// The sentence as compared: whitespace runs count as one space, so
+/// reflowing a line isn't a change.
+fn comparison_key(sentence: &str) -> String {
+    sentence.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The changes from `old` to `new` along their longest common subsequence,
+/// in order. The common prefix and suffix are set aside first, which keeps
+/// the table small for the usual edit of a few sentences.
+fn diff(old: &[String], new: &[String]) -> Vec<Hunk> {
+    let old: Vec<String> = old.iter().map(|s| comparison_key(s)).collect();
+    let new: Vec<String> = new.iter().map(|s| comparison_key(s)).collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+    if a.is_empty() && b.is_empty() {
+        return Vec::new();
+    }
+    let whole = Hunk {
+        old_start: prefix,
+        old_end: prefix + a.len(),
+        new_start: prefix,
+        new_end: prefix + b.len(),
+    };
+    if a.is_empty() || b.is_empty() || (a.len() + 1) * (b.len() + 1) > MAX_DIFF_CELLS {
+        return vec![whole];
+    }
+
+    // lcs[i][j]: length of the longest common subsequence of a[i..] and b[j..]
+    let width = b.len() + 1;
+    let mut lcs = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i * width + j] = if a[i] == b[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut hunks = Vec::new();
+    let mut open: Option<(usize, usize)> = None;
+    let (mut i, mut j) = (0, 0);
+    let mut close = |open: &mut Option<(usize, usize)>, i: usize, j: usize| {
+        if let Some((old_start, new_start)) = open.take() {
+            hunks.push(Hunk {
+                old_start: prefix + old_start,
+                old_end: prefix + i,
+                new_start: prefix + new_start,
+                new_end: prefix + j,
+            });
+        }
+    };
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            close(&mut open, i, j);
+            i += 1;
+            j += 1;
+            continue;
+        }
+        open.get_or_insert((i, j));
+        let remove =
+            j == b.len() || (i < a.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]);
+        if remove {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    close(&mut open, i, j);
+    hunks
+}
+
+/// Merges hunks whose context windows in the new script touch or overlap,
+/// so no sentence is spoken in two clips.
+fn merge(hunks: Vec<Hunk>, context: usize) -> Vec<Hunk> {
+    let mut merged: Vec<Hunk> = Vec::new();
+    for hunk in hunks {
+        match merged.last_mut() {
+            Some(last) if hunk.new_start.saturating_sub(context) <= last.new_end + context => {
+                last.old_end = hunk.old_end;
+                last.new_end = hunk.new_end;
+            }
+            _ => merged.push(hunk),
+        }
+    }
+    merged
+}
+
+/// Splits both scripts into sentences, diffs them and synthesizes a clip
+/// per change: the new sentences with `context_sentences` (default 1)
+/// unchanged ones before and after. Sentences are requested one at a time
+/// and cached under their own fingerprints, so unchanged context and
+/// sentences heard before aren't billed again; the clips themselves are
+/// cached too, and each comes back as an asset URL. An empty list means
+/// nothing changed.
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn preview_changes(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+    capabilities: tauri::State<'_, CapabilityStore>,
+    old_text: String,
+    new_text: String,
+    voice_name: String,
+    language_code: String,
+    context_sentences: Option<usize>,
+    options: Option<SynthesisOptions>,
+) -> Result<Vec<ChangePreview>, AppError> {
+    if super::native::voice_id(&voice_name).is_some() {
+        return Err(AppError::InvalidAudioRequest {
+            message: "offline voices aren't cached; use synthesize_speech_audio".to_string(),
+        });
+    }
+    let options = options.unwrap_or_default();
+    let defaults = settings.get();
+    let paragraph_pause_ms = options
+        .paragraph_pause_ms
+        .unwrap_or(defaults.paragraph_pause_ms);
+    let line_pause_ms = options.line_pause_ms.unwrap_or(defaults.line_pause_ms);
+    let breathing = options.breathing_room.unwrap_or(defaults.breathing_room);
+    let policy = options
+        .unsupported_features
+        .unwrap_or(defaults.unsupported_features);
+    let context = context_sentences.unwrap_or(DEFAULT_CONTEXT_SENTENCES);
+
+    let placeholders = Placeholders::new(&defaults.placeholders);
+    super::placeholders::check(&app_handle, &placeholders, &new_text)?;
+
+    let old = super::chunks::script_sentences(&old_text);
+    let new = super::chunks::script_sentences(&new_text);
+    let changes = merge(diff(&old, &new), context);
+    if changes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let cache = crate::cache::tts_cache(&app_handle)?;
+    let approval = tts.spend().redeem(options.spend_token.as_deref())?;
+    let mut previews = Vec::with_capacity(changes.len());
+    for (change_index, change) in changes.into_iter().enumerate() {
+        let clip_end = (change.new_end + context).min(new.len());
+        let clip = &new[change.new_start.saturating_sub(context)..clip_end];
+        let mut parts = Vec::with_capacity(clip.len());
+        let mut clip_key = Sha256::new();
+        let mut synthesized_sentences = 0;
+        let mut billed_characters = 0;
+        for sentence in clip {
+            let input = super::synthesis::speech_input(
+                sentence.clone(),
+                paragraph_pause_ms,
+                line_pause_ms,
+                breathing,
+            );
+            let input = super::synthesis::check_and_strip(
+                &tts,
+                &capabilities,
+                policy,
+                &placeholders,
+                &voice_name,
+                &language_code,
+                sentence,
+                input,
+            )
+            .await?;
+            let params = TtsRequestParams::speech(&voice_name, &language_code, &input);
+            clip_key.update(params.fingerprint().as_bytes());
+            let characters = params.billable_characters();
+            let speech = super::chunks::synthesize_chunk(
+                &tts,
+                &cache,
+                &voice_name,
+                &language_code,
+                input,
+                options.quota_project.as_deref(),
+                approval.as_ref(),
+            )
+            .await?;
+            if !speech.hit {
+                synthesized_sentences += 1;
+                billed_characters += characters;
+            }
+            parts.push(speech.audio);
+        }
+
+        let url = if parts.is_empty() {
+            None
+        } else {
+            let key = format!("{:x}", clip_key.finalize());
+            let audio = concat::concat(&parts)?.audio;
+            match cache.put(&key, &audio, &voice_name, 0) {
+                Ok(()) => Some(asset_url(AssetKind::Tts, &key)),
+                Err(e) => {
+                    log::warn!("Failed to cache change preview {}: {}", key, e);
+                    None
+                }
+            }
+        };
+        previews.push(ChangePreview {
+            change_index,
+            url,
+            old_text: old[change.old_start..change.old_end].join(" "),
+            new_text: new[change.new_start..change.new_end].join(" "),
+            clip_text: clip.join(" "),
+            synthesized_sentences,
+            billed_characters,
+        });
+    }
+    Ok(previews)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentences(text: &str) -> Vec<String> {
+        text.split('|').map(str::to_string).collect()
+    }
+
+    fn hunk(old: std::ops::Range<usize>, new: std::ops::Range<usize>) -> Hunk {
+        Hunk {
+            old_start: old.start,
+            old_end: old.end,
+            new_start: new.start,
+            new_end: new.end,
+        }
+    }
+
+    #[test]
+    fn reflowed_sentences_are_not_changes() {
+        let old = sentences("One.|Two  words.|Three.");
+        let new = sentences("One.|Two\nwords.|Three.");
+        assert!(diff(&old, &new).is_empty());
+        assert!(diff(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn finds_replacements_insertions_and_removals() {
+        let old = sentences("A.|B.|C.|D.|E.|F.");
+        // B and E replaced
+        let new = sentences("A.|B2.|C.|D.|New.|F.");
+        assert_eq!(diff(&old, &new), [hunk(1..2, 1..2), hunk(4..5, 4..5)]);
+
+        // X inserted before D, E removed
+        let new = sentences("A.|B.|C.|X.|D.|F.");
+        assert_eq!(diff(&old, &new), [hunk(3..3, 3..4), hunk(4..5, 5..5)]);
+    }
+
+    #[test]
+    fn one_side_empty_is_one_change() {
+        let old = sentences("A.|B.");
+        assert_eq!(diff(&old, &[]), [hunk(0..2, 0..0)]);
+        assert_eq!(diff(&[], &old), [hunk(0..0, 0..2)]);
+        let new = sentences("A.|B.|C.|D.");
+        assert_eq!(diff(&old, &new), [hunk(2..2, 2..4)]);
+    }
+
+    #[test]
+    fn changes_whose_context_touches_are_merged() {
+        let hunks = vec![hunk(1..2, 1..2), hunk(3..4, 3..4), hunk(8..9, 8..9)];
+        assert_eq!(
+            merge(hunks.clone(), 1),
+            [hunk(1..4, 1..4), hunk(8..9, 8..9)]
+        );
+        assert_eq!(merge(hunks.clone(), 0), hunks);
+        assert_eq!(merge(hunks, 3), [hunk(1..9, 1..9)]);
+        // Adjacent changes merge even without context
+        assert_eq!(
+            merge(vec![hunk(1..2, 1..2), hunk(2..3, 2..3)], 0),
+            [hunk(1..3, 1..3)]
+        );
+    }
+}
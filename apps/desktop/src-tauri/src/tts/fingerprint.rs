@@ -0,0 +1,433 @@
+// Canonical fingerprint of a synthesis request, shared by every cache of its
+// result so the backend and the frontend can compute the same key
+use gcloud_sdk::google::cloud::texttospeech::v1::{
+    synthesis_input::InputSource, AudioConfig, AudioEncoding, SsmlVoiceGender, SynthesisInput,
+    SynthesizeSpeechRequest, VoiceSelectionParams,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use super::synthesis::SpeechInput;
+
+/// Bumped whenever the encoding below changes, so old keys can't collide with
+/// new ones.
+pub const FINGERPRINT_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputKind {
+    #[default]
+    Text,
+    Ssml,
+}
+
+/// Everything that determines the synthesized audio. Unset fields take the
+/// API defaults the app synthesizes with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TtsRequestParams {
+    pub voice_name: String,
+    pub language_code: String,
+    pub input_kind: InputKind,
+    pub input: String,
+    /// API enum name, e.g. "MP3" or "LINEAR16".
+    pub audio_encoding: String,
+    pub speaking_rate: f64,
+    pub pitch: f64,
+    pub volume_gain_db: f64,
+    /// 0 means the voice's native rate.
+    pub sample_rate_hertz: u32,
+    pub effects_profile_ids: Vec<String>,
+    /// Caller's correlation id; doesn't affect the audio, so it is never part
+    /// of the fingerprint.
+    pub request_id: Option<String>,
+}
+
+impl Default for TtsRequestParams {
+    fn default() -> Self {
+        Self {
+            voice_name: String::new(),
+            language_code: String::new(),
+            input_kind: InputKind::Text,
+            input: String::new(),
+            audio_encoding: "MP3".to_string(),
+            speaking_rate: 1.0,
+            pitch: 0.0,
+            volume_gain_db: 0.0,
+            sample_rate_hertz: 0,
+            effects_profile_ids: Vec::new(),
+            request_id: None,
+        }
+    }
+}
+
+/// BCP-47 casing: "en-us" and "EN-US" are the same language as "en-US".
+fn normalize_language(code: &str) -> String {
+    code.trim()
+        .split(['-', '_'])
+        .enumerate()
+        .map(|(i, part)| match (i, part.len()) {
+            (0, _) => part.to_ascii_lowercase(),
+            (_, 2) => part.to_ascii_uppercase(),
+            (_, 4) => {
+                let (first, rest) = part.split_at(1);
+                first.to_ascii_uppercase() + &rest.to_ascii_lowercase()
+            }
+            _ => part.to_ascii_lowercase(),
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Fixed precision so 1.0, 1.00000001 and -0.0 vs 0.0 don't split the cache
+/// over differences the API can't hear.
+fn normalize_float(value: f64) -> String {
+    format!("{:.4}", value + 0.0)
+}
+
+impl TtsRequestParams {
+    /// Parameters for the app's own synthesis calls, which use the defaults
+    /// for everything but voice and input.
+    pub fn speech(voice_name: &str, language_code: &str, input: &SpeechInput) -> Self {
+        Self {
+            voice_name: voice_name.to_string(),
+            language_code: language_code.to_string(),
+            input_kind: match input {
+                SpeechInput::Text(_) => InputKind::Text,
+                SpeechInput::Ssml(_) => InputKind::Ssml,
+            },
+            input: input.content().to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Copy with defaults filled in and equivalent spellings collapsed.
+    pub fn normalized(&self) -> Self {
+        // Unknown encodings are sent as MP3, the only format the app decodes
+        let audio_encoding =
+            AudioEncoding::from_str_name(self.audio_encoding.trim().to_ascii_uppercase().as_str())
+                .filter(|e| *e != AudioEncoding::Unspecified)
+                .unwrap_or(AudioEncoding::Mp3);
+        let mut effects: Vec<String> = self
+            .effects_profile_ids
+            .iter()
+            .map(|e| e.trim().to_string())
+            .filter(|e| !e.is_empty())
+            .collect();
+        effects.sort();
+        effects.dedup();
+        Self {
+            voice_name: self.voice_name.trim().to_string(),
+            language_code: normalize_language(&self.language_code),
+            input_kind: self.input_kind,
+            input: self.input.replace("\r\n", "\n"),
+            audio_encoding: audio_encoding.as_str_name().to_string(),
+            // The API treats 0 as the default rate
+            speaking_rate: if self.speaking_rate == 0.0 {
+                1.0
+            } else {
+                self.speaking_rate
+            },
+            pitch: self.pitch,
+            volume_gain_db: self.volume_gain_db,
+            sample_rate_hertz: self.sample_rate_hertz,
+            effects_profile_ids: effects,
+            request_id: None,
+        }
+    }
+
+    /// Hex SHA-256 over the version byte and the normalized fields in a
+    /// fixed order, each length-prefixed so no two requests can encode alike.
+    pub fn fingerprint(&self) -> String {
+        let p = self.normalized();
+        let input_kind = match p.input_kind {
+            InputKind::Text => "text",
+            InputKind::Ssml => "ssml",
+        };
+        let fields: [(&str, String); 10] = [
+            ("voice_name", p.voice_name),
+            ("language_code", p.language_code),
+            ("input_kind", input_kind.to_string()),
+            ("input", p.input),
+            ("audio_encoding", p.audio_encoding),
+            ("speaking_rate", normalize_float(p.speaking_rate)),
+            ("pitch", normalize_float(p.pitch)),
+            ("volume_gain_db", normalize_float(p.volume_gain_db)),
+            ("sample_rate_hertz", p.sample_rate_hertz.to_string()),
+            ("effects_profile_ids", p.effects_profile_ids.join("\n")),
+        ];
+
+        let mut hasher = Sha256::new();
+        hasher.update([FINGERPRINT_VERSION]);
+        for (name, value) in &fields {
+            hasher.update(name.as_bytes());
+            hasher.update([0u8]);
+            hasher.update((value.len() as u64).to_le_bytes());
+            hasher.update(value.as_bytes());
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Characters billed for this request.
+    pub fn billable_characters(&self) -> usize {
+        super::length::billable_characters(&self.input, self.input_kind)
+    }
+
+    /// The API request these parameters describe.
+    pub fn to_request(&self) -> SynthesizeSpeechRequest {
+        let p = self.normalized();
+        let input_source = match p.input_kind {
+            InputKind::Text => InputSource::Text(p.input),
+            InputKind::Ssml => InputSource::Ssml(p.input),
+        };
+        let audio_encoding =
+            AudioEncoding::from_str_name(&p.audio_encoding).unwrap_or(AudioEncoding::Mp3);
+        SynthesizeSpeechRequest {
+            input: Some(SynthesisInput {
+                input_source: Some(input_source),
+                custom_pronunciations: None,
+            }),
+            voice: Some(VoiceSelectionParams {
+                language_code: p.language_code,
+                name: p.voice_name,
+                ssml_gender: SsmlVoiceGender::Unspecified as i32,
+                custom_voice: None,
+                voice_clone: None,
+            }),
+            audio_config: Some(AudioConfig {
+                audio_encoding: audio_encoding as i32,
+                speaking_rate: p.speaking_rate,
+                pitch: p.pitch,
+                volume_gain_db: p.volume_gain_db,
+                sample_rate_hertz: p.sample_rate_hertz as i32,
+                effects_profile_id: p.effects_profile_ids,
+            }),
+            advanced_voice_options: None,
+        }
+    }
+}
+
+/// `request` as the REST API spells it, for showing what would be sent.
+pub fn request_json(request: &SynthesizeSpeechRequest) -> serde_json::Value {
+    let input = match request.input.as_ref().and_then(|i| i.input_source.as_ref()) {
+        Some(InputSource::Text(text)) => json!({ "text": text }),
+        Some(InputSource::Ssml(ssml)) => json!({ "ssml": ssml }),
+        Some(InputSource::Markup(markup)) => json!({ "markup": markup }),
+        Some(InputSource::MultiSpeakerMarkup(_)) | None => json!({}),
+    };
+    let voice = request.voice.as_ref().map(|v| {
+        json!({
+            "languageCode": v.language_code,
+            "name": v.name,
+            "ssmlGender": SsmlVoiceGender::try_from(v.ssml_gender)
+                .unwrap_or_default()
+                .as_str_name(),
+        })
+    });
+    let audio_config = request.audio_config.as_ref().map(|c| {
+        json!({
+            "audioEncoding": AudioEncoding::try_from(c.audio_encoding)
+                .unwrap_or_default()
+                .as_str_name(),
+            "speakingRate": c.speaking_rate,
+            "pitch": c.pitch,
+            "volumeGainDb": c.volume_gain_db,
+            "sampleRateHertz": c.sample_rate_hertz,
+            "effectsProfileId": c.effects_profile_id,
+        })
+    });
+    json!({
+        "input": input,
+        "voice": voice,
+        "audioConfig": audio_config,
+    })
+}
+
+impl TtsRequestParams {
+    /// `to_request` in the API's JSON form.
+    pub fn to_request_json(&self) -> serde_json::Value {
+        request_json(&self.to_request())
+    }
+}
+
+/// Lets the backend and frontend key their own caches exactly like this one.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn fingerprint_tts_request(params: TtsRequestParams) -> String {
+    params.fingerprint()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> TtsRequestParams {
+        TtsRequestParams {
+            voice_name: "en-US-Neural2-A".to_string(),
+            language_code: "en-US".to_string(),
+            input: "Hello, world.".to_string(),
+            ..TtsRequestParams::default()
+        }
+    }
+
+    /// The documented encoding, written out: the version byte, then each
+    /// field's name, a NUL, its length and its value.
+    fn encode(version: u8, fields: &[(&str, &str)]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update([version]);
+        for (name, value) in fields {
+            hasher.update(name.as_bytes());
+            hasher.update([0u8]);
+            hasher.update((value.len() as u64).to_le_bytes());
+            hasher.update(value.as_bytes());
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    #[test]
+    fn a_known_request_keeps_its_fingerprint() {
+        // Changing this breaks every cache key the backend and frontend
+        // hold; bump FINGERPRINT_VERSION then
+        assert_eq!(FINGERPRINT_VERSION, 1);
+        assert_eq!(
+            request().fingerprint(),
+            "a7d68853e38c0de0ea1a8cafc52f121951080d303484dbb209ba6f4287871e32"
+        );
+    }
+
+    #[test]
+    fn the_version_byte_leads_the_hash() {
+        let fields = [
+            ("voice_name", "en-US-Neural2-A"),
+            ("language_code", "en-US"),
+            ("input_kind", "text"),
+            ("input", "Hello, world."),
+            ("audio_encoding", "MP3"),
+            ("speaking_rate", "1.0000"),
+            ("pitch", "0.0000"),
+            ("volume_gain_db", "0.0000"),
+            ("sample_rate_hertz", "0"),
+            ("effects_profile_ids", ""),
+        ];
+        assert_eq!(
+            request().fingerprint(),
+            encode(FINGERPRINT_VERSION, &fields)
+        );
+        assert_ne!(
+            request().fingerprint(),
+            encode(FINGERPRINT_VERSION + 1, &fields)
+        );
+    }
+
+    #[test]
+    fn equivalent_spellings_share_a_fingerprint() {
+        let key = request().fingerprint();
+        let same = [
+            TtsRequestParams {
+                language_code: "en_us".to_string(),
+                voice_name: " en-US-Neural2-A ".to_string(),
+                ..request()
+            },
+            TtsRequestParams {
+                audio_encoding: "mp3".to_string(),
+                speaking_rate: 0.0,
+                pitch: -0.0,
+                request_id: Some("req-1".to_string()),
+                ..request()
+            },
+            TtsRequestParams {
+                // Not an encoding the API knows, so sent as MP3
+                audio_encoding: "OGG_VORBIS_PLUS".to_string(),
+                speaking_rate: 1.000_000_01,
+                ..request()
+            },
+        ];
+        for params in same {
+            assert_eq!(params.fingerprint(), key, "{:?}", params);
+        }
+
+        let effects = |ids: &[&str]| TtsRequestParams {
+            effects_profile_ids: ids.iter().map(|id| id.to_string()).collect(),
+            ..request()
+        };
+        assert_eq!(
+            effects(&["telephony-class-application", "handset-class-device"]).fingerprint(),
+            effects(&[
+                "handset-class-device",
+                " ",
+                "telephony-class-application",
+                "handset-class-device"
+            ])
+            .fingerprint()
+        );
+        let crlf = TtsRequestParams {
+            input: "One\r\nTwo".to_string(),
+            ..request()
+        };
+        let lf = TtsRequestParams {
+            input: "One\nTwo".to_string(),
+            ..request()
+        };
+        assert_eq!(crlf.fingerprint(), lf.fingerprint());
+        assert_eq!(normalize_language("cmn-hans-cn"), "cmn-Hans-CN");
+    }
+
+    #[test]
+    fn what_changes_the_audio_changes_the_fingerprint() {
+        let key = request().fingerprint();
+        let different = [
+            TtsRequestParams {
+                input_kind: InputKind::Ssml,
+                ..request()
+            },
+            TtsRequestParams {
+                pitch: 1.0,
+                ..request()
+            },
+            TtsRequestParams {
+                sample_rate_hertz: 24_000,
+                ..request()
+            },
+            TtsRequestParams {
+                audio_encoding: "LINEAR16".to_string(),
+                ..request()
+            },
+            TtsRequestParams {
+                effects_profile_ids: vec!["headphone-class-device".to_string()],
+                ..request()
+            },
+        ];
+        for params in different {
+            assert_ne!(params.fingerprint(), key, "{:?}", params);
+        }
+        // Fields are length-prefixed, so text can't move between them
+        let split = |voice: &str, language: &str| TtsRequestParams {
+            voice_name: voice.to_string(),
+            language_code: language.to_string(),
+            ..request()
+        };
+        assert_ne!(
+            split("ab", "c").fingerprint(),
+            split("a", "bc").fingerprint()
+        );
+    }
+
+    #[test]
+    fn speech_requests_take_their_kind_from_the_input() {
+        let ssml = SpeechInput::Ssml("<speak>Hi</speak>".to_string());
+        let params = TtsRequestParams::speech("en-US-Neural2-A", "en-US", &ssml);
+        assert_eq!(params.input_kind, InputKind::Ssml);
+        assert_eq!(params.input, "<speak>Hi</speak>");
+
+        let json = TtsRequestParams {
+            language_code: "en-us".to_string(),
+            ..params
+        }
+        .to_request_json();
+        assert_eq!(json["input"]["ssml"], "<speak>Hi</speak>");
+        assert_eq!(json["voice"]["languageCode"], "en-US");
+        assert_eq!(json["audioConfig"]["audioEncoding"], "MP3");
+        assert_eq!(json["audioConfig"]["speakingRate"], 1.0);
+    }
+}
@@ -0,0 +1,142 @@
+// Passive health tracking of the shared TTS connection. Only real calls are
+// observed; nothing here generates traffic of its own.
+
+use chrono::{DateTime, Utc};
+use gcloud_sdk::tonic::Code;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Number of most recent calls the status is derived from.
+const WINDOW: usize = 20;
+/// p95 latency above which the connection counts as degraded.
+const DEGRADED_P95: Duration = Duration::from_millis(2500);
+/// Error rate above which the connection counts as degraded.
+const DEGRADED_ERROR_RATE: f64 = 0.2;
+/// Consecutive connection failures after which it counts as down.
+const DOWN_AFTER_FAILURES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionStatus {
+    Unknown,
+    Healthy,
+    Degraded,
+    Down,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionStatusReport {
+    pub status: ConnectionStatus,
+    pub sample_count: usize,
+    pub error_rate: f64,
+    pub p95_latency_ms: Option<u64>,
+    pub last_latency_ms: Option<u64>,
+    pub last_error: Option<String>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+struct Sample {
+    ok: bool,
+    latency: Duration,
+}
+
+pub struct ConnectionHealth {
+    samples: VecDeque<Sample>,
+    last_error: Option<String>,
+    updated_at: Option<DateTime<Utc>>,
+    status: ConnectionStatus,
+}
+
+/// Whether an error says something about the connection itself. Errors like
+/// INVALID_ARGUMENT prove the round trip worked and count as successes.
+pub fn is_connection_error(code: Code) -> bool {
+    matches!(
+        code,
+        Code::Unavailable | Code::DeadlineExceeded | Code::Unknown | Code::Internal | Code::Aborted
+    )
+}
+
+impl Default for ConnectionHealth {
+    fn default() -> Self {
+        Self {
+            samples: VecDeque::with_capacity(WINDOW),
+            last_error: None,
+            updated_at: None,
+            status: ConnectionStatus::Unknown,
+        }
+    }
+}
+
+impl ConnectionHealth {
+    /// Records one call and returns the new status if it changed.
+    pub fn record(
+        &mut self,
+        ok: bool,
+        latency: Duration,
+        error: Option<String>,
+    ) -> Option<ConnectionStatus> {
+        if self.samples.len() == WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample { ok, latency });
+        if error.is_some() {
+            self.last_error = error;
+        }
+        self.updated_at = Some(Utc::now());
+
+        let status = self.derive_status();
+        if status != self.status {
+            self.status = status;
+            Some(status)
+        } else {
+            None
+        }
+    }
+
+    fn error_rate(&self) -> f64 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        let errors = self.samples.iter().filter(|s| !s.ok).count();
+        errors as f64 / self.samples.len() as f64
+    }
+
+    fn p95_latency(&self) -> Option<Duration> {
+        let mut latencies: Vec<Duration> = self.samples.iter().map(|s| s.latency).collect();
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort();
+        let rank = ((latencies.len() as f64) * 0.95).ceil() as usize;
+        Some(latencies[rank.clamp(1, latencies.len()) - 1])
+    }
+
+    fn derive_status(&self) -> ConnectionStatus {
+        if self.samples.is_empty() {
+            return ConnectionStatus::Unknown;
+        }
+        let trailing_failures = self.samples.iter().rev().take_while(|s| !s.ok).count();
+        if trailing_failures >= DOWN_AFTER_FAILURES.min(self.samples.len()) {
+            return ConnectionStatus::Down;
+        }
+        let slow = self.p95_latency().is_some_and(|p95| p95 > DEGRADED_P95);
+        if slow || self.error_rate() > DEGRADED_ERROR_RATE {
+            ConnectionStatus::Degraded
+        } else {
+            ConnectionStatus::Healthy
+        }
+    }
+
+    pub fn report(&self) -> ConnectionStatusReport {
+        ConnectionStatusReport {
+            status: self.status,
+            sample_count: self.samples.len(),
+            error_rate: self.error_rate(),
+            p95_latency_ms: self.p95_latency().map(|d| d.as_millis() as u64),
+            last_latency_ms: self.samples.back().map(|s| s.latency.as_millis() as u64),
+            last_error: self.last_error.clone(),
+            updated_at: self.updated_at,
+        }
+    }
+}
@@ -0,0 +1,287 @@
+// Session history of synthesized clips, so an earlier take overwritten by a
+// new one can be played or exported again. Entries point at TTS cache
+// entries, which `clear_tts_cache` leaves in place while they're listed;
+// the script itself is kept only as a short snippet and a hash
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tauri::Manager;
+
+use crate::assets::{asset_url, AssetKind};
+use crate::cache::tts::Lookup;
+use crate::cache::write_atomic;
+use crate::error::AppError;
+use crate::read_only::WriteAccess;
+use crate::settings::SettingsStore;
+
+/// Unpinned entries kept; pinned ones don't count against it.
+const HISTORY_SIZE: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistorySettings {
+    /// Characters of the text kept with each entry; 0 keeps only its hash.
+    pub snippet_chars: usize,
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        Self { snippet_chars: 60 }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: String,
+    /// The TTS cache key of the audio.
+    pub fingerprint: String,
+    pub voice_name: String,
+    pub language_code: String,
+    /// The start of the text, ending in "…" when cut.
+    pub snippet: String,
+    pub text_sha256: String,
+    pub characters: usize,
+    pub synthesized_at: DateTime<Utc>,
+    /// Kept past `HISTORY_SIZE` and by `clear_history` unless asked.
+    pub pinned: bool,
+}
+
+/// `restore_history_entry`'s result: the entry's audio, and a URL the
+/// webview can play it from.
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoredClip {
+    pub entry: HistoryEntry,
+    pub url: String,
+    pub audio: Vec<u8>,
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn snippet(text: &str, chars: usize) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(chars) {
+        Some((end, _)) => format!("{}…", text[..end].trim_end()),
+        None => text,
+    }
+}
+
+/// Managed state holding the history, persisted as synthesis_history.json
+/// in app data. Newest entries are at the back.
+pub struct SynthesisHistory {
+    path: PathBuf,
+    entries: Mutex<VecDeque<HistoryEntry>>,
+}
+
+impl SynthesisHistory {
+    pub fn load(path: PathBuf) -> Self {
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| match serde_json::from_str(&contents) {
+                Ok(entries) => Some(entries),
+                Err(e) => {
+                    log::warn!(
+                        "Ignoring unreadable synthesis history {}: {}",
+                        path.display(),
+                        e
+                    );
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Self {
+            path,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    fn save(&self, entries: &VecDeque<HistoryEntry>) {
+        let result = serde_json::to_vec_pretty(entries)
+            .map_err(std::io::Error::other)
+            .and_then(|json| write_atomic(&self.path, &json));
+        if let Err(e) = result {
+            log::warn!(
+                "Failed to write synthesis history {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    /// Adds a synthesis as the newest entry. Synthesizing the same audio
+    /// again moves its entry up instead of adding another.
+    pub fn record(
+        &self,
+        fingerprint: &str,
+        voice_name: &str,
+        language_code: &str,
+        text: &str,
+        settings: HistorySettings,
+    ) -> HistoryEntry {
+        let mut entries = self.entries.lock().unwrap();
+        let previous = entries
+            .iter()
+            .position(|e| e.fingerprint == fingerprint)
+            .and_then(|i| entries.remove(i));
+        let entry = HistoryEntry {
+            id: previous.as_ref().map_or_else(
+                || uuid::Uuid::new_v4().simple().to_string(),
+                |p| p.id.clone(),
+            ),
+            fingerprint: fingerprint.to_string(),
+            voice_name: voice_name.to_string(),
+            language_code: language_code.to_string(),
+            snippet: snippet(text, settings.snippet_chars),
+            text_sha256: sha256_hex(text.as_bytes()),
+            characters: text.chars().count(),
+            synthesized_at: Utc::now(),
+            pinned: previous.is_some_and(|p| p.pinned),
+        };
+        entries.push_back(entry.clone());
+        while entries.iter().filter(|e| !e.pinned).count() > HISTORY_SIZE {
+            let Some(oldest) = entries.iter().position(|e| !e.pinned) else {
+                break;
+            };
+            entries.remove(oldest);
+        }
+        self.save(&entries);
+        entry
+    }
+
+    /// Newest first.
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        self.entries.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<HistoryEntry> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|e| e.id == id)
+            .cloned()
+    }
+
+    pub fn set_pinned(&self, id: &str, pinned: bool) -> Option<HistoryEntry> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.iter_mut().find(|e| e.id == id)?;
+        entry.pinned = pinned;
+        let entry = entry.clone();
+        self.save(&entries);
+        Some(entry)
+    }
+
+    /// Removes the unpinned entries, or all of them with `include_pinned`;
+    /// returns how many were removed.
+    pub fn clear(&self, include_pinned: bool) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|e| e.pinned && !include_pinned);
+        self.save(&entries);
+        before - entries.len()
+    }
+
+    /// Cache keys that must survive clearing the TTS cache.
+    pub fn protected_keys(&self) -> HashSet<String> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| e.fingerprint.clone())
+            .collect()
+    }
+}
+
+/// Records what a synthesis command just stored in the TTS cache under
+/// `fingerprint`. Audio that wasn't cached, as in read-only mode, couldn't
+/// be restored and isn't recorded.
+pub fn remember(
+    app_handle: &tauri::AppHandle,
+    fingerprint: &str,
+    voice_name: &str,
+    language_code: &str,
+    text: &str,
+) {
+    let (Some(history), Some(settings)) = (
+        app_handle.try_state::<SynthesisHistory>(),
+        app_handle.try_state::<SettingsStore>(),
+    ) else {
+        return;
+    };
+    let cached = crate::cache::tts_cache(app_handle).is_ok_and(|cache| cache.contains(fingerprint));
+    if cached {
+        history.record(
+            fingerprint,
+            voice_name,
+            language_code,
+            text,
+            settings.get().synthesis_history,
+        );
+    }
+}
+
+/// Clips synthesized recently, newest first.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_synthesis_history(history: tauri::State<'_, SynthesisHistory>) -> Vec<HistoryEntry> {
+    history.entries()
+}
+
+/// The audio of a history entry, read back from the TTS cache.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn restore_history_entry(
+    app_handle: tauri::AppHandle,
+    history: tauri::State<'_, SynthesisHistory>,
+    id: String,
+) -> Result<RestoredClip, AppError> {
+    let entry = history
+        .get(&id)
+        .ok_or_else(|| AppError::HistoryEntryNotFound {
+            entry_id: id.clone(),
+        })?;
+    match crate::cache::tts_cache(&app_handle)?.get(&entry.fingerprint)? {
+        Lookup::Hit(audio) => Ok(RestoredClip {
+            url: asset_url(AssetKind::Tts, &entry.fingerprint),
+            entry,
+            audio,
+        }),
+        Lookup::Miss | Lookup::Modified { .. } => {
+            Err(AppError::HistoryAudioMissing { entry_id: id })
+        }
+    }
+}
+
+/// Pins or, with `pinned: false`, unpins a history entry; pinned entries
+/// stay in the history until unpinned.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn pin_history_entry(
+    history: tauri::State<'_, SynthesisHistory>,
+    access: tauri::State<'_, WriteAccess>,
+    id: String,
+    pinned: Option<bool>,
+) -> Result<HistoryEntry, AppError> {
+    access.check()?;
+    history
+        .set_pinned(&id, pinned.unwrap_or(true))
+        .ok_or(AppError::HistoryEntryNotFound { entry_id: id })
+}
+
+/// Empties the history, keeping pinned entries unless `include_pinned` is
+/// set. The audio stays in the TTS cache, unprotected.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn clear_history(
+    history: tauri::State<'_, SynthesisHistory>,
+    access: tauri::State<'_, WriteAccess>,
+    include_pinned: Option<bool>,
+) -> Result<usize, AppError> {
+    access.check()?;
+    Ok(history.clear(include_pinned.unwrap_or(false)))
+}
@@ -0,0 +1,300 @@
+// The synthesis call slots, handed out by lane so a preview the user just
+// clicked doesn't wait behind a 200-voice batch. A freed slot goes to the
+// longest-waiting interactive call, and only to a background one when no
+// interactive call waits; a background job gives its slot back after every
+// item, so an interactive call waits at most for one call to finish. With
+// a shared directory the cap also holds across processes, e.g. the CLI next
+// to the app: a slot then also holds one of `capacity` lock files there,
+// which go to whichever process asks first
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+use crate::error::AppError;
+
+/// How often a call waiting on another process's slots tries them again.
+const SHARED_SLOT_POLL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Lane {
+    /// A call the user is waiting on: a single preview or synthesis.
+    Interactive,
+    /// Batches, prefetching and cache warming.
+    Background,
+}
+
+/// Payload of "tts-queue-status".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct QueueStatus {
+    pub capacity: usize,
+    pub in_use: usize,
+    pub interactive_waiting: usize,
+    pub background_waiting: usize,
+}
+
+type QueueListener = Box<dyn Fn(QueueStatus) + Send + Sync>;
+
+struct State {
+    capacity: usize,
+    free: usize,
+    interactive: VecDeque<oneshot::Sender<Slot>>,
+    background: VecDeque<oneshot::Sender<Slot>>,
+}
+
+impl State {
+    fn status(&self) -> QueueStatus {
+        QueueStatus {
+            capacity: self.capacity,
+            in_use: self.capacity - self.free,
+            interactive_waiting: self.interactive.len(),
+            background_waiting: self.background.len(),
+        }
+    }
+}
+
+struct Inner {
+    state: Mutex<State>,
+    on_change: Mutex<Option<QueueListener>>,
+}
+
+impl Inner {
+    fn notify(&self, status: QueueStatus) {
+        if let Some(listener) = self.on_change.lock().unwrap().as_ref() {
+            listener(status);
+        }
+    }
+
+    /// Passes a freed slot on to the next waiter, interactive first, or
+    /// returns it to the pool. Waiters whose call was dropped are skipped.
+    fn release(self: &Arc<Self>) {
+        let status = {
+            let mut state = self.state.lock().unwrap();
+            let mut handed_over = false;
+            while let Some(waiter) = state
+                .interactive
+                .pop_front()
+                .or_else(|| state.background.pop_front())
+            {
+                let slot = Slot {
+                    inner: Some(self.clone()),
+                    shared: None,
+                };
+                match waiter.send(slot) {
+                    Ok(()) => {
+                        handed_over = true;
+                        break;
+                    }
+                    // The waiter is gone; the returned slot must not release
+                    Err(mut slot) => slot.inner = None,
+                }
+            }
+            if !handed_over {
+                state.free += 1;
+            }
+            state.status()
+        };
+        self.notify(status);
+    }
+}
+
+/// One call slot, given back when dropped.
+pub struct Slot {
+    inner: Option<Arc<Inner>>,
+    /// The locked file of the slot across processes, if shared.
+    shared: Option<fs::File>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            inner.release();
+        }
+    }
+}
+
+/// `capacity` call slots shared by the two lanes.
+pub struct Scheduler {
+    inner: Arc<Inner>,
+    /// Where the lock files of the slots across processes are.
+    shared_dir: Option<PathBuf>,
+}
+
+impl Scheduler {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State {
+                    capacity,
+                    free: capacity,
+                    interactive: VecDeque::new(),
+                    background: VecDeque::new(),
+                }),
+                on_change: Mutex::new(None),
+            }),
+            shared_dir: None,
+        }
+    }
+
+    /// Shares the slots with every process using `dir` too.
+    pub fn with_shared_dir(self, dir: PathBuf) -> Self {
+        Self {
+            shared_dir: Some(dir),
+            ..self
+        }
+    }
+
+    /// Calls `listener` whenever a slot is taken or freed or a call starts
+    /// waiting.
+    pub fn set_listener(&self, listener: impl Fn(QueueStatus) + Send + Sync + 'static) {
+        *self.inner.on_change.lock().unwrap() = Some(Box::new(listener));
+    }
+
+    pub fn status(&self) -> QueueStatus {
+        self.inner.state.lock().unwrap().status()
+    }
+
+    /// Waits for a slot in `lane`, then for one not held by another process
+    /// when shared.
+    pub async fn acquire(&self, lane: Lane) -> Result<Slot, AppError> {
+        let mut slot = self.acquire_local(lane).await?;
+        if let Some(dir) = &self.shared_dir {
+            slot.shared = self.acquire_shared(dir).await;
+        }
+        Ok(slot)
+    }
+
+    /// Locks one of the slot files in `dir`, polling until another process
+    /// frees one. Where they can't be made, as in a read-only directory,
+    /// the cap only holds within this process.
+    async fn acquire_shared(&self, dir: &Path) -> Option<fs::File> {
+        let capacity = self.inner.state.lock().unwrap().capacity;
+        if let Err(e) = fs::create_dir_all(dir) {
+            log::warn!("Synthesis slots aren't shared: {}: {}", dir.display(), e);
+            return None;
+        }
+        loop {
+            for index in 0..capacity {
+                let path = dir.join(format!("{}.lock", index));
+                let file = match OpenOptions::new()
+                    .create(true)
+                    .truncate(false)
+                    .write(true)
+                    .open(&path)
+                {
+                    Ok(file) => file,
+                    Err(e) => {
+                        log::warn!("Synthesis slots aren't shared: {}: {}", path.display(), e);
+                        return None;
+                    }
+                };
+                match file.try_lock() {
+                    Ok(()) => return Some(file),
+                    Err(TryLockError::WouldBlock) => {}
+                    Err(TryLockError::Error(e)) => {
+                        log::warn!("Synthesis slots aren't shared: {}: {}", path.display(), e);
+                        return None;
+                    }
+                }
+            }
+            tokio::time::sleep(SHARED_SLOT_POLL).await;
+        }
+    }
+
+    /// Waits for a slot of this process in `lane`. A free slot is taken
+    /// right away unless calls of the same or a higher lane are already
+    /// waiting for one.
+    async fn acquire_local(&self, lane: Lane) -> Result<Slot, AppError> {
+        let (receiver, status) = {
+            let mut state = self.inner.state.lock().unwrap();
+            // Calls dropped while waiting would otherwise hold up the queue
+            state.interactive.retain(|waiter| !waiter.is_closed());
+            state.background.retain(|waiter| !waiter.is_closed());
+            let ahead = match lane {
+                Lane::Interactive => state.interactive.len(),
+                Lane::Background => state.interactive.len() + state.background.len(),
+            };
+            if state.free > 0 && ahead == 0 {
+                state.free -= 1;
+                let status = state.status();
+                drop(state);
+                self.inner.notify(status);
+                return Ok(Slot {
+                    inner: Some(self.inner.clone()),
+                    shared: None,
+                });
+            }
+            let (sender, receiver) = oneshot::channel();
+            match lane {
+                Lane::Interactive => state.interactive.push_back(sender),
+                Lane::Background => state.background.push_back(sender),
+            }
+            (receiver, state.status())
+        };
+        self.inner.notify(status);
+        receiver.await.map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    #[tokio::test]
+    async fn interactive_calls_go_before_background_ones() {
+        let scheduler = Arc::new(Scheduler::new(1));
+        let held = scheduler.acquire(Lane::Background).await.unwrap();
+        let background = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move {
+                scheduler
+                    .acquire(Lane::Background)
+                    .await
+                    .map(|_| "background")
+            }
+        });
+        tokio::task::yield_now().await;
+        let interactive = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move {
+                scheduler
+                    .acquire(Lane::Interactive)
+                    .await
+                    .map(|_| "interactive")
+            }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(scheduler.status().interactive_waiting, 1);
+        assert_eq!(scheduler.status().background_waiting, 1);
+        drop(held);
+        assert_eq!(interactive.await.unwrap().unwrap(), "interactive");
+        assert_eq!(background.await.unwrap().unwrap(), "background");
+    }
+
+    #[tokio::test]
+    async fn shared_slots_hold_across_schedulers() {
+        let scratch = Scratch::new("slots");
+        let app = Scheduler::new(1).with_shared_dir(scratch.0.clone());
+        let cli = Scheduler::new(1).with_shared_dir(scratch.0.clone());
+        let held = app.acquire(Lane::Interactive).await.unwrap();
+        let waiting =
+            tokio::time::timeout(SHARED_SLOT_POLL * 4, cli.acquire(Lane::Interactive)).await;
+        assert!(
+            waiting.is_err(),
+            "the other scheduler's slot was taken twice"
+        );
+        drop(held);
+        let slot = tokio::time::timeout(SHARED_SLOT_POLL * 4, cli.acquire(Lane::Interactive))
+            .await
+            .expect("the freed slot wasn't taken")
+            .unwrap();
+        assert!(slot.shared.is_some());
+    }
+}
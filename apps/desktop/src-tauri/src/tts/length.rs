@@ -0,0 +1,200 @@
+// How Google sizes a synthesis input. The request limit is in UTF-8 bytes of
+// the input as sent: the whole document, tags included, for SSML, and the
+// text for plain input. Billing is in characters instead, so a line of
+// Hindi or Japanese costs its character count while using two to three
+// times as many bytes of the limit; `<mark>` tags are not billed
+use serde::Serialize;
+
+use super::fingerprint::InputKind;
+use super::plan::MAX_INPUT_BYTES;
+use super::synthesis::SpeechInput;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InputLength {
+    pub input_kind: InputKind,
+    /// What counts against `limit_bytes`.
+    pub bytes: usize,
+    pub characters: usize,
+    pub billable_characters: usize,
+    pub limit_bytes: usize,
+    /// Negative when over the limit.
+    pub remaining_bytes: i64,
+    pub over_limit: bool,
+}
+
+/// `content` as the request carries it; line endings are normalized
+/// before sending, as in `TtsRequestParams::normalized`.
+fn as_sent(content: &str) -> std::borrow::Cow<'_, str> {
+    if content.contains("\r\n") {
+        content.replace("\r\n", "\n").into()
+    } else {
+        content.into()
+    }
+}
+
+/// Characters of the `<mark>` tags in `ssml`, which Google doesn't bill.
+fn mark_characters(ssml: &str) -> usize {
+    let mut characters = 0;
+    let mut rest = ssml;
+    while let Some(start) = rest.find("<mark") {
+        let tag = &rest[start..];
+        let boundary = tag[5..].chars().next();
+        let Some(end) = tag.find('>') else {
+            break;
+        };
+        if boundary.is_some_and(|c| c.is_whitespace() || c == '/' || c == '>') {
+            characters += tag[..=end].chars().count();
+        }
+        rest = &tag[end + 1..];
+    }
+    characters
+}
+
+/// Characters Google bills for `content`.
+pub fn billable_characters(content: &str, input_kind: InputKind) -> usize {
+    let content = as_sent(content);
+    let characters = content.chars().count();
+    match input_kind {
+        InputKind::Text => characters,
+        InputKind::Ssml => characters - mark_characters(&content),
+    }
+}
+
+pub fn measure(content: &str, input_kind: InputKind) -> InputLength {
+    let sent = as_sent(content);
+    let bytes = sent.len();
+    InputLength {
+        input_kind,
+        bytes,
+        characters: sent.chars().count(),
+        billable_characters: billable_characters(&sent, input_kind),
+        limit_bytes: MAX_INPUT_BYTES,
+        remaining_bytes: MAX_INPUT_BYTES as i64 - bytes as i64,
+        over_limit: bytes > MAX_INPUT_BYTES,
+    }
+}
+
+pub fn measure_input(input: &SpeechInput) -> InputLength {
+    let input_kind = match input {
+        SpeechInput::Text(_) => InputKind::Text,
+        SpeechInput::Ssml(_) => InputKind::Ssml,
+    };
+    measure(input.content(), input_kind)
+}
+
+/// Whether `input` is within Google's request limit.
+pub fn fits(input: &SpeechInput) -> bool {
+    !measure_input(input).over_limit
+}
+
+/// Sizes `text_or_ssml` by Google's rules, as sent; nothing is added, so
+/// this measures plain text as-is rather than with the app's pauses.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn measure_synthesis_input(text_or_ssml: String, is_ssml: bool) -> InputLength {
+    let input_kind = if is_ssml {
+        InputKind::Ssml
+    } else {
+        InputKind::Text
+    };
+    measure(&text_or_ssml, input_kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Real sentences in scripts taking one to four UTF-8 bytes a character.
+    const SCRIPTS: &[(&str, &str)] = &[
+        ("English", "The quick brown fox jumps over the lazy dog. "),
+        ("German", "Größere Übungen fördern die Ausdauer spürbar. "),
+        ("Hindi", "नमस्ते, आज मौसम बहुत अच्छा है। "),
+        ("Japanese", "今日はとても良い天気ですね。"),
+        ("Arabic", "مرحبا بكم في برنامجنا اليوم. "),
+        ("Emoji", "Party time 🎉🎈 "),
+    ];
+
+    /// `sentence` repeated, then padded with ASCII to exactly `bytes`.
+    fn text_of(sentence: &str, bytes: usize) -> String {
+        let mut text = sentence.repeat(bytes / sentence.len());
+        text.push_str(&"x".repeat(bytes - text.len()));
+        assert_eq!(text.len(), bytes);
+        text
+    }
+
+    #[test]
+    fn any_script_fits_up_to_the_byte_limit() {
+        for (script, sentence) in SCRIPTS {
+            let at_limit = measure(&text_of(sentence, MAX_INPUT_BYTES), InputKind::Text);
+            assert_eq!(at_limit.bytes, MAX_INPUT_BYTES, "{}", script);
+            assert_eq!(at_limit.remaining_bytes, 0, "{}", script);
+            assert!(!at_limit.over_limit, "{}", script);
+            assert!(fits(&SpeechInput::Text(text_of(sentence, MAX_INPUT_BYTES))));
+
+            let over = measure(&text_of(sentence, MAX_INPUT_BYTES + 1), InputKind::Text);
+            assert_eq!(over.remaining_bytes, -1, "{}", script);
+            assert!(over.over_limit, "{}", script);
+            assert!(!fits(&SpeechInput::Text(text_of(
+                sentence,
+                MAX_INPUT_BYTES + 1
+            ))));
+        }
+    }
+
+    #[test]
+    fn non_latin_scripts_bill_fewer_characters_than_they_use_bytes() {
+        for (script, sentence) in SCRIPTS {
+            let length = measure(&text_of(sentence, MAX_INPUT_BYTES), InputKind::Text);
+            assert_eq!(length.characters, length.billable_characters, "{}", script);
+            let ratio = length.bytes as f64 / length.characters as f64;
+            match *script {
+                "English" => assert_eq!(ratio, 1.0),
+                "German" => assert!(ratio > 1.0 && ratio < 1.2, "{}", ratio),
+                "Hindi" | "Japanese" => assert!(ratio > 2.5, "{}: {}", script, ratio),
+                _ => assert!(ratio > 1.4, "{}: {}", script, ratio),
+            }
+        }
+        // One emoji is one character of four bytes
+        let emoji = measure("🎉", InputKind::Text);
+        assert_eq!((emoji.bytes, emoji.characters), (4, 1));
+    }
+
+    #[test]
+    fn ssml_counts_its_tags_against_the_limit() {
+        let body = text_of("नमस्ते, आज मौसम बहुत अच्छा है। ", MAX_INPUT_BYTES - 15);
+        let ssml = format!("<speak>{}</speak>", body);
+        let length = measure(&ssml, InputKind::Ssml);
+        assert_eq!(length.bytes, MAX_INPUT_BYTES);
+        assert!(!length.over_limit);
+        assert!(measure(&format!("<speak>{} </speak>", body), InputKind::Ssml).over_limit);
+        // The same text sent plain leaves room for the tags
+        assert_eq!(measure(&body, InputKind::Text).remaining_bytes, 15);
+    }
+
+    #[test]
+    fn line_endings_are_measured_as_sent() {
+        let crlf = format!(
+            "{}\r\n",
+            text_of("今日はとても良い天気ですね。", MAX_INPUT_BYTES - 1)
+        );
+        assert_eq!(crlf.len(), MAX_INPUT_BYTES + 1);
+        let length = measure(&crlf, InputKind::Text);
+        assert_eq!(length.bytes, MAX_INPUT_BYTES);
+        assert!(!length.over_limit);
+        assert_eq!(billable_characters("a\r\nb", InputKind::Text), 3);
+    }
+
+    #[test]
+    fn marks_are_not_billed() {
+        let ssml = r#"<speak>Hallo <mark name="a"/>Welt<mark name='b'></mark><marker/></speak>"#;
+        let billed = billable_characters(ssml, InputKind::Ssml);
+        let marks = r#"<mark name="a"/>"#.len() + "<mark name='b'>".len();
+        assert_eq!(billed, ssml.chars().count() - marks);
+        assert_eq!(
+            billable_characters(ssml, InputKind::Text),
+            ssml.chars().count()
+        );
+        // An unterminated tag is billed as text
+        assert_eq!(billable_characters("<speak><mark", InputKind::Ssml), 12);
+    }
+}
@@ -0,0 +1,599 @@
+// A stand-in for Google's TextToSpeech service on a local port, for tests
+// and for frontend work without credentials; never in release builds. It
+// lists a fixed set of voices and answers synthesis with audio derived from
+// the request alone, so the same request gets the same bytes. Faults queued
+// with `fail_next`, or the "Mock-Unavailable" style voices, fail calls with
+// the status Google would send
+use gcloud_sdk::google::cloud::texttospeech::v1::{
+    synthesis_input::InputSource, AudioEncoding, ListVoicesRequest, ListVoicesResponse,
+    SsmlVoiceGender, SynthesizeSpeechRequest, SynthesizeSpeechResponse, Voice,
+};
+use gcloud_sdk::tonic::codec::ProstCodec;
+use gcloud_sdk::tonic::codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError};
+use gcloud_sdk::tonic::{self, Code};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio_stream::wrappers::TcpListenerStream;
+
+const SERVICE_NAME: &str = "google.cloud.texttospeech.v1.TextToSpeech";
+/// Sample rate of the mock voices, as of Google's standard ones.
+const SAMPLE_RATE: u32 = 24_000;
+/// Speech is about 15 characters a second.
+const MS_PER_CHARACTER: u64 = 66;
+const MIN_DURATION_MS: u64 = 200;
+/// An MPEG-2 layer III frame at 32 kbps, 24 kHz mono, as Google sends: 576
+/// samples in 96 bytes. All-zero side info makes it decode to silence.
+const MP3_FRAME_HEADER: [u8; 4] = [0xFF, 0xF3, 0x44, 0xC0];
+const MP3_FRAME_LEN: usize = 96;
+const MP3_FRAME_SAMPLES: u64 = 576;
+
+/// A status the mock fails a call with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    Unavailable,
+    ResourceExhausted,
+    Unauthenticated,
+}
+
+impl Fault {
+    fn status(self) -> tonic::Status {
+        match self {
+            Fault::Unavailable => tonic::Status::unavailable("mock: service unavailable"),
+            Fault::ResourceExhausted => {
+                tonic::Status::resource_exhausted("mock: quota exceeded for characters per minute")
+            }
+            Fault::Unauthenticated => {
+                tonic::Status::unauthenticated("mock: request had invalid credentials")
+            }
+        }
+    }
+
+    /// The fault a voice of the fixture list always fails with.
+    fn of_voice(name: &str) -> Option<Fault> {
+        match name.rsplit('-').next()? {
+            "Unavailable" => Some(Fault::Unavailable),
+            "Exhausted" => Some(Fault::ResourceExhausted),
+            "Unauthenticated" => Some(Fault::Unauthenticated),
+            _ => None,
+        }
+    }
+}
+
+/// A synthesis request as the mock received it.
+#[derive(Debug, Clone)]
+pub struct ReceivedRequest {
+    pub request: SynthesizeSpeechRequest,
+    /// The x-goog-user-project header, naming the quota project.
+    pub user_project: Option<String>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Faults the next calls fail with, one per call, before any audio.
+    faults: VecDeque<Fault>,
+    delay: Duration,
+    list_voices_calls: usize,
+    received: Vec<ReceivedRequest>,
+}
+
+/// The fixture voice list: two English voices and a German one, plus one
+/// per fault for clicking through error handling in the UI.
+pub fn voices() -> Vec<Voice> {
+    let voice = |name: &str, language: &str, gender: SsmlVoiceGender| Voice {
+        language_codes: vec![language.to_string()],
+        name: name.to_string(),
+        ssml_gender: gender as i32,
+        natural_sample_rate_hertz: SAMPLE_RATE as i32,
+    };
+    vec![
+        voice("en-US-Mock-A", "en-US", SsmlVoiceGender::Female),
+        voice("en-US-Mock-B", "en-US", SsmlVoiceGender::Male),
+        voice("de-DE-Mock-A", "de-DE", SsmlVoiceGender::Female),
+        voice("en-US-Mock-Unavailable", "en-US", SsmlVoiceGender::Neutral),
+        voice("en-US-Mock-Exhausted", "en-US", SsmlVoiceGender::Neutral),
+        voice(
+            "en-US-Mock-Unauthenticated",
+            "en-US",
+            SsmlVoiceGender::Neutral,
+        ),
+    ]
+}
+
+fn input_text(request: &SynthesizeSpeechRequest) -> &str {
+    match request.input.as_ref().and_then(|i| i.input_source.as_ref()) {
+        Some(InputSource::Text(text) | InputSource::Ssml(text) | InputSource::Markup(text)) => text,
+        _ => "",
+    }
+}
+
+/// How long the mock's speech for `text` plays.
+fn duration_ms(text: &str) -> u64 {
+    (text.chars().count() as u64 * MS_PER_CHARACTER).max(MIN_DURATION_MS)
+}
+
+/// Silent MP3 frames lasting about as long as `text` would be spoken: a
+/// tone would need an MP3 encoder, and the frames are what the app's MP3
+/// handling looks at.
+fn mp3(text: &str) -> Vec<u8> {
+    let frames = (duration_ms(text) * SAMPLE_RATE as u64 / 1000).div_ceil(MP3_FRAME_SAMPLES);
+    let mut frame = [0u8; MP3_FRAME_LEN];
+    frame[..4].copy_from_slice(&MP3_FRAME_HEADER);
+    frame.repeat(frames as usize)
+}
+
+/// A 16-bit mono WAV of a sine at a pitch picked by a hash of the request,
+/// as LINEAR16 responses carry their header.
+fn wav(seed: &[u8], text: &str, sample_rate: u32) -> Result<Vec<u8>, Box<tonic::Status>> {
+    let digest = Sha256::digest(seed);
+    let hz = 220.0 + (u16::from_le_bytes([digest[0], digest[1]]) % 660) as f64;
+    let samples = duration_ms(text) * sample_rate as u64 / 1000;
+    let header = crate::audio::placeholder::wav_header(sample_rate, 1, samples)
+        .map_err(|e| Box::new(tonic::Status::invalid_argument(e.to_string())))?;
+    let mut wav = header.to_vec();
+    for n in 0..samples {
+        let t = n as f64 / sample_rate as f64;
+        let value = (2.0 * std::f64::consts::PI * hz * t).sin() * 0.25 * i16::MAX as f64;
+        wav.extend_from_slice(&(value as i16).to_le_bytes());
+    }
+    Ok(wav)
+}
+
+/// The audio for `request`, or the status Google would refuse it with;
+/// boxed, being large.
+fn synthesize(request: &SynthesizeSpeechRequest) -> Result<Vec<u8>, Box<tonic::Status>> {
+    let voice = request.voice.as_ref();
+    let name = voice.map_or("", |v| v.name.as_str());
+    if let Some(fault) = Fault::of_voice(name) {
+        return Err(Box::new(fault.status()));
+    }
+    if !name.is_empty() && !voices().iter().any(|v| v.name == name) {
+        return Err(Box::new(tonic::Status::invalid_argument(format!(
+            "mock: voice {} does not exist",
+            name
+        ))));
+    }
+    let text = input_text(request);
+    if text.trim().is_empty() {
+        return Err(Box::new(tonic::Status::invalid_argument(
+            "mock: input text is empty",
+        )));
+    }
+    let config = request.audio_config.clone().unwrap_or_default();
+    match AudioEncoding::try_from(config.audio_encoding) {
+        Ok(AudioEncoding::Mp3) => Ok(mp3(text)),
+        Ok(AudioEncoding::Linear16) => {
+            let sample_rate = match config.sample_rate_hertz {
+                rate if rate > 0 => rate as u32,
+                _ => SAMPLE_RATE,
+            };
+            let seed = format!("{}\n{}\n{}", name, config.speaking_rate, text);
+            wav(seed.as_bytes(), text, sample_rate)
+        }
+        _ => Err(Box::new(tonic::Status::invalid_argument(
+            "mock: only MP3 and LINEAR16 are supported",
+        ))),
+    }
+}
+
+/// The TextToSpeech service as tonic-build would generate its server,
+/// answering ListVoices and SynthesizeSpeech.
+#[derive(Clone)]
+struct MockService {
+    state: Arc<Mutex<State>>,
+}
+
+impl tonic::server::NamedService for MockService {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+struct ListVoices(Arc<Mutex<State>>);
+
+impl tonic::server::UnaryService<ListVoicesRequest> for ListVoices {
+    type Response = ListVoicesResponse;
+    type Future = BoxFuture<tonic::Response<ListVoicesResponse>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<ListVoicesRequest>) -> Self::Future {
+        let state = self.0.clone();
+        Box::pin(async move {
+            let (fault, delay) = {
+                let mut state = state.lock().unwrap();
+                state.list_voices_calls += 1;
+                (state.faults.pop_front(), state.delay)
+            };
+            tokio::time::sleep(delay).await;
+            if let Some(fault) = fault {
+                return Err(fault.status());
+            }
+            let language = request.into_inner().language_code;
+            let voices = voices()
+                .into_iter()
+                .filter(|v| language.is_empty() || v.language_codes.contains(&language))
+                .collect();
+            Ok(tonic::Response::new(ListVoicesResponse { voices }))
+        })
+    }
+}
+
+struct Synthesize(Arc<Mutex<State>>);
+
+impl tonic::server::UnaryService<SynthesizeSpeechRequest> for Synthesize {
+    type Response = SynthesizeSpeechResponse;
+    type Future = BoxFuture<tonic::Response<SynthesizeSpeechResponse>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<SynthesizeSpeechRequest>) -> Self::Future {
+        let state = self.0.clone();
+        Box::pin(async move {
+            let user_project = request
+                .metadata()
+                .get("x-goog-user-project")
+                .and_then(|v| v.to_str().ok())
+                .map(String::from);
+            let request = request.into_inner();
+            let (fault, delay) = {
+                let mut state = state.lock().unwrap();
+                state.received.push(ReceivedRequest {
+                    request: request.clone(),
+                    user_project,
+                });
+                (state.faults.pop_front(), state.delay)
+            };
+            tokio::time::sleep(delay).await;
+            if let Some(fault) = fault {
+                return Err(fault.status());
+            }
+            let audio_content = synthesize(&request).map_err(|status| *status)?;
+            Ok(tonic::Response::new(SynthesizeSpeechResponse {
+                audio_content,
+            }))
+        })
+    }
+}
+
+impl<B> Service<http::Request<B>> for MockService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<tonic::body::Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let state = self.state.clone();
+        let method = request
+            .uri()
+            .path()
+            .strip_prefix(&format!("/{}/", SERVICE_NAME))
+            .unwrap_or_default()
+            .to_string();
+        Box::pin(async move {
+            Ok(match method.as_str() {
+                "ListVoices" => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(ListVoices(state), request)
+                        .await
+                }
+                "SynthesizeSpeech" => {
+                    tonic::server::Grpc::new(ProstCodec::default())
+                        .unary(Synthesize(state), request)
+                        .await
+                }
+                _ => tonic::Status::new(Code::Unimplemented, "mock: method not implemented")
+                    .into_http(),
+            })
+        })
+    }
+}
+
+/// A running mock server; stops when dropped.
+pub struct MockTts {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl MockTts {
+    /// What `TtsService::with_endpoint` and `SCLIP_TTS_ENDPOINT` take.
+    pub fn endpoint(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Fails each of the next `times` calls with `fault`, after any queued
+    /// before.
+    pub fn fail_next(&self, fault: Fault, times: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.faults.extend(std::iter::repeat_n(fault, times));
+    }
+
+    /// Holds every answer back for `delay`, e.g. to cancel a call midway.
+    pub fn set_delay(&self, delay: Duration) {
+        self.state.lock().unwrap().delay = delay;
+    }
+
+    /// Synthesis requests received so far, failed ones included.
+    pub fn received(&self) -> Vec<ReceivedRequest> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    pub fn synthesize_calls(&self) -> usize {
+        self.state.lock().unwrap().received.len()
+    }
+
+    pub fn list_voices_calls(&self) -> usize {
+        self.state.lock().unwrap().list_voices_calls
+    }
+}
+
+/// Starts a mock server on `addr`; port 0 picks a free one.
+pub async fn start_mock_tts_on(addr: SocketAddr) -> std::io::Result<MockTts> {
+    let listener = TcpListener::bind(addr).await?;
+    let addr = listener.local_addr()?;
+    let state = Arc::new(Mutex::new(State::default()));
+    let (shutdown, stopped) = oneshot::channel::<()>();
+    let service = MockService {
+        state: state.clone(),
+    };
+    tokio::spawn(async move {
+        let served = tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async {
+                let _ = stopped.await;
+            })
+            .await;
+        if let Err(e) = served {
+            log::warn!("Mock TTS server stopped: {}", e);
+        }
+    });
+    Ok(MockTts {
+        addr,
+        state,
+        _shutdown: shutdown,
+    })
+}
+
+/// Starts a mock server on a free loopback port.
+pub async fn start_mock_tts() -> std::io::Result<MockTts> {
+    start_mock_tts_on(SocketAddr::from(([127, 0, 0, 1], 0))).await
+}
+
+/// A `TtsService` and synthesis cache wired to a fresh mock server, kept in
+/// a scratch directory removed on drop.
+#[cfg(test)]
+pub(crate) struct Harness {
+    pub server: MockTts,
+    pub tts: super::TtsService,
+    pub cache: crate::cache::tts::TtsCache,
+    dir: std::path::PathBuf,
+}
+
+#[cfg(test)]
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+pub(crate) async fn harness() -> Harness {
+    let server = start_mock_tts().await.unwrap();
+    let dir = std::env::temp_dir().join(format!("sclip-mock-tts-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let tts = super::TtsService::new(
+        None,
+        None,
+        super::recording::Recorder::new(dir.join("tts_recordings"), Default::default()),
+        super::usage::UsageLedger::load(dir.join("usage.json")),
+        |_| {},
+    )
+    .with_endpoint(server.endpoint());
+    let cache = crate::cache::tts::TtsCache::new(dir.join("tts_cache"));
+    Harness {
+        server,
+        tts,
+        cache,
+        dir,
+    }
+}
+
+/// Runs a mock server until Ctrl-C, for `cargo run --features mock-tts
+/// --bin mock-tts -- [port]`; start the app with the `SCLIP_TTS_ENDPOINT`
+/// it prints to synthesize against it.
+#[cfg(feature = "mock-tts")]
+pub fn main() -> std::process::ExitCode {
+    let port = match std::env::args().nth(1).map(|arg| arg.parse::<u16>()) {
+        None => 0,
+        Some(Ok(port)) => port,
+        Some(Err(e)) => {
+            eprintln!("error: the port must be a number: {}", e);
+            return std::process::ExitCode::from(2);
+        }
+    };
+    let served = tokio::runtime::Runtime::new().and_then(|runtime| {
+        runtime.block_on(async {
+            let server = start_mock_tts_on(SocketAddr::from(([127, 0, 0, 1], port))).await?;
+            println!("SCLIP_TTS_ENDPOINT={}", server.endpoint());
+            tokio::signal::ctrl_c().await
+        })
+    });
+    match served {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::mp3;
+    use crate::error::AppError;
+    use crate::tts::lanes::Lane;
+    use crate::tts::spend::SpendCaps;
+    use crate::tts::synthesis::{self, SpeechInput};
+
+    const VOICE: &str = "en-US-Mock-A";
+
+    async fn speak(
+        harness: &Harness,
+        voice: &str,
+        text: &str,
+    ) -> Result<(Vec<u8>, bool), AppError> {
+        synthesis::synthesize_cached_hit(
+            &harness.tts,
+            &harness.cache,
+            voice,
+            "en-US",
+            SpeechInput::Text(text.to_string()),
+            None,
+            Lane::Interactive,
+            None,
+        )
+        .await
+        .map(|speech| (speech.audio, speech.hit))
+    }
+
+    #[tokio::test]
+    async fn lists_the_fixture_voices_once_until_refreshed() {
+        let harness = harness().await;
+        let listed = harness.tts.voices(false, None).await.unwrap();
+        assert_eq!(listed, voices());
+        harness.tts.voices(false, None).await.unwrap();
+        assert_eq!(harness.server.list_voices_calls(), 1);
+        harness.tts.voices(true, None).await.unwrap();
+        assert_eq!(harness.server.list_voices_calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn second_identical_request_is_served_from_the_cache() {
+        let harness = harness().await;
+        let (audio, hit) = speak(&harness, VOICE, "Hello from the mock.")
+            .await
+            .unwrap();
+        assert!(!hit);
+        assert!(mp3::is_plausible_mp3(&audio));
+        assert!(mp3::duration_ms(&audio).unwrap() >= MIN_DURATION_MS as f64);
+
+        let (cached, hit) = speak(&harness, VOICE, "Hello from the mock.")
+            .await
+            .unwrap();
+        assert!(hit);
+        assert_eq!(cached, audio);
+        assert_eq!(harness.server.synthesize_calls(), 1);
+        assert_eq!(
+            harness.tts.day_characters(),
+            "Hello from the mock.".len() as u64
+        );
+
+        speak(&harness, "en-US-Mock-B", "Hello from the mock.")
+            .await
+            .unwrap();
+        assert_eq!(harness.server.synthesize_calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn statuses_map_to_app_errors() {
+        let harness = harness().await;
+        harness.server.fail_next(Fault::Unavailable, 1);
+        harness.server.fail_next(Fault::ResourceExhausted, 1);
+        harness.server.fail_next(Fault::Unauthenticated, 1);
+        assert!(matches!(
+            speak(&harness, VOICE, "one").await,
+            Err(AppError::TtsOffline { .. })
+        ));
+        assert!(matches!(
+            speak(&harness, VOICE, "two").await,
+            Err(AppError::TtsQuotaExceeded { .. })
+        ));
+        assert!(matches!(
+            speak(&harness, VOICE, "three").await,
+            Err(AppError::TtsUnauthorized { .. })
+        ));
+        assert!(matches!(
+            speak(&harness, "en-US-Mock-Exhausted", "four").await,
+            Err(AppError::TtsQuotaExceeded { .. })
+        ));
+        assert!(matches!(
+            speak(&harness, "en-US-Nonexistent", "five").await,
+            Err(AppError::Tts { .. })
+        ));
+        // Failed calls aren't billed, and leave nothing cached
+        assert_eq!(harness.tts.day_characters(), 0);
+        let (_, hit) = speak(&harness, VOICE, "one").await.unwrap();
+        assert!(!hit);
+    }
+
+    #[tokio::test]
+    async fn cancelled_call_releases_its_slot_and_spend() {
+        let harness = harness().await;
+        harness.tts.spend().set_caps(SpendCaps {
+            daily_characters: Some(100),
+            ..Default::default()
+        });
+        harness.server.set_delay(Duration::from_secs(5));
+        let text = "x".repeat(80);
+        let cancelled =
+            tokio::time::timeout(Duration::from_millis(200), speak(&harness, VOICE, &text)).await;
+        assert!(cancelled.is_err());
+        assert_eq!(harness.tts.queue_status().in_use, 0);
+
+        // Neither the dropped call's characters nor its dedup entry linger
+        harness.server.set_delay(Duration::ZERO);
+        let (_, hit) = speak(&harness, VOICE, &text).await.unwrap();
+        assert!(!hit);
+        assert_eq!(harness.tts.day_characters(), 80);
+        assert!(matches!(
+            speak(&harness, VOICE, &"y".repeat(40)).await,
+            Err(AppError::SpendCapExceeded { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn sends_the_quota_project_header() {
+        let harness = harness().await;
+        harness
+            .tts
+            .set_quota_project(Some("billing-project".to_string()));
+        speak(&harness, VOICE, "Billed elsewhere.").await.unwrap();
+        let received = harness.server.received();
+        assert_eq!(received[0].user_project.as_deref(), Some("billing-project"));
+        assert_eq!(input_text(&received[0].request), "Billed elsewhere.");
+    }
+
+    #[tokio::test]
+    async fn linear16_is_a_deterministic_wav() {
+        let harness = harness().await;
+        let synthesize = || {
+            synthesis::synthesize_linear16(
+                &harness.tts,
+                VOICE,
+                "en-US",
+                SpeechInput::Text("Tone please.".to_string()),
+                Some(16_000),
+                None,
+                Lane::Interactive,
+                None,
+            )
+        };
+        let first = synthesize().await.unwrap();
+        let second = synthesize().await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(&first[..4], b"RIFF");
+        assert_eq!(
+            u32::from_le_bytes(first[24..28].try_into().unwrap()),
+            16_000
+        );
+        assert_eq!(harness.server.synthesize_calls(), 2);
+    }
+}
@@ -0,0 +1,629 @@
+// Shared Google Text-to-Speech client
+pub mod chunks;
+pub mod diff_preview;
+pub mod fingerprint;
+pub mod health;
+pub mod history;
+pub mod lanes;
+pub mod length;
+#[cfg(any(test, feature = "mock-tts"))]
+pub mod mock;
+pub mod native;
+pub mod placeholders;
+pub mod plan;
+pub mod pronounce;
+pub mod quota;
+pub mod recording;
+pub mod speakable;
+pub mod spend;
+pub mod synthesis;
+pub mod usage;
+pub mod voice_list;
+
+use chrono::{DateTime, Utc};
+use gcloud_sdk::google::cloud::speech::v1::speech_client::SpeechClient;
+use gcloud_sdk::google::cloud::texttospeech::v1::text_to_speech_client::TextToSpeechClient;
+use gcloud_sdk::google::cloud::texttospeech::v1::{ListVoicesRequest, Voice};
+use gcloud_sdk::tonic;
+use gcloud_sdk::{
+    ExternalJwtFunctionSource, GoogleApi, GoogleAuthMiddleware, Token, TokenSourceType,
+    GCP_DEFAULT_SCOPES,
+};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::{OnceCell, RwLock};
+
+use crate::credentials::ActiveCredentials;
+use crate::error::AppError;
+use crate::read_only::WriteAccess;
+use crate::settings::SettingsStore;
+use crate::ssml::policy::SsmlPolicy;
+use crate::voices::remap::VoiceRemap;
+use health::{is_connection_error, ConnectionHealth, ConnectionStatusReport};
+use lanes::{Lane, QueueStatus, Scheduler, Slot};
+use recording::{Recorder, RecordingStatus};
+use spend::SpendGuard;
+use usage::{
+    BudgetStatus, Reservation, UsageLedger, UsageTotals, UsageVerification, DEFAULT_PROFILE,
+};
+use voice_list::StoredVoiceList;
+
+pub type TtsApi = GoogleApi<TextToSpeechClient<GoogleAuthMiddleware>>;
+/// Speech-to-Text, used to align recorded narration with its script.
+pub type SpeechApi = GoogleApi<SpeechClient<GoogleAuthMiddleware>>;
+
+const TTS_ENDPOINT: &str = "https://texttospeech.googleapis.com";
+const SPEECH_ENDPOINT: &str = "https://speech.googleapis.com";
+/// Debug builds connect here instead when it's set, e.g. to the `mock-tts`
+/// server; release builds ignore it.
+const ENDPOINT_OVERRIDE_VAR: &str = "SCLIP_TTS_ENDPOINT";
+/// Synthesis calls the app has open to Google at once, across every
+/// command and, through the slot files, the CLI.
+const MAX_CONCURRENT_SYNTHESES: usize = 4;
+/// Directory of the data dir holding the slot files.
+pub const SLOTS_DIR: &str = "tts_slots";
+
+type StatusListener = Box<dyn Fn(ConnectionStatusReport) + Send + Sync>;
+type InFlight = Arc<OnceCell<Result<Vec<u8>, AppError>>>;
+
+/// A voice list and how current it is.
+#[derive(Debug, Clone)]
+pub struct FetchedVoices {
+    pub voices: Vec<Voice>,
+    pub fetched_at: DateTime<Utc>,
+    /// Loaded from the list stored by an earlier launch, or the starter
+    /// snapshot, rather than fetched by this one.
+    pub from_cache: bool,
+}
+
+/// Managed state owning the one TTS client every command shares, plus the
+/// health record of the calls made through it.
+pub struct TtsService {
+    credentials: RwLock<Option<ActiveCredentials>>,
+    client: RwLock<Option<Arc<TtsApi>>>,
+    /// Authenticated like `client`, and rebuilt with it.
+    speech_client: RwLock<Option<Arc<SpeechApi>>>,
+    /// Where the client connects; Google's global endpoint unless replaced
+    /// with `with_endpoint`.
+    endpoint: String,
+    voices: RwLock<Option<FetchedVoices>>,
+    /// Where each fetched list is stored for the next launch.
+    voice_list_path: Option<PathBuf>,
+    health: Mutex<ConnectionHealth>,
+    usage: Mutex<UsageLedger>,
+    /// Synthesis calls in progress, by request fingerprint.
+    in_flight: Mutex<HashMap<String, InFlight>>,
+    synthesis_slots: Scheduler,
+    /// Project usage is billed to when a call doesn't name one; mirrors the
+    /// `quota_project` setting.
+    quota_project: Mutex<Option<String>>,
+    /// Applied to every SSML request; mirrors the `ssml_policy` setting.
+    ssml_policy: Mutex<SsmlPolicy>,
+    /// Renames of retired voices; mirrors the `voice_remap` setting.
+    voice_remap: Mutex<VoiceRemap>,
+    /// Caps mirroring the `spend_caps` setting, and their confirmations.
+    spend: SpendGuard,
+    recorder: Recorder,
+    on_status_change: StatusListener,
+}
+
+impl TtsService {
+    pub fn new(
+        credentials: Option<ActiveCredentials>,
+        quota_project: Option<String>,
+        recorder: Recorder,
+        usage: UsageLedger,
+        on_status_change: impl Fn(ConnectionStatusReport) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            credentials: RwLock::new(credentials),
+            client: RwLock::new(None),
+            speech_client: RwLock::new(None),
+            endpoint: TTS_ENDPOINT.to_string(),
+            voices: RwLock::new(None),
+            voice_list_path: None,
+            health: Mutex::new(ConnectionHealth::default()),
+            usage: Mutex::new(usage),
+            in_flight: Mutex::new(HashMap::new()),
+            synthesis_slots: Scheduler::new(MAX_CONCURRENT_SYNTHESES),
+            quota_project: Mutex::new(quota_project),
+            ssml_policy: Mutex::new(SsmlPolicy::default()),
+            voice_remap: Mutex::new(VoiceRemap::new(&Default::default())),
+            spend: SpendGuard::default(),
+            recorder,
+            on_status_change: Box::new(on_status_change),
+        }
+    }
+
+    /// Stores fetched voice lists at `path`, starting from the one already
+    /// there until the first fetch.
+    pub fn with_voice_list(self, path: PathBuf) -> Self {
+        let stored = voice_list::load(&path).map(|list| FetchedVoices {
+            fetched_at: list.fetched_at,
+            voices: list.into_voices(),
+            from_cache: true,
+        });
+        Self {
+            voices: RwLock::new(stored),
+            voice_list_path: Some(path),
+            ..self
+        }
+    }
+
+    /// Shares the cap on synthesis calls with other processes using `dir`,
+    /// so the CLI and the app together stay under it.
+    pub fn with_shared_slots(self, dir: PathBuf) -> Self {
+        Self {
+            synthesis_slots: self.synthesis_slots.with_shared_dir(dir),
+            ..self
+        }
+    }
+
+    /// Connects to `endpoint` instead of Google's global one.
+    pub fn with_endpoint(self, endpoint: String) -> Self {
+        log::info!("TTS calls go to {}", endpoint);
+        Self { endpoint, ..self }
+    }
+
+    /// Reports every change of the synthesis queues to `listener`.
+    pub fn with_queue_listener(
+        self,
+        listener: impl Fn(QueueStatus) + Send + Sync + 'static,
+    ) -> Self {
+        self.synthesis_slots.set_listener(listener);
+        self
+    }
+
+    /// Switches the credentials future calls authenticate with. The client is
+    /// rebuilt on next use and the voice list is fetched again, since
+    /// projects can differ in which voices they are allowed to use.
+    pub async fn set_credentials(&self, credentials: Option<ActiveCredentials>) {
+        let mut current = self.credentials.write().await;
+        *self.client.write().await = None;
+        *self.speech_client.write().await = None;
+        *self.voices.write().await = None;
+        *current = credentials;
+    }
+
+    pub fn set_quota_project(&self, quota_project: Option<String>) {
+        *self.quota_project.lock().unwrap() = quota_project;
+    }
+
+    pub fn set_ssml_policy(&self, policy: SsmlPolicy) {
+        *self.ssml_policy.lock().unwrap() = policy;
+    }
+
+    pub fn ssml_policy(&self) -> SsmlPolicy {
+        *self.ssml_policy.lock().unwrap()
+    }
+
+    pub fn set_voice_remap(&self, remap: VoiceRemap) {
+        *self.voice_remap.lock().unwrap() = remap;
+    }
+
+    pub fn voice_remap(&self) -> VoiceRemap {
+        self.voice_remap.lock().unwrap().clone()
+    }
+
+    pub fn spend(&self) -> &SpendGuard {
+        &self.spend
+    }
+
+    /// Characters billed in the last 24 hours, for the daily spend cap.
+    pub fn day_characters(&self) -> u64 {
+        self.usage.lock().unwrap().last_day_characters(Utc::now())
+    }
+
+    /// The quota project for a call: its own override, else the default.
+    pub fn quota_project(&self, call_override: Option<&str>) -> Option<String> {
+        call_override
+            .map(String::from)
+            .or_else(|| self.quota_project.lock().unwrap().clone())
+    }
+
+    pub fn recorder(&self) -> &Recorder {
+        &self.recorder
+    }
+
+    pub fn recording_status(&self) -> RecordingStatus {
+        let settings = self.recorder.settings();
+        RecordingStatus {
+            enabled: settings.enabled,
+            include_text: settings.include_text,
+            directory: self.recorder.dir().clone(),
+        }
+    }
+
+    /// Name usage is accounted under: the active profile, or "default" for
+    /// application-default credentials.
+    async fn usage_profile(&self) -> String {
+        self.credentials
+            .read()
+            .await
+            .as_ref()
+            .map_or_else(|| DEFAULT_PROFILE.to_string(), |c| c.profile.clone())
+    }
+
+    /// Adds one billable request of `characters` to the active profile; on
+    /// disk once this returns.
+    pub async fn record_usage(&self, characters: usize, fingerprint: &str, voice_name: &str) {
+        let profile = self.usage_profile().await;
+        self.usage
+            .lock()
+            .unwrap()
+            .record(&profile, characters, fingerprint, voice_name);
+    }
+
+    /// Runs `request` unless one with the same fingerprint is already
+    /// running, in which case its result is awaited and shared instead.
+    pub async fn dedup(
+        &self,
+        fingerprint: &str,
+        request: impl Future<Output = Result<Vec<u8>, AppError>>,
+    ) -> Result<Vec<u8>, AppError> {
+        let call = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(fingerprint.to_string())
+            .or_default()
+            .clone();
+        let result = call.get_or_init(|| request).await.clone();
+
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight
+            .get(fingerprint)
+            .is_some_and(|current| Arc::ptr_eq(current, &call))
+        {
+            in_flight.remove(fingerprint);
+        }
+        result
+    }
+
+    /// Waits in `lane` for one of the `MAX_CONCURRENT_SYNTHESES` call
+    /// slots, held until the slot is dropped.
+    pub async fn synthesis_slot(&self, lane: Lane) -> Result<Slot, AppError> {
+        self.synthesis_slots.acquire(lane).await
+    }
+
+    pub fn queue_status(&self) -> QueueStatus {
+        self.synthesis_slots.status()
+    }
+
+    pub fn usage(&self) -> UsageTotals {
+        self.usage.lock().unwrap().totals()
+    }
+
+    pub fn verify_usage(&self) -> UsageVerification {
+        self.usage.lock().unwrap().verify()
+    }
+
+    /// Counts characters a batch with `use_reservation` was billed against
+    /// the reservations for its quota project.
+    pub fn draw_reservation(&self, characters: usize) -> u64 {
+        let project = self.quota_project(None);
+        self.usage
+            .lock()
+            .unwrap()
+            .draw(project.as_deref(), characters as u64)
+    }
+
+    /// The voice list from the last fetch, without fetching one.
+    pub async fn cached_voices(&self) -> Option<Vec<Voice>> {
+        self.voices
+            .read()
+            .await
+            .as_ref()
+            .map(|list| list.voices.clone())
+    }
+
+    /// Voices available to the current credentials, cached until `refresh`
+    /// is set or the credentials change. `quota_project` overrides the
+    /// default for a fetch.
+    pub async fn voices(
+        &self,
+        refresh: bool,
+        quota_project: Option<&str>,
+    ) -> Result<Vec<Voice>, AppError> {
+        Ok(self.voice_list(refresh, quota_project).await?.voices)
+    }
+
+    /// `voices` with when the list was fetched, which for a stored list can
+    /// be long ago.
+    pub async fn voice_list(
+        &self,
+        refresh: bool,
+        quota_project: Option<&str>,
+    ) -> Result<FetchedVoices, AppError> {
+        if !refresh {
+            if let Some(list) = self.voices.read().await.as_ref() {
+                return Ok(list.clone());
+            }
+        }
+
+        let client = self.client().await?;
+        let quota_project = self.quota_project(quota_project);
+        let request = quota::request(
+            ListVoicesRequest {
+                ..Default::default()
+            },
+            quota_project.as_deref(),
+        )?;
+        let voices = self
+            .observe(client.get().list_voices(request))
+            .await
+            .map_err(|status| quota::status_error(status, quota_project.as_deref()))?
+            .into_inner()
+            .voices;
+        let list = FetchedVoices {
+            voices,
+            fetched_at: Utc::now(),
+            from_cache: false,
+        };
+        if let Some(path) = &self.voice_list_path {
+            let stored = StoredVoiceList::new(&list.voices, list.fetched_at);
+            if let Err(e) = voice_list::save(path, &stored) {
+                log::warn!("Failed to store the voice list: {}", e);
+            }
+        }
+        *self.voices.write().await = Some(list.clone());
+        Ok(list)
+    }
+
+    /// Replaces a voice list loaded from disk with a fetched one; true if
+    /// it did, false if the list was already current.
+    pub async fn refresh_stored_voices(&self) -> Result<bool, AppError> {
+        let stored = self
+            .voices
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|list| list.from_cache);
+        if !stored {
+            return Ok(false);
+        }
+        self.voice_list(true, None).await?;
+        Ok(true)
+    }
+
+    /// Returns the shared client, connecting on first use.
+    pub async fn client(&self) -> Result<Arc<TtsApi>, AppError> {
+        if let Some(client) = self.client.read().await.as_ref() {
+            return Ok(client.clone());
+        }
+
+        let credentials = self.credentials.read().await;
+        let mut slot = self.client.write().await;
+        if let Some(client) = slot.as_ref() {
+            return Ok(client.clone());
+        }
+        let client = Arc::new(
+            GoogleApi::from_function_with_token_source(
+                TextToSpeechClient::new,
+                &self.endpoint,
+                None,
+                GCP_DEFAULT_SCOPES.clone(),
+                token_source(&self.endpoint, credentials.as_ref()),
+            )
+            .await?,
+        );
+        *slot = Some(client.clone());
+        Ok(client)
+    }
+
+    /// Returns the shared Speech-to-Text client, connecting on first use
+    /// with the credentials synthesis uses.
+    pub async fn speech_client(&self) -> Result<Arc<SpeechApi>, AppError> {
+        if let Some(client) = self.speech_client.read().await.as_ref() {
+            return Ok(client.clone());
+        }
+
+        let credentials = self.credentials.read().await;
+        let mut slot = self.speech_client.write().await;
+        if let Some(client) = slot.as_ref() {
+            return Ok(client.clone());
+        }
+        let client = Arc::new(
+            GoogleApi::from_function_with_token_source(
+                SpeechClient::new,
+                SPEECH_ENDPOINT,
+                None,
+                GCP_DEFAULT_SCOPES.clone(),
+                token_source(SPEECH_ENDPOINT, credentials.as_ref()),
+            )
+            .await?,
+        );
+        *slot = Some(client.clone());
+        Ok(client)
+    }
+
+    /// Runs one API call and records its outcome and latency.
+    pub async fn observe<T>(
+        &self,
+        call: impl Future<Output = Result<T, tonic::Status>>,
+    ) -> Result<T, tonic::Status> {
+        let started = Instant::now();
+        let result = call.await;
+        let latency = started.elapsed();
+
+        let (ok, error) = match &result {
+            Ok(_) => (true, None),
+            Err(status) if is_connection_error(status.code()) => (false, Some(status.to_string())),
+            Err(_) => (true, None),
+        };
+        let changed = {
+            let mut health = self.health.lock().unwrap();
+            health.record(ok, latency, error).map(|_| health.report())
+        };
+        if let Some(report) = changed {
+            (self.on_status_change)(report);
+        }
+        result
+    }
+
+    pub fn connection_status(&self) -> ConnectionStatusReport {
+        self.health.lock().unwrap().report()
+    }
+}
+
+/// Without an active profile this relies on application-default
+/// credentials, typically GOOGLE_APPLICATION_CREDENTIALS pointing at a
+/// service account key file. A plain-HTTP endpoint can only be a local
+/// server such as `mock::start_mock_tts`, which gets a placeholder token
+/// rather than one fetched from Google.
+fn token_source(endpoint: &str, credentials: Option<&ActiveCredentials>) -> TokenSourceType {
+    if endpoint.starts_with("http://") {
+        return TokenSourceType::ExternalSource(Box::new(ExternalJwtFunctionSource::new(
+            || async {
+                Ok(Token::new(
+                    "Bearer".to_string(),
+                    "local".into(),
+                    Utc::now() + chrono::Duration::hours(1),
+                ))
+            },
+        )));
+    }
+    match credentials {
+        Some(credentials) => TokenSourceType::Json(credentials.key_json.clone()),
+        None => TokenSourceType::Default,
+    }
+}
+
+/// The endpoint from `SCLIP_TTS_ENDPOINT`, in debug builds only.
+pub fn endpoint_override() -> Option<String> {
+    if !cfg!(debug_assertions) {
+        return None;
+    }
+    std::env::var(ENDPOINT_OVERRIDE_VAR)
+        .ok()
+        .map(|endpoint| endpoint.trim().to_string())
+        .filter(|endpoint| !endpoint.is_empty())
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_tts_connection_status(tts: tauri::State<'_, TtsService>) -> ConnectionStatusReport {
+    tts.connection_status()
+}
+
+/// The current "tts-queue-status", for a frontend that just started
+/// listening.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_tts_queue_status(tts: tauri::State<'_, TtsService>) -> QueueStatus {
+    tts.queue_status()
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_tts_usage(tts: tauri::State<'_, TtsService>) -> UsageTotals {
+    tts.usage()
+}
+
+/// This month's usage and reservations against the `usage_budget` setting.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_tts_budget(
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+) -> BudgetStatus {
+    tts.usage
+        .lock()
+        .unwrap()
+        .budget_status(&settings.get().usage_budget)
+}
+
+/// What `reserve_tts_budget` returns.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ReservationReceipt {
+    pub reservation: Reservation,
+    pub budget: BudgetStatus,
+}
+
+/// Sets `characters` of the monthly budget aside for batches billed to
+/// `project_id` (the credentials' own project when unset) until
+/// `expires_at`. Local bookkeeping only: Google doesn't hold the quota.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn reserve_tts_budget(
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+    access: tauri::State<'_, WriteAccess>,
+    project_id: Option<String>,
+    characters: u64,
+    expires_at: DateTime<Utc>,
+) -> Result<ReservationReceipt, AppError> {
+    access.check()?;
+    if let Some(project_id) = &project_id {
+        quota::validate_project_id(project_id)?;
+    }
+    let budget = settings.get().usage_budget;
+    let mut usage = tts.usage.lock().unwrap();
+    let reservation = usage.reserve(project_id, characters, expires_at, &budget)?;
+    Ok(ReservationReceipt {
+        reservation,
+        budget: usage.budget_status(&budget),
+    })
+}
+
+/// Every reservation, newest first; expired and released ones are kept
+/// for 30 days.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn list_reservations(tts: tauri::State<'_, TtsService>) -> Vec<Reservation> {
+    tts.usage.lock().unwrap().reservations()
+}
+
+/// Frees what's left of a reservation.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn release_reservation(
+    tts: tauri::State<'_, TtsService>,
+    access: tauri::State<'_, WriteAccess>,
+    id: String,
+) -> Result<Reservation, AppError> {
+    access.check()?;
+    tts.usage.lock().unwrap().release(&id)
+}
+
+/// Recomputes usage from the journal on disk and reports where it differs
+/// from what `get_tts_usage` returns.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn verify_usage_journal(tts: tauri::State<'_, TtsService>) -> UsageVerification {
+    tts.verify_usage()
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![
+        synthesis::synthesize_speech,
+        synthesis::synthesize_speech_audio,
+        synthesis::plan_synthesis,
+        native::list_native_voices,
+        plan::validate_synthesis_plan,
+        get_tts_connection_status,
+        get_tts_usage,
+        verify_usage_journal,
+        get_tts_budget,
+        reserve_tts_budget,
+        list_reservations,
+        release_reservation,
+        spend::confirm_spend,
+        fingerprint::fingerprint_tts_request,
+        recording::set_tts_recording,
+        recording::replay_tts_recording,
+        pronounce::pronounce_word,
+        chunks::synthesize_long_speech,
+        diff_preview::preview_changes,
+        length::measure_synthesis_input,
+        get_tts_queue_status,
+        history::get_synthesis_history,
+        history::restore_history_entry,
+        history::pin_history_entry,
+        history::clear_history,
+    ]
+}
@@ -0,0 +1,285 @@
+// Offline scratch-track synthesis through the operating system's own speech
+// engine: SAPI on Windows, `say` on macOS, espeak-ng on Linux
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::error::AppError;
+use crate::voices::capabilities::Capabilities;
+use crate::voices::google::GoogleVoice;
+
+/// `technology` of every native voice.
+pub const TECHNOLOGY: &str = "Native";
+/// Native voice names carry this prefix so they can never be mistaken for,
+/// or sent to, Google.
+pub const VOICE_PREFIX: &str = "native:";
+
+/// The OS voice id behind a `native:` voice name.
+pub fn voice_id(voice_name: &str) -> Option<&str> {
+    voice_name.strip_prefix(VOICE_PREFIX)
+}
+
+fn native_error(message: impl Into<String>) -> AppError {
+    AppError::NativeTts {
+        message: message.into(),
+    }
+}
+
+/// Platform voice as reported by the engine.
+struct OsVoice {
+    id: String,
+    language_code: String,
+    gender: String,
+}
+
+impl OsVoice {
+    fn into_google_voice(self) -> GoogleVoice {
+        GoogleVoice {
+            name: format!("{}{}", VOICE_PREFIX, self.id),
+            display_name: format!("{} (offline)", self.id),
+            language_name: crate::voices::google::get_language_display_name(&self.language_code),
+            language_codes: vec![self.language_code],
+            gender: self.gender,
+            technology: TECHNOLOGY.to_string(),
+            variant: String::new(),
+            preview_path: String::new(),
+            capabilities: Capabilities::NONE,
+            preview_generated_at: None,
+        }
+    }
+}
+
+/// "en_US" and "en-us" both become "en-US".
+fn language_tag(code: &str) -> String {
+    let mut parts = code.split(['_', '-']);
+    let language = parts.next().unwrap_or_default().to_ascii_lowercase();
+    match parts.next() {
+        Some(region) => format!("{}-{}", language, region.to_ascii_uppercase()),
+        None => language,
+    }
+}
+
+async fn run(command: &mut Command, stdin: Option<&str>) -> Result<String, AppError> {
+    command
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(windows)]
+    {
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+    let mut child = command
+        .spawn()
+        .map_err(|e| native_error(format!("speech engine not available: {}", e)))?;
+    if let (Some(text), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(text.as_bytes()).await?;
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(native_error(format!(
+            "speech engine exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(target_os = "macos")]
+async fn os_voices() -> Result<Vec<OsVoice>, AppError> {
+    // Lines look like "Bad News            en_US    # The light you see..."
+    let listing = run(Command::new("say").args(["-v", "?"]), None).await?;
+    Ok(listing
+        .lines()
+        .filter_map(|line| {
+            let (left, _) = line.split_once('#')?;
+            let left = left.trim_end();
+            let (name, locale) = left.rsplit_once(char::is_whitespace)?;
+            Some(OsVoice {
+                id: name.trim().to_string(),
+                language_code: language_tag(locale),
+                gender: "Unspecified".to_string(),
+            })
+        })
+        .collect())
+}
+
+#[cfg(target_os = "macos")]
+async fn render(voice: Option<&str>, text: &str, output: &Path) -> Result<(), AppError> {
+    let mut command = Command::new("say");
+    if let Some(voice) = voice {
+        command.args(["-v", voice]);
+    }
+    command
+        .arg("-o")
+        .arg(output)
+        .args(["--file-format=WAVE", "--data-format=LEI16@22050"]);
+    run(&mut command, Some(text)).await.map(|_| ())
+}
+
+// speech-dispatcher can only play to the sound card, so files are rendered
+// with espeak-ng, its default output module
+#[cfg(all(unix, not(target_os = "macos")))]
+async fn os_voices() -> Result<Vec<OsVoice>, AppError> {
+    // " 5  en-us           --/M      English_(America)  gmw/en-US   (en 10)"
+    let listing = run(Command::new("espeak-ng").arg("--voices"), None).await?;
+    Ok(listing
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let _priority = columns.next()?;
+            let language = columns.next()?;
+            let gender = match columns.next()?.rsplit('/').next()? {
+                "M" => "Male",
+                "F" => "Female",
+                _ => "Unspecified",
+            };
+            let _name = columns.next()?;
+            let file = columns.next()?;
+            Some(OsVoice {
+                id: file.to_string(),
+                language_code: language_tag(language),
+                gender: gender.to_string(),
+            })
+        })
+        .collect())
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+async fn render(voice: Option<&str>, text: &str, output: &Path) -> Result<(), AppError> {
+    let mut command = Command::new("espeak-ng");
+    if let Some(voice) = voice {
+        command.args(["-v", voice]);
+    }
+    command.arg("-w").arg(output).arg("--stdin");
+    run(&mut command, Some(text)).await.map(|_| ())
+}
+
+// Values reach PowerShell through the environment, never the script text
+#[cfg(windows)]
+const LIST_SCRIPT: &str = "Add-Type -AssemblyName System.Speech; \
+    $s = New-Object System.Speech.Synthesis.SpeechSynthesizer; \
+    $s.GetInstalledVoices() | Where-Object { $_.Enabled } | ForEach-Object { \
+    $v = $_.VoiceInfo; \"$($v.Name)`t$($v.Culture.Name)`t$($v.Gender)\" }";
+
+#[cfg(windows)]
+const RENDER_SCRIPT: &str = "Add-Type -AssemblyName System.Speech; \
+    $s = New-Object System.Speech.Synthesis.SpeechSynthesizer; \
+    if ($env:SCLIP_NATIVE_VOICE) { $s.SelectVoice($env:SCLIP_NATIVE_VOICE) }; \
+    $s.SetOutputToWaveFile($env:SCLIP_NATIVE_OUTPUT); \
+    $s.Speak([Console]::In.ReadToEnd()); $s.Dispose()";
+
+#[cfg(windows)]
+fn powershell(script: &str) -> Command {
+    let mut command = Command::new("powershell");
+    command.args(["-NoProfile", "-NonInteractive", "-Command", script]);
+    command
+}
+
+#[cfg(windows)]
+async fn os_voices() -> Result<Vec<OsVoice>, AppError> {
+    let listing = run(&mut powershell(LIST_SCRIPT), None).await?;
+    Ok(listing
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.trim_end().split('\t');
+            Some(OsVoice {
+                id: fields.next()?.to_string(),
+                language_code: language_tag(fields.next()?),
+                gender: fields.next().unwrap_or("Unspecified").to_string(),
+            })
+        })
+        .collect())
+}
+
+#[cfg(windows)]
+async fn render(voice: Option<&str>, text: &str, output: &Path) -> Result<(), AppError> {
+    let mut command = powershell(RENDER_SCRIPT);
+    command
+        .env("SCLIP_NATIVE_VOICE", voice.unwrap_or_default())
+        .env("SCLIP_NATIVE_OUTPUT", output);
+    run(&mut command, Some(text)).await.map(|_| ())
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn os_voices() -> Result<Vec<OsVoice>, AppError> {
+    Err(native_error("no speech engine on this platform"))
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn render(_voice: Option<&str>, _text: &str, _output: &Path) -> Result<(), AppError> {
+    Err(native_error("no speech engine on this platform"))
+}
+
+pub async fn list_voices() -> Result<Vec<GoogleVoice>, AppError> {
+    Ok(os_voices()
+        .await?
+        .into_iter()
+        .map(OsVoice::into_google_voice)
+        .collect())
+}
+
+/// Picks the installed voice closest to `language_code`: an exact match,
+/// then the same base language, then the engine default.
+async fn voice_for(language_code: &str) -> Option<String> {
+    let voices = os_voices().await.ok()?;
+    let base = language_code.split('-').next().unwrap_or(language_code);
+    voices
+        .iter()
+        .find(|v| v.language_code.eq_ignore_ascii_case(language_code))
+        .or_else(|| {
+            voices.iter().find(|v| {
+                v.language_code
+                    .split('-')
+                    .next()
+                    .is_some_and(|b| b.eq_ignore_ascii_case(base))
+            })
+        })
+        .map(|v| v.id.clone())
+}
+
+/// Renders `text` to WAV with the native voice `voice` (an OS voice id), or
+/// one matching `language_code` when `None`. Returns the `native:` voice name
+/// used and the audio.
+pub async fn synthesize(
+    voice: Option<&str>,
+    language_code: &str,
+    text: &str,
+) -> Result<(String, Vec<u8>), AppError> {
+    let voice = match voice {
+        Some(voice) => Some(voice.to_string()),
+        None => voice_for(language_code).await,
+    };
+    let dir = std::env::temp_dir().join("sclip-native-tts");
+    tokio::fs::create_dir_all(&dir).await?;
+    let output: PathBuf = dir.join(format!("{}.wav", uuid::Uuid::new_v4()));
+
+    let result = render(voice.as_deref(), text, &output).await;
+    let audio = match result {
+        Ok(()) => tokio::fs::read(&output).await.map_err(AppError::from),
+        Err(e) => Err(e),
+    };
+    let _ = tokio::fs::remove_file(&output).await;
+    let name = format!(
+        "{}{}",
+        VOICE_PREFIX,
+        voice.unwrap_or_else(|| "default".into())
+    );
+    Ok((name, audio?))
+}
+
+/// Voices of the OS speech engine, shaped like the Google list with
+/// `technology: "Native"`.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn list_native_voices() -> Result<Vec<GoogleVoice>, AppError> {
+    list_voices().await
+}
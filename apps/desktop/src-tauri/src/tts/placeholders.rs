@@ -0,0 +1,215 @@
+// Template placeholders left in a script, such as "{{name}}" or "[INSERT
+// QUOTE]", which would otherwise be read out and billed
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use tauri::Emitter;
+
+use crate::error::AppError;
+
+/// Longest text between delimiters still taken for a placeholder; longer
+/// spans are prose.
+const MAX_INNER_CHARS: usize = 60;
+
+/// Checked whatever the settings add: mail-merge fields, bracketed
+/// instructions to the writer, and the usual to-do markers.
+const BUILT_IN: &[(&str, Option<&str>)] = &[
+    ("{{", Some("}}")),
+    ("[INSERT", Some("]")),
+    ("<TODO>", None),
+    ("XXX", None),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlaceholderPattern {
+    /// Case-sensitive text a placeholder starts with, e.g. "{{".
+    pub open: String,
+    /// Text it ends with on the same line. `None` matches `open` alone, as
+    /// a whole word if it starts or ends with one.
+    #[serde(default)]
+    pub close: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlaceholderSettings {
+    /// Refuses synthesis of text with placeholders instead of warning.
+    pub strict: bool,
+    /// Checked besides the built-in patterns.
+    pub patterns: Vec<PlaceholderPattern>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlaceholderMatch {
+    /// In characters from the start of the text.
+    pub offset: usize,
+    pub text: String,
+}
+
+/// Payload of the "placeholders-detected" event.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaceholdersDetected {
+    pub matches: Vec<PlaceholderMatch>,
+}
+
+/// The built-in patterns plus those from settings.
+pub struct Placeholders {
+    pub strict: bool,
+    patterns: Vec<PlaceholderPattern>,
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Characters of a writer's note such as "client's name: first, last".
+/// Code between the same delimiters, like `{{ user.name | upper }}` in a
+/// script about templates, has others and isn't flagged.
+fn reads_as_words(c: char) -> bool {
+    is_word(c) || matches!(c, ' ' | '\t' | '-' | ':' | ',' | '\'' | '’' | '/' | '#')
+}
+
+/// Byte ranges of `code spans` and ```fenced blocks```, which quote code
+/// rather than leave something to fill in.
+fn code_spans(text: &str) -> Vec<Range<usize>> {
+    let mut spans = Vec::new();
+    let mut open: Option<(usize, usize)> = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c != '`' {
+            continue;
+        }
+        let mut end = start + 1;
+        while let Some(&(i, '`')) = chars.peek() {
+            end = i + 1;
+            chars.next();
+        }
+        let run = end - start;
+        match open {
+            // A span closes at a run of as many backticks as opened it
+            Some((from, opened)) if opened == run => {
+                spans.push(from..end);
+                open = None;
+            }
+            Some(_) => {}
+            None => open = Some((start, run)),
+        }
+    }
+    spans
+}
+
+/// End of the placeholder `pattern` matches at byte `start` of `text`.
+fn match_at(text: &str, start: usize, pattern: &PlaceholderPattern) -> Option<usize> {
+    let ends_in_word = |s: &str| s.chars().next_back().is_some_and(is_word);
+    let word_follows = |at: usize| text[at..].chars().next().is_some_and(is_word);
+    // Not glued to a word before it, so `items[INSERT]` and `fooXXX` are
+    // left alone
+    if text[..start].chars().next_back().is_some_and(is_word) {
+        return None;
+    }
+    let after_open = start + pattern.open.len();
+    if ends_in_word(&pattern.open) && word_follows(after_open) {
+        return None;
+    }
+    let Some(close) = pattern.close.as_deref() else {
+        return Some(after_open);
+    };
+
+    let rest = &text[after_open..];
+    let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+    let inner = &line[..line.find(close)?];
+    if inner.chars().count() > MAX_INNER_CHARS || !inner.chars().all(reads_as_words) {
+        return None;
+    }
+    // "{{}}" is an escaped brace pair, not an empty field
+    if !pattern
+        .open
+        .chars()
+        .chain(inner.chars())
+        .any(char::is_alphanumeric)
+    {
+        return None;
+    }
+    let end = after_open + inner.len() + close.len();
+    if ends_in_word(close) && word_follows(end) {
+        return None;
+    }
+    Some(end)
+}
+
+impl Placeholders {
+    pub fn new(settings: &PlaceholderSettings) -> Self {
+        let built_in = BUILT_IN.iter().map(|(open, close)| PlaceholderPattern {
+            open: open.to_string(),
+            close: close.map(str::to_string),
+        });
+        let patterns = built_in
+            .chain(settings.patterns.iter().cloned())
+            .filter(|p| !p.open.is_empty() && p.close.as_deref() != Some(""))
+            .collect();
+        Self {
+            strict: settings.strict,
+            patterns,
+        }
+    }
+
+    /// Every placeholder in `text`, in order; a match overlapping an
+    /// earlier one is dropped.
+    pub fn find(&self, text: &str) -> Vec<PlaceholderMatch> {
+        let code = code_spans(text);
+        let mut found: Vec<Range<usize>> = Vec::new();
+        for pattern in &self.patterns {
+            for (start, _) in text.match_indices(pattern.open.as_str()) {
+                if let Some(end) = match_at(text, start, pattern) {
+                    found.push(start..end);
+                }
+            }
+        }
+        found.retain(|m| !code.iter().any(|c| c.start <= m.start && m.start < c.end));
+        found.sort_by_key(|m| (m.start, std::cmp::Reverse(m.end)));
+
+        let mut matches = Vec::new();
+        let mut taken = 0;
+        for m in found {
+            if m.start < taken {
+                continue;
+            }
+            taken = m.end;
+            matches.push(PlaceholderMatch {
+                offset: text[..m.start].chars().count(),
+                text: text[m].to_string(),
+            });
+        }
+        matches
+    }
+}
+
+/// The placeholders in `text`, or `PlaceholdersPresent` in strict mode if
+/// it has any.
+pub fn refuse_strict(
+    placeholders: &Placeholders,
+    text: &str,
+) -> Result<Vec<PlaceholderMatch>, AppError> {
+    let matches = placeholders.find(text);
+    if placeholders.strict && !matches.is_empty() {
+        return Err(AppError::PlaceholdersPresent { matches });
+    }
+    Ok(matches)
+}
+
+/// Refuses `text` with `PlaceholdersPresent` in strict mode if it has
+/// placeholders; otherwise reports them with a "placeholders-detected"
+/// event and lets synthesis go ahead.
+pub fn check(
+    app_handle: &tauri::AppHandle,
+    placeholders: &Placeholders,
+    text: &str,
+) -> Result<(), AppError> {
+    let matches = refuse_strict(placeholders, text)?;
+    if matches.is_empty() {
+        return Ok(());
+    }
+    if let Err(e) = app_handle.emit("placeholders-detected", PlaceholdersDetected { matches }) {
+        log::warn!("Failed to emit placeholders-detected: {}", e);
+    }
+    Ok(())
+}
@@ -0,0 +1,874 @@
+// Local pre-flight checks of synthesis requests, so a script can be vetted
+// before anything is billed
+use gcloud_sdk::google::cloud::texttospeech::v1::Voice;
+use serde::Serialize;
+
+use super::fingerprint::TtsRequestParams;
+use super::placeholders::Placeholders;
+use super::synthesis::{SpeechInput, SynthesisOptions};
+use super::{length, native, speakable, TtsService};
+use crate::batch::BatchSegment;
+use crate::error::AppError;
+use crate::format::DisplayFormat;
+use crate::settings::SettingsStore;
+use crate::spelling::dictionaries::{DictionaryStore, SpellingSettings};
+use crate::spelling::{SpellingIssueKind, SpellingStatus};
+use crate::ssml::BreathingRoom;
+use crate::voices::capabilities::{self, Capabilities, CapabilityStore, UnsupportedFeaturePolicy};
+use crate::voices::name::technology;
+
+/// Google rejects text or SSML inputs longer than this many bytes.
+pub const MAX_INPUT_BYTES: usize = 5000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// Google would reject the request.
+    Error,
+    /// The request would go through but probably not sound as intended.
+    Warning,
+    /// Worth a look, such as a possible typo; never blocks synthesis.
+    Info,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueCode {
+    EmptyText,
+    InputTooLong,
+    MalformedSsml,
+    /// Plain text containing SSML tags, which will be read out literally.
+    LiteralMarkup,
+    UnknownVoice,
+    /// The voice doesn't support the segment's language code.
+    UnsupportedLanguage,
+    /// The text reads as a different language than the voice speaks.
+    LanguageMismatch,
+    /// The request uses SSML, pitch or rate the voice doesn't support.
+    UnsupportedFeature,
+    /// Template placeholders such as "{{name}}" were left in the text; an
+    /// error in strict mode.
+    Placeholder,
+    /// A word the dictionary doesn't know.
+    Misspelled,
+    /// A word that sounds like another one, which may be the one meant.
+    Homophone,
+    /// There is no spelling dictionary for the segment's language.
+    SpellingUnchecked,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanIssue {
+    pub severity: Severity,
+    pub code: IssueCode,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SegmentReport {
+    pub id: String,
+    pub characters: usize,
+    /// Size of the input as sent, pauses included.
+    pub bytes: usize,
+    pub estimated_cost_usd: f64,
+    pub cost_display: String,
+    pub issues: Vec<PlanIssue>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanReport {
+    pub segments: Vec<SegmentReport>,
+    pub total_characters: usize,
+    pub estimated_cost_usd: f64,
+    pub cost_display: String,
+    pub errors: usize,
+    pub warnings: usize,
+    /// False when no voice list was cached, so voice names and language
+    /// support went unchecked.
+    pub voices_checked: bool,
+}
+
+impl PlanReport {
+    /// `InvalidSynthesisPlan` naming the first error, if there is one.
+    pub fn check(&self) -> Result<(), AppError> {
+        let first = self.segments.iter().find_map(|s| {
+            s.issues
+                .iter()
+                .find(|i| i.severity == Severity::Error)
+                .map(|i| format!("segment {}: {}", s.id, i.message))
+        });
+        match first {
+            Some(message) => Err(AppError::InvalidSynthesisPlan {
+                errors: self.errors,
+                message,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// USD per million characters at list price, by voice family. An estimate
+/// only: the free tier and committed-use discounts aren't known here.
+pub fn price_per_million(voice_name: &str) -> f64 {
+    if native::voice_id(voice_name).is_some() {
+        return 0.0;
+    }
+    match technology(voice_name).to_ascii_lowercase().as_str() {
+        "standard" => 4.0,
+        "studio" => 160.0,
+        "journey" | "chirp-hd" | "chirp3-hd" => 30.0,
+        _ => 16.0,
+    }
+}
+
+fn base_language(code: &str) -> &str {
+    code.split(['-', '_']).next().unwrap_or(code)
+}
+
+/// Google base language codes a detected ISO 639-3 language is spoken with.
+fn google_languages(iso639_3: &str) -> &'static [&'static str] {
+    match iso639_3 {
+        "afr" => &["af"],
+        "ara" => &["ar"],
+        "ben" => &["bn"],
+        "bul" => &["bg"],
+        "cat" => &["ca"],
+        "ces" => &["cs"],
+        "cmn" => &["cmn", "zh", "yue"],
+        "dan" => &["da"],
+        "deu" => &["de"],
+        "ell" => &["el"],
+        "eng" => &["en"],
+        "est" => &["et"],
+        "fin" => &["fi"],
+        "fra" => &["fr"],
+        "guj" => &["gu"],
+        "heb" => &["he", "iw"],
+        "hin" => &["hi"],
+        "hrv" => &["hr"],
+        "hun" => &["hu"],
+        "ind" => &["id"],
+        "ita" => &["it"],
+        "jpn" => &["ja"],
+        "kan" => &["kn"],
+        "kor" => &["ko"],
+        "lat" => &["la"],
+        "lav" => &["lv"],
+        "lit" => &["lt"],
+        "mal" => &["ml"],
+        "mar" => &["mr"],
+        "nld" => &["nl"],
+        "nob" => &["nb", "no"],
+        "pan" => &["pa"],
+        "pol" => &["pl"],
+        "por" => &["pt"],
+        "ron" => &["ro"],
+        "rus" => &["ru"],
+        "slk" => &["sk"],
+        "slv" => &["sl"],
+        "spa" => &["es"],
+        "srp" => &["sr"],
+        "swe" => &["sv"],
+        "tam" => &["ta"],
+        "tel" => &["te"],
+        "tgl" => &["fil", "tl"],
+        "tha" => &["th"],
+        "tur" => &["tr"],
+        "ukr" => &["uk"],
+        "urd" => &["ur"],
+        "vie" => &["vi"],
+        _ => &[],
+    }
+}
+
+fn issue(severity: Severity, code: IssueCode, message: String) -> PlanIssue {
+    PlanIssue {
+        severity,
+        code,
+        message,
+    }
+}
+
+/// Every check for one request: `input` is what would be sent for `text`,
+/// and `voices` the cached voice list, if any. Features the voice lacks are
+/// reported as `policy` says, placeholders as `placeholders` says. Makes no
+/// network calls.
+#[allow(clippy::too_many_arguments)]
+pub fn check_segment(
+    id: &str,
+    voice_name: &str,
+    language_code: &str,
+    text: &str,
+    input: &SpeechInput,
+    voices: Option<&[Voice]>,
+    voice_capabilities: &Capabilities,
+    policy: UnsupportedFeaturePolicy,
+    placeholders: &Placeholders,
+    format: &DisplayFormat,
+) -> SegmentReport {
+    let mut issues = Vec::new();
+    let length = length::measure_input(input);
+
+    // Not an error: a batch skips the segment rather than failing
+    if let Some(reason) = speakable::nothing_to_speak(text) {
+        issues.push(issue(
+            Severity::Warning,
+            IssueCode::EmptyText,
+            format!("{}; the segment is skipped", reason),
+        ));
+    }
+    if length.over_limit {
+        issues.push(issue(
+            Severity::Error,
+            IssueCode::InputTooLong,
+            format!(
+                "input is {} bytes, over Google's {}-byte limit; split the segment",
+                length.bytes, length.limit_bytes
+            ),
+        ));
+    }
+    if let SpeechInput::Ssml(ssml) = input {
+        if let Err(reason) = crate::ssml::check_well_formed(ssml) {
+            issues.push(issue(
+                Severity::Error,
+                IssueCode::MalformedSsml,
+                format!("SSML is malformed: {}", reason),
+            ));
+        }
+    }
+    // Script text is always escaped, so markup typed into it is spoken
+    if text.contains("<speak") || text.contains("<break") {
+        issues.push(issue(
+            Severity::Warning,
+            IssueCode::LiteralMarkup,
+            "text contains SSML tags, which will be read out as text".to_string(),
+        ));
+    }
+    let found = placeholders.find(text);
+    if !found.is_empty() {
+        let texts: Vec<&str> = found.iter().map(|m| m.text.as_str()).collect();
+        issues.push(issue(
+            if placeholders.strict {
+                Severity::Error
+            } else {
+                Severity::Warning
+            },
+            IssueCode::Placeholder,
+            format!(
+                "text contains unreplaced placeholders: {}",
+                texts.join(", ")
+            ),
+        ));
+    }
+
+    if let (Some(voices), None) = (voices, native::voice_id(voice_name)) {
+        match voices.iter().find(|v| v.name == voice_name) {
+            None => issues.push(issue(
+                Severity::Error,
+                IssueCode::UnknownVoice,
+                format!("voice {} is not available", voice_name),
+            )),
+            Some(voice)
+                if !voice
+                    .language_codes
+                    .iter()
+                    .any(|c| c.eq_ignore_ascii_case(language_code)) =>
+            {
+                issues.push(issue(
+                    Severity::Error,
+                    IssueCode::UnsupportedLanguage,
+                    format!(
+                        "voice {} does not speak {} (supports {})",
+                        voice_name,
+                        language_code,
+                        voice.language_codes.join(", ")
+                    ),
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+
+    // Short or mixed text isn't detected reliably and is left alone
+    if let Some(info) = whatlang::detect(text).filter(|i| i.is_reliable()) {
+        let detected = google_languages(info.lang().code());
+        let spoken = base_language(language_code);
+        if !detected.is_empty() && !detected.iter().any(|d| d.eq_ignore_ascii_case(spoken)) {
+            issues.push(issue(
+                Severity::Warning,
+                IssueCode::LanguageMismatch,
+                format!(
+                    "text looks like {} but is spoken as {}",
+                    info.lang().eng_name(),
+                    language_code
+                ),
+            ));
+        }
+    }
+
+    let params = TtsRequestParams::speech(voice_name, language_code, input);
+    for feature in capabilities::unsupported_features(&params, voice_capabilities) {
+        let (severity, outcome) = match policy {
+            UnsupportedFeaturePolicy::Warn => (Severity::Warning, "it is sent anyway"),
+            UnsupportedFeaturePolicy::Strip => (Severity::Warning, "it is removed before sending"),
+            UnsupportedFeaturePolicy::Error => (Severity::Error, "the request is refused"),
+        };
+        issues.push(issue(
+            severity,
+            IssueCode::UnsupportedFeature,
+            format!(
+                "voice {} does not support {}; {}",
+                voice_name,
+                feature.as_str(),
+                outcome
+            ),
+        ));
+    }
+
+    let characters = params.billable_characters();
+    let estimated_cost_usd = characters as f64 * price_per_million(voice_name) / 1_000_000.0;
+    SegmentReport {
+        id: id.to_string(),
+        characters,
+        bytes: length.bytes,
+        estimated_cost_usd,
+        cost_display: format.cost_usd(estimated_cost_usd),
+        issues,
+    }
+}
+
+/// Checks a whole script with the given pauses against `voices`.
+#[allow(clippy::too_many_arguments)]
+pub fn validate(
+    segments: &[BatchSegment],
+    paragraph_pause_ms: u32,
+    line_pause_ms: u32,
+    breathing: BreathingRoom,
+    voices: Option<&[Voice]>,
+    capabilities: &CapabilityStore,
+    policy: UnsupportedFeaturePolicy,
+    placeholders: &Placeholders,
+    format: &DisplayFormat,
+) -> PlanReport {
+    let segments: Vec<SegmentReport> = segments
+        .iter()
+        .map(|s| {
+            let input = super::synthesis::speech_input(
+                s.text.clone(),
+                paragraph_pause_ms,
+                line_pause_ms,
+                breathing,
+            );
+            check_segment(
+                &s.id,
+                &s.voice_name,
+                &s.language_code,
+                &s.text,
+                &input,
+                voices,
+                &capabilities.get(&s.voice_name),
+                policy,
+                placeholders,
+                format,
+            )
+        })
+        .collect();
+    let count = |severity: Severity| {
+        segments
+            .iter()
+            .flat_map(|s| &s.issues)
+            .filter(|i| i.severity == severity)
+            .count()
+    };
+    let estimated_cost_usd = segments.iter().map(|s| s.estimated_cost_usd).sum();
+    PlanReport {
+        total_characters: segments.iter().map(|s| s.characters).sum(),
+        estimated_cost_usd,
+        cost_display: format.cost_usd(estimated_cost_usd),
+        errors: count(Severity::Error),
+        warnings: count(Severity::Warning),
+        voices_checked: voices.is_some(),
+        segments,
+    }
+}
+
+/// Spelling issues of each segment, in the order of `segments`; a language
+/// without a dictionary gets one `SpellingUnchecked` note per segment.
+pub async fn spelling_issues(
+    store: &DictionaryStore,
+    settings: &SpellingSettings,
+    segments: &[BatchSegment],
+    read_only: bool,
+) -> Vec<Vec<PlanIssue>> {
+    let mut all = Vec::with_capacity(segments.len());
+    for segment in segments {
+        let check = crate::spelling::check(
+            store,
+            settings,
+            &segment.text,
+            &segment.language_code,
+            read_only,
+        )
+        .await;
+        let mut issues = Vec::new();
+        if let SpellingStatus::Unchecked { reason } = &check.spelling {
+            issues.push(issue(
+                Severity::Info,
+                IssueCode::SpellingUnchecked,
+                format!("Spelling not checked: {}", reason),
+            ));
+        }
+        for found in check.issues {
+            let (code, message) = match found.kind {
+                SpellingIssueKind::Misspelled => (
+                    IssueCode::Misspelled,
+                    format!("\"{}\" may be misspelled", found.word),
+                ),
+                SpellingIssueKind::Homophone => (
+                    IssueCode::Homophone,
+                    format!("\"{}\" sounds the same as another word", found.word),
+                ),
+            };
+            let suggestions = if found.suggestions.is_empty() {
+                String::new()
+            } else {
+                format!("; did you mean {}?", found.suggestions.join(", "))
+            };
+            issues.push(issue(
+                Severity::Info,
+                code,
+                format!(
+                    "{} at bytes {}..{}{}",
+                    message, found.start, found.end, suggestions
+                ),
+            ));
+        }
+        all.push(issues);
+    }
+    all
+}
+
+/// The single-request form used before `synthesize_speech` calls Google.
+#[allow(clippy::too_many_arguments)]
+pub async fn check_request(
+    tts: &TtsService,
+    voice_name: &str,
+    language_code: &str,
+    text: &str,
+    input: &SpeechInput,
+    voice_capabilities: &Capabilities,
+    policy: UnsupportedFeaturePolicy,
+    placeholders: &Placeholders,
+) -> Result<(), AppError> {
+    let voices = tts.cached_voices().await;
+    let report = check_segment(
+        "",
+        voice_name,
+        language_code,
+        text,
+        input,
+        voices.as_deref(),
+        voice_capabilities,
+        policy,
+        placeholders,
+        &DisplayFormat::default(),
+    );
+    let mut errors = report
+        .issues
+        .into_iter()
+        .filter(|i| i.severity == Severity::Error);
+    match errors.next() {
+        Some(first) => Err(AppError::InvalidSynthesisPlan {
+            errors: 1 + errors.count(),
+            message: first.message,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Reports errors and warnings per segment, with character and cost totals,
+/// using only the cached voice list; never calls the API. With
+/// `check_spelling` it also notes possible typos, which never count as errors.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn validate_synthesis_plan(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+    capabilities: tauri::State<'_, CapabilityStore>,
+    dictionaries: tauri::State<'_, DictionaryStore>,
+    segments: Vec<BatchSegment>,
+    options: Option<SynthesisOptions>,
+) -> Result<PlanReport, AppError> {
+    let options = options.unwrap_or_default();
+    let defaults = settings.get();
+    let voices = tts.cached_voices().await;
+    let mut report = validate(
+        &segments,
+        options
+            .paragraph_pause_ms
+            .unwrap_or(defaults.paragraph_pause_ms),
+        options.line_pause_ms.unwrap_or(defaults.line_pause_ms),
+        options.breathing_room.unwrap_or(defaults.breathing_room),
+        voices.as_deref(),
+        &capabilities,
+        options
+            .unsupported_features
+            .unwrap_or(defaults.unsupported_features),
+        &Placeholders::new(&defaults.placeholders),
+        &DisplayFormat::for_locale(&defaults.ui_locale),
+    );
+    if options.check_spelling == Some(true) {
+        let spelling = spelling_issues(
+            &dictionaries,
+            &defaults.spelling,
+            &segments,
+            crate::read_only::is_read_only(&app_handle),
+        )
+        .await;
+        for (report, issues) in report.segments.iter_mut().zip(spelling) {
+            report.issues.extend(issues);
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssml::policy::{SsmlPolicy, SsmlPolicyMode};
+    use crate::tts::placeholders::PlaceholderSettings;
+    use crate::tts::synthesis::{as_sent, speech_input};
+    use gcloud_sdk::google::cloud::texttospeech::v1::SsmlVoiceGender;
+    use serde_json::json;
+
+    const VOICE: &str = "en-US-Neural2-C";
+
+    /// What synthesis sends for `text`: pauses as SSML, unsupported
+    /// features stripped, then the SSML policy.
+    fn sent(
+        text: &str,
+        pauses: (u32, u32),
+        breathing: BreathingRoom,
+        voice_capabilities: &Capabilities,
+        policy: &SsmlPolicy,
+    ) -> Result<serde_json::Value, AppError> {
+        let input = speech_input(text.to_string(), pauses.0, pauses.1, breathing);
+        let input = capabilities::strip_input(VOICE, "en-US", input, voice_capabilities);
+        let params = TtsRequestParams::speech(VOICE, "en-US", &input);
+        Ok(as_sent(&params, policy)?.to_request_json())
+    }
+
+    fn sent_input(text: &str, pauses: (u32, u32), breathing: BreathingRoom) -> serde_json::Value {
+        sent(
+            text,
+            pauses,
+            breathing,
+            &Capabilities::ALL,
+            &SsmlPolicy::default(),
+        )
+        .unwrap()["input"]
+            .clone()
+    }
+
+    #[test]
+    fn plain_text_is_sent_as_text_with_the_default_audio_config() {
+        let request = sent(
+            "Hello there.",
+            (600, 0),
+            BreathingRoom::Off,
+            &Capabilities::ALL,
+            &SsmlPolicy::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            request,
+            json!({
+                "input": { "text": "Hello there." },
+                "voice": {
+                    "languageCode": "en-US",
+                    "name": VOICE,
+                    "ssmlGender": "SSML_VOICE_GENDER_UNSPECIFIED",
+                },
+                "audioConfig": {
+                    "audioEncoding": "MP3",
+                    "speakingRate": 1.0,
+                    "pitch": 0.0,
+                    "volumeGainDb": 0.0,
+                    "sampleRateHertz": 0,
+                    "effectsProfileId": [],
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn paragraph_and_line_breaks_become_pauses() {
+        assert_eq!(
+            sent_input("One.\n\nTwo.", (600, 0), BreathingRoom::Off),
+            json!({ "ssml": "<speak>One. <break time=\"600ms\"/> Two.</speak>" })
+        );
+        assert_eq!(
+            sent_input(
+                "One.\nTwo.\n\nThree & four.",
+                (600, 200),
+                BreathingRoom::Off
+            ),
+            json!({
+                "ssml": "<speak>One. <break time=\"200ms\"/> Two. <break time=\"600ms\"/> \
+                         Three &amp; four.</speak>"
+            })
+        );
+        // Windows line ends pause like any others
+        assert_eq!(
+            sent_input("One.\r\n\r\nTwo.", (600, 0), BreathingRoom::Off),
+            sent_input("One.\n\nTwo.", (600, 0), BreathingRoom::Off)
+        );
+    }
+
+    #[test]
+    fn breathing_room_pauses_within_a_line() {
+        assert_eq!(
+            sent_input(
+                "First, we wait. Then we go; quickly.",
+                (600, 0),
+                BreathingRoom::Normal
+            ),
+            json!({
+                "ssml": "<speak>First, <break time=\"60ms\"/> we wait. <break time=\"230ms\"/> \
+                         Then we go; <break time=\"180ms\"/> quickly.</speak>"
+            })
+        );
+    }
+
+    #[test]
+    fn a_voice_without_ssml_gets_the_text_it_speaks() {
+        let no_ssml = Capabilities {
+            ssml: false,
+            ..Capabilities::ALL
+        };
+        let request = sent(
+            "One.\n\nTwo & three.",
+            (600, 0),
+            BreathingRoom::Off,
+            &no_ssml,
+            &SsmlPolicy::default(),
+        )
+        .unwrap();
+        assert_eq!(request["input"], json!({ "text": "One. Two & three." }));
+    }
+
+    #[test]
+    fn the_ssml_policy_strips_or_refuses_what_it_disallows() {
+        let shallow = SsmlPolicy {
+            max_depth: 1,
+            ..SsmlPolicy::default()
+        };
+        let request = sent(
+            "One.\n\nTwo.",
+            (600, 0),
+            BreathingRoom::Off,
+            &Capabilities::ALL,
+            &shallow,
+        )
+        .unwrap();
+        assert_eq!(
+            request["input"],
+            json!({ "ssml": "<speak>One.  Two.</speak>" })
+        );
+
+        let reject = SsmlPolicy {
+            mode: SsmlPolicyMode::Reject,
+            ..shallow
+        };
+        let refused = sent(
+            "One.\n\nTwo.",
+            (600, 0),
+            BreathingRoom::Off,
+            &Capabilities::ALL,
+            &reject,
+        );
+        assert!(
+            matches!(refused, Err(AppError::SsmlRejected { .. })),
+            "{:?}",
+            refused
+        );
+    }
+
+    #[test]
+    fn the_policy_changes_only_what_is_sent() {
+        let input = speech_input("One.\n\nTwo.".to_string(), 600, 0, BreathingRoom::Off);
+        let params = TtsRequestParams::speech(VOICE, "en-US", &input);
+        let shallow = SsmlPolicy {
+            max_depth: 1,
+            ..SsmlPolicy::default()
+        };
+        let sent = as_sent(&params, &shallow).unwrap();
+        assert_ne!(sent.input, params.input);
+        // The cache and the spend caps go by the request as asked
+        assert_eq!(params.input, input.content());
+        assert_ne!(sent.fingerprint(), params.fingerprint());
+    }
+
+    fn voice(name: &str, language: &str) -> Voice {
+        Voice {
+            language_codes: vec![language.to_string()],
+            name: name.to_string(),
+            ssml_gender: SsmlVoiceGender::Female as i32,
+            natural_sample_rate_hertz: 24000,
+        }
+    }
+
+    fn check(
+        voice_name: &str,
+        text: &str,
+        voices: Option<&[Voice]>,
+        voice_capabilities: &Capabilities,
+        policy: UnsupportedFeaturePolicy,
+        placeholders: &PlaceholderSettings,
+    ) -> Vec<(Severity, IssueCode)> {
+        let input = speech_input(text.to_string(), 600, 0, BreathingRoom::Off);
+        check_segment(
+            "s1",
+            voice_name,
+            "en-US",
+            text,
+            &input,
+            voices,
+            voice_capabilities,
+            policy,
+            &Placeholders::new(placeholders),
+            &DisplayFormat::default(),
+        )
+        .issues
+        .into_iter()
+        .map(|i| (i.severity, i.code))
+        .collect()
+    }
+
+    fn issues(text: &str) -> Vec<(Severity, IssueCode)> {
+        check(
+            VOICE,
+            text,
+            None,
+            &Capabilities::ALL,
+            UnsupportedFeaturePolicy::Warn,
+            &PlaceholderSettings::default(),
+        )
+    }
+
+    #[test]
+    fn segment_fixtures_report_their_issues() {
+        assert_eq!(issues("A plain sentence, nothing more."), vec![]);
+        assert_eq!(
+            issues(" \u{a0}\n"),
+            vec![(Severity::Warning, IssueCode::EmptyText)]
+        );
+        assert_eq!(
+            issues(&"word ".repeat(1100)),
+            vec![(Severity::Error, IssueCode::InputTooLong)]
+        );
+        assert_eq!(
+            issues("Say <break time=\"1s\"/> twice."),
+            vec![(Severity::Warning, IssueCode::LiteralMarkup)]
+        );
+        assert_eq!(
+            issues("Dear {{name}}, thanks."),
+            vec![(Severity::Warning, IssueCode::Placeholder)]
+        );
+        let strict = PlaceholderSettings {
+            strict: true,
+            ..PlaceholderSettings::default()
+        };
+        assert_eq!(
+            check(
+                VOICE,
+                "Dear {{name}}, thanks.",
+                None,
+                &Capabilities::ALL,
+                UnsupportedFeaturePolicy::Warn,
+                &strict
+            ),
+            vec![(Severity::Error, IssueCode::Placeholder)]
+        );
+    }
+
+    #[test]
+    fn segment_fixtures_check_the_voice_list_when_there_is_one() {
+        let voices = [voice(VOICE, "en-US"), voice("de-DE-Neural2-A", "de-DE")];
+        let run = |voice_name: &str| {
+            check(
+                voice_name,
+                "Hello there.",
+                Some(&voices),
+                &Capabilities::ALL,
+                UnsupportedFeaturePolicy::Warn,
+                &PlaceholderSettings::default(),
+            )
+        };
+        assert_eq!(run(VOICE), vec![]);
+        assert_eq!(
+            run("en-US-Neural2-Z"),
+            vec![(Severity::Error, IssueCode::UnknownVoice)]
+        );
+        assert_eq!(
+            run("de-DE-Neural2-A"),
+            vec![(Severity::Error, IssueCode::UnsupportedLanguage)]
+        );
+        // The OS engine's voices aren't in Google's list
+        assert_eq!(run("native:any"), vec![]);
+    }
+
+    #[test]
+    fn segment_fixtures_report_unsupported_features_per_policy() {
+        let no_ssml = Capabilities {
+            ssml: false,
+            ..Capabilities::ALL
+        };
+        let run = |policy| {
+            check(
+                VOICE,
+                "One.\n\nTwo.",
+                None,
+                &no_ssml,
+                policy,
+                &PlaceholderSettings::default(),
+            )
+        };
+        assert_eq!(
+            run(UnsupportedFeaturePolicy::Warn),
+            vec![(Severity::Warning, IssueCode::UnsupportedFeature)]
+        );
+        assert_eq!(
+            run(UnsupportedFeaturePolicy::Strip),
+            vec![(Severity::Warning, IssueCode::UnsupportedFeature)]
+        );
+        assert_eq!(
+            run(UnsupportedFeaturePolicy::Error),
+            vec![(Severity::Error, IssueCode::UnsupportedFeature)]
+        );
+    }
+
+    #[test]
+    fn a_segment_costs_its_billable_characters_at_the_voice_price() {
+        let input = speech_input("One.\n\nTwo.".to_string(), 600, 0, BreathingRoom::Off);
+        let report = check_segment(
+            "s1",
+            "en-US-Studio-O",
+            "en-US",
+            "One.\n\nTwo.",
+            &input,
+            None,
+            &Capabilities::ALL,
+            UnsupportedFeaturePolicy::Warn,
+            &Placeholders::new(&PlaceholderSettings::default()),
+            &DisplayFormat::default(),
+        );
+        assert_eq!(report.characters, 46);
+        assert_eq!(report.bytes, input.content().len());
+        assert_eq!(
+            report.estimated_cost_usd,
+            report.characters as f64 * 160.0 / 1_000_000.0
+        );
+    }
+}
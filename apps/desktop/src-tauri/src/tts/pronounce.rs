@@ -0,0 +1,157 @@
+// Single-word pronunciation previews, read inside a short carrier sentence so
+// the voice uses its sentence intonation rather than a citation form
+use serde::Serialize;
+use std::path::PathBuf;
+
+use super::fingerprint::TtsRequestParams;
+use super::lanes::Lane;
+use super::synthesis::SpeechInput;
+use super::TtsService;
+use crate::assets::{asset_url, AssetKind};
+use crate::error::AppError;
+use crate::project::pronunciation::{Dictionary, Pronunciation};
+use crate::project::ProjectLocation;
+use crate::ssml::escape;
+use crate::voices::capabilities::CapabilityStore;
+
+const MAX_WORD_CHARS: usize = 100;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PronouncedWord {
+    pub word: String,
+    pub voice_name: String,
+    /// The sentence spoken, with the word in place.
+    pub carrier_text: String,
+    /// Exactly what was sent: the carrier text, or SSML when a dictionary
+    /// entry was applied.
+    pub input: String,
+    pub url: String,
+    /// The dictionary entry for the word, whether or not it was applied.
+    pub pronunciation: Option<Pronunciation>,
+    pub dictionary_applied: bool,
+    /// Why an existing entry wasn't applied.
+    pub note: Option<String>,
+}
+
+/// "The word is {}." in the language's own words, `{}` marking the word.
+/// Languages without one get the bare word.
+fn carrier(language_code: &str) -> &'static str {
+    let base = language_code.split(['-', '_']).next().unwrap_or_default();
+    match base.to_ascii_lowercase().as_str() {
+        "en" => "The word is {}.",
+        "de" => "Das Wort ist {}.",
+        "fr" => "Le mot est {}.",
+        "es" => "La palabra es {}.",
+        "it" => "La parola è {}.",
+        "pt" => "A palavra é {}.",
+        "nl" => "Het woord is {}.",
+        "sv" => "Ordet är {}.",
+        "da" | "nb" | "no" => "Ordet er {}.",
+        "fi" => "Sana on {}.",
+        "pl" => "To słowo to {}.",
+        "cs" => "To slovo je {}.",
+        "ru" => "Это слово {}.",
+        "uk" => "Це слово {}.",
+        "tr" => "Kelime {}.",
+        "hi" => "शब्द है {}।",
+        "ja" => "単語は{}です。",
+        "ko" => "단어는 {}입니다.",
+        "cmn" | "zh" => "这个词是{}。",
+        "yue" => "呢個詞係{}。",
+        _ => "{}",
+    }
+}
+
+/// Speaks `word` in a carrier sentence for `language_code`. With
+/// `use_dictionary` (the default) and a `project_path`, the project's entry
+/// for the word is applied, so the same word can be heard with and without
+/// it. Repeats are answered from the TTS cache.
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn pronounce_word(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    capabilities: tauri::State<'_, CapabilityStore>,
+    word: String,
+    voice_name: String,
+    language_code: String,
+    project_path: Option<PathBuf>,
+    use_dictionary: Option<bool>,
+) -> Result<PronouncedWord, AppError> {
+    let word = word.trim().to_string();
+    if word.is_empty() || word.chars().count() > MAX_WORD_CHARS || word.contains('\n') {
+        return Err(AppError::InvalidAudioRequest {
+            message: format!(
+                "word must be 1 to {} characters on one line",
+                MAX_WORD_CHARS
+            ),
+        });
+    }
+
+    let pronunciation = match project_path {
+        Some(path) => match ProjectLocation::locate(&path)?.pronunciation_file() {
+            Some(file) => Dictionary::load(&file)?.lookup(&word).cloned(),
+            None => None,
+        },
+        None => None,
+    };
+
+    let (before, after) = carrier(&language_code).split_once("{}").unwrap_or_default();
+    let carrier_text = format!("{}{}{}", before, word, after);
+    let mut note = None;
+    let mut applied = None;
+    if let (Some(entry), true) = (&pronunciation, use_dictionary.unwrap_or(true)) {
+        if capabilities.get(&voice_name).ssml {
+            applied = entry.to_ssml(&word).map(|spoken| {
+                SpeechInput::Ssml(format!(
+                    "<speak>{}{}{}</speak>",
+                    escape(before),
+                    spoken,
+                    escape(after)
+                ))
+            });
+        } else if let Some(alias) = entry.alias.as_deref().filter(|a| !a.trim().is_empty()) {
+            // Without SSML an alias can still be spoken in place of the word
+            applied = Some(SpeechInput::Text(format!(
+                "{}{}{}",
+                before,
+                alias.trim(),
+                after
+            )));
+        } else {
+            note = Some(format!(
+                "voice {} takes no SSML, so the IPA pronunciation can't be applied",
+                voice_name
+            ));
+        }
+    }
+    let dictionary_applied = applied.is_some();
+    let input = applied.unwrap_or_else(|| SpeechInput::Text(carrier_text.clone()));
+
+    let sent = input.content().to_string();
+    let key = TtsRequestParams::speech(&voice_name, &language_code, &input).fingerprint();
+    let cache = crate::cache::tts_cache(&app_handle)?;
+    super::synthesis::synthesize_cached(
+        &tts,
+        &cache,
+        &voice_name,
+        &language_code,
+        input,
+        None,
+        Lane::Interactive,
+        None,
+    )
+    .await?;
+
+    Ok(PronouncedWord {
+        word,
+        voice_name,
+        carrier_text,
+        input: sent,
+        url: asset_url(AssetKind::Tts, &key),
+        pronunciation,
+        dictionary_applied,
+        note,
+    })
+}
@@ -0,0 +1,174 @@
+// Quota project selection: which GCP project TTS usage is billed to, sent as
+// the x-goog-user-project header
+use gcloud_sdk::tonic::{self, metadata::MetadataValue, Code};
+
+use crate::error::AppError;
+
+pub const HEADER: &str = "x-goog-user-project";
+
+/// Checks a GCP project id: 6 to 30 lowercase letters, digits and hyphens,
+/// starting with a letter and not ending with a hyphen.
+pub fn validate_project_id(project_id: &str) -> Result<(), AppError> {
+    let invalid = |reason: &str| AppError::InvalidQuotaProject {
+        project_id: project_id.to_string(),
+        reason: reason.to_string(),
+    };
+    if !(6..=30).contains(&project_id.len()) {
+        return Err(invalid("must be 6 to 30 characters long"));
+    }
+    if !project_id
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(invalid(
+            "may only contain lowercase letters, digits and hyphens",
+        ));
+    }
+    if !project_id.starts_with(|c: char| c.is_ascii_lowercase()) {
+        return Err(invalid("must start with a letter"));
+    }
+    if project_id.ends_with('-') {
+        return Err(invalid("must not end with a hyphen"));
+    }
+    Ok(())
+}
+
+/// Wraps `message` in a request carrying the quota project header, if any.
+pub fn request<T>(message: T, project_id: Option<&str>) -> Result<tonic::Request<T>, AppError> {
+    let mut request = tonic::Request::new(message);
+    if let Some(project_id) = project_id {
+        validate_project_id(project_id)?;
+        let value: MetadataValue<_> =
+            project_id
+                .parse()
+                .map_err(|_| AppError::InvalidQuotaProject {
+                    project_id: project_id.to_string(),
+                    reason: "is not a valid header value".to_string(),
+                })?;
+        request.metadata_mut().insert(HEADER, value);
+    }
+    Ok(request)
+}
+
+/// Google's PERMISSION_DENIED for a quota project the credentials may not
+/// bill to names `serviceusage.services.use` or USER_PROJECT_DENIED; anything
+/// else converts as usual.
+pub fn status_error(status: tonic::Status, project_id: Option<&str>) -> AppError {
+    let message = status.message();
+    let about_quota_project = message.contains("serviceusage.services.use")
+        || message.contains("USER_PROJECT_DENIED")
+        || message.to_ascii_lowercase().contains("quota project");
+    match project_id {
+        Some(project_id) if status.code() == Code::PermissionDenied && about_quota_project => {
+            AppError::QuotaProjectDenied {
+                project_id: project_id.to_string(),
+                message: message.to_string(),
+            }
+        }
+        _ => status.into(),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_project_ids_google_would() {
+        for id in [
+            "my-project",
+            "sclip-tts-123456",
+            "abcdef",
+            "a-b-c-d-e-f-g-h-i-j-k-l-m-n-o1",
+        ] {
+            assert!(validate_project_id(id).is_ok(), "{}", id);
+        }
+    }
+
+    #[test]
+    fn refuses_malformed_project_ids_with_a_reason() {
+        for (id, reason) in [
+            ("abc", "must be 6 to 30 characters long"),
+            (
+                "a-very-long-project-id-over-thirty",
+                "must be 6 to 30 characters long",
+            ),
+            (
+                "My-Project",
+                "may only contain lowercase letters, digits and hyphens",
+            ),
+            (
+                "my_project",
+                "may only contain lowercase letters, digits and hyphens",
+            ),
+            (
+                "my project",
+                "may only contain lowercase letters, digits and hyphens",
+            ),
+            ("1project", "must start with a letter"),
+            ("-project", "must start with a letter"),
+            ("project-", "must not end with a hyphen"),
+        ] {
+            match validate_project_id(id) {
+                Err(AppError::InvalidQuotaProject {
+                    project_id,
+                    reason: got,
+                }) => {
+                    assert_eq!(project_id, id);
+                    assert_eq!(got, reason, "{}", id);
+                }
+                other => panic!("{}: {:?}", id, other),
+            }
+        }
+    }
+
+    #[test]
+    fn sends_the_header_only_with_a_project() {
+        let with = request((), Some("my-project")).unwrap();
+        assert_eq!(with.metadata().get(HEADER).unwrap(), "my-project");
+        let without = request((), None).unwrap();
+        assert!(without.metadata().get(HEADER).is_none());
+        assert!(matches!(
+            request((), Some("Bad Project")),
+            Err(AppError::InvalidQuotaProject { .. })
+        ));
+    }
+
+    #[test]
+    fn names_the_project_when_billing_to_it_is_denied() {
+        let denied = || {
+            tonic::Status::permission_denied(
+                "Caller does not have required permission to use project my-project. \
+                 Grant the caller the roles/serviceusage.serviceUsageConsumer role, \
+                 or a custom role with the serviceusage.services.use permission",
+            )
+        };
+        assert!(matches!(
+            status_error(denied(), Some("my-project")),
+            AppError::QuotaProjectDenied { project_id, .. } if project_id == "my-project"
+        ));
+        assert!(matches!(
+            status_error(
+                tonic::Status::permission_denied("USER_PROJECT_DENIED"),
+                Some("my-project")
+            ),
+            AppError::QuotaProjectDenied { .. }
+        ));
+        // Without a quota project it's an ordinary authorization failure
+        assert!(matches!(
+            status_error(denied(), None),
+            AppError::TtsUnauthorized { .. }
+        ));
+        // As is a denial about something else
+        assert!(matches!(
+            status_error(
+                tonic::Status::permission_denied("Cloud Text-to-Speech API has not been used"),
+                Some("my-project")
+            ),
+            AppError::TtsUnauthorized { .. }
+        ));
+        assert!(matches!(
+            status_error(tonic::Status::unavailable("down"), Some("my-project")),
+            AppError::TtsOffline { .. }
+        ));
+    }
+}
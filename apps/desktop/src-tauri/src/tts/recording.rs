@@ -0,0 +1,274 @@
+// Debug recording of synthesis calls as JSON lines, so a "sounds wrong"
+// report can be reproduced from the exact request
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::fingerprint::TtsRequestParams;
+use super::lanes::Lane;
+use super::TtsService;
+use crate::audio::mp3;
+use crate::error::AppError;
+use crate::settings::SettingsStore;
+
+/// Off unless turned on; the text itself is kept only with `include_text`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordingSettings {
+    pub enabled: bool,
+    pub include_text: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    /// "ok", or the error kind.
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub bytes: usize,
+    pub duration_ms: Option<f64>,
+    pub latency_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_sha256: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordingEntry {
+    pub id: String,
+    pub recorded_at: DateTime<Utc>,
+    pub fingerprint: String,
+    /// The request with `input` emptied unless text was included.
+    pub request: TtsRequestParams,
+    pub text_included: bool,
+    pub text_sha256: String,
+    pub characters: usize,
+    pub quota_project: Option<String>,
+    pub response: RecordedResponse,
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Writes one `YYYY-MM-DD.jsonl` file per day into its directory.
+pub struct Recorder {
+    dir: PathBuf,
+    settings: Mutex<RecordingSettings>,
+}
+
+impl Recorder {
+    pub fn new(dir: PathBuf, settings: RecordingSettings) -> Self {
+        Self {
+            dir,
+            settings: Mutex::new(settings),
+        }
+    }
+
+    pub fn dir(&self) -> &PathBuf {
+        &self.dir
+    }
+
+    pub fn settings(&self) -> RecordingSettings {
+        *self.settings.lock().unwrap()
+    }
+
+    pub fn set_settings(&self, settings: RecordingSettings) {
+        *self.settings.lock().unwrap() = settings;
+    }
+
+    /// Appends an entry for one synthesis call if recording is on. Failures
+    /// are logged: recording must never break synthesis.
+    pub fn record(
+        &self,
+        params: &TtsRequestParams,
+        quota_project: Option<&str>,
+        latency: Duration,
+        result: &Result<Vec<u8>, AppError>,
+    ) {
+        let settings = self.settings();
+        if !settings.enabled {
+            return;
+        }
+        let params = params.normalized();
+        let response = match result {
+            Ok(audio) => RecordedResponse {
+                status: "ok".to_string(),
+                error: None,
+                bytes: audio.len(),
+                duration_ms: mp3::duration_ms(audio),
+                latency_ms: latency.as_millis() as u64,
+                audio_sha256: Some(sha256_hex(audio)),
+            },
+            Err(e) => RecordedResponse {
+                status: serde_json::to_value(e)
+                    .ok()
+                    .and_then(|v| v.get("kind").and_then(|k| k.as_str()).map(String::from))
+                    .unwrap_or_else(|| "error".to_string()),
+                error: Some(e.to_string()),
+                bytes: 0,
+                duration_ms: None,
+                latency_ms: latency.as_millis() as u64,
+                audio_sha256: None,
+            },
+        };
+        let recorded_at = Utc::now();
+        let entry = RecordingEntry {
+            id: format!(
+                "{}-{}",
+                recorded_at.format("%Y%m%dT%H%M%S"),
+                &uuid::Uuid::new_v4().simple().to_string()[..8]
+            ),
+            recorded_at,
+            fingerprint: params.fingerprint(),
+            text_included: settings.include_text,
+            text_sha256: sha256_hex(params.input.as_bytes()),
+            characters: params.billable_characters(),
+            quota_project: quota_project.map(String::from),
+            request: TtsRequestParams {
+                input: if settings.include_text {
+                    params.input.clone()
+                } else {
+                    String::new()
+                },
+                ..params
+            },
+            response,
+        };
+
+        let path = self
+            .dir
+            .join(format!("{}.jsonl", recorded_at.format("%Y-%m-%d")));
+        let result = fs::create_dir_all(&self.dir).and_then(|_| {
+            let mut line = serde_json::to_vec(&entry).map_err(std::io::Error::other)?;
+            line.push(b'\n');
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)?
+                .write_all(&line)
+        });
+        if let Err(e) = result {
+            log::warn!("Failed to write TTS recording {}: {}", path.display(), e);
+        }
+    }
+
+    /// Finds an entry by id; ids start with their date, so only that day's
+    /// file is read.
+    pub fn find(&self, entry_id: &str) -> Result<RecordingEntry, AppError> {
+        let not_found = || AppError::RecordingNotFound {
+            entry_id: entry_id.to_string(),
+        };
+        let day = DateTime::parse_from_str(
+            &format!("{}+0000", entry_id.get(..15).ok_or_else(not_found)?),
+            "%Y%m%dT%H%M%S%z",
+        )
+        .map_err(|_| not_found())?;
+        let path = self.dir.join(format!("{}.jsonl", day.format("%Y-%m-%d")));
+        let file = fs::File::open(&path).map_err(|_| not_found())?;
+        BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str::<RecordingEntry>(&line).ok())
+            .find(|entry| entry.id == entry_id)
+            .ok_or_else(not_found)
+    }
+}
+
+/// The state `get_app_info` shows so recording is never on unnoticed.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingStatus {
+    pub enabled: bool,
+    pub include_text: bool,
+    pub directory: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayReport {
+    pub entry_id: String,
+    pub original: RecordedResponse,
+    pub replay: RecordedResponse,
+    /// Same audio bytes as the original.
+    pub identical: bool,
+    pub bytes_delta: i64,
+    pub duration_delta_ms: Option<f64>,
+}
+
+/// Turns recording on or off; `include_text` defaults to off.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn set_tts_recording(
+    tts: tauri::State<'_, TtsService>,
+    store: tauri::State<'_, SettingsStore>,
+    enabled: bool,
+    include_text: Option<bool>,
+) -> Result<RecordingStatus, AppError> {
+    let recording = RecordingSettings {
+        enabled,
+        include_text: include_text.unwrap_or(false),
+    };
+    store.update(|settings| settings.tts_recording = recording)?;
+    tts.recorder().set_settings(recording);
+    log::info!(
+        "TTS recording {} (text {})",
+        if enabled { "enabled" } else { "disabled" },
+        if recording.include_text {
+            "included"
+        } else {
+            "hashed"
+        }
+    );
+    Ok(tts.recording_status())
+}
+
+/// Re-issues a recorded request, bypassing the cache, and compares the
+/// audio with the original. Only entries recorded with their text can be
+/// replayed; the call is billed like any other.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn replay_tts_recording(
+    tts: tauri::State<'_, TtsService>,
+    entry_id: String,
+) -> Result<ReplayReport, AppError> {
+    let entry = tts.recorder().find(&entry_id)?;
+    if !entry.text_included {
+        return Err(AppError::RecordingNotReplayable {
+            entry_id,
+            reason: "recorded without its text".to_string(),
+        });
+    }
+
+    let started = std::time::Instant::now();
+    let audio = super::synthesis::request_synthesis(
+        &tts,
+        &entry.request,
+        entry.quota_project.as_deref(),
+        Lane::Background,
+        None,
+    )
+    .await?;
+    let replay = RecordedResponse {
+        status: "ok".to_string(),
+        error: None,
+        bytes: audio.len(),
+        duration_ms: mp3::duration_ms(&audio),
+        latency_ms: started.elapsed().as_millis() as u64,
+        audio_sha256: Some(sha256_hex(&audio)),
+    };
+    Ok(ReplayReport {
+        identical: entry.response.audio_sha256 == replay.audio_sha256,
+        bytes_delta: replay.bytes as i64 - entry.response.bytes as i64,
+        duration_delta_ms: entry
+            .response
+            .duration_ms
+            .zip(replay.duration_ms)
+            .map(|(original, replay)| replay - original),
+        entry_id,
+        original: entry.response,
+        replay,
+    })
+}
@@ -0,0 +1,127 @@
+// Detection of text with nothing to speak. Google answers it with a bare
+// INVALID_ARGUMENT, so single requests are refused locally with the reason
+// and batches skip the segment instead
+use crate::error::AppError;
+
+/// Longest excerpt of the leftover characters quoted in a reason.
+const MAX_EXCERPT_CHARS: usize = 24;
+
+/// Format characters that take no space yet aren't `char::is_whitespace`:
+/// soft hyphen, combining grapheme joiner, Mongolian vowel separator,
+/// zero-width space, (non-)joiners and direction marks, bidi embeddings and
+/// isolates, word joiner and invisible operators, and the byte order mark.
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}'
+            | '\u{034F}'
+            | '\u{180E}'
+            | '\u{200B}'..='\u{200F}'
+            | '\u{202A}'..='\u{202E}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{2066}'..='\u{2069}'
+            | '\u{FEFF}'
+    )
+}
+
+/// Whitespace for this check: Unicode White_Space (which covers NBSP and
+/// the ideographic space), control characters and invisible ones.
+pub fn is_blank(c: char) -> bool {
+    c.is_whitespace() || c.is_control() || is_invisible(c)
+}
+
+/// Why `text` gives Google nothing to say, or `None` if it has a letter or
+/// digit in any script. Punctuation, symbols and emoji alone are dropped
+/// by the voice, so they count as nothing.
+pub fn nothing_to_speak(text: &str) -> Option<String> {
+    let visible: Vec<char> = text.chars().filter(|&c| !is_blank(c)).collect();
+    if visible.is_empty() {
+        return Some(if text.is_empty() {
+            "text is empty".to_string()
+        } else {
+            format!(
+                "text is only whitespace ({} space, line break or invisible character(s))",
+                text.chars().count()
+            )
+        });
+    }
+    if visible.iter().any(|c| c.is_alphanumeric()) {
+        return None;
+    }
+    let mut excerpt: String = visible.iter().take(MAX_EXCERPT_CHARS).collect();
+    if visible.len() > MAX_EXCERPT_CHARS {
+        excerpt.push('…');
+    }
+    Some(format!(
+        "text is only punctuation, symbols or emoji (\"{}\"), which aren't spoken",
+        excerpt
+    ))
+}
+
+/// Fails with `EmptyInput` when `text` has nothing to speak.
+pub fn check(text: &str) -> Result<(), AppError> {
+    match nothing_to_speak(text) {
+        Some(reason) => Err(AppError::EmptyInput { reason }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unicode_spaces_and_invisible_characters_are_blank() {
+        for c in [
+            ' ', '\t', '\n', '\r', '\u{00A0}', '\u{2007}', '\u{202F}', '\u{3000}', '\u{200B}',
+            '\u{200C}', '\u{200D}', '\u{2060}', '\u{FEFF}', '\u{00AD}', '\u{200E}', '\u{2066}',
+            '\u{0000}',
+        ] {
+            assert!(is_blank(c), "U+{:04X}", c as u32);
+        }
+        for c in ['a', '1', '.', '\u{4E00}', '\u{1F600}'] {
+            assert!(!is_blank(c), "U+{:04X}", c as u32);
+        }
+    }
+
+    #[test]
+    fn text_of_only_blanks_has_nothing_to_speak() {
+        assert_eq!(nothing_to_speak("").as_deref(), Some("text is empty"));
+        let blanks = "\u{00A0}\u{3000}\u{200B}\u{FEFF}\n";
+        assert_eq!(
+            nothing_to_speak(blanks).as_deref(),
+            Some("text is only whitespace (5 space, line break or invisible character(s))")
+        );
+        assert!(matches!(check(blanks), Err(AppError::EmptyInput { .. })));
+    }
+
+    #[test]
+    fn text_of_only_punctuation_quotes_an_excerpt() {
+        assert_eq!(
+            nothing_to_speak("\u{00A0}?! \u{1F600}").as_deref(),
+            Some(
+                "text is only punctuation, symbols or emoji (\"?!\u{1F600}\"), which aren't spoken"
+            )
+        );
+        let long = nothing_to_speak(&"-".repeat(30)).unwrap();
+        assert!(
+            long.contains(&format!("\"{}…\"", "-".repeat(MAX_EXCERPT_CHARS))),
+            "{}",
+            long
+        );
+    }
+
+    #[test]
+    fn a_letter_or_digit_in_any_script_is_speakable() {
+        for text in [
+            "Hi",
+            "\u{200B}7\u{200B}",
+            "\u{3000}日本\u{3000}",
+            "مرحبا",
+            "\u{00A0}ö",
+        ] {
+            assert_eq!(nothing_to_speak(text), None, "{:?}", text);
+            assert!(check(text).is_ok());
+        }
+    }
+}
@@ -0,0 +1,438 @@
+// Spend caps: billable characters one request, one batch and any 24 hours
+// may send before the call has to be confirmed. `confirm_spend` hands out a
+// single-use token, good for a minute, that lets one call bill that many
+// characters past the caps; the UI asks for one only after an explicit
+// dialog. Requests are checked in `request_synthesis`, which every API call
+// goes through, so no command gets around them
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use super::TtsService;
+use crate::error::AppError;
+
+/// How long a confirmation can be redeemed after `confirm_spend`.
+const CONFIRMATION_LIFETIME_SECS: i64 = 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpendCaps {
+    /// Billable characters one synthesis request may send; `None` sets no
+    /// cap, as for the others.
+    pub per_call_characters: Option<u64>,
+    /// Characters a batch run may have left to synthesize when it starts;
+    /// cached segments are free and don't count.
+    pub per_batch_characters: Option<u64>,
+    /// Characters billed in any 24 hours, across profiles, per the usage
+    /// journal.
+    pub daily_characters: Option<u64>,
+}
+
+impl SpendCaps {
+    /// A cap of zero would refuse everything; leaving it unset is how to
+    /// have none.
+    pub fn validate(&self) -> Result<(), String> {
+        let caps = [
+            ("per_call_characters", self.per_call_characters),
+            ("per_batch_characters", self.per_batch_characters),
+            ("daily_characters", self.daily_characters),
+        ];
+        match caps.iter().find(|(_, cap)| *cap == Some(0)) {
+            Some((name, _)) => Err(format!("{} must be at least 1; unset it for no cap", name)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpendCap {
+    PerCall,
+    PerBatch,
+    Daily,
+}
+
+impl SpendCap {
+    pub fn label(self) -> &'static str {
+        match self {
+            SpendCap::PerCall => "per-call",
+            SpendCap::PerBatch => "per-batch",
+            SpendCap::Daily => "24-hour",
+        }
+    }
+}
+
+/// What `confirm_spend` returns; `token` goes in the call's
+/// `spend_token` option.
+#[derive(Debug, Clone, Serialize)]
+pub struct SpendConfirmation {
+    pub token: String,
+    pub characters: u64,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A redeemed confirmation: the characters one call may still bill past
+/// the caps. Its requests draw on it as they're sent and give back what a
+/// failed one took, so retries are covered too.
+#[derive(Debug)]
+pub struct SpendApproval {
+    remaining: AtomicU64,
+}
+
+impl SpendApproval {
+    pub fn remaining(&self) -> u64 {
+        self.remaining.load(Ordering::Relaxed)
+    }
+
+    fn take(&self, characters: u64) -> bool {
+        self.remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                left.checked_sub(characters)
+            })
+            .is_ok()
+    }
+
+    fn give_back(&self, characters: u64) {
+        self.remaining.fetch_add(characters, Ordering::Relaxed);
+    }
+}
+
+/// Characters confirmed under a token, until it expires.
+struct Confirmed {
+    characters: u64,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct State {
+    caps: SpendCaps,
+    confirmations: HashMap<String, Confirmed>,
+    /// Characters of requests admitted but not yet billed or failed, which
+    /// count against the daily cap meanwhile.
+    in_flight: u64,
+}
+
+/// Part of `TtsService`; the caps mirror the `spend_caps` setting.
+#[derive(Default)]
+pub struct SpendGuard {
+    state: Mutex<State>,
+}
+
+/// A request let through the caps, in flight until dropped.
+pub struct Admission<'a> {
+    guard: &'a SpendGuard,
+    characters: u64,
+    /// The approval it drew on, if it needed one.
+    drawn: Option<&'a SpendApproval>,
+    billed: bool,
+}
+
+impl Admission<'_> {
+    /// The request was billed and its characters are in the usage journal,
+    /// so what it drew stays spent.
+    pub fn billed(mut self) {
+        self.billed = true;
+    }
+}
+
+impl Drop for Admission<'_> {
+    fn drop(&mut self) {
+        let mut state = self.guard.state.lock().unwrap();
+        state.in_flight = state.in_flight.saturating_sub(self.characters);
+        if let (false, Some(approval)) = (self.billed, self.drawn) {
+            approval.give_back(self.characters);
+        }
+    }
+}
+
+impl SpendGuard {
+    pub fn set_caps(&self, caps: SpendCaps) {
+        self.state.lock().unwrap().caps = caps;
+    }
+
+    pub fn caps(&self) -> SpendCaps {
+        self.state.lock().unwrap().caps
+    }
+
+    /// Issues a token confirming `characters` past the caps for one call.
+    pub fn confirm(&self, characters: u64) -> Result<SpendConfirmation, AppError> {
+        if characters == 0 {
+            return Err(AppError::InvalidSpendConfirmation {
+                reason: "a confirmation needs at least one character".to_string(),
+            });
+        }
+        let now = Utc::now();
+        let expires_at = now + Duration::seconds(CONFIRMATION_LIFETIME_SECS);
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        let mut state = self.state.lock().unwrap();
+        state.confirmations.retain(|_, c| c.expires_at > now);
+        state.confirmations.insert(
+            token.clone(),
+            Confirmed {
+                characters,
+                expires_at,
+            },
+        );
+        Ok(SpendConfirmation {
+            token,
+            characters,
+            expires_at,
+        })
+    }
+
+    /// Uses up `token`, if the call has one. Unknown, used and expired
+    /// tokens are refused rather than ignored, so the call fails instead of
+    /// hitting the cap the user just confirmed past.
+    pub fn redeem(&self, token: Option<&str>) -> Result<Option<SpendApproval>, AppError> {
+        let Some(token) = token else {
+            return Ok(None);
+        };
+        let now = Utc::now();
+        let mut state = self.state.lock().unwrap();
+        let confirmed = state.confirmations.remove(token);
+        state.confirmations.retain(|_, c| c.expires_at > now);
+        match confirmed {
+            Some(c) if c.expires_at > now => Ok(Some(SpendApproval {
+                remaining: AtomicU64::new(c.characters),
+            })),
+            Some(_) => Err(AppError::InvalidSpendConfirmation {
+                reason: "the confirmation expired; confirm the spend again".to_string(),
+            }),
+            None => Err(AppError::InvalidSpendConfirmation {
+                reason: "unknown or already used confirmation".to_string(),
+            }),
+        }
+    }
+
+    /// Lets a request of `characters` through, on top of `day_used`
+    /// billed in the last 24 hours. An approval that covers it lets it past
+    /// any cap; otherwise it must fit the per-call cap and, with the other
+    /// requests in flight, the daily one.
+    pub fn admit<'a>(
+        &'a self,
+        characters: u64,
+        day_used: u64,
+        approval: Option<&'a SpendApproval>,
+    ) -> Result<Admission<'a>, AppError> {
+        let mut state = self.state.lock().unwrap();
+        let drawn = approval.filter(|approval| approval.take(characters));
+        if drawn.is_none() {
+            if let Some(limit) = state.caps.per_call_characters {
+                if characters > limit {
+                    return Err(AppError::SpendCapExceeded {
+                        cap: SpendCap::PerCall,
+                        limit,
+                        attempted: characters,
+                        used: 0,
+                    });
+                }
+            }
+            if let Some(limit) = state.caps.daily_characters {
+                let used = day_used.saturating_add(state.in_flight);
+                if used.saturating_add(characters) > limit {
+                    return Err(AppError::SpendCapExceeded {
+                        cap: SpendCap::Daily,
+                        limit,
+                        attempted: characters,
+                        used,
+                    });
+                }
+            }
+        }
+        state.in_flight += characters;
+        Ok(Admission {
+            guard: self,
+            characters,
+            drawn,
+            billed: false,
+        })
+    }
+
+    /// Checks the `characters` a batch run has left to bill against the
+    /// per-batch cap; `used` is those of its segments already done.
+    /// An approval covering them lets the run start; its requests then draw
+    /// on it as they go.
+    pub fn check_batch(
+        &self,
+        characters: u64,
+        used: u64,
+        approval: Option<&SpendApproval>,
+    ) -> Result<(), AppError> {
+        let Some(limit) = self.caps().per_batch_characters else {
+            return Ok(());
+        };
+        if characters <= limit || approval.is_some_and(|a| a.remaining() >= characters) {
+            return Ok(());
+        }
+        Err(AppError::SpendCapExceeded {
+            cap: SpendCap::PerBatch,
+            limit,
+            attempted: characters,
+            used,
+        })
+    }
+}
+
+/// Confirms `amount` billable characters past the spend caps for one call,
+/// after the UI showed the user what it will cost. The token is good for
+/// one call within a minute.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn confirm_spend(
+    tts: tauri::State<'_, TtsService>,
+    amount: u64,
+) -> Result<SpendConfirmation, AppError> {
+    let confirmation = tts.spend().confirm(amount)?;
+    log::info!("Confirmed spending {} characters past the caps", amount);
+    Ok(confirmation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard(caps: SpendCaps) -> SpendGuard {
+        let guard = SpendGuard::default();
+        guard.set_caps(caps);
+        guard
+    }
+
+    fn exceeded(result: Result<impl Sized, AppError>) -> SpendCap {
+        match result {
+            Err(AppError::SpendCapExceeded { cap, .. }) => cap,
+            Err(e) => panic!("expected a spend cap error, got {:?}", e),
+            Ok(_) => panic!("expected a spend cap error"),
+        }
+    }
+
+    #[test]
+    fn zero_caps_are_refused() {
+        let caps = SpendCaps {
+            daily_characters: Some(0),
+            ..SpendCaps::default()
+        };
+        assert!(caps.validate().unwrap_err().contains("daily_characters"));
+        assert!(SpendCaps::default().validate().is_ok());
+    }
+
+    #[test]
+    fn admit_enforces_the_per_call_cap() {
+        let guard = guard(SpendCaps {
+            per_call_characters: Some(100),
+            ..SpendCaps::default()
+        });
+        assert!(guard.admit(100, 0, None).is_ok());
+        assert_eq!(exceeded(guard.admit(101, 0, None)), SpendCap::PerCall);
+    }
+
+    #[test]
+    fn admit_counts_requests_in_flight_against_the_daily_cap() {
+        let guard = guard(SpendCaps {
+            daily_characters: Some(1000),
+            ..SpendCaps::default()
+        });
+        let first = guard.admit(600, 300, None).unwrap();
+        assert_eq!(exceeded(guard.admit(200, 300, None)), SpendCap::Daily);
+        // A request that failed gives its characters back
+        drop(first);
+        assert!(guard.admit(200, 300, None).is_ok());
+    }
+
+    #[test]
+    fn an_approval_lets_a_request_past_the_caps() {
+        let guard = guard(SpendCaps {
+            per_call_characters: Some(10),
+            daily_characters: Some(10),
+            ..SpendCaps::default()
+        });
+        let token = guard.confirm(50).unwrap().token;
+        let approval = guard.redeem(Some(&token)).unwrap().unwrap();
+        guard.admit(30, 0, Some(&approval)).unwrap().billed();
+        assert_eq!(approval.remaining(), 20);
+        // Past what the approval has left, the caps apply again
+        assert_eq!(
+            exceeded(guard.admit(30, 0, Some(&approval))),
+            SpendCap::PerCall
+        );
+    }
+
+    #[test]
+    fn a_failed_request_gives_back_what_it_drew() {
+        let guard = guard(SpendCaps {
+            per_call_characters: Some(10),
+            ..SpendCaps::default()
+        });
+        let token = guard.confirm(50).unwrap().token;
+        let approval = guard.redeem(Some(&token)).unwrap().unwrap();
+        drop(guard.admit(40, 0, Some(&approval)).unwrap());
+        assert_eq!(approval.remaining(), 50);
+    }
+
+    #[test]
+    fn confirmations_are_single_use() {
+        let guard = SpendGuard::default();
+        let token = guard.confirm(10).unwrap().token;
+        assert!(guard.redeem(Some(&token)).unwrap().is_some());
+        assert!(matches!(
+            guard.redeem(Some(&token)),
+            Err(AppError::InvalidSpendConfirmation { .. })
+        ));
+        assert!(matches!(
+            guard.redeem(Some("unknown")),
+            Err(AppError::InvalidSpendConfirmation { .. })
+        ));
+        assert!(guard.redeem(None).unwrap().is_none());
+    }
+
+    #[test]
+    fn expired_confirmations_are_refused() {
+        let guard = SpendGuard::default();
+        let token = guard.confirm(10).unwrap().token;
+        guard
+            .state
+            .lock()
+            .unwrap()
+            .confirmations
+            .get_mut(&token)
+            .unwrap()
+            .expires_at = Utc::now() - Duration::seconds(1);
+        match guard.redeem(Some(&token)) {
+            Err(AppError::InvalidSpendConfirmation { reason }) => {
+                assert!(reason.contains("expired"))
+            }
+            other => panic!("expected an expired confirmation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn empty_confirmations_are_refused() {
+        assert!(matches!(
+            SpendGuard::default().confirm(0),
+            Err(AppError::InvalidSpendConfirmation { .. })
+        ));
+    }
+
+    #[test]
+    fn check_batch_enforces_the_per_batch_cap() {
+        let guard = guard(SpendCaps {
+            per_batch_characters: Some(1000),
+            ..SpendCaps::default()
+        });
+        assert!(guard.check_batch(1000, 0, None).is_ok());
+        assert_eq!(
+            exceeded(guard.check_batch(1001, 200, None)),
+            SpendCap::PerBatch
+        );
+
+        let token = guard.confirm(1500).unwrap().token;
+        let approval = guard.redeem(Some(&token)).unwrap().unwrap();
+        assert!(guard.check_batch(1500, 0, Some(&approval)).is_ok());
+        assert_eq!(
+            exceeded(guard.check_batch(1600, 0, Some(&approval))),
+            SpendCap::PerBatch
+        );
+        assert!(SpendGuard::default().check_batch(u64::MAX, 0, None).is_ok());
+    }
+}
@@ -0,0 +1,900 @@
+// Synthesis as every command does it: plain text turned into the request
+// input, the pre-flight checks, the cache, and the API call in its lane
+use std::borrow::Cow;
+use std::path::PathBuf;
+
+use super::fingerprint::{InputKind, TtsRequestParams};
+use super::lanes::Lane;
+use super::placeholders::Placeholders;
+use super::spend::SpendApproval;
+use super::TtsService;
+use crate::audio::channels;
+use crate::audio::presets::{OutputFormat, OutputOptions};
+use crate::audio::quality::{self, AudioAnalysis, QualityThresholds};
+use crate::cache::tts::{Lookup, TtsCache};
+use crate::error::AppError;
+use crate::settings::{Settings, SettingsStore};
+use crate::ssml::{self, BreathingRoom};
+use crate::voices::capabilities::{self, CapabilityStore, UnsupportedFeaturePolicy};
+use crate::voices::remap;
+
+#[derive(Clone)]
+pub enum SpeechInput {
+    Text(String),
+    Ssml(String),
+}
+
+impl SpeechInput {
+    pub fn content(&self) -> &str {
+        match self {
+            SpeechInput::Text(s) | SpeechInput::Ssml(s) => s,
+        }
+    }
+}
+
+pub fn speech_input(
+    text: String,
+    paragraph_pause_ms: u32,
+    line_pause_ms: u32,
+    breathing: BreathingRoom,
+) -> SpeechInput {
+    match ssml::text_with_pauses(&text, paragraph_pause_ms, line_pause_ms, breathing) {
+        Some(ssml) => SpeechInput::Ssml(ssml),
+        None => SpeechInput::Text(text),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn synthesize_cached(
+    tts: &TtsService,
+    cache: &TtsCache,
+    voice_name: &str,
+    language_code: &str,
+    input: SpeechInput,
+    quota_project: Option<&str>,
+    lane: Lane,
+    approval: Option<&SpendApproval>,
+) -> Result<Vec<u8>, AppError> {
+    synthesize_cached_hit(
+        tts,
+        cache,
+        voice_name,
+        language_code,
+        input,
+        quota_project,
+        lane,
+        approval,
+    )
+    .await
+    .map(|speech| speech.audio)
+}
+
+/// Audio from `synthesize_cached_hit`.
+pub struct CachedSpeech {
+    pub audio: Vec<u8>,
+    /// Came from the cache rather than an API call.
+    pub hit: bool,
+    /// Where a cache entry edited outside the app was moved before the
+    /// audio was synthesized anew.
+    pub modified_entry: Option<PathBuf>,
+}
+
+/// `synthesize_cached`, also returning whether the audio came from the cache
+/// rather than an API call.
+#[allow(clippy::too_many_arguments)]
+pub async fn synthesize_cached_hit(
+    tts: &TtsService,
+    cache: &TtsCache,
+    voice_name: &str,
+    language_code: &str,
+    input: SpeechInput,
+    quota_project: Option<&str>,
+    lane: Lane,
+    approval: Option<&SpendApproval>,
+) -> Result<CachedSpeech, AppError> {
+    let params = TtsRequestParams::speech(voice_name, language_code, &input);
+    let key = params.fingerprint();
+    let modified_entry = match lookup(cache, &key) {
+        Ok(hit) => return Ok(hit),
+        Err(modified_entry) => modified_entry,
+    };
+    synthesize_missed(
+        tts,
+        cache,
+        &params,
+        &key,
+        quota_project,
+        lane,
+        approval,
+        modified_entry,
+    )
+    .await
+}
+
+/// `synthesize_cached_hit` for a call confirmed past the spend caps with
+/// `spend_token`, which is only redeemed on a cache miss: audio already
+/// cached costs nothing, so it doesn't use the confirmation up.
+#[allow(clippy::too_many_arguments)]
+pub async fn synthesize_cached_redeeming(
+    tts: &TtsService,
+    cache: &TtsCache,
+    voice_name: &str,
+    language_code: &str,
+    input: SpeechInput,
+    quota_project: Option<&str>,
+    lane: Lane,
+    spend_token: Option<&str>,
+) -> Result<CachedSpeech, AppError> {
+    let params = TtsRequestParams::speech(voice_name, language_code, &input);
+    let key = params.fingerprint();
+    let modified_entry = match lookup(cache, &key) {
+        Ok(hit) => return Ok(hit),
+        Err(modified_entry) => modified_entry,
+    };
+    let approval = tts.spend().redeem(spend_token)?;
+    synthesize_missed(
+        tts,
+        cache,
+        &params,
+        &key,
+        quota_project,
+        lane,
+        approval.as_ref(),
+        modified_entry,
+    )
+    .await
+}
+
+/// The cached audio for `key`, or on a miss where an entry edited outside
+/// the app was moved, if there was one.
+fn lookup(cache: &TtsCache, key: &str) -> Result<CachedSpeech, Option<PathBuf>> {
+    match cache.get(key) {
+        Ok(Lookup::Hit(audio)) => Ok(CachedSpeech {
+            audio,
+            hit: true,
+            modified_entry: None,
+        }),
+        Ok(Lookup::Miss) => Err(None),
+        Ok(Lookup::Modified { moved_to }) => Err(Some(moved_to)),
+        Err(e) => {
+            log::warn!("TTS cache lookup failed for {}: {}", key, e);
+            Err(None)
+        }
+    }
+}
+
+/// Synthesizes `params` after a cache miss and caches the audio.
+#[allow(clippy::too_many_arguments)]
+async fn synthesize_missed(
+    tts: &TtsService,
+    cache: &TtsCache,
+    params: &TtsRequestParams,
+    key: &str,
+    quota_project: Option<&str>,
+    lane: Lane,
+    approval: Option<&SpendApproval>,
+    modified_entry: Option<PathBuf>,
+) -> Result<CachedSpeech, AppError> {
+    // Identical requests already in flight share one API call
+    let characters = params.billable_characters();
+    tts.dedup(key, async {
+        let audio_content = request_synthesis(tts, params, quota_project, lane, approval).await?;
+        if let Err(e) = cache.put(key, &audio_content, &params.voice_name, characters) {
+            log::warn!("Failed to write TTS cache entry {}: {}", key, e);
+        }
+        Ok(audio_content)
+    })
+    .await
+    .map(|audio| CachedSpeech {
+        audio,
+        hit: false,
+        modified_entry,
+    })
+}
+
+/// Runs the pre-flight checks, then removes what the voice doesn't support
+/// under the `Strip` policy.
+#[allow(clippy::too_many_arguments)]
+pub async fn check_and_strip(
+    tts: &TtsService,
+    capabilities: &CapabilityStore,
+    policy: UnsupportedFeaturePolicy,
+    placeholders: &Placeholders,
+    voice_name: &str,
+    language_code: &str,
+    text: &str,
+    input: SpeechInput,
+) -> Result<SpeechInput, AppError> {
+    let voice_capabilities = capabilities.get(voice_name);
+    super::plan::check_request(
+        tts,
+        voice_name,
+        language_code,
+        text,
+        &input,
+        &voice_capabilities,
+        policy,
+        placeholders,
+    )
+    .await?;
+    Ok(match policy {
+        UnsupportedFeaturePolicy::Strip => {
+            capabilities::strip_input(voice_name, language_code, input, &voice_capabilities)
+        }
+        _ => input,
+    })
+}
+
+/// The language code a request for `voice_name` is sent with: `requested`
+/// if the voice speaks it, else the voice's primary code, or an error when
+/// `strict`. Judged by the cached voice list only; without one the code is
+/// sent as given, with a warning. The flag says whether it was changed.
+pub async fn resolve_language(
+    tts: &TtsService,
+    voice_name: &str,
+    requested: String,
+    strict: bool,
+) -> Result<(String, bool), AppError> {
+    if super::native::voice_id(voice_name).is_some() {
+        return Ok((requested, false));
+    }
+    let Some(voices) = tts.cached_voices().await else {
+        log::warn!(
+            "No cached voice list, so {} for voice {} is sent unchecked",
+            requested,
+            voice_name
+        );
+        return Ok((requested, false));
+    };
+    // An unknown voice is the pre-flight checks' to report
+    let Some(voice) = voices.iter().find(|v| v.name == voice_name) else {
+        return Ok((requested, false));
+    };
+    if voice
+        .language_codes
+        .iter()
+        .any(|c| c.eq_ignore_ascii_case(&requested))
+    {
+        return Ok((requested, false));
+    }
+    let Some(primary) = voice.language_codes.first().filter(|_| !strict) else {
+        return Err(AppError::VoiceLanguageMismatch {
+            voice_name: voice_name.to_string(),
+            language_code: requested,
+            supported: voice.language_codes.clone(),
+        });
+    };
+    log::info!(
+        "Voice {} doesn't speak {}, synthesizing as {}",
+        voice_name,
+        requested,
+        primary
+    );
+    Ok((primary.clone(), true))
+}
+
+/// The id `voice_name` is synthesized under, after the renames of
+/// `voices::remap`; a rename is announced with "voices-remapped".
+fn current_voice(app_handle: &tauri::AppHandle, tts: &TtsService, voice_name: String) -> String {
+    match tts.voice_remap().resolve(&voice_name) {
+        Some(renamed) => {
+            let change = remap::VoiceRemapping {
+                from: voice_name,
+                to: renamed.clone(),
+            };
+            remap::announce(app_handle, "synthesis", vec![change]);
+            renamed
+        }
+        None => voice_name,
+    }
+}
+
+/// A request as `prepare_speech` leaves it.
+struct PreparedSpeech {
+    voice_name: String,
+    language_code: String,
+    /// See `resolve_language`.
+    language_corrected: bool,
+    input: SpeechInput,
+}
+
+/// What every synthesis command does to its arguments before sending them:
+/// the voice renamed per `voices::remap`, the language resolved, pauses as
+/// SSML, the pre-flight checks and stripping of unsupported features.
+#[allow(clippy::too_many_arguments)]
+async fn prepare_speech(
+    app_handle: &tauri::AppHandle,
+    tts: &TtsService,
+    capabilities: &CapabilityStore,
+    defaults: &Settings,
+    placeholders: &Placeholders,
+    voice_name: String,
+    language_code: String,
+    text: &str,
+    options: &SynthesisOptions,
+) -> Result<PreparedSpeech, AppError> {
+    let voice_name = current_voice(app_handle, tts, voice_name);
+    let (language_code, language_corrected) = resolve_language(
+        tts,
+        &voice_name,
+        language_code,
+        options.strict_language == Some(true),
+    )
+    .await?;
+    let input = speech_input(
+        text.to_string(),
+        options
+            .paragraph_pause_ms
+            .unwrap_or(defaults.paragraph_pause_ms),
+        options.line_pause_ms.unwrap_or(defaults.line_pause_ms),
+        options.breathing_room.unwrap_or(defaults.breathing_room),
+    );
+    let policy = options
+        .unsupported_features
+        .unwrap_or(defaults.unsupported_features);
+    let input = check_and_strip(
+        tts,
+        capabilities,
+        policy,
+        placeholders,
+        &voice_name,
+        &language_code,
+        text,
+        input,
+    )
+    .await?;
+    Ok(PreparedSpeech {
+        voice_name,
+        language_code,
+        language_corrected,
+        input,
+    })
+}
+
+/// Per-call overrides of synthesis settings; unset fields use the settings defaults.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct SynthesisOptions {
+    pub paragraph_pause_ms: Option<u32>,
+    pub line_pause_ms: Option<u32>,
+    /// Overrides the `breathing_room` setting.
+    pub breathing_room: Option<BreathingRoom>,
+    /// Lets `synthesize_speech_audio` render with the OS speech engine when
+    /// Google is unreachable. Off unless asked for.
+    pub allow_offline_fallback: Option<bool>,
+    /// Makes `synthesize_batch` run `validate_synthesis_plan` first and
+    /// refuse to start if it finds errors.
+    pub validate_plan: Option<bool>,
+    /// Makes `validate_synthesis_plan` report misspellings and homophones as
+    /// info. Off by default since it may download a dictionary.
+    pub check_spelling: Option<bool>,
+    /// Makes `synthesize_batch` move long segments to cheaper voices per the
+    /// `cost_saver` settings policy, recording each swap in the manifest.
+    pub cost_saver: Option<bool>,
+    /// Makes `synthesize_batch` count what it's billed against the budget
+    /// reservations for its quota project first; see `reserve_tts_budget`.
+    pub use_reservation: Option<bool>,
+    /// Bills this call to another project than the `quota_project` setting.
+    pub quota_project: Option<String>,
+    /// Token from `confirm_spend`, letting this call bill past the
+    /// `spend_caps` setting. Audio served from the cache leaves it unused.
+    pub spend_token: Option<String>,
+    /// Overrides the `unsupported_features` setting.
+    pub unsupported_features: Option<UnsupportedFeaturePolicy>,
+    /// Refuses a `language_code` the voice doesn't speak instead of
+    /// switching to the voice's own; see `resolve_language`.
+    pub strict_language: Option<bool>,
+    /// Output preset and format overrides, flattened so `channels` and the
+    /// others sit beside the options above; see `audio::presets`. Stereo
+    /// copies the mono voice to both sides.
+    #[serde(flatten)]
+    pub output: OutputOptions,
+}
+
+/// Synthesized audio and where it came from.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SynthesizedAudio {
+    /// "google" or "os-native".
+    provider: &'static str,
+    /// "standard" for Google, "low" for the offline scratch quality.
+    quality: &'static str,
+    mime_type: &'static str,
+    voice_name: String,
+    /// What the audio was synthesized as.
+    language_code: String,
+    /// `language_code` is the voice's own rather than the one asked for,
+    /// which the voice doesn't speak.
+    language_corrected: bool,
+    /// Why Google wasn't used, for a native fallback.
+    fallback_reason: Option<String>,
+    /// Where the cached audio was moved because it had been edited outside
+    /// the app; this audio was synthesized anew.
+    modified_cache_entry: Option<PathBuf>,
+    channels: Option<u16>,
+    /// Levels of WAV audio; Google's MP3s aren't analyzed.
+    analysis: Option<AudioAnalysis>,
+    audio: Vec<u8>,
+}
+
+impl SynthesizedAudio {
+    /// OS engine audio, a WAV, processed to `format`; its rate stays the
+    /// engine's.
+    fn native(
+        voice_name: String,
+        language_code: String,
+        audio: Vec<u8>,
+        fallback_reason: Option<String>,
+        format: &OutputFormat,
+    ) -> Result<Self, AppError> {
+        let audio = format.process_wav(&audio)?;
+        Ok(Self {
+            provider: "os-native",
+            quality: "low",
+            mime_type: "audio/wav",
+            voice_name,
+            language_code,
+            language_corrected: false,
+            fallback_reason,
+            modified_cache_entry: None,
+            channels: channels::count(&audio),
+            analysis: None,
+            audio,
+        })
+    }
+
+    /// Fills in `analysis`, warning about silent or clipped audio.
+    fn analyzed(mut self, app_handle: &tauri::AppHandle, thresholds: &QualityThresholds) -> Self {
+        self.analysis = quality::analyze_audio(&self.audio, thresholds);
+        if let Some(analysis) = &self.analysis {
+            quality::warn(app_handle, None, analysis);
+        }
+        self
+    }
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn synthesize_speech(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+    capabilities: tauri::State<'_, CapabilityStore>,
+    voice_name: String,
+    language_code: String,
+    text: String,
+    options: Option<SynthesisOptions>,
+) -> Result<Vec<u8>, AppError> {
+    let options = options.unwrap_or_default();
+    let defaults = settings.get();
+    let placeholders = Placeholders::new(&defaults.placeholders);
+    super::placeholders::check(&app_handle, &placeholders, &text)?;
+    super::speakable::check(&text)?;
+    let PreparedSpeech {
+        voice_name,
+        language_code,
+        input,
+        ..
+    } = prepare_speech(
+        &app_handle,
+        &tts,
+        &capabilities,
+        &defaults,
+        &placeholders,
+        voice_name,
+        language_code,
+        &text,
+        &options,
+    )
+    .await?;
+    let cache = crate::cache::tts_cache(&app_handle)?;
+    let fingerprint = TtsRequestParams::speech(&voice_name, &language_code, &input).fingerprint();
+    let audio = synthesize_cached_redeeming(
+        &tts,
+        &cache,
+        &voice_name,
+        &language_code,
+        input,
+        options.quota_project.as_deref(),
+        Lane::Interactive,
+        options.spend_token.as_deref(),
+    )
+    .await?
+    .audio;
+    super::history::remember(
+        &app_handle,
+        &fingerprint,
+        &voice_name,
+        &language_code,
+        &text,
+    );
+    Ok(audio)
+}
+
+/// What `synthesize_speech` would send for the same arguments, without
+/// sending it.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SynthesisDryRun {
+    /// The voice sent, which a rename in `voices::remap` may have changed.
+    voice_name: String,
+    input_kind: InputKind,
+    /// The text or SSML as sent, after preprocessing and the SSML policy.
+    input: String,
+    /// The pauses `breathing_room` added to the text; empty when the SSML
+    /// wasn't sent after all.
+    inserted_pauses: Vec<ssml::InsertedPause>,
+    /// The language code sent, and whether it's the voice's own rather than
+    /// the one asked for; see `resolve_language`.
+    language_code: String,
+    language_corrected: bool,
+    /// The SynthesizeSpeechRequest in the API's JSON form, with the
+    /// effective `voice` and `audioConfig`.
+    request: serde_json::Value,
+    /// Sent as the quota project header, if any.
+    quota_project: Option<String>,
+    /// Of the request as asked, before the SSML policy, as the cache keys it.
+    fingerprint: String,
+    billable_characters: usize,
+    /// Whether the audio is already cached, so synthesizing would be free.
+    cached: bool,
+    /// What the SSML policy changed before sending; `None` for plain text.
+    /// SSML it refuses fails the plan as it would fail synthesis.
+    ssml_policy: Option<ssml::policy::PolicyReport>,
+}
+
+/// Runs `synthesize_speech`'s preprocessing and returns the request it
+/// would make; never calls the API.
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn plan_synthesis(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+    capabilities: tauri::State<'_, CapabilityStore>,
+    voice_name: String,
+    language_code: String,
+    text: String,
+    options: Option<SynthesisOptions>,
+) -> Result<SynthesisDryRun, AppError> {
+    let options = options.unwrap_or_default();
+    let defaults = settings.get();
+    let placeholders = Placeholders::new(&defaults.placeholders);
+    super::placeholders::check(&app_handle, &placeholders, &text)?;
+    super::speakable::check(&text)?;
+    let PreparedSpeech {
+        voice_name,
+        language_code,
+        language_corrected,
+        input,
+    } = prepare_speech(
+        &app_handle,
+        &tts,
+        &capabilities,
+        &defaults,
+        &placeholders,
+        voice_name,
+        language_code,
+        &text,
+        &options,
+    )
+    .await?;
+    let params = TtsRequestParams::speech(&voice_name, &language_code, &input);
+    let quota_project = tts.quota_project(options.quota_project.as_deref());
+    if let Some(project_id) = &quota_project {
+        super::quota::validate_project_id(project_id)?;
+    }
+    let fingerprint = params.fingerprint();
+    let policy = tts.ssml_policy();
+    let sent = as_sent(&params, &policy)?;
+    let normalized = sent.normalized();
+    let ssml_policy = match params.input_kind {
+        InputKind::Ssml => ssml::policy::apply(&params.normalized().input, &policy).ok(),
+        InputKind::Text => None,
+    };
+    let inserted_pauses = match normalized.input_kind {
+        InputKind::Ssml => ssml::inserted_pauses(
+            &text,
+            options.breathing_room.unwrap_or(defaults.breathing_room),
+        ),
+        InputKind::Text => Vec::new(),
+    };
+    Ok(SynthesisDryRun {
+        voice_name,
+        input_kind: normalized.input_kind,
+        input: normalized.input,
+        inserted_pauses,
+        language_code,
+        language_corrected,
+        request: sent.to_request_json(),
+        quota_project,
+        cached: crate::cache::tts_cache(&app_handle)?
+            .entry_path(&fingerprint)
+            .is_file(),
+        billable_characters: params.billable_characters(),
+        fingerprint,
+        ssml_policy,
+    })
+}
+
+/// Like `synthesize_speech`, but reports the provider. `native:` voices always
+/// use the OS engine; Google voices fall back to it only when Google is
+/// unreachable and `allow_offline_fallback` is set, never otherwise.
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn synthesize_speech_audio(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+    capabilities: tauri::State<'_, CapabilityStore>,
+    voice_name: String,
+    language_code: String,
+    text: String,
+    options: Option<SynthesisOptions>,
+) -> Result<SynthesizedAudio, AppError> {
+    let options = options.unwrap_or_default();
+    let defaults = settings.get();
+    let format = options.output.resolve(&defaults)?;
+    let placeholders = Placeholders::new(&defaults.placeholders);
+    super::placeholders::check(&app_handle, &placeholders, &text)?;
+    super::speakable::check(&text)?;
+    if let Some(voice) = super::native::voice_id(&voice_name) {
+        let (voice_name, audio) =
+            super::native::synthesize(Some(voice), &language_code, &text).await?;
+        return SynthesizedAudio::native(voice_name, language_code, audio, None, &format)
+            .map(|audio| audio.analyzed(&app_handle, &defaults.audio_quality));
+    }
+
+    let PreparedSpeech {
+        voice_name,
+        language_code,
+        language_corrected,
+        input,
+    } = prepare_speech(
+        &app_handle,
+        &tts,
+        &capabilities,
+        &defaults,
+        &placeholders,
+        voice_name,
+        language_code,
+        &text,
+        &options,
+    )
+    .await?;
+    let cache = crate::cache::tts_cache(&app_handle)?;
+    let quota_project = options.quota_project.as_deref();
+    // Google's MP3s are mono and can't be decoded; anything else needs PCM
+    let result = if format.needs_pcm() {
+        // Never cached, so always a request to confirm
+        let approval = tts.spend().redeem(options.spend_token.as_deref())?;
+        synthesize_linear16(
+            &tts,
+            &voice_name,
+            &language_code,
+            input,
+            format.sample_rate_hertz,
+            quota_project,
+            Lane::Interactive,
+            approval.as_ref(),
+        )
+        .await
+        .and_then(|wav| {
+            Ok(CachedSpeech {
+                audio: format.process_wav(&wav)?,
+                hit: false,
+                modified_entry: None,
+            })
+        })
+    } else {
+        let fingerprint =
+            TtsRequestParams::speech(&voice_name, &language_code, &input).fingerprint();
+        let speech = synthesize_cached_redeeming(
+            &tts,
+            &cache,
+            &voice_name,
+            &language_code,
+            input,
+            quota_project,
+            Lane::Interactive,
+            options.spend_token.as_deref(),
+        )
+        .await;
+        if speech.is_ok() {
+            super::history::remember(
+                &app_handle,
+                &fingerprint,
+                &voice_name,
+                &language_code,
+                &text,
+            );
+        }
+        speech
+    };
+    let synthesized = match result {
+        Ok(speech) => Ok(SynthesizedAudio {
+            provider: "google",
+            quality: "standard",
+            mime_type: if format.needs_pcm() {
+                "audio/wav"
+            } else {
+                "audio/mpeg"
+            },
+            voice_name,
+            language_code,
+            language_corrected,
+            fallback_reason: None,
+            modified_cache_entry: speech.modified_entry,
+            channels: channels::count(&speech.audio),
+            analysis: None,
+            audio: speech.audio,
+        }),
+        Err(AppError::TtsOffline { message }) if options.allow_offline_fallback == Some(true) => {
+            log::warn!(
+                "Google TTS unreachable ({}), using the OS speech engine for {}",
+                message,
+                voice_name
+            );
+            let (native_voice, audio) =
+                super::native::synthesize(None, &language_code, &text).await?;
+            SynthesizedAudio::native(native_voice, language_code, audio, Some(message), &format)
+                .map(|audio| SynthesizedAudio {
+                    language_corrected,
+                    ..audio
+                })
+        }
+        Err(e) => Err(e),
+    }?;
+    Ok(synthesized.analyzed(&app_handle, &defaults.audio_quality))
+}
+
+/// Synthesizes `input` as a LINEAR16 WAV, which Google sends with its
+/// header, at `sample_rate_hertz` or the voice's own rate. Never cached:
+/// the cache holds the MP3s everything else plays.
+#[allow(clippy::too_many_arguments)]
+pub async fn synthesize_linear16(
+    tts: &TtsService,
+    voice_name: &str,
+    language_code: &str,
+    input: SpeechInput,
+    sample_rate_hertz: Option<u32>,
+    quota_project: Option<&str>,
+    lane: Lane,
+    approval: Option<&SpendApproval>,
+) -> Result<Vec<u8>, AppError> {
+    let mut params = TtsRequestParams::speech(voice_name, language_code, &input);
+    params.audio_encoding = "LINEAR16".to_string();
+    params.sample_rate_hertz = sample_rate_hertz.unwrap_or(0);
+    tts.dedup(
+        &params.fingerprint(),
+        request_synthesis(tts, &params, quota_project, lane, approval),
+    )
+    .await
+}
+
+/// `params` as they're sent: SSML passed through `policy`, stripped or
+/// refused per its mode. The fingerprint stays that of `params` as asked.
+pub fn as_sent<'a>(
+    params: &'a TtsRequestParams,
+    policy: &ssml::policy::SsmlPolicy,
+) -> Result<Cow<'a, TtsRequestParams>, AppError> {
+    let mut sent = Cow::Borrowed(params);
+    if params.input_kind == InputKind::Ssml {
+        let ssml = ssml::policy::enforce(&params.input, policy)?;
+        if ssml != params.input {
+            sent.to_mut().input = ssml;
+        }
+    }
+    Ok(sent)
+}
+
+/// Sends one request to Google, once it has passed the SSML policy and the
+/// spend caps; `approval` is the call's confirmation past them, if any.
+pub async fn request_synthesis(
+    tts: &TtsService,
+    params: &TtsRequestParams,
+    quota_project: Option<&str>,
+    lane: Lane,
+    approval: Option<&SpendApproval>,
+) -> Result<Vec<u8>, AppError> {
+    // Every SSML request passes the policy, whichever command built it
+    let sent = as_sent(params, &tts.ssml_policy())?;
+    let quota_project = tts.quota_project(quota_project);
+    let admission = tts.spend().admit(
+        params.billable_characters() as u64,
+        tts.day_characters(),
+        approval,
+    )?;
+    let _slot = tts.synthesis_slot(lane).await?;
+    let started = std::time::Instant::now();
+    let result = async {
+        let client = tts.client().await?;
+        let request = super::quota::request(sent.to_request(), quota_project.as_deref())?;
+        let response = tts
+            .observe(client.get().synthesize_speech(request))
+            .await
+            .map_err(|status| super::quota::status_error(status, quota_project.as_deref()))?;
+        tts.record_usage(
+            params.billable_characters(),
+            &params.fingerprint(),
+            &params.voice_name,
+        )
+        .await;
+        Ok(response.into_inner().audio_content)
+    }
+    .await;
+    tts.recorder()
+        .record(params, quota_project.as_deref(), started.elapsed(), &result);
+    if result.is_ok() {
+        admission.billed();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tts::mock;
+    use crate::tts::spend::SpendCaps;
+
+    async fn speak(
+        harness: &mock::Harness,
+        text: &str,
+        token: Option<&str>,
+    ) -> Result<CachedSpeech, AppError> {
+        synthesize_cached_redeeming(
+            &harness.tts,
+            &harness.cache,
+            "en-US-Mock-A",
+            "en-US",
+            SpeechInput::Text(text.to_string()),
+            None,
+            Lane::Interactive,
+            token,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn a_spend_confirmation_is_only_used_up_by_a_cache_miss() {
+        let harness = mock::harness().await;
+        harness.tts.spend().set_caps(SpendCaps {
+            per_call_characters: Some(1),
+            ..SpendCaps::default()
+        });
+        assert!(matches!(
+            speak(&harness, "Hello there", None).await,
+            Err(AppError::SpendCapExceeded { .. })
+        ));
+
+        let first = harness.tts.spend().confirm(100).unwrap().token;
+        let speech = speak(&harness, "Hello there", Some(&first)).await.unwrap();
+        assert!(!speech.hit);
+        assert!(harness.tts.spend().redeem(Some(&first)).is_err());
+
+        // Replaying cached audio costs nothing, so the confirmation is kept
+        let second = harness.tts.spend().confirm(100).unwrap().token;
+        let speech = speak(&harness, "Hello there", Some(&second)).await.unwrap();
+        assert!(speech.hit);
+        assert_eq!(harness.server.synthesize_calls(), 1);
+        assert!(harness.tts.spend().redeem(Some(&second)).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn a_bad_confirmation_fails_only_a_call_that_needs_it() {
+        let harness = mock::harness().await;
+        speak(&harness, "Cached", None).await.unwrap();
+        assert!(
+            speak(&harness, "Cached", Some("unknown"))
+                .await
+                .unwrap()
+                .hit
+        );
+        assert!(matches!(
+            speak(&harness, "Not cached", Some("unknown")).await,
+            Err(AppError::InvalidSpendConfirmation { .. })
+        ));
+        assert_eq!(harness.server.synthesize_calls(), 1);
+    }
+}
@@ -0,0 +1,1082 @@
+// Billable character accounting, kept per credential profile and month.
+// Every billed request is appended to a journal and synced before its audio
+// is returned, so a crash can't lose it; usage.json is only a compacted
+// snapshot of older journal entries. Reservations set characters of the
+// monthly budget aside for a scheduled batch; Google knows nothing of them,
+// so they're local bookkeeping in usage.reservations.json. The journal's
+// last 24 hours are also kept in memory for the daily spend cap. The app
+// and the CLI share the journal: appends and compaction hold usage.lock,
+// and each process reads again what the other added before counting
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cache::write_atomic;
+use crate::error::AppError;
+
+/// Expired and released reservations are listed this long, then dropped.
+const RESERVATION_RETENTION_DAYS: i64 = 30;
+/// Window of `last_day_characters`.
+const DAY_WINDOW_HOURS: i64 = 24;
+
+/// Usage key for synthesis done with application-default credentials.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Fingerprint prefix length kept per month; enough to tell requests apart
+/// without the file growing by a full hash per call.
+const FINGERPRINT_PREFIX: usize = 16;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MonthlyUsage {
+    pub characters: u64,
+    pub requests: u64,
+    /// Requests for audio already paid for this month, e.g. after the cache
+    /// was cleared or from another device's cache miss.
+    #[serde(default)]
+    pub repeat_requests: u64,
+    /// Characters by voice family ("Neural2", "Wavenet", ...), which Google
+    /// prices differently.
+    #[serde(default)]
+    pub characters_by_family: BTreeMap<String, u64>,
+    /// Fingerprint prefixes of the month's requests; not returned by
+    /// `totals`.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub fingerprints: BTreeSet<String>,
+}
+
+/// Profile name -> "YYYY-MM" -> usage.
+pub type UsageTotals = BTreeMap<String, BTreeMap<String, MonthlyUsage>>;
+
+/// Characters a month may use across all profiles, counting reservations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageBudget {
+    /// `None` sets no budget.
+    pub monthly_characters: Option<u64>,
+    /// Share of the budget, in percent, past which the status is `Warning`.
+    pub warn_at_percent: u8,
+}
+
+impl Default for UsageBudget {
+    fn default() -> Self {
+        Self {
+            monthly_characters: None,
+            warn_at_percent: 80,
+        }
+    }
+}
+
+/// Characters set aside for one quota project until `expires_at`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reservation {
+    pub id: String,
+    /// `None` for usage billed to the credentials' own project.
+    pub project_id: Option<String>,
+    pub characters: u64,
+    /// Used up by batches started with `use_reservation`.
+    pub drawn: u64,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub released_at: Option<DateTime<Utc>>,
+}
+
+impl Reservation {
+    /// Characters still set aside; none once expired or released.
+    pub fn outstanding(&self, now: DateTime<Utc>) -> u64 {
+        if self.released_at.is_some() || self.expires_at <= now {
+            return 0;
+        }
+        self.characters.saturating_sub(self.drawn)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetLevel {
+    Ok,
+    Warning,
+    /// Usage and reservations together are over the budget.
+    Exceeded,
+}
+
+/// The current month against the budget.
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetStatus {
+    pub month: String,
+    pub used_characters: u64,
+    /// Outstanding characters of active reservations.
+    pub reserved_characters: u64,
+    pub monthly_characters: Option<u64>,
+    /// What's left after usage and reservations; `None` without a budget.
+    pub remaining_characters: Option<u64>,
+    pub level: BudgetLevel,
+}
+
+/// One line of the journal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JournalEntry {
+    seq: u64,
+    at: DateTime<Utc>,
+    profile: String,
+    characters: u64,
+    family: String,
+    fingerprint: String,
+}
+
+/// usage.json. Journal entries up to `journal_seq` are already counted in
+/// `totals`, so a compaction interrupted before the journal was cleared
+/// doesn't count them twice.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Snapshot {
+    #[serde(default)]
+    journal_seq: u64,
+    totals: UsageTotals,
+}
+
+/// What the snapshot and journal on disk add up to.
+struct DiskUsage {
+    totals: UsageTotals,
+    last_seq: u64,
+    journal_entries: usize,
+    unreadable_lines: usize,
+    /// Length of the journal up to its last complete line; shorter than the
+    /// file when a crash cut the last append short.
+    complete_len: u64,
+    /// Whether the journal holds entries from before the current month.
+    has_old_months: bool,
+    /// When and how many characters each entry of the last day billed, in
+    /// journal order.
+    recent: Vec<(DateTime<Utc>, u64)>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageDrift {
+    pub profile: String,
+    pub month: String,
+    pub memory_characters: u64,
+    pub disk_characters: u64,
+    pub memory_requests: u64,
+    pub disk_requests: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageVerification {
+    pub journal_path: PathBuf,
+    pub journal_entries: usize,
+    /// Lines that couldn't be parsed and so aren't counted on disk.
+    pub unreadable_lines: usize,
+    /// Months where the totals in memory differ from the files, e.g. after
+    /// a journal write failed; empty when they agree.
+    pub drift: Vec<UsageDrift>,
+}
+
+/// Counts what was actually sent to Google (cache hits are free), so totals
+/// survive restarts and crashes.
+pub struct UsageLedger {
+    snapshot_path: PathBuf,
+    journal_path: PathBuf,
+    /// Held while the journal is written to, by whichever process.
+    lock_path: PathBuf,
+    /// Length of the journal as last read or written by this process.
+    journal_len: u64,
+    totals: UsageTotals,
+    next_seq: u64,
+    reservations_path: PathBuf,
+    reservations: Vec<Reservation>,
+    /// Journal entries of the last day, oldest first. Compaction leaves
+    /// them in the journal, so they're back after a restart.
+    recent: VecDeque<(DateTime<Utc>, u64)>,
+}
+
+fn month_of(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m").to_string()
+}
+
+/// "Neural2" for "en-US-Neural2-A"; Google's oldest voices have no family
+/// in their name and are Standard.
+fn voice_family(voice_name: &str) -> String {
+    let technology = crate::voices::name::parse(voice_name, None).technology;
+    if technology.is_empty() {
+        "Standard".to_string()
+    } else {
+        technology.to_string()
+    }
+}
+
+fn apply(totals: &mut UsageTotals, entry: &JournalEntry) {
+    let usage = totals
+        .entry(entry.profile.clone())
+        .or_default()
+        .entry(month_of(entry.at))
+        .or_default();
+    usage.characters += entry.characters;
+    usage.requests += 1;
+    *usage
+        .characters_by_family
+        .entry(entry.family.clone())
+        .or_default() += entry.characters;
+    if !usage.fingerprints.insert(entry.fingerprint.clone()) {
+        usage.repeat_requests += 1;
+    }
+}
+
+fn read_snapshot(path: &Path) -> Snapshot {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return Snapshot::default();
+    };
+    serde_json::from_str::<Snapshot>(&contents)
+        .or_else(|_| {
+            // Before the journal, usage.json held the totals alone
+            serde_json::from_str::<UsageTotals>(&contents).map(|totals| Snapshot {
+                journal_seq: 0,
+                totals,
+            })
+        })
+        .unwrap_or_else(|e| {
+            log::warn!("Ignoring unreadable usage file {}: {}", path.display(), e);
+            Snapshot::default()
+        })
+}
+
+fn read_reservations(path: &Path) -> Vec<Reservation> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    serde_json::from_str(&contents).unwrap_or_else(|e| {
+        log::warn!("Ignoring unreadable reservations {}: {}", path.display(), e);
+        Vec::new()
+    })
+}
+
+/// Waits for `path` to be locked by no other process, then holds it until
+/// the returned file is dropped. A lock that can't be taken, as in a
+/// read-only directory, isn't waited for; the writes would fail anyway.
+fn lock_journal(path: &Path) -> Option<fs::File> {
+    let locked = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .and_then(|file| file.lock().map(|()| file));
+    match locked {
+        Ok(file) => Some(file),
+        Err(e) => {
+            log::warn!("Failed to lock {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn journal_len(path: &Path) -> u64 {
+    fs::metadata(path).map_or(0, |m| m.len())
+}
+
+fn read_disk(snapshot_path: &Path, journal_path: &Path) -> DiskUsage {
+    let snapshot = read_snapshot(snapshot_path);
+    let journal = fs::read(journal_path).unwrap_or_default();
+    let complete_len = journal
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |i| i + 1);
+
+    let now = Utc::now();
+    let current_month = month_of(now);
+    let day_start = now - Duration::hours(DAY_WINDOW_HOURS);
+    let mut disk = DiskUsage {
+        totals: snapshot.totals,
+        last_seq: snapshot.journal_seq,
+        journal_entries: 0,
+        unreadable_lines: 0,
+        complete_len: complete_len as u64,
+        has_old_months: false,
+        recent: Vec::new(),
+    };
+    for line in journal[..complete_len].split(|&b| b == b'\n') {
+        if line.is_empty() {
+            continue;
+        }
+        let Ok(entry) = serde_json::from_slice::<JournalEntry>(line) else {
+            disk.unreadable_lines += 1;
+            continue;
+        };
+        disk.journal_entries += 1;
+        disk.has_old_months |= month_of(entry.at) < current_month;
+        // Billed either way, even when the snapshot already counts it
+        if entry.at > day_start {
+            disk.recent.push((entry.at, entry.characters));
+        }
+        if entry.seq > snapshot.journal_seq {
+            apply(&mut disk.totals, &entry);
+        }
+        disk.last_seq = disk.last_seq.max(entry.seq);
+    }
+    disk
+}
+
+impl UsageLedger {
+    /// Loads the snapshot at `path` and replays the journal next to it. A
+    /// last line cut short by a crash is dropped, since its audio was never
+    /// returned; on the first launch of a month, earlier months are
+    /// compacted into the snapshot.
+    pub fn load(path: PathBuf) -> Self {
+        let journal_path = path.with_extension("journal");
+        let lock_path = path.with_extension("lock");
+        // Another process may be appending; its line isn't torn
+        let lock = lock_journal(&lock_path);
+        let disk = read_disk(&path, &journal_path);
+        if disk.unreadable_lines > 0 {
+            log::warn!(
+                "Skipped {} unreadable lines of {}",
+                disk.unreadable_lines,
+                journal_path.display()
+            );
+        }
+        let torn = fs::metadata(&journal_path).is_ok_and(|m| m.len() > disk.complete_len);
+        if torn {
+            log::warn!(
+                "Dropping an incomplete last line of {}",
+                journal_path.display()
+            );
+            let truncated = OpenOptions::new()
+                .write(true)
+                .open(&journal_path)
+                .and_then(|file| file.set_len(disk.complete_len));
+            if let Err(e) = truncated {
+                log::warn!("Failed to truncate {}: {}", journal_path.display(), e);
+            }
+        }
+
+        let reservations_path = path.with_extension("reservations.json");
+        let mut reservations = read_reservations(&reservations_path);
+        let cutoff = Utc::now() - Duration::days(RESERVATION_RETENTION_DAYS);
+        reservations.retain(|r| r.released_at.unwrap_or(r.expires_at) > cutoff);
+
+        let mut ledger = Self {
+            snapshot_path: path,
+            journal_len: journal_len(&journal_path),
+            journal_path,
+            lock_path,
+            totals: disk.totals,
+            next_seq: disk.last_seq + 1,
+            reservations_path,
+            reservations,
+            recent: disk.recent.into(),
+        };
+        if disk.has_old_months {
+            if let Err(e) = ledger.compact() {
+                log::warn!("Failed to compact usage journal: {}", e);
+            }
+            ledger.journal_len = journal_len(&ledger.journal_path);
+        }
+        drop(lock);
+        ledger
+    }
+
+    /// Takes in what another process appended to the journal or compacted
+    /// since this one last read or wrote it. The journal only changes
+    /// length when written to, so one that did is read again whole; usage
+    /// whose write failed is then forgotten, as a restart would.
+    fn catch_up(&mut self) {
+        let len = journal_len(&self.journal_path);
+        if len == self.journal_len {
+            return;
+        }
+        let disk = read_disk(&self.snapshot_path, &self.journal_path);
+        self.totals = disk.totals;
+        self.next_seq = disk.last_seq + 1;
+        self.recent = disk.recent.into();
+        self.journal_len = len;
+    }
+
+    /// Folds the journal into the snapshot, then drops from it all but the
+    /// entries of the last day, which the daily cap still counts. Safe to
+    /// interrupt at any point: replay skips entries the snapshot includes.
+    fn compact(&self) -> io::Result<()> {
+        let snapshot = Snapshot {
+            journal_seq: self.next_seq - 1,
+            totals: self.totals.clone(),
+        };
+        let json = serde_json::to_vec_pretty(&snapshot).map_err(io::Error::other)?;
+        write_atomic(&self.snapshot_path, &json)?;
+
+        let day_start = Utc::now() - Duration::hours(DAY_WINDOW_HOURS);
+        let journal = fs::read(&self.journal_path).unwrap_or_default();
+        let mut kept = Vec::new();
+        for line in journal.split(|&b| b == b'\n') {
+            let recent = serde_json::from_slice::<JournalEntry>(line)
+                .is_ok_and(|entry| entry.at > day_start);
+            if recent {
+                kept.extend_from_slice(line);
+                kept.push(b'\n');
+            }
+        }
+        write_atomic(&self.journal_path, &kept)
+    }
+
+    /// Appends `entry` and returns the bytes written.
+    fn append(&self, entry: &JournalEntry) -> io::Result<u64> {
+        let mut line = serde_json::to_vec(entry).map_err(io::Error::other)?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.journal_path)?;
+        file.write_all(&line)?;
+        file.sync_data()?;
+        Ok(line.len() as u64)
+    }
+
+    /// Journals one billable request, then counts it. Called before the
+    /// audio is returned, so usage is on disk by the time anyone sees it;
+    /// entries of other processes are taken in first, so it numbers after
+    /// them.
+    pub fn record(
+        &mut self,
+        profile: &str,
+        characters: usize,
+        fingerprint: &str,
+        voice_name: &str,
+    ) {
+        let lock = lock_journal(&self.lock_path);
+        self.catch_up();
+        let entry = JournalEntry {
+            seq: self.next_seq,
+            at: Utc::now(),
+            profile: profile.to_string(),
+            characters: characters as u64,
+            family: voice_family(voice_name),
+            fingerprint: fingerprint[..fingerprint.len().min(FINGERPRINT_PREFIX)].to_string(),
+        };
+        // Counted in memory even when the write fails, since Google has
+        // billed it; `verify` then reports the drift
+        match self.append(&entry) {
+            Ok(written) => self.journal_len += written,
+            Err(e) => log::warn!(
+                "Failed to write usage journal {}: {}",
+                self.journal_path.display(),
+                e
+            ),
+        }
+        drop(lock);
+        self.next_seq += 1;
+        self.recent.push_back((entry.at, entry.characters));
+        apply(&mut self.totals, &entry);
+    }
+
+    /// Characters billed across all profiles in the 24 hours before `now`,
+    /// forgetting entries that have left the window.
+    pub fn last_day_characters(&mut self, now: DateTime<Utc>) -> u64 {
+        self.catch_up();
+        let day_start = now - Duration::hours(DAY_WINDOW_HOURS);
+        while self.recent.front().is_some_and(|(at, _)| *at <= day_start) {
+            self.recent.pop_front();
+        }
+        self.recent.iter().map(|(_, characters)| characters).sum()
+    }
+
+    fn save_reservations(&self) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(&self.reservations).map_err(io::Error::other)?;
+        write_atomic(&self.reservations_path, &json)
+    }
+
+    /// Characters of `month` used across all profiles.
+    fn month_characters(&self, month: &str) -> u64 {
+        self.totals
+            .values()
+            .filter_map(|months| months.get(month))
+            .map(|usage| usage.characters)
+            .sum()
+    }
+
+    pub fn budget_status(&mut self, budget: &UsageBudget) -> BudgetStatus {
+        self.catch_up();
+        let now = Utc::now();
+        let month = month_of(now);
+        let used = self.month_characters(&month);
+        let reserved: u64 = self.reservations.iter().map(|r| r.outstanding(now)).sum();
+        let committed = used.saturating_add(reserved);
+        let level = match budget.monthly_characters {
+            Some(limit) if committed > limit => BudgetLevel::Exceeded,
+            Some(limit)
+                if u128::from(committed) * 100
+                    >= u128::from(limit) * u128::from(budget.warn_at_percent) =>
+            {
+                BudgetLevel::Warning
+            }
+            _ => BudgetLevel::Ok,
+        };
+        BudgetStatus {
+            month,
+            used_characters: used,
+            reserved_characters: reserved,
+            monthly_characters: budget.monthly_characters,
+            remaining_characters: budget
+                .monthly_characters
+                .map(|limit| limit.saturating_sub(committed)),
+            level,
+        }
+    }
+
+    /// Sets `characters` aside for `project_id` until `expires_at`. Refused
+    /// when it doesn't fit in what the budget has left.
+    pub fn reserve(
+        &mut self,
+        project_id: Option<String>,
+        characters: u64,
+        expires_at: DateTime<Utc>,
+        budget: &UsageBudget,
+    ) -> Result<Reservation, AppError> {
+        let now = Utc::now();
+        if characters == 0 {
+            return Err(AppError::InvalidReservation {
+                reason: "a reservation needs at least one character".to_string(),
+            });
+        }
+        if expires_at <= now {
+            return Err(AppError::InvalidReservation {
+                reason: format!("expires_at {} is in the past", expires_at),
+            });
+        }
+        if let Some(remaining) = self.budget_status(budget).remaining_characters {
+            if characters > remaining {
+                return Err(AppError::BudgetExceeded {
+                    requested: characters,
+                    remaining,
+                });
+            }
+        }
+        let reservation = Reservation {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            project_id,
+            characters,
+            drawn: 0,
+            created_at: now,
+            expires_at,
+            released_at: None,
+        };
+        self.reservations.push(reservation.clone());
+        if let Err(e) = self.save_reservations() {
+            self.reservations.pop();
+            return Err(e.into());
+        }
+        Ok(reservation)
+    }
+
+    /// Frees what's left of reservation `id`; releasing twice is harmless.
+    pub fn release(&mut self, id: &str) -> Result<Reservation, AppError> {
+        let reservation = self
+            .reservations
+            .iter_mut()
+            .find(|r| r.id == id)
+            .ok_or_else(|| AppError::ReservationNotFound {
+                reservation_id: id.to_string(),
+            })?;
+        reservation.released_at.get_or_insert_with(Utc::now);
+        let reservation = reservation.clone();
+        self.save_reservations()?;
+        Ok(reservation)
+    }
+
+    /// Counts `characters` billed for `project_id` against its active
+    /// reservations, the soonest to expire first, and returns how many
+    /// they covered. Usage is recorded separately, by `record`.
+    pub fn draw(&mut self, project_id: Option<&str>, characters: u64) -> u64 {
+        let now = Utc::now();
+        let mut active: Vec<&mut Reservation> = self
+            .reservations
+            .iter_mut()
+            .filter(|r| r.project_id.as_deref() == project_id && r.outstanding(now) > 0)
+            .collect();
+        active.sort_by_key(|r| r.expires_at);
+        let mut left = characters;
+        for reservation in active {
+            let taken = left.min(reservation.outstanding(now));
+            reservation.drawn += taken;
+            left -= taken;
+            if left == 0 {
+                break;
+            }
+        }
+        let covered = characters - left;
+        if covered > 0 {
+            if let Err(e) = self.save_reservations() {
+                log::warn!(
+                    "Failed to write reservations {}: {}",
+                    self.reservations_path.display(),
+                    e
+                );
+            }
+        }
+        covered
+    }
+
+    /// Newest first, expired and released ones included.
+    pub fn reservations(&self) -> Vec<Reservation> {
+        self.reservations.iter().rev().cloned().collect()
+    }
+
+    pub fn totals(&mut self) -> UsageTotals {
+        self.catch_up();
+        let mut totals = self.totals.clone();
+        for usage in totals.values_mut().flat_map(|months| months.values_mut()) {
+            usage.fingerprints.clear();
+        }
+        totals
+    }
+
+    /// Recomputes the totals from the files and compares them with the ones
+    /// in memory.
+    pub fn verify(&mut self) -> UsageVerification {
+        self.catch_up();
+        let disk = read_disk(&self.snapshot_path, &self.journal_path);
+        let empty = MonthlyUsage::default();
+        let mut months = BTreeSet::new();
+        for totals in [&self.totals, &disk.totals] {
+            for (profile, by_month) in totals {
+                months.extend(by_month.keys().map(|m| (profile.clone(), m.clone())));
+            }
+        }
+        let drift = months
+            .into_iter()
+            .filter_map(|(profile, month)| {
+                let lookup = |totals: &UsageTotals| {
+                    totals
+                        .get(&profile)
+                        .and_then(|m| m.get(&month))
+                        .cloned()
+                        .unwrap_or_else(|| empty.clone())
+                };
+                let (memory, disk) = (lookup(&self.totals), lookup(&disk.totals));
+                if memory.characters == disk.characters && memory.requests == disk.requests {
+                    return None;
+                }
+                Some(UsageDrift {
+                    profile,
+                    month,
+                    memory_characters: memory.characters,
+                    disk_characters: disk.characters,
+                    memory_requests: memory.requests,
+                    disk_requests: disk.requests,
+                })
+            })
+            .collect();
+        UsageVerification {
+            journal_path: self.journal_path.clone(),
+            journal_entries: disk.journal_entries,
+            unreadable_lines: disk.unreadable_lines,
+            drift,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::scratch::Scratch;
+
+    fn ledger_path(scratch: &Scratch) -> PathBuf {
+        scratch.0.join("usage.json")
+    }
+
+    /// Writes `entries` as the journal next to `ledger_path`.
+    fn write_journal(scratch: &Scratch, entries: &[JournalEntry]) {
+        let mut lines = Vec::new();
+        for entry in entries {
+            lines.extend(serde_json::to_vec(entry).unwrap());
+            lines.push(b'\n');
+        }
+        fs::write(scratch.0.join("usage.journal"), lines).unwrap();
+    }
+
+    fn journal_lines(scratch: &Scratch) -> usize {
+        fs::read_to_string(scratch.0.join("usage.journal"))
+            .unwrap()
+            .lines()
+            .count()
+    }
+
+    fn entry(seq: u64, at: DateTime<Utc>, characters: u64) -> JournalEntry {
+        JournalEntry {
+            seq,
+            at,
+            profile: DEFAULT_PROFILE.to_string(),
+            characters,
+            family: "Neural2".to_string(),
+            fingerprint: format!("{:016x}", seq),
+        }
+    }
+
+    fn characters(ledger: &mut UsageLedger) -> u64 {
+        ledger
+            .totals()
+            .values()
+            .flat_map(|months| months.values())
+            .map(|usage| usage.characters)
+            .sum()
+    }
+
+    #[test]
+    fn compaction_keeps_the_last_day_in_the_journal() {
+        let scratch = Scratch::new("usage");
+        let now = Utc::now();
+        write_journal(
+            &scratch,
+            &[
+                entry(1, now - Duration::days(40), 500),
+                entry(2, now - Duration::hours(30), 200),
+                entry(3, now - Duration::hours(2), 70),
+            ],
+        );
+
+        let mut ledger = UsageLedger::load(ledger_path(&scratch));
+        assert_eq!(characters(&mut ledger), 770);
+        assert_eq!(ledger.last_day_characters(Utc::now()), 70);
+        assert_eq!(journal_lines(&scratch), 1);
+
+        // A restart counts the entry of the last day again for the cap, but
+        // not twice in the totals
+        let mut reloaded = UsageLedger::load(ledger_path(&scratch));
+        assert_eq!(characters(&mut reloaded), 770);
+        assert_eq!(reloaded.last_day_characters(Utc::now()), 70);
+        assert!(reloaded.verify().drift.is_empty());
+    }
+
+    #[test]
+    fn recorded_usage_survives_a_restart() {
+        let scratch = Scratch::new("usage");
+        let mut ledger = UsageLedger::load(ledger_path(&scratch));
+        ledger.record(
+            DEFAULT_PROFILE,
+            120,
+            "0123456789abcdef0123",
+            "en-US-Neural2-A",
+        );
+        ledger.record(
+            DEFAULT_PROFILE,
+            30,
+            "0123456789abcdef0123",
+            "en-US-Standard-B",
+        );
+
+        let mut reloaded = UsageLedger::load(ledger_path(&scratch));
+        assert_eq!(characters(&mut reloaded), 150);
+        assert_eq!(reloaded.last_day_characters(Utc::now()), 150);
+        let month = reloaded.totals()[DEFAULT_PROFILE][&month_of(Utc::now())].clone();
+        assert_eq!(month.requests, 2);
+        assert_eq!(month.repeat_requests, 1);
+        assert_eq!(month.characters_by_family["Neural2"], 120);
+    }
+
+    #[test]
+    fn the_day_window_forgets_older_entries() {
+        let scratch = Scratch::new("usage");
+        let mut ledger = UsageLedger::load(ledger_path(&scratch));
+        ledger.record(DEFAULT_PROFILE, 40, "fingerprint", "en-US-Neural2-A");
+        let later = Utc::now() + Duration::hours(DAY_WINDOW_HOURS) + Duration::seconds(1);
+        assert_eq!(ledger.last_day_characters(later), 0);
+    }
+
+    #[test]
+    fn a_torn_last_line_is_dropped() {
+        let scratch = Scratch::new("usage");
+        write_journal(&scratch, &[entry(1, Utc::now(), 10)]);
+        let journal = scratch.0.join("usage.journal");
+        let mut file = OpenOptions::new().append(true).open(&journal).unwrap();
+        file.write_all(b"{\"seq\":2,\"at\":").unwrap();
+
+        let mut ledger = UsageLedger::load(ledger_path(&scratch));
+        assert_eq!(characters(&mut ledger), 10);
+        assert_eq!(journal_lines(&scratch), 1);
+    }
+
+    #[test]
+    fn processes_sharing_the_journal_see_each_others_usage() {
+        let scratch = Scratch::new("usage");
+        let mut app = UsageLedger::load(ledger_path(&scratch));
+        let mut cli = UsageLedger::load(ledger_path(&scratch));
+        app.record(DEFAULT_PROFILE, 100, "aaaaaaaaaaaaaaaa", "en-US-Neural2-A");
+        assert_eq!(cli.last_day_characters(Utc::now()), 100);
+        cli.record(DEFAULT_PROFILE, 40, "bbbbbbbbbbbbbbbb", "en-US-Neural2-A");
+        app.record(DEFAULT_PROFILE, 1, "cccccccccccccccc", "en-US-Neural2-A");
+
+        assert_eq!(characters(&mut app), 141);
+        assert_eq!(characters(&mut cli), 141);
+        assert_eq!(app.last_day_characters(Utc::now()), 141);
+        let journal = fs::read_to_string(scratch.0.join("usage.journal")).unwrap();
+        let seqs: Vec<u64> = journal
+            .lines()
+            .map(|line| serde_json::from_str::<JournalEntry>(line).unwrap().seq)
+            .collect();
+        assert_eq!(seqs, [1, 2, 3]);
+        assert!(app.verify().drift.is_empty());
+    }
+
+    #[test]
+    fn an_interrupted_compaction_counts_nothing_twice() {
+        let scratch = Scratch::new("usage");
+        let now = Utc::now();
+        let month = month_of(now);
+        // The snapshot was written with entries 1 and 2 folded in, but the
+        // journal wasn't cleared yet
+        let mut totals = UsageTotals::new();
+        totals
+            .entry(DEFAULT_PROFILE.to_string())
+            .or_default()
+            .insert(
+                month.clone(),
+                MonthlyUsage {
+                    characters: 30,
+                    requests: 2,
+                    ..MonthlyUsage::default()
+                },
+            );
+        let snapshot = Snapshot {
+            journal_seq: 2,
+            totals,
+        };
+        fs::write(
+            ledger_path(&scratch),
+            serde_json::to_vec(&snapshot).unwrap(),
+        )
+        .unwrap();
+        write_journal(
+            &scratch,
+            &[entry(1, now, 10), entry(2, now, 20), entry(3, now, 5)],
+        );
+
+        let mut ledger = UsageLedger::load(ledger_path(&scratch));
+        let usage = &ledger.totals()[DEFAULT_PROFILE][&month];
+        assert_eq!((usage.characters, usage.requests), (35, 3));
+        // All three were still billed within the day
+        assert_eq!(ledger.last_day_characters(Utc::now()), 35);
+        ledger.record(DEFAULT_PROFILE, 1, "dddddddddddddddd", "en-US-Neural2-A");
+        let journal = fs::read_to_string(scratch.0.join("usage.journal")).unwrap();
+        let last: JournalEntry = serde_json::from_str(journal.lines().last().unwrap()).unwrap();
+        assert_eq!(last.seq, 4);
+    }
+
+    #[test]
+    fn reads_totals_saved_before_the_journal() {
+        let scratch = Scratch::new("usage");
+        fs::write(
+            ledger_path(&scratch),
+            r#"{"default": {"2024-03": {"characters": 900, "requests": 4}}}"#,
+        )
+        .unwrap();
+        let mut ledger = UsageLedger::load(ledger_path(&scratch));
+        let usage = &ledger.totals()[DEFAULT_PROFILE]["2024-03"];
+        assert_eq!((usage.characters, usage.requests), (900, 4));
+        assert_eq!(usage.repeat_requests, 0);
+    }
+
+    #[test]
+    fn verify_reports_unreadable_lines_and_drift() {
+        let scratch = Scratch::new("usage");
+        let now = Utc::now();
+        write_journal(&scratch, &[entry(1, now, 10), entry(2, now, 20)]);
+        let journal = scratch.0.join("usage.journal");
+        let mut lines = fs::read_to_string(&journal).unwrap();
+        lines.insert_str(0, "not json\n");
+        fs::write(&journal, lines).unwrap();
+
+        let mut ledger = UsageLedger::load(ledger_path(&scratch));
+        let verification = ledger.verify();
+        assert_eq!(verification.journal_entries, 2);
+        assert_eq!(verification.unreadable_lines, 1);
+        assert!(verification.drift.is_empty());
+
+        // A journal that can't be written to: the usage is still counted,
+        // and reported as missing from disk
+        fs::remove_file(&journal).unwrap();
+        fs::create_dir(&journal).unwrap();
+        ledger.record(DEFAULT_PROFILE, 50, "eeeeeeeeeeeeeeee", "en-US-Neural2-A");
+        assert_eq!(characters(&mut ledger), 50);
+        let drift = ledger.verify().drift;
+        assert_eq!(drift.len(), 1);
+        assert_eq!(
+            (drift[0].memory_characters, drift[0].disk_characters),
+            (50, 0)
+        );
+        assert_eq!((drift[0].memory_requests, drift[0].disk_requests), (1, 0));
+    }
+
+    fn budget(monthly_characters: u64) -> UsageBudget {
+        UsageBudget {
+            monthly_characters: Some(monthly_characters),
+            warn_at_percent: 80,
+        }
+    }
+
+    #[test]
+    fn reservations_count_against_the_budget() {
+        let scratch = Scratch::new("usage");
+        let mut ledger = UsageLedger::load(ledger_path(&scratch));
+        let budget = budget(1000);
+        let tomorrow = Utc::now() + Duration::days(1);
+        ledger.record(DEFAULT_PROFILE, 200, "aaaaaaaaaaaaaaaa", "en-US-Neural2-A");
+        ledger
+            .reserve(Some("my-project".to_string()), 500, tomorrow, &budget)
+            .unwrap();
+
+        let status = ledger.budget_status(&budget);
+        assert_eq!(status.used_characters, 200);
+        assert_eq!(status.reserved_characters, 500);
+        assert_eq!(status.remaining_characters, Some(300));
+        assert_eq!(status.level, BudgetLevel::Ok);
+
+        assert!(matches!(
+            ledger.reserve(None, 301, tomorrow, &budget),
+            Err(AppError::BudgetExceeded {
+                requested: 301,
+                remaining: 300
+            })
+        ));
+        ledger.reserve(None, 300, tomorrow, &budget).unwrap();
+        let status = ledger.budget_status(&budget);
+        assert_eq!(status.remaining_characters, Some(0));
+        assert_eq!(status.level, BudgetLevel::Warning);
+
+        // Usage beyond what was set aside goes over
+        ledger.record(DEFAULT_PROFILE, 1, "bbbbbbbbbbbbbbbb", "en-US-Neural2-A");
+        assert_eq!(ledger.budget_status(&budget).level, BudgetLevel::Exceeded);
+        // Without a budget anything fits
+        assert!(ledger
+            .reserve(None, 1_000_000, tomorrow, &UsageBudget::default())
+            .is_ok());
+    }
+
+    #[test]
+    fn refuses_empty_and_expired_reservations() {
+        let scratch = Scratch::new("usage");
+        let mut ledger = UsageLedger::load(ledger_path(&scratch));
+        let budget = UsageBudget::default();
+        for (characters, expires_at) in [
+            (0, Utc::now() + Duration::days(1)),
+            (10, Utc::now() - Duration::seconds(1)),
+        ] {
+            assert!(matches!(
+                ledger.reserve(None, characters, expires_at, &budget),
+                Err(AppError::InvalidReservation { .. })
+            ));
+        }
+        assert!(ledger.reservations().is_empty());
+        assert!(matches!(
+            ledger.release("no-such-reservation"),
+            Err(AppError::ReservationNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn draws_on_the_soonest_to_expire_reservation_of_the_project() {
+        let scratch = Scratch::new("usage");
+        let mut ledger = UsageLedger::load(ledger_path(&scratch));
+        let budget = UsageBudget::default();
+        let now = Utc::now();
+        let later = ledger
+            .reserve(
+                Some("my-project".to_string()),
+                100,
+                now + Duration::days(2),
+                &budget,
+            )
+            .unwrap();
+        let sooner = ledger
+            .reserve(
+                Some("my-project".to_string()),
+                50,
+                now + Duration::days(1),
+                &budget,
+            )
+            .unwrap();
+        let other = ledger
+            .reserve(
+                Some("other-project".to_string()),
+                100,
+                now + Duration::days(1),
+                &budget,
+            )
+            .unwrap();
+
+        assert_eq!(ledger.draw(Some("my-project"), 80), 80);
+        // More than is left is covered only as far as it goes
+        assert_eq!(ledger.draw(Some("my-project"), 100), 70);
+        assert_eq!(ledger.draw(None, 10), 0);
+        let drawn = |ledger: &UsageLedger, id: &str| {
+            ledger
+                .reservations()
+                .into_iter()
+                .find(|r| r.id == id)
+                .unwrap()
+                .drawn
+        };
+        assert_eq!(drawn(&ledger, &sooner.id), 50);
+        assert_eq!(drawn(&ledger, &later.id), 100);
+        assert_eq!(drawn(&ledger, &other.id), 0);
+    }
+
+    #[test]
+    fn released_reservations_free_their_characters_and_survive_a_restart() {
+        let scratch = Scratch::new("usage");
+        let mut ledger = UsageLedger::load(ledger_path(&scratch));
+        let budget = budget(1000);
+        let reservation = ledger
+            .reserve(None, 400, Utc::now() + Duration::days(1), &budget)
+            .unwrap();
+        assert_eq!(ledger.budget_status(&budget).reserved_characters, 400);
+
+        let released = ledger.release(&reservation.id).unwrap();
+        let again = ledger.release(&reservation.id).unwrap();
+        assert_eq!(again.released_at, released.released_at);
+        assert_eq!(released.outstanding(Utc::now()), 0);
+        assert_eq!(ledger.budget_status(&budget).reserved_characters, 0);
+        assert_eq!(ledger.draw(None, 10), 0);
+
+        let reloaded = UsageLedger::load(ledger_path(&scratch));
+        let listed = reloaded.reservations();
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].released_at.is_some());
+    }
+
+    #[test]
+    fn old_reservations_are_dropped_on_load() {
+        let scratch = Scratch::new("usage");
+        let now = Utc::now();
+        let reservation = |id: &str, expires_at: DateTime<Utc>| Reservation {
+            id: id.to_string(),
+            project_id: None,
+            characters: 10,
+            drawn: 0,
+            created_at: expires_at - Duration::days(1),
+            expires_at,
+            released_at: None,
+        };
+        let reservations = [
+            reservation(
+                "expired-long-ago",
+                now - Duration::days(RESERVATION_RETENTION_DAYS + 1),
+            ),
+            reservation("expired-recently", now - Duration::days(1)),
+        ];
+        fs::write(
+            scratch.0.join("usage.reservations.json"),
+            serde_json::to_vec(&reservations).unwrap(),
+        )
+        .unwrap();
+
+        let mut ledger = UsageLedger::load(ledger_path(&scratch));
+        let ids: Vec<String> = ledger.reservations().into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, ["expired-recently"]);
+        assert_eq!(
+            ledger
+                .budget_status(&UsageBudget::default())
+                .reserved_characters,
+            0
+        );
+    }
+}
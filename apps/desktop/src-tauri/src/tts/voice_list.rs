@@ -0,0 +1,85 @@
+// The voice list kept on disk between launches, so the voice picker has
+// something to show before the first fetch answers (or when it can't). The
+// starter snapshot bundled with the installer is a file in the same format
+use chrono::{DateTime, Utc};
+use gcloud_sdk::google::cloud::texttospeech::v1::Voice;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::cache::write_atomic;
+
+/// Name of the stored list in app data, and of the list in the starter
+/// snapshot.
+pub const FILE_NAME: &str = "voice_list.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredVoice {
+    pub name: String,
+    pub language_codes: Vec<String>,
+    /// `SsmlVoiceGender` as its wire value.
+    pub ssml_gender: i32,
+    pub natural_sample_rate_hertz: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredVoiceList {
+    /// When Google returned the list; for the starter snapshot, when the
+    /// installer was built.
+    pub fetched_at: DateTime<Utc>,
+    pub voices: Vec<StoredVoice>,
+}
+
+impl From<&Voice> for StoredVoice {
+    fn from(voice: &Voice) -> Self {
+        Self {
+            name: voice.name.clone(),
+            language_codes: voice.language_codes.clone(),
+            ssml_gender: voice.ssml_gender,
+            natural_sample_rate_hertz: voice.natural_sample_rate_hertz,
+        }
+    }
+}
+
+impl From<StoredVoice> for Voice {
+    fn from(voice: StoredVoice) -> Self {
+        Self {
+            name: voice.name,
+            language_codes: voice.language_codes,
+            ssml_gender: voice.ssml_gender,
+            natural_sample_rate_hertz: voice.natural_sample_rate_hertz,
+        }
+    }
+}
+
+impl StoredVoiceList {
+    pub fn new(voices: &[Voice], fetched_at: DateTime<Utc>) -> Self {
+        Self {
+            fetched_at,
+            voices: voices.iter().map(StoredVoice::from).collect(),
+        }
+    }
+
+    pub fn into_voices(self) -> Vec<Voice> {
+        self.voices.into_iter().map(Voice::from).collect()
+    }
+}
+
+/// The list stored at `path`; `None` when missing or unreadable, which only
+/// means the first fetch has nothing to fall back on.
+pub fn load(path: &Path) -> Option<StoredVoiceList> {
+    let contents = fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&contents) {
+        Ok(list) => Some(list),
+        Err(e) => {
+            log::warn!("Ignoring unreadable voice list {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+pub fn save(path: &Path, list: &StoredVoiceList) -> io::Result<()> {
+    let json = serde_json::to_vec_pretty(list).map_err(io::Error::other)?;
+    write_atomic(path, &json)
+}
@@ -0,0 +1,453 @@
+// Which request features each voice family honors, so unsupported ones are
+// caught before the audio comes back wrong
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use super::name::technology;
+use crate::cache::write_atomic;
+use crate::error::AppError;
+use crate::read_only::WriteAccess;
+use crate::tts::fingerprint::{InputKind, TtsRequestParams};
+use crate::tts::native;
+use crate::tts::synthesis::SpeechInput;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    Ssml,
+    Pitch,
+    SpeakingRate,
+    Timepoints,
+    CustomPronunciations,
+}
+
+impl Feature {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Feature::Ssml => "ssml",
+            Feature::Pitch => "pitch",
+            Feature::SpeakingRate => "speaking_rate",
+            Feature::Timepoints => "timepoints",
+            Feature::CustomPronunciations => "custom_pronunciations",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub ssml: bool,
+    pub pitch: bool,
+    pub speaking_rate: bool,
+    pub timepoints: bool,
+    pub custom_pronunciations: bool,
+}
+
+impl Capabilities {
+    pub const NONE: Self = Self {
+        ssml: false,
+        pitch: false,
+        speaking_rate: false,
+        timepoints: false,
+        custom_pronunciations: false,
+    };
+
+    pub const ALL: Self = Self {
+        ssml: true,
+        pitch: true,
+        speaking_rate: true,
+        timepoints: true,
+        custom_pronunciations: true,
+    };
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::Ssml => self.ssml,
+            Feature::Pitch => self.pitch,
+            Feature::SpeakingRate => self.speaking_rate,
+            Feature::Timepoints => self.timepoints,
+            Feature::CustomPronunciations => self.custom_pronunciations,
+        }
+    }
+}
+
+/// Voices listed before capabilities were tracked deserialize as supporting
+/// everything, which is how they were treated.
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// What to do with a request using a feature its voice doesn't support.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnsupportedFeaturePolicy {
+    /// Send it as is and report a warning.
+    #[default]
+    Warn,
+    /// Remove the feature before sending, see `strip`.
+    Strip,
+    /// Refuse the request.
+    Error,
+}
+
+/// Capabilities by lowercase voice family, as in `technology()`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapabilityTable {
+    pub families: BTreeMap<String, Capabilities>,
+    /// For families not in `families`, such as ones Google adds later.
+    pub default: Capabilities,
+}
+
+/// A downloaded table: its families replace the compiled-in ones of the same
+/// name, and `default` the compiled-in default if given.
+#[derive(Debug, Clone, Deserialize)]
+struct CapabilityOverrides {
+    families: BTreeMap<String, Capabilities>,
+    #[serde(default)]
+    default: Option<Capabilities>,
+}
+
+const fn caps(
+    ssml: bool,
+    pitch: bool,
+    speaking_rate: bool,
+    timepoints: bool,
+    custom_pronunciations: bool,
+) -> Capabilities {
+    Capabilities {
+        ssml,
+        pitch,
+        speaking_rate,
+        timepoints,
+        custom_pronunciations,
+    }
+}
+
+impl CapabilityTable {
+    /// The table shipped with the app. Studio voices ignore pitch, Journey
+    /// and Chirp HD voices reject SSML and every prosody setting, and Chirp 3
+    /// HD voices take only the speaking rate and custom pronunciations.
+    pub fn builtin() -> Self {
+        let families = [
+            ("standard", Capabilities::ALL),
+            ("wavenet", Capabilities::ALL),
+            ("neural2", Capabilities::ALL),
+            ("news", caps(true, true, true, true, false)),
+            ("polyglot", caps(true, true, true, true, false)),
+            ("casual", caps(true, true, true, true, false)),
+            ("studio", caps(true, false, true, true, false)),
+            ("journey", Capabilities::NONE),
+            ("chirp-hd", Capabilities::NONE),
+            ("chirp3-hd", caps(false, false, true, false, true)),
+        ];
+        Self {
+            families: families
+                .into_iter()
+                .map(|(family, caps)| (family.to_string(), caps))
+                .collect(),
+            default: caps(true, true, true, false, false),
+        }
+    }
+
+    fn with_overrides(mut self, overrides: CapabilityOverrides) -> Self {
+        for (family, caps) in overrides.families {
+            self.families.insert(family.to_ascii_lowercase(), caps);
+        }
+        if let Some(default) = overrides.default {
+            self.default = default;
+        }
+        self
+    }
+
+    pub fn lookup(&self, voice_name: &str) -> Capabilities {
+        // OS voices are rendered from plain text with no prosody controls
+        if native::voice_id(voice_name).is_some() {
+            return Capabilities::NONE;
+        }
+        self.families
+            .get(&technology(voice_name).to_ascii_lowercase())
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// Features `params` uses beyond a plain default request, in `Feature` order.
+pub fn requested_features(params: &TtsRequestParams) -> Vec<Feature> {
+    let params = params.normalized();
+    let mut features = Vec::new();
+    if params.input_kind == InputKind::Ssml {
+        features.push(Feature::Ssml);
+    }
+    if params.pitch != 0.0 {
+        features.push(Feature::Pitch);
+    }
+    if params.speaking_rate != 1.0 {
+        features.push(Feature::SpeakingRate);
+    }
+    features
+}
+
+pub fn unsupported_features(
+    params: &TtsRequestParams,
+    capabilities: &Capabilities,
+) -> Vec<Feature> {
+    requested_features(params)
+        .into_iter()
+        .filter(|f| !capabilities.supports(*f))
+        .collect()
+}
+
+/// `params` without the features `capabilities` lacks: SSML becomes the text
+/// it speaks (see `ssml::to_plain_text`), pitch goes to 0 and the speaking
+/// rate to 1. Depends only on its arguments, so the same request always
+/// strips to the same fingerprint.
+pub fn strip(params: &TtsRequestParams, capabilities: &Capabilities) -> TtsRequestParams {
+    let mut stripped = params.clone();
+    for feature in unsupported_features(params, capabilities) {
+        match feature {
+            Feature::Ssml => {
+                stripped.input = crate::ssml::to_plain_text(&stripped.input);
+                stripped.input_kind = InputKind::Text;
+            }
+            Feature::Pitch => stripped.pitch = 0.0,
+            Feature::SpeakingRate => stripped.speaking_rate = 1.0,
+            // Never part of a request the app builds
+            Feature::Timepoints | Feature::CustomPronunciations => {}
+        }
+    }
+    stripped
+}
+
+/// `strip` for the app's own requests, which vary only in their input.
+pub fn strip_input(
+    voice_name: &str,
+    language_code: &str,
+    input: SpeechInput,
+    capabilities: &Capabilities,
+) -> SpeechInput {
+    let params = TtsRequestParams::speech(voice_name, language_code, &input);
+    let stripped = strip(&params, capabilities);
+    match stripped.input_kind {
+        InputKind::Text => SpeechInput::Text(stripped.input),
+        InputKind::Ssml => SpeechInput::Ssml(stripped.input),
+    }
+}
+
+/// Managed state holding the effective table; a downloaded override is kept
+/// as voice_capabilities.json in app data.
+pub struct CapabilityStore {
+    path: PathBuf,
+    table: RwLock<CapabilityTable>,
+}
+
+fn parse_overrides(contents: &str) -> Result<CapabilityOverrides, AppError> {
+    let overrides: CapabilityOverrides =
+        serde_json::from_str(contents).map_err(|e| AppError::VoiceCapabilities {
+            message: format!("invalid capability table: {}", e),
+        })?;
+    if overrides.families.keys().any(|f| f.trim().is_empty()) {
+        return Err(AppError::VoiceCapabilities {
+            message: "invalid capability table: empty family name".to_string(),
+        });
+    }
+    Ok(overrides)
+}
+
+impl CapabilityStore {
+    pub fn load(path: PathBuf) -> Self {
+        let table = match fs::read_to_string(&path) {
+            Ok(contents) => match parse_overrides(&contents) {
+                Ok(overrides) => CapabilityTable::builtin().with_overrides(overrides),
+                Err(e) => {
+                    log::warn!("Ignoring {}: {}", path.display(), e);
+                    CapabilityTable::builtin()
+                }
+            },
+            Err(_) => CapabilityTable::builtin(),
+        };
+        Self {
+            path,
+            table: RwLock::new(table),
+        }
+    }
+
+    pub fn get(&self, voice_name: &str) -> Capabilities {
+        self.table.read().unwrap().lookup(voice_name)
+    }
+
+    /// Validates `contents`, keeps it for later launches and applies it.
+    fn replace(&self, contents: &str) -> Result<CapabilityTable, AppError> {
+        let table = CapabilityTable::builtin().with_overrides(parse_overrides(contents)?);
+        write_atomic(&self.path, contents.as_bytes())?;
+        *self.table.write().unwrap() = table.clone();
+        Ok(table)
+    }
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_voice_capabilities(
+    store: tauri::State<'_, CapabilityStore>,
+    voice_name: String,
+) -> Capabilities {
+    store.get(&voice_name)
+}
+
+/// Downloads a capability table from `url` and applies it over the
+/// compiled-in one. A table that fails to parse leaves the current one.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn update_voice_capabilities(
+    store: tauri::State<'_, CapabilityStore>,
+    access: tauri::State<'_, WriteAccess>,
+    url: String,
+) -> Result<CapabilityTable, AppError> {
+    access.check()?;
+    let download_error = |e: reqwest::Error| AppError::VoiceCapabilities {
+        message: format!("download failed: {}", e),
+    };
+    let response = reqwest::Client::new()
+        .get(&url)
+        .timeout(Duration::from_secs(30))
+        .send()
+        .await
+        .map_err(download_error)?
+        .error_for_status()
+        .map_err(download_error)?;
+    let contents = response.text().await.map_err(download_error)?;
+    let table = store.replace(&contents)?;
+    log::info!(
+        "Voice capabilities updated from {} ({} families)",
+        url,
+        table.families.len()
+    );
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A request using every feature the app can send.
+    fn full_request(voice_name: &str) -> TtsRequestParams {
+        TtsRequestParams {
+            voice_name: voice_name.to_string(),
+            language_code: "en-US".to_string(),
+            input_kind: InputKind::Ssml,
+            input: "<speak>Fish &amp; <emphasis>chips</emphasis>.</speak>".to_string(),
+            speaking_rate: 1.25,
+            pitch: -2.0,
+            ..TtsRequestParams::default()
+        }
+    }
+
+    /// What `strip` keeps of `full_request` for each voice family: SSML,
+    /// pitch and speaking rate.
+    #[test]
+    fn strips_per_family() {
+        let table = CapabilityTable::builtin();
+        let cases = [
+            ("en-US-Standard-A", (true, true, true)),
+            ("en-US-Wavenet-A", (true, true, true)),
+            ("en-US-Neural2-C", (true, true, true)),
+            ("en-US-News-K", (true, true, true)),
+            ("en-US-Polyglot-1", (true, true, true)),
+            ("en-US-Casual-K", (true, true, true)),
+            ("en-US-Studio-O", (true, false, true)),
+            ("en-US-Journey-F", (false, false, false)),
+            ("en-US-Chirp-HD-F", (false, false, false)),
+            ("en-US-Chirp3-HD-Aoede", (false, false, true)),
+            ("en-US-Futuristic-A", (true, true, true)),
+        ];
+        for (voice, (ssml, pitch, rate)) in cases {
+            let request = full_request(voice);
+            let stripped = strip(&request, &table.lookup(voice));
+            assert_eq!(stripped.input_kind == InputKind::Ssml, ssml, "{}", voice);
+            assert_eq!(stripped.pitch != 0.0, pitch, "{}", voice);
+            assert_eq!(stripped.speaking_rate != 1.0, rate, "{}", voice);
+            if !ssml {
+                // Tags become spaces
+                assert_eq!(stripped.input, "Fish & chips .", "{}", voice);
+            }
+            assert!(unsupported_features(&stripped, &table.lookup(voice)).is_empty());
+            assert_eq!(stripped.audio_encoding, request.audio_encoding);
+        }
+    }
+
+    #[test]
+    fn strips_to_the_same_fingerprint_every_time() {
+        let table = CapabilityTable::builtin();
+        for voice in ["en-US-Studio-O", "en-US-Journey-F", "en-US-Chirp3-HD-Aoede"] {
+            let request = full_request(voice);
+            let capabilities = table.lookup(voice);
+            let once = strip(&request, &capabilities);
+            assert_eq!(
+                strip(&request, &capabilities).fingerprint(),
+                once.fingerprint()
+            );
+            // Stripping what was already stripped changes nothing
+            assert_eq!(
+                strip(&once, &capabilities).fingerprint(),
+                once.fingerprint()
+            );
+            assert_ne!(once.fingerprint(), request.fingerprint(), "{}", voice);
+        }
+        let supported = full_request("en-US-Neural2-C");
+        assert_eq!(
+            strip(&supported, &Capabilities::ALL).fingerprint(),
+            supported.fingerprint()
+        );
+    }
+
+    #[test]
+    fn lists_requested_features_in_order() {
+        let request = full_request("en-US-Studio-O");
+        assert_eq!(
+            requested_features(&request),
+            [Feature::Ssml, Feature::Pitch, Feature::SpeakingRate]
+        );
+        assert_eq!(
+            unsupported_features(
+                &request,
+                &CapabilityTable::builtin().lookup("en-US-Studio-O")
+            ),
+            [Feature::Pitch]
+        );
+        let plain = TtsRequestParams {
+            input: "Hello.".to_string(),
+            ..TtsRequestParams::default()
+        };
+        assert!(requested_features(&plain).is_empty());
+    }
+
+    #[test]
+    fn overrides_replace_families_and_the_default() {
+        let overrides = parse_overrides(
+            r#"{"families": {"Studio": {"ssml": false, "pitch": false, "speaking_rate": false,
+                "timepoints": false, "custom_pronunciations": false}},
+                "default": {"ssml": true, "pitch": false, "speaking_rate": true,
+                "timepoints": false, "custom_pronunciations": false}}"#,
+        )
+        .unwrap();
+        let table = CapabilityTable::builtin().with_overrides(overrides);
+        assert_eq!(table.lookup("en-US-Studio-O"), Capabilities::NONE);
+        assert_eq!(table.lookup("en-US-Neural2-C"), Capabilities::ALL);
+        assert!(!table.lookup("en-US-Futuristic-A").pitch);
+        let empty_name = serde_json::json!({ "families": { " ": Capabilities::ALL } });
+        match parse_overrides(&empty_name.to_string()) {
+            Err(AppError::VoiceCapabilities { message }) => {
+                assert!(message.contains("empty family name"), "{}", message)
+            }
+            other => panic!(
+                "expected an empty family error, got {:?}",
+                other.map(|_| ())
+            ),
+        }
+        assert!(parse_overrides("[]").is_err());
+    }
+}
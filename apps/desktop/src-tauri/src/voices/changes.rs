@@ -0,0 +1,152 @@
+// Tracking of voices Google adds or retires between voice list fetches
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use super::google::GoogleVoice;
+use crate::cache::write_atomic;
+use crate::settings::Settings;
+
+/// Diffs kept for the "What's new" panel.
+const HISTORY_SIZE: usize = 100;
+
+/// Payload of `voices-changed`, and one entry of the persisted history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoiceChange {
+    pub detected_at: DateTime<Utc>,
+    pub added: Vec<GoogleVoice>,
+    pub removed: Vec<String>,
+}
+
+/// A removed voice the user still refers to.
+#[derive(Debug, Clone, Serialize)]
+pub struct MissingVoice {
+    pub name: String,
+    pub favorite: bool,
+    pub default_voice: bool,
+}
+
+/// Payload of `voices-missing`.
+#[derive(Debug, Clone, Serialize)]
+pub struct MissingVoices {
+    pub voices: Vec<MissingVoice>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct CatalogFile {
+    /// Names in the last fetched list. Only names are compared, so churn in
+    /// display fields or preview paths never counts as a change.
+    names: BTreeSet<String>,
+    history: VecDeque<VoiceChange>,
+}
+
+/// Managed state remembering the last voice list, persisted as
+/// voice_catalog.json in app data.
+pub struct VoiceCatalog {
+    path: PathBuf,
+    file: Mutex<CatalogFile>,
+}
+
+impl VoiceCatalog {
+    pub fn load(path: PathBuf) -> Self {
+        let file = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| match serde_json::from_str(&contents) {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    log::warn!(
+                        "Ignoring unreadable voice catalog {}: {}",
+                        path.display(),
+                        e
+                    );
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Self {
+            path,
+            file: Mutex::new(file),
+        }
+    }
+
+    /// Compares `voices` with the previous list and records the difference.
+    /// The very first list is only remembered, not reported as all-new.
+    pub fn observe(&self, voices: &[GoogleVoice]) -> Option<VoiceChange> {
+        let names: BTreeSet<String> = voices.iter().map(|v| v.name.clone()).collect();
+        let mut file = self.file.lock().unwrap();
+        if names == file.names {
+            return None;
+        }
+
+        let first = file.names.is_empty();
+        let change = VoiceChange {
+            detected_at: Utc::now(),
+            added: voices
+                .iter()
+                .filter(|v| !file.names.contains(&v.name))
+                .cloned()
+                .collect(),
+            removed: file.names.difference(&names).cloned().collect(),
+        };
+        file.names = names;
+        if !first {
+            if file.history.len() == HISTORY_SIZE {
+                file.history.pop_front();
+            }
+            file.history.push_back(change.clone());
+        }
+
+        let result = serde_json::to_vec_pretty(&*file)
+            .map_err(std::io::Error::other)
+            .and_then(|json| write_atomic(&self.path, &json));
+        if let Err(e) = result {
+            log::warn!(
+                "Failed to write voice catalog {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+        (!first).then_some(change)
+    }
+
+    /// Most recent changes first.
+    pub fn history(&self, limit: usize) -> Vec<VoiceChange> {
+        self.file
+            .lock()
+            .unwrap()
+            .history
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Removed voices that are still a favorite or the default voice.
+pub fn missing_references(removed: &[String], settings: &Settings) -> Option<MissingVoices> {
+    let voices: Vec<MissingVoice> = removed
+        .iter()
+        .map(|name| MissingVoice {
+            name: name.clone(),
+            favorite: settings.favorite_voices.contains(name),
+            default_voice: settings.default_voice.as_deref() == Some(name.as_str()),
+        })
+        .filter(|v| v.favorite || v.default_voice)
+        .collect();
+    (!voices.is_empty()).then_some(MissingVoices { voices })
+}
+
+/// Recently added and removed voices, newest first.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_voice_changes(
+    catalog: tauri::State<'_, VoiceCatalog>,
+    limit: Option<usize>,
+) -> Vec<VoiceChange> {
+    catalog.history(limit.unwrap_or(20))
+}
@@ -0,0 +1,253 @@
+// Cost saver: swapping long segments onto a cheaper voice family of the same
+// language and gender
+use gcloud_sdk::google::cloud::texttospeech::v1::Voice;
+use serde::{Deserialize, Serialize};
+
+use super::name::{technology, variant};
+use super::natural_cmp;
+
+/// Segments of at least `min_characters` spoken by a `from` voice use the
+/// equivalent `to` voice instead. Technologies are voice-name families such
+/// as "Studio" or "Neural2", compared case-insensitively.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DowngradeRule {
+    pub min_characters: usize,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CostSaverPolicy {
+    pub rules: Vec<DowngradeRule>,
+}
+
+impl Default for CostSaverPolicy {
+    fn default() -> Self {
+        Self {
+            rules: vec![DowngradeRule {
+                min_characters: 2000,
+                from: "Studio".to_string(),
+                to: "Neural2".to_string(),
+            }],
+        }
+    }
+}
+
+/// Recorded on a batch segment whose voice the cost saver replaced.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoiceSubstitution {
+    /// The voice the script asked for.
+    pub original_voice: String,
+    pub voice: String,
+    pub characters: usize,
+    /// `min_characters` of the rule that applied.
+    pub threshold: usize,
+}
+
+impl CostSaverPolicy {
+    /// The rule for a segment of `characters` spoken by `voice_name`: the one
+    /// with the highest threshold it reaches.
+    pub fn rule_for(&self, voice_name: &str, characters: usize) -> Option<&DowngradeRule> {
+        let family = technology(voice_name);
+        self.rules
+            .iter()
+            .filter(|r| r.from.eq_ignore_ascii_case(family) && characters >= r.min_characters)
+            .max_by_key(|r| r.min_characters)
+    }
+}
+
+/// The `technology` voice equivalent to `original` for `language_code`:
+///
+/// 1. it must list `language_code` among its languages, so a substitute never
+///    speaks a different language or region than the segment asked for;
+/// 2. it must have the same SSML gender as `original`;
+/// 3. among those, one with the same variant letter is preferred, otherwise
+///    the first in natural name order, so the choice is stable between runs.
+///
+/// `None` when `original` isn't in `voices` or no voice meets 1 and 2.
+pub fn equivalent_voice<'a>(
+    voices: &'a [Voice],
+    original: &str,
+    language_code: &str,
+    technology_name: &str,
+) -> Option<&'a Voice> {
+    let original = voices.iter().find(|v| v.name == original)?;
+    let mut candidates: Vec<&Voice> = voices
+        .iter()
+        .filter(|v| v.name != original.name)
+        .filter(|v| technology(&v.name).eq_ignore_ascii_case(technology_name))
+        .filter(|v| {
+            v.language_codes
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(language_code))
+        })
+        .filter(|v| v.ssml_gender == original.ssml_gender)
+        .collect();
+    candidates.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+    let letter = variant(&original.name);
+    candidates
+        .iter()
+        .find(|v| variant(&v.name) == letter)
+        .or_else(|| candidates.first())
+        .copied()
+}
+
+/// Applies `policy` to one segment, returning the substitution to make.
+pub fn substitute(
+    policy: &CostSaverPolicy,
+    voices: &[Voice],
+    voice_name: &str,
+    language_code: &str,
+    text: &str,
+) -> Option<VoiceSubstitution> {
+    let characters = text.chars().count();
+    let rule = policy.rule_for(voice_name, characters)?;
+    let Some(voice) = equivalent_voice(voices, voice_name, language_code, &rule.to) else {
+        log::info!(
+            "Cost saver: no {} voice matches {} for {}, keeping it",
+            rule.to,
+            voice_name,
+            language_code
+        );
+        return None;
+    };
+    Some(VoiceSubstitution {
+        original_voice: voice_name.to_string(),
+        voice: voice.name.clone(),
+        characters,
+        threshold: rule.min_characters,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gcloud_sdk::google::cloud::texttospeech::v1::SsmlVoiceGender;
+
+    fn voice(name: &str, languages: &[&str], gender: SsmlVoiceGender) -> Voice {
+        Voice {
+            language_codes: languages.iter().map(|l| l.to_string()).collect(),
+            name: name.to_string(),
+            ssml_gender: gender as i32,
+            natural_sample_rate_hertz: 24000,
+        }
+    }
+
+    fn voices() -> Vec<Voice> {
+        use SsmlVoiceGender::{Female, Male};
+        vec![
+            voice("en-US-Studio-O", &["en-US"], Female),
+            voice("en-US-Studio-Q", &["en-US"], Male),
+            voice("en-US-Neural2-F", &["en-US"], Female),
+            voice("en-US-Neural2-C", &["en-US"], Female),
+            voice("en-US-Neural2-O", &["en-US"], Female),
+            voice("en-US-Neural2-J", &["en-US"], Male),
+            voice("en-GB-Studio-B", &["en-GB"], Male),
+            voice("en-GB-Neural2-O", &["en-GB"], Male),
+            voice("en-AU-Neural2-B", &["en-AU"], Male),
+            voice("de-DE-Studio-B", &["de-DE"], Male),
+        ]
+    }
+
+    fn equivalent(original: &str, language_code: &str, technology: &str) -> Option<String> {
+        equivalent_voice(&voices(), original, language_code, technology).map(|v| v.name.clone())
+    }
+
+    #[test]
+    fn prefers_the_same_variant_letter() {
+        assert_eq!(
+            equivalent("en-US-Studio-O", "en-US", "Neural2").as_deref(),
+            Some("en-US-Neural2-O")
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_first_voice_of_the_same_gender_by_name() {
+        assert_eq!(
+            equivalent("en-US-Studio-Q", "en-US", "neural2").as_deref(),
+            Some("en-US-Neural2-J")
+        );
+    }
+
+    #[test]
+    fn never_crosses_languages_or_regions() {
+        assert_eq!(
+            equivalent("en-GB-Studio-B", "en-GB", "Neural2").as_deref(),
+            Some("en-GB-Neural2-O")
+        );
+        // Only other regions have a male Neural2 voice with the variant
+        assert_eq!(equivalent("de-DE-Studio-B", "de-DE", "Neural2"), None);
+        assert_eq!(equivalent("en-US-Studio-O", "en-AU", "Neural2"), None);
+    }
+
+    #[test]
+    fn never_changes_the_gender() {
+        let voices = voices();
+        for original in &voices {
+            for language in &original.language_codes {
+                if let Some(found) = equivalent_voice(&voices, &original.name, language, "Neural2")
+                {
+                    assert_eq!(found.ssml_gender, original.ssml_gender, "{}", original.name);
+                    assert!(found.language_codes.contains(language), "{}", original.name);
+                }
+            }
+        }
+        let only_male = [
+            voice("en-US-Studio-O", &["en-US"], SsmlVoiceGender::Female),
+            voice("en-US-Neural2-O", &["en-US"], SsmlVoiceGender::Male),
+        ];
+        assert!(equivalent_voice(&only_male, "en-US-Studio-O", "en-US", "Neural2").is_none());
+    }
+
+    #[test]
+    fn needs_the_original_among_the_voices() {
+        assert_eq!(equivalent("en-US-Studio-X", "en-US", "Neural2"), None);
+    }
+
+    #[test]
+    fn substitutes_only_at_the_highest_threshold_reached() {
+        let policy = CostSaverPolicy {
+            rules: vec![
+                DowngradeRule {
+                    min_characters: 10,
+                    from: "Studio".to_string(),
+                    to: "Neural2".to_string(),
+                },
+                DowngradeRule {
+                    min_characters: 20,
+                    from: "studio".to_string(),
+                    to: "Standard".to_string(),
+                },
+            ],
+        };
+        let voices = voices();
+        assert_eq!(
+            substitute(&policy, &voices, "en-US-Studio-O", "en-US", "short"),
+            None
+        );
+        assert_eq!(
+            substitute(&policy, &voices, "en-US-Studio-O", "en-US", "ten chars!"),
+            Some(VoiceSubstitution {
+                original_voice: "en-US-Studio-O".to_string(),
+                voice: "en-US-Neural2-O".to_string(),
+                characters: 10,
+                threshold: 10,
+            })
+        );
+        // No Standard voice to take, so the voice is kept
+        let long = "x".repeat(20);
+        assert_eq!(
+            policy.rule_for("en-US-Studio-O", 20).unwrap().to,
+            "Standard"
+        );
+        assert_eq!(
+            substitute(&policy, &voices, "en-US-Studio-O", "en-US", &long),
+            None
+        );
+        assert_eq!(
+            substitute(&policy, &voices, "en-US-Neural2-C", "en-US", &long),
+            None
+        );
+    }
+}
@@ -0,0 +1,423 @@
+// The Google voice list as the voice picker shows it: every voice with its
+// language, gender, technology and bundled preview
+use gcloud_sdk::google::cloud::texttospeech::v1::{SsmlVoiceGender, Voice};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tauri::{Emitter, Manager};
+
+use super::capabilities::{Capabilities, CapabilityStore};
+use super::changes::{self, VoiceCatalog};
+use super::{name, sentences, GroupedVoiceList, VoiceList, VoiceWarning};
+use crate::cache::preview::PreviewCache;
+use crate::cancellation::CancellationRegistry;
+use crate::error::AppError;
+use crate::settings::{Settings, SettingsStore};
+use crate::tts::TtsService;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GoogleVoice {
+    pub name: String,
+    pub display_name: String,
+    pub language_codes: Vec<String>,
+    pub language_name: String,
+    pub gender: String,
+    pub technology: String,
+    /// Last part of the name: a letter, a number or a star name.
+    #[serde(default)]
+    pub variant: String,
+    pub preview_path: String,
+    #[serde(default)]
+    pub capabilities: Capabilities,
+    /// When the preview this voice plays was generated, if there is one.
+    #[serde(default)]
+    pub preview_generated_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub fn get_language_display_name(lang_code: &str) -> String {
+    match lang_code {
+        "af-ZA" => "Afrikaans (South Africa)".to_string(),
+        "ar-XA" => "Arabic".to_string(),
+        "eu-ES" => "Basque (Spain)".to_string(),
+        "bn-IN" => "Bengali (India)".to_string(),
+        "bg-BG" => "Bulgarian (Bulgaria)".to_string(),
+        "ca-ES" => "Catalan (Spain)".to_string(),
+        "yue-HK" => "Chinese (Hong Kong)".to_string(),
+        "cs-CZ" => "Czech (Czech Republic)".to_string(),
+        "da-DK" => "Danish (Denmark)".to_string(),
+        "nl-BE" => "Dutch (Belgium)".to_string(),
+        "nl-NL" => "Dutch (Netherlands)".to_string(),
+        "en-AU" => "English (Australia)".to_string(),
+        "en-IN" => "English (India)".to_string(),
+        "en-GB" => "English (UK)".to_string(),
+        "en-US" => "English (US)".to_string(),
+        "fil-PH" => "Filipino (Philippines)".to_string(),
+        "fi-FI" => "Finnish (Finland)".to_string(),
+        "fr-CA" => "French (Canada)".to_string(),
+        "fr-FR" => "French (France)".to_string(),
+        "gl-ES" => "Galician (Spain)".to_string(),
+        "de-DE" => "German (Germany)".to_string(),
+        "el-GR" => "Greek (Greece)".to_string(),
+        "gu-IN" => "Gujarati (India)".to_string(),
+        "he-IL" => "Hebrew (Israel)".to_string(),
+        "hi-IN" => "Hindi (India)".to_string(),
+        "hu-HU" => "Hungarian (Hungary)".to_string(),
+        "is-IS" => "Icelandic (Iceland)".to_string(),
+        "id-ID" => "Indonesian (Indonesia)".to_string(),
+        "it-IT" => "Italian (Italy)".to_string(),
+        "ja-JP" => "Japanese (Japan)".to_string(),
+        "kn-IN" => "Kannada (India)".to_string(),
+        "ko-KR" => "Korean (South Korea)".to_string(),
+        "lv-LV" => "Latvian (Latvia)".to_string(),
+        "lt-LT" => "Lithuanian (Lithuania)".to_string(),
+        "ms-MY" => "Malay (Malaysia)".to_string(),
+        "ml-IN" => "Malayalam (India)".to_string(),
+        "cmn-CN" => "Mandarin Chinese (China)".to_string(),
+        "cmn-TW" => "Mandarin Chinese (Taiwan)".to_string(),
+        "mr-IN" => "Marathi (India)".to_string(),
+        "nb-NO" => "Norwegian (Norway)".to_string(),
+        "pl-PL" => "Polish (Poland)".to_string(),
+        "pt-BR" => "Portuguese (Brazil)".to_string(),
+        "pt-PT" => "Portuguese (Portugal)".to_string(),
+        "pa-IN" => "Punjabi (India)".to_string(),
+        "ro-RO" => "Romanian (Romania)".to_string(),
+        "ru-RU" => "Russian (Russia)".to_string(),
+        "sr-RS" => "Serbian (Serbia)".to_string(),
+        "sk-SK" => "Slovak (Slovakia)".to_string(),
+        "es-ES" => "Spanish (Spain)".to_string(),
+        "es-US" => "Spanish (US)".to_string(),
+        "sv-SE" => "Swedish (Sweden)".to_string(),
+        "ta-IN" => "Tamil (India)".to_string(),
+        "te-IN" => "Telugu (India)".to_string(),
+        "th-TH" => "Thai (Thailand)".to_string(),
+        "tr-TR" => "Turkish (Turkey)".to_string(),
+        "uk-UA" => "Ukrainian (Ukraine)".to_string(),
+        "vi-VN" => "Vietnamese (Vietnam)".to_string(),
+        _ => lang_code.to_string(),
+    }
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn list_google_voices(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    refresh: Option<bool>,
+    quota_project: Option<String>,
+    cancel_token: Option<String>,
+) -> Result<VoiceList, AppError> {
+    let token = app_handle
+        .state::<CancellationRegistry>()
+        .register(cancel_token, "list_google_voices")?;
+    let list = token
+        .run(tts.voice_list(refresh.unwrap_or(false), quota_project.as_deref()))
+        .await?;
+
+    let mut warnings = Vec::new();
+    let previews = match crate::cache::preview_cache(&app_handle) {
+        Ok(previews) => Some(previews),
+        Err(e) => {
+            warn(
+                &mut warnings,
+                None,
+                format!("preview cache unavailable: {}", e),
+            );
+            None
+        }
+    };
+    let filtered_voices = picker_voices(
+        list.voices,
+        &app_handle.state::<SettingsStore>().get(),
+        &app_handle.state::<CapabilityStore>(),
+        previews.as_ref(),
+        |voice_name| {
+            app_handle.path().resolve(
+                format!("../resources/preview_cache/voice_{}.mp3", voice_name),
+                tauri::path::BaseDirectory::Resource,
+            )
+        },
+        &mut warnings,
+    );
+    report_voice_changes(&app_handle, &filtered_voices);
+    Ok(VoiceList {
+        voices: filtered_voices,
+        warnings,
+        from_cache: list.from_cache,
+        fetched_at: list.fetched_at,
+    })
+}
+
+fn warn(warnings: &mut Vec<VoiceWarning>, voice: Option<&str>, reason: String) {
+    log::warn!("Voice list: {}: {}", voice.unwrap_or("all voices"), reason);
+    warnings.push(VoiceWarning {
+        voice: voice.map(str::to_string),
+        reason,
+    });
+}
+
+/// The picker's entries for the voices of the families it offers, with the
+/// bundled preview `resolve_preview` finds for each. Filling in a voice
+/// never fails the list: what can't be worked out is left empty and added
+/// to `warnings`.
+fn picker_voices(
+    voices: Vec<Voice>,
+    settings: &Settings,
+    capabilities: &CapabilityStore,
+    previews: Option<&PreviewCache>,
+    resolve_preview: impl Fn(&str) -> tauri::Result<PathBuf>,
+    warnings: &mut Vec<VoiceWarning>,
+) -> Vec<GoogleVoice> {
+    let mut filtered_voices: Vec<GoogleVoice> = Vec::new();
+    for v in voices.into_iter().filter(|v| {
+        let name_lower = v.name.to_lowercase();
+        name_lower.contains("neural2")
+            || name_lower.contains("wavenet")
+            || name_lower.contains("polyglot")
+            || name_lower.contains("standard")
+    }) {
+        let language_code = v.language_codes.first().cloned().unwrap_or_default();
+        if language_code.is_empty() {
+            warn(warnings, Some(&v.name), "no language code".to_string());
+        }
+        let parsed = name::parse(&v.name, Some(&language_code));
+        let technology = Some(parsed.technology)
+            .filter(|t| !t.is_empty())
+            .unwrap_or("Standard")
+            .to_string();
+        let variant = parsed.variant.to_string();
+        let language_name = get_language_display_name(&language_code);
+
+        let gender = SsmlVoiceGender::try_from(v.ssml_gender)
+            .map(|g| format!("{:?}", g))
+            .unwrap_or_else(|_| "Neutral".to_string());
+
+        let preview_path = match resolve_preview(&v.name) {
+            Ok(p) => p.to_string_lossy().to_string(),
+            Err(e) => {
+                warn(
+                    warnings,
+                    Some(&v.name),
+                    format!("preview path unresolvable: {}", e),
+                );
+                String::new()
+            }
+        };
+
+        let sentence = sentences::sentence_for(
+            &settings.preview_sentences,
+            &name::voice_language_code(&v.name),
+        );
+        let preview_generated_at = previews.and_then(|p| p.generated_at(&v.name, &sentence));
+
+        filtered_voices.push(GoogleVoice {
+            capabilities: capabilities.get(&v.name),
+            preview_generated_at,
+            display_name: String::new(),
+            language_name,
+            technology,
+            variant,
+            name: v.name,
+            language_codes: v.language_codes,
+            gender,
+            preview_path,
+        });
+    }
+    name::unique_display_names(&mut filtered_voices);
+
+    for favorite in &settings.favorite_voices {
+        if crate::tts::native::voice_id(favorite).is_none()
+            && !filtered_voices.iter().any(|v| &v.name == favorite)
+        {
+            warn(
+                warnings,
+                Some(favorite),
+                "favorite voice is not in the list".to_string(),
+            );
+        }
+    }
+    filtered_voices
+}
+
+/// Emits `voices-changed` when the list differs from the last one seen, and
+/// `voices-missing` when a removed voice is still a favorite or the default.
+fn report_voice_changes(app_handle: &tauri::AppHandle, voices: &[GoogleVoice]) {
+    let Some(change) = app_handle.state::<VoiceCatalog>().observe(voices) else {
+        return;
+    };
+    log::info!(
+        "Voice list changed: {} added, {} removed",
+        change.added.len(),
+        change.removed.len()
+    );
+    let settings = app_handle.state::<SettingsStore>().get();
+    if let Some(missing) = changes::missing_references(&change.removed, &settings) {
+        if let Err(e) = app_handle.emit("voices-missing", missing) {
+            log::warn!("Failed to emit voices-missing: {}", e);
+        }
+    }
+    if let Err(e) = app_handle.emit("voices-changed", change) {
+        log::warn!("Failed to emit voices-changed: {}", e);
+    }
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn list_google_voices_grouped(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+    sort_locale: Option<String>,
+    refresh: Option<bool>,
+    quota_project: Option<String>,
+    cancel_token: Option<String>,
+) -> Result<GroupedVoiceList, AppError> {
+    let sort_locale = sort_locale.unwrap_or_else(|| settings.get().ui_locale);
+    let list = list_google_voices(
+        app_handle.clone(),
+        tts,
+        refresh,
+        quota_project,
+        cancel_token,
+        app_handle.state(),
+    )
+    .await?;
+    Ok(GroupedVoiceList {
+        groups: super::group_voices(list.voices, &sort_locale),
+        warnings: list.warnings,
+        from_cache: list.from_cache,
+        fetched_at: list.fetched_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+    use serde_json::json;
+
+    fn voice() -> GoogleVoice {
+        GoogleVoice {
+            name: "en-US-Neural2-C".to_string(),
+            display_name: "Neural2 C".to_string(),
+            language_codes: vec!["en-US".to_string()],
+            language_name: "English (US)".to_string(),
+            gender: "Female".to_string(),
+            technology: "Neural2".to_string(),
+            variant: "C".to_string(),
+            preview_path: "/res/preview_cache/voice_en-US-Neural2-C.mp3".to_string(),
+            capabilities: Capabilities {
+                pitch: false,
+                ..Capabilities::ALL
+            },
+            preview_generated_at: Some("2024-05-01T12:00:00Z".parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn voices_round_trip_through_json() {
+        let value = serde_json::to_value(voice()).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "name": "en-US-Neural2-C",
+                "display_name": "Neural2 C",
+                "language_codes": ["en-US"],
+                "language_name": "English (US)",
+                "gender": "Female",
+                "technology": "Neural2",
+                "variant": "C",
+                "preview_path": "/res/preview_cache/voice_en-US-Neural2-C.mp3",
+                "capabilities": {
+                    "ssml": true,
+                    "pitch": false,
+                    "speaking_rate": true,
+                    "timepoints": true,
+                    "custom_pronunciations": true,
+                },
+                "preview_generated_at": "2024-05-01T12:00:00Z",
+            })
+        );
+        let back: GoogleVoice = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(serde_json::to_value(back).unwrap(), value);
+    }
+
+    #[test]
+    fn voices_stored_before_later_fields_load_with_defaults() {
+        let old: GoogleVoice = serde_json::from_value(json!({
+            "name": "en-US-Standard-C",
+            "display_name": "Standard C",
+            "language_codes": ["en-US"],
+            "language_name": "English (US)",
+            "gender": "Female",
+            "technology": "Standard",
+            "preview_path": "",
+        }))
+        .unwrap();
+        assert_eq!(old.variant, "");
+        assert_eq!(old.capabilities, Capabilities::ALL);
+        assert_eq!(old.preview_generated_at, None);
+    }
+
+    fn api_voice(name: &str, gender: SsmlVoiceGender) -> Voice {
+        Voice {
+            language_codes: vec![name[..5].to_string()],
+            name: name.to_string(),
+            ssml_gender: gender as i32,
+            natural_sample_rate_hertz: 24000,
+        }
+    }
+
+    fn picker(
+        resolve_preview: impl Fn(&str) -> tauri::Result<PathBuf>,
+    ) -> (Vec<GoogleVoice>, Vec<VoiceWarning>) {
+        let scratch = Scratch::new("google");
+        let settings = Settings {
+            favorite_voices: vec!["en-US-Wavenet-Z".to_string()],
+            ..Settings::default()
+        };
+        let mut warnings = Vec::new();
+        let voices = picker_voices(
+            vec![
+                api_voice("en-US-Neural2-C", SsmlVoiceGender::Female),
+                api_voice("en-US-Studio-O", SsmlVoiceGender::Female),
+                api_voice("de-DE-Wavenet-B", SsmlVoiceGender::Male),
+            ],
+            &settings,
+            &CapabilityStore::load(scratch.0.join("voice_capabilities.json")),
+            None,
+            resolve_preview,
+            &mut warnings,
+        );
+        (voices, warnings)
+    }
+
+    #[test]
+    fn fills_in_the_offered_families() {
+        let (voices, warnings) =
+            picker(|name| Ok(PathBuf::from(format!("/res/voice_{}.mp3", name))));
+        let names: Vec<&str> = voices.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, ["en-US-Neural2-C", "de-DE-Wavenet-B"]);
+        let german = &voices[1];
+        assert_eq!(german.language_name, "German (Germany)");
+        assert_eq!(german.gender, "Male");
+        assert_eq!(german.technology, "Wavenet");
+        assert_eq!(german.variant, "B");
+        assert_eq!(german.preview_path, "/res/voice_de-DE-Wavenet-B.mp3");
+        assert_eq!(german.preview_generated_at, None);
+        // Only the favorite missing from the list is reported
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].voice.as_deref(), Some("en-US-Wavenet-Z"));
+    }
+
+    #[test]
+    fn an_unresolvable_preview_leaves_it_empty_with_a_warning() {
+        let (voices, warnings) = picker(|_| Err(tauri::Error::UnknownPath));
+        assert_eq!(voices.len(), 2);
+        assert!(voices.iter().all(|v| v.preview_path.is_empty()));
+        let unresolved: Vec<&str> = warnings
+            .iter()
+            .filter(|w| w.reason.starts_with("preview path unresolvable"))
+            .filter_map(|w| w.voice.as_deref())
+            .collect();
+        assert_eq!(unresolved, ["en-US-Neural2-C", "de-DE-Wavenet-B"]);
+    }
+}
@@ -0,0 +1,295 @@
+// Grouping and ordering of the Google voice list for the voice picker
+pub mod capabilities;
+pub mod changes;
+pub mod downgrade;
+pub mod google;
+pub mod name;
+pub mod prefetch;
+pub mod previews;
+pub mod recommend;
+pub mod remap;
+pub mod sentences;
+pub mod shortlist;
+pub mod starter;
+
+use chrono::{DateTime, Utc};
+use icu_collator::{Collator, CollatorOptions};
+use icu_locid::Locale;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use google::GoogleVoice;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VoiceGroup {
+    pub language_code: String,
+    pub language_name: String,
+    pub voices: Vec<GoogleVoice>,
+}
+
+/// Something that went wrong filling in the voice list without losing it,
+/// e.g. a preview path that couldn't be resolved on a broken install.
+#[derive(Debug, Clone, Serialize)]
+pub struct VoiceWarning {
+    /// `None` when it affects every voice.
+    pub voice: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VoiceList {
+    pub voices: Vec<GoogleVoice>,
+    pub warnings: Vec<VoiceWarning>,
+    /// True while the list is the one stored by an earlier launch or the
+    /// starter snapshot; a fresh one is being fetched in the background.
+    pub from_cache: bool,
+    pub fetched_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupedVoiceList {
+    pub groups: Vec<VoiceGroup>,
+    pub warnings: Vec<VoiceWarning>,
+    pub from_cache: bool,
+    pub fetched_at: DateTime<Utc>,
+}
+
+/// Lower is better; unknown families sort after every known one.
+pub fn technology_rank(technology: &str) -> u8 {
+    match technology.to_ascii_lowercase().as_str() {
+        "studio" => 0,
+        "neural2" => 1,
+        "wavenet" => 2,
+        "polyglot" => 3,
+        "standard" => 4,
+        _ => 5,
+    }
+}
+
+/// Compares strings treating runs of ASCII digits as numbers, so
+/// "Wavenet-9" < "Wavenet-10" and "Neural2-A" < "Neural2-J".
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_len = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+                let b_len = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+                let (a_num, a_rest) = a.split_at(a_len);
+                let (b_num, b_rest) = b.split_at(b_len);
+                let a_trimmed = a_num.trim_start_matches('0');
+                let b_trimmed = b_num.trim_start_matches('0');
+                let ordering = a_trimmed
+                    .len()
+                    .cmp(&b_trimmed.len())
+                    .then_with(|| a_trimmed.cmp(b_trimmed))
+                    .then_with(|| a_num.len().cmp(&b_num.len()));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a = a_rest;
+                b = b_rest;
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.cmp(&y);
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a = &a[x.len_utf8()..];
+                b = &b[y.len_utf8()..];
+            }
+        }
+    }
+}
+
+fn base_language(code: &str) -> &str {
+    code.split(['-', '_']).next().unwrap_or(code)
+}
+
+/// 0 for the user's exact locale, 1 for other regions of their language,
+/// 2 for everything else.
+fn pin_rank(language_code: &str, sort_locale: &str) -> u8 {
+    if language_code.eq_ignore_ascii_case(sort_locale) {
+        0
+    } else if base_language(language_code).eq_ignore_ascii_case(base_language(sort_locale)) {
+        1
+    } else {
+        2
+    }
+}
+
+fn collator_for(sort_locale: &str) -> Option<Collator> {
+    let locale: Locale = sort_locale.parse().unwrap_or_else(|_| {
+        log::warn!("Invalid sort locale {}, using root collation", sort_locale);
+        Locale::UND
+    });
+    Collator::try_new(&(&locale).into(), CollatorOptions::new()).ok()
+}
+
+/// Groups voices by primary language code and orders them for display: the
+/// user's own languages first, then by collated language name in
+/// `sort_locale`; within a group by technology rank, then natural name order.
+pub fn group_voices(voices: Vec<GoogleVoice>, sort_locale: &str) -> Vec<VoiceGroup> {
+    let mut groups: BTreeMap<String, VoiceGroup> = BTreeMap::new();
+    for voice in voices {
+        let language_code = voice.language_codes.first().cloned().unwrap_or_default();
+        groups
+            .entry(language_code.clone())
+            .or_insert_with(|| VoiceGroup {
+                language_code,
+                language_name: voice.language_name.clone(),
+                voices: Vec::new(),
+            })
+            .voices
+            .push(voice);
+    }
+
+    let collator = collator_for(sort_locale);
+    let mut groups: Vec<VoiceGroup> = groups.into_values().collect();
+    groups.sort_by(|a, b| {
+        pin_rank(&a.language_code, sort_locale)
+            .cmp(&pin_rank(&b.language_code, sort_locale))
+            .then_with(|| match &collator {
+                Some(collator) => collator.compare(&a.language_name, &b.language_name),
+                None => a.language_name.cmp(&b.language_name),
+            })
+            .then_with(|| a.language_code.cmp(&b.language_code))
+    });
+
+    for group in &mut groups {
+        group.voices.sort_by(|a, b| {
+            technology_rank(&a.technology)
+                .cmp(&technology_rank(&b.technology))
+                .then_with(|| natural_cmp(&a.name, &b.name))
+        });
+    }
+    groups
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![
+        google::list_google_voices,
+        google::list_google_voices_grouped,
+        changes::get_voice_changes,
+        previews::get_voice_preview_audio,
+        prefetch::prefetch_previews,
+        prefetch::cancel_prefetch_previews,
+        capabilities::get_voice_capabilities,
+        capabilities::update_voice_capabilities,
+        sentences::get_preview_sentences,
+        sentences::set_preview_sentence,
+        shortlist::export_voice_shortlist,
+        shortlist::import_voice_shortlist,
+        prefetch::regenerate_preview,
+        recommend::recommend_voice,
+        starter::generate_starter_snapshot,
+        remap::get_voice_remap,
+        remap::remap_project_voices,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capabilities::Capabilities;
+
+    fn voice(name: &str, technology: &str, language_name: &str) -> GoogleVoice {
+        GoogleVoice {
+            name: name.to_string(),
+            display_name: name.to_string(),
+            language_codes: vec![name[..5].to_string()],
+            language_name: language_name.to_string(),
+            gender: "FEMALE".to_string(),
+            technology: technology.to_string(),
+            variant: name.rsplit('-').next().unwrap().to_string(),
+            preview_path: String::new(),
+            capabilities: Capabilities::ALL,
+            preview_generated_at: None,
+        }
+    }
+
+    fn languages(groups: &[VoiceGroup]) -> Vec<&str> {
+        groups.iter().map(|g| g.language_code.as_str()).collect()
+    }
+
+    #[test]
+    fn numbers_in_names_compare_as_numbers() {
+        assert_eq!(natural_cmp("Wavenet-9", "Wavenet-10"), Ordering::Less);
+        assert_eq!(natural_cmp("Neural2-A", "Neural2-J"), Ordering::Less);
+        assert_eq!(natural_cmp("A2", "A02"), Ordering::Less);
+        assert_eq!(natural_cmp("A10", "A10"), Ordering::Equal);
+        assert_eq!(natural_cmp("A", "A1"), Ordering::Less);
+    }
+
+    #[test]
+    fn orders_voices_by_technology_then_name() {
+        let groups = group_voices(
+            vec![
+                voice("en-US-Standard-B", "Standard", "English (US)"),
+                voice("en-US-Wavenet-10", "Wavenet", "English (US)"),
+                voice("en-US-Chirp3-HD-Aoede", "Chirp3-HD", "English (US)"),
+                voice("en-US-Wavenet-9", "Wavenet", "English (US)"),
+                voice("en-US-Studio-O", "Studio", "English (US)"),
+                voice("en-US-Neural2-A", "Neural2", "English (US)"),
+            ],
+            "en-US",
+        );
+        let names: Vec<&str> = groups[0].voices.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "en-US-Studio-O",
+                "en-US-Neural2-A",
+                "en-US-Wavenet-9",
+                "en-US-Wavenet-10",
+                "en-US-Standard-B",
+                "en-US-Chirp3-HD-Aoede",
+            ]
+        );
+    }
+
+    #[test]
+    fn the_users_languages_come_first() {
+        let voices = || {
+            vec![
+                voice("de-DE-Standard-A", "Standard", "German (Germany)"),
+                voice("en-GB-Standard-A", "Standard", "English (UK)"),
+                voice("fr-FR-Standard-A", "Standard", "French (France)"),
+                voice("fr-CA-Standard-A", "Standard", "French (Canada)"),
+            ]
+        };
+        assert_eq!(
+            languages(&group_voices(voices(), "fr-FR")),
+            ["fr-FR", "fr-CA", "en-GB", "de-DE"]
+        );
+        assert_eq!(
+            languages(&group_voices(voices(), "ja-JP")),
+            ["en-GB", "fr-CA", "fr-FR", "de-DE"]
+        );
+    }
+
+    #[test]
+    fn language_names_collate_in_the_sort_locale() {
+        let voices = || {
+            vec![
+                voice("zu-ZA-Standard-A", "Standard", "Zulu"),
+                voice("is-IS-Standard-A", "Standard", "Íslenska"),
+                voice("nl-NL-Standard-A", "Standard", "Nederlands"),
+            ]
+        };
+        // Not by code point, which would put "Í" after "Z"
+        assert_eq!(
+            languages(&group_voices(voices(), "en-US")),
+            ["is-IS", "nl-NL", "zu-ZA"]
+        );
+        assert_eq!(
+            languages(&group_voices(voices(), "not a locale")),
+            ["is-IS", "nl-NL", "zu-ZA"]
+        );
+    }
+}
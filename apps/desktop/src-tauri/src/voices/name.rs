@@ -0,0 +1,261 @@
+// Parsing of Google voice names into locale, technology family and variant,
+// and the display names built from them
+use std::collections::HashMap;
+
+use super::google::GoogleVoice;
+use crate::tts::native;
+
+/// Display name levels `unique_display_names` escalates through.
+const DISPLAY_LEVELS: u8 = 4;
+
+/// The parts of a name such as "en-US-Chirp3-HD-Achernar": locale "en-US",
+/// technology "Chirp3-HD", variant "Achernar".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoiceName<'a> {
+    pub locale: &'a str,
+    pub technology: &'a str,
+    pub variant: &'a str,
+}
+
+/// Splits `voice_name`, using `language_code` (the voice's first language
+/// code) as the locale prefix when the name starts with it. Otherwise the
+/// first two parts are the locale, which holds for every name Google ships,
+/// three-letter languages such as "cmn-CN" and "fil-PH" included.
+///
+/// Everything between the locale and the last part is the technology, so
+/// multi-part families such as "Chirp-HD" stay whole; the last part is the
+/// variant, a letter, a number ("Polyglot-1") or a star name. A name with a
+/// single part after the locale has that part as its technology and no
+/// variant.
+pub fn parse<'a>(voice_name: &'a str, language_code: Option<&str>) -> VoiceName<'a> {
+    let prefix_len = language_code
+        .filter(|code| {
+            voice_name.len() > code.len()
+                && voice_name.as_bytes()[code.len()] == b'-'
+                && voice_name[..code.len()].eq_ignore_ascii_case(code)
+        })
+        .map(|code| code.len())
+        .or_else(|| voice_name.match_indices('-').nth(1).map(|(index, _)| index))
+        .unwrap_or(voice_name.len());
+    let locale = &voice_name[..prefix_len];
+    let rest = voice_name.get(prefix_len + 1..).unwrap_or_default();
+    let (technology, variant) = match rest.rsplit_once('-') {
+        Some((technology, variant)) => (technology, variant),
+        None => (rest, ""),
+    };
+    VoiceName {
+        locale,
+        technology,
+        variant,
+    }
+}
+
+/// Technology family of a voice name: "Studio" for "en-US-Studio-O",
+/// "Chirp3-HD" for "en-US-Chirp3-HD-Achernar", "Native" for OS voices.
+pub fn technology(voice_name: &str) -> &str {
+    if native::voice_id(voice_name).is_some() {
+        return native::TECHNOLOGY;
+    }
+    parse(voice_name, None).technology
+}
+
+/// The last part of a voice name, "O" for "en-US-Studio-O".
+pub fn variant(voice_name: &str) -> &str {
+    parse(voice_name, None).variant
+}
+
+/// Language code embedded in a voice name, e.g. "cmn-CN" for "cmn-CN-Wavenet-A".
+pub fn voice_language_code(voice_name: &str) -> String {
+    voice_name
+        .splitn(3, '-')
+        .take(2)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// For `level` 0, "English A"; 1 adds the region, "English (US) A"; 2 the
+/// technology, "English (US) · Neural2 A"; 3 the full voice name.
+fn display_name(voice: &GoogleVoice, level: u8) -> String {
+    let short = voice.language_name.split('(').next().unwrap_or("").trim();
+    let name = match level {
+        0 => format!("{} {}", short, voice.variant),
+        1 => format!("{} {}", voice.language_name, voice.variant),
+        _ => format!(
+            "{} · {} {}",
+            voice.language_name, voice.technology, voice.variant
+        ),
+    };
+    let name = name.trim().to_string();
+    if level >= 3 {
+        format!("{} ({})", name, voice.name)
+    } else {
+        name
+    }
+}
+
+/// Sets every `display_name` to the shortest form that no other voice in
+/// `voices` shares: voices start at "English A", and only those whose name
+/// collides gain the region, then the technology, then the voice name.
+pub fn unique_display_names(voices: &mut [GoogleVoice]) {
+    let mut levels = vec![0u8; voices.len()];
+    loop {
+        let names: Vec<String> = voices
+            .iter()
+            .zip(&levels)
+            .map(|(voice, &level)| display_name(voice, level))
+            .collect();
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for name in &names {
+            *counts.entry(name).or_default() += 1;
+        }
+        let mut escalated = false;
+        for (level, name) in levels.iter_mut().zip(&names) {
+            if counts[name.as_str()] > 1 && *level + 1 < DISPLAY_LEVELS {
+                *level += 1;
+                escalated = true;
+            }
+        }
+        if !escalated {
+            for (voice, name) in voices.iter_mut().zip(names) {
+                voice.display_name = name;
+            }
+            return;
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voices::capabilities::Capabilities;
+
+    fn voice(name: &str, language_name: &str) -> GoogleVoice {
+        let parts = parse(name, None);
+        GoogleVoice {
+            name: name.to_string(),
+            display_name: String::new(),
+            language_codes: vec![parts.locale.to_string()],
+            language_name: language_name.to_string(),
+            gender: "FEMALE".to_string(),
+            technology: parts.technology.to_string(),
+            variant: parts.variant.to_string(),
+            preview_path: String::new(),
+            capabilities: Capabilities::ALL,
+            preview_generated_at: None,
+        }
+    }
+
+    fn display_names(voices: &[(&str, &str)]) -> Vec<String> {
+        let mut voices: Vec<GoogleVoice> = voices
+            .iter()
+            .map(|(name, language_name)| voice(name, language_name))
+            .collect();
+        unique_display_names(&mut voices);
+        voices.into_iter().map(|v| v.display_name).collect()
+    }
+
+    fn parts<'a>(voice_name: &'a str, language_code: Option<&str>) -> (&'a str, &'a str, &'a str) {
+        let name = parse(voice_name, language_code);
+        (name.locale, name.technology, name.variant)
+    }
+
+    #[test]
+    fn splits_names_into_locale_technology_and_variant() {
+        assert_eq!(parts("en-US-Studio-O", None), ("en-US", "Studio", "O"));
+        assert_eq!(parts("cmn-CN-Wavenet-A", None), ("cmn-CN", "Wavenet", "A"));
+        assert_eq!(
+            parts("fil-PH-Standard-B", None),
+            ("fil-PH", "Standard", "B")
+        );
+        assert_eq!(
+            parts("en-US-Chirp3-HD-Achernar", None),
+            ("en-US", "Chirp3-HD", "Achernar")
+        );
+        assert_eq!(
+            parts("en-US-Polyglot-1", Some("en-US")),
+            ("en-US", "Polyglot", "1")
+        );
+        assert_eq!(parts("en-US-Casual", None), ("en-US", "Casual", ""));
+        assert_eq!(parts("en-US", None), ("en-US", "", ""));
+    }
+
+    #[test]
+    fn prefers_the_voices_language_code_as_the_locale() {
+        assert_eq!(
+            parts("cmn-Hans-CN-Standard-A", Some("cmn-Hans-CN")),
+            ("cmn-Hans-CN", "Standard", "A")
+        );
+        // Matched regardless of case, but only on a whole part
+        assert_eq!(
+            parts("en-us-Neural2-A", Some("en-US")),
+            ("en-us", "Neural2", "A")
+        );
+        assert_eq!(
+            parts("en-USX-Neural2-A", Some("en-US")),
+            ("en-USX", "Neural2", "A")
+        );
+        assert_eq!(
+            parts("de-DE-Neural2-A", Some("en-US")),
+            ("de-DE", "Neural2", "A")
+        );
+    }
+
+    #[test]
+    fn names_the_technology_and_variant_of_any_voice() {
+        assert_eq!(technology("en-US-Chirp-HD-F"), "Chirp-HD");
+        assert_eq!(
+            technology("native:com.apple.voice.Samantha"),
+            native::TECHNOLOGY
+        );
+        assert_eq!(variant("en-US-Studio-O"), "O");
+        assert_eq!(voice_language_code("cmn-CN-Wavenet-A"), "cmn-CN");
+    }
+
+    #[test]
+    fn keeps_display_names_short_unless_they_collide() {
+        assert_eq!(
+            display_names(&[
+                ("en-US-Neural2-A", "English (US)"),
+                ("en-US-Neural2-C", "English (US)"),
+            ]),
+            ["English A", "English C"]
+        );
+    }
+
+    #[test]
+    fn adds_only_what_tells_colliding_voices_apart() {
+        assert_eq!(
+            display_names(&[
+                ("en-US-Neural2-A", "English (US)"),
+                ("en-GB-Neural2-A", "English (UK)"),
+                ("en-GB-Neural2-B", "English (UK)"),
+            ]),
+            ["English (US) A", "English (UK) A", "English B"]
+        );
+        assert_eq!(
+            display_names(&[
+                ("en-US-Neural2-A", "English (US)"),
+                ("en-US-Wavenet-A", "English (US)"),
+                ("en-US-Studio-O", "English (US)"),
+            ]),
+            [
+                "English (US) · Neural2 A",
+                "English (US) · Wavenet A",
+                "English O"
+            ]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_voice_name_for_identical_voices() {
+        assert_eq!(
+            display_names(&[
+                ("en-US-Neural2-A", "English (US)"),
+                ("en-us-Neural2-A", "English (US)"),
+            ]),
+            [
+                "English (US) · Neural2 A (en-US-Neural2-A)",
+                "English (US) · Neural2 A (en-us-Neural2-A)"
+            ]
+        );
+    }
+}
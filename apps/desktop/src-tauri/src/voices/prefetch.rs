@@ -0,0 +1,285 @@
+// Prefetch of every voice preview in a language group, so expanding the group
+// in the voice picker costs one IPC call instead of one per voice, and
+// regeneration of a single stale preview
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OnceCell, Semaphore};
+
+use crate::assets::{asset_url, AssetKind};
+use crate::cancellation::CancellationRegistry;
+use crate::error::AppError;
+use crate::jobs::{JobKind, JobManager};
+use crate::paths::roots::AllowedRoots;
+use crate::read_only::WriteAccess;
+use crate::settings::SettingsStore;
+use crate::trash::{Trash, TrashEntry};
+use crate::tts::lanes::Lane;
+use crate::tts::TtsService;
+
+/// Previews synthesized at once across all prefetches, so expanding several
+/// groups quickly doesn't burst the API.
+const MAX_CONCURRENT_GENERATIONS: usize = 2;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PreviewPrefetch {
+    pub language_code: String,
+    /// Voice name -> asset URL of its preview.
+    pub urls: BTreeMap<String, String>,
+    /// Voices whose preview couldn't be generated.
+    pub failed: Vec<String>,
+    /// Stopped by `cancel_prefetch_previews`; `urls` holds what was ready.
+    pub cancelled: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RegeneratedPreview {
+    pub voice_name: String,
+    /// Differs per generation so the webview doesn't play a cached response.
+    pub url: String,
+    pub generated_at: DateTime<Utc>,
+    /// Where the replaced preview went; `None` if only the bundled one, which
+    /// stays, existed.
+    pub trash_entry: Option<TrashEntry>,
+}
+
+type Regeneration = Arc<OnceCell<Result<RegeneratedPreview, AppError>>>;
+
+/// Managed state: the generation limiter, a cancel flag per language being
+/// prefetched and the single-voice regenerations in flight.
+pub struct PrefetchJobs {
+    limiter: Semaphore,
+    running: Mutex<HashMap<String, Arc<AtomicBool>>>,
+    regenerating: Mutex<HashMap<String, Regeneration>>,
+}
+
+impl Default for PrefetchJobs {
+    fn default() -> Self {
+        Self {
+            limiter: Semaphore::new(MAX_CONCURRENT_GENERATIONS),
+            running: Mutex::new(HashMap::new()),
+            regenerating: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl PrefetchJobs {
+    fn start(&self, language_code: &str) -> Arc<AtomicBool> {
+        let flag = Arc::new(AtomicBool::new(false));
+        let previous = self
+            .running
+            .lock()
+            .unwrap()
+            .insert(language_code.to_string(), flag.clone());
+        // A second prefetch of the same group supersedes the first
+        if let Some(previous) = previous {
+            previous.store(true, Ordering::Relaxed);
+        }
+        flag
+    }
+
+    fn finish(&self, language_code: &str, flag: &Arc<AtomicBool>) {
+        let mut running = self.running.lock().unwrap();
+        if running
+            .get(language_code)
+            .is_some_and(|current| Arc::ptr_eq(current, flag))
+        {
+            running.remove(language_code);
+        }
+    }
+
+    fn cancel(&self, language_code: &str) -> bool {
+        match self.running.lock().unwrap().get(language_code) {
+            Some(flag) => {
+                flag.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Makes sure every voice of `language_code` has a preview in the cache and
+/// returns their asset URLs. Cached previews are answered from disk
+/// without touching the network; missing ones are generated a few at a time.
+/// `cancel_prefetch_previews` stops it with the URLs so far, `cancel_token`
+/// with `Cancelled`.
+#[crate::metrics::timed]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn prefetch_previews(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    jobs: tauri::State<'_, PrefetchJobs>,
+    job_manager: tauri::State<'_, JobManager>,
+    settings: tauri::State<'_, SettingsStore>,
+    cancellation: tauri::State<'_, CancellationRegistry>,
+    access: tauri::State<'_, WriteAccess>,
+    language_code: String,
+    cancel_token: Option<String>,
+) -> Result<PreviewPrefetch, AppError> {
+    access.check()?;
+    let token = cancellation.register(cancel_token, "prefetch_previews")?;
+    let cache = crate::cache::preview_cache(&app_handle)?;
+    let mut voices: Vec<String> = token
+        .run(tts.voices(false, None))
+        .await?
+        .into_iter()
+        .filter(|v| {
+            v.language_codes
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(&language_code))
+        })
+        .map(|v| v.name)
+        .collect();
+    voices.sort();
+
+    let mut result = PreviewPrefetch {
+        language_code: language_code.clone(),
+        urls: BTreeMap::new(),
+        failed: Vec::new(),
+        cancelled: false,
+    };
+    let mut missing = Vec::new();
+    for voice in voices {
+        let sentence = super::sentences::for_voice(&settings, &voice);
+        if cache.locate(&voice, &sentence).is_some() {
+            result
+                .urls
+                .insert(voice.clone(), asset_url(AssetKind::Preview, &voice));
+        } else {
+            missing.push((voice, sentence));
+        }
+    }
+    if missing.is_empty() {
+        return Ok(result);
+    }
+
+    let cancelled = jobs.start(&language_code);
+    let job = job_manager.register(JobKind::PreviewPrefetch, language_code.clone(), None);
+    for (voice, sentence) in missing {
+        let permit = jobs.limiter.acquire().await.map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })?;
+        if token.is_cancelled() {
+            jobs.finish(&language_code, &cancelled);
+            return Err(token.cancelled_error());
+        }
+        if cancelled.load(Ordering::Relaxed) || job.is_cancelled() {
+            result.cancelled = true;
+            break;
+        }
+        match super::previews::regenerate_preview(&tts, &cache, &voice, &sentence, Lane::Background)
+            .await
+        {
+            Ok(_) => {
+                result
+                    .urls
+                    .insert(voice.clone(), asset_url(AssetKind::Preview, &voice));
+            }
+            Err(e) => {
+                log::warn!("Preview prefetch for {} failed: {}", voice, e);
+                result.failed.push(voice);
+            }
+        }
+        drop(permit);
+    }
+    jobs.finish(&language_code, &cancelled);
+    Ok(result)
+}
+
+/// Stops a running prefetch after the preview being generated; false if
+/// none was running for `language_code`.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn cancel_prefetch_previews(
+    jobs: tauri::State<'_, PrefetchJobs>,
+    language_code: String,
+) -> bool {
+    jobs.cancel(&language_code)
+}
+
+async fn regenerate(
+    app_handle: &tauri::AppHandle,
+    tts: &TtsService,
+    jobs: &PrefetchJobs,
+    settings: &SettingsStore,
+    trash: &Trash,
+    voice_name: &str,
+) -> Result<RegeneratedPreview, AppError> {
+    let sentence = super::sentences::for_voice(settings, voice_name);
+    let cache = crate::cache::preview_cache(app_handle)?;
+    let permit = jobs.limiter.acquire().await.map_err(|e| AppError::Io {
+        message: e.to_string(),
+    })?;
+    let audio =
+        super::previews::synthesize_preview(tts, voice_name, &sentence, Lane::Interactive).await?;
+    drop(permit);
+
+    // The old preview is only given up once its replacement exists
+    let trash_entry = match cache.locate_writable(voice_name, &sentence) {
+        Some(old) => {
+            use tauri::Manager;
+
+            let old = app_handle.state::<AllowedRoots>().validate_path(&old)?;
+            trash.discard("regenerate_preview", &[old])?
+        }
+        None => None,
+    };
+    cache.store(voice_name, &sentence, &audio)?;
+    let generated_at = cache
+        .generated_at(voice_name, &sentence)
+        .unwrap_or_else(Utc::now);
+    Ok(RegeneratedPreview {
+        voice_name: voice_name.to_string(),
+        url: format!(
+            "{}?v={}",
+            asset_url(AssetKind::Preview, voice_name),
+            generated_at.timestamp_millis()
+        ),
+        generated_at,
+        trash_entry,
+    })
+}
+
+/// Replaces the preview of `voice_name` with a fresh one in its current
+/// preview sentence, moving the old file to the trash. Requests for a voice
+/// already being regenerated wait for that one and share its result.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn regenerate_preview(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    jobs: tauri::State<'_, PrefetchJobs>,
+    settings: tauri::State<'_, SettingsStore>,
+    trash: tauri::State<'_, Trash>,
+    access: tauri::State<'_, WriteAccess>,
+    voice_name: String,
+) -> Result<RegeneratedPreview, AppError> {
+    access.check()?;
+    if !crate::cache::is_voice_name(&voice_name) {
+        return Err(AppError::InvalidVoiceName { voice_name });
+    }
+    let regeneration = jobs
+        .regenerating
+        .lock()
+        .unwrap()
+        .entry(voice_name.clone())
+        .or_default()
+        .clone();
+    let result = regeneration
+        .get_or_init(|| regenerate(&app_handle, &tts, &jobs, &settings, &trash, &voice_name))
+        .await
+        .clone();
+    // Whoever gets here first retires it, so the next request starts afresh
+    let mut regenerating = jobs.regenerating.lock().unwrap();
+    if regenerating
+        .get(&voice_name)
+        .is_some_and(|current| Arc::ptr_eq(current, &regeneration))
+    {
+        regenerating.remove(&voice_name);
+    }
+    result
+}
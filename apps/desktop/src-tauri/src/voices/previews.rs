@@ -0,0 +1,150 @@
+// Voice previews: the bundled or regenerated sample of each voice, and
+// synthesizing a new one when it's missing, corrupt or for another sentence
+use std::fs;
+
+use super::name::voice_language_code;
+use crate::audio::mp3;
+use crate::cache::preview::{self, PreviewCache};
+use crate::error::AppError;
+use crate::paths::roots::{AllowedRoots, Read};
+use crate::settings::SettingsStore;
+use crate::tts::fingerprint::TtsRequestParams;
+use crate::tts::lanes::Lane;
+use crate::tts::synthesis::{request_synthesis, SpeechInput};
+use crate::tts::TtsService;
+
+/// The preview of `voice_name`, time-stretched to play at `rate` (default:
+/// the preview rate of the settings) as a WAV unless that's 1×.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn get_voice_preview_audio(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+    roots: tauri::State<'_, AllowedRoots>,
+    voice_name: String,
+    full: Option<bool>,
+    rate: Option<f32>,
+) -> Result<Vec<u8>, AppError> {
+    let rate = rate.unwrap_or_else(|| crate::audio::arbitration::default_rate(&app_handle));
+    let audio = preview_audio(&app_handle, &tts, &settings, &roots, voice_name, full).await?;
+    tauri::async_runtime::spawn_blocking(move || crate::audio::stretch::at_rate(audio, rate))
+        .await
+        .map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })?
+}
+
+async fn preview_audio(
+    app_handle: &tauri::AppHandle,
+    tts: &TtsService,
+    settings: &SettingsStore,
+    roots: &AllowedRoots,
+    voice_name: String,
+    full: Option<bool>,
+) -> Result<Vec<u8>, AppError> {
+    if !crate::cache::is_voice_name(&voice_name) {
+        return Err(AppError::InvalidVoiceName { voice_name });
+    }
+    // Unless `full`, long previews are played trimmed to `max_preview_ms`
+    let max_preview_ms = match full {
+        Some(true) => 0,
+        _ => settings.get().max_preview_ms,
+    };
+    let shorten =
+        |bytes: Vec<u8>| crate::audio::trim::trim(&bytes, max_preview_ms).unwrap_or(bytes);
+    // Regenerated previews in app data take precedence over the bundled resources/preview_cache
+    let cache = crate::cache::preview_cache(app_handle)?;
+    let sentence = super::sentences::for_voice(settings, &voice_name);
+    let Some(file_path) = cache.locate(&voice_name, &sentence) else {
+        // Only the bundled sentence exists: try the configured one, and fall back to it
+        let bundled = cache
+            .locate(&voice_name, preview::BUNDLED_SENTENCE)
+            .ok_or_else(|| AppError::PreviewNotFound {
+                voice_name: voice_name.clone(),
+            })?;
+        return match regenerate_preview(tts, &cache, &voice_name, &sentence, Lane::Interactive)
+            .await
+        {
+            Ok(bytes) => Ok(shorten(bytes)),
+            Err(e) => {
+                log::warn!(
+                    "Preview for {} in its own sentence failed, using the bundled one: {}",
+                    voice_name,
+                    e
+                );
+                let bytes = fs::read(&bundled)?;
+                Ok(cache
+                    .trimmed(&bundled, &bytes, max_preview_ms)
+                    .unwrap_or(bytes))
+            }
+        };
+    };
+
+    let bytes = roots.validate_path::<Read>(&file_path)?.read()?;
+    if mp3::is_plausible_mp3(&bytes) {
+        return Ok(cache
+            .trimmed(&file_path, &bytes, max_preview_ms)
+            .unwrap_or(bytes));
+    }
+
+    log::warn!(
+        "Voice preview {} is corrupt ({} bytes), regenerating",
+        file_path.display(),
+        bytes.len()
+    );
+    if let Err(e) = fs::remove_file(&file_path) {
+        // Bundled resources may be read-only; the regenerated copy shadows them anyway
+        log::warn!(
+            "Could not delete corrupt preview {}: {}",
+            file_path.display(),
+            e
+        );
+    }
+
+    regenerate_preview(tts, &cache, &voice_name, &sentence, Lane::Interactive)
+        .await
+        .map(shorten)
+        .map_err(|e| {
+            log::error!("Regenerating preview for {} failed: {}", voice_name, e);
+            AppError::CorruptPreview {
+                voice_name: voice_name.clone(),
+            }
+        })
+}
+
+/// `voice_name` speaking `sentence`, checked to be a playable MP3.
+pub async fn synthesize_preview(
+    tts: &TtsService,
+    voice_name: &str,
+    sentence: &str,
+    lane: Lane,
+) -> Result<Vec<u8>, AppError> {
+    let params = TtsRequestParams::speech(
+        voice_name,
+        &voice_language_code(voice_name),
+        &SpeechInput::Text(sentence.to_string()),
+    );
+    let audio_content = request_synthesis(tts, &params, None, lane, None).await?;
+
+    if !mp3::is_plausible_mp3(&audio_content) {
+        return Err(AppError::CorruptPreview {
+            voice_name: voice_name.to_string(),
+        });
+    }
+    Ok(audio_content)
+}
+
+pub async fn regenerate_preview(
+    tts: &TtsService,
+    cache: &PreviewCache,
+    voice_name: &str,
+    sentence: &str,
+    lane: Lane,
+) -> Result<Vec<u8>, AppError> {
+    let audio_content = synthesize_preview(tts, voice_name, sentence, lane).await?;
+    if cache.stores() {
+        cache.store(voice_name, sentence, &audio_content)?;
+    }
+    Ok(audio_content)
+}
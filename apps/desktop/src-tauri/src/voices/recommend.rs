@@ -0,0 +1,405 @@
+// Picking voices for a description such as "a calm female German voice", for
+// the orchestrator and the voice picker's suggestions
+use gcloud_sdk::google::cloud::texttospeech::v1::{SsmlVoiceGender, Voice};
+use serde::{Deserialize, Serialize};
+
+use super::name::technology;
+use super::{natural_cmp, technology_rank};
+use crate::error::AppError;
+use crate::tts::TtsService;
+
+const DEFAULT_LIMIT: usize = 3;
+const MAX_LIMIT: usize = 20;
+
+/// Every score a candidate can earn. Language decides which voices are
+/// candidates at all (see `LanguageMatch`), so it only adds a constant.
+struct Weights {
+    exact_language: u32,
+    base_language: u32,
+    gender: u32,
+    /// For the first family a style lists; each later one earns
+    /// `style_step` less.
+    style: u32,
+    style_step: u32,
+    technology_preference: u32,
+    /// Per place in `technology_rank` above the unknown families.
+    technology_rank_step: u32,
+}
+
+const WEIGHTS: Weights = Weights {
+    exact_language: 100,
+    base_language: 60,
+    gender: 40,
+    style: 30,
+    style_step: 10,
+    technology_preference: 25,
+    technology_rank_step: 3,
+};
+
+/// Style hints and the voice families known to suit them, best first.
+const STYLES: &[(&[&str], &[&str])] = &[
+    (
+        &["news", "newscast", "announcer"],
+        &["News", "Neural2", "Wavenet"],
+    ),
+    (
+        &["narration", "audiobook", "storytelling", "documentary"],
+        &["Studio", "Neural2", "Wavenet"],
+    ),
+    (
+        &["calm", "soothing", "meditation"],
+        &["Studio", "Wavenet", "Neural2"],
+    ),
+    (
+        &["casual", "conversational", "friendly"],
+        &["Casual", "Journey", "Neural2"],
+    ),
+    (&["multilingual", "polyglot"], &["Polyglot"]),
+];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct VoiceCriteria {
+    pub language_code: String,
+    /// "female", "male" or "neutral".
+    pub gender: Option<String>,
+    pub style_hint: Option<String>,
+    /// A family such as "Neural2".
+    pub technology_preference: Option<String>,
+}
+
+/// Which rung of the fallback chain the candidates came from: voices of the
+/// exact language code, else any region of its base language. With neither
+/// the command fails rather than suggest another language.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LanguageMatch {
+    Exact,
+    BaseLanguage,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VoiceRecommendation {
+    pub voice_name: String,
+    pub language_code: String,
+    pub gender: String,
+    pub technology: String,
+    pub score: u32,
+    /// What each part of `score` was awarded for, or why it wasn't.
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VoiceRecommendations {
+    pub language_match: LanguageMatch,
+    pub recommendations: Vec<VoiceRecommendation>,
+    /// Criteria that couldn't be used, e.g. an unknown style hint.
+    pub notes: Vec<String>,
+}
+
+fn base_language(code: &str) -> &str {
+    code.split(['-', '_']).next().unwrap_or(code)
+}
+
+fn gender_name(voice: &Voice) -> String {
+    SsmlVoiceGender::try_from(voice.ssml_gender)
+        .map(|g| format!("{:?}", g))
+        .unwrap_or_else(|_| "Neutral".to_string())
+}
+
+fn style_families(hint: &str) -> Option<&'static [&'static str]> {
+    STYLES
+        .iter()
+        .find(|(hints, _)| hints.iter().any(|h| h.eq_ignore_ascii_case(hint.trim())))
+        .map(|(_, families)| *families)
+}
+
+/// The candidates for `language_code`, walking the fallback chain.
+fn candidates<'a>(
+    voices: &'a [Voice],
+    language_code: &str,
+) -> Result<(LanguageMatch, Vec<&'a Voice>), AppError> {
+    let speaks = |voice: &Voice, matches: &dyn Fn(&str) -> bool| {
+        voice.language_codes.iter().any(|code| matches(code))
+    };
+    let exact: Vec<&Voice> = voices
+        .iter()
+        .filter(|v| speaks(v, &|code| code.eq_ignore_ascii_case(language_code)))
+        .collect();
+    if !exact.is_empty() {
+        return Ok((LanguageMatch::Exact, exact));
+    }
+    let base = base_language(language_code);
+    let same_base: Vec<&Voice> = voices
+        .iter()
+        .filter(|v| speaks(v, &|code| base_language(code).eq_ignore_ascii_case(base)))
+        .collect();
+    if !same_base.is_empty() {
+        return Ok((LanguageMatch::BaseLanguage, same_base));
+    }
+    Err(AppError::NoMatchingVoice {
+        language_code: language_code.to_string(),
+    })
+}
+
+fn score(
+    voice: &Voice,
+    criteria: &VoiceCriteria,
+    language_match: LanguageMatch,
+    style: Option<&[&str]>,
+) -> VoiceRecommendation {
+    let family = technology(&voice.name);
+    let gender = gender_name(voice);
+    // (points, what they were awarded for); zero points explain a miss
+    let mut parts: Vec<(u32, String)> = Vec::new();
+
+    parts.push(match language_match {
+        LanguageMatch::Exact => (
+            WEIGHTS.exact_language,
+            format!("speaks {}", criteria.language_code),
+        ),
+        LanguageMatch::BaseLanguage => (
+            WEIGHTS.base_language,
+            format!(
+                "speaks {}, a region of {}",
+                voice.language_codes.first().map_or("", |c| c.as_str()),
+                base_language(&criteria.language_code)
+            ),
+        ),
+    });
+    if let Some(wanted) = &criteria.gender {
+        parts.push(if gender.eq_ignore_ascii_case(wanted) {
+            (WEIGHTS.gender, format!("is {}", gender))
+        } else {
+            (0, format!("is {}, not {}", gender, wanted))
+        });
+    }
+    if let Some(families) = style {
+        let hint = criteria.style_hint.as_deref().unwrap_or_default();
+        parts.push(
+            match families.iter().position(|f| f.eq_ignore_ascii_case(family)) {
+                Some(i) => (
+                    WEIGHTS.style.saturating_sub(i as u32 * WEIGHTS.style_step),
+                    format!("{} suits {}", family, hint),
+                ),
+                None => (0, format!("{} isn't known to suit {}", family, hint)),
+            },
+        );
+    }
+    if let Some(preferred) = &criteria.technology_preference {
+        if family.eq_ignore_ascii_case(preferred) {
+            parts.push((WEIGHTS.technology_preference, format!("is {}", family)));
+        }
+    }
+    let rank_points = (5 - technology_rank(family).min(5)) as u32 * WEIGHTS.technology_rank_step;
+    if rank_points > 0 {
+        parts.push((rank_points, format!("{} quality rank", family)));
+    }
+
+    VoiceRecommendation {
+        voice_name: voice.name.clone(),
+        language_code: voice.language_codes.first().cloned().unwrap_or_default(),
+        gender,
+        technology: family.to_string(),
+        score: parts.iter().map(|(points, _)| points).sum(),
+        reasons: parts
+            .iter()
+            .map(|(points, reason)| format!("+{} {}", points, reason))
+            .collect(),
+    }
+}
+
+/// The best `limit` voices for `criteria`, highest score first and by name
+/// among equal scores.
+pub fn recommend(
+    voices: &[Voice],
+    criteria: &VoiceCriteria,
+    limit: usize,
+) -> Result<VoiceRecommendations, AppError> {
+    let (language_match, candidates) = candidates(voices, &criteria.language_code)?;
+    let mut notes = Vec::new();
+    if language_match == LanguageMatch::BaseLanguage {
+        notes.push(format!(
+            "No {} voices; using other regions of the language",
+            criteria.language_code
+        ));
+    }
+    let style = criteria.style_hint.as_deref().and_then(|hint| {
+        let families = style_families(hint);
+        if families.is_none() {
+            notes.push(format!("Unknown style hint \"{}\" was ignored", hint));
+        }
+        families
+    });
+
+    let mut recommendations: Vec<VoiceRecommendation> = candidates
+        .into_iter()
+        .map(|voice| score(voice, criteria, language_match, style))
+        .collect();
+    recommendations.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then_with(|| natural_cmp(&a.voice_name, &b.voice_name))
+    });
+    recommendations.truncate(limit);
+    Ok(VoiceRecommendations {
+        language_match,
+        recommendations,
+        notes,
+    })
+}
+
+/// Scores the voice list, fetched only if not cached, against `criteria`.
+/// `limit` defaults to 3 and is capped at 20.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn recommend_voice(
+    tts: tauri::State<'_, TtsService>,
+    criteria: VoiceCriteria,
+    limit: Option<usize>,
+) -> Result<VoiceRecommendations, AppError> {
+    let voices = tts.voices(false, None).await?;
+    recommend(
+        &voices,
+        &criteria,
+        limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voice(name: &str, gender: SsmlVoiceGender) -> Voice {
+        Voice {
+            language_codes: vec![name[..5].to_string()],
+            name: name.to_string(),
+            ssml_gender: gender as i32,
+            natural_sample_rate_hertz: 24000,
+        }
+    }
+
+    fn voices() -> Vec<Voice> {
+        use SsmlVoiceGender::{Female, Male};
+        vec![
+            voice("en-US-Standard-A", Male),
+            voice("en-US-Neural2-C", Female),
+            voice("en-GB-Studio-B", Male),
+            voice("en-GB-Wavenet-A", Female),
+            voice("de-DE-Wavenet-B", Male),
+        ]
+    }
+
+    fn criteria(language_code: &str) -> VoiceCriteria {
+        VoiceCriteria {
+            language_code: language_code.to_string(),
+            gender: None,
+            style_hint: None,
+            technology_preference: None,
+        }
+    }
+
+    fn names(recommendations: &VoiceRecommendations) -> Vec<&str> {
+        recommendations
+            .recommendations
+            .iter()
+            .map(|r| r.voice_name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn takes_the_exact_language_first() {
+        let found = recommend(&voices(), &criteria("en-us"), 5).unwrap();
+        assert_eq!(found.language_match, LanguageMatch::Exact);
+        assert_eq!(names(&found), ["en-US-Neural2-C", "en-US-Standard-A"]);
+        assert!(found.notes.is_empty());
+        let best = &found.recommendations[0];
+        assert_eq!(best.score, 112);
+        assert_eq!(
+            best.reasons,
+            ["+100 speaks en-us", "+12 Neural2 quality rank"]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_other_regions_of_the_language() {
+        let found = recommend(&voices(), &criteria("en-AU"), 5).unwrap();
+        assert_eq!(found.language_match, LanguageMatch::BaseLanguage);
+        assert_eq!(
+            names(&found),
+            [
+                "en-GB-Studio-B",
+                "en-US-Neural2-C",
+                "en-GB-Wavenet-A",
+                "en-US-Standard-A"
+            ]
+        );
+        assert_eq!(
+            found.recommendations[0].reasons[0],
+            "+60 speaks en-GB, a region of en"
+        );
+        assert_eq!(found.notes.len(), 1);
+    }
+
+    #[test]
+    fn fails_rather_than_suggest_another_language() {
+        match recommend(&voices(), &criteria("fr-FR"), 5) {
+            Err(AppError::NoMatchingVoice { language_code }) => assert_eq!(language_code, "fr-FR"),
+            other => panic!(
+                "expected NoMatchingVoice, got {:?}",
+                other.map(|r| r.language_match)
+            ),
+        }
+        assert!(recommend(&[], &criteria("en-US"), 5).is_err());
+    }
+
+    #[test]
+    fn scores_gender_style_and_preference() {
+        let found = recommend(
+            &voices(),
+            &VoiceCriteria {
+                gender: Some("female".to_string()),
+                style_hint: Some("Calm".to_string()),
+                technology_preference: Some("wavenet".to_string()),
+                ..criteria("en-GB")
+            },
+            1,
+        )
+        .unwrap();
+        let best = &found.recommendations[0];
+        assert_eq!(best.voice_name, "en-GB-Wavenet-A");
+        assert_eq!(
+            best.reasons,
+            [
+                "+100 speaks en-GB",
+                "+40 is Female",
+                "+20 Wavenet suits Calm",
+                "+25 is Wavenet",
+                "+9 Wavenet quality rank"
+            ]
+        );
+        assert_eq!(best.score, 194);
+        assert_eq!(found.recommendations.len(), 1);
+
+        let unknown = recommend(
+            &voices(),
+            &VoiceCriteria {
+                style_hint: Some("spooky".to_string()),
+                ..criteria("en-GB")
+            },
+            5,
+        )
+        .unwrap();
+        assert_eq!(unknown.notes, ["Unknown style hint \"spooky\" was ignored"]);
+        assert_eq!(names(&unknown), ["en-GB-Studio-B", "en-GB-Wavenet-A"]);
+    }
+
+    #[test]
+    fn orders_equal_scores_by_name() {
+        let voices = [
+            voice("en-US-Wavenet-10", SsmlVoiceGender::Male),
+            voice("en-US-Wavenet-9", SsmlVoiceGender::Male),
+        ];
+        let found = recommend(&voices, &criteria("en-US"), 5).unwrap();
+        assert_eq!(names(&found), ["en-US-Wavenet-9", "en-US-Wavenet-10"]);
+    }
+}
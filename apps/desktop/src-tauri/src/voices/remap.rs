@@ -0,0 +1,385 @@
+// Renamed voice ids: Google retires a voice name and serves the same voice
+// under another, which would strand every project, favorite and default
+// that names the old one. The compiled-in table below holds the renames
+// Google announced; `voice_remap` in the settings adds to it and wins over
+// it. Names are followed to the end of a chain, so applying the map twice
+// changes nothing more
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use tauri::Emitter;
+
+use crate::settings::Settings;
+
+/// Retired voice ids and the ones that replaced them.
+pub const BUILTIN_REMAP: &[(&str, &str)] = &[
+    ("en-US-Journey-D", "en-US-Chirp-HD-D"),
+    ("en-US-Journey-F", "en-US-Chirp-HD-F"),
+    ("en-US-Journey-O", "en-US-Chirp-HD-O"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemapSource {
+    Builtin,
+    User,
+}
+
+/// One entry of the table `get_voice_remap` returns.
+#[derive(Debug, Clone, Serialize)]
+pub struct RemapEntry {
+    pub from: String,
+    pub to: String,
+    pub source: RemapSource,
+}
+
+/// One rewrite made.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct VoiceRemapping {
+    pub from: String,
+    pub to: String,
+}
+
+/// Payload of "voices-remapped".
+#[derive(Debug, Clone, Serialize)]
+pub struct VoicesRemapped {
+    /// What was rewritten: "settings", "project", "synthesis" or "batch".
+    pub source: &'static str,
+    pub changes: Vec<VoiceRemapping>,
+}
+
+/// The built-in renames overlaid with the user's.
+#[derive(Debug, Clone, Default)]
+pub struct VoiceRemap {
+    entries: BTreeMap<String, (String, RemapSource)>,
+}
+
+impl VoiceRemap {
+    pub fn new(user: &BTreeMap<String, String>) -> Self {
+        let mut entries: BTreeMap<String, (String, RemapSource)> = BUILTIN_REMAP
+            .iter()
+            .map(|(from, to)| (from.to_string(), (to.to_string(), RemapSource::Builtin)))
+            .collect();
+        for (from, to) in user {
+            let (from, to) = (from.trim(), to.trim());
+            if from.is_empty() {
+                continue;
+            }
+            // An empty target cancels a built-in rename
+            if to.is_empty() || to == from {
+                entries.remove(from);
+            } else {
+                entries.insert(from.to_string(), (to.to_string(), RemapSource::User));
+            }
+        }
+        Self { entries }
+    }
+
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self::new(&settings.voice_remap)
+    }
+
+    /// The id `voice_name` is served under now, following renames of
+    /// renames; `None` if it wasn't renamed or the chain loops.
+    pub fn resolve(&self, voice_name: &str) -> Option<String> {
+        let mut seen = BTreeSet::new();
+        let mut current = voice_name;
+        while let Some((next, _)) = self.entries.get(current) {
+            if !seen.insert(current) {
+                log::warn!("Voice remap loops through {}; ignoring it", voice_name);
+                return None;
+            }
+            current = next;
+        }
+        (current != voice_name).then(|| current.to_string())
+    }
+
+    pub fn entries(&self) -> Vec<RemapEntry> {
+        self.entries
+            .iter()
+            .map(|(from, (to, source))| RemapEntry {
+                from: from.clone(),
+                to: to.clone(),
+                source: *source,
+            })
+            .collect()
+    }
+
+    /// Rewrites the favorites, the default voice and the voice presets,
+    /// dropping favorites that become duplicates.
+    pub fn apply_to_settings(&self, settings: &mut Settings) -> Vec<VoiceRemapping> {
+        let mut changes = BTreeSet::new();
+        let mut favorites: Vec<String> = Vec::with_capacity(settings.favorite_voices.len());
+        for favorite in &settings.favorite_voices {
+            let to = match self.resolve(favorite) {
+                Some(to) => {
+                    changes.insert(VoiceRemapping {
+                        from: favorite.clone(),
+                        to: to.clone(),
+                    });
+                    to
+                }
+                None => favorite.clone(),
+            };
+            if !favorites.contains(&to) {
+                favorites.push(to);
+            }
+        }
+        settings.favorite_voices = favorites;
+        if let Some(default_voice) = &settings.default_voice {
+            if let Some(to) = self.resolve(default_voice) {
+                changes.insert(VoiceRemapping {
+                    from: default_voice.clone(),
+                    to: to.clone(),
+                });
+                settings.default_voice = Some(to);
+            }
+        }
+        // A preset already under the new name is the newer one and stays
+        let presets = std::mem::take(&mut settings.voice_presets);
+        for (voice, preset) in presets {
+            match self.resolve(&voice) {
+                Some(to) => {
+                    changes.insert(VoiceRemapping {
+                        from: voice,
+                        to: to.clone(),
+                    });
+                    settings.voice_presets.entry(to).or_insert(preset);
+                }
+                None => {
+                    settings.voice_presets.insert(voice, preset);
+                }
+            }
+        }
+        changes.into_iter().collect()
+    }
+
+    /// Rewrites every string in `value` that is exactly a renamed voice id.
+    pub fn apply_to_value(&self, value: &mut serde_json::Value) -> Vec<VoiceRemapping> {
+        let mut found = BTreeMap::new();
+        crate::project::visit_strings(value, &mut |s| {
+            if !found.contains_key(s) {
+                if let Some(to) = self.resolve(s) {
+                    found.insert(s.to_string(), to);
+                }
+            }
+        });
+        if found.is_empty() {
+            return Vec::new();
+        }
+        crate::project::rewrite_strings(value, &found);
+        found
+            .into_iter()
+            .map(|(from, to)| VoiceRemapping { from, to })
+            .collect()
+    }
+}
+
+/// Emits "voices-remapped" for `changes`, if there are any.
+pub fn announce(app_handle: &tauri::AppHandle, source: &'static str, changes: Vec<VoiceRemapping>) {
+    if changes.is_empty() {
+        return;
+    }
+    log::info!(
+        "Remapped renamed voices in {}: {}",
+        source,
+        changes
+            .iter()
+            .map(|c| format!("{} -> {}", c.from, c.to))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    if let Err(e) = app_handle.emit("voices-remapped", VoicesRemapped { source, changes }) {
+        log::warn!("Failed to emit voices-remapped: {}", e);
+    }
+}
+
+/// The rename table in effect, built-in entries the user overrode left out.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_voice_remap(
+    settings: tauri::State<'_, crate::settings::SettingsStore>,
+) -> Vec<RemapEntry> {
+    VoiceRemap::from_settings(&settings.get()).entries()
+}
+
+/// Payload returned by `remap_project_voices`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RemappedProject {
+    pub project: serde_json::Value,
+    pub changes: Vec<VoiceRemapping>,
+}
+
+/// `project` with renamed voice ids rewritten, for the frontend to apply
+/// when it loads a project; announces the changes with "voices-remapped".
+/// The autosave and the next save then keep the new ids.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn remap_project_voices(
+    app_handle: tauri::AppHandle,
+    settings: tauri::State<'_, crate::settings::SettingsStore>,
+    mut project: serde_json::Value,
+) -> RemappedProject {
+    let changes = VoiceRemap::from_settings(&settings.get()).apply_to_value(&mut project);
+    announce(&app_handle, "project", changes.clone());
+    RemappedProject { project, changes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voices::shortlist::VoicePreset;
+    use serde_json::json;
+
+    fn user(entries: &[(&str, &str)]) -> VoiceRemap {
+        VoiceRemap::new(
+            &entries
+                .iter()
+                .map(|(from, to)| (from.to_string(), to.to_string()))
+                .collect(),
+        )
+    }
+
+    fn change(from: &str, to: &str) -> VoiceRemapping {
+        VoiceRemapping {
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    #[test]
+    fn the_table_lists_every_rename_with_its_source() {
+        let remap = user(&[("en-GB-Old-A", "en-GB-New-A")]);
+        let entries = serde_json::to_value(remap.entries()).unwrap();
+        assert_eq!(
+            entries,
+            json!([
+                { "from": "en-GB-Old-A", "to": "en-GB-New-A", "source": "user" },
+                { "from": "en-US-Journey-D", "to": "en-US-Chirp-HD-D", "source": "builtin" },
+                { "from": "en-US-Journey-F", "to": "en-US-Chirp-HD-F", "source": "builtin" },
+                { "from": "en-US-Journey-O", "to": "en-US-Chirp-HD-O", "source": "builtin" },
+            ])
+        );
+    }
+
+    #[test]
+    fn user_entries_win_over_builtin_ones() {
+        let remap = user(&[
+            ("en-US-Journey-D", "en-US-Chirp3-HD-D"),
+            (" en-US-Journey-F ", ""),
+            ("en-US-Journey-O", "en-US-Journey-O"),
+            ("", "en-US-Standard-A"),
+        ]);
+        assert_eq!(
+            remap.resolve("en-US-Journey-D").as_deref(),
+            Some("en-US-Chirp3-HD-D")
+        );
+        // An empty target, or the name itself, cancels the built-in rename
+        assert_eq!(remap.resolve("en-US-Journey-F"), None);
+        assert_eq!(remap.resolve("en-US-Journey-O"), None);
+        let entries = remap.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].source, RemapSource::User);
+    }
+
+    #[test]
+    fn renames_of_renames_are_followed_and_loops_ignored() {
+        let remap = user(&[
+            ("en-US-Chirp-HD-D", "en-US-Chirp3-HD-D"),
+            ("a", "b"),
+            ("b", "c"),
+            ("c", "a"),
+            ("x", "y"),
+            ("y", "y2"),
+            ("y2", "y"),
+        ]);
+        assert_eq!(
+            remap.resolve("en-US-Journey-D").as_deref(),
+            Some("en-US-Chirp3-HD-D")
+        );
+        assert_eq!(remap.resolve("a"), None);
+        // Into a loop further along the chain
+        assert_eq!(remap.resolve("x"), None);
+        assert_eq!(remap.resolve("en-US-Standard-C"), None);
+    }
+
+    #[test]
+    fn applying_the_map_twice_changes_nothing_more() {
+        let remap = user(&[("en-US-Chirp-HD-F", "en-US-Chirp3-HD-F")]);
+        let mut settings = Settings {
+            favorite_voices: vec![
+                "en-US-Journey-F".to_string(),
+                "en-US-Chirp3-HD-F".to_string(),
+                "en-US-Standard-C".to_string(),
+            ],
+            default_voice: Some("en-US-Journey-D".to_string()),
+            ..Settings::default()
+        };
+        let old = VoicePreset {
+            speaking_rate: Some(0.9),
+            ..VoicePreset::default()
+        };
+        let new = VoicePreset {
+            speaking_rate: Some(1.1),
+            ..VoicePreset::default()
+        };
+        settings
+            .voice_presets
+            .insert("en-US-Journey-F".to_string(), old);
+        settings
+            .voice_presets
+            .insert("en-US-Chirp3-HD-F".to_string(), new);
+
+        assert_eq!(
+            remap.apply_to_settings(&mut settings),
+            vec![
+                change("en-US-Journey-D", "en-US-Chirp-HD-D"),
+                change("en-US-Journey-F", "en-US-Chirp3-HD-F"),
+            ]
+        );
+        // The duplicate favorite is dropped, the newer preset kept
+        assert_eq!(
+            settings.favorite_voices,
+            vec!["en-US-Chirp3-HD-F", "en-US-Standard-C"]
+        );
+        assert_eq!(settings.default_voice.as_deref(), Some("en-US-Chirp-HD-D"));
+        assert_eq!(settings.voice_presets.len(), 1);
+        assert_eq!(
+            settings.voice_presets["en-US-Chirp3-HD-F"].speaking_rate,
+            Some(1.1)
+        );
+
+        let once = serde_json::to_value(&settings).unwrap();
+        assert!(remap.apply_to_settings(&mut settings).is_empty());
+        assert_eq!(serde_json::to_value(&settings).unwrap(), once);
+    }
+
+    #[test]
+    fn only_whole_voice_ids_are_rewritten_in_a_project() {
+        let remap = VoiceRemap::new(&BTreeMap::new());
+        let mut project = json!({
+            "voice": "en-US-Journey-O",
+            "segments": [
+                { "voice_name": "en-US-Journey-O", "text": "The en-US-Journey-O voice" },
+                { "voice_name": "en-US-Journey-D" },
+            ],
+        });
+        assert_eq!(
+            remap.apply_to_value(&mut project),
+            vec![
+                change("en-US-Journey-D", "en-US-Chirp-HD-D"),
+                change("en-US-Journey-O", "en-US-Chirp-HD-O"),
+            ]
+        );
+        assert_eq!(
+            project,
+            json!({
+                "voice": "en-US-Chirp-HD-O",
+                "segments": [
+                    { "voice_name": "en-US-Chirp-HD-O", "text": "The en-US-Journey-O voice" },
+                    { "voice_name": "en-US-Chirp-HD-D" },
+                ],
+            })
+        );
+        assert!(remap.apply_to_value(&mut project).is_empty());
+    }
+}
@@ -0,0 +1,215 @@
+// Sentences voice previews speak, in the voice's own language, with user
+// overrides kept in settings
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::cache::preview::BUNDLED_SENTENCE;
+use crate::error::AppError;
+use crate::settings::SettingsStore;
+
+/// Overrides are spoken once per voice; the cap keeps that cheap.
+pub const MAX_SENTENCE_CHARS: usize = 120;
+
+/// Built-in sentences by base language. English is the bundled sentence so
+/// English voices keep using the previews shipped with the app.
+const BUILTIN: &[(&str, &str)] = &[
+    ("ar", "مرحبًا، هذه معاينة لصوتي. أتمنى أن يعجبك!"),
+    ("cmn", "你好，这是我的声音预览。希望你喜欢！"),
+    (
+        "cs",
+        "Dobrý den, toto je ukázka mého hlasu. Doufám, že se vám líbí!",
+    ),
+    (
+        "da",
+        "Hej, dette er en prøve på min stemme. Jeg håber, du kan lide den!",
+    ),
+    (
+        "de",
+        "Hallo, das ist eine Vorschau meiner Stimme. Ich hoffe, sie gefällt dir!",
+    ),
+    ("en", BUNDLED_SENTENCE),
+    (
+        "es",
+        "Hola, esta es una muestra de mi voz. ¡Espero que te guste cómo sueno!",
+    ),
+    (
+        "fi",
+        "Hei, tämä on näyte äänestäni. Toivottavasti pidät siitä!",
+    ),
+    (
+        "fr",
+        "Bonjour, voici un aperçu de ma voix. J'espère qu'elle vous plaît !",
+    ),
+    ("hi", "नमस्ते, यह मेरी आवाज़ का एक नमूना है। आशा है आपको पसंद आएगी!"),
+    (
+        "id",
+        "Halo, ini adalah contoh suara saya. Semoga Anda menyukainya!",
+    ),
+    (
+        "it",
+        "Ciao, questa è un'anteprima della mia voce. Spero che ti piaccia!",
+    ),
+    (
+        "ja",
+        "こんにちは、これは私の声のサンプルです。気に入っていただけると嬉しいです。",
+    ),
+    (
+        "ko",
+        "안녕하세요, 제 목소리 미리듣기입니다. 마음에 드셨으면 좋겠어요!",
+    ),
+    (
+        "nb",
+        "Hei, dette er en prøve på stemmen min. Jeg håper du liker den!",
+    ),
+    (
+        "nl",
+        "Hallo, dit is een voorbeeld van mijn stem. Ik hoop dat je hem mooi vindt!",
+    ),
+    (
+        "pl",
+        "Cześć, to jest próbka mojego głosu. Mam nadzieję, że ci się podoba!",
+    ),
+    (
+        "pt",
+        "Olá, esta é uma amostra da minha voz. Espero que goste de como eu soo!",
+    ),
+    (
+        "ru",
+        "Здравствуйте, это образец моего голоса. Надеюсь, он вам понравится!",
+    ),
+    (
+        "sv",
+        "Hej, det här är ett smakprov på min röst. Jag hoppas att du gillar den!",
+    ),
+    ("tr", "Merhaba, bu sesimin bir örneği. Umarım beğenirsiniz!"),
+    (
+        "uk",
+        "Привіт, це зразок мого голосу. Сподіваюся, він вам сподобається!",
+    ),
+    (
+        "vi",
+        "Xin chào, đây là bản nghe thử giọng của tôi. Hy vọng bạn sẽ thích!",
+    ),
+    ("yue", "你好，呢個係我把聲嘅試聽。希望你鍾意！"),
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PreviewSentence {
+    /// A base language ("ja") or a full code ("pt-BR").
+    pub language_code: String,
+    pub text: String,
+    pub is_override: bool,
+}
+
+fn base_language(language_code: &str) -> String {
+    match language_code.split(['-', '_']).next().unwrap_or_default() {
+        "zh" => "cmn".to_string(),
+        "no" => "nb".to_string(),
+        base => base.to_ascii_lowercase(),
+    }
+}
+
+fn builtin(base: &str) -> Option<&'static str> {
+    BUILTIN.iter().find(|(b, _)| *b == base).map(|(_, s)| *s)
+}
+
+/// The sentence for a voice of `language_code`: an override for the exact
+/// code, then one for its base language, then the built-in sentence, then
+/// English.
+pub fn sentence_for(overrides: &BTreeMap<String, String>, language_code: &str) -> String {
+    let base = base_language(language_code);
+    let find = |code: &str| {
+        overrides
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(code))
+            .map(|(_, v)| v.clone())
+    };
+    find(language_code)
+        .or_else(|| find(&base))
+        .or_else(|| builtin(&base).map(str::to_string))
+        .unwrap_or_else(|| BUNDLED_SENTENCE.to_string())
+}
+
+/// `sentence_for` the language of `voice_name` with the current settings.
+pub fn for_voice(settings: &SettingsStore, voice_name: &str) -> String {
+    sentence_for(
+        &settings.get().preview_sentences,
+        &super::name::voice_language_code(voice_name),
+    )
+}
+
+fn valid_language_code(code: &str) -> bool {
+    (2..=35).contains(&code.len())
+        && code
+            .split('-')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// The built-in sentences with the overrides applied, by language code.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_preview_sentences(settings: tauri::State<'_, SettingsStore>) -> Vec<PreviewSentence> {
+    let mut sentences: BTreeMap<String, PreviewSentence> = BUILTIN
+        .iter()
+        .map(|(code, text)| {
+            let sentence = PreviewSentence {
+                language_code: code.to_string(),
+                text: text.to_string(),
+                is_override: false,
+            };
+            (code.to_string(), sentence)
+        })
+        .collect();
+    for (code, text) in settings.get().preview_sentences {
+        let sentence = PreviewSentence {
+            language_code: code.clone(),
+            text,
+            is_override: true,
+        };
+        sentences.insert(code.to_ascii_lowercase(), sentence);
+    }
+    sentences.into_values().collect()
+}
+
+/// Sets the preview sentence of `language_code`, a base language or a full
+/// code; empty `text` goes back to the built-in one. Previews already
+/// generated for the old sentence are no longer used.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn set_preview_sentence(
+    settings: tauri::State<'_, SettingsStore>,
+    language_code: String,
+    text: String,
+) -> Result<PreviewSentence, AppError> {
+    let invalid = |reason: &str| AppError::InvalidPreviewSentence {
+        language_code: language_code.clone(),
+        reason: reason.to_string(),
+    };
+    if !valid_language_code(&language_code) {
+        return Err(invalid("not a language code"));
+    }
+    let text = text.trim().to_string();
+    if text.chars().count() > MAX_SENTENCE_CHARS {
+        return Err(invalid(&format!(
+            "longer than {} characters",
+            MAX_SENTENCE_CHARS
+        )));
+    }
+    if text.contains(['\n', '\r']) {
+        return Err(invalid("must be a single line"));
+    }
+
+    let updated = settings.update(|s| {
+        s.preview_sentences
+            .retain(|code, _| !code.eq_ignore_ascii_case(&language_code));
+        if !text.is_empty() {
+            s.preview_sentences
+                .insert(language_code.clone(), text.clone());
+        }
+    })?;
+    Ok(PreviewSentence {
+        text: sentence_for(&updated.preview_sentences, &language_code),
+        is_override: !text.is_empty(),
+        language_code,
+    })
+}
@@ -0,0 +1,441 @@
+// Team-shared voice shortlists: the favorites, per-voice presets and default
+// voice as a small versioned JSON file, so a curated list of approved voices
+// is imported once instead of re-favorited by hand. Readers ignore fields
+// they don't know, so a newer app's file still imports; what it adds is lost
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tauri::Emitter;
+
+use super::remap::VoiceRemap;
+use crate::error::AppError;
+use crate::paths::roots::{AllowedRoots, Read, Write};
+use crate::read_only::WriteAccess;
+use crate::settings::{Settings, SettingsStore};
+use crate::tts::TtsService;
+
+/// Written into exported files; older files are read as this version too.
+const FORMAT_VERSION: u32 = 1;
+/// Files larger than this aren't shortlists.
+const MAX_FILE_BYTES: usize = 1024 * 1024;
+
+/// What the voice picker applies when a voice is chosen.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VoicePreset {
+    /// Name of an output preset; see `audio::presets`.
+    pub output_preset: Option<String>,
+    pub speaking_rate: Option<f64>,
+    pub pitch: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Shortlist {
+    pub version: u32,
+    pub favorites: Vec<String>,
+    /// By voice name.
+    pub presets: BTreeMap<String, VoicePreset>,
+    pub default_voice: Option<String>,
+}
+
+impl Shortlist {
+    fn from_settings(settings: &Settings) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            favorites: settings.favorite_voices.clone(),
+            presets: settings.voice_presets.clone(),
+            default_voice: settings.default_voice.clone(),
+        }
+    }
+}
+
+/// A voice whose local preset was kept over the imported one.
+#[derive(Debug, Clone, Serialize)]
+pub struct PresetConflict {
+    pub voice_name: String,
+    pub local: VoicePreset,
+    pub imported: VoicePreset,
+}
+
+/// What `import_voice_shortlist` changed, and the payload of
+/// "voice-shortlist-imported".
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ShortlistImport {
+    pub merged: bool,
+    pub favorites_added: Vec<String>,
+    pub favorites_removed: Vec<String>,
+    pub presets_added: Vec<String>,
+    pub presets_replaced: Vec<String>,
+    pub presets_removed: Vec<String>,
+    /// Same voice, different presets, with the local one kept in a merge.
+    pub preset_conflicts: Vec<PresetConflict>,
+    pub default_voice_changed: Option<String>,
+    /// Voices not in the cached voice list, left out of the import.
+    pub unknown_voices: Vec<String>,
+    /// False when no voice list was cached, so nothing was left out.
+    pub voices_checked: bool,
+}
+
+/// Applies `shortlist` to `settings`. A merge adds what's missing and keeps
+/// local presets and the local default voice where both have one; without
+/// `merge` the imported values replace the local ones.
+fn apply(settings: &mut Settings, shortlist: Shortlist, merge: bool) -> ShortlistImport {
+    let mut report = ShortlistImport {
+        merged: merge,
+        ..ShortlistImport::default()
+    };
+
+    let favorites = if merge {
+        let mut favorites = settings.favorite_voices.clone();
+        for voice in shortlist.favorites {
+            if !favorites.contains(&voice) {
+                favorites.push(voice);
+            }
+        }
+        favorites
+    } else {
+        let mut favorites = Vec::with_capacity(shortlist.favorites.len());
+        for voice in shortlist.favorites {
+            if !favorites.contains(&voice) {
+                favorites.push(voice);
+            }
+        }
+        favorites
+    };
+    report.favorites_added = favorites
+        .iter()
+        .filter(|v| !settings.favorite_voices.contains(v))
+        .cloned()
+        .collect();
+    report.favorites_removed = settings
+        .favorite_voices
+        .iter()
+        .filter(|v| !favorites.contains(v))
+        .cloned()
+        .collect();
+    settings.favorite_voices = favorites;
+
+    let mut presets = if merge {
+        settings.voice_presets.clone()
+    } else {
+        BTreeMap::new()
+    };
+    for (voice, imported) in shortlist.presets {
+        match settings.voice_presets.get(&voice) {
+            None => report.presets_added.push(voice.clone()),
+            Some(local) if *local == imported => {}
+            Some(local) if merge => {
+                report.preset_conflicts.push(PresetConflict {
+                    voice_name: voice,
+                    local: local.clone(),
+                    imported,
+                });
+                continue;
+            }
+            Some(_) => report.presets_replaced.push(voice.clone()),
+        }
+        presets.insert(voice, imported);
+    }
+    report.presets_removed = settings
+        .voice_presets
+        .keys()
+        .filter(|v| !presets.contains_key(*v))
+        .cloned()
+        .collect();
+    settings.voice_presets = presets;
+
+    let default_voice = match (merge, &settings.default_voice) {
+        (true, Some(_)) => settings.default_voice.clone(),
+        _ => shortlist.default_voice,
+    };
+    if default_voice != settings.default_voice {
+        report.default_voice_changed = default_voice.clone();
+        settings.default_voice = default_voice;
+    }
+    report
+}
+
+/// The shortlist in `bytes`, read from `path`.
+fn parse(path: &Path, bytes: &[u8]) -> Result<Shortlist, AppError> {
+    let invalid = |message: String| AppError::InvalidShortlist {
+        path: path.display().to_string(),
+        message,
+    };
+    if bytes.len() > MAX_FILE_BYTES {
+        return Err(invalid(format!(
+            "{} bytes is too large for a voice shortlist",
+            bytes.len()
+        )));
+    }
+    let shortlist: Shortlist = serde_json::from_slice(bytes).map_err(|e| invalid(e.to_string()))?;
+    if shortlist.version == 0 {
+        return Err(invalid("not a voice shortlist: no version".to_string()));
+    }
+    if shortlist.version > FORMAT_VERSION {
+        log::info!(
+            "Reading version {} voice shortlist {} as version {}",
+            shortlist.version,
+            path.display(),
+            FORMAT_VERSION
+        );
+    }
+    Ok(shortlist)
+}
+
+/// Writes the favorites, voice presets and default voice to `path`.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn export_voice_shortlist(
+    settings: tauri::State<'_, SettingsStore>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    path: PathBuf,
+) -> Result<Shortlist, AppError> {
+    access.check()?;
+    let path = roots.validate_path::<Write>(&path)?;
+    let shortlist = Shortlist::from_settings(&settings.get());
+    let json = serde_json::to_vec_pretty(&shortlist).map_err(|e| AppError::Io {
+        message: e.to_string(),
+    })?;
+    path.write_atomic(&json)?;
+    Ok(shortlist)
+}
+
+/// Reads a shortlist from `path` into the settings, merging it into the
+/// local one with `merge` or replacing it otherwise. Renamed voices are
+/// followed; voices missing from the cached voice list are reported and
+/// left out.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn import_voice_shortlist(
+    app_handle: tauri::AppHandle,
+    tts: tauri::State<'_, TtsService>,
+    settings: tauri::State<'_, SettingsStore>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    path: PathBuf,
+    merge: bool,
+) -> Result<ShortlistImport, AppError> {
+    access.check()?;
+    let file = roots.validate_path::<Read>(&path)?;
+    let mut shortlist = parse(&path, &file.read()?)?;
+
+    let remap = VoiceRemap::from_settings(&settings.get());
+    let rename = |voice: String| remap.resolve(&voice).unwrap_or(voice);
+    shortlist.favorites = shortlist.favorites.into_iter().map(rename).collect();
+    shortlist.presets = shortlist
+        .presets
+        .into_iter()
+        .map(|(voice, preset)| (rename(voice), preset))
+        .collect();
+    shortlist.default_voice = shortlist.default_voice.map(rename);
+
+    let voices = tts.cached_voices().await;
+    let mut unknown = Vec::new();
+    if let Some(voices) = &voices {
+        let mut known = |voice: &String| {
+            let found = crate::tts::native::voice_id(voice).is_some()
+                || voices.iter().any(|v| v.name == *voice);
+            if !found && !unknown.contains(voice) {
+                unknown.push(voice.clone());
+            }
+            found
+        };
+        shortlist.favorites.retain(&mut known);
+        shortlist.presets.retain(|voice, _| known(voice));
+        shortlist.default_voice = shortlist.default_voice.filter(&mut known);
+    }
+
+    let mut report = ShortlistImport::default();
+    settings.update(|settings| report = apply(settings, shortlist, merge))?;
+    report.unknown_voices = unknown;
+    report.voices_checked = voices.is_some();
+    log::info!(
+        "Imported voice shortlist {}: {} favorite(s) added, {} preset conflict(s), {} unknown voice(s)",
+        path.display(),
+        report.favorites_added.len(),
+        report.preset_conflicts.len(),
+        report.unknown_voices.len()
+    );
+    if let Err(e) = app_handle.emit("voice-shortlist-imported", &report) {
+        log::warn!("Failed to emit voice-shortlist-imported: {}", e);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preset(rate: f64) -> VoicePreset {
+        VoicePreset {
+            output_preset: Some("podcast".to_string()),
+            speaking_rate: Some(rate),
+            pitch: None,
+        }
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    /// Local settings and a shortlist that disagree on one preset and the
+    /// default voice.
+    fn conflicting() -> (Settings, Shortlist) {
+        let local = Settings {
+            favorite_voices: names(&["en-US-Studio-O", "en-GB-Neural2-B"]),
+            voice_presets: BTreeMap::from([
+                ("en-US-Studio-O".to_string(), preset(1.1)),
+                ("en-GB-Neural2-B".to_string(), preset(0.9)),
+            ]),
+            default_voice: Some("en-US-Studio-O".to_string()),
+            ..Settings::default()
+        };
+        let shortlist = Shortlist {
+            version: FORMAT_VERSION,
+            favorites: names(&["en-US-Studio-Q", "en-US-Studio-O", "en-US-Studio-Q"]),
+            presets: BTreeMap::from([
+                ("en-US-Studio-O".to_string(), preset(1.3)),
+                ("en-US-Studio-Q".to_string(), preset(1.0)),
+            ]),
+            default_voice: Some("en-US-Studio-Q".to_string()),
+        };
+        (local, shortlist)
+    }
+
+    fn parsed(json: &str) -> Result<Shortlist, AppError> {
+        parse(Path::new("team.json"), json.as_bytes())
+    }
+
+    #[test]
+    fn an_export_imports_back_unchanged() {
+        let (local, _) = conflicting();
+        let json = serde_json::to_vec_pretty(&Shortlist::from_settings(&local)).unwrap();
+        let shortlist = parse(Path::new("team.json"), &json).unwrap();
+        assert_eq!(shortlist.version, FORMAT_VERSION);
+
+        let mut fresh = Settings::default();
+        let report = apply(&mut fresh, shortlist, false);
+        assert_eq!(fresh.favorite_voices, local.favorite_voices);
+        assert_eq!(fresh.voice_presets, local.voice_presets);
+        assert_eq!(fresh.default_voice, local.default_voice);
+        assert_eq!(report.favorites_added, local.favorite_voices);
+        assert_eq!(
+            report.presets_added,
+            names(&["en-GB-Neural2-B", "en-US-Studio-O"])
+        );
+        assert_eq!(report.default_voice_changed, local.default_voice);
+
+        // Importing it again over itself changes nothing
+        let shortlist = parse(Path::new("team.json"), &json).unwrap();
+        let report = apply(&mut fresh, shortlist, true);
+        assert!(report.favorites_added.is_empty());
+        assert!(report.presets_added.is_empty());
+        assert!(report.preset_conflicts.is_empty());
+        assert_eq!(report.default_voice_changed, None);
+    }
+
+    #[test]
+    fn reads_newer_files_and_refuses_what_is_not_a_shortlist() {
+        let newer = parsed(
+            r#"{"version": 7, "favorites": ["en-US-Studio-O"], "tags": {"a": 1},
+                "presets": {"en-US-Studio-O": {"pitch": -2.0, "effects": []}}}"#,
+        )
+        .unwrap();
+        assert_eq!(newer.favorites, names(&["en-US-Studio-O"]));
+        assert_eq!(newer.presets["en-US-Studio-O"].pitch, Some(-2.0));
+        assert_eq!(newer.default_voice, None);
+
+        assert!(matches!(
+            parsed(r#"{"favorites": []}"#),
+            Err(AppError::InvalidShortlist { .. })
+        ));
+        assert!(matches!(
+            parsed("[1, 2]"),
+            Err(AppError::InvalidShortlist { .. })
+        ));
+        let huge = format!(
+            r#"{{"version": 1, "favorites": ["{}"]}}"#,
+            "x".repeat(MAX_FILE_BYTES)
+        );
+        assert!(matches!(
+            parsed(&huge),
+            Err(AppError::InvalidShortlist { .. })
+        ));
+    }
+
+    #[test]
+    fn a_merge_keeps_the_local_preset_and_default_voice() {
+        let (mut settings, shortlist) = conflicting();
+        let report = apply(&mut settings, shortlist, true);
+
+        assert!(report.merged);
+        assert_eq!(
+            settings.favorite_voices,
+            names(&["en-US-Studio-O", "en-GB-Neural2-B", "en-US-Studio-Q"])
+        );
+        assert_eq!(report.favorites_added, names(&["en-US-Studio-Q"]));
+        assert!(report.favorites_removed.is_empty());
+
+        assert_eq!(settings.voice_presets["en-US-Studio-O"], preset(1.1));
+        assert_eq!(settings.voice_presets["en-US-Studio-Q"], preset(1.0));
+        assert_eq!(settings.voice_presets["en-GB-Neural2-B"], preset(0.9));
+        assert_eq!(report.presets_added, names(&["en-US-Studio-Q"]));
+        assert!(report.presets_replaced.is_empty());
+        assert!(report.presets_removed.is_empty());
+        assert_eq!(report.preset_conflicts.len(), 1);
+        let conflict = &report.preset_conflicts[0];
+        assert_eq!(conflict.voice_name, "en-US-Studio-O");
+        assert_eq!(conflict.local, preset(1.1));
+        assert_eq!(conflict.imported, preset(1.3));
+
+        assert_eq!(settings.default_voice.as_deref(), Some("en-US-Studio-O"));
+        assert_eq!(report.default_voice_changed, None);
+    }
+
+    #[test]
+    fn a_merge_takes_the_imported_default_when_there_is_none() {
+        let (mut settings, shortlist) = conflicting();
+        settings.default_voice = None;
+        let report = apply(&mut settings, shortlist, true);
+        assert_eq!(settings.default_voice.as_deref(), Some("en-US-Studio-Q"));
+        assert_eq!(
+            report.default_voice_changed.as_deref(),
+            Some("en-US-Studio-Q")
+        );
+    }
+
+    #[test]
+    fn a_replace_takes_the_imported_values() {
+        let (mut settings, shortlist) = conflicting();
+        let report = apply(&mut settings, shortlist, false);
+
+        assert!(!report.merged);
+        // Deduplicated, in the file's order
+        assert_eq!(
+            settings.favorite_voices,
+            names(&["en-US-Studio-Q", "en-US-Studio-O"])
+        );
+        assert_eq!(report.favorites_added, names(&["en-US-Studio-Q"]));
+        assert_eq!(report.favorites_removed, names(&["en-GB-Neural2-B"]));
+
+        assert_eq!(
+            settings.voice_presets,
+            BTreeMap::from([
+                ("en-US-Studio-O".to_string(), preset(1.3)),
+                ("en-US-Studio-Q".to_string(), preset(1.0)),
+            ])
+        );
+        assert_eq!(report.presets_added, names(&["en-US-Studio-Q"]));
+        assert_eq!(report.presets_replaced, names(&["en-US-Studio-O"]));
+        assert_eq!(report.presets_removed, names(&["en-GB-Neural2-B"]));
+        assert!(report.preset_conflicts.is_empty());
+
+        assert_eq!(settings.default_voice.as_deref(), Some("en-US-Studio-Q"));
+        assert_eq!(
+            report.default_voice_changed.as_deref(),
+            Some("en-US-Studio-Q")
+        );
+    }
+}
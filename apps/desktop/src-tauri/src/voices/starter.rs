@@ -0,0 +1,113 @@
+// The starter snapshot bundled with the installer: a voice list and a
+// preview for each of the most used languages, in the runtime caches' own
+// formats, so a first launch shows a populated voice picker before any
+// network call. The `starter-snapshot-v1` migration copies it in; the list
+// then reports `from_cache` until the background refresh replaces it
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use super::{name, sentences, technology_rank};
+use crate::cache::preview::PreviewCache;
+use crate::cache::write_atomic;
+use crate::error::AppError;
+use crate::paths::roots::{AllowedRoots, Write};
+use crate::tts::lanes::Lane;
+use crate::tts::voice_list::{self, StoredVoiceList};
+use crate::tts::TtsService;
+
+/// Directory of the snapshot in the resources.
+pub const SNAPSHOT_DIR: &str = "starter_snapshot";
+/// Directory of the previews inside it, laid out like the writable preview
+/// cache.
+pub const PREVIEWS_DIR: &str = "previews";
+
+/// Languages the snapshot has a preview for when none are given.
+pub const STARTER_LANGUAGES: &[&str] = &[
+    "en-US", "en-GB", "es-ES", "es-US", "fr-FR", "de-DE", "it-IT", "pt-BR", "ja-JP", "ko-KR",
+    "cmn-CN", "hi-IN",
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StarterSnapshotReport {
+    pub output_dir: PathBuf,
+    pub fetched_at: DateTime<Utc>,
+    pub voices: usize,
+    /// Voice names whose preview was written.
+    pub previews: Vec<String>,
+    /// Languages without a preview: no voice, or synthesis failed.
+    pub failed: Vec<String>,
+}
+
+/// The voice of `language_code` a new user most likely wants to hear: the
+/// best technology the voice picker lists, then the first by name.
+fn pick_voice<'a>(voices: &'a [String], language_code: &str) -> Option<&'a String> {
+    let rank = |v: &str| technology_rank(name::parse(v, Some(language_code)).technology);
+    voices
+        .iter()
+        .filter(|v| super::name::voice_language_code(v).eq_ignore_ascii_case(language_code))
+        // Neural2 to Standard; Studio and newer families aren't listed
+        .filter(|v| (1..=4).contains(&rank(v)))
+        .min_by(|a, b| rank(a).cmp(&rank(b)).then_with(|| super::natural_cmp(a, b)))
+}
+
+/// Writes a starter snapshot of a freshly fetched voice list and previews
+/// of `languages` (default `STARTER_LANGUAGES`) to `output_dir`, to be
+/// copied to `resources/starter_snapshot` when building an installer.
+/// Previews speak the built-in sentences, which is what a new install asks
+/// for.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn generate_starter_snapshot(
+    tts: tauri::State<'_, TtsService>,
+    roots: tauri::State<'_, AllowedRoots>,
+    output_dir: PathBuf,
+    languages: Option<Vec<String>>,
+) -> Result<StarterSnapshotReport, AppError> {
+    let output_dir = roots.validate_path::<Write>(&output_dir)?.into_path_buf();
+    let list = tts.voice_list(true, None).await?;
+    voice_list::save(
+        &output_dir.join(voice_list::FILE_NAME),
+        &StoredVoiceList::new(&list.voices, list.fetched_at),
+    )?;
+
+    let names: Vec<String> = list.voices.iter().map(|v| v.name.clone()).collect();
+    let languages =
+        languages.unwrap_or_else(|| STARTER_LANGUAGES.iter().map(|l| l.to_string()).collect());
+    let mut report = StarterSnapshotReport {
+        output_dir: output_dir.clone(),
+        fetched_at: list.fetched_at,
+        voices: names.len(),
+        previews: Vec::new(),
+        failed: Vec::new(),
+    };
+    let previews_dir = output_dir.join(PREVIEWS_DIR);
+    for language_code in languages {
+        let Some(voice) = pick_voice(&names, &language_code) else {
+            log::warn!("Starter snapshot: no voice for {}", language_code);
+            report.failed.push(language_code);
+            continue;
+        };
+        let sentence = sentences::sentence_for(&BTreeMap::new(), &language_code);
+        let written =
+            match super::previews::synthesize_preview(&tts, voice, &sentence, Lane::Background)
+                .await
+            {
+                Ok(audio) => write_atomic(
+                    &previews_dir.join(PreviewCache::file_name(voice, &sentence)),
+                    &audio,
+                )
+                .map_err(AppError::from),
+                Err(e) => Err(e),
+            };
+        match written {
+            Ok(()) => report.previews.push(voice.clone()),
+            Err(e) => {
+                log::warn!("Starter snapshot: preview of {} failed: {}", voice, e);
+                report.failed.push(language_code);
+            }
+        }
+    }
+    Ok(report)
+}
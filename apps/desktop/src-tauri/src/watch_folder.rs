@@ -0,0 +1,296 @@
+// A folder watched for script files, e.g. one writers share over Dropbox.
+// New and changed `.txt` and `.md` files are decoded like an opened script
+// and offered to the frontend with "script-file-detected". The folder is
+// polled rather than watched through OS events, which network drives and
+// sync clients don't deliver reliably; a file is only read once it has
+// stopped changing, and an unreachable folder is retried until it's back
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use tauri::{Emitter, Manager};
+
+use crate::error::AppError;
+use crate::paths::grants;
+use crate::paths::roots::{AllowedRoots, Read};
+use crate::read_only::WriteAccess;
+use crate::script::{decode_script, ScriptFile};
+use crate::settings::SettingsStore;
+use crate::shutdown::{ShutdownController, ShutdownSignal, TaskId};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Polls of an unreachable folder back off up to this.
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+/// A file is read once its size and modification time held this long.
+const SETTLE_TIME: Duration = Duration::from_secs(3);
+/// Larger files are skipped; no script is this long.
+const MAX_SCRIPT_BYTES: u64 = 16 * 1024 * 1024;
+const EXTENSIONS: &[&str] = &["txt", "md"];
+/// Names sync clients and editors give files still being written.
+const TEMP_SUFFIXES: &[&str] = &[
+    ".tmp",
+    ".part",
+    ".partial",
+    ".crdownload",
+    ".download",
+    ".swp",
+    "~",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatchFolderSettings {
+    pub path: Option<PathBuf>,
+    pub enabled: bool,
+}
+
+/// Payload of "script-file-detected".
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptFileDetected {
+    #[serde(flatten)]
+    pub script: ScriptFile,
+    /// ISO 639-3 code of the language the text reads as, when reliable.
+    pub detected_language: Option<String>,
+}
+
+/// Payload of "watch-folder-status", sent when the folder goes away or
+/// comes back.
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchFolderStatus {
+    pub path: PathBuf,
+    pub available: bool,
+    pub message: Option<String>,
+}
+
+/// Size and modification time, which change while a file is written.
+type Signature = (u64, SystemTime);
+
+struct Running {
+    path: PathBuf,
+    task: TaskId,
+}
+
+/// Managed state: the watcher task, if a folder is watched.
+#[derive(Default)]
+pub struct FolderWatcher {
+    running: Mutex<Option<Running>>,
+}
+
+impl FolderWatcher {
+    /// Watches `path`, replacing any folder watched before.
+    pub fn start(&self, app_handle: &tauri::AppHandle, path: PathBuf) {
+        let shutdown = app_handle.state::<ShutdownController>();
+        let task = shutdown.spawn(format!("watch folder {}", path.display()), |signal| {
+            watch(app_handle.clone(), path.clone(), signal)
+        });
+        let previous = self.running.lock().unwrap().replace(Running { path, task });
+        if let Some(previous) = previous {
+            shutdown.stop(previous.task);
+        }
+    }
+
+    pub fn stop(&self, app_handle: &tauri::AppHandle) {
+        if let Some(running) = self.running.lock().unwrap().take() {
+            log::info!("Stopped watching {}", running.path.display());
+            app_handle.state::<ShutdownController>().stop(running.task);
+        }
+    }
+
+    /// Starts or stops watching to match `settings`.
+    pub fn apply(&self, app_handle: &tauri::AppHandle, settings: &WatchFolderSettings) {
+        match (&settings.path, settings.enabled) {
+            (Some(path), true) => self.start(app_handle, path.clone()),
+            _ => self.stop(app_handle),
+        }
+    }
+}
+
+fn is_candidate(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    // Hidden files, Office lock files and editor backups
+    if name.starts_with('.') || name.starts_with("~$") {
+        return false;
+    }
+    let lower = name.to_lowercase();
+    if TEMP_SUFFIXES.iter().any(|s| lower.ends_with(s)) {
+        return false;
+    }
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+fn scan(dir: &Path) -> std::io::Result<HashMap<PathBuf, Signature>> {
+    let mut files = HashMap::new();
+    for entry in fs::read_dir(dir)?.flatten() {
+        let path = entry.path();
+        if !is_candidate(&path) {
+            continue;
+        }
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        if meta.is_file() {
+            let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.insert(path, (meta.len(), modified));
+        }
+    }
+    Ok(files)
+}
+
+fn emit_status(app_handle: &tauri::AppHandle, path: &Path, message: Option<String>) {
+    let status = WatchFolderStatus {
+        path: path.to_path_buf(),
+        available: message.is_none(),
+        message,
+    };
+    if let Err(e) = app_handle.emit("watch-folder-status", status) {
+        log::warn!("Failed to emit watch-folder-status: {}", e);
+    }
+}
+
+fn import(app_handle: &tauri::AppHandle, path: &Path) -> Result<(), AppError> {
+    let roots = app_handle.state::<AllowedRoots>();
+    let bytes = roots.validate_path::<Read>(path)?.read()?;
+    let script = decode_script(path.to_path_buf(), &bytes);
+    let detected_language = whatlang::detect(&script.text)
+        .filter(|i| i.is_reliable())
+        .map(|i| i.lang().code().to_string());
+    log::info!("Watch folder picked up {}", path.display());
+    app_handle.emit(
+        "script-file-detected",
+        ScriptFileDetected {
+            script,
+            detected_language,
+        },
+    )?;
+    Ok(())
+}
+
+/// Polls `dir` until `signal` fires. Files already there at the first scan are
+/// taken as imported; what the folder held when it went away is kept, so
+/// files added or changed while it was unreachable are picked up after.
+async fn watch(app_handle: tauri::AppHandle, dir: PathBuf, mut signal: ShutdownSignal) {
+    log::info!("Watching {} for scripts", dir.display());
+    // What each file looked like when reported, or at the start
+    let mut reported: HashMap<PathBuf, Signature> = HashMap::new();
+    // Changes not yet settled, with when they were first seen as they are
+    let mut pending: HashMap<PathBuf, (Signature, Instant)> = HashMap::new();
+    let mut started = false;
+    let mut available = true;
+    let mut interval = POLL_INTERVAL;
+    loop {
+        // Checked each time, as the grant may have been revoked
+        let scanned = app_handle
+            .state::<AllowedRoots>()
+            .validate_path::<Read>(&dir)
+            .map_err(|e| e.to_string())
+            .and_then(|_| scan(&dir).map_err(|e| e.to_string()));
+        let files = match scanned {
+            Ok(files) => files,
+            Err(message) => {
+                if available {
+                    log::warn!("Watch folder {} is unavailable: {}", dir.display(), message);
+                    emit_status(&app_handle, &dir, Some(message));
+                    available = false;
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(interval) => {}
+                    _ = signal.triggered() => return,
+                }
+                interval = (interval * 2).min(MAX_RETRY_INTERVAL);
+                continue;
+            }
+        };
+        if !available {
+            log::info!("Watch folder {} is back", dir.display());
+            emit_status(&app_handle, &dir, None);
+            available = true;
+        }
+        interval = POLL_INTERVAL;
+
+        if !started {
+            reported = files.clone();
+            started = true;
+        }
+        let now = Instant::now();
+        pending.retain(|path, _| files.contains_key(path));
+        reported.retain(|path, _| files.contains_key(path));
+        for (path, signature) in files {
+            if signal.is_triggered() {
+                return;
+            }
+            if reported.get(&path) == Some(&signature) {
+                pending.remove(&path);
+                continue;
+            }
+            if signature.0 > MAX_SCRIPT_BYTES {
+                log::warn!(
+                    "Skipping {}: over {} bytes",
+                    path.display(),
+                    MAX_SCRIPT_BYTES
+                );
+                reported.insert(path, signature);
+                continue;
+            }
+            let since = match pending.get(&path) {
+                Some((seen, since)) if *seen == signature => *since,
+                _ => {
+                    pending.insert(path, (signature, now));
+                    continue;
+                }
+            };
+            if now.duration_since(since) < SETTLE_TIME {
+                continue;
+            }
+            pending.remove(&path);
+            match import(&app_handle, &path) {
+                Ok(()) => {
+                    reported.insert(path, signature);
+                }
+                // Left unreported, so the next poll tries it again
+                Err(e) => log::warn!("Failed to import {}: {}", path.display(), e),
+            }
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = signal.triggered() => return,
+        }
+    }
+}
+
+/// Persists the watch folder and starts or stops watching it. The folder is
+/// granted like an export directory, asking the user first, so its files
+/// can be read.
+#[crate::metrics::timed]
+#[tauri::command]
+pub async fn set_watch_folder(
+    app_handle: tauri::AppHandle,
+    settings: tauri::State<'_, SettingsStore>,
+    watcher: tauri::State<'_, FolderWatcher>,
+    access: tauri::State<'_, WriteAccess>,
+    roots: tauri::State<'_, AllowedRoots>,
+    path: Option<PathBuf>,
+    enabled: bool,
+) -> Result<WatchFolderSettings, AppError> {
+    access.check()?;
+    let path = match path {
+        Some(path) if enabled => {
+            Some(grants::ensure(&settings, &roots, &path, "Watch for new scripts").await?)
+        }
+        path => path,
+    };
+    let watch_folder = WatchFolderSettings { path, enabled };
+    settings.update(|s| s.watch_folder = watch_folder.clone())?;
+    watcher.apply(&app_handle, &watch_folder);
+    Ok(watch_folder)
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![set_watch_folder]
+}
@@ -0,0 +1,339 @@
+// State scoped to one editor window, keyed by window label, so a second
+// window (File → New Window) edits its own project. The TTS client, caches
+// and settings stay app-global; jobs record their window in `JobInfo`
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::cache::write_atomic;
+use crate::error::AppError;
+use crate::paths::roots::{AllowedRoots, RootKind};
+use crate::paths::AppPaths;
+use crate::project::review::Review;
+use crate::project::ProjectLocation;
+use crate::read_only::WriteAccess;
+use crate::voices::remap::VoiceRemap;
+
+/// Characters of the project name kept in its autosave file name.
+const STEM_LEN: usize = 40;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WindowProject {
+    pub window: String,
+    pub file: PathBuf,
+    pub dir: PathBuf,
+    /// Where `autosave_window_project` writes; named after the project, so
+    /// windows with different projects never share a file.
+    pub autosave_path: PathBuf,
+    pub opened_at: DateTime<Utc>,
+    pub last_autosave: Option<DateTime<Utc>>,
+}
+
+struct WindowScope {
+    project: WindowProject,
+    /// Held while autosaving, so two saves from one window can't interleave.
+    autosave: Arc<Mutex<()>>,
+}
+
+/// Managed state holding each window's active project.
+#[derive(Default)]
+pub struct WindowScopes {
+    windows: Mutex<HashMap<String, WindowScope>>,
+}
+
+fn autosave_file(autosave_dir: &Path, project_file: &Path) -> PathBuf {
+    let hash = format!(
+        "{:x}",
+        Sha256::digest(project_file.to_string_lossy().as_bytes())
+    );
+    let stem = project_file
+        .file_stem()
+        .map(|s| crate::batch::naming::slugify(&s.to_string_lossy(), STEM_LEN))
+        .unwrap_or_default();
+    autosave_dir.join(format!("{}-{}.json", stem, &hash[..16]))
+}
+
+impl WindowScopes {
+    /// Makes `location` the active project of `window`, replacing any it
+    /// had. A project can be active in one window at a time.
+    pub fn open(
+        &self,
+        window: &str,
+        location: ProjectLocation,
+        autosave_dir: &Path,
+    ) -> Result<WindowProject, AppError> {
+        let file = location.file.canonicalize()?;
+        let mut windows = self.windows.lock().unwrap();
+        if let Some((other, _)) = windows
+            .iter()
+            .find(|(label, scope)| *label != window && scope.project.file == file)
+        {
+            return Err(AppError::ProjectOpenInOtherWindow {
+                path: file.display().to_string(),
+                window: other.clone(),
+            });
+        }
+        let project = WindowProject {
+            window: window.to_string(),
+            autosave_path: autosave_file(autosave_dir, &file),
+            file,
+            dir: location.dir,
+            opened_at: Utc::now(),
+            last_autosave: None,
+        };
+        windows.insert(
+            window.to_string(),
+            WindowScope {
+                project: project.clone(),
+                autosave: Arc::new(Mutex::new(())),
+            },
+        );
+        Ok(project)
+    }
+
+    pub fn get(&self, window: &str) -> Option<WindowProject> {
+        self.windows
+            .lock()
+            .unwrap()
+            .get(window)
+            .map(|scope| scope.project.clone())
+    }
+
+    /// Drops everything scoped to `window`; false if it had nothing.
+    pub fn remove(&self, window: &str) -> bool {
+        self.windows.lock().unwrap().remove(window).is_some()
+    }
+
+    /// Writes `project`, with the review status of its segments, to the
+    /// autosave file of `window`'s active project.
+    pub fn autosave(
+        &self,
+        window: &str,
+        project: &serde_json::Value,
+    ) -> Result<WindowProject, AppError> {
+        let no_project = || AppError::NoActiveProject {
+            window: window.to_string(),
+        };
+        let (path, lock, location) = {
+            let windows = self.windows.lock().unwrap();
+            let scope = windows.get(window).ok_or_else(no_project)?;
+            let location = ProjectLocation {
+                file: scope.project.file.clone(),
+                dir: scope.project.dir.clone(),
+            };
+            (
+                scope.project.autosave_path.clone(),
+                scope.autosave.clone(),
+                location,
+            )
+        };
+        // Review status lives beside the project file, so it's saved along
+        let mut project = project.clone();
+        match Review::load(&location) {
+            Ok(review) => {
+                if let (Some(map), Ok(review)) =
+                    (project.as_object_mut(), serde_json::to_value(review))
+                {
+                    map.insert("review".to_string(), review);
+                }
+            }
+            Err(e) => log::warn!("Autosaving without the review status: {}", e),
+        }
+        let json = serde_json::to_vec_pretty(&project).map_err(|e| AppError::Io {
+            message: e.to_string(),
+        })?;
+        {
+            let _saving = lock.lock().unwrap();
+            write_atomic(&path, &json)?;
+        }
+
+        let mut windows = self.windows.lock().unwrap();
+        // Still the same project: the window may have switched meanwhile
+        let scope = windows
+            .get_mut(window)
+            .filter(|scope| scope.project.autosave_path == path)
+            .ok_or_else(no_project)?;
+        scope.project.last_autosave = Some(Utc::now());
+        Ok(scope.project.clone())
+    }
+}
+
+/// Makes `project_path` (a `.sclip` file or a project directory) the active
+/// project of the calling window, and its directory an allowed root.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn open_window_project(
+    app_handle: tauri::AppHandle,
+    window: tauri::Window,
+    scopes: tauri::State<'_, WindowScopes>,
+    roots: tauri::State<'_, AllowedRoots>,
+    project_path: PathBuf,
+) -> Result<WindowProject, AppError> {
+    let location = ProjectLocation::locate(&project_path)?;
+    roots.grant(RootKind::Project, &location.dir)?;
+    let paths = AppPaths::resolve(&app_handle)?;
+    scopes.open(window.label(), location, &paths.autosave_dir)
+}
+
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn get_window_project(
+    window: tauri::Window,
+    scopes: tauri::State<'_, WindowScopes>,
+) -> Option<WindowProject> {
+    scopes.get(window.label())
+}
+
+/// Closes the calling window's project; false if it had none.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn close_window_project(window: tauri::Window, scopes: tauri::State<'_, WindowScopes>) -> bool {
+    scopes.remove(window.label())
+}
+
+/// Payload of "autosave-disabled", sent to the window whose autosave was
+/// refused.
+#[derive(Debug, Clone, Serialize)]
+pub struct AutosaveDisabled {
+    pub window: String,
+    pub unwritable_path: Option<PathBuf>,
+}
+
+/// Autosaves the calling window's project; fails with `NoActiveProject` if
+/// it has none. In read-only mode it warns the window with
+/// "autosave-disabled" and fails with `ReadOnlyMode`.
+#[crate::metrics::timed]
+#[tauri::command]
+pub fn autosave_window_project(
+    window: tauri::Window,
+    scopes: tauri::State<'_, WindowScopes>,
+    access: tauri::State<'_, WriteAccess>,
+    settings: tauri::State<'_, crate::settings::SettingsStore>,
+    mut project: serde_json::Value,
+) -> Result<WindowProject, AppError> {
+    use tauri::{Emitter, Manager};
+
+    if let Err(e) = access.check() {
+        let payload = AutosaveDisabled {
+            window: window.label().to_string(),
+            unwritable_path: access.status().unwritable_path,
+        };
+        if let Err(e) = window.emit_to(window.label(), "autosave-disabled", payload) {
+            log::warn!("Failed to emit autosave-disabled: {}", e);
+        }
+        return Err(e);
+    }
+    // Renamed voice ids are saved under their new names
+    let changes = VoiceRemap::from_settings(&settings.get()).apply_to_value(&mut project);
+    crate::voices::remap::announce(window.app_handle(), "project", changes);
+    scopes.autosave(window.label(), &project)
+}
+
+/// The commands of this module, for `commands::handler`.
+pub(crate) fn commands() -> crate::commands::CommandGroup {
+    crate::commands::command_group![
+        open_window_project,
+        get_window_project,
+        close_window_project,
+        autosave_window_project,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+    use serde_json::json;
+    use std::fs;
+    use std::sync::Barrier;
+
+    /// A project file `name` in its own directory `dir`.
+    fn project(scratch: &Scratch, dir: &str, name: &str) -> ProjectLocation {
+        let dir = scratch.0.join(dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(name);
+        fs::write(&file, "{}").unwrap();
+        ProjectLocation::locate(&file).unwrap()
+    }
+
+    fn saved(project: &WindowProject) -> serde_json::Value {
+        serde_json::from_slice(&fs::read(&project.autosave_path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn windows_autosave_their_own_projects_concurrently() {
+        const SAVES: usize = 25;
+        let scratch = Scratch::new("concurrent");
+        let autosave_dir = scratch.0.join("autosave");
+        let scopes = WindowScopes::default();
+        // The same file name in two folders still gets two autosave files
+        let windows = ["main", "second"];
+        let projects: Vec<WindowProject> = windows
+            .iter()
+            .map(|window| {
+                let location = project(&scratch, window, "Episode 1.sclip");
+                scopes.open(window, location, &autosave_dir).unwrap()
+            })
+            .collect();
+        assert_ne!(projects[0].autosave_path, projects[1].autosave_path);
+
+        let start = Barrier::new(windows.len());
+        std::thread::scope(|s| {
+            for window in windows {
+                let (scopes, start) = (&scopes, &start);
+                s.spawn(move || {
+                    start.wait();
+                    for n in 0..SAVES {
+                        let project = json!({ "window": window, "n": n });
+                        let saved = scopes.autosave(window, &project).unwrap();
+                        assert_eq!(saved.window, window);
+                    }
+                });
+            }
+        });
+
+        for (window, project) in windows.iter().zip(&projects) {
+            let saved = saved(project);
+            assert_eq!(saved["window"], *window);
+            assert_eq!(saved["n"], SAVES - 1);
+            assert!(saved["review"].is_object());
+            assert!(scopes.get(window).unwrap().last_autosave.is_some());
+        }
+        let files = fs::read_dir(&autosave_dir).unwrap().count();
+        assert_eq!(files, 2, "no temp files left behind");
+    }
+
+    #[test]
+    fn a_project_is_open_in_one_window_at_a_time() {
+        let scratch = Scratch::new("exclusive");
+        let autosave_dir = scratch.0.join("autosave");
+        let scopes = WindowScopes::default();
+        let location = project(&scratch, "show", "show.sclip");
+        scopes
+            .open("main", location.clone(), &autosave_dir)
+            .unwrap();
+        match scopes.open("second", location.clone(), &autosave_dir) {
+            Err(AppError::ProjectOpenInOtherWindow { window, .. }) => assert_eq!(window, "main"),
+            other => panic!("expected ProjectOpenInOtherWindow, got {:?}", other),
+        }
+        // Reopening in the same window is fine, and closing frees it
+        scopes
+            .open("main", location.clone(), &autosave_dir)
+            .unwrap();
+        assert!(scopes.remove("main"));
+        assert!(!scopes.remove("main"));
+        scopes.open("second", location, &autosave_dir).unwrap();
+    }
+
+    #[test]
+    fn autosave_needs_an_active_project() {
+        let scopes = WindowScopes::default();
+        assert!(matches!(
+            scopes.autosave("main", &json!({})),
+            Err(AppError::NoActiveProject { window }) if window == "main"
+        ));
+    }
+}
@@ -9,6 +9,7 @@ import {
 } from 'lucide-react';
 import { useRealtimeStore } from '../hooks/useRealtimeStore';
 import { useWebSocket } from '../hooks/useWebSocket';
+import { backendStreamProtocol } from '../utils/api';
 
 interface Project {
   id: string;
@@ -48,9 +49,11 @@ const Dashboard: React.FC<DashboardProps> = ({ onOpenProject, onCreateProject })
 
   // Test WebSocket connection on component mount
   useEffect(() => {
-    const testWebSocket = () => {
+    const testWebSocket = async () => {
       console.log('Testing WebSocket connection...');
-      const testWs = new WebSocket(`ws://127.0.0.1:8001/api/stream/${defaultSessionId}`);
+      const testWs = new WebSocket(`ws://127.0.0.1:8001/api/stream/${defaultSessionId}`, [
+        await backendStreamProtocol(),
+      ]);
       
       testWs.onopen = () => {
         console.log('✅ Test WebSocket connected successfully');
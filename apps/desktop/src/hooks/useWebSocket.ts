@@ -1,5 +1,6 @@
 import { useEffect, useRef, useState, useCallback } from "react";
 import { useRealtimeStore } from "./useRealtimeStore";
+import { backendStreamProtocol } from "../utils/api";
 
 export type WebSocketMessage = any;
 
@@ -25,7 +26,7 @@ export function useWebSocket({ sessionId, onMessage, url }: UseWebSocketOptions)
 
   const wsUrl = url || (sessionId ? `ws://localhost:8001/api/stream/${sessionId}` : "");
 
-  const connect = useCallback(() => {
+  const connect = useCallback(async () => {
     if (!sessionIdRef.current) return; // Do not connect if sessionId is missing
     
     // Don't reconnect if already connected
@@ -38,7 +39,16 @@ export function useWebSocket({ sessionId, onMessage, url }: UseWebSocketOptions)
       wsRef.current.close();
     }
     
-    const ws = new WebSocket(wsUrl);
+    // Fetched each time: the token rotates when the backend restarts
+    let protocol: string;
+    try {
+      protocol = await backendStreamProtocol();
+    } catch (e) {
+      console.error("Failed to get the backend token:", e);
+      setError("WebSocket error");
+      return;
+    }
+    const ws = new WebSocket(wsUrl, [protocol]);
     wsRef.current = ws;
     
     ws.onopen = () => {
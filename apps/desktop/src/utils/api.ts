@@ -1,38 +1,46 @@
-const BASE_URL = "http://localhost:8001";
+import { invoke } from "@tauri-apps/api/core";
 
-// Helper for fetch with error handling
-async function fetchJson(url: string, options: RequestInit = {}) {
-  const res = await fetch(url, options);
-  if (!res.ok) throw new Error(await res.text());
-  return res.json();
+// Backend requests go through the Rust `backend_request` proxy, which adds the
+// backend's auth token and maps connection failures to structured errors.
+type BackendResponse =
+  | { kind: "json"; status: number; body: any }
+  | { kind: "file"; status: number; path: string; bytes: number; content_type: string | null };
+
+// Helper for proxied requests with error handling
+async function fetchJson(path: string, method = "GET", body?: any, timeoutMs?: number) {
+  const res = await invoke<BackendResponse>("backend_request", {
+    method,
+    path,
+    bodyJson: body ?? null,
+    timeoutMs: timeoutMs ?? null,
+  });
+  return res.kind === "json" ? res.body : res;
+}
+
+// Subprotocol carrying the backend's auth token, for `new WebSocket(url, [protocol])`;
+// a browser can't set the header on a websocket. Changes when the backend restarts.
+export async function backendStreamProtocol(): Promise<string> {
+  return invoke<string>("backend_stream_protocol");
 }
 
 // Submit user prompt
-export async function submitPrompt(data: any, baseUrl = BASE_URL) {
-  return fetchJson(`${baseUrl}/api/prompt`, {
-    method: "POST",
-    headers: { "Content-Type": "application/json" },
-    body: JSON.stringify(data),
-  });
+export async function submitPrompt(data: any) {
+  return fetchJson("/api/prompt", "POST", data);
 }
 
 // Approve a step
-export async function approveStep(sessionId: string, approval: any, baseUrl = BASE_URL) {
-  return fetchJson(`${baseUrl}/api/approve/${sessionId}`, {
-    method: "POST",
-    headers: { "Content-Type": "application/json" },
-    body: JSON.stringify(approval),
-  });
+export async function approveStep(sessionId: string, approval: any) {
+  return fetchJson(`/api/approve/${sessionId}`, "POST", approval);
 }
 
 // List sessions
-export async function listSessions(baseUrl = BASE_URL) {
-  return fetchJson(`${baseUrl}/api/sessions`);
+export async function listSessions() {
+  return fetchJson("/api/sessions");
 }
 
 // List files for a session
-export async function listSessionFiles(sessionId: string, baseUrl = BASE_URL) {
-  return fetchJson(`${baseUrl}/api/files/list/${sessionId}`);
+export async function listSessionFiles(sessionId: string) {
+  return fetchJson(`/api/files/list/${sessionId}`);
 }
 
 // Add more API functions as needed (file upload, download, etc.) 
\ No newline at end of file
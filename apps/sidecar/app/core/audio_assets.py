@@ -0,0 +1,123 @@
+"""
+Audio the desktop app synthesized, registered so the backend uses it.
+
+After synthesizing a segment, the Tauri shell POSTs its file's path, hash and
+duration to /api/projects/{project_id}/assets and keeps the asset id it gets
+back. Registrations are stored in the project's audio_assets.json, one per
+segment: registering a segment again replaces its entry, and keeps its asset
+id when the audio is the same. The file is hashed on registration, so an entry
+always names audio that was on disk as described.
+"""
+
+import hashlib
+import json
+import os
+import re
+from datetime import datetime, timezone
+from pathlib import Path
+from typing import Callable, Dict, Literal, Optional
+import uuid
+
+from fastapi import APIRouter, HTTPException
+from pydantic import BaseModel, Field
+
+ASSETS_FILE = "audio_assets.json"
+
+# The ids the desktop app accepts (sidecar/handoff.rs `check_id`)
+ID_PATTERN = r"^[A-Za-z0-9._-]{1,128}$"
+
+
+class AudioRegistration(BaseModel):
+    segment_id: str = Field(pattern=ID_PATTERN)
+    path: str
+    # Set when the audio is an entry of the app's TTS cache
+    cache_key: Optional[str] = None
+    sha256: str = Field(pattern=r"^[0-9a-f]{64}$")
+    bytes: int = Field(ge=0)
+    mime_type: Literal["audio/mpeg", "audio/wav"]
+    duration_ms: Optional[float] = Field(default=None, ge=0)
+
+
+def valid_id(value: str) -> bool:
+    return re.match(ID_PATTERN, value) is not None and value not in (".", "..")
+
+
+def load_assets(project_path: Path) -> Dict[str, dict]:
+    """Registrations of a project by segment id; empty when there are none."""
+    path = project_path / ASSETS_FILE
+    try:
+        with open(path) as f:
+            assets = json.load(f)
+    except FileNotFoundError:
+        return {}
+    return assets if isinstance(assets, dict) else {}
+
+
+def save_assets(project_path: Path, assets: Dict[str, dict]) -> None:
+    path = project_path / ASSETS_FILE
+    tmp = path.with_suffix(".json.tmp")
+    with open(tmp, "w") as f:
+        json.dump(assets, f, indent=2)
+    os.replace(tmp, path)
+
+
+def registered_audio(project_path: Path, segment_id: str) -> Optional[dict]:
+    """The audio registered for a segment, if its file is still there."""
+    asset = load_assets(project_path).get(segment_id)
+    if asset and Path(asset["path"]).is_file():
+        return asset
+    return None
+
+
+def file_sha256(path: Path) -> str:
+    digest = hashlib.sha256()
+    with open(path, "rb") as f:
+        for block in iter(lambda: f.read(1 << 16), b""):
+            digest.update(block)
+    return digest.hexdigest()
+
+
+def make_router(project_path: Callable[[str], Path]) -> APIRouter:
+    """Routes for the projects `project_path` locates by id."""
+    router = APIRouter()
+
+    def existing_project(project_id: str) -> Path:
+        if not valid_id(project_id):
+            raise HTTPException(status_code=422, detail="Invalid project id")
+        path = project_path(project_id)
+        if not path.is_dir():
+            raise HTTPException(status_code=404, detail="Project not found")
+        return path
+
+    @router.post("/api/projects/{project_id}/assets")
+    async def register_audio(project_id: str, registration: AudioRegistration):
+        """Register synthesized audio as the audio of one segment"""
+        path = existing_project(project_id)
+        audio = Path(registration.path)
+        if not audio.is_file():
+            raise HTTPException(status_code=422, detail=f"No audio file at {audio}")
+        if audio.stat().st_size != registration.bytes or file_sha256(audio) != registration.sha256:
+            raise HTTPException(status_code=409, detail=f"{audio} doesn't match its hash")
+
+        assets = load_assets(path)
+        previous = assets.get(registration.segment_id)
+        if previous and previous.get("sha256") == registration.sha256:
+            asset_id = previous["asset_id"]
+        else:
+            asset_id = uuid.uuid4().hex
+        asset = {
+            "asset_id": asset_id,
+            "project_id": project_id,
+            **registration.model_dump(),
+            "registered_at": datetime.now(timezone.utc).isoformat(),
+        }
+        assets[registration.segment_id] = asset
+        save_assets(path, assets)
+        return asset
+
+    @router.get("/api/projects/{project_id}/assets")
+    async def list_audio(project_id: str):
+        """Audio registered for a project, by segment id"""
+        return load_assets(existing_project(project_id))
+
+    return router
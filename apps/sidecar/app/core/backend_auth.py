@@ -0,0 +1,91 @@
+"""
+Shared-secret authentication for requests proxied by the desktop app.
+
+The Tauri shell generates a random token each time it spawns the backend and
+passes it in SCLIP_BACKEND_TOKEN. When set, every HTTP request must carry it in
+the X-Sclip-Token header; other local processes hitting the port are rejected.
+Websockets carry it the same way, or, since a browser can't set headers on
+one, as the subprotocol "sclip-token.<token>". A backend started by hand (no
+token in the environment) stays open, as before.
+"""
+
+import hmac
+import json
+from typing import Optional
+
+TOKEN_ENV = "SCLIP_BACKEND_TOKEN"
+TOKEN_HEADER = "x-sclip-token"
+TOKEN_SUBPROTOCOL_PREFIX = "sclip-token."
+# Close code for a websocket refused for its token, mirroring HTTP 401
+WEBSOCKET_UNAUTHORIZED = 4401
+
+# Reachable without the token: liveness probes carry no secret
+EXEMPT_PATHS = {"/api/health"}
+
+
+class BackendTokenMiddleware:
+    """Pure ASGI middleware so it also covers streaming and file responses."""
+
+    def __init__(self, app, token: Optional[str] = None):
+        self.app = app
+        self.token = token or None
+
+    async def __call__(self, scope, receive, send):
+        if scope["type"] not in ("http", "websocket") or self.token is None:
+            await self.app(scope, receive, send)
+            return
+        if scope["type"] == "websocket":
+            await self._websocket(scope, receive, send)
+            return
+        if scope["method"] == "OPTIONS" or scope["path"] in EXEMPT_PATHS:
+            await self.app(scope, receive, send)
+            return
+
+        if self._valid(_header(scope, TOKEN_HEADER)):
+            await self.app(scope, receive, send)
+            return
+
+        body = json.dumps({"detail": "Missing or invalid backend token"}).encode()
+        await send({
+            "type": "http.response.start",
+            "status": 401,
+            "headers": [
+                (b"content-type", b"application/json"),
+                (b"content-length", str(len(body)).encode()),
+            ],
+        })
+        await send({"type": "http.response.body", "body": body})
+
+    async def _websocket(self, scope, receive, send):
+        if self._valid(_header(scope, TOKEN_HEADER)):
+            await self.app(scope, receive, send)
+            return
+        protocol = next(
+            (p for p in scope.get("subprotocols", []) if p.startswith(TOKEN_SUBPROTOCOL_PREFIX)),
+            None,
+        )
+        if protocol is not None and self._valid(protocol[len(TOKEN_SUBPROTOCOL_PREFIX):]):
+            # A browser drops the connection unless the offered protocol is
+            # echoed back, which the endpoint itself knows nothing about
+            async def send_accepting_protocol(message):
+                if message["type"] == "websocket.accept" and not message.get("subprotocol"):
+                    message = {**message, "subprotocol": protocol}
+                await send(message)
+
+            await self.app(scope, receive, send_accepting_protocol)
+            return
+
+        # Refused before the handshake completes, so the endpoint never runs
+        message = await receive()
+        if message["type"] == "websocket.connect":
+            await send({"type": "websocket.close", "code": WEBSOCKET_UNAUTHORIZED})
+
+    def _valid(self, presented: Optional[str]) -> bool:
+        return presented is not None and hmac.compare_digest(presented, self.token)
+
+
+def _header(scope, wanted: str) -> Optional[str]:
+    for name, value in scope.get("headers", []):
+        if name.decode("latin-1").lower() == wanted:
+            return value.decode("latin-1")
+    return None
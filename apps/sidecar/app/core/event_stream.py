@@ -0,0 +1,74 @@
+"""
+Process-wide event stream consumed by the desktop app.
+
+Every message sent to a session's WebSocket is also published here with a
+monotonically increasing sequence number, so a single long-lived
+Server-Sent Events connection (`GET /api/events`) can follow all sessions and
+resume after a reconnect with `since_seq` without losing or repeating events.
+"""
+
+import asyncio
+import json
+import uuid
+from collections import deque
+from typing import Any, AsyncIterator, Deque, Dict, List, Optional
+
+# Events kept for replay to reconnecting clients
+REPLAY_BUFFER_SIZE = 500
+# Comment lines sent while idle so the client can tell a quiet stream from a dead one
+KEEPALIVE_SECONDS = 15
+
+
+class EventHub:
+    def __init__(self):
+        # Changes on every backend start; clients reset their sequence tracking when it does
+        self.boot_id = uuid.uuid4().hex
+        self.seq = 0
+        self.buffer: Deque[Dict[str, Any]] = deque(maxlen=REPLAY_BUFFER_SIZE)
+        self.subscribers: List[asyncio.Queue] = []
+
+    def publish(self, session_id: Optional[str], message: Dict[str, Any]) -> None:
+        self.seq += 1
+        event = {
+            "seq": self.seq,
+            "boot_id": self.boot_id,
+            "session_id": session_id,
+            "type": message.get("type", "message"),
+            "payload": message,
+        }
+        self.buffer.append(event)
+        for queue in list(self.subscribers):
+            try:
+                queue.put_nowait(event)
+            except asyncio.QueueFull:
+                # A stalled subscriber loses live events but can replay on reconnect
+                pass
+
+    async def stream(self, since_seq: int = 0) -> AsyncIterator[str]:
+        queue: asyncio.Queue = asyncio.Queue(maxsize=REPLAY_BUFFER_SIZE)
+        self.subscribers.append(queue)
+        try:
+            yield f"event: hello\ndata: {json.dumps({'boot_id': self.boot_id, 'seq': self.seq})}\n\n"
+            last_sent = since_seq
+            for event in list(self.buffer):
+                if event["seq"] > last_sent:
+                    yield format_event(event)
+                    last_sent = event["seq"]
+            while True:
+                try:
+                    event = await asyncio.wait_for(queue.get(), timeout=KEEPALIVE_SECONDS)
+                except asyncio.TimeoutError:
+                    yield ": keepalive\n\n"
+                    continue
+                if event["seq"] > last_sent:
+                    yield format_event(event)
+                    last_sent = event["seq"]
+        finally:
+            self.subscribers.remove(queue)
+
+
+def format_event(event: Dict[str, Any]) -> str:
+    return f"id: {event['seq']}\nevent: message\ndata: {json.dumps(event, default=str)}\n\n"
+
+
+event_hub = EventHub()
@@ -16,6 +16,15 @@ import os
 from dotenv import load_dotenv
 load_dotenv()
 
+# Let the desktop app's watchdog collect every thread's stack from a hung
+# backend: SIGQUIT writes them to the file it names, without needing the GIL
+import faulthandler
+import signal
+_stack_dump_path = os.environ.get("SCLIP_STACK_DUMP_FILE")
+if _stack_dump_path and hasattr(signal, "SIGQUIT"):
+    _stack_dump_file = open(_stack_dump_path, "w")
+    faulthandler.register(signal.SIGQUIT, file=_stack_dump_file, all_threads=True)
+
 from fastapi import FastAPI, WebSocket, WebSocketDisconnect, HTTPException, Depends, UploadFile, File, Query, BackgroundTasks, Body
 from fastapi.middleware.cors import CORSMiddleware
 from fastapi.responses import JSONResponse, FileResponse, StreamingResponse, Response
@@ -38,6 +47,9 @@ from app.utils.input_validator import InputValidator
 from app.orchestrator.sclip_brain import SclipBrain
 from app.core.professional_handler import ProfessionalMessageHandler, setup_professional_system
 from app.core.context_manager import context_manager
+from app.core.backend_auth import BackendTokenMiddleware, TOKEN_ENV
+from app.core.event_stream import event_hub
+from app.core.audio_assets import make_router as make_audio_assets_router
 input_validator = InputValidator()
 
 # --- 3.2: Real-Time Streaming Infrastructure Additions ---
@@ -96,6 +108,9 @@ app.add_middleware(
     allow_headers=["*"],
 )
 
+# Require the desktop app's per-spawn secret; added last so it runs before CORS
+app.add_middleware(BackendTokenMiddleware, token=os.environ.get(TOKEN_ENV))
+
 # Import new AI agent and services
 from app.core.true_ai_agent import TrueAIAgent
 from app.services.rag_service import rag_service
@@ -160,6 +175,7 @@ class ConnectionManager:
         message["message_id"] = message.get("message_id") or str(uuid.uuid4())
         message["timestamp"] = message.get("timestamp") or datetime.now().isoformat()
         add_message_to_queue(session_id, message)
+        event_hub.publish(session_id, message)
         if session_id in self.session_connections:
             for connection_id in self.session_connections[session_id]:
                 if connection_id in self.active_connections:
@@ -248,6 +264,12 @@ async def health_check():
         }
     }
 
+# Lets the desktop app verify that its token is being checked and accepted
+@app.get("/api/auth/status")
+async def auth_status():
+    """Backend token enforcement status"""
+    return {"enforced": bool(os.environ.get(TOKEN_ENV))}
+
 # Main prompt endpoint
 @app.post("/api/prompt")
 async def submit_prompt(request: PromptRequest):
@@ -379,6 +401,19 @@ async def websocket_endpoint(websocket: WebSocket, session_id: str, user_id: str
         if connection_id:
             manager.disconnect(connection_id, session_id)
 
+# Server-Sent Events stream of all sessions' messages for the desktop app
+@app.get("/api/events")
+async def event_stream(since_seq: int = Query(0), boot_id: str = Query(None)):
+    """Stream every session message with a sequence number, replaying those after since_seq"""
+    # Sequence numbers from a previous backend run mean nothing to this one
+    if boot_id != event_hub.boot_id:
+        since_seq = 0
+    return StreamingResponse(
+        event_hub.stream(since_seq),
+        media_type="text/event-stream",
+        headers={"Cache-Control": "no-cache"},
+    )
+
 # --- GLOBAL OPTIONS HANDLER FOR CORS ---
 @app.options("/{rest_of_path:path}")
 def options_handler(rest_of_path: str):
@@ -998,6 +1033,9 @@ def save_project_info(project_path: Path, info: Dict[str, Any]) -> None:
     with open(project_json, 'w') as f:
         json.dump(info, f, indent=2, default=str)
 
+# Audio the desktop app synthesized for a project's segments
+app.include_router(make_audio_assets_router(get_project_path))
+
 def calculate_project_size(project_path: Path) -> int:
     """Calculate the total size of a project in bytes"""
     total_size = 0
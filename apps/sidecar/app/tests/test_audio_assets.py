@@ -0,0 +1,101 @@
+import hashlib
+
+import pytest
+from fastapi import FastAPI
+from fastapi.testclient import TestClient
+
+from app.core.audio_assets import ASSETS_FILE, make_router, registered_audio
+
+AUDIO = b"RIFF\x24\x00\x00\x00WAVEfmt "
+
+
+@pytest.fixture
+def projects(tmp_path):
+    (tmp_path / "p1").mkdir()
+    return tmp_path
+
+
+@pytest.fixture
+def client(projects):
+    stub = FastAPI()
+    stub.include_router(make_router(lambda project_id: projects / project_id))
+    return TestClient(stub)
+
+
+@pytest.fixture
+def audio(tmp_path):
+    path = tmp_path / "segment.wav"
+    path.write_bytes(AUDIO)
+    return path
+
+
+def registration(path, **overrides):
+    body = {
+        "segment_id": "s1",
+        "path": str(path),
+        "cache_key": None,
+        "sha256": hashlib.sha256(AUDIO).hexdigest(),
+        "bytes": len(AUDIO),
+        "mime_type": "audio/wav",
+        "duration_ms": 1250.0,
+    }
+    body.update(overrides)
+    return body
+
+
+def test_registration_returns_an_asset_id(client, projects, audio):
+    resp = client.post("/api/projects/p1/assets", json=registration(audio))
+    assert resp.status_code == 200
+    asset = resp.json()
+    assert asset["asset_id"]
+    assert asset["project_id"] == "p1"
+    assert asset["segment_id"] == "s1"
+    assert (projects / "p1" / ASSETS_FILE).exists()
+    assert registered_audio(projects / "p1", "s1")["asset_id"] == asset["asset_id"]
+
+
+def test_registering_the_same_audio_keeps_its_asset_id(client, audio):
+    first = client.post("/api/projects/p1/assets", json=registration(audio)).json()
+    again = client.post("/api/projects/p1/assets", json=registration(audio)).json()
+    assert again["asset_id"] == first["asset_id"]
+
+    listed = client.get("/api/projects/p1/assets").json()
+    assert list(listed) == ["s1"]
+
+
+def test_new_audio_for_a_segment_gets_a_new_asset_id(client, audio):
+    first = client.post("/api/projects/p1/assets", json=registration(audio)).json()
+    audio.write_bytes(AUDIO + b"data")
+    changed = registration(
+        audio,
+        sha256=hashlib.sha256(AUDIO + b"data").hexdigest(),
+        bytes=len(AUDIO) + 4,
+    )
+    second = client.post("/api/projects/p1/assets", json=changed).json()
+    assert second["asset_id"] != first["asset_id"]
+
+
+def test_unknown_project_is_not_found(client, audio):
+    resp = client.post("/api/projects/nope/assets", json=registration(audio))
+    assert resp.status_code == 404
+
+
+def test_mismatched_hash_is_refused(client, audio):
+    resp = client.post("/api/projects/p1/assets", json=registration(audio, sha256="0" * 64))
+    assert resp.status_code == 409
+
+
+def test_missing_file_is_refused(client, tmp_path):
+    resp = client.post("/api/projects/p1/assets", json=registration(tmp_path / "gone.wav"))
+    assert resp.status_code == 422
+
+
+@pytest.mark.parametrize("overrides", [
+    {"segment_id": "../s1"},
+    {"mime_type": "video/mp4"},
+    {"sha256": "not-a-hash"},
+    {"bytes": -1},
+])
+def test_malformed_registration_is_refused(client, audio, overrides):
+    resp = client.post("/api/projects/p1/assets", json=registration(audio, **overrides))
+    assert resp.status_code == 422
@@ -0,0 +1,96 @@
+import pytest
+from fastapi import FastAPI, WebSocket
+from fastapi.testclient import TestClient
+from starlette.websockets import WebSocketDisconnect
+
+from app.core.backend_auth import (
+    BackendTokenMiddleware,
+    TOKEN_HEADER,
+    TOKEN_SUBPROTOCOL_PREFIX,
+    WEBSOCKET_UNAUTHORIZED,
+)
+
+TOKEN = "0123456789abcdef0123456789abcdef"
+
+
+def make_stub(token):
+    stub = FastAPI()
+    stub.add_middleware(BackendTokenMiddleware, token=token)
+
+    @stub.get("/api/health")
+    async def health():
+        return {"status": "healthy"}
+
+    @stub.post("/api/prompt")
+    async def prompt():
+        return {"session_id": "stub"}
+
+    @stub.websocket("/api/stream/{session_id}")
+    async def stream(websocket: WebSocket, session_id: str):
+        await websocket.accept()
+        await websocket.send_json({"type": "connection_established", "session_id": session_id})
+        await websocket.close()
+
+    return stub
+
+
+@pytest.fixture
+def client():
+    return TestClient(make_stub(TOKEN))
+
+
+def test_direct_request_without_token_is_rejected(client):
+    resp = client.post("/api/prompt", json={})
+    assert resp.status_code == 401
+    assert "token" in resp.json()["detail"]
+
+
+def test_direct_request_with_wrong_token_is_rejected(client):
+    resp = client.post("/api/prompt", json={}, headers={TOKEN_HEADER: "not-the-token"})
+    assert resp.status_code == 401
+
+
+def test_proxied_request_succeeds(client):
+    resp = client.post("/api/prompt", json={}, headers={"X-Sclip-Token": TOKEN})
+    assert resp.status_code == 200
+    assert resp.json() == {"session_id": "stub"}
+
+
+def test_health_is_exempt(client):
+    resp = client.get("/api/health")
+    assert resp.status_code == 200
+
+
+def test_no_token_configured_leaves_backend_open():
+    resp = TestClient(make_stub(None)).post("/api/prompt", json={})
+    assert resp.status_code == 200
+
+
+def test_websocket_without_token_is_refused(client):
+    with pytest.raises(WebSocketDisconnect) as refused:
+        with client.websocket_connect("/api/stream/stub"):
+            pass
+    assert refused.value.code == WEBSOCKET_UNAUTHORIZED
+
+
+def test_websocket_with_wrong_token_is_refused(client):
+    for kwargs in (
+        {"headers": {TOKEN_HEADER: "not-the-token"}},
+        {"subprotocols": [TOKEN_SUBPROTOCOL_PREFIX + "not-the-token"]},
+    ):
+        with pytest.raises(WebSocketDisconnect):
+            with client.websocket_connect("/api/stream/stub", **kwargs):
+                pass
+
+
+def test_websocket_with_token_header_connects(client):
+    with client.websocket_connect("/api/stream/stub", headers={TOKEN_HEADER: TOKEN}) as ws:
+        assert ws.receive_json()["session_id"] == "stub"
+
+
+def test_websocket_with_token_subprotocol_connects(client):
+    protocol = TOKEN_SUBPROTOCOL_PREFIX + TOKEN
+    with client.websocket_connect("/api/stream/stub", subprotocols=[protocol]) as ws:
+        # Echoed back, or a browser would drop the connection
+        assert ws.accepted_subprotocol == protocol
+        assert ws.receive_json()["type"] == "connection_established"
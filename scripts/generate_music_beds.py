@@ -0,0 +1,95 @@
+import math
+import struct
+import wave
+from pathlib import Path
+
+"""
+Music Bed Generator
+- Writes the music beds bundled under resources/music, for previewing
+  narration over music in the desktop app
+- The beds are synthesized here from sine tones, so they carry no third-party
+  rights; they are dedicated to the public domain (see resources/music/LICENSE.md)
+- Each bed loops seamlessly: its chords crossfade into each other and the last
+  one into the first
+Usage: python scripts/generate_music_beds.py
+"""
+
+ROOT = Path(__file__).resolve().parents[1]
+OUT_DIR = ROOT / "resources" / "music"
+
+SAMPLE_RATE = 22050
+PEAK = 0.5  # of full scale; the app sets the bed's level itself
+
+# MIDI note numbers of each chord; every chord lasts CHORD_SECONDS
+BEDS = {
+    "soft-pad": {
+        "chords": [
+            [45, 57, 60, 64],  # A minor
+            [41, 57, 60, 65],  # F major
+            [48, 55, 60, 64],  # C major
+            [43, 55, 59, 62],  # G major
+        ],
+        "chord_seconds": 6.0,
+        "crossfade_seconds": 1.5,
+    },
+}
+
+
+def frequency(note: int) -> float:
+    return 440.0 * 2 ** ((note - 69) / 12)
+
+
+def chord_gain(t: float, start: float, length: float, fade: float, total: float) -> float:
+    """Raised-cosine envelope of one chord, wrapping around the loop."""
+    offset = (t - start + fade) % total
+    if offset < fade:
+        return 0.5 - 0.5 * math.cos(math.pi * offset / fade)
+    if offset < length:
+        return 1.0
+    if offset < length + fade:
+        return 0.5 + 0.5 * math.cos(math.pi * (offset - length) / fade)
+    return 0.0
+
+
+def render(chords, chord_seconds: float, crossfade_seconds: float):
+    total = chord_seconds * len(chords)
+    frames = int(total * SAMPLE_RATE)
+    # Periods are rounded to whole cycles over the loop, so it has no click
+    cycles = [[round(frequency(note) * total) for note in chord] for chord in chords]
+    samples = []
+    for i in range(frames):
+        t = i / SAMPLE_RATE
+        value = 0.0
+        for index, chord in enumerate(cycles):
+            gain = chord_gain(t, index * chord_seconds, chord_seconds, crossfade_seconds, total)
+            if gain == 0.0:
+                continue
+            for n, cycle in enumerate(chord):
+                phase = 2 * math.pi * cycle * t / total
+                # A soft second partial; the bass note louder than the rest
+                voice = math.sin(phase) + 0.25 * math.sin(2 * phase)
+                value += gain * voice * (1.0 if n == 0 else 0.6)
+        samples.append(value)
+    peak = max(abs(s) for s in samples) or 1.0
+    return [int(round(s / peak * PEAK * 32767)) for s in samples]
+
+
+def write_wav(path: Path, samples) -> None:
+    with wave.open(str(path), "wb") as out:
+        out.setnchannels(1)
+        out.setsampwidth(2)
+        out.setframerate(SAMPLE_RATE)
+        out.writeframes(struct.pack(f"<{len(samples)}h", *samples))
+
+
+def main():
+    OUT_DIR.mkdir(parents=True, exist_ok=True)
+    for name, bed in BEDS.items():
+        samples = render(bed["chords"], bed["chord_seconds"], bed["crossfade_seconds"])
+        path = OUT_DIR / f"{name}.wav"
+        write_wav(path, samples)
+        print(f"{path.relative_to(ROOT)}: {len(samples) / SAMPLE_RATE:.1f}s")
+
+
+if __name__ == "__main__":
+    main()